@@ -0,0 +1,217 @@
+//! Lockdown for when a keyfob or phone is reported stolen
+//!
+//! The master's `lockdown` command stops BLE devices, RF remotes and the door
+//! keypad from disarming. Until `lift_lockdown`, the system is disarmed only
+//! from the cloud, or over the API or WebSocket with a PIN and a TOTP code
+//! from the key the command carried. The lockdown is kept in the secrets file
+//! with that key, so a restart does not lift it. Arming works as before.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use tracing::warn;
+
+use crate::config::AppConfig;
+use crate::events::{Event, EventBus};
+use crate::security::{constant_time_eq, SecretStore};
+use crate::state::AppState;
+
+/// Command engaging lockdown, with `{"otp_secret": <base32>, "reason": ...}`, both optional
+pub const LOCKDOWN: &str = "lockdown";
+/// Command lifting lockdown
+pub const LIFT_LOCKDOWN: &str = "lift_lockdown";
+
+/// Lockdown in force and its TOTP key, as JSON
+const LOCKDOWN_SECRET: &str = "lockdown";
+
+pub(super) const TOTP_STEP_S: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+/// Steps either side of now a code is accepted for, for clock drift
+const TOTP_DRIFT_STEPS: i64 = 1;
+
+/// Lockdown in force, as shown in the shared state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockdownStatus {
+    pub since: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Whether a PIN and one-time code can disarm locally
+    pub local_disarm: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredLockdown {
+    status: LockdownStatus,
+    #[serde(default)]
+    otp_key: Option<String>,
+}
+
+/// Engages and lifts lockdown, and checks one-time codes while it holds
+#[derive(Clone)]
+pub struct Lockdown {
+    secrets_path: PathBuf,
+    state: AppState,
+    event_bus: EventBus,
+}
+
+impl Lockdown {
+    pub fn new(config: &AppConfig, state: AppState, event_bus: EventBus) -> Self {
+        Self {
+            secrets_path: config.secrets_path(),
+            state,
+            event_bus,
+        }
+    }
+
+    /// Put back a lockdown that was in force before a restart
+    pub fn restore(&self) -> Result<()> {
+        if let Some(stored) = self.stored()? {
+            warn!(since = %stored.status.since, reason = ?stored.status.reason, "Lockdown still in force");
+            self.state.write().lockdown = Some(stored.status);
+        }
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state.read().lockdown.is_some()
+    }
+
+    /// Engage lockdown from a verified `lockdown` command
+    pub fn engage(&self, args: &Value) -> Result<()> {
+        let otp_key = args.get("otp_secret").and_then(Value::as_str);
+        if let Some(key) = otp_key {
+            decode_base32(key).context("Invalid lockdown otp_secret")?;
+        }
+        let status = LockdownStatus {
+            since: Utc::now(),
+            reason: args.get("reason").and_then(Value::as_str).map(str::to_string),
+            local_disarm: otp_key.is_some(),
+        };
+        let stored = StoredLockdown {
+            status: status.clone(),
+            otp_key: otp_key.map(str::to_string),
+        };
+        SecretStore::load(&self.secrets_path)?.set(LOCKDOWN_SECRET, &serde_json::to_vec(&stored)?)?;
+        warn!(reason = ?status.reason, local_disarm = status.local_disarm, "Lockdown engaged");
+        self.state.write().lockdown = Some(status.clone());
+        self.emit(Event::Lockdown { active: true, reason: status.reason });
+        Ok(())
+    }
+
+    /// Lift lockdown from a verified `lift_lockdown` command
+    pub fn lift(&self) -> Result<()> {
+        SecretStore::load(&self.secrets_path)?.remove(LOCKDOWN_SECRET)?;
+        if self.state.write().lockdown.take().is_some() {
+            warn!("Lockdown lifted");
+            self.emit(Event::Lockdown { active: false, reason: None });
+        }
+        Ok(())
+    }
+
+    /// Whether `code` is the current one-time code of the lockdown in force
+    pub fn verify_code(&self, code: &str) -> Result<bool> {
+        let Some(key) = self.stored()?.and_then(|stored| stored.otp_key) else {
+            return Ok(false);
+        };
+        let key = decode_base32(&key)?;
+        let step = Utc::now().timestamp() / TOTP_STEP_S;
+        Ok((-TOTP_DRIFT_STEPS..=TOTP_DRIFT_STEPS)
+            .any(|drift| constant_time_eq(totp(&key, step + drift).as_bytes(), code.as_bytes())))
+    }
+
+    fn stored(&self) -> Result<Option<StoredLockdown>> {
+        SecretStore::load(&self.secrets_path)?
+            .get(LOCKDOWN_SECRET)?
+            .map(|json| serde_json::from_slice(&json).context("Stored lockdown is not valid JSON"))
+            .transpose()
+    }
+
+    fn emit(&self, event: Event) {
+        if let Err(e) = self.event_bus.emit(event) {
+            warn!(error = %e, "Failed to report lockdown");
+        }
+    }
+}
+
+/// RFC 6238 code for a time step, with HMAC-SHA1 as authenticator apps expect
+pub(super) fn totp(key: &[u8], step: i64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key);
+    let digest = hmac::sign(&key, &step.to_be_bytes());
+    let digest = digest.as_ref();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]]) & 0x7fff_ffff;
+    format!("{:0width$}", value % 10u32.pow(TOTP_DIGITS), width = TOTP_DIGITS as usize)
+}
+
+/// RFC 4648 base32, as TOTP keys are written, without padding
+pub(super) fn decode_base32(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.trim_end_matches('=').chars().filter(|c| !c.is_whitespace()) {
+        let value = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u32 - 'A' as u32,
+            c @ '2'..='7' => c as u32 - '2' as u32 + 26,
+            c => bail!("Invalid base32 character {:?}", c),
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    if bytes.is_empty() {
+        bail!("Empty base32 key");
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::new_app_state;
+    use serde_json::json;
+
+    #[test]
+    fn test_totp_matches_rfc_6238() {
+        // The SHA-1 test key of RFC 6238, "12345678901234567890"
+        let key = decode_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+        assert_eq!(key, b"12345678901234567890");
+        assert_eq!(totp(&key, 59 / TOTP_STEP_S), "287082");
+        assert_eq!(totp(&key, 1_111_111_109 / TOTP_STEP_S), "081804");
+        assert!(decode_base32("not base32!").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_engage_restore_and_lift() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::test_default();
+        config.system.data_dir = dir.path().to_path_buf();
+        let (bus, mut rx) = EventBus::new();
+        let lockdown = Lockdown::new(&config, new_app_state(), bus.clone());
+
+        let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        lockdown.engage(&json!({"otp_secret": secret, "reason": "fob stolen"})).unwrap();
+        assert!(lockdown.is_active());
+        assert!(matches!(rx.recv().await.unwrap(), Event::Lockdown { active: true, .. }));
+        let code = totp(&decode_base32(secret).unwrap(), Utc::now().timestamp() / TOTP_STEP_S);
+        assert!(lockdown.verify_code(&code).unwrap());
+        assert!(!lockdown.verify_code("000000").unwrap() || code == "000000");
+
+        // A restart keeps the lockdown
+        let restarted = Lockdown::new(&config, new_app_state(), bus);
+        restarted.restore().unwrap();
+        let status = restarted.state.read().lockdown.clone().unwrap();
+        assert_eq!(status.reason.as_deref(), Some("fob stolen"));
+        assert!(status.local_disarm);
+
+        restarted.lift().unwrap();
+        assert!(!restarted.is_active());
+        assert!(!restarted.verify_code(&code).unwrap());
+        assert!(lockdown.engage(&json!({"otp_secret": "1!"})).is_err());
+    }
+}
@@ -0,0 +1,292 @@
+//! Door reader authentication: enrolled cards and PINs arm and disarm the system
+//!
+//! Credentials live in a small JSON file that the API edits and the reader
+//! re-reads on every presentation, so enrollments apply without a restart.
+
+mod lockdown;
+mod pins;
+mod wiegand;
+
+pub use lockdown::{Lockdown, LockdownStatus, LIFT_LOCKDOWN, LOCKDOWN};
+pub use pins::{PinGuard, PinLockout, PinRejection};
+pub use wiegand::{decode, WiegandData, WiegandReader};
+
+use crate::config::AppConfig;
+use crate::events::{AccessMethod, Event, EventBus, EventSource};
+use crate::security::{AuditEntry, AuditLog, AuditSource};
+use crate::state::{AlarmState, AppState, ArmMode};
+use anyhow::{bail, Context, Result};
+use pins::{hash_pin, validate_pin, verify_pin};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// What one user can present at the reader
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserCredentials {
+    #[serde(default)]
+    pub card: Option<u64>,
+    /// Argon2id PHC string, or `salt:sha256(salt || pin)` from before
+    #[serde(default)]
+    pub pin_hash: Option<String>,
+}
+
+/// Enrolled credential summary, without secrets
+#[derive(Debug, Clone, Serialize)]
+pub struct UserSummary {
+    pub name: String,
+    pub card: Option<u64>,
+    pub has_pin: bool,
+}
+
+/// Enrolled users keyed by name, persisted as JSON
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CredentialStore {
+    #[serde(skip)]
+    path: PathBuf,
+    users: BTreeMap<String, UserCredentials>,
+}
+
+impl CredentialStore {
+    /// Load the store, starting empty if the file does not exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut store: Self = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid credentials file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        store.path = path.to_path_buf();
+        Ok(store)
+    }
+
+    /// Write the store atomically so the reader never sees a partial file
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path).with_context(|| format!("Failed to replace {}", self.path.display()))
+    }
+
+    /// Add or update a user; fields left as `None` keep their current value
+    pub fn enroll(&mut self, name: &str, card: Option<u64>, pin: Option<&str>) -> Result<()> {
+        if name.is_empty() {
+            bail!("User name cannot be empty");
+        }
+        if card.is_none() && pin.is_none() && !self.users.contains_key(name) {
+            bail!("A new user needs a card or a PIN");
+        }
+        if let Some(card) = card {
+            if let Some(owner) = self.find_card(card).filter(|owner| *owner != name) {
+                bail!("Card {} is already enrolled to {}", card, owner);
+            }
+        }
+        let pin_hash = match pin {
+            Some(pin) => {
+                validate_pin(pin)?;
+                if let Some(owner) = self.find_pin(pin).filter(|owner| *owner != name) {
+                    bail!("PIN is already enrolled to {}", owner);
+                }
+                Some(hash_pin(pin)?)
+            }
+            None => None,
+        };
+
+        let user = self.users.entry(name.to_string()).or_default();
+        if card.is_some() {
+            user.card = card;
+        }
+        if pin_hash.is_some() {
+            user.pin_hash = pin_hash;
+        }
+        Ok(())
+    }
+
+    /// Remove a user; returns false if no such user was enrolled
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.users.remove(name).is_some()
+    }
+
+    pub fn users(&self) -> Vec<UserSummary> {
+        self.users
+            .iter()
+            .map(|(name, user)| UserSummary {
+                name: name.clone(),
+                card: user.card,
+                has_pin: user.pin_hash.is_some(),
+            })
+            .collect()
+    }
+
+    /// Owner of a card number
+    pub fn find_card(&self, card: u64) -> Option<&str> {
+        self.users
+            .iter()
+            .find(|(_, user)| user.card == Some(card))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Owner of a PIN
+    pub fn find_pin(&self, pin: &str) -> Option<&str> {
+        self.users
+            .iter()
+            .find(|(_, user)| user.pin_hash.as_deref().is_some_and(|stored| verify_pin(pin, stored)))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Something presented at the door reader
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credential {
+    Card(u64),
+    Pin(String),
+}
+
+/// Checks presented credentials and toggles the alarm for accepted ones
+#[derive(Clone)]
+pub struct AccessControl {
+    state: AppState,
+    event_bus: EventBus,
+    guard: PinGuard,
+    audit: Option<Arc<AuditLog>>,
+}
+
+impl AccessControl {
+    pub fn new(config: &AppConfig, state: AppState, event_bus: EventBus) -> Self {
+        Self {
+            guard: PinGuard::new(config, state.clone(), event_bus.clone()),
+            state,
+            event_bus,
+            audit: None,
+        }
+    }
+
+    /// Record every credential presented, and what it was meant to do, in the audit log
+    pub fn set_audit(&mut self, audit: Arc<AuditLog>) {
+        self.audit = Some(audit);
+    }
+
+    /// Authenticate a credential; accepted ones disarm when armed and arm when disarmed
+    pub fn present(&self, credential: Credential) -> Result<()> {
+        let arm = self.state.read().alarm_state == AlarmState::Disarmed;
+        let mut user = self.authenticate(&credential)?;
+        // A keypad cannot take the one-time code a disarm needs under lockdown
+        if !arm && user.is_ok() && self.state.read().lockdown.is_some() {
+            user = Err(PinRejection::Lockdown.to_string());
+        }
+        if let Some(audit) = &self.audit {
+            let source = AuditSource::Keypad { user: user.as_ref().ok().cloned() };
+            audit.record(AuditEntry::new(if arm { "arm" } else { "disarm" }, source, &user));
+        }
+        if user.is_err() {
+            return Ok(());
+        }
+
+        let event = if arm {
+            Event::UserArm {
+                source: EventSource::Keypad,
+                exit_delay_s: None,
+                mode: ArmMode::Away,
+            }
+        } else {
+            Event::UserDisarm {
+                source: EventSource::Keypad,
+                auto_rearm_s: None,
+            }
+        };
+        self.event_bus.emit(event)
+    }
+
+    /// The user a credential belongs to, or why it was refused
+    fn authenticate(&self, credential: &Credential) -> Result<Result<String, String>> {
+        match credential {
+            Credential::Card(card) => {
+                if let Some(remaining) = self.guard.locked_out() {
+                    self.guard.deny(AccessMethod::Card, Some(*card), EventSource::Keypad);
+                    let remaining_s = remaining.as_secs().max(1);
+                    return Ok(Err(PinRejection::LockedOut { remaining_s }.to_string()));
+                }
+                let store = self.guard.credentials()?;
+                let Some(user) = store.find_card(*card) else {
+                    self.guard.fail(AccessMethod::Card, Some(*card), EventSource::Keypad);
+                    return Ok(Err("Unknown card".to_string()));
+                };
+                self.guard.grant(user, AccessMethod::Card, EventSource::Keypad);
+                Ok(Ok(user.to_string()))
+            }
+            Credential::Pin(pin) => Ok(self
+                .guard
+                .check(Some(pin), EventSource::Keypad)
+                .map_err(|rejection| rejection.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::new_app_state;
+
+    #[test]
+    fn test_store_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.json");
+
+        let mut store = CredentialStore::load(&path).unwrap();
+        store.enroll("alice", Some(65_537), Some("1234")).unwrap();
+        store.enroll("bob", None, Some("987654")).unwrap();
+        assert!(store.enroll("carol", Some(65_537), None).is_err());
+        assert!(store.enroll("carol", None, Some("12a4")).is_err());
+        store.save().unwrap();
+
+        let store = CredentialStore::load(&path).unwrap();
+        assert_eq!(store.find_card(65_537), Some("alice"));
+        assert_eq!(store.find_pin("987654"), Some("bob"));
+        assert_eq!(store.find_pin("0000"), None);
+        // PINs are never written in the clear
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("987654"));
+    }
+
+    #[test]
+    fn test_grant_toggles_and_lockout() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::test_default();
+        config.access.credentials_path = Some(dir.path().join("credentials.json"));
+        config.access.max_failures = 2;
+
+        let mut store = CredentialStore::load(&config.credentials_path()).unwrap();
+        store.enroll("alice", Some(42), None).unwrap();
+        store.save().unwrap();
+
+        let state = new_app_state();
+        let (bus, mut rx) = EventBus::new();
+        let access = AccessControl::new(&config, state.clone(), bus);
+
+        access.present(Credential::Card(42)).unwrap();
+        assert!(matches!(rx.try_recv().unwrap(), Event::AccessGranted { .. }));
+        assert!(matches!(rx.try_recv().unwrap(), Event::UserArm { source: EventSource::Keypad, .. }));
+
+        state.write().set_alarm_state(AlarmState::Armed);
+        access.present(Credential::Card(42)).unwrap();
+        rx.try_recv().unwrap();
+        assert!(matches!(rx.try_recv().unwrap(), Event::UserDisarm { .. }));
+
+        // Two wrong PINs lock the reader, after which even a valid card is refused
+        access.present(Credential::Pin("0000".to_string())).unwrap();
+        access.present(Credential::Pin("1111".to_string())).unwrap();
+        access.present(Credential::Card(42)).unwrap();
+        for _ in 0..2 {
+            assert!(matches!(rx.try_recv().unwrap(), Event::AccessDenied { .. }));
+        }
+        assert!(matches!(rx.try_recv().unwrap(), Event::AccessLockout { .. }));
+        assert!(matches!(
+            rx.try_recv().unwrap(),
+            Event::AccessDenied { card: Some(42), source: Some(EventSource::Keypad), .. }
+        ));
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,418 @@
+//! PIN checks shared by the keypad, BLE and the HTTP API
+//!
+//! PINs are stored as argon2id hashes; the `salt:sha256` hashes of earlier
+//! versions still verify until the user is enrolled again. Wrong PINs count
+//! toward one lockout in the shared state wherever they are entered, so
+//! guessing cannot be spread over the keypad, a phone and the API. Under
+//! lockdown, disarming also takes the lockdown's one-time code. The enrolled
+//! users are read once and again only after the file changes.
+
+use anyhow::{bail, Result};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use parking_lot::Mutex;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+use super::{CredentialStore, Lockdown};
+use crate::config::{AccessConfig, AppConfig};
+use crate::events::{correlated_sync, current_correlation_id, AccessMethod, Event, EventBus, EventSource};
+use crate::state::AppState;
+
+/// Shortest and longest PIN accepted
+const PIN_LENGTH: std::ops::RangeInclusive<usize> = 4..=12;
+
+pub(super) fn validate_pin(pin: &str) -> Result<()> {
+    if !PIN_LENGTH.contains(&pin.len()) || !pin.bytes().all(|b| b.is_ascii_digit()) {
+        bail!("PIN must be {} to {} digits", PIN_LENGTH.start(), PIN_LENGTH.end());
+    }
+    Ok(())
+}
+
+/// Argon2id hash of `pin` in PHC string format
+pub(super) fn hash_pin(pin: &str) -> Result<String> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let salt = SaltString::encode_b64(&salt).map_err(|e| anyhow::anyhow!("Invalid PIN salt: {}", e))?;
+    let hash = Argon2::default()
+        .hash_password(pin.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Failed to hash PIN: {}", e))?;
+    Ok(hash.to_string())
+}
+
+pub(super) fn verify_pin(pin: &str, stored: &str) -> bool {
+    if let Ok(hash) = PasswordHash::new(stored) {
+        return Argon2::default().verify_password(pin.as_bytes(), &hash).is_ok();
+    }
+    // Hashes written before argon2: `salt:sha256(salt || pin)`
+    stored.split_once(':').is_some_and(|(salt, digest)| {
+        let expected = Sha256::new().chain_update(salt).chain_update(pin).finalize();
+        crate::security::constant_time_eq(crate::security::encode_hex(&expected).as_bytes(), digest.as_bytes())
+    })
+}
+
+/// Failed attempts across every place a credential is entered
+#[derive(Debug, Clone, Default)]
+pub struct PinLockout {
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+impl PinLockout {
+    /// Time left until credentials are accepted again
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.locked_until
+            .filter(|until| now < *until)
+            .map(|until| until - now)
+    }
+
+    /// Count a failure; true when it starts a lockout
+    pub fn fail(&mut self, now: Instant, config: &AccessConfig) -> bool {
+        self.failures += 1;
+        if self.failures < config.max_failures {
+            return false;
+        }
+        self.failures = 0;
+        self.locked_until = Some(now + Duration::from_secs(config.lockout_s));
+        true
+    }
+
+    pub fn succeed(&mut self) {
+        self.failures = 0;
+    }
+}
+
+/// Why a PIN was not accepted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinRejection {
+    Missing,
+    Wrong,
+    LockedOut { remaining_s: u64 },
+    /// Disarming from this channel is off during lockdown
+    Lockdown,
+    CodeMissing,
+    WrongCode,
+}
+
+impl std::fmt::Display for PinRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing => write!(f, "A PIN is required"),
+            Self::Wrong => write!(f, "Wrong PIN"),
+            Self::LockedOut { remaining_s } => write!(f, "Too many failed attempts, retry in {} s", remaining_s),
+            Self::Lockdown => write!(f, "Disarming from here is off during lockdown"),
+            Self::CodeMissing => write!(f, "A one-time code is required during lockdown"),
+            Self::WrongCode => write!(f, "Wrong one-time code"),
+        }
+    }
+}
+
+/// What identifies one write of the credentials file: its inode, length and modification time
+///
+/// Saves replace the file, so the inode alone changes on every one of them.
+type FileVersion = Option<(u64, u64, SystemTime)>;
+
+/// The credentials file as last loaded, with the version it was read from
+type CachedCredentials = Option<(FileVersion, Arc<CredentialStore>)>;
+
+#[cfg(unix)]
+fn file_version(path: &Path) -> FileVersion {
+    use std::os::unix::fs::MetadataExt;
+    let meta = std::fs::metadata(path).ok()?;
+    Some((meta.ino(), meta.len(), meta.modified().ok()?))
+}
+
+#[cfg(not(unix))]
+fn file_version(path: &Path) -> FileVersion {
+    let meta = std::fs::metadata(path).ok()?;
+    Some((0, meta.len(), meta.modified().ok()?))
+}
+
+/// Checks PINs against the enrolled users and keeps the shared lockout
+///
+/// Clones share the loaded credentials.
+#[derive(Clone)]
+pub struct PinGuard {
+    config: AccessConfig,
+    path: PathBuf,
+    credentials: Arc<Mutex<CachedCredentials>>,
+    state: AppState,
+    event_bus: EventBus,
+    lockdown: Lockdown,
+}
+
+impl PinGuard {
+    pub fn new(config: &AppConfig, state: AppState, event_bus: EventBus) -> Self {
+        Self {
+            config: config.access.clone(),
+            path: config.credentials_path(),
+            credentials: Arc::new(Mutex::new(None)),
+            lockdown: Lockdown::new(config, state.clone(), event_bus.clone()),
+            state,
+            event_bus,
+        }
+    }
+
+    /// Whether disarming over BLE and the API needs a PIN
+    pub fn required_to_disarm(&self) -> bool {
+        self.config.require_pin_to_disarm
+    }
+
+    /// Whether disarming needs checking, for a PIN or for lockdown
+    pub fn guards_disarm(&self) -> bool {
+        self.required_to_disarm() || self.lockdown.is_active()
+    }
+
+    /// Check what a disarm at `source` carries
+    ///
+    /// That is the PIN when one is required. Under lockdown it is a PIN and the
+    /// lockdown's one-time code, which only the API and WebSocket can send.
+    pub fn check_disarm(&self, pin: Option<&str>, code: Option<&str>, source: EventSource) -> Result<(), PinRejection> {
+        let local_disarm = self.state.read().lockdown.as_ref().map(|lockdown| lockdown.local_disarm);
+        match local_disarm {
+            None if self.required_to_disarm() => return self.check(pin, source).map(|_| ()),
+            None => return Ok(()),
+            Some(true) if matches!(source, EventSource::Local | EventSource::Ws) => {}
+            Some(_) => return Err(PinRejection::Lockdown),
+        }
+        self.check(pin, source)?;
+        let code = code.ok_or(PinRejection::CodeMissing)?;
+        match self.lockdown.verify_code(code) {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                self.fail(AccessMethod::Pin, None, source);
+                Err(PinRejection::WrongCode)
+            }
+            Err(e) => {
+                warn!(error = %e, "Lockdown key unreadable, one-time code refused");
+                Err(PinRejection::WrongCode)
+            }
+        }
+    }
+
+    /// [`check_disarm`](Self::check_disarm) on the blocking pool, since argon2 takes a while
+    pub async fn check_disarm_async(
+        &self,
+        pin: Option<String>,
+        code: Option<String>,
+        source: EventSource,
+    ) -> Result<(), PinRejection> {
+        let guard = self.clone();
+        let correlation_id = current_correlation_id();
+        tokio::task::spawn_blocking(move || {
+            correlated_sync(correlation_id, || guard.check_disarm(pin.as_deref(), code.as_deref(), source))
+        })
+        .await
+        .unwrap_or_else(|e| {
+            warn!(error = %e, "PIN check did not finish, PIN refused");
+            Err(PinRejection::Wrong)
+        })
+    }
+
+    /// Check a PIN entered at `source`, returning who it belongs to
+    ///
+    /// Raises `access_granted` or `access_denied` either way.
+    pub fn check(&self, pin: Option<&str>, source: EventSource) -> Result<String, PinRejection> {
+        if let Some(remaining) = self.locked_out() {
+            self.deny(AccessMethod::Pin, None, source);
+            return Err(PinRejection::LockedOut {
+                remaining_s: remaining.as_secs().max(1),
+            });
+        }
+        let Some(pin) = pin else {
+            return Err(PinRejection::Missing);
+        };
+        let user = self
+            .credentials()
+            .map_err(|e| warn!(error = %e, "Credentials unreadable, PIN refused"))
+            .ok()
+            .and_then(|store| store.find_pin(pin).map(str::to_string));
+        match user {
+            Some(user) => {
+                self.grant(&user, AccessMethod::Pin, source);
+                Ok(user)
+            }
+            None => {
+                self.fail(AccessMethod::Pin, None, source);
+                Err(PinRejection::Wrong)
+            }
+        }
+    }
+
+    /// The enrolled users, loaded again only when the file has changed
+    pub(super) fn credentials(&self) -> Result<Arc<CredentialStore>> {
+        let version = file_version(&self.path);
+        let mut cached = self.credentials.lock();
+        if let Some((_, store)) = cached.as_ref().filter(|(loaded, _)| version.is_some() && *loaded == version) {
+            return Ok(store.clone());
+        }
+        let store = Arc::new(CredentialStore::load(&self.path)?);
+        *cached = Some((version, store.clone()));
+        Ok(store)
+    }
+
+    /// Drop the loaded credentials, after enrolling or removing a user
+    pub fn forget_credentials(&self) {
+        *self.credentials.lock() = None;
+    }
+
+    pub(super) fn locked_out(&self) -> Option<Duration> {
+        self.state.read().pin_lockout.remaining(Instant::now())
+    }
+
+    pub(super) fn grant(&self, user: &str, method: AccessMethod, source: EventSource) {
+        info!(user, ?method, ?source, "Access granted");
+        self.state.write().pin_lockout.succeed();
+        self.emit(Event::AccessGranted {
+            user: user.to_string(),
+            method,
+            source: Some(source),
+        });
+    }
+
+    /// Deny without counting, while already locked out
+    pub(super) fn deny(&self, method: AccessMethod, card: Option<u64>, source: EventSource) {
+        warn!(?method, ?source, "Locked out, credential ignored");
+        self.emit(Event::AccessDenied {
+            method,
+            card,
+            source: Some(source),
+        });
+    }
+
+    pub(super) fn fail(&self, method: AccessMethod, card: Option<u64>, source: EventSource) {
+        warn!(?method, ?card, ?source, "Access denied");
+        let locked = self.state.write().pin_lockout.fail(Instant::now(), &self.config);
+        self.emit(Event::AccessDenied {
+            method,
+            card,
+            source: Some(source),
+        });
+        if locked {
+            warn!(lockout_s = self.config.lockout_s, "Too many failed attempts, locking out credentials");
+            self.emit(Event::AccessLockout {
+                lockout_s: self.config.lockout_s,
+            });
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        if let Err(e) = self.event_bus.emit(event) {
+            warn!(error = %e, "Failed to emit access event");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::new_app_state;
+
+    #[test]
+    fn test_lockout_is_shared_across_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::test_default();
+        config.access.credentials_path = Some(dir.path().join("credentials.json"));
+        config.access.max_failures = 2;
+        let mut store = CredentialStore::load(&config.credentials_path()).unwrap();
+        store.enroll("alice", None, Some("2468")).unwrap();
+        store.save().unwrap();
+
+        let (bus, mut rx) = EventBus::new();
+        let guard = PinGuard::new(&config, new_app_state(), bus);
+        assert_eq!(guard.check(Some("2468"), EventSource::Ble), Ok("alice".to_string()));
+        assert!(matches!(
+            rx.try_recv().unwrap(),
+            Event::AccessGranted { user, source: Some(EventSource::Ble), .. } if user == "alice"
+        ));
+        assert_eq!(guard.check(None, EventSource::Local), Err(PinRejection::Missing));
+
+        // One wrong PIN over BLE and one over the API lock out both
+        assert_eq!(guard.check(Some("1111"), EventSource::Ble), Err(PinRejection::Wrong));
+        assert_eq!(guard.check(Some("2222"), EventSource::Local), Err(PinRejection::Wrong));
+        assert!(matches!(
+            guard.check(Some("2468"), EventSource::Local),
+            Err(PinRejection::LockedOut { .. })
+        ));
+        let events: Vec<Event> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert_eq!(events.iter().filter(|event| matches!(event, Event::AccessDenied { .. })).count(), 3);
+        assert!(events.iter().any(|event| matches!(event, Event::AccessLockout { lockout_s: 60 })));
+    }
+
+    #[tokio::test]
+    async fn test_credentials_reload_after_enrolment() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::test_default();
+        config.access.credentials_path = Some(dir.path().join("credentials.json"));
+        config.access.require_pin_to_disarm = true;
+        let mut store = CredentialStore::load(&config.credentials_path()).unwrap();
+        store.enroll("alice", None, Some("2468")).unwrap();
+        store.save().unwrap();
+
+        let (bus, _rx) = EventBus::new();
+        let guard = PinGuard::new(&config, new_app_state(), bus);
+        let disarm = |pin: &str| guard.check_disarm_async(Some(pin.to_string()), None, EventSource::Ws);
+        assert_eq!(disarm("2468").await, Ok(()));
+        assert!(Arc::ptr_eq(&guard.credentials().unwrap(), &guard.clone().credentials().unwrap()));
+        assert_eq!(disarm("1357").await, Err(PinRejection::Wrong));
+
+        store.enroll("bob", None, Some("1357")).unwrap();
+        store.save().unwrap();
+        assert_eq!(disarm("1357").await, Ok(()));
+
+        // A revoked PIN stops working at once, even within the same mtime tick
+        assert!(store.remove("alice"));
+        store.save().unwrap();
+        assert_eq!(disarm("2468").await, Err(PinRejection::Wrong));
+    }
+
+    #[test]
+    fn test_lockdown_needs_pin_and_code() {
+        use super::super::lockdown::{decode_base32, totp, TOTP_STEP_S};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::test_default();
+        config.system.data_dir = dir.path().to_path_buf();
+        config.access.credentials_path = Some(dir.path().join("credentials.json"));
+        let mut store = CredentialStore::load(&config.credentials_path()).unwrap();
+        store.enroll("alice", None, Some("2468")).unwrap();
+        store.save().unwrap();
+
+        let (bus, _rx) = EventBus::new();
+        let guard = PinGuard::new(&config, new_app_state(), bus);
+        assert!(!guard.guards_disarm());
+        assert_eq!(guard.check_disarm(None, None, EventSource::Ble), Ok(()));
+
+        let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        guard.lockdown.engage(&serde_json::json!({"otp_secret": secret})).unwrap();
+        assert!(guard.guards_disarm());
+        let code = totp(&decode_base32(secret).unwrap(), chrono::Utc::now().timestamp() / TOTP_STEP_S);
+        assert_eq!(guard.check_disarm(Some("2468"), Some(&code), EventSource::Ble), Err(PinRejection::Lockdown));
+        assert_eq!(guard.check_disarm(Some("2468"), None, EventSource::Ws), Err(PinRejection::CodeMissing));
+        assert_eq!(guard.check_disarm(None, Some(&code), EventSource::Ws), Err(PinRejection::Missing));
+        assert_eq!(guard.check_disarm(Some("2468"), Some(&code), EventSource::Local), Ok(()));
+
+        // Without a key only the cloud can disarm
+        guard.lockdown.engage(&serde_json::json!({"reason": "phone stolen"})).unwrap();
+        assert_eq!(guard.check_disarm(Some("2468"), Some(&code), EventSource::Local), Err(PinRejection::Lockdown));
+        guard.lockdown.lift().unwrap();
+        assert_eq!(guard.check_disarm(None, None, EventSource::Ble), Ok(()));
+    }
+
+    #[test]
+    fn test_legacy_hashes_still_verify() {
+        let legacy = "00ff:".to_string()
+            + &crate::security::encode_hex(&Sha256::new().chain_update("00ff").chain_update("1234").finalize());
+        assert!(verify_pin("1234", &legacy));
+        assert!(!verify_pin("4321", &legacy));
+
+        let hash = hash_pin("1234").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_pin("1234", &hash));
+        assert!(!verify_pin("1235", &hash));
+    }
+}
@@ -0,0 +1,261 @@
+//! Wiegand keypad and card reader on two GPIO data lines
+//!
+//! Each bit is a short low pulse on D0 (a zero) or D1 (a one); a pause ends
+//! the frame. Keypads send one 4- or 8-bit frame per key, card readers send
+//! 26- or 34-bit frames with a parity bit at each end.
+
+use super::{AccessControl, Credential};
+use crate::gpio::{Edge, GpioController};
+use anyhow::{bail, Result};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+/// Silence that ends a frame; bits within a frame are about 2 ms apart
+const FRAME_GAP: Duration = Duration::from_millis(25);
+
+/// Keypad codes for the two non-digit keys
+const KEY_ESCAPE: u8 = 10;
+const KEY_ENTER: u8 = 11;
+
+/// Longest PIN buffered before further digits are dropped
+const MAX_PIN_DIGITS: usize = 12;
+
+/// A decoded frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WiegandData {
+    /// Card payload without the parity bits (facility code and card number)
+    Card(u64),
+    /// Keypad key: 0-9, 10 for `*`, 11 for `#`
+    Key(u8),
+}
+
+fn value(bits: &[bool]) -> u64 {
+    bits.iter().fold(0, |acc, &bit| (acc << 1) | bit as u64)
+}
+
+fn ones(bits: &[bool]) -> usize {
+    bits.iter().filter(|&&bit| bit).count()
+}
+
+/// Decode a complete frame
+pub fn decode(bits: &[bool]) -> Result<WiegandData> {
+    match bits.len() {
+        4 => Ok(WiegandData::Key(value(bits) as u8)),
+        8 => {
+            // The high nibble repeats the key inverted
+            let (check, key) = (value(&bits[..4]) as u8, value(&bits[4..]) as u8);
+            if check != !key & 0x0f {
+                bail!("Wiegand keypad frame failed its check nibble");
+            }
+            Ok(WiegandData::Key(key))
+        }
+        26 | 34 => {
+            // Leading even parity over the first half, trailing odd parity over the second
+            let half = bits.len() / 2;
+            if !ones(&bits[..half]).is_multiple_of(2) || ones(&bits[half..]) % 2 != 1 {
+                bail!("Wiegand {}-bit frame failed parity", bits.len());
+            }
+            Ok(WiegandData::Card(value(&bits[1..bits.len() - 1])))
+        }
+        len => bail!("Unsupported Wiegand frame length {}", len),
+    }
+}
+
+/// Collects keypad digits into a PIN, submitted with `#` and cleared with `*`
+struct Keypad {
+    digits: String,
+    last_key: Option<Instant>,
+    timeout: Duration,
+}
+
+impl Keypad {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            digits: String::new(),
+            last_key: None,
+            timeout,
+        }
+    }
+
+    /// Feed a key; returns the entered PIN when `#` is pressed
+    fn press(&mut self, key: u8, now: Instant) -> Option<String> {
+        if self.last_key.is_some_and(|last| now.duration_since(last) > self.timeout) {
+            self.digits.clear();
+        }
+        self.last_key = Some(now);
+
+        match key {
+            0..=9 if self.digits.len() < MAX_PIN_DIGITS => {
+                self.digits.push(char::from(b'0' + key));
+                None
+            }
+            KEY_ESCAPE => {
+                self.digits.clear();
+                None
+            }
+            KEY_ENTER if !self.digits.is_empty() => Some(std::mem::take(&mut self.digits)),
+            _ => None,
+        }
+    }
+}
+
+/// Read the next frame, or `None` once both line watchers have stopped
+async fn read_frame(rx: &mut mpsc::UnboundedReceiver<bool>) -> Option<Vec<bool>> {
+    let mut bits = vec![rx.recv().await?];
+    while let Ok(Some(bit)) = tokio::time::timeout(FRAME_GAP, rx.recv()).await {
+        bits.push(bit);
+    }
+    Some(bits)
+}
+
+/// Decodes reader frames and hands credentials to access control
+pub struct WiegandReader {
+    gpio: Arc<dyn GpioController>,
+    d0: u8,
+    d1: u8,
+    access: AccessControl,
+    keypad: Keypad,
+}
+
+impl WiegandReader {
+    pub fn new(gpio: Arc<dyn GpioController>, d0: u8, d1: u8, access: AccessControl, keypad_timeout_s: u64) -> Self {
+        Self {
+            gpio,
+            d0,
+            d1,
+            access,
+            keypad: Keypad::new(Duration::from_secs(keypad_timeout_s)),
+        }
+    }
+
+    /// Spawn the line watchers and the frame decoder
+    pub fn spawn(mut self) -> JoinHandle<()> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(Self::watch_line(self.gpio.clone(), self.d0, false, tx.clone()));
+        tokio::spawn(Self::watch_line(self.gpio.clone(), self.d1, true, tx));
+
+        tokio::spawn(async move {
+            info!(d0 = self.d0, d1 = self.d1, "Wiegand reader started");
+            while let Some(bits) = read_frame(&mut rx).await {
+                let Some(credential) = self.credential(&bits) else {
+                    continue;
+                };
+                // Argon2 takes a while; keep it off the async workers
+                let access = self.access.clone();
+                match tokio::task::spawn_blocking(move || access.present(credential)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!(error = %e, "Failed to check credential"),
+                    Err(e) => warn!(error = %e, "Credential check did not finish"),
+                }
+            }
+        })
+    }
+
+    /// Send one bit per pulse; lines are active-low, so a pulse reads as a rising edge
+    async fn watch_line(gpio: Arc<dyn GpioController>, pin: u8, bit: bool, tx: mpsc::UnboundedSender<bool>) {
+        loop {
+            match gpio.wait_for_input_edge(pin).await {
+                Ok(Edge::Rising) => {
+                    if tx.send(bit).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    error!(pin, error = %e, "Wiegand line watcher failed");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// The credential a frame completes, if any
+    fn credential(&mut self, bits: &[bool]) -> Option<Credential> {
+        match decode(bits) {
+            Ok(WiegandData::Card(card)) => Some(Credential::Card(card)),
+            Ok(WiegandData::Key(key)) => self.keypad.press(key, Instant::now()).map(Credential::Pin),
+            Err(e) => {
+                debug!(bits = bits.len(), error = %e, "Discarding Wiegand frame");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(value: u64, len: usize) -> Vec<bool> {
+        (0..len).rev().map(|i| value >> i & 1 == 1).collect()
+    }
+
+    /// Wrap a payload in the leading even and trailing odd parity bits
+    fn card_frame(payload: u64, len: usize) -> Vec<bool> {
+        let data = bits(payload, len - 2);
+        let half = len / 2 - 1;
+        let mut frame = vec![ones(&data[..half]) % 2 == 1];
+        frame.extend(&data);
+        frame.push(ones(&data[half..]).is_multiple_of(2));
+        frame
+    }
+
+    #[test]
+    fn test_decode_cards() {
+        // Facility 1, card 1
+        let frame = card_frame(0x01_0001, 26);
+        assert_eq!(decode(&frame).unwrap(), WiegandData::Card(0x01_0001));
+
+        let frame = card_frame(0xdead_beef, 34);
+        assert_eq!(decode(&frame).unwrap(), WiegandData::Card(0xdead_beef));
+
+        let mut corrupt = card_frame(0x01_0001, 26);
+        corrupt[5] = !corrupt[5];
+        assert!(decode(&corrupt).is_err());
+        assert!(decode(&bits(1, 30)).is_err());
+    }
+
+    #[test]
+    fn test_decode_keys() {
+        assert_eq!(decode(&bits(7, 4)).unwrap(), WiegandData::Key(7));
+        assert_eq!(decode(&bits(0x4b, 8)).unwrap(), WiegandData::Key(KEY_ENTER));
+        assert!(decode(&bits(0x0b, 8)).is_err());
+    }
+
+    #[test]
+    fn test_keypad_pin_entry() {
+        let mut keypad = Keypad::new(Duration::from_secs(10));
+        let start = Instant::now();
+
+        for key in [9, 9, KEY_ESCAPE, 1, 2, 3, 4] {
+            assert_eq!(keypad.press(key, start), None);
+        }
+        assert_eq!(keypad.press(KEY_ENTER, start).as_deref(), Some("1234"));
+
+        // A pause longer than the timeout discards the digits typed before it
+        keypad.press(5, start);
+        let later = start + Duration::from_secs(11);
+        keypad.press(6, later);
+        assert_eq!(keypad.press(KEY_ENTER, later).as_deref(), Some("6"));
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_splits_on_gap() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        for bit in [true, false, true, true] {
+            tx.send(bit).unwrap();
+        }
+        let sender = tokio::spawn(async move {
+            tokio::time::sleep(FRAME_GAP * 3).await;
+            tx.send(false).unwrap();
+        });
+
+        assert_eq!(read_frame(&mut rx).await.unwrap(), vec![true, false, true, true]);
+        assert_eq!(read_frame(&mut rx).await.unwrap(), vec![false]);
+        sender.await.unwrap();
+        assert!(read_frame(&mut rx).await.is_none());
+    }
+}
@@ -0,0 +1,153 @@
+//! Piezo buzzer beeping along with the entry and exit countdowns
+
+use crate::config::GpioConfig;
+use crate::events::TimerId;
+use crate::gpio::GpioController;
+use crate::state::{AlarmState, ArmMode, SharedState};
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::error;
+
+/// Seconds before the exit delay ends at which the beeping speeds up
+const EXIT_WARNING_S: u64 = 10;
+
+/// Beeps for one countdown second as (on, milliseconds) steps
+fn beeps_for(state: &SharedState, id: &TimerId, remaining_s: u64) -> &'static [(bool, u64)] {
+    match (id, state.alarm_state) {
+        (TimerId::ExitDelay, AlarmState::ExitDelay) if remaining_s > EXIT_WARNING_S => &[(true, 100)],
+        (TimerId::ExitDelay, AlarmState::ExitDelay) => {
+            &[(true, 100), (false, 150), (true, 100), (false, 150), (true, 100)]
+        }
+        (TimerId::EntryDelay, AlarmState::EntryDelay) => &[(true, 250), (false, 250), (true, 250)],
+        _ => &[],
+    }
+}
+
+/// Drives a piezo buzzer from the per-second countdown ticks
+pub struct Buzzer {
+    gpio: Arc<dyn GpioController>,
+    pin: u8,
+    tone: Option<(f64, f64)>,
+    quiet_at_night: bool,
+    current: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Buzzer {
+    pub fn new(gpio: Arc<dyn GpioController>, pin: u8, config: &GpioConfig) -> Self {
+        Self {
+            gpio,
+            pin,
+            tone: config.buzzer_hz.map(|hz| (hz, config.buzzer_duty)),
+            quiet_at_night: config.buzzer_quiet_at_night,
+            current: Mutex::new(None),
+        }
+    }
+
+    /// Beep for one second of a countdown
+    pub fn tick(&self, state: &SharedState, id: &TimerId, remaining_s: u64) {
+        if self.quiet_at_night && state.arm_mode == ArmMode::Night {
+            return;
+        }
+
+        let steps = beeps_for(state, id, remaining_s);
+        if steps.is_empty() {
+            return;
+        }
+
+        let gpio = self.gpio.clone();
+        let (pin, tone) = (self.pin, self.tone);
+        let handle = tokio::spawn(async move {
+            if let Err(e) = beep(gpio.as_ref(), pin, tone, steps).await {
+                error!(error = %e, "Buzzer beep failed");
+            }
+        });
+
+        if let Some(previous) = self.current.lock().replace(handle) {
+            previous.abort();
+        }
+    }
+}
+
+impl Drop for Buzzer {
+    fn drop(&mut self) {
+        if let Some(handle) = self.current.lock().take() {
+            handle.abort();
+        }
+    }
+}
+
+/// Play a beep sequence and leave the buzzer silent
+async fn beep(
+    gpio: &dyn GpioController,
+    pin: u8,
+    tone: Option<(f64, f64)>,
+    steps: &[(bool, u64)],
+) -> Result<()> {
+    for &(on, ms) in steps {
+        match tone {
+            Some((hz, duty)) if on => gpio.set_output_tone(pin, hz, duty).await?,
+            _ => gpio.set_output(pin, on).await?,
+        }
+        tokio::time::sleep(Duration::from_millis(ms)).await;
+    }
+    gpio.set_output(pin, false).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::gpio::MockGpio;
+
+    #[test]
+    fn test_exit_beeps_accelerate() {
+        let mut state = SharedState::new();
+        state.set_alarm_state(AlarmState::ExitDelay);
+
+        let slow = beeps_for(&state, &TimerId::ExitDelay, 20);
+        let fast = beeps_for(&state, &TimerId::ExitDelay, 5);
+        assert!(fast.iter().filter(|(on, _)| *on).count() > slow.iter().filter(|(on, _)| *on).count());
+
+        // Stale ticks from a countdown that no longer applies stay silent
+        assert!(beeps_for(&state, &TimerId::EntryDelay, 5).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tick_beeps_with_tone() {
+        let gpio = Arc::new(MockGpio::new());
+        let mut config = AppConfig::test_default().gpio;
+        config.buzzer_hz = Some(4000.0);
+        config.buzzer_duty = 0.2;
+        let buzzer = Buzzer::new(gpio.clone(), 21, &config);
+
+        let mut state = SharedState::new();
+        state.set_alarm_state(AlarmState::EntryDelay);
+        buzzer.tick(&state, &TimerId::EntryDelay, 10);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(gpio.output_tone(21), Some((4000.0, 0.2)));
+
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(gpio.output_tone(21), None);
+        assert!(!gpio.get_output_state(21).await.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_quiet_at_night() {
+        let gpio = Arc::new(MockGpio::new());
+        let mut config = AppConfig::test_default().gpio;
+        config.buzzer_quiet_at_night = true;
+        let buzzer = Buzzer::new(gpio.clone(), 21, &config);
+
+        let mut state = SharedState::new();
+        state.set_alarm_state(AlarmState::ExitDelay);
+        state.set_arm_mode(ArmMode::Night);
+        buzzer.tick(&state, &TimerId::ExitDelay, 20);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!gpio.get_output_state(21).await.unwrap());
+    }
+}
@@ -0,0 +1,172 @@
+//! Status LED whose blink pattern encodes the system state
+
+use crate::gpio::GpioController;
+use crate::state::{AlarmState, CloudStatus, SharedState};
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, error};
+
+/// Blink pattern shown on the status LED
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedPattern {
+    Off,
+    /// Armed
+    Solid,
+    /// Exit delay running
+    SlowBlink,
+    /// Entry delay or alarm
+    FastBlink,
+    /// Cloud link configured but offline
+    DoubleBlink,
+}
+
+impl LedPattern {
+    /// Pick the pattern for the current state, most urgent first
+    pub fn for_state(state: &SharedState, cloud_enabled: bool) -> Self {
+        match state.alarm_state {
+            AlarmState::Alarm | AlarmState::EntryDelay => LedPattern::FastBlink,
+            AlarmState::ExitDelay => LedPattern::SlowBlink,
+            _ if cloud_enabled && state.connectivity.cloud != CloudStatus::Online => {
+                LedPattern::DoubleBlink
+            }
+            AlarmState::Armed => LedPattern::Solid,
+            AlarmState::Disarmed => LedPattern::Off,
+        }
+    }
+
+    /// One repeating cycle of the pattern as (on, milliseconds) steps
+    fn steps(&self) -> &'static [(bool, u64)] {
+        match self {
+            LedPattern::Off | LedPattern::Solid => &[],
+            LedPattern::SlowBlink => &[(true, 500), (false, 500)],
+            LedPattern::FastBlink => &[(true, 100), (false, 100)],
+            LedPattern::DoubleBlink => &[(true, 150), (false, 150), (true, 150), (false, 1050)],
+        }
+    }
+}
+
+/// Plays blink patterns on the status LED output
+pub struct StatusLed {
+    gpio: Arc<dyn GpioController>,
+    pin: u8,
+    cloud_enabled: bool,
+    current: Mutex<Option<(LedPattern, Option<JoinHandle<()>>)>>,
+}
+
+impl StatusLed {
+    pub fn new(gpio: Arc<dyn GpioController>, pin: u8, cloud_enabled: bool) -> Self {
+        Self {
+            gpio,
+            pin,
+            cloud_enabled,
+            current: Mutex::new(None),
+        }
+    }
+
+    /// Pattern currently shown
+    pub fn current(&self) -> Option<LedPattern> {
+        self.current.lock().as_ref().map(|(pattern, _)| *pattern)
+    }
+
+    /// Show the pattern matching the given state, leaving an unchanged pattern running
+    pub async fn update(&self, state: &SharedState) -> Result<()> {
+        self.apply(LedPattern::for_state(state, self.cloud_enabled)).await
+    }
+
+    async fn apply(&self, pattern: LedPattern) -> Result<()> {
+        {
+            let mut current = self.current.lock();
+            if current.as_ref().map(|(pattern, _)| *pattern) == Some(pattern) {
+                return Ok(());
+            }
+            if let Some((_, Some(handle))) = current.take() {
+                handle.abort();
+            }
+        }
+        debug!(?pattern, "Status LED pattern changed");
+
+        let handle = match pattern {
+            LedPattern::Off | LedPattern::Solid => {
+                self.gpio.set_output(self.pin, pattern == LedPattern::Solid).await?;
+                None
+            }
+            _ => {
+                let gpio = self.gpio.clone();
+                let pin = self.pin;
+                Some(tokio::spawn(async move {
+                    if let Err(e) = blink(gpio.as_ref(), pin, pattern).await {
+                        error!(error = %e, "Status LED blink failed");
+                    }
+                }))
+            }
+        };
+        *self.current.lock() = Some((pattern, handle));
+
+        Ok(())
+    }
+}
+
+impl Drop for StatusLed {
+    fn drop(&mut self) {
+        if let Some((_, Some(handle))) = self.current.lock().take() {
+            handle.abort();
+        }
+    }
+}
+
+/// Repeat a blink pattern until aborted
+async fn blink(gpio: &dyn GpioController, pin: u8, pattern: LedPattern) -> Result<()> {
+    loop {
+        for &(on, ms) in pattern.steps() {
+            gpio.set_output(pin, on).await?;
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::MockGpio;
+
+    #[test]
+    fn test_pattern_for_state() {
+        let mut state = SharedState::new();
+        assert_eq!(LedPattern::for_state(&state, false), LedPattern::Off);
+        assert_eq!(LedPattern::for_state(&state, true), LedPattern::DoubleBlink);
+
+        state.set_alarm_state(AlarmState::ExitDelay);
+        assert_eq!(LedPattern::for_state(&state, true), LedPattern::SlowBlink);
+
+        state.set_alarm_state(AlarmState::Armed);
+        assert_eq!(LedPattern::for_state(&state, false), LedPattern::Solid);
+
+        state.set_alarm_state(AlarmState::Alarm);
+        assert_eq!(LedPattern::for_state(&state, true), LedPattern::FastBlink);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_blink_and_solid() {
+        let gpio = Arc::new(MockGpio::new());
+        let led = StatusLed::new(gpio.clone(), 20, false);
+        let mut state = SharedState::new();
+
+        state.set_alarm_state(AlarmState::ExitDelay);
+        led.update(&state).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(gpio.get_output_state(20).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!gpio.get_output_state(20).await.unwrap());
+
+        state.set_alarm_state(AlarmState::Armed);
+        led.update(&state).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert!(gpio.get_output_state(20).await.unwrap());
+        assert_eq!(led.current(), Some(LedPattern::Solid));
+    }
+}
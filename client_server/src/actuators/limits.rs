@@ -0,0 +1,208 @@
+//! Hard on-time and cooldown limits, enforced just before GPIO is driven
+//!
+//! These sit below the state machine on purpose: whatever the events ask for,
+//! a stuck-on siren is cut after its maximum on-time and a relay is not
+//! re-energised before its cooldown has passed.
+
+use crate::config::{ActuatorLimits, GpioConfig};
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Which limit an actuator ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitKind {
+    /// On longer than `max_on_s`; held off until the request drops
+    MaxOn,
+    /// Asked to switch on again within `min_off_s`
+    Cooldown,
+}
+
+/// A single enforced limit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LimitViolation {
+    pub actuator: String,
+    pub kind: LimitKind,
+    pub at: DateTime<Utc>,
+}
+
+/// Limit summary reported by the health endpoint
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LimitStatus {
+    /// Actuators currently forced off after exceeding their maximum on-time
+    pub tripped: Vec<String>,
+    pub violations: u64,
+    pub last_violation: Option<LimitViolation>,
+}
+
+#[derive(Debug, Default)]
+struct Channel {
+    on_since: Option<Instant>,
+    off_since: Option<Instant>,
+    tripped: bool,
+    cooling_down: bool,
+}
+
+/// Tracks each limited actuator and decides the level it may actually be driven to
+#[derive(Debug)]
+pub struct DutyLimiter {
+    limits: BTreeMap<String, ActuatorLimits>,
+    channels: Mutex<BTreeMap<String, Channel>>,
+    status: Mutex<LimitStatus>,
+}
+
+impl DutyLimiter {
+    /// Build limits for every output the GPIO configuration drives
+    pub fn from_config(config: &GpioConfig) -> Self {
+        let limits = config
+            .output_pins()
+            .into_iter()
+            .filter_map(|(name, _)| {
+                let limits = config.limits_for(&name)?;
+                Some((name, limits))
+            })
+            .collect();
+        Self {
+            limits,
+            channels: Mutex::new(BTreeMap::new()),
+            status: Mutex::new(LimitStatus::default()),
+        }
+    }
+
+    /// Level the actuator may be driven to when `requested` is asked for at `now`
+    pub fn gate(&self, name: &str, requested: bool, now: Instant) -> bool {
+        let Some(limits) = self.limits.get(name) else {
+            return requested;
+        };
+        let mut channels = self.channels.lock();
+        let channel = channels.entry(name.to_string()).or_default();
+
+        if !requested {
+            if channel.on_since.take().is_some() {
+                channel.off_since = Some(now);
+            }
+            channel.cooling_down = false;
+            if channel.tripped {
+                channel.tripped = false;
+                self.status.lock().tripped.retain(|tripped| tripped != name);
+            }
+            return false;
+        }
+        if channel.tripped {
+            return false;
+        }
+
+        let seconds = Duration::from_secs;
+        match channel.on_since {
+            Some(since) if limits.max_on_s.is_some_and(|max| now.duration_since(since) >= seconds(max)) => {
+                warn!(actuator = name, max_on_s = limits.max_on_s, "Actuator exceeded its maximum on-time, forcing off");
+                channel.on_since = None;
+                channel.off_since = Some(now);
+                channel.tripped = true;
+                self.record(name, LimitKind::MaxOn);
+                false
+            }
+            Some(_) => true,
+            None => {
+                let cooling = limits.min_off_s.is_some_and(|min| {
+                    channel.off_since.is_some_and(|off| now.duration_since(off) < seconds(min))
+                });
+                if cooling {
+                    if !channel.cooling_down {
+                        warn!(actuator = name, min_off_s = limits.min_off_s, "Actuator still cooling down, holding off");
+                        channel.cooling_down = true;
+                        self.record(name, LimitKind::Cooldown);
+                    }
+                    return false;
+                }
+                channel.cooling_down = false;
+                channel.on_since = Some(now);
+                true
+            }
+        }
+    }
+
+    /// Whether a limit may change an actuator without any new request
+    pub fn pending(&self) -> bool {
+        self.channels
+            .lock()
+            .values()
+            .any(|channel| channel.on_since.is_some() || channel.cooling_down)
+    }
+
+    pub fn status(&self) -> LimitStatus {
+        self.status.lock().clone()
+    }
+
+    fn record(&self, name: &str, kind: LimitKind) {
+        let mut status = self.status.lock();
+        if kind == LimitKind::MaxOn {
+            status.tripped.push(name.to_string());
+        }
+        status.violations += 1;
+        status.last_violation = Some(LimitViolation {
+            actuator: name.to_string(),
+            kind,
+            at: Utc::now(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> DutyLimiter {
+        let mut config = crate::config::AppConfig::test_default().gpio;
+        config.limits.insert(
+            "floodlight_out".to_string(),
+            ActuatorLimits {
+                max_on_s: None,
+                min_off_s: Some(10),
+            },
+        );
+        DutyLimiter::from_config(&config)
+    }
+
+    #[test]
+    fn test_siren_cut_after_default_max_on() {
+        let limiter = limiter();
+        let start = Instant::now();
+
+        assert!(limiter.gate("siren_out", true, start));
+        assert!(limiter.gate("siren_out", true, start + Duration::from_secs(899)));
+        assert!(!limiter.gate("siren_out", true, start + Duration::from_secs(900)));
+        // Stays off while the stuck request persists
+        assert!(!limiter.gate("siren_out", true, start + Duration::from_secs(2000)));
+        assert_eq!(limiter.status().tripped, vec!["siren_out"]);
+
+        // Dropping the request clears the trip
+        assert!(!limiter.gate("siren_out", false, start + Duration::from_secs(2001)));
+        assert!(limiter.status().tripped.is_empty());
+        assert!(limiter.gate("siren_out", true, start + Duration::from_secs(2002)));
+        assert_eq!(limiter.status().violations, 1);
+    }
+
+    #[test]
+    fn test_cooldown_holds_off() {
+        let limiter = limiter();
+        let start = Instant::now();
+
+        assert!(limiter.gate("floodlight_out", true, start));
+        assert!(!limiter.gate("floodlight_out", false, start + Duration::from_secs(1)));
+        assert!(!limiter.gate("floodlight_out", true, start + Duration::from_secs(5)));
+        assert!(!limiter.gate("floodlight_out", true, start + Duration::from_secs(6)));
+        assert!(limiter.gate("floodlight_out", true, start + Duration::from_secs(11)));
+
+        let status = limiter.status();
+        assert_eq!(status.violations, 1);
+        assert_eq!(status.last_violation.unwrap().kind, LimitKind::Cooldown);
+
+        // Actuators without limits pass straight through
+        assert!(limiter.gate("strobe_out", true, start));
+    }
+}
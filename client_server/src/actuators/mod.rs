@@ -1,39 +1,336 @@
 //! Actuator control module
 
+mod buzzer;
+mod led;
+mod limits;
+mod siren;
+
+pub use buzzer::Buzzer;
+pub use led::{LedPattern, StatusLed};
+pub use limits::{DutyLimiter, LimitKind, LimitStatus, LimitViolation};
+pub use siren::{SirenDriver, SirenPattern};
+
+use crate::config::AppConfig;
+use crate::events::{Event, EventEnvelope};
 use crate::gpio::GpioController;
+use crate::rf433::RfTransmitter;
 use crate::state::{ActuatorState, AppState};
-use anyhow::Result;
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
-use tracing::debug;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::{debug, error, warn};
+
+/// How often time-based limits are re-evaluated while an actuator is on or cooling down
+const LIMIT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Lookup of configured auxiliary outputs by name
+#[derive(Debug, Clone, Default)]
+pub struct OutputRegistry {
+    outputs: BTreeMap<String, u8>,
+    rf: BTreeSet<String>,
+    pulses: BTreeMap<String, u64>,
+}
+
+impl OutputRegistry {
+    /// Build the registry from the `gpio.outputs` and `rf433.outputs` configuration
+    pub fn from_config(config: &AppConfig) -> Self {
+        let outputs = config
+            .gpio
+            .outputs
+            .iter()
+            .map(|output| (output.name.clone(), output.pin))
+            .collect();
+        let rf = config.rf433.outputs.iter().map(|output| output.name.clone()).collect();
+        let gpio_pulses = config.gpio.outputs.iter().map(|output| (&output.name, output.pulse_ms));
+        let rf_pulses = config.rf433.outputs.iter().map(|output| (&output.name, output.pulse_ms));
+        let pulses = gpio_pulses
+            .chain(rf_pulses)
+            .filter_map(|(name, pulse_ms)| Some((name.clone(), pulse_ms?)))
+            .collect();
+        Self { outputs, rf, pulses }
+    }
+
+    /// Whether an output with this name is configured
+    pub fn contains(&self, name: &str) -> bool {
+        self.outputs.contains_key(name) || self.rf.contains(name)
+    }
+
+    /// Whether the named output is a wireless receiver switched over 433 MHz
+    pub fn is_rf(&self, name: &str) -> bool {
+        self.rf.contains(name)
+    }
+
+    /// GPIO pin driving the named output
+    pub fn pin(&self, name: &str) -> Option<u8> {
+        self.outputs.get(name).copied()
+    }
+
+    /// Pulse length of a pulse-mode output, `None` for latching outputs
+    pub fn pulse_ms(&self, name: &str) -> Option<u64> {
+        self.pulses.get(name).copied()
+    }
+
+    /// Names of all configured outputs
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.outputs.keys().chain(&self.rf).map(String::as_str)
+    }
+
+    /// Reject a remote command switching an output that is not configured, or turning on a pulse-only one
+    pub fn check_command(&self, event: &Event) -> Result<()> {
+        if let Event::OutputControl { name, on, .. } = event {
+            if !self.contains(name) {
+                bail!("Unknown output: {}", name);
+            }
+            if *on && self.pulse_ms(name).is_some() {
+                bail!("Output {} is pulse-only", name);
+            }
+        }
+        Ok(())
+    }
+}
 
-/// Actuator controller manages siren and floodlight outputs
+/// Actuator controller manages siren, floodlight, status LED and auxiliary outputs
 pub struct ActuatorController {
     gpio: Arc<dyn GpioController>,
     state: AppState,
+    outputs: OutputRegistry,
+    siren: SirenDriver,
+    status_led: Option<StatusLed>,
+    buzzer: Option<Buzzer>,
+    strobe_out: Option<u8>,
+    rf: Option<RfTransmitter>,
+    limiter: DutyLimiter,
 }
 
 impl ActuatorController {
-    pub fn new(gpio: Arc<dyn GpioController>, state: AppState) -> Self {
-        Self { gpio, state }
+    pub fn new(gpio: Arc<dyn GpioController>, state: AppState, config: &AppConfig) -> Self {
+        let status_led = config
+            .gpio
+            .status_led_out
+            .map(|pin| StatusLed::new(gpio.clone(), pin, !config.cloud.url.is_empty()));
+
+        let buzzer = config
+            .gpio
+            .buzzer_out
+            .map(|pin| Buzzer::new(gpio.clone(), pin, &config.gpio));
+
+        let rf = config
+            .gpio
+            .radio433_tx_out
+            .filter(|_| !config.rf433.outputs.is_empty())
+            .map(|pin| RfTransmitter::new(gpio.clone(), pin, &config.rf433));
+
+        Self {
+            siren: SirenDriver::new(gpio.clone(), &config.gpio),
+            buzzer,
+            outputs: OutputRegistry::from_config(config),
+            status_led,
+            strobe_out: config.gpio.strobe_out,
+            rf,
+            limiter: DutyLimiter::from_config(&config.gpio),
+            gpio,
+            state,
+        }
     }
 
     /// Update actuators based on current state
     pub async fn update(&self) -> Result<()> {
-        let target_state = {
-            let state = self.state.read();
-            state.actuators
-        };
+        let snapshot = self.state.read().clone();
 
-        self.apply_state(target_state).await
+        let siren = self.apply_state(snapshot.actuators, snapshot.siren_pattern).await?;
+        if let Some(led) = &self.status_led {
+            led.update(&snapshot).await?;
+        }
+        self.apply_outputs(&snapshot.outputs, siren).await?;
+
+        let limits = self.limiter.status();
+        if limits.violations != snapshot.actuator_limits.violations || limits.tripped != snapshot.actuator_limits.tripped {
+            self.state.write().set_actuator_limits(limits);
+        }
+        Ok(())
     }
 
-    /// Apply actuator state to GPIO
-    async fn apply_state(&self, target: ActuatorState) -> Result<()> {
-        debug!(?target, "Applying actuator state");
+    /// Keep GPIO in step with state, re-applying after every processed event
+    pub async fn run(self, mut rx: broadcast::Receiver<EventEnvelope>) {
+        // Limits expire with time alone, so re-check them while any are in play
+        let mut limit_check = tokio::time::interval(LIMIT_CHECK_INTERVAL);
 
-        self.gpio.set_siren(target.siren).await?;
-        self.gpio.set_floodlight(target.floodlight).await?;
+        loop {
+            let result = tokio::select! {
+                received = rx.recv() => match received {
+                    Ok(envelope) => self.handle(&envelope.event).await,
+                    // Only the latest state matters, so a lagged receiver just re-applies it
+                    Err(broadcast::error::RecvError::Lagged(_)) => self.update().await,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = limit_check.tick() => {
+                    if !self.limiter.pending() {
+                        continue;
+                    }
+                    self.update().await
+                }
+            };
+            if let Err(e) = result {
+                error!(error = %e, "Failed to update actuators");
+            }
+        }
+    }
+
+    /// React to a processed event
+    async fn handle(&self, event: &Event) -> Result<()> {
+        // Countdown ticks only drive the buzzer; outputs are unchanged
+        if let Event::TimerTick { id, remaining_s } = event {
+            if let Some(buzzer) = &self.buzzer {
+                let state = self.state.read().clone();
+                buzzer.tick(&state, id, *remaining_s);
+            }
+            return Ok(());
+        }
+
+        self.update().await
+    }
+
+    /// Apply actuator state to GPIO, within the safety limits; returns whether the siren sounds
+    async fn apply_state(&self, target: ActuatorState, pattern: SirenPattern) -> Result<bool> {
+        debug!(?target, %pattern, "Applying actuator state");
+        let now = Instant::now();
+
+        let siren = self.limiter.gate("siren_out", target.siren, now);
+        self.siren.apply(siren, pattern).await?;
+        self.gpio
+            .set_floodlight(self.limiter.gate("floodlight_out", target.floodlight, now))
+            .await?;
+        if let Some(pin) = self.strobe_out {
+            self.gpio.set_output(pin, self.limiter.gate("strobe_out", target.strobe, now)).await?;
+        }
+
+        Ok(siren)
+    }
+
+    /// Apply auxiliary output state to GPIO and wireless receivers
+    async fn apply_outputs(&self, targets: &BTreeMap<String, bool>, siren: bool) -> Result<()> {
+        let now = Instant::now();
+        for (name, on) in targets {
+            match self.outputs.pin(name) {
+                Some(pin) => self.gpio.set_output(pin, self.limiter.gate(name, *on, now)).await?,
+                None if self.outputs.is_rf(name) => {}
+                None => warn!(output = %name, "Ignoring unknown output"),
+            }
+        }
+
+        let Some(rf) = &self.rf else {
+            return Ok(());
+        };
+        for output in rf.outputs() {
+            let mut on = targets.get(&output.name).copied();
+            if output.follow_siren {
+                on = Some(siren || on.unwrap_or(false));
+            }
+            // Outputs never switched are left alone, as the receiver's state is unknown
+            if let Some(on) = on {
+                rf.switch(&output.name, self.limiter.gate(&output.name, on, now)).await?;
+            }
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OutputConfig;
+    use crate::gpio::MockGpio;
+    use crate::state::new_app_state;
+
+    #[tokio::test]
+    async fn test_update_drives_outputs() {
+        let mut config = AppConfig::test_default();
+        config.gpio.strobe_out = Some(26);
+        config.gpio.status_led_out = Some(20);
+        config.gpio.outputs.push(OutputConfig {
+            name: "gate".to_string(),
+            pin: 5,
+            pulse_ms: None,
+        });
+
+        let gpio = Arc::new(MockGpio::new());
+        let state = new_app_state();
+        let controller = ActuatorController::new(gpio.clone(), state.clone(), &config);
+
+        {
+            let mut s = state.write();
+            s.set_output("gate", true);
+            s.set_output("unknown", true);
+            s.actuators.floodlight = true;
+            s.actuators.strobe = true;
+        }
+        controller.update().await.unwrap();
+
+        assert!(gpio.get_output_state(5).await.unwrap());
+        assert!(gpio.get_floodlight_state().await.unwrap());
+        assert!(gpio.get_output_state(26).await.unwrap());
+        // Disarmed without a cloud link: LED off
+        assert!(!gpio.get_output_state(20).await.unwrap());
+        assert!(!gpio.get_siren_state().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_rf_outputs() {
+        let mut config = AppConfig::test_default();
+        config.gpio.radio433_tx_out = Some(24);
+        config.rf433.outputs.push(crate::config::RfOutputConfig {
+            name: "yard_siren".to_string(),
+            on_code: "0x0A0A01".to_string(),
+            off_code: Some("0x0A0A02".to_string()),
+            pulse_us: 350,
+            repeats: 8,
+            pulse_ms: None,
+            follow_siren: true,
+        });
+
+        let gpio = Arc::new(MockGpio::new());
+        let state = new_app_state();
+        let controller = ActuatorController::new(gpio.clone(), state.clone(), &config);
+        assert!(OutputRegistry::from_config(&config).contains("yard_siren"));
+
+        // Silenced once at start, then sounded with the alarm siren
+        controller.update().await.unwrap();
+        state.write().actuators.siren = true;
+        controller.update().await.unwrap();
+        controller.update().await.unwrap();
+
+        let codes: Vec<_> = [0x0A0A02, 0x0A0A01]
+            .into_iter()
+            .map(|code| crate::rf433::encode(code, 350, 8))
+            .collect();
+        assert_eq!(gpio.pulse_trains(24), codes);
+    }
+
+    #[tokio::test]
+    async fn test_stuck_siren_forced_off() {
+        let mut config = AppConfig::test_default();
+        config.gpio.limits.insert(
+            "siren_out".to_string(),
+            crate::config::ActuatorLimits {
+                max_on_s: Some(1),
+                min_off_s: None,
+            },
+        );
+
+        let gpio = Arc::new(MockGpio::new());
+        let state = new_app_state();
+        let controller = ActuatorController::new(gpio.clone(), state.clone(), &config);
+
+        state.write().actuators.siren = true;
+        controller.update().await.unwrap();
+        assert!(gpio.get_siren_state().await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(1050)).await;
+        controller.update().await.unwrap();
+        assert!(!gpio.get_siren_state().await.unwrap());
+        assert_eq!(state.read().actuator_limits.tripped, vec!["siren_out"]);
+    }
+}
@@ -0,0 +1,231 @@
+//! Siren cadence patterns and the task that plays them
+
+use crate::config::GpioConfig;
+use crate::gpio::GpioController;
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, error};
+
+/// On/off cadence the siren is driven with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SirenPattern {
+    /// Continuous tone
+    #[default]
+    Steady,
+    /// Burglar cadence: half a second on, half a second off
+    Pulsed,
+    /// Fire evacuation cadence (ANSI/NFPA temporal-3): three beeps then a pause
+    Temporal3,
+    /// Single short double-chirp, e.g. for arm confirmation
+    Chirp,
+}
+
+impl SirenPattern {
+    /// One cycle of the cadence as (on, milliseconds) steps
+    pub fn steps(&self) -> &'static [(bool, u64)] {
+        match self {
+            SirenPattern::Steady => &[(true, 1000)],
+            SirenPattern::Pulsed => &[(true, 500), (false, 500)],
+            SirenPattern::Temporal3 => &[
+                (true, 500),
+                (false, 500),
+                (true, 500),
+                (false, 500),
+                (true, 500),
+                (false, 1500),
+            ],
+            SirenPattern::Chirp => &[(true, 100), (false, 100), (true, 100), (false, 0)],
+        }
+    }
+
+    /// Whether the cycle repeats until the siren is switched off
+    pub fn repeats(&self) -> bool {
+        !matches!(self, SirenPattern::Chirp)
+    }
+
+    /// Length of one cycle of the cadence
+    pub fn cycle(&self) -> Duration {
+        Duration::from_millis(self.steps().iter().map(|(_, ms)| ms).sum())
+    }
+}
+
+impl std::fmt::Display for SirenPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SirenPattern::Steady => write!(f, "steady"),
+            SirenPattern::Pulsed => write!(f, "pulsed"),
+            SirenPattern::Temporal3 => write!(f, "temporal3"),
+            SirenPattern::Chirp => write!(f, "chirp"),
+        }
+    }
+}
+
+/// Optional PWM tone used while the siren is sounding
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Tone {
+    frequency_hz: f64,
+    duty_cycle: f64,
+}
+
+/// Plays siren cadences on the GPIO siren output
+pub struct SirenDriver {
+    gpio: Arc<dyn GpioController>,
+    tone: Option<Tone>,
+    current: Mutex<Option<(SirenPattern, Option<JoinHandle<()>>)>>,
+}
+
+impl SirenDriver {
+    pub fn new(gpio: Arc<dyn GpioController>, config: &GpioConfig) -> Self {
+        let tone = config.siren_pwm_hz.map(|frequency_hz| Tone {
+            frequency_hz,
+            duty_cycle: config.siren_pwm_duty,
+        });
+
+        Self {
+            gpio,
+            tone,
+            current: Mutex::new(None),
+        }
+    }
+
+    /// Pattern currently being played, if the siren is on
+    pub fn current(&self) -> Option<SirenPattern> {
+        self.current.lock().as_ref().map(|(pattern, _)| *pattern)
+    }
+
+    /// Switch the siren on with the given pattern, or off.
+    ///
+    /// Re-applying the pattern that is already playing leaves the cadence
+    /// running undisturbed.
+    pub async fn apply(&self, on: bool, pattern: SirenPattern) -> Result<()> {
+        let target = on.then_some(pattern);
+        {
+            let mut current = self.current.lock();
+            if current.as_ref().map(|(pattern, _)| *pattern) == target {
+                return Ok(());
+            }
+            if let Some((_, Some(handle))) = current.take() {
+                handle.abort();
+            }
+        }
+
+        match target {
+            None => self.gpio.set_siren(false).await?,
+            Some(SirenPattern::Steady) => {
+                drive(self.gpio.as_ref(), self.tone, true).await?;
+                *self.current.lock() = Some((SirenPattern::Steady, None));
+            }
+            Some(pattern) => {
+                debug!(%pattern, "Starting siren cadence");
+                let gpio = self.gpio.clone();
+                let tone = self.tone;
+                let handle = tokio::spawn(async move {
+                    if let Err(e) = play(gpio.as_ref(), tone, pattern).await {
+                        error!(error = %e, %pattern, "Siren cadence failed");
+                    }
+                });
+                *self.current.lock() = Some((pattern, Some(handle)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for SirenDriver {
+    fn drop(&mut self) {
+        if let Some((_, Some(handle))) = self.current.lock().take() {
+            handle.abort();
+        }
+    }
+}
+
+/// Switch the siren output, using the PWM tone when one is configured
+async fn drive(gpio: &dyn GpioController, tone: Option<Tone>, on: bool) -> Result<()> {
+    match tone {
+        Some(tone) if on => gpio.set_siren_tone(tone.frequency_hz, tone.duty_cycle).await,
+        _ => gpio.set_siren(on).await,
+    }
+}
+
+/// Play a cadence until aborted, or once for non-repeating patterns
+async fn play(gpio: &dyn GpioController, tone: Option<Tone>, pattern: SirenPattern) -> Result<()> {
+    loop {
+        for &(on, ms) in pattern.steps() {
+            drive(gpio, tone, on).await?;
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+        }
+        if !pattern.repeats() {
+            return gpio.set_siren(false).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::gpio::MockGpio;
+
+    #[test]
+    fn test_pattern_cycles() {
+        assert_eq!(SirenPattern::Pulsed.cycle(), Duration::from_secs(1));
+        assert_eq!(SirenPattern::Temporal3.cycle(), Duration::from_millis(4000));
+        assert!(!SirenPattern::Chirp.repeats());
+        assert_eq!(
+            serde_json::to_string(&SirenPattern::Temporal3).unwrap(),
+            "\"temporal3\""
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pulsed_cadence_toggles_siren() {
+        let gpio = Arc::new(MockGpio::new());
+        let driver = SirenDriver::new(gpio.clone(), &AppConfig::test_default().gpio);
+
+        driver.apply(true, SirenPattern::Pulsed).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(gpio.get_siren_state().await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!gpio.get_siren_state().await.unwrap());
+
+        driver.apply(false, SirenPattern::Pulsed).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!gpio.get_siren_state().await.unwrap());
+        assert_eq!(driver.current(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_steady_uses_pwm_tone() {
+        let gpio = Arc::new(MockGpio::new());
+        let mut config = AppConfig::test_default().gpio;
+        config.siren_pwm_hz = Some(2800.0);
+        let driver = SirenDriver::new(gpio.clone(), &config);
+
+        driver.apply(true, SirenPattern::Steady).await.unwrap();
+        assert_eq!(gpio.siren_tone(), Some((2800.0, 0.5)));
+
+        driver.apply(false, SirenPattern::Steady).await.unwrap();
+        assert_eq!(gpio.siren_tone(), None);
+        assert!(!gpio.get_siren_state().await.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chirp_stops_by_itself() {
+        let gpio = Arc::new(MockGpio::new());
+        let driver = SirenDriver::new(gpio.clone(), &AppConfig::test_default().gpio);
+
+        driver.apply(true, SirenPattern::Chirp).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(gpio.get_siren_state().await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!gpio.get_siren_state().await.unwrap());
+    }
+}
@@ -0,0 +1,46 @@
+//! MCP3008 8-channel 10-bit ADC on a Linux spidev node
+
+use super::AdcReader;
+use crate::config::AdcConfig;
+use anyhow::{bail, Context, Result};
+use spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
+use tracing::info;
+
+/// MCP3008 reached through `/dev/spidevB.C`
+pub struct Mcp3008 {
+    spi: Spidev,
+}
+
+impl Mcp3008 {
+    /// Open and configure the SPI device
+    pub fn open(config: &AdcConfig) -> Result<Self> {
+        info!(dev = %config.spi_dev.display(), hz = config.spi_hz, "Opening MCP3008 ADC");
+
+        let mut spi = Spidev::open(&config.spi_dev)
+            .with_context(|| format!("Failed to open SPI device {}", config.spi_dev.display()))?;
+        let options = SpidevOptions::new()
+            .bits_per_word(8)
+            .max_speed_hz(config.spi_hz)
+            .mode(SpiModeFlags::SPI_MODE_0)
+            .build();
+        spi.configure(&options).context("Failed to configure SPI device")?;
+
+        Ok(Self { spi })
+    }
+}
+
+impl AdcReader for Mcp3008 {
+    fn read_raw(&mut self, channel: u8) -> Result<u16> {
+        if channel > 7 {
+            bail!("MCP3008 channel {} out of range", channel);
+        }
+
+        // Start bit, then single-ended mode and the channel in the high nibble
+        let tx = [0x01, (0x08 | channel) << 4, 0x00];
+        let mut rx = [0u8; 3];
+        let mut transfer = SpidevTransfer::read_write(&tx, &mut rx);
+        self.spi.transfer(&mut transfer).context("SPI transfer failed")?;
+
+        Ok((((rx[1] & 0x03) as u16) << 8) | rx[2] as u16)
+    }
+}
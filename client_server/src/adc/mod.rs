@@ -0,0 +1,215 @@
+//! Analog inputs through an MCP3008 SPI ADC, including backup battery monitoring
+
+#[cfg(feature = "adc")]
+mod mcp3008;
+
+#[cfg(feature = "adc")]
+pub use mcp3008::Mcp3008;
+
+use crate::config::{AdcConfig, BatteryConfig};
+use crate::events::{Event, EventBus};
+use crate::state::AppState;
+use anyhow::Result;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Full-scale code of the 10-bit converter
+const FULL_SCALE: f64 = 1023.0;
+
+/// Source of raw conversions, one channel at a time
+pub trait AdcReader: Send {
+    /// Raw 10-bit conversion of a single-ended channel (0-7)
+    fn read_raw(&mut self, channel: u8) -> Result<u16>;
+}
+
+/// Convert a raw code to the voltage on the input pin
+pub fn raw_to_volts(raw: u16, vref: f64) -> f64 {
+    raw as f64 * vref / FULL_SCALE
+}
+
+/// Where the system is currently drawing power from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PowerSource {
+    Mains,
+    Battery,
+    LowBattery,
+}
+
+/// Turns supply voltage readings into power events, with hysteresis on recovery
+struct BatteryTracker {
+    config: BatteryConfig,
+    source: PowerSource,
+}
+
+impl BatteryTracker {
+    fn new(config: BatteryConfig) -> Self {
+        Self {
+            config,
+            source: PowerSource::Mains,
+        }
+    }
+
+    fn update(&mut self, voltage: f64) -> Vec<Event> {
+        let mut events = Vec::new();
+        let restored = voltage >= self.config.on_battery_below_v + self.config.hysteresis_v;
+
+        if self.source == PowerSource::Mains && voltage < self.config.on_battery_below_v {
+            self.source = PowerSource::Battery;
+            events.push(Event::PowerLost { voltage });
+        }
+        if self.source == PowerSource::Battery && voltage < self.config.low_below_v {
+            self.source = PowerSource::LowBattery;
+            events.push(Event::LowBattery { voltage });
+        }
+        // A low battery only recovers by charging, which needs mains back
+        if self.source != PowerSource::Mains && restored {
+            self.source = PowerSource::Mains;
+            events.push(Event::PowerRestored { voltage });
+        }
+
+        events
+    }
+}
+
+/// Periodically samples the configured channels into state and reports power changes
+pub struct AdcMonitor {
+    reader: Box<dyn AdcReader>,
+    config: AdcConfig,
+    state: AppState,
+    event_bus: EventBus,
+}
+
+impl AdcMonitor {
+    pub fn new(reader: Box<dyn AdcReader>, config: AdcConfig, state: AppState, event_bus: EventBus) -> Self {
+        Self {
+            reader,
+            config,
+            state,
+            event_bus,
+        }
+    }
+
+    /// Open the ADC described by the configuration
+    pub fn open(config: AdcConfig, state: AppState, event_bus: EventBus) -> Result<Self> {
+        #[cfg(feature = "adc")]
+        {
+            let reader = Mcp3008::open(&config)?;
+            Ok(Self::new(Box::new(reader), config, state, event_bus))
+        }
+
+        #[cfg(not(feature = "adc"))]
+        {
+            let _ = (config, state, event_bus);
+            anyhow::bail!("ADC support is not compiled in; rebuild with the adc feature")
+        }
+    }
+
+    /// Spawn the sampling task
+    pub fn spawn(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            info!(channels = self.config.channels.len(), "ADC monitor started");
+            let mut battery = self.config.battery.clone().map(BatteryTracker::new);
+            let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_s));
+
+            loop {
+                interval.tick().await;
+                self.sample(battery.as_mut());
+            }
+        })
+    }
+
+    fn sample(&mut self, mut battery: Option<&mut BatteryTracker>) {
+        for channel in &self.config.channels {
+            // A conversion is a three-byte SPI transfer, short enough to run inline
+            let raw = match self.reader.read_raw(channel.channel) {
+                Ok(raw) => raw,
+                Err(e) => {
+                    warn!(channel = %channel.name, error = %e, "ADC reading failed");
+                    continue;
+                }
+            };
+            let value = raw_to_volts(raw, self.config.vref) * channel.scale;
+            self.state.write().set_analog(&channel.name, value);
+
+            let Some(tracker) = battery.as_deref_mut() else {
+                continue;
+            };
+            if tracker.config.channel != channel.name {
+                continue;
+            }
+            for event in tracker.update(value) {
+                if let Err(e) = self.event_bus.emit(event) {
+                    warn!(error = %e, "Failed to emit power event");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AnalogChannelConfig;
+    use crate::state::new_app_state;
+
+    struct FixedReader(u16);
+
+    impl AdcReader for FixedReader {
+        fn read_raw(&mut self, _channel: u8) -> Result<u16> {
+            Ok(self.0)
+        }
+    }
+
+    fn battery() -> BatteryConfig {
+        BatteryConfig {
+            channel: "supply".to_string(),
+            on_battery_below_v: 13.0,
+            low_below_v: 11.8,
+            hysteresis_v: 0.2,
+        }
+    }
+
+    #[test]
+    fn test_battery_transitions() {
+        let mut tracker = BatteryTracker::new(battery());
+        assert!(tracker.update(13.8).is_empty());
+        assert!(matches!(tracker.update(12.6)[..], [Event::PowerLost { .. }]));
+        assert!(matches!(tracker.update(11.5)[..], [Event::LowBattery { .. }]));
+
+        // Within the hysteresis band mains is not yet considered back
+        assert!(tracker.update(13.1).is_empty());
+        assert!(matches!(tracker.update(13.8)[..], [Event::PowerRestored { .. }]));
+
+        // A sudden drop straight to flat reports both steps
+        assert!(matches!(
+            tracker.update(11.0)[..],
+            [Event::PowerLost { .. }, Event::LowBattery { .. }]
+        ));
+    }
+
+    #[test]
+    fn test_sample_scales_into_state() {
+        let config = AdcConfig {
+            enabled: true,
+            channels: vec![AnalogChannelConfig {
+                name: "supply".to_string(),
+                channel: 0,
+                scale: 5.7,
+            }],
+            battery: Some(battery()),
+            ..Default::default()
+        };
+        let state = new_app_state();
+        let (bus, mut rx) = EventBus::new();
+        // 682 / 1023 * 3.3 V = 2.2 V on the pin, 12.54 V at the supply
+        let mut monitor = AdcMonitor::new(Box::new(FixedReader(682)), config.clone(), state.clone(), bus);
+        let mut tracker = BatteryTracker::new(battery());
+
+        monitor.sample(Some(&mut tracker));
+
+        let supply = state.read().power.analog["supply"];
+        assert!((supply - 12.54).abs() < 0.01);
+        assert!(matches!(rx.try_recv().unwrap(), Event::PowerLost { .. }));
+    }
+}
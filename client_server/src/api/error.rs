@@ -7,6 +7,13 @@ use axum::{
 };
 use serde_json::json;
 
+use crate::access::PinRejection;
+use crate::config::ConfigError;
+
+/// Message of an `ApiError`, kept on its response for the audit log
+#[derive(Debug, Clone)]
+pub struct ErrorMessage(pub String);
+
 #[derive(Debug)]
 pub struct ApiError {
     pub message: String,
@@ -18,9 +25,35 @@ impl IntoResponse for ApiError {
         let body = Json(json!({
             "error": self.message,
             "code": self.status.as_u16(),
+            "request_id": super::current_request_id(),
         }));
 
-        (self.status, body).into_response()
+        let mut response = (self.status, body).into_response();
+        response.extensions_mut().insert(ErrorMessage(self.message));
+        response
+    }
+}
+
+/// A configuration update that failed validation, answered with a 400 listing every setting at fault
+#[derive(Debug)]
+pub struct InvalidConfig {
+    pub message: String,
+    pub errors: Vec<ConfigError>,
+}
+
+impl IntoResponse for InvalidConfig {
+    fn into_response(self) -> Response {
+        let status = StatusCode::BAD_REQUEST;
+        let body = Json(json!({
+            "error": self.message,
+            "code": status.as_u16(),
+            "errors": self.errors,
+            "request_id": super::current_request_id(),
+        }));
+
+        let mut response = (status, body).into_response();
+        response.extensions_mut().insert(ErrorMessage(self.message));
+        response
     }
 }
 
@@ -32,3 +65,20 @@ impl From<anyhow::Error> for ApiError {
         }
     }
 }
+
+impl From<PinRejection> for ApiError {
+    fn from(rejection: PinRejection) -> Self {
+        let status = match rejection {
+            PinRejection::LockedOut { .. } => StatusCode::TOO_MANY_REQUESTS,
+            PinRejection::Missing
+            | PinRejection::Wrong
+            | PinRejection::Lockdown
+            | PinRejection::CodeMissing
+            | PinRejection::WrongCode => StatusCode::FORBIDDEN,
+        };
+        ApiError {
+            message: rejection.to_string(),
+            status,
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Door reader credential enrollment endpoints
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::access::{CredentialStore, UserSummary};
+use crate::api::{ApiContext, ApiError};
+
+#[derive(Deserialize)]
+pub struct EnrollRequest {
+    /// Card payload as reported in `access_denied` events
+    #[serde(default)]
+    pub card: Option<u64>,
+    #[serde(default)]
+    pub pin: Option<String>,
+}
+
+fn load_store(ctx: &ApiContext) -> Result<CredentialStore, ApiError> {
+    Ok(CredentialStore::load(&ctx.config.credentials_path())?)
+}
+
+/// GET /v1/access/users - List enrolled users without their secrets
+pub async fn list_users(State(ctx): State<Arc<ApiContext>>) -> Result<Json<Vec<UserSummary>>, ApiError> {
+    Ok(Json(load_store(&ctx)?.users()))
+}
+
+/// PUT /v1/access/users/:name - Enroll a card and/or PIN for a user
+pub async fn enroll_user(
+    State(ctx): State<Arc<ApiContext>>,
+    Path(name): Path<String>,
+    Json(req): Json<EnrollRequest>,
+) -> Result<(StatusCode, Json<UserSummary>), ApiError> {
+    info!(user = %name, card = ?req.card, pin = req.pin.is_some(), "Received enrollment request");
+
+    let mut store = load_store(&ctx)?;
+    store
+        .enroll(&name, req.card, req.pin.as_deref())
+        .map_err(|e| ApiError {
+            message: e.to_string(),
+            status: StatusCode::BAD_REQUEST,
+        })?;
+    store.save()?;
+    ctx.pins.forget_credentials();
+
+    let user = store
+        .users()
+        .into_iter()
+        .find(|user| user.name == name)
+        .expect("enrolled user is listed");
+    Ok((StatusCode::OK, Json(user)))
+}
+
+/// DELETE /v1/access/users/:name - Revoke all credentials of a user
+pub async fn remove_user(
+    State(ctx): State<Arc<ApiContext>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    info!(user = %name, "Received user removal request");
+
+    let mut store = load_store(&ctx)?;
+    if !store.remove(&name) {
+        return Err(ApiError {
+            message: format!("Unknown user: {}", name),
+            status: StatusCode::NOT_FOUND,
+        });
+    }
+    store.save()?;
+    ctx.pins.forget_credentials();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::events::EventBus;
+    use crate::state::new_app_state;
+
+    #[tokio::test]
+    async fn test_enroll_list_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::test_default();
+        config.access.credentials_path = Some(dir.path().join("credentials.json"));
+        let (event_bus, _rx) = EventBus::new();
+        let ctx = Arc::new(ApiContext::for_test(new_app_state(), event_bus, config));
+
+        let req = EnrollRequest {
+            card: Some(65_537),
+            pin: Some("2468".to_string()),
+        };
+        let (status, user) = enroll_user(State(ctx.clone()), Path("alice".to_string()), Json(req))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(user.has_pin);
+
+        let req = EnrollRequest {
+            card: None,
+            pin: Some("12".to_string()),
+        };
+        let result = enroll_user(State(ctx.clone()), Path("bob".to_string()), Json(req)).await;
+        assert_eq!(result.err().unwrap().status, StatusCode::BAD_REQUEST);
+
+        let users = list_users(State(ctx.clone())).await.unwrap().0;
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].card, Some(65_537));
+
+        assert_eq!(
+            remove_user(State(ctx.clone()), Path("alice".to_string())).await.unwrap(),
+            StatusCode::NO_CONTENT
+        );
+        let result = remove_user(State(ctx), Path("alice".to_string())).await;
+        assert_eq!(result.err().unwrap().status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_credentials_need_api_key() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::test_default();
+        config.access.credentials_path = Some(dir.path().join("credentials.json"));
+        config.system.api_key = Some("secret-token".to_string());
+        let (event_bus, _rx) = EventBus::new();
+        let app = crate::api::create_router(ApiContext::for_test(new_app_state(), event_bus, config));
+        let request = |method: &str, uri: &str, token: Option<&str>| {
+            let mut request = Request::builder().method(method).uri(uri).header("content-type", "application/json");
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.body(Body::from(r#"{"pin": "2468"}"#)).unwrap()
+        };
+
+        for (method, uri) in [
+            ("GET", "/v1/access/users"),
+            ("PUT", "/v1/access/users/mallory"),
+            ("DELETE", "/v1/access/users/alice"),
+        ] {
+            let response = app.clone().oneshot(request(method, uri, None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+        }
+        let token = Some("secret-token");
+        let response = app.clone().oneshot(request("PUT", "/v1/access/users/alice", token)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.oneshot(request("DELETE", "/v1/access/users/alice", token)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+}
@@ -1,17 +1,36 @@
 //! Actuator control endpoint handlers
 
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tracing::info;
 
+use crate::actuators::{OutputRegistry, SirenPattern};
 use crate::api::{ApiContext, ApiError};
 use crate::events::Event;
+use crate::state::AlarmState;
 
 #[derive(Deserialize)]
 pub struct SirenRequest {
     pub on: bool,
     pub duration_s: Option<u64>,
+    #[serde(default)]
+    pub pattern: Option<SirenPattern>,
+}
+
+#[derive(Deserialize)]
+pub struct SirenTestRequest {
+    pub pattern: SirenPattern,
+}
+
+#[derive(Serialize)]
+pub struct SirenTestResponse {
+    pub pattern: SirenPattern,
+    pub duration_s: u64,
 }
 
 #[derive(Serialize)]
@@ -32,6 +51,25 @@ pub struct FloodlightResponse {
     pub duration_s: Option<u64>,
 }
 
+#[derive(Deserialize)]
+pub struct OutputRequest {
+    pub on: bool,
+    pub duration_s: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct OutputResponse {
+    pub output: String,
+    pub on: bool,
+    pub duration_s: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct PulseResponse {
+    pub output: String,
+    pub duration_ms: u64,
+}
+
 #[derive(Serialize)]
 pub struct ActuatorsStatus {
     pub siren: bool,
@@ -49,6 +87,7 @@ pub async fn control_siren(
     let event = Event::SirenControl {
         on: req.on,
         duration_s: req.duration_s,
+        pattern: req.pattern,
     };
     
     ctx.event_bus.emit(event).map_err(|e| ApiError {
@@ -71,6 +110,42 @@ pub async fn control_siren(
     ))
 }
 
+/// POST /v1/siren/test - Play one cycle of a siren pattern
+pub async fn test_siren(
+    State(ctx): State<Arc<ApiContext>>,
+    Json(req): Json<SirenTestRequest>,
+) -> Result<(StatusCode, Json<SirenTestResponse>), ApiError> {
+    info!(pattern = %req.pattern, "Received siren test request");
+
+    // A test must never cut short a real alarm, so only allow it while disarmed
+    if ctx.state.read().alarm_state != AlarmState::Disarmed {
+        return Err(ApiError {
+            message: "Siren test is only allowed while disarmed".to_string(),
+            status: StatusCode::CONFLICT,
+        });
+    }
+
+    let duration_s = req.pattern.cycle().as_secs_f64().ceil().max(1.0) as u64;
+    let event = Event::SirenControl {
+        on: true,
+        duration_s: Some(duration_s),
+        pattern: Some(req.pattern),
+    };
+
+    ctx.event_bus.emit(event).map_err(|e| ApiError {
+        message: format!("Failed to emit siren test event: {}", e),
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+    })?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(SirenTestResponse {
+            pattern: req.pattern,
+            duration_s,
+        }),
+    ))
+}
+
 /// POST /v1/floodlight - Control floodlight
 pub async fn control_floodlight(
     State(ctx): State<Arc<ApiContext>>,
@@ -104,6 +179,154 @@ pub async fn control_floodlight(
     ))
 }
 
+/// POST /v1/outputs/:name - Control a named auxiliary output
+pub async fn control_output(
+    State(ctx): State<Arc<ApiContext>>,
+    Path(name): Path<String>,
+    Json(req): Json<OutputRequest>,
+) -> Result<(StatusCode, Json<OutputResponse>), ApiError> {
+    info!(output = %name, on = req.on, duration_s = ?req.duration_s, "Received output control request");
+
+    let registry = OutputRegistry::from_config(&ctx.config);
+    if !registry.contains(&name) {
+        return Err(ApiError {
+            message: format!("Unknown output: {}", name),
+            status: StatusCode::NOT_FOUND,
+        });
+    }
+    if req.on && registry.pulse_ms(&name).is_some() {
+        return Err(ApiError {
+            message: format!("Output {} is pulse-only; use /v1/outputs/{}/pulse", name, name),
+            status: StatusCode::BAD_REQUEST,
+        });
+    }
+
+    let event = Event::OutputControl {
+        name: name.clone(),
+        on: req.on,
+        duration_s: req.duration_s,
+    };
+
+    ctx.event_bus.emit(event).map_err(|e| ApiError {
+        message: format!("Failed to emit output control event: {}", e),
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+    })?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(OutputResponse {
+            output: name,
+            on: req.on,
+            duration_s: req.duration_s,
+        }),
+    ))
+}
+
+/// POST /v1/outputs/:name/pulse - Pulse a pulse-mode output
+pub async fn pulse_output(
+    State(ctx): State<Arc<ApiContext>>,
+    Path(name): Path<String>,
+) -> Result<(StatusCode, Json<PulseResponse>), ApiError> {
+    info!(output = %name, "Received output pulse request");
+
+    let registry = OutputRegistry::from_config(&ctx.config);
+    if !registry.contains(&name) {
+        return Err(ApiError {
+            message: format!("Unknown output: {}", name),
+            status: StatusCode::NOT_FOUND,
+        });
+    }
+
+    emit_pulse(&ctx, &registry, &name)
+}
+
+/// POST /v1/lock/unlock - Release the door lock strike
+pub async fn unlock(
+    State(ctx): State<Arc<ApiContext>>,
+) -> Result<(StatusCode, Json<PulseResponse>), ApiError> {
+    info!("Received unlock request");
+
+    let name = ctx.config.gpio.lock_output.clone().ok_or_else(|| ApiError {
+        message: "No lock output configured".to_string(),
+        status: StatusCode::NOT_FOUND,
+    })?;
+
+    emit_pulse(&ctx, &OutputRegistry::from_config(&ctx.config), &name)
+}
+
+/// POST /v1/garage/open - Pulse the garage opener if the door is closed
+pub async fn open_garage(
+    State(ctx): State<Arc<ApiContext>>,
+) -> Result<(StatusCode, Json<PulseResponse>), ApiError> {
+    info!("Received garage open request");
+    pulse_garage(&ctx, true)
+}
+
+/// POST /v1/garage/close - Pulse the garage opener if the door is open
+pub async fn close_garage(
+    State(ctx): State<Arc<ApiContext>>,
+) -> Result<(StatusCode, Json<PulseResponse>), ApiError> {
+    info!("Received garage close request");
+    pulse_garage(&ctx, false)
+}
+
+/// A single-button opener toggles, so only pulse when it moves the door the requested way
+fn pulse_garage(ctx: &ApiContext, open: bool) -> Result<(StatusCode, Json<PulseResponse>), ApiError> {
+    let name = ctx.config.gpio.garage_output.clone().ok_or_else(|| ApiError {
+        message: "No garage output configured".to_string(),
+        status: StatusCode::NOT_FOUND,
+    })?;
+
+    if let Some(zone) = &ctx.config.gpio.garage_zone {
+        let is_open = ctx.state.read().zones.get(zone).copied().unwrap_or(false);
+        if is_open == open {
+            return Err(ApiError {
+                message: format!("Garage door is already {}", if open { "open" } else { "closed" }),
+                status: StatusCode::CONFLICT,
+            });
+        }
+    }
+
+    emit_pulse(ctx, &OutputRegistry::from_config(&ctx.config), &name)
+}
+
+fn emit_pulse(
+    ctx: &ApiContext,
+    registry: &OutputRegistry,
+    name: &str,
+) -> Result<(StatusCode, Json<PulseResponse>), ApiError> {
+    let duration_ms = registry.pulse_ms(name).ok_or_else(|| ApiError {
+        message: format!("Output {} is not a pulse output", name),
+        status: StatusCode::BAD_REQUEST,
+    })?;
+
+    // Checked here for a clear response; the state machine enforces it again
+    if ctx.state.read().outputs.get(name).copied().unwrap_or(false) {
+        return Err(ApiError {
+            message: format!("Output {} is already active", name),
+            status: StatusCode::CONFLICT,
+        });
+    }
+
+    ctx.event_bus
+        .emit(Event::OutputPulse {
+            name: name.to_string(),
+            duration_ms,
+        })
+        .map_err(|e| ApiError {
+            message: format!("Failed to emit output pulse event: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(PulseResponse {
+            output: name.to_string(),
+            duration_ms,
+        }),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -116,15 +339,12 @@ mod tests {
         let state = new_app_state();
         let (event_bus, _rx) = EventBus::new();
         let config = AppConfig::test_default();
-        let ctx = Arc::new(ApiContext {
-            state,
-            event_bus,
-            config,
-        });
+        let ctx = Arc::new(ApiContext::for_test(state, event_bus, config));
 
         let req = SirenRequest {
             on: true,
             duration_s: Some(60),
+            pattern: None,
         };
 
         let result = control_siren(State(ctx), Json(req)).await;
@@ -134,16 +354,40 @@ mod tests {
         assert_eq!(status, StatusCode::ACCEPTED);
     }
 
+    #[tokio::test]
+    async fn test_siren_test_pattern() {
+        let state = new_app_state();
+        let (event_bus, mut rx) = EventBus::new();
+        let ctx = Arc::new(ApiContext::for_test(state.clone(), event_bus, AppConfig::test_default()));
+
+        let req = SirenTestRequest {
+            pattern: SirenPattern::Temporal3,
+        };
+        let (status, response) = test_siren(State(ctx.clone()), Json(req)).await.unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(response.duration_s, 4);
+        match rx.try_recv().unwrap() {
+            Event::SirenControl { on, pattern, .. } => {
+                assert!(on);
+                assert_eq!(pattern, Some(SirenPattern::Temporal3));
+            }
+            _ => panic!("Wrong event type"),
+        }
+
+        state.write().set_alarm_state(AlarmState::Armed);
+        let req = SirenTestRequest {
+            pattern: SirenPattern::Chirp,
+        };
+        let result = test_siren(State(ctx), Json(req)).await;
+        assert_eq!(result.err().unwrap().status, StatusCode::CONFLICT);
+    }
+
     #[tokio::test]
     async fn test_floodlight_control() {
         let state = new_app_state();
         let (event_bus, _rx) = EventBus::new();
         let config = AppConfig::test_default();
-        let ctx = Arc::new(ApiContext {
-            state,
-            event_bus,
-            config,
-        });
+        let ctx = Arc::new(ApiContext::for_test(state, event_bus, config));
 
         let req = FloodlightRequest {
             on: true,
@@ -156,4 +400,86 @@ mod tests {
         let (status, _response) = result.unwrap();
         assert_eq!(status, StatusCode::ACCEPTED);
     }
+
+    #[tokio::test]
+    async fn test_output_control() {
+        let state = new_app_state();
+        let (event_bus, _rx) = EventBus::new();
+        let mut config = AppConfig::test_default();
+        config.gpio.outputs.push(crate::config::OutputConfig {
+            name: "gate".to_string(),
+            pin: 5,
+            pulse_ms: None,
+        });
+        let ctx = Arc::new(ApiContext::for_test(state, event_bus, config));
+
+        let req = OutputRequest {
+            on: true,
+            duration_s: Some(2),
+        };
+        let result = control_output(State(ctx.clone()), Path("gate".to_string()), Json(req)).await;
+        let (status, _response) = result.unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        let req = OutputRequest {
+            on: true,
+            duration_s: None,
+        };
+        let result = control_output(State(ctx), Path("porch".to_string()), Json(req)).await;
+        assert_eq!(result.err().unwrap().status, StatusCode::NOT_FOUND);
+    }
+
+    fn pulse_context() -> (Arc<ApiContext>, crate::events::EventReceiver) {
+        let mut config = AppConfig::test_default();
+        for (name, pin) in [("front_lock", 5), ("garage", 6)] {
+            config.gpio.outputs.push(crate::config::OutputConfig {
+                name: name.to_string(),
+                pin,
+                pulse_ms: Some(500),
+            });
+        }
+        config.gpio.lock_output = Some("front_lock".to_string());
+        config.gpio.garage_output = Some("garage".to_string());
+        config.gpio.garage_zone = Some("garage_door".to_string());
+        let (event_bus, rx) = EventBus::new();
+        let ctx = Arc::new(ApiContext::for_test(new_app_state(), event_bus, config));
+        (ctx, rx)
+    }
+
+    #[tokio::test]
+    async fn test_unlock_interlock() {
+        let (ctx, mut rx) = pulse_context();
+
+        let (status, response) = unlock(State(ctx.clone())).await.unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(response.duration_ms, 500);
+        assert!(matches!(rx.try_recv().unwrap(), Event::OutputPulse { duration_ms: 500, .. }));
+
+        ctx.state.write().set_output("front_lock", true);
+        let result = unlock(State(ctx.clone())).await;
+        assert_eq!(result.err().unwrap().status, StatusCode::CONFLICT);
+
+        let req = OutputRequest {
+            on: true,
+            duration_s: None,
+        };
+        let result = control_output(State(ctx), Path("front_lock".to_string()), Json(req)).await;
+        assert_eq!(result.err().unwrap().status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_garage_open_close() {
+        let (ctx, _rx) = pulse_context();
+
+        let result = close_garage(State(ctx.clone())).await;
+        assert_eq!(result.err().unwrap().status, StatusCode::CONFLICT);
+        let (status, _response) = open_garage(State(ctx.clone())).await.unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        ctx.state.write().set_zone_state("garage_door", true);
+        let result = open_garage(State(ctx.clone())).await;
+        assert_eq!(result.err().unwrap().status, StatusCode::CONFLICT);
+        let (status, _response) = close_garage(State(ctx)).await.unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+    }
 }
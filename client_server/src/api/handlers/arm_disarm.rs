@@ -3,25 +3,35 @@
 use axum::{extract::State, http::StatusCode, Json};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::api::{ApiContext, ApiError};
 use crate::events::{Event, EventSource};
+use crate::state::ArmMode;
 
 #[derive(Deserialize)]
 pub struct ArmRequest {
     pub exit_delay_s: Option<u64>,
+    #[serde(default)]
+    pub mode: ArmMode,
 }
 
 #[derive(Serialize)]
 pub struct ArmResponse {
     pub state: String,
     pub exit_delay_s: u64,
+    pub mode: ArmMode,
 }
 
 #[derive(Deserialize)]
 pub struct DisarmRequest {
     pub auto_rearm_s: Option<u64>,
+    /// Needed with `access.require_pin_to_disarm`, and during lockdown
+    #[serde(default)]
+    pub pin: Option<String>,
+    /// One-time code from the lockdown's key, needed during lockdown
+    #[serde(default)]
+    pub code: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -35,12 +45,13 @@ pub async fn arm(
     State(ctx): State<Arc<ApiContext>>,
     Json(req): Json<ArmRequest>,
 ) -> Result<(StatusCode, Json<ArmResponse>), ApiError> {
-    info!(exit_delay_s = ?req.exit_delay_s, "Received arm request");
+    info!(exit_delay_s = ?req.exit_delay_s, mode = %req.mode, "Received arm request");
     
     // Emit arm event
     let event = Event::UserArm {
         source: EventSource::Local,
         exit_delay_s: req.exit_delay_s,
+        mode: req.mode,
     };
     
     ctx.event_bus.emit(event).map_err(|e| ApiError {
@@ -56,6 +67,7 @@ pub async fn arm(
         Json(ArmResponse {
             state: "exit_delay".to_string(),
             exit_delay_s: exit_delay,
+            mode: req.mode,
         }),
     ))
 }
@@ -66,6 +78,10 @@ pub async fn disarm(
     Json(req): Json<DisarmRequest>,
 ) -> Result<(StatusCode, Json<DisarmResponse>), ApiError> {
     info!(auto_rearm_s = ?req.auto_rearm_s, "Received disarm request");
+
+    if ctx.pins.guards_disarm() {
+        ctx.pins.check_disarm_async(req.pin.clone(), req.code.clone(), EventSource::Local).await?;
+    }
     
     // Emit disarm event
     let event = Event::UserDisarm {
@@ -87,6 +103,42 @@ pub async fn disarm(
     ))
 }
 
+/// POST /v1/alarm/ack - Acknowledge alarm memory and silence the strobe
+pub async fn acknowledge_alarm(
+    State(ctx): State<Arc<ApiContext>>,
+) -> Result<StatusCode, ApiError> {
+    info!("Received alarm acknowledge request");
+
+    ctx.event_bus
+        .emit(Event::AlarmAcknowledged {
+            source: EventSource::Local,
+        })
+        .map_err(|e| ApiError {
+            message: format!("Failed to emit acknowledge event: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// POST /v1/panic - Sound a panic alarm immediately, armed or not
+pub async fn trigger_panic(
+    State(ctx): State<Arc<ApiContext>>,
+) -> Result<StatusCode, ApiError> {
+    warn!("Received panic request");
+
+    ctx.event_bus
+        .emit(Event::Panic {
+            source: EventSource::Local,
+        })
+        .map_err(|e| ApiError {
+            message: format!("Failed to emit panic event: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,14 +151,11 @@ mod tests {
         let state = new_app_state();
         let (event_bus, _rx) = EventBus::new();
         let config = AppConfig::test_default();
-        let ctx = Arc::new(ApiContext {
-            state,
-            event_bus,
-            config,
-        });
+        let ctx = Arc::new(ApiContext::for_test(state, event_bus, config));
 
         let req = ArmRequest {
             exit_delay_s: Some(30),
+            mode: ArmMode::Night,
         };
 
         let result = arm(State(ctx), Json(req)).await;
@@ -116,6 +165,7 @@ mod tests {
         assert_eq!(status, StatusCode::ACCEPTED);
         assert_eq!(response.state, "exit_delay");
         assert_eq!(response.exit_delay_s, 30);
+        assert_eq!(response.mode, ArmMode::Night);
     }
 
     #[tokio::test]
@@ -123,14 +173,12 @@ mod tests {
         let state = new_app_state();
         let (event_bus, _rx) = EventBus::new();
         let config = AppConfig::test_default();
-        let ctx = Arc::new(ApiContext {
-            state,
-            event_bus,
-            config,
-        });
+        let ctx = Arc::new(ApiContext::for_test(state, event_bus, config));
 
         let req = DisarmRequest {
             auto_rearm_s: Some(120),
+            pin: None,
+            code: None,
         };
 
         let result = disarm(State(ctx), Json(req)).await;
@@ -141,4 +189,46 @@ mod tests {
         assert_eq!(response.state, "disarmed");
         assert_eq!(response.auto_rearm_s, Some(120));
     }
+
+    #[tokio::test]
+    async fn test_disarm_requires_pin() {
+        let dir = tempfile::tempdir().unwrap();
+        let (event_bus, mut rx) = EventBus::new();
+        let mut config = AppConfig::test_default();
+        config.access.credentials_path = Some(dir.path().join("credentials.json"));
+        config.access.require_pin_to_disarm = true;
+        let mut store = crate::access::CredentialStore::load(&config.credentials_path()).unwrap();
+        store.enroll("alice", None, Some("2468")).unwrap();
+        store.save().unwrap();
+        let ctx = Arc::new(ApiContext::for_test(new_app_state(), event_bus, config));
+        let req = |pin: Option<&str>| DisarmRequest {
+            auto_rearm_s: None,
+            pin: pin.map(str::to_string),
+            code: None,
+        };
+
+        let missing = disarm(State(ctx.clone()), Json(req(None))).await.err().unwrap();
+        assert_eq!(missing.status, StatusCode::FORBIDDEN);
+        let wrong = disarm(State(ctx.clone()), Json(req(Some("1111")))).await.err().unwrap();
+        assert_eq!(wrong.status, StatusCode::FORBIDDEN);
+        assert!(matches!(rx.try_recv().unwrap(), Event::AccessDenied { .. }));
+        assert!(rx.try_recv().is_err());
+
+        let (status, _) = disarm(State(ctx), Json(req(Some("2468")))).await.unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert!(matches!(rx.try_recv().unwrap(), Event::AccessGranted { user, .. } if user == "alice"));
+        assert!(matches!(rx.try_recv().unwrap(), Event::UserDisarm { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_acknowledge_handler() {
+        let state = new_app_state();
+        let (event_bus, mut rx) = EventBus::new();
+        let config = AppConfig::test_default();
+        let ctx = Arc::new(ApiContext::for_test(state, event_bus, config));
+
+        let status = acknowledge_alarm(State(ctx)).await.unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert!(matches!(rx.try_recv().unwrap(), Event::AlarmAcknowledged { .. }));
+    }
 }
@@ -0,0 +1,78 @@
+//! Audit log endpoint
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use std::sync::Arc;
+
+use crate::api::{ApiContext, ApiError};
+use crate::security::{AuditEntry, AuditQuery};
+
+/// GET /v1/audit - Audited control requests, newest first, filtered by `since`, `until`, `action`, `channel`, `outcome` and `limit`
+pub async fn list_audit(
+    State(ctx): State<Arc<ApiContext>>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, ApiError> {
+    let audit = ctx.audit.as_ref().ok_or_else(|| ApiError {
+        message: "Audit log is disabled".to_string(),
+        status: StatusCode::SERVICE_UNAVAILABLE,
+    })?;
+    Ok(Json(audit.query(&query)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{create_router, ApiContext};
+    use crate::config::AppConfig;
+    use crate::events::EventBus;
+    use crate::security::{AuditLog, AuditOutcome, AuditSource};
+    use crate::state::new_app_state;
+    use axum::{body::Body, extract::ConnectInfo, http::Request};
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_requests_are_audited() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::test_default();
+        config.access.require_pin_to_disarm = true;
+        let audit = Arc::new(AuditLog::open(dir.path(), &config.audit).unwrap());
+        let (event_bus, _rx) = EventBus::new();
+        let mut ctx = ApiContext::for_test(new_app_state(), event_bus, config);
+        ctx.audit = Some(audit.clone());
+        let app = create_router(ctx);
+        let peer: SocketAddr = "192.168.1.20:50000".parse().unwrap();
+        let post = |uri: &str, body: &str| {
+            Request::post(uri)
+                .header("content-type", "application/json")
+                .extension(ConnectInfo(peer))
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        app.clone().oneshot(post("/v1/arm", r#"{"mode": "away"}"#)).await.unwrap();
+        app.clone().oneshot(post("/v1/disarm", "{}")).await.unwrap();
+        app.clone().oneshot(Request::get("/v1/status").body(Body::empty()).unwrap()).await.unwrap();
+
+        let response = app
+            .oneshot(Request::get("/v1/audit?outcome=rejected").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let rejected: Vec<crate::security::AuditEntry> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].action, "POST /v1/disarm");
+        assert_eq!(rejected[0].reason.as_deref(), Some("A PIN is required"));
+        assert!(rejected[0].correlation_id.is_some());
+
+        // Reads are not audited
+        let entries = audit.query(&Default::default()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].action, "POST /v1/arm");
+        assert_eq!(entries[1].outcome, AuditOutcome::Accepted);
+        assert_eq!(entries[1].source, AuditSource::Http { ip: Some(peer.ip()) });
+    }
+}
@@ -1,119 +1,324 @@
-//! BLE pairing management endpoints
+//! BLE service, pairing and bonded device endpoints
 
 use axum::{
-    extract::State,
+    extract::{Path, State},
     http::StatusCode,
     Json,
 };
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::info;
 
 use crate::api::{ApiContext, ApiError};
+use crate::ble::{BlePermission, BleService, BondedDevice};
+
+#[derive(Deserialize)]
+pub struct BleSwitchRequest {
+    pub enabled: bool,
+}
 
 #[derive(Deserialize)]
 pub struct BlePairingRequest {
     pub enable: bool,
-    #[serde(default = "default_pairing_seconds")]
-    pub seconds: u64,
+    /// Window length; `ble.pairing_window_s` when omitted
+    #[serde(default)]
+    pub seconds: Option<u64>,
 }
 
-fn default_pairing_seconds() -> u64 {
-    120
+#[derive(Deserialize)]
+pub struct BlePasskeyConfirmation {
+    pub passkey: u32,
+    #[serde(default = "default_accept")]
+    pub accept: bool,
 }
 
-#[derive(Serialize)]
-pub struct BlePairingResponse {
-    pub enabled: bool,
-    pub expires_in_s: Option<u64>,
-    pub message: String,
+fn default_accept() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+pub struct BlePermissionsRequest {
+    pub permissions: Vec<BlePermission>,
+}
+
+fn unknown_device(address: &str) -> ApiError {
+    ApiError {
+        message: format!("Device {} is not bonded", address),
+        status: StatusCode::NOT_FOUND,
+    }
+}
+
+fn ble(ctx: &ApiContext) -> Result<&BleService, ApiError> {
+    ctx.ble.as_ref().ok_or_else(|| ApiError {
+        message: "BLE is disabled".to_string(),
+        status: StatusCode::SERVICE_UNAVAILABLE,
+    })
+}
+
+fn service_status(ble: &BleService) -> Value {
+    let remaining = ble.pairing_remaining();
+    json!({
+        "service": ble.service_status(),
+        "pairing": {
+            "enabled": remaining.is_some(),
+            "expires_in_s": remaining.map(|left| left.as_secs()),
+        },
+        "bonded_devices": ble.bonded_devices().len(),
+    })
+}
+
+/// GET /v1/ble - Whether the service runs, adapter, connections and pairing window
+pub async fn get_ble(State(ctx): State<Arc<ApiContext>>) -> Result<Json<Value>, ApiError> {
+    Ok(Json(service_status(ble(&ctx)?)))
 }
 
-/// POST /v1/ble/pairing - Enable/disable BLE pairing mode
+/// POST /v1/ble - Switch the BLE service on or off until the next restart
+pub async fn switch_ble(
+    State(ctx): State<Arc<ApiContext>>,
+    Json(request): Json<BleSwitchRequest>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let ble = ble(&ctx)?;
+    ble.set_enabled(request.enabled);
+    Ok((StatusCode::ACCEPTED, Json(service_status(ble))))
+}
+
+/// GET /v1/ble/pairing - Pairing window, request awaiting confirmation and bonded devices
+pub async fn ble_pairing_status(State(ctx): State<Arc<ApiContext>>) -> Result<Json<Value>, ApiError> {
+    let ble = ble(&ctx)?;
+    let remaining = ble.pairing_remaining();
+    Ok(Json(json!({
+        "enabled": remaining.is_some(),
+        "expires_in_s": remaining.map(|left| left.as_secs()),
+        "pending": ble.pending_passkey().map(|(device, passkey)| json!({
+            "device": device,
+            "passkey": format!("{:06}", passkey),
+        })),
+        "bonded": ble.bonded_devices(),
+    })))
+}
+
+/// POST /v1/ble/pairing - Open or close the BLE pairing window
 pub async fn ble_pairing(
-    State(_ctx): State<Arc<ApiContext>>,
+    State(ctx): State<Arc<ApiContext>>,
     Json(request): Json<BlePairingRequest>,
 ) -> Result<(StatusCode, Json<Value>), ApiError> {
-    info!(
-        enable = request.enable,
-        duration_s = request.seconds,
-        "BLE pairing mode request"
-    );
+    let ble = ble(&ctx)?;
+    info!(enable = request.enable, duration_s = ?request.seconds, "BLE pairing mode request");
 
     if request.enable {
-        // In a real implementation, this would:
-        // 1. Enable BLE discoverable/pairable mode
-        // 2. Set a timer to disable after the specified duration
-        // 3. Return the pairing PIN if numeric passkey is used
-        
+        if request.seconds == Some(0) {
+            return Err(ApiError {
+                message: "seconds must be greater than 0".to_string(),
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+        let duration = ble.open_pairing(request.seconds.map(Duration::from_secs));
         Ok((
             StatusCode::ACCEPTED,
             Json(json!({
                 "enabled": true,
-                "expires_in_s": request.seconds,
-                "message": format!("BLE pairing mode enabled for {} seconds", request.seconds),
-                "note": "BLE pairing requires hardware support and is not active in mock mode"
-            }))
+                "expires_in_s": duration.as_secs(),
+                "message": format!("BLE pairing mode enabled for {} seconds", duration.as_secs()),
+            })),
         ))
     } else {
-        // Disable pairing mode
+        ble.close_pairing();
         Ok((
             StatusCode::ACCEPTED,
             Json(json!({
                 "enabled": false,
                 "expires_in_s": null,
                 "message": "BLE pairing mode disabled"
-            }))
+            })),
         ))
     }
 }
 
+/// POST /v1/ble/pairing/confirm - Accept or reject the passkey shown on the phone
+pub async fn confirm_ble_pairing(
+    State(ctx): State<Arc<ApiContext>>,
+    Json(request): Json<BlePasskeyConfirmation>,
+) -> Result<Json<Value>, ApiError> {
+    ble(&ctx)?
+        .confirm_passkey(request.passkey, request.accept)
+        .map_err(|e| ApiError {
+            message: e.to_string(),
+            status: StatusCode::NOT_FOUND,
+        })?;
+    Ok(Json(json!({ "accepted": request.accept })))
+}
+
+/// GET /v1/ble/devices - Bonded devices and what each may do
+pub async fn list_ble_devices(State(ctx): State<Arc<ApiContext>>) -> Result<Json<Vec<BondedDevice>>, ApiError> {
+    Ok(Json(ble(&ctx)?.bonded_devices()))
+}
+
+/// PUT /v1/ble/devices/:address - Replace the permissions of a bonded device
+pub async fn set_ble_permissions(
+    State(ctx): State<Arc<ApiContext>>,
+    Path(address): Path<String>,
+    Json(request): Json<BlePermissionsRequest>,
+) -> Result<Json<Value>, ApiError> {
+    info!(device = %address, permissions = ?request.permissions, "BLE permissions request");
+    if !ble(&ctx)?.set_permissions(&address, request.permissions.clone())? {
+        return Err(unknown_device(&address));
+    }
+    Ok(Json(json!({ "address": address, "permissions": request.permissions })))
+}
+
+/// DELETE /v1/ble/devices/:address - Remove a device from the whitelist
+pub async fn remove_ble_device(
+    State(ctx): State<Arc<ApiContext>>,
+    Path(address): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    info!(device = %address, "BLE device removal request");
+    if !ble(&ctx)?.forget(&address)? {
+        return Err(unknown_device(&address));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::AppConfig;
     use crate::events::EventBus;
     use crate::state::new_app_state;
+    use tempfile::TempDir;
 
-    #[tokio::test]
-    async fn test_enable_ble_pairing() {
+    fn api_context(dir: &TempDir, mut config: AppConfig) -> ApiContext {
         let state = new_app_state();
         let (event_bus, _) = EventBus::new();
-        let config = AppConfig::test_default();
-        let ctx = Arc::new(ApiContext { state, event_bus, config });
+        config.system.data_dir = dir.path().to_path_buf();
+        let ble = BleService::new(&config, state.clone(), event_bus.clone());
+        let mut ctx = ApiContext::for_test(state, event_bus, config);
+        ctx.ble = Some(ble);
+        ctx
+    }
+
+    fn context(dir: &TempDir) -> Arc<ApiContext> {
+        Arc::new(api_context(dir, AppConfig::test_default()))
+    }
+
+    #[tokio::test]
+    async fn test_enable_ble_pairing() {
+        let dir = TempDir::new().unwrap();
+        let ctx = context(&dir);
 
         let request = BlePairingRequest {
             enable: true,
-            seconds: 120,
+            seconds: None,
         };
 
-        let result = ble_pairing(State(ctx), Json(request)).await;
+        let result = ble_pairing(State(ctx.clone()), Json(request)).await;
         assert!(result.is_ok());
         
         let (status, json) = result.unwrap();
         assert_eq!(status, StatusCode::ACCEPTED);
         assert_eq!(json["enabled"], true);
         assert_eq!(json["expires_in_s"], 120);
+
+        let Json(status) = ble_pairing_status(State(ctx)).await.unwrap();
+        assert_eq!(status["enabled"], true);
+        assert!(status["pending"].is_null());
     }
 
     #[tokio::test]
     async fn test_disable_ble_pairing() {
-        let state = new_app_state();
-        let (event_bus, _) = EventBus::new();
-        let config = AppConfig::test_default();
-        let ctx = Arc::new(ApiContext { state, event_bus, config });
+        let dir = TempDir::new().unwrap();
+        let ctx = context(&dir);
+        ctx.ble.as_ref().unwrap().open_pairing(None);
 
         let request = BlePairingRequest {
             enable: false,
-            seconds: 0,
+            seconds: None,
         };
 
-        let result = ble_pairing(State(ctx), Json(request)).await;
+        let result = ble_pairing(State(ctx.clone()), Json(request)).await;
         assert!(result.is_ok());
         
         let (status, json) = result.unwrap();
         assert_eq!(status, StatusCode::ACCEPTED);
         assert_eq!(json["enabled"], false);
+        assert!(ctx.ble.as_ref().unwrap().pairing_remaining().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_switch_ble() {
+        let dir = TempDir::new().unwrap();
+        let ctx = context(&dir);
+
+        let (status, Json(json)) = switch_ble(State(ctx.clone()), Json(BleSwitchRequest { enabled: false }))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(json["service"]["enabled"], false);
+
+        let Json(json) = get_ble(State(ctx)).await.unwrap();
+        assert_eq!(json["service"]["running"], false);
+        assert_eq!(json["pairing"]["enabled"], false);
+    }
+
+    #[tokio::test]
+    async fn test_confirm_without_request() {
+        let dir = TempDir::new().unwrap();
+        let ctx = context(&dir);
+        let request = BlePasskeyConfirmation {
+            passkey: 123456,
+            accept: true,
+        };
+        let err = confirm_ble_pairing(State(ctx), Json(request)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_ble_device_permissions() {
+        let dir = TempDir::new().unwrap();
+        let ctx = context(&dir);
+        let phone = "AA:BB:CC:DD:EE:FF".to_string();
+        ctx.ble.as_ref().unwrap().record_bond(phone.clone(), None, None).unwrap();
+
+        let request = BlePermissionsRequest {
+            permissions: vec![BlePermission::Arm, BlePermission::Disarm],
+        };
+        let Json(updated) = set_ble_permissions(State(ctx.clone()), Path(phone.clone()), Json(request)).await.unwrap();
+        assert_eq!(updated["permissions"], json!(["arm", "disarm"]));
+        let Json(devices) = list_ble_devices(State(ctx.clone())).await.unwrap();
+        assert!(devices[0].allows(BlePermission::Disarm));
+
+        assert_eq!(remove_ble_device(State(ctx.clone()), Path(phone.clone())).await.unwrap(), StatusCode::NO_CONTENT);
+        let err = remove_ble_device(State(ctx), Path(phone)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_device_changes_need_api_key() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let dir = TempDir::new().unwrap();
+        let mut config = AppConfig::test_default();
+        config.system.api_key = Some("secret-token".to_string());
+        let app = crate::api::create_router(api_context(&dir, config));
+        let request = |method: &str, token: Option<&str>| {
+            let mut request = Request::builder()
+                .method(method)
+                .uri("/v1/ble/devices/AA:BB:CC:DD:EE:FF")
+                .header("content-type", "application/json");
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.body(Body::from(r#"{"permissions": ["disarm"]}"#)).unwrap()
+        };
+
+        for method in ["PUT", "DELETE"] {
+            let response = app.clone().oneshot(request(method, None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", method);
+            let response = app.clone().oneshot(request(method, Some("secret-token"))).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", method);
+        }
     }
 }
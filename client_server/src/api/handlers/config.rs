@@ -1,11 +1,22 @@
 //! Configuration management endpoints
 
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
-use crate::api::{ApiContext, ApiError};
+use crate::api::{ApiContext, ApiError, InvalidConfig};
+use crate::config::{
+    changed_settings, merge_update, ActuatorLimits, GpioBackend, OutputConfig, PinOptions, ValidationErrors, ZoneConfig,
+};
+use crate::events::{AlarmKind, Severity, ZoneType};
+use tracing::info;
 
 #[derive(Serialize)]
 pub struct ConfigResponse {
@@ -18,6 +29,7 @@ pub struct ConfigResponse {
     pub timers: TimerConfigView,
     pub ble: BleConfigView,
     pub rf433: Rf433ConfigView,
+    pub display: DisplayConfigView,
 }
 
 #[derive(Serialize)]
@@ -25,6 +37,7 @@ pub struct SystemConfigView {
     pub client_id: String,
     pub data_dir: String,
     pub log_level: String,
+    pub developer_mode: bool,
 }
 
 #[derive(Serialize)]
@@ -45,7 +58,7 @@ pub struct WsLocalConfigView {
 
 #[derive(Serialize)]
 pub struct CloudConfigView {
-    pub url: Option<String>,
+    pub url: Vec<String>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub spki_pins: Vec<String>,
     pub heartbeat_s: u64,
@@ -53,16 +66,45 @@ pub struct CloudConfigView {
     pub backoff_max_s: u64,
     pub queue_max_events: usize,
     pub queue_max_age_days: u32,
+    pub min_severity: Severity,
+    pub lte_min_severity: Severity,
 }
 
 #[derive(Serialize)]
 pub struct GpioConfigView {
+    pub backend: GpioBackend,
+    pub chip: String,
+    pub file_dir: String,
     pub reed_in: u8,
     pub reed_active_low: bool,
     pub siren_out: u8,
     pub floodlight_out: u8,
     pub radio433_rx_in: u8,
+    pub radio433_tx_out: Option<u8>,
     pub debounce_ms: u64,
+    pub siren_pwm_hz: Option<f64>,
+    pub siren_pwm_duty: f64,
+    pub strobe_out: Option<u8>,
+    pub status_led_out: Option<u8>,
+    pub buzzer_out: Option<u8>,
+    pub buzzer_hz: Option<f64>,
+    pub buzzer_duty: f64,
+    pub buzzer_quiet_at_night: bool,
+    pub self_test_on_startup: bool,
+    pub motion_in: Option<u8>,
+    pub motion_zone: ZoneType,
+    pub vibration_in: Option<u8>,
+    pub vibration_pulses: u32,
+    pub vibration_window_ms: u64,
+    pub wiegand_d0_in: Option<u8>,
+    pub wiegand_d1_in: Option<u8>,
+    pub zones: Vec<ZoneConfig>,
+    pub outputs: Vec<OutputConfig>,
+    pub lock_output: Option<String>,
+    pub garage_output: Option<String>,
+    pub garage_zone: Option<String>,
+    pub pin_options: BTreeMap<String, PinOptions>,
+    pub limits: BTreeMap<String, ActuatorLimits>,
 }
 
 #[derive(Serialize)]
@@ -71,6 +113,12 @@ pub struct TimerConfigView {
     pub entry_delay_s: u64,
     pub auto_rearm_s: u64,
     pub siren_max_s: u64,
+    pub siren_max_by_kind: BTreeMap<AlarmKind, u64>,
+    pub motion_floodlight_s: u64,
+    pub courtesy_light_s: u64,
+    pub courtesy_override_s: u64,
+    pub night_start: String,
+    pub night_end: String,
 }
 
 #[derive(Serialize)]
@@ -86,6 +134,14 @@ pub struct Rf433ConfigView {
     pub debounce_ms: u64,
 }
 
+#[derive(Serialize)]
+pub struct DisplayConfigView {
+    pub enabled: bool,
+    pub i2c_bus: String,
+    pub address: u8,
+    pub short_panel: bool,
+}
+
 #[derive(Deserialize)]
 pub struct ConfigUpdateRequest {
     #[serde(flatten)]
@@ -103,6 +159,7 @@ pub async fn get_config(
             client_id: config.system.client_id.clone(),
             data_dir: config.system.data_dir.display().to_string(),
             log_level: config.system.log_level.clone(),
+            developer_mode: config.system.developer_mode,
         },
         network: NetworkConfigView {
             prefer: config.network.prefer.clone(),
@@ -122,20 +179,55 @@ pub async fn get_config(
             backoff_max_s: config.cloud.backoff_max_s,
             queue_max_events: config.cloud.queue_max_events,
             queue_max_age_days: config.cloud.queue_max_age_days,
+            min_severity: config.cloud.min_severity,
+            lte_min_severity: config.cloud.lte_min_severity,
         },
         gpio: GpioConfigView {
+            backend: config.gpio.backend,
+            chip: config.gpio.chip.display().to_string(),
+            file_dir: config.gpio.file_dir.display().to_string(),
             reed_in: config.gpio.reed_in,
             reed_active_low: config.gpio.reed_active_low,
             siren_out: config.gpio.siren_out,
             floodlight_out: config.gpio.floodlight_out,
             radio433_rx_in: config.gpio.radio433_rx_in,
+            radio433_tx_out: config.gpio.radio433_tx_out,
             debounce_ms: config.gpio.debounce_ms,
+            siren_pwm_hz: config.gpio.siren_pwm_hz,
+            siren_pwm_duty: config.gpio.siren_pwm_duty,
+            strobe_out: config.gpio.strobe_out,
+            status_led_out: config.gpio.status_led_out,
+            buzzer_out: config.gpio.buzzer_out,
+            buzzer_hz: config.gpio.buzzer_hz,
+            buzzer_duty: config.gpio.buzzer_duty,
+            buzzer_quiet_at_night: config.gpio.buzzer_quiet_at_night,
+            self_test_on_startup: config.gpio.self_test_on_startup,
+            motion_in: config.gpio.motion_in,
+            motion_zone: config.gpio.motion_zone,
+            vibration_in: config.gpio.vibration_in,
+            vibration_pulses: config.gpio.vibration_pulses,
+            vibration_window_ms: config.gpio.vibration_window_ms,
+            wiegand_d0_in: config.gpio.wiegand_d0_in,
+            wiegand_d1_in: config.gpio.wiegand_d1_in,
+            zones: config.gpio.zones.clone(),
+            outputs: config.gpio.outputs.clone(),
+            lock_output: config.gpio.lock_output.clone(),
+            garage_output: config.gpio.garage_output.clone(),
+            garage_zone: config.gpio.garage_zone.clone(),
+            pin_options: config.gpio.pin_options.clone(),
+            limits: config.gpio.limits.clone(),
         },
         timers: TimerConfigView {
             exit_delay_s: config.timers.exit_delay_s,
             entry_delay_s: config.timers.entry_delay_s,
             auto_rearm_s: config.timers.auto_rearm_s,
             siren_max_s: config.timers.siren_max_s,
+            siren_max_by_kind: config.timers.siren_max_by_kind.clone(),
+            motion_floodlight_s: config.timers.motion_floodlight_s,
+            courtesy_light_s: config.timers.courtesy_light_s,
+            courtesy_override_s: config.timers.courtesy_override_s,
+            night_start: config.timers.night_start.clone(),
+            night_end: config.timers.night_end.clone(),
         },
         ble: BleConfigView {
             enabled: config.ble.enabled,
@@ -146,46 +238,82 @@ pub async fn get_config(
             allow_disarm: config.rf433.allow_disarm,
             debounce_ms: config.rf433.debounce_ms,
         },
+        display: DisplayConfigView {
+            enabled: config.display.enabled,
+            i2c_bus: config.display.i2c_bus.display().to_string(),
+            address: config.display.address,
+            short_panel: config.display.short_panel,
+        },
     };
 
     Ok(Json(response))
 }
 
-/// PUT /v1/config - Update configuration (requires restart)
+/// PUT /v1/config - Validate a configuration update and save it (applied on reload or restart)
+///
+/// An update failing validation is answered with every setting at fault in `errors`.
 pub async fn update_config(
-    State(_ctx): State<Arc<ApiContext>>,
+    State(ctx): State<Arc<ApiContext>>,
     Json(request): Json<ConfigUpdateRequest>,
-) -> Result<(StatusCode, Json<Value>), ApiError> {
-    // Validate the configuration update
-    // In a real implementation, this would:
-    // 1. Validate the configuration against schema
-    // 2. Write to disk at /etc/pi-door-client/config.toml
-    // 3. Mark restart as required
-    // 4. Optionally trigger SIGHUP for hot-reload of certain configs
-
-    // For now, just validate it's valid JSON
-    if request.config.is_null() {
-        return Err(ApiError {
-            message: "Configuration cannot be null".to_string(),
-            status: StatusCode::BAD_REQUEST,
-        });
-    }
+) -> Result<(StatusCode, Json<Value>), Response> {
+    // Same checks as a configuration pulled from the master
+    let result = match &ctx.config_store {
+        Some(store) => store.run_blocking(move |store| store.apply(&request.config)).await,
+        None => merge_update(&ctx.config, &request.config),
+    };
+    let change = result.map_err(|e| {
+        let message = format!("{:#}", e);
+        match ValidationErrors::of(&e) {
+            errors if errors.is_empty() => ApiError { message, status: StatusCode::BAD_REQUEST }.into_response(),
+            errors => InvalidConfig { message, errors }.into_response(),
+        }
+    })?;
+    let saved = ctx.config_store.is_some() && !change.changed.is_empty();
 
     Ok((
         StatusCode::ACCEPTED,
         Json(json!({
             "applied": false,
+            "saved": saved,
             "restart_required": true,
-            "message": "Configuration update received. Restart required to apply changes.",
-            "note": "Configuration persistence requires write access to /etc/pi-door-client/config.toml"
+            "changed": change.changed,
+            "message": if saved {
+                "Configuration saved. Reload the agent to apply timers, log level, RF mappings and sinks; restart it for the rest."
+            } else {
+                "Configuration update received. Restart required to apply changes."
+            },
         })),
     ))
 }
 
+/// POST /v1/config/rollback - Restore the configuration file saved before the last change
+pub async fn rollback_config(State(ctx): State<Arc<ApiContext>>) -> Result<Json<Value>, ApiError> {
+    let store = ctx.config_store.as_ref().ok_or_else(|| ApiError {
+        message: "Configuration cannot be saved".to_string(),
+        status: StatusCode::SERVICE_UNAVAILABLE,
+    })?;
+    let before = store.current();
+    let restored = store.run_blocking(|store| store.rollback()).await.map_err(|e| ApiError {
+        message: format!("{:#}", e),
+        status: StatusCode::CONFLICT,
+    })?;
+    let changed = changed_settings(&before, &restored).map_err(|e| ApiError {
+        message: format!("{:#}", e),
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+    })?;
+    info!(?changed, "Configuration rolled back");
+
+    Ok(Json(json!({
+        "restored": true,
+        "changed": changed,
+        "message": "Previous configuration restored. Reload the agent to apply timers, log level, RF mappings and sinks; restart it for the rest.",
+    })))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::AppConfig;
+    use crate::config::{AppConfig, ConfigStore};
     use crate::events::EventBus;
     use crate::state::new_app_state;
 
@@ -194,11 +322,7 @@ mod tests {
         let state = new_app_state();
         let (event_bus, _) = EventBus::new();
         let config = AppConfig::test_default();
-        let ctx = Arc::new(ApiContext {
-            state,
-            event_bus,
-            config,
-        });
+        let ctx = Arc::new(ApiContext::for_test(state, event_bus, config));
 
         let result = get_config(State(ctx)).await;
         assert!(result.is_ok());
@@ -214,11 +338,7 @@ mod tests {
         let state = new_app_state();
         let (event_bus, _) = EventBus::new();
         let config = AppConfig::test_default();
-        let ctx = Arc::new(ApiContext {
-            state,
-            event_bus,
-            config,
-        });
+        let ctx = Arc::new(ApiContext::for_test(state, event_bus, config));
 
         let request = ConfigUpdateRequest {
             config: json!({"timers": {"exit_delay_s": 45}}),
@@ -230,5 +350,86 @@ mod tests {
         let (status, json) = result.unwrap();
         assert_eq!(status, StatusCode::ACCEPTED);
         assert_eq!(json["restart_required"], true);
+        assert_eq!(json["changed"], json!(["timers"]));
+    }
+
+    #[tokio::test]
+    async fn test_update_config_rejects_invalid() {
+        let (event_bus, _) = EventBus::new();
+        let ctx = Arc::new(ApiContext::for_test(new_app_state(), event_bus, AppConfig::test_default()));
+
+        let request = ConfigUpdateRequest {
+            config: json!({"timers": {"exit_delay_s": 0, "entry_delay_s": 0}}),
+        };
+        let response = update_config(State(ctx.clone()), Json(request)).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let fields: Vec<&str> = body["errors"].as_array().unwrap().iter().map(|e| e["field"].as_str().unwrap()).collect();
+        assert_eq!(fields, ["timers.exit_delay_s", "timers.entry_delay_s"]);
+        assert!(body["errors"][0]["message"].is_string());
+
+        // An update that does not even parse has no settings to list
+        let request = ConfigUpdateRequest {
+            config: json!({"timers": {"exit_delay_s": "soon"}}),
+        };
+        let response = update_config(State(ctx), Json(request)).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_update_config_saves_and_rolls_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ConfigStore::new(AppConfig::test_default(), dir.path().join("config.toml"));
+        let (event_bus, _) = EventBus::new();
+        let mut ctx = ApiContext::for_test(new_app_state(), event_bus, AppConfig::test_default());
+        ctx.config_store = Some(store.clone());
+        let ctx = Arc::new(ctx);
+        let update = |exit_delay_s: u64| ConfigUpdateRequest {
+            config: json!({"timers": {"exit_delay_s": exit_delay_s}}),
+        };
+
+        // Nothing saved yet to go back to
+        let err = rollback_config(State(ctx.clone())).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::CONFLICT);
+
+        let (_, json) = update_config(State(ctx.clone()), Json(update(45))).await.unwrap();
+        assert_eq!(json["saved"], true);
+        let (_, json) = update_config(State(ctx.clone()), Json(update(60))).await.unwrap();
+        assert_eq!(json["changed"], json!(["timers"]));
+        assert_eq!(store.current().timers.exit_delay_s, 60);
+
+        let json = rollback_config(State(ctx)).await.unwrap().0;
+        assert_eq!(json["changed"], json!(["timers"]));
+        assert_eq!(store.current().timers.exit_delay_s, 45);
+    }
+
+    #[tokio::test]
+    async fn test_config_changes_need_api_key() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let mut config = AppConfig::test_default();
+        config.system.api_key = Some("secret-token".to_string());
+        let (event_bus, _) = EventBus::new();
+        let app = crate::api::create_router(ApiContext::for_test(new_app_state(), event_bus, config));
+        let request = |method: &str, uri: &str, token: Option<&str>| {
+            let mut request = Request::builder().method(method).uri(uri).header("content-type", "application/json");
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.body(Body::from(r#"{"config": {"timers": {"exit_delay_s": 45}}}"#)).unwrap()
+        };
+
+        let response = app.clone().oneshot(request("PUT", "/v1/config", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app.clone().oneshot(request("POST", "/v1/config/rollback", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app.clone().oneshot(request("PUT", "/v1/config", Some("secret-token"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        // Reading it stays open
+        let response = app.oneshot(request("GET", "/v1/config", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
     }
 }
@@ -0,0 +1,104 @@
+//! Dead-letter inspection and requeue endpoints
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tracing::info;
+
+use crate::api::{ApiContext, ApiError};
+use crate::events::{correlated_sync, DeadLetterStore};
+
+fn store(ctx: &ApiContext) -> Result<&DeadLetterStore, ApiError> {
+    ctx.dead_letters.as_deref().ok_or_else(|| ApiError {
+        message: "Dead-letter store is disabled".to_string(),
+        status: StatusCode::SERVICE_UNAVAILABLE,
+    })
+}
+
+fn not_found(id: u64) -> ApiError {
+    ApiError {
+        message: format!("No dead letter {}", id),
+        status: StatusCode::NOT_FOUND,
+    }
+}
+
+/// GET /v1/dead-letters - Events that failed processing, oldest first, with counters
+pub async fn list_dead_letters(
+    State(ctx): State<Arc<ApiContext>>,
+) -> Result<Json<Value>, ApiError> {
+    let store = store(&ctx)?;
+    Ok(Json(json!({
+        "metrics": store.metrics(),
+        "events": store.list()?,
+    })))
+}
+
+/// POST /v1/dead-letters/:id/requeue - Put a failed event back on the bus under its original correlation ID
+pub async fn requeue_dead_letter(
+    State(ctx): State<Arc<ApiContext>>,
+    Path(id): Path<u64>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let letter = store(&ctx)?.take(id)?.ok_or_else(|| not_found(id))?;
+    info!(id, event = ?letter.event, "Requeueing dead-lettered event");
+
+    let queued = letter.into_queued();
+    correlated_sync(queued.correlation_id, || ctx.event_bus.emit(queued.event))?;
+    Ok((StatusCode::ACCEPTED, Json(json!({ "requeued": id }))))
+}
+
+/// DELETE /v1/dead-letters/:id - Drop a failed event for good
+pub async fn discard_dead_letter(
+    State(ctx): State<Arc<ApiContext>>,
+    Path(id): Path<u64>,
+) -> Result<StatusCode, ApiError> {
+    if !store(&ctx)?.discard(id)? {
+        return Err(not_found(id));
+    }
+    info!(id, "Discarded dead-lettered event");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, DeadLetterConfig};
+    use crate::events::{Event, EventBus, QueuedEvent};
+    use crate::state::new_app_state;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_requeue_and_discard() {
+        let dir = TempDir::new().unwrap();
+        let store = Arc::new(DeadLetterStore::open(dir.path(), &DeadLetterConfig::default()).unwrap());
+        let failed = |event| QueuedEvent { event, correlation_id: Some("c123".to_string()), queued_at: std::time::Instant::now() };
+        let first = store.record(failed(Event::DoorOpen), "boom", 3).unwrap();
+        let second = store.record(failed(Event::DoorClose), "boom", 3).unwrap();
+
+        let (event_bus, mut rx) = EventBus::new();
+        let mut ctx = ApiContext::for_test(new_app_state(), event_bus, AppConfig::test_default());
+        ctx.dead_letters = Some(store.clone());
+        let ctx = Arc::new(ctx);
+
+        let Json(listed) = list_dead_letters(State(ctx.clone())).await.unwrap();
+        assert_eq!(listed["events"].as_array().unwrap().len(), 2);
+        assert_eq!(listed["metrics"]["pending"], 2);
+
+        let (status, _) = requeue_dead_letter(State(ctx.clone()), Path(first.id)).await.unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let queued = rx.try_recv_queued().unwrap();
+        assert!(matches!(queued.event, Event::DoorOpen));
+        assert_eq!(queued.correlation_id.as_deref(), Some("c123"));
+
+        assert_eq!(
+            discard_dead_letter(State(ctx.clone()), Path(second.id)).await.unwrap(),
+            StatusCode::NO_CONTENT
+        );
+        let err = requeue_dead_letter(State(ctx), Path(second.id)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::NOT_FOUND);
+        assert!(store.is_empty());
+    }
+}
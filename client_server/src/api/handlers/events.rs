@@ -0,0 +1,172 @@
+//! Event history, export and replay endpoints
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::api::{ApiContext, ApiError};
+use crate::events::{EventEnvelope, EventQuery};
+
+/// Longest pause kept between replayed events in real-time mode
+const MAX_REPLAY_GAP: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+pub struct ReplayOptions {
+    /// Keep the original spacing between events (gaps capped at a minute)
+    #[serde(default)]
+    pub realtime: bool,
+}
+
+/// GET /v1/events - Recorded events, newest first, filtered by `since`, `until`, `type` and `limit`
+///
+/// Served from the local journal; with the journal disabled only the recent
+/// in-memory history is available.
+pub async fn list_events(
+    State(ctx): State<Arc<ApiContext>>,
+    Query(query): Query<EventQuery>,
+) -> Result<Json<Vec<EventEnvelope>>, ApiError> {
+    if let Some(journal) = &ctx.journal {
+        return Ok(Json(journal.query(&query)?));
+    }
+
+    let state = ctx.state.read();
+    let events = state
+        .last_events
+        .iter()
+        .rev()
+        .filter(|envelope| query.matches(envelope))
+        .take(query.limit())
+        .cloned()
+        .collect();
+    Ok(Json(events))
+}
+
+/// GET /v1/events/export - Journal entries matching `since`, `until` and `type` as NDJSON, oldest first
+pub async fn export_events(
+    State(ctx): State<Arc<ApiContext>>,
+    Query(query): Query<EventQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let journal = ctx.journal.as_ref().ok_or_else(|| ApiError {
+        message: "Event journal is disabled".to_string(),
+        status: StatusCode::SERVICE_UNAVAILABLE,
+    })?;
+
+    let mut body = Vec::new();
+    let count = journal.export(&query, &mut body)?;
+    info!(count, "Exported event journal");
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], body))
+}
+
+/// POST /v1/events/replay - Re-inject an exported NDJSON stream into the event bus (developer mode only)
+pub async fn replay_events(
+    State(ctx): State<Arc<ApiContext>>,
+    Query(options): Query<ReplayOptions>,
+    body: String,
+) -> Result<Json<Value>, ApiError> {
+    if !ctx.config.system.developer_mode {
+        return Err(ApiError {
+            message: "Event replay requires system.developer_mode".to_string(),
+            status: StatusCode::FORBIDDEN,
+        });
+    }
+
+    // Parse everything first so a bad capture replays nothing
+    let envelopes = body
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str::<EventEnvelope>(line).map_err(|e| ApiError {
+                message: format!("Line {}: {}", i + 1, e),
+                status: StatusCode::BAD_REQUEST,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    warn!(count = envelopes.len(), realtime = options.realtime, "Replaying captured events");
+    let mut previous: Option<chrono::DateTime<chrono::Utc>> = None;
+    for envelope in &envelopes {
+        if options.realtime {
+            if let Some(gap) = previous.and_then(|previous| (envelope.timestamp - previous).to_std().ok()) {
+                tokio::time::sleep(gap.min(MAX_REPLAY_GAP)).await;
+            }
+            previous = Some(envelope.timestamp);
+        }
+        ctx.event_bus.send(envelope.event.clone()).await?;
+    }
+
+    Ok(Json(json!({ "replayed": envelopes.len() })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::events::{Event, EventBus, EventJournal};
+    use crate::state::new_app_state;
+
+    #[tokio::test]
+    async fn test_list_events_from_journal_and_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AppConfig::test_default();
+        let journal = Arc::new(EventJournal::open(dir.path(), &config.journal).unwrap());
+        journal.record(&EventEnvelope::new(Event::DoorOpen, "test".to_string())).unwrap();
+        journal.record(&EventEnvelope::new(Event::GlassBreak, "test".to_string())).unwrap();
+
+        let state = new_app_state();
+        state.write().add_event(EventEnvelope::new(Event::DoorClose, "test".to_string()));
+        let (event_bus, _rx) = EventBus::new();
+        let mut ctx = ApiContext::for_test(state.clone(), event_bus.clone(), config.clone());
+        ctx.journal = Some(journal);
+        let ctx = Arc::new(ctx);
+
+        let query = EventQuery {
+            event_type: Some("glass_break".to_string()),
+            ..EventQuery::default()
+        };
+        let events = list_events(State(ctx), Query(query)).await.unwrap().0;
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0].event, Event::GlassBreak));
+
+        // Without a journal the in-memory history answers instead
+        let ctx = Arc::new(ApiContext::for_test(state, event_bus, config));
+        let events = list_events(State(ctx), Query(EventQuery::default())).await.unwrap().0;
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0].event, Event::DoorClose));
+    }
+
+    #[tokio::test]
+    async fn test_replay_requires_developer_mode() {
+        let mut config = AppConfig::test_default();
+        let (event_bus, mut rx) = EventBus::new();
+        let capture = [Event::DoorOpen, Event::DoorClose]
+            .into_iter()
+            .map(|event| serde_json::to_string(&EventEnvelope::new(event, "field".to_string())).unwrap())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let context = |config: AppConfig| {
+            Arc::new(ApiContext::for_test(new_app_state(), event_bus.clone(), config))
+        };
+        let options = || Query(ReplayOptions { realtime: false });
+
+        let result = replay_events(State(context(config.clone())), options(), capture.clone()).await;
+        assert_eq!(result.err().unwrap().status, StatusCode::FORBIDDEN);
+
+        config.system.developer_mode = true;
+        let result = replay_events(State(context(config.clone())), options(), "{not json".to_string()).await;
+        assert_eq!(result.err().unwrap().status, StatusCode::BAD_REQUEST);
+
+        let replayed = replay_events(State(context(config)), options(), capture).await.unwrap().0;
+        assert_eq!(replayed["replayed"], 2);
+        assert!(matches!(rx.try_recv().unwrap(), Event::DoorOpen));
+        assert!(matches!(rx.try_recv().unwrap(), Event::DoorClose));
+    }
+}
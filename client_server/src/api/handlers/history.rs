@@ -0,0 +1,80 @@
+//! Usage history endpoints
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::api::{ApiContext, ApiError};
+use crate::history::{AlarmDay, ArmedSummary, DoorDay, HistoryStore};
+
+/// Days looked back when the query does not say
+const DEFAULT_DAYS: u32 = 30;
+
+/// Query parameters of the history endpoints
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    /// Days to look back, 30 by default
+    pub days: Option<u32>,
+}
+
+impl HistoryQuery {
+    fn days(&self) -> u32 {
+        self.days.unwrap_or(DEFAULT_DAYS)
+    }
+}
+
+fn history(ctx: &ApiContext) -> Result<&HistoryStore, ApiError> {
+    ctx.history.as_deref().ok_or_else(|| ApiError {
+        message: "Usage history is disabled".to_string(),
+        status: StatusCode::SERVICE_UNAVAILABLE,
+    })
+}
+
+/// GET /v1/history/doors - Door openings per day with how long the door stayed open
+pub async fn door_history(
+    State(ctx): State<Arc<ApiContext>>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<DoorDay>>, ApiError> {
+    Ok(Json(history(&ctx)?.door_days(query.days())?))
+}
+
+/// GET /v1/history/armed - Time spent armed
+pub async fn armed_history(
+    State(ctx): State<Arc<ApiContext>>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<ArmedSummary>, ApiError> {
+    Ok(Json(history(&ctx)?.armed(query.days())?))
+}
+
+/// GET /v1/history/alarms - Alarms per day and kind
+pub async fn alarm_history(
+    State(ctx): State<Arc<ApiContext>>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<AlarmDay>>, ApiError> {
+    Ok(Json(history(&ctx)?.alarm_days(query.days())?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{create_router, ApiContext};
+    use crate::config::AppConfig;
+    use crate::events::EventBus;
+    use crate::state::new_app_state;
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_disabled_history() {
+        let (event_bus, _rx) = EventBus::new();
+        let app = create_router(ApiContext::for_test(new_app_state(), event_bus, AppConfig::test_default()));
+
+        for uri in ["/v1/history/doors", "/v1/history/armed?days=7", "/v1/history/alarms"] {
+            let response = app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+    }
+}
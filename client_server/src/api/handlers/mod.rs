@@ -6,30 +6,159 @@ mod actuators;
 mod websocket;
 mod config;
 mod ble;
+mod access;
+mod events;
+mod audit;
+mod history;
+mod dead_letters;
+mod rf433;
+mod network;
+mod token;
 
 pub use status::get_status;
-pub use arm_disarm::{arm, disarm};
-pub use actuators::{control_siren, control_floodlight};
+pub use arm_disarm::{arm, disarm, acknowledge_alarm, trigger_panic};
+pub use actuators::{control_siren, test_siren, control_floodlight, control_output, pulse_output, unlock, open_garage, close_garage};
 pub use websocket::websocket_handler;
-pub use config::{get_config, update_config};
-pub use ble::ble_pairing;
+pub use config::{get_config, rollback_config, update_config};
+pub use ble::{get_ble, switch_ble, ble_pairing, ble_pairing_status, confirm_ble_pairing, list_ble_devices, set_ble_permissions, remove_ble_device};
+pub use access::{list_users, enroll_user, remove_user};
+pub use events::{list_events, export_events, replay_events};
+pub use audit::list_audit;
+pub use history::{door_history, armed_history, alarm_history};
+pub use dead_letters::{list_dead_letters, requeue_dead_letter, discard_dead_letter};
+pub use rf433::{get_rf_learn, start_rf_learn, stop_rf_learn, bind_rf_code};
+pub use network::{get_network, set_wifi, set_addressing, diagnose_network};
+pub use token::get_api_token;
 
-use axum::{extract::State, Json};
+use axum::{extract::State, http::StatusCode, Json};
 use serde_json::{json, Value};
 use std::sync::Arc;
+use tracing::info;
 
-use crate::api::ApiContext;
+use crate::api::{ApiContext, ApiError};
+use crate::events::{Event, EventSource};
+use crate::health::HealthStatus;
+use crate::state::AlarmState;
 
 /// Health check endpoint
+///
+/// Answers 503 while a subsystem has failed, so monitoring can alert on the status alone.
 pub async fn health(
     State(ctx): State<Arc<ApiContext>>,
-) -> Json<Value> {
+) -> (StatusCode, Json<Value>) {
     let state = ctx.state.read();
-    
-    Json(json!({
-        "status": "ok",
-        "ready": true,
+    let wiring_ok = state.wiring.as_ref().is_none_or(|report| report.ok);
+    let limits_ok = state.actuator_limits.tripped.is_empty();
+    let clock_ok = !state.connectivity.clock_skewed;
+    let ble_ok = ctx.ble.as_ref().is_none_or(|ble| ble.healthy());
+    let privileges = crate::security::privilege_status();
+    // Still root although configured to drop it
+    let privileges_ok = !(ctx.config.privileges.drop && privileges.root);
+    let event_loop_ok = !state.event_loop.lagging;
+    let ready = state.health.ready();
+    let status = match state.health.status() {
+        HealthStatus::Failed => "failed",
+        HealthStatus::Degraded => "degraded",
+        HealthStatus::Ok if wiring_ok && limits_ok && clock_ok && ble_ok && privileges_ok && event_loop_ok => "ok",
+        HealthStatus::Ok => "degraded",
+    };
+
+    let body = Json(json!({
+        "status": status,
+        "ready": ready,
+        "subsystems": state.health,
         "uptime_s": state.uptime_s(),
         "version": crate::VERSION,
-    }))
+        "wiring": state.wiring,
+        "actuator_limits": state.actuator_limits,
+        "clock_skew_ms": state.connectivity.clock_skew_ms,
+        "cloud_link": state.cloud_link,
+        "rf433": state.rf433,
+        "event_bus": ctx.event_bus.metrics(),
+        "event_loop": state.event_loop,
+        "soc": state.soc,
+        "storage": state.storage,
+        "memory": state.memory,
+        "tasks": state.tasks,
+        "dead_letters": ctx.dead_letters.as_ref().map(|store| store.metrics()),
+        "ble": ctx.ble.as_ref().map(|ble| ble.service_status()),
+        "privileges": privileges,
+    }));
+    (if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE }, body)
+}
+
+/// GET /metrics - Prometheus metrics
+#[cfg(feature = "metrics")]
+pub async fn metrics(
+    State(ctx): State<Arc<ApiContext>>,
+) -> Result<([(axum::http::header::HeaderName, &'static str); 1], String), ApiError> {
+    let metrics = match &ctx.metrics {
+        Some(metrics) => metrics.clone(),
+        None => crate::observability::Metrics::new()?,
+    };
+    let body = metrics.render(&ctx.state.read(), &ctx.event_bus.metrics())?;
+    Ok(([(axum::http::header::CONTENT_TYPE, crate::observability::metrics::CONTENT_TYPE)], body))
+}
+
+/// POST /v1/selftest - Re-run the GPIO wiring self-test
+pub async fn run_self_test(
+    State(ctx): State<Arc<ApiContext>>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    info!("Received GPIO self-test request");
+
+    // The test pulses every output, so keep it away from an armed system
+    if ctx.state.read().alarm_state != AlarmState::Disarmed {
+        return Err(ApiError {
+            message: "Self-test is only allowed while disarmed".to_string(),
+            status: StatusCode::CONFLICT,
+        });
+    }
+
+    ctx.event_bus
+        .emit(Event::SelfTestRequested { source: EventSource::Local })
+        .map_err(|e| ApiError {
+            message: format!("Failed to emit self-test event: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    Ok((StatusCode::ACCEPTED, Json(json!({ "status": "started" }))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{create_router, ApiContext};
+    use crate::config::AppConfig;
+    use crate::events::EventBus;
+    use crate::health::{Component, HealthStatus};
+    use crate::state::new_app_state;
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_health_reports_subsystems() {
+        let state = new_app_state();
+        let (event_bus, _rx) = EventBus::new();
+        let app = create_router(ApiContext::for_test(state.clone(), event_bus, AppConfig::test_default()));
+        let health = || async {
+            let response = app.clone().oneshot(Request::get("/v1/health").body(Body::empty()).unwrap()).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice::<Value>(&body).unwrap())
+        };
+
+        state.write().health.report_ok(Component::Gpio);
+        state.write().health.report_error(Component::Cloud, HealthStatus::Degraded, "connection refused");
+        let (status, json) = health().await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((json["status"].as_str(), json["ready"].as_bool()), (Some("degraded"), Some(true)));
+        assert_eq!(json["subsystems"]["cloud"]["last_error"], "connection refused");
+
+        // A failed subsystem takes the agent out of readiness
+        state.write().health.report_error(Component::Gpio, HealthStatus::Failed, "gpiochip0 gone");
+        let (status, json) = health().await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!((json["status"].as_str(), json["ready"].as_bool()), (Some("failed"), Some(false)));
+        assert_eq!(json["subsystems"]["gpio"]["status"], "failed");
+    }
 }
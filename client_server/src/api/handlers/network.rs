@@ -0,0 +1,208 @@
+//! Network status and settings endpoints
+//!
+//! Changes can cut the device off, so every route here takes the agent's API
+//! key, and a change that loses connectivity is undone after `network.rollback_s`.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+use crate::api::{ApiContext, ApiError};
+use crate::events::{Event, EventSource, NetworkChange};
+use crate::network::{
+    confirm_or_roll_back, diagnose, interface_ipv4, settings::Rollback, DiagnosticReport, IpSettings, NetworkSettings,
+    PendingNetworkChange, WifiSettings,
+};
+use crate::state::CloudStatus;
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    ApiError {
+        message: message.into(),
+        status: StatusCode::BAD_REQUEST,
+    }
+}
+
+/// GET /v1/network - Active uplink, interface addresses and health, radio link quality and any change waiting for confirmation
+pub async fn get_network(State(ctx): State<Arc<ApiContext>>) -> Json<Value> {
+    let interfaces: Vec<Value> = ctx
+        .config
+        .network
+        .prefer
+        .iter()
+        .map(|name| json!({ "name": name, "ipv4": interface_ipv4(name) }))
+        .collect();
+    let state = ctx.state.read();
+    Json(json!({
+        "interface": state.connectivity.interface,
+        "interfaces": interfaces,
+        "health": state.interface_health,
+        "links": state.link_quality,
+        "setup_ap": state.connectivity.setup_ap,
+        "pending_change": state.network_change,
+    }))
+}
+
+/// POST /v1/network/diagnose - Check interfaces, route, gateway, DNS and the master step by step
+pub async fn diagnose_network(State(ctx): State<Arc<ApiContext>>) -> Json<DiagnosticReport> {
+    let uplink = ctx.state.read().connectivity.interface.clone();
+    let report = diagnose(&ctx.config, uplink).await;
+    info!(ok = report.ok, "Network diagnostics run");
+    Json(report)
+}
+
+/// PUT /v1/network/wifi - Join a Wi-Fi network
+pub async fn set_wifi(
+    State(ctx): State<Arc<ApiContext>>,
+    Json(request): Json<WifiSettings>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    request.validate().map_err(|e| bad_request(e.to_string()))?;
+    let settings = NetworkSettings::new(&ctx.config.network);
+    let interface = settings.wifi_interface().to_string();
+    info!(%interface, ssid = %request.ssid, "Changing Wi-Fi network");
+    apply_change(&ctx, interface, NetworkChange::Wifi, settings.set_wifi(&request)).await
+}
+
+/// PUT /v1/network/interfaces/:name - Switch an interface between DHCP and a static address
+pub async fn set_addressing(
+    State(ctx): State<Arc<ApiContext>>,
+    Path(name): Path<String>,
+    Json(request): Json<IpSettings>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    if !ctx.config.network.prefer.contains(&name) {
+        return Err(ApiError {
+            message: format!("Interface {} is not in network.prefer", name),
+            status: StatusCode::NOT_FOUND,
+        });
+    }
+    request.validate().map_err(|e| bad_request(e.to_string()))?;
+    let settings = NetworkSettings::new(&ctx.config.network);
+    info!(interface = %name, ?request, "Changing interface addressing");
+    apply_change(&ctx, name.clone(), request.change(), settings.set_ip(&name, &request)).await
+}
+
+/// Apply one change at a time and watch that connectivity survives it
+async fn apply_change(
+    ctx: &ApiContext,
+    interface: String,
+    change: NetworkChange,
+    apply: impl Future<Output = anyhow::Result<Rollback>>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let now = chrono::Utc::now();
+    let rollback_s = ctx.config.network.rollback_s;
+    let pending = {
+        let mut state = ctx.state.write();
+        if state.network_change.is_some() {
+            return Err(ApiError {
+                message: "A network change is still waiting for confirmation".to_string(),
+                status: StatusCode::CONFLICT,
+            });
+        }
+        let pending = PendingNetworkChange {
+            interface: interface.clone(),
+            change,
+            applied_at: now,
+            confirm_by: now + chrono::Duration::seconds(rollback_s as i64),
+            needs_cloud: state.connectivity.cloud == CloudStatus::Online,
+        };
+        state.network_change = Some(pending.clone());
+        pending
+    };
+
+    let rollback = match apply.await {
+        Ok(rollback) => rollback,
+        Err(e) => {
+            ctx.state.write().network_change = None;
+            return Err(ApiError {
+                message: format!("Failed to apply network change: {:#}", e),
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+            });
+        }
+    };
+    ctx.event_bus
+        .emit(Event::NetworkConfigChanged {
+            interface,
+            change,
+            source: EventSource::Local,
+        })
+        .map_err(|e| ApiError {
+            message: format!("Failed to emit network change event: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+    tokio::spawn(confirm_or_roll_back(
+        ctx.state.clone(),
+        ctx.event_bus.clone(),
+        pending.clone(),
+        rollback,
+        Duration::from_secs(rollback_s),
+    ));
+
+    Ok((StatusCode::ACCEPTED, Json(json!(pending))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::events::EventBus;
+    use crate::state::new_app_state;
+    use axum::{body::Body, http::Request};
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_wifi_change_needs_api_key() {
+        let dir = TempDir::new().unwrap();
+        let mut config = AppConfig::test_default();
+        config.system.api_key = Some("secret-token".to_string());
+        config.network.wpa_supplicant_conf = dir.path().join("wpa_supplicant.conf");
+        config.network.wifi_interface = "wltest9".to_string();
+        let (event_bus, _rx) = EventBus::new();
+        let state = new_app_state();
+        let app = crate::api::create_router(crate::api::ApiContext::for_test(state.clone(), event_bus, config));
+        let request = |token: Option<&str>, body: &str| {
+            let mut request = Request::put("/v1/network/wifi").header("content-type", "application/json");
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.body(Body::from(body.to_string())).unwrap()
+        };
+
+        let body = r#"{"ssid": "Home", "psk": "correct horse"}"#;
+        let response = app.clone().oneshot(request(None, body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app.clone().oneshot(request(Some("wrong-token"), body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .clone()
+            .oneshot(request(Some("secret-token"), r#"{"ssid": "Home", "psk": "short"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // wpa_cli cannot reload a missing interface, and the failed reload undoes the change
+        let response = app.clone().oneshot(request(Some("secret-token"), body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!dir.path().join("wpa_supplicant.conf").exists());
+        assert!(state.read().network_change.is_none());
+
+        let response = app
+            .oneshot(
+                Request::put("/v1/network/interfaces/eth9")
+                    .header("authorization", "Bearer secret-token")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"mode": "dhcp"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,266 @@
+//! RF learn mode endpoints
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tracing::info;
+
+use crate::api::{ApiContext, ApiError};
+use crate::config::{default_restore_s, RfSensorConfig};
+use crate::events::{Event, RfSensorKind};
+use crate::rf433::{format_code, parse_code};
+
+/// Learn session length when the request gives none
+const DEFAULT_LEARN_S: u64 = 120;
+
+/// Longest learn session; codes of neighbours pile up after that
+const MAX_LEARN_S: u64 = 600;
+
+#[derive(Deserialize)]
+pub struct RfLearnRequest {
+    #[serde(default)]
+    pub seconds: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct RfBindRequest {
+    pub code: String,
+    #[serde(rename = "type")]
+    pub kind: RfSensorKind,
+    /// Zone to report as, new or one learned before; not used for panic buttons
+    #[serde(default)]
+    pub zone: Option<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    ApiError {
+        message: message.into(),
+        status: StatusCode::BAD_REQUEST,
+    }
+}
+
+fn session_status(ctx: &ApiContext) -> Value {
+    let state = ctx.state.read();
+    let now = chrono::Utc::now();
+    let session = &state.rf_learn;
+    json!({
+        "active": session.active(now),
+        "expires_in_s": session.until.filter(|_| session.active(now)).map(|until| (until - now).num_seconds()),
+        "heard": session.heard,
+    })
+}
+
+/// GET /v1/rf433/learn - Learn session and the unknown codes it heard
+pub async fn get_rf_learn(State(ctx): State<Arc<ApiContext>>) -> Json<Value> {
+    Json(session_status(&ctx))
+}
+
+/// POST /v1/rf433/learn - Open a learn session; unknown codes pressed meanwhile become bindable
+pub async fn start_rf_learn(
+    State(ctx): State<Arc<ApiContext>>,
+    Json(request): Json<RfLearnRequest>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let seconds = request.seconds.unwrap_or(DEFAULT_LEARN_S);
+    if seconds == 0 || seconds > MAX_LEARN_S {
+        return Err(bad_request(format!("seconds must be within 1-{}", MAX_LEARN_S)));
+    }
+    info!(seconds, "RF learn mode started");
+    ctx.state
+        .write()
+        .rf_learn
+        .start(chrono::Duration::seconds(seconds as i64), chrono::Utc::now());
+    Ok((StatusCode::ACCEPTED, Json(session_status(&ctx))))
+}
+
+/// DELETE /v1/rf433/learn - Close the learn session; codes heard stay bindable
+pub async fn stop_rf_learn(State(ctx): State<Arc<ApiContext>>) -> Json<Value> {
+    ctx.state.write().rf_learn.stop(chrono::Utc::now());
+    Json(session_status(&ctx))
+}
+
+/// POST /v1/rf433/learn/bind - Save a heard code as a door contact, PIR or panic button
+pub async fn bind_rf_code(
+    State(ctx): State<Arc<ApiContext>>,
+    Json(request): Json<RfBindRequest>,
+) -> Result<Json<Value>, ApiError> {
+    let code = format_code(parse_code(&request.code).map_err(|e| bad_request(e.to_string()))?);
+    if !ctx.state.read().rf_learn.heard.iter().any(|heard| heard.code == code) {
+        return Err(ApiError {
+            message: format!("Code {} was not heard in learn mode", code),
+            status: StatusCode::NOT_FOUND,
+        });
+    }
+    let zone = match (request.kind.zone_type(), &request.zone) {
+        (Some(_), None) => return Err(bad_request("zone is required for door and motion sensors")),
+        (Some(_), Some(zone)) => Some(zone.clone()),
+        (None, _) => None,
+    };
+    let store = ctx.config_store.as_ref().ok_or_else(|| ApiError {
+        message: "Configuration cannot be saved".to_string(),
+        status: StatusCode::SERVICE_UNAVAILABLE,
+    })?;
+
+    // Saving syncs the file to disk; keep it off the async workers
+    let change = {
+        let (kind, label, code, zone) = (request.kind, request.label.clone(), code.clone(), zone.clone());
+        store
+            .run_blocking(move |store| {
+                store.edit(|config| {
+                    let (Some(zone_type), Some(zone)) = (kind.zone_type(), &zone) else {
+                        config.rf433.panic_codes.push(code.clone());
+                        return Ok(());
+                    };
+                    match config.rf433.sensors.iter_mut().find(|sensor| &sensor.zone == zone) {
+                        Some(sensor) if sensor.zone_type != zone_type => {
+                            anyhow::bail!("Zone {} has a different type", zone)
+                        }
+                        Some(sensor) => {
+                            sensor.codes.push(code.clone());
+                            if label.is_some() {
+                                sensor.label = label.clone();
+                            }
+                        }
+                        None => config.rf433.sensors.push(RfSensorConfig {
+                            zone: zone.clone(),
+                            label: label.clone(),
+                            zone_type,
+                            codes: vec![code.clone()],
+                            restore_s: default_restore_s(),
+                        }),
+                    }
+                    Ok(())
+                })
+            })
+            .await
+    }
+    .map_err(|e| bad_request(format!("{:#}", e)))?;
+    ctx.state.write().rf_learn.take(&code);
+    info!(%code, kind = ?request.kind, ?zone, "RF code learned");
+
+    // Forwarded to the master like any event, so its copy of the zones follows
+    ctx.event_bus
+        .emit(Event::RfSensorLearned {
+            code: code.clone(),
+            kind: request.kind,
+            zone: zone.clone(),
+            label: request.label.clone(),
+        })
+        .map_err(|e| ApiError {
+            message: format!("Failed to emit learn event: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    Ok(Json(json!({
+        "code": code,
+        "type": request.kind,
+        "zone": zone,
+        "label": request.label,
+        "changed": change.changed,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, ConfigStore};
+    use crate::events::{EventBus, RfProtocol};
+    use crate::state::new_app_state;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_learn_and_bind() {
+        let dir = TempDir::new().unwrap();
+        let (event_bus, mut rx) = EventBus::new();
+        let store = ConfigStore::new(AppConfig::test_default(), dir.path().join("config.toml"));
+        let mut ctx = ApiContext::for_test(new_app_state(), event_bus, AppConfig::test_default());
+        ctx.config_store = Some(store.clone());
+        let ctx = Arc::new(ctx);
+
+        let (status, _) = start_rf_learn(State(ctx.clone()), Json(RfLearnRequest { seconds: None }))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        for code in ["0x00A001", "0x00A002", "0x00A003"] {
+            ctx.state.write().rf_learn.hear(code, RfProtocol::Ev1527, chrono::Utc::now());
+        }
+        assert_eq!(get_rf_learn(State(ctx.clone())).await["heard"].as_array().unwrap().len(), 3);
+
+        let bind = |code: &str, kind, zone: Option<&str>| RfBindRequest {
+            code: code.to_string(),
+            kind,
+            zone: zone.map(str::to_string),
+            label: Some("Back door".to_string()),
+        };
+        // A code never heard, or a sensor without a zone, is refused
+        let err = bind_rf_code(State(ctx.clone()), Json(bind("0x00B001", RfSensorKind::Door, Some("back"))))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status, StatusCode::NOT_FOUND);
+        let err = bind_rf_code(State(ctx.clone()), Json(bind("0x00A001", RfSensorKind::Door, None)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+
+        let learned = bind_rf_code(State(ctx.clone()), Json(bind("0xA001", RfSensorKind::Door, Some("back"))))
+            .await
+            .unwrap();
+        assert_eq!(learned["code"], "0x00A001");
+        assert_eq!(learned["changed"], json!(["rf433"]));
+        let learned = bind_rf_code(State(ctx.clone()), Json(bind("0x00A002", RfSensorKind::Door, Some("back"))))
+            .await
+            .unwrap();
+        assert_eq!(learned["zone"], "back");
+        // An existing zone keeps its type
+        let err = bind_rf_code(State(ctx.clone()), Json(bind("0x00A003", RfSensorKind::Motion, Some("back"))))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        let learned = bind_rf_code(State(ctx.clone()), Json(bind("0x00A003", RfSensorKind::Panic, None)))
+            .await
+            .unwrap();
+        assert_eq!(learned["zone"], Value::Null);
+
+        let config = store.current();
+        assert_eq!(config.rf433.sensors.len(), 1);
+        assert_eq!(config.rf433.sensors[0].codes, ["0x00A001", "0x00A002"]);
+        assert_eq!(config.rf433.sensors[0].label.as_deref(), Some("Back door"));
+        assert_eq!(config.rf433.panic_codes, ["0x00A003"]);
+        assert!(dir.path().join("config.toml").exists());
+        assert!(ctx.state.read().rf_learn.heard.is_empty());
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::RfSensorLearned { kind: RfSensorKind::Door, zone: Some(zone), .. } if zone == "back"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_bind_needs_api_key() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let mut config = AppConfig::test_default();
+        config.system.api_key = Some("secret-token".to_string());
+        let (event_bus, _) = EventBus::new();
+        let app = crate::api::create_router(ApiContext::for_test(new_app_state(), event_bus, config));
+        let request = |token: Option<&str>| {
+            let mut request = Request::builder()
+                .method("POST")
+                .uri("/v1/rf433/learn/bind")
+                .header("content-type", "application/json");
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.body(Body::from(r#"{"code": "0x00A001", "type": "panic"}"#)).unwrap()
+        };
+
+        let response = app.clone().oneshot(request(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        // With the key it gets as far as finding the code was never heard
+        let response = app.oneshot(request(Some("secret-token"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
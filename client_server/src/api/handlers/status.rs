@@ -3,18 +3,36 @@
 use axum::{extract::State, Json};
 use serde::Serialize;
 use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 
+use crate::access::LockdownStatus;
 use crate::api::ApiContext;
-use crate::state::AlarmState;
+use crate::actuators::SirenPattern;
+use crate::events::AlarmKind;
+use crate::state::{AlarmState, ArmMode, PowerState};
 
 #[derive(Serialize)]
 pub struct StatusResponse {
     pub state: String,
+    pub mode: ArmMode,
     pub door: String,
+    pub last_motion: Option<String>,
     pub timers: TimersStatus,
     pub actuators: ActuatorsStatus,
+    pub alarm_memory: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alarm_kind: Option<AlarmKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub siren_pattern: Option<SirenPattern>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lockdown: Option<LockdownStatus>,
     pub connectivity: ConnectivityStatus,
+    pub zones: BTreeMap<String, String>,
+    pub outputs: BTreeMap<String, bool>,
+    pub temperatures: BTreeMap<String, f64>,
+    pub present_beacons: BTreeSet<String>,
+    pub power: PowerState,
     pub last_events: Vec<Value>,
 }
 
@@ -29,12 +47,17 @@ pub struct TimersStatus {
 pub struct ActuatorsStatus {
     pub siren: bool,
     pub floodlight: bool,
+    pub strobe: bool,
 }
 
 #[derive(Serialize)]
 pub struct ConnectivityStatus {
     pub cloud: String,
     pub iface: Option<String>,
+    pub cloud_last_connected: Option<String>,
+    /// SSID of the Wi-Fi setup access point while it runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub setup_ap: Option<String>,
 }
 
 /// GET /v1/status - Get current system status
@@ -59,6 +82,17 @@ pub async fn get_status(
         crate::state::CloudStatus::Connecting => "connecting",
     };
     
+    let mut zones: BTreeMap<String, String> = state.zones
+        .iter()
+        .map(|(name, active)| {
+            let status = if *active { "active" } else { "normal" };
+            (name.clone(), status.to_string())
+        })
+        .collect();
+    for zone in &state.bypassed_zones {
+        zones.insert(zone.clone(), "bypassed".to_string());
+    }
+    
     // Convert last events to JSON
     let last_events: Vec<Value> = state.last_events
         .iter()
@@ -67,7 +101,9 @@ pub async fn get_status(
     
     Json(StatusResponse {
         state: alarm_state.to_string(),
+        mode: state.arm_mode,
         door: door_state.to_string(),
+        last_motion: state.last_motion.map(|t| t.to_rfc3339()),
         timers: TimersStatus {
             exit_s: state.timers.exit_s,
             entry_s: state.timers.entry_s,
@@ -76,11 +112,23 @@ pub async fn get_status(
         actuators: ActuatorsStatus {
             siren: state.actuators.siren,
             floodlight: state.actuators.floodlight,
+            strobe: state.actuators.strobe,
         },
+        alarm_memory: state.alarm_memory,
+        alarm_kind: state.alarm_kind,
+        siren_pattern: state.actuators.siren.then_some(state.siren_pattern),
+        lockdown: state.lockdown.clone(),
         connectivity: ConnectivityStatus {
             cloud: cloud_status.to_string(),
             iface: state.connectivity.interface.clone(),
+            cloud_last_connected: state.connectivity.cloud_last_connected.map(|t| t.to_rfc3339()),
+            setup_ap: state.connectivity.setup_ap.clone(),
         },
+        zones,
+        outputs: state.outputs.clone(),
+        temperatures: state.temperatures.clone(),
+        present_beacons: state.present_beacons.clone(),
+        power: state.power.clone(),
         last_events,
     })
 }
@@ -0,0 +1,19 @@
+//! Local API token endpoint
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::api::{ApiContext, ApiError};
+
+/// GET /v1/token - Current local API token, for consumers still holding the one it replaced
+pub async fn get_api_token(State(ctx): State<Arc<ApiContext>>) -> Result<Json<Value>, ApiError> {
+    let tokens = ctx.api_tokens.as_ref().ok_or_else(|| ApiError {
+        message: "Token rotation is off".to_string(),
+        status: StatusCode::NOT_FOUND,
+    })?;
+    Ok(Json(json!({
+        "token": tokens.current(),
+        "previous_valid_until": tokens.previous_valid_until(),
+    })))
+}
@@ -3,18 +3,27 @@
 use axum::{
     extract::{
         ws::{Message, WebSocket},
-        State, WebSocketUpgrade,
+        ConnectInfo, State, WebSocketUpgrade,
     },
     response::Response,
 };
 use futures::{sink::SinkExt, stream::StreamExt};
 use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
 use tokio::time::{interval, Duration};
 use tracing::{debug, error, info, warn};
 
+use crate::access::PinGuard;
+use crate::actuators::OutputRegistry;
 use crate::api::ApiContext;
-use crate::events::{Event, EventSource};
+use crate::security::{AuditEntry, AuditSource};
+use crate::state::CloudStatus;
+use crate::update::UpdateStage;
+use crate::events::{
+    command_to_event, correlated, new_correlation_id, Event, EventSource, Severity, TemperatureLimit,
+};
 
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -23,18 +32,34 @@ enum WsMessage {
         name: String,
         value: Option<String>,
         ts: String,
+        /// Command the event stems from
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        correlation_id: Option<String>,
     },
     Cmd {
         name: String,
         #[serde(flatten)]
         args: serde_json::Value,
         id: String,
+        /// Used once only; a command repeating a recent nonce is refused
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+        /// Unix seconds after which the command is refused
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<i64>,
     },
     Ack {
         id: String,
         ok: bool,
         #[serde(skip_serializing_if = "Option::is_none")]
         error: Option<String>,
+        /// Carried by every event the command causes
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        correlation_id: Option<String>,
+    },
+    /// Client asks for events at or above a severity only
+    Subscribe {
+        min_severity: Severity,
     },
     Ping,
     Pong,
@@ -44,16 +69,21 @@ enum WsMessage {
 pub async fn websocket_handler(
     ws: WebSocketUpgrade,
     State(ctx): State<Arc<ApiContext>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
 ) -> Response {
-    info!("WebSocket connection request");
-    ws.on_upgrade(move |socket| handle_socket(socket, ctx))
+    let ip = peer.map(|ConnectInfo(addr)| addr.ip());
+    let connection = uuid::Uuid::new_v4().to_string();
+    info!(?ip, %connection, "WebSocket connection request");
+    ws.on_upgrade(move |socket| handle_socket(socket, ctx, connection, ip))
 }
 
-async fn handle_socket(socket: WebSocket, ctx: Arc<ApiContext>) {
+async fn handle_socket(socket: WebSocket, ctx: Arc<ApiContext>, connection: String, ip: Option<IpAddr>) {
     let (mut sender, mut receiver) = socket.split();
     
-    // Subscribe to event bus
+    // Subscribe to event bus; clients may narrow it down by severity
     let mut event_rx = ctx.event_bus.subscribe();
+    let (filter_tx, filter_rx) = watch::channel(Severity::Debug);
+    let (ack_tx, mut ack_rx) = mpsc::unbounded_channel::<WsMessage>();
     
     // Spawn task to send events to client
     let mut send_task = tokio::spawn(async move {
@@ -69,33 +99,475 @@ async fn handle_socket(socket: WebSocket, ctx: Arc<ApiContext>) {
                     }
                 }
                 
+                // Acknowledge commands received by the other task
+                Some(ack) = ack_rx.recv() => {
+                    let Ok(json) = serde_json::to_string(&ack) else {
+                        continue;
+                    };
+                    if sender.send(Message::Text(json)).await.is_err() {
+                        break;
+                    }
+                }
+
                 // Forward events from event bus to WebSocket
                 Ok(envelope) = event_rx.recv() => {
+                    if envelope.severity < *filter_rx.borrow() {
+                        continue;
+                    }
                     let ws_msg = match &envelope.event {
                         Event::UserArm { .. } => WsMessage::Event {
                             name: "state".to_string(),
                             value: Some("exit_delay".to_string()),
                             ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
                         },
                         Event::UserDisarm { .. } => WsMessage::Event {
                             name: "state".to_string(),
                             value: Some("disarmed".to_string()),
                             ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
                         },
                         Event::DoorOpen => WsMessage::Event {
                             name: "door".to_string(),
                             value: Some("open".to_string()),
                             ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
                         },
                         Event::DoorClose => WsMessage::Event {
                             name: "door".to_string(),
                             value: Some("closed".to_string()),
                             ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::MotionDetected { zone } => WsMessage::Event {
+                            name: "motion".to_string(),
+                            value: serde_json::to_value(zone)
+                                .ok()
+                                .and_then(|v| v.as_str().map(str::to_string)),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
                         },
                         Event::TimerEntryExpired => WsMessage::Event {
                             name: "alarm_triggered".to_string(),
                             value: None,
                             ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::ZoneTriggered { zone, .. } => WsMessage::Event {
+                            name: "zone_active".to_string(),
+                            value: Some(zone.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::ZoneRestored { zone, .. } => WsMessage::Event {
+                            name: "zone_normal".to_string(),
+                            value: Some(zone.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::AlarmAcknowledged { .. } => WsMessage::Event {
+                            name: "alarm_ack".to_string(),
+                            value: None,
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::AlarmTriggered { kind, .. } => WsMessage::Event {
+                            name: "alarm".to_string(),
+                            value: Some(kind.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::GlassBreak => WsMessage::Event {
+                            name: "glass_break".to_string(),
+                            value: None,
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::OutputControl { name, on, .. } => WsMessage::Event {
+                            name: if *on { "output_on" } else { "output_off" }.to_string(),
+                            value: Some(name.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::OutputPulse { name, .. } => WsMessage::Event {
+                            name: "output_pulse".to_string(),
+                            value: Some(name.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::TemperatureReading { sensor, celsius } => WsMessage::Event {
+                            name: "temperature".to_string(),
+                            value: Some(format!("{}:{}", sensor, celsius)),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::TemperatureAlert { sensor, limit, .. } => WsMessage::Event {
+                            name: match limit {
+                                TemperatureLimit::High => "temperature_high",
+                                TemperatureLimit::Low => "temperature_low",
+                            }
+                            .to_string(),
+                            value: Some(sensor.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::TemperatureRestored { sensor, .. } => WsMessage::Event {
+                            name: "temperature_normal".to_string(),
+                            value: Some(sensor.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::SocOverheating { celsius } => WsMessage::Event {
+                            name: "soc_overheating".to_string(),
+                            value: Some(format!("{:.1}", celsius)),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::SocCooled { celsius } => WsMessage::Event {
+                            name: "soc_cooled".to_string(),
+                            value: Some(format!("{:.1}", celsius)),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::DiskSpaceLow { free_mb, .. } => WsMessage::Event {
+                            name: "disk_space_low".to_string(),
+                            value: Some(free_mb.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::DiskSpaceCritical { free_mb, .. } => WsMessage::Event {
+                            name: "disk_space_critical".to_string(),
+                            value: Some(free_mb.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::DiskSpaceRestored { free_mb } => WsMessage::Event {
+                            name: "disk_space_restored".to_string(),
+                            value: Some(free_mb.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::MemoryGrowing { rss_mb, .. } => WsMessage::Event {
+                            name: "memory_growing".to_string(),
+                            value: Some(rss_mb.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::MemoryCeilingExceeded { rss_mb, .. } => WsMessage::Event {
+                            name: "memory_ceiling_exceeded".to_string(),
+                            value: Some(rss_mb.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::QueueRecovered { salvaged, .. } => WsMessage::Event {
+                            name: "queue_recovered".to_string(),
+                            value: Some(salvaged.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::PowerLost { voltage } => WsMessage::Event {
+                            name: "power_lost".to_string(),
+                            value: Some(format!("{:.2}", voltage)),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::LowBattery { voltage } => WsMessage::Event {
+                            name: "low_battery".to_string(),
+                            value: Some(format!("{:.2}", voltage)),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::PowerRestored { voltage } => WsMessage::Event {
+                            name: "power_restored".to_string(),
+                            value: Some(format!("{:.2}", voltage)),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::PowerUndervoltage { active } => WsMessage::Event {
+                            name: "power_undervoltage".to_string(),
+                            value: Some(active.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::Tamper { device } => WsMessage::Event {
+                            name: "tamper".to_string(),
+                            value: Some(device.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::RfJamming { duration_ms } => WsMessage::Event {
+                            name: "rf_jamming".to_string(),
+                            value: Some(duration_ms.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::SensorLost { sensor, .. } => WsMessage::Event {
+                            name: "sensor_lost".to_string(),
+                            value: Some(sensor.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::SensorRestored { sensor } => WsMessage::Event {
+                            name: "sensor_restored".to_string(),
+                            value: Some(sensor.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::NetworkInterfaceChanged { interface, .. } => WsMessage::Event {
+                            name: "network_interface_changed".to_string(),
+                            value: interface.clone(),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::NetworkOnline { interface } => WsMessage::Event {
+                            name: "network_online".to_string(),
+                            value: Some(interface.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::NetworkOffline => WsMessage::Event {
+                            name: "network_offline".to_string(),
+                            value: None,
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::NetworkInterfaceFlapping { interface, .. } => WsMessage::Event {
+                            name: "network_interface_flapping".to_string(),
+                            value: Some(interface.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::CaptivePortalDetected { interface, .. } => WsMessage::Event {
+                            name: "captive_portal_detected".to_string(),
+                            value: Some(interface.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::CaptivePortalCleared { interface } => WsMessage::Event {
+                            name: "captive_portal_cleared".to_string(),
+                            value: Some(interface.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::NetworkConfigChanged { interface, .. } => WsMessage::Event {
+                            name: "network_config_changed".to_string(),
+                            value: Some(interface.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::NetworkConfigRolledBack { interface, .. } => WsMessage::Event {
+                            name: "network_config_rolled_back".to_string(),
+                            value: Some(interface.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::SetupApStarted { ssid } => WsMessage::Event {
+                            name: "setup_ap_started".to_string(),
+                            value: Some(ssid.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::SetupApStopped { network_saved } => WsMessage::Event {
+                            name: "setup_ap_stopped".to_string(),
+                            value: Some(network_saved.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::CloudConnected => WsMessage::Event {
+                            name: "cloud_connected".to_string(),
+                            value: None,
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::CloudDisconnected => WsMessage::Event {
+                            name: "cloud_disconnected".to_string(),
+                            value: None,
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::CloudStatusChanged { status, .. } => WsMessage::Event {
+                            name: "cloud_status".to_string(),
+                            value: Some(
+                                match status {
+                                    CloudStatus::Online => "online",
+                                    CloudStatus::Offline => "offline",
+                                    CloudStatus::Connecting => "connecting",
+                                }
+                                .to_string(),
+                            ),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::ClockSkewDetected { skew_ms } => WsMessage::Event {
+                            name: "clock_skew".to_string(),
+                            value: Some(skew_ms.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::ConfigApplied { changed, .. } => WsMessage::Event {
+                            name: "config_applied".to_string(),
+                            value: Some(changed.join(",")),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::ConfigReloaded { changed, .. } => WsMessage::Event {
+                            name: "config_reloaded".to_string(),
+                            value: Some(changed.join(",")),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::ConfigRejected { error, .. } => WsMessage::Event {
+                            name: "config_rejected".to_string(),
+                            value: Some(error.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::AgentUpdate { version, stage, .. } => WsMessage::Event {
+                            name: "agent_update".to_string(),
+                            value: Some(format!(
+                                "{}:{}",
+                                version,
+                                match stage {
+                                    UpdateStage::Staged => "staged",
+                                    UpdateStage::Failed => "failed",
+                                    UpdateStage::Installed => "installed",
+                                    UpdateStage::RolledBack => "rolled_back",
+                                }
+                            )),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::BlePairingWindow { open, .. } => WsMessage::Event {
+                            name: "ble_pairing_window".to_string(),
+                            value: Some(if *open { "open" } else { "closed" }.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::BlePairingRequested { passkey, .. } => WsMessage::Event {
+                            name: "ble_pairing_requested".to_string(),
+                            value: Some(format!("{:06}", passkey)),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::BleDevicePaired { device, .. } => WsMessage::Event {
+                            name: "ble_device_paired".to_string(),
+                            value: Some(device.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::BleCommandRejected { device, .. } => WsMessage::Event {
+                            name: "ble_command_rejected".to_string(),
+                            value: Some(device.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::CommandReplayRejected { command, .. } => WsMessage::Event {
+                            name: "command_replay_rejected".to_string(),
+                            value: Some(command.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::Lockdown { active, .. } => WsMessage::Event {
+                            name: "lockdown".to_string(),
+                            value: Some(if *active { "on" } else { "off" }.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::TokenRotated { token, .. } => WsMessage::Event {
+                            name: "token_rotated".to_string(),
+                            value: Some(token.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::BleDeviceMissing { device, name, .. } => WsMessage::Event {
+                            name: "ble_device_missing".to_string(),
+                            value: Some(name.clone().unwrap_or_else(|| device.clone())),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::BeaconPresence { beacon, present } => WsMessage::Event {
+                            name: if *present { "beacon_present" } else { "beacon_away" }.to_string(),
+                            value: Some(beacon.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::OccupancyChanged { occupied } => WsMessage::Event {
+                            name: "occupancy".to_string(),
+                            value: Some(if *occupied { "occupied" } else { "empty" }.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::RfCodeReceived { code, .. } => WsMessage::Event {
+                            name: "rf_code".to_string(),
+                            value: Some(code.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::RfCodeRejected { remote, .. } => WsMessage::Event {
+                            name: "rf_code_rejected".to_string(),
+                            value: Some(remote.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::RfCommandAccepted { remote, .. } => WsMessage::Event {
+                            name: "rf_command_accepted".to_string(),
+                            value: Some(remote.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::RfCommandRejected { remote, .. } => WsMessage::Event {
+                            name: "rf_command_rejected".to_string(),
+                            value: Some(remote.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::RfSensorLearned { code, .. } => WsMessage::Event {
+                            name: "rf_sensor_learned".to_string(),
+                            value: Some(code.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::RfReplaySuspected { code, .. } => WsMessage::Event {
+                            name: "rf_replay_suspected".to_string(),
+                            value: Some(code.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::CertificatePinMismatch { host } => WsMessage::Event {
+                            name: "certificate_pin_mismatch".to_string(),
+                            value: Some(host.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::ZoneBypassed { zone, bypassed } => WsMessage::Event {
+                            name: if *bypassed { "zone_bypassed" } else { "zone_unbypassed" }.to_string(),
+                            value: Some(zone.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::AccessGranted { user, .. } => WsMessage::Event {
+                            name: "access_granted".to_string(),
+                            value: Some(user.clone()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::AccessDenied { card, .. } => WsMessage::Event {
+                            name: "access_denied".to_string(),
+                            value: card.map(|card| card.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::AccessLockout { lockout_s } => WsMessage::Event {
+                            name: "access_lockout".to_string(),
+                            value: Some(lockout_s.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
+                        },
+                        Event::SelfTestCompleted { report } => WsMessage::Event {
+                            name: "self_test".to_string(),
+                            value: Some(if report.ok { "ok" } else { "failed" }.to_string()),
+                            ts: envelope.timestamp.to_rfc3339(),
+                            correlation_id: envelope.correlation_id.clone(),
                         },
                         _ => continue, // Skip other events
                     };
@@ -118,6 +590,10 @@ async fn handle_socket(socket: WebSocket, ctx: Arc<ApiContext>) {
 
     // Spawn task to receive messages from client
     let event_bus = ctx.event_bus.clone();
+    let pins = ctx.pins.clone();
+    let outputs = OutputRegistry::from_config(&ctx.config);
+    let audit = ctx.audit.clone();
+    let replay = ctx.replay.clone();
     let mut recv_task = tokio::spawn(async move {
         while let Some(Ok(msg)) = receiver.next().await {
             match msg {
@@ -127,11 +603,39 @@ async fn handle_socket(socket: WebSocket, ctx: Arc<ApiContext>) {
                     // Parse command
                     let ws_msg: Result<WsMessage, _> = serde_json::from_str(&text);
                     match ws_msg {
-                        Ok(WsMessage::Cmd { name, args, id: _id }) => {
-                            // Note: Command acknowledgments with id could be implemented here
-                            if let Err(e) = handle_command(&name, args, &event_bus) {
+                        Ok(WsMessage::Cmd { name, args, id, nonce, expires_at }) => {
+                            let correlation_id = new_correlation_id();
+                            let result = correlated(Some(correlation_id.clone()), async {
+                                let result = match &replay {
+                                    Some(replay) => {
+                                        replay.check(EventSource::Ws, &id, &name, &args, nonce.as_deref(), expires_at)
+                                    }
+                                    None => Ok(()),
+                                };
+                                let result = match result {
+                                    Ok(()) => handle_command(&name, args, &event_bus, &pins, &outputs).await,
+                                    Err(e) => Err(e),
+                                };
+                                if let Some(audit) = &audit {
+                                    let source = AuditSource::Ws { connection: connection.clone(), ip };
+                                    audit.record(AuditEntry::new(&name, source, &result));
+                                }
+                                result
+                            })
+                            .await;
+                            if let Err(e) = &result {
                                 warn!(command = %name, error = %e, "Failed to handle command");
                             }
+                            let _ = ack_tx.send(WsMessage::Ack {
+                                id,
+                                ok: result.is_ok(),
+                                error: result.err().map(|e| e.to_string()),
+                                correlation_id: Some(correlation_id),
+                            });
+                        }
+                        Ok(WsMessage::Subscribe { min_severity }) => {
+                            debug!(?min_severity, "WebSocket severity filter changed");
+                            filter_tx.send_replace(min_severity);
                         }
                         Ok(_) => {
                             debug!("Received non-command message");
@@ -166,44 +670,21 @@ async fn handle_socket(socket: WebSocket, ctx: Arc<ApiContext>) {
     info!("WebSocket connection closed");
 }
 
-fn handle_command(
+async fn handle_command(
     name: &str,
     args: serde_json::Value,
     event_bus: &crate::events::EventBus,
+    pins: &PinGuard,
+    outputs: &OutputRegistry,
 ) -> anyhow::Result<()> {
-    let event = match name {
-        "arm" => {
-            let exit_delay = args.get("exit_delay_s")
-                .and_then(|v| v.as_u64());
-            Event::UserArm {
-                source: EventSource::Ws,
-                exit_delay_s: exit_delay,
-            }
-        }
-        "disarm" => {
-            let auto_rearm = args.get("auto_rearm_s")
-                .and_then(|v| v.as_u64());
-            Event::UserDisarm {
-                source: EventSource::Ws,
-                auto_rearm_s: auto_rearm,
-            }
-        }
-        "siren" => {
-            let on = args.get("on")
-                .and_then(|v| v.as_bool())
-                .unwrap_or(false);
-            let duration = args.get("duration_s")
-                .and_then(|v| v.as_u64());
-            Event::SirenControl {
-                on,
-                duration_s: duration,
-            }
-        }
-        _ => {
-            return Err(anyhow::anyhow!("Unknown command: {}", name));
-        }
-    };
-
+    if name == "disarm" && pins.guards_disarm() {
+        let field = |key: &str| args.get(key).and_then(|value| value.as_str()).map(str::to_string);
+        pins.check_disarm_async(field("pin"), field("code"), EventSource::Ws)
+            .await
+            .map_err(|rejection| anyhow::anyhow!(rejection.to_string()))?;
+    }
+    let event = command_to_event(name, &args, EventSource::Ws)?;
+    outputs.check_command(&event)?;
     event_bus.emit(event)?;
     info!(command = %name, "Command executed");
     Ok(())
@@ -219,6 +700,7 @@ mod tests {
             name: "door".to_string(),
             value: Some("open".to_string()),
             ts: "2025-01-01T12:00:00Z".to_string(),
+            correlation_id: None,
         };
 
         let json = serde_json::to_string(&msg).unwrap();
@@ -237,5 +719,23 @@ mod tests {
             }
             _ => panic!("Wrong message type"),
         }
+
+        // Replay protection fields stay out of the command arguments
+        let json = r#"{"type":"cmd","name":"disarm","id":"c2","nonce":"5f1c","expires_at":1700000060}"#;
+        match serde_json::from_str(json).unwrap() {
+            WsMessage::Cmd { args, nonce, expires_at, .. } => {
+                assert_eq!(args, serde_json::json!({}));
+                assert_eq!(nonce.as_deref(), Some("5f1c"));
+                assert_eq!(expires_at, Some(1_700_000_060));
+            }
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    #[test]
+    fn test_subscribe_deserialization() {
+        let json = r#"{"type":"subscribe","min_severity":"warn"}"#;
+        let msg: WsMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, WsMessage::Subscribe { min_severity: Severity::Warn }));
     }
 }
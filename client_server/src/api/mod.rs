@@ -7,42 +7,282 @@ mod error;
 pub use models::*;
 pub use error::*;
 
-use crate::config::AppConfig;
-use crate::events::EventBus;
+use crate::access::PinGuard;
+use crate::ble::BleService;
+use crate::config::{AppConfig, ConfigStore};
+use crate::events::{correlated, new_correlation_id, DeadLetterStore, EventBus, EventJournal, CORRELATION_HEADER};
+use crate::history::HistoryStore;
+use crate::observability::Metrics;
+use crate::security::{constant_time_eq, ApiTokens, AuditEntry, AuditLog, AuditSource, ReplayGuard};
 use crate::state::AppState;
 use axum::{
     Router,
-    routing::{get, post, put},
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    http::{header::AUTHORIZATION, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    routing::{delete, get, post, put},
 };
+use std::net::SocketAddr;
 use std::sync::Arc;
+use tower::ServiceBuilder;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::{Level, Span};
+
+/// Header carrying the ID of each API request, the caller's or a new UUID
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    /// ID of the request being handled, for error responses
+    static REQUEST_ID: String;
+}
+
+/// ID of the API request being handled on this task, if any
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
 
 /// Create the API router
-pub fn create_router(state: AppState, event_bus: EventBus, config: AppConfig) -> Router {
-    let ctx = Arc::new(ApiContext { state, event_bus, config });
+pub fn create_router(ctx: ApiContext) -> Router {
+    let ctx = Arc::new(ctx);
+
+    // These can cut the device off (network, configuration) or decide who may disarm and which
+    // signals the alarm trusts (credentials, BLE permissions, RF codes), so they take the API key
+    let keyed = Router::new()
+        .route("/v1/access/users", get(handlers::list_users))
+        .route("/v1/access/users/:name", put(handlers::enroll_user))
+        .route("/v1/access/users/:name", delete(handlers::remove_user))
+        .route("/v1/ble/devices/:address", put(handlers::set_ble_permissions))
+        .route("/v1/ble/devices/:address", delete(handlers::remove_ble_device))
+        .route("/v1/rf433/learn/bind", post(handlers::bind_rf_code))
+        .route("/v1/config", put(handlers::update_config))
+        .route("/v1/config/rollback", post(handlers::rollback_config))
+        .route("/v1/network", get(handlers::get_network))
+        .route("/v1/network/wifi", put(handlers::set_wifi))
+        .route("/v1/network/interfaces/:name", put(handlers::set_addressing))
+        .route("/v1/network/diagnose", post(handlers::diagnose_network))
+        .route("/v1/token", get(handlers::get_api_token))
+        .route_layer(middleware::from_fn_with_state(ctx.clone(), require_api_key));
     
-    Router::new()
+    let router = Router::new()
         // Health and status
         .route("/v1/health", get(handlers::health))
         .route("/v1/status", get(handlers::get_status))
+        .route("/v1/selftest", post(handlers::run_self_test))
+        .route("/v1/events", get(handlers::list_events))
+        .route("/v1/events/export", get(handlers::export_events))
+        .route("/v1/events/replay", post(handlers::replay_events))
+        .route("/v1/dead-letters", get(handlers::list_dead_letters))
+        .route("/v1/dead-letters/:id", delete(handlers::discard_dead_letter))
+        .route("/v1/dead-letters/:id/requeue", post(handlers::requeue_dead_letter))
+        .route("/v1/audit", get(handlers::list_audit))
+        .route("/v1/history/doors", get(handlers::door_history))
+        .route("/v1/history/armed", get(handlers::armed_history))
+        .route("/v1/history/alarms", get(handlers::alarm_history))
         // Arm and disarm
         .route("/v1/arm", post(handlers::arm))
         .route("/v1/disarm", post(handlers::disarm))
+        .route("/v1/alarm/ack", post(handlers::acknowledge_alarm))
+        .route("/v1/panic", post(handlers::trigger_panic))
         // Actuator control
         .route("/v1/siren", post(handlers::control_siren))
+        .route("/v1/siren/test", post(handlers::test_siren))
         .route("/v1/floodlight", post(handlers::control_floodlight))
+        .route("/v1/outputs/:name", post(handlers::control_output))
+        .route("/v1/outputs/:name/pulse", post(handlers::pulse_output))
+        .route("/v1/lock/unlock", post(handlers::unlock))
+        .route("/v1/garage/open", post(handlers::open_garage))
+        .route("/v1/garage/close", post(handlers::close_garage))
         // Configuration management
         .route("/v1/config", get(handlers::get_config))
-        .route("/v1/config", put(handlers::update_config))
-        // BLE pairing
+        // BLE service, pairing and bonded devices
+        .route("/v1/ble", get(handlers::get_ble))
+        .route("/v1/ble", post(handlers::switch_ble))
+        .route("/v1/ble/pairing", get(handlers::ble_pairing_status))
         .route("/v1/ble/pairing", post(handlers::ble_pairing))
+        .route("/v1/ble/pairing/confirm", post(handlers::confirm_ble_pairing))
+        .route("/v1/ble/devices", get(handlers::list_ble_devices))
+        // RF learn mode
+        .route("/v1/rf433/learn", get(handlers::get_rf_learn))
+        .route("/v1/rf433/learn", post(handlers::start_rf_learn))
+        .route("/v1/rf433/learn", delete(handlers::stop_rf_learn))
         // WebSocket for real-time events
         .route("/v1/ws", get(handlers::websocket_handler))
+        .merge(keyed);
+    #[cfg(feature = "metrics")]
+    let router = router.route("/metrics", get(handlers::metrics));
+
+    let request_id = HeaderName::from_static(REQUEST_ID_HEADER);
+    router
+        .layer(middleware::from_fn_with_state(ctx.clone(), audit))
+        .layer(middleware::from_fn(correlate))
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::new(request_id.clone(), MakeRequestUuid))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(request_span)
+                        .on_response(DefaultOnResponse::new().level(Level::INFO)),
+                )
+                .layer(PropagateRequestIdLayer::new(request_id)),
+        )
         .with_state(ctx)
 }
 
+/// Span every log line of a request is recorded under
+fn request_span(request: &Request) -> Span {
+    let request_id = request_id(request).unwrap_or_default();
+    tracing::info_span!("request", method = %request.method(), path = %request.uri().path(), %request_id)
+}
+
+fn request_id(request: &Request) -> Option<String> {
+    request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .map(str::to_string)
+}
+
+/// Longest caller-supplied correlation ID that is accepted as is
+const MAX_CORRELATION_ID_LEN: usize = 128;
+
+/// Run each request in a correlation scope, reusing the caller's ID when it sends one
+/// and the request ID otherwise, so the events it causes can be traced back to it
+async fn correlate(request: Request, next: Next) -> Response {
+    let request_id = request_id(&request).unwrap_or_else(new_correlation_id);
+    let id = request
+        .headers()
+        .get(CORRELATION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_CORRELATION_ID_LEN)
+        .map(str::to_string)
+        .unwrap_or_else(|| request_id.clone());
+
+    let mut response = REQUEST_ID.scope(request_id, correlated(Some(id.clone()), next.run(request))).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(CORRELATION_HEADER, value);
+    }
+    response
+}
+
+/// Record every request that changes something in the audit log, with the caller's address
+async fn audit(State(ctx): State<Arc<ApiContext>>, request: Request, next: Next) -> Response {
+    let Some(audit) = ctx.audit.as_ref() else {
+        return next.run(request).await;
+    };
+    if matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.run(request).await;
+    }
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let action = format!("{} {}", request.method(), path);
+    let ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+
+    let response = next.run(request).await;
+    let result = if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(response
+            .extensions()
+            .get::<ErrorMessage>()
+            .map(|ErrorMessage(message)| message.clone())
+            .unwrap_or_else(|| response.status().to_string()))
+    };
+    audit.record(AuditEntry::new(&action, AuditSource::Http { ip }, &result));
+    response
+}
+
+/// Let a request through only with `Authorization: Bearer <system.api_key>`,
+/// or with a rotated token when rotation is on
+async fn require_api_key(
+    State(ctx): State<Arc<ApiContext>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let is_set = match &ctx.api_tokens {
+        Some(tokens) => tokens.current().is_some(),
+        None => ctx.config.system.api_key.is_some(),
+    };
+    if !is_set {
+        return Err(ApiError {
+            message: "No API key is set".to_string(),
+            status: StatusCode::FORBIDDEN,
+        });
+    }
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let accepted = token.is_some_and(|token| match &ctx.api_tokens {
+        Some(tokens) => tokens.accepts(token),
+        None => ctx
+            .config
+            .system
+            .api_key
+            .as_deref()
+            .is_some_and(|api_key| constant_time_eq(token.as_bytes(), api_key.as_bytes())),
+    });
+    if !accepted {
+        return Err(ApiError {
+            message: "Missing or wrong API key".to_string(),
+            status: StatusCode::UNAUTHORIZED,
+        });
+    }
+    Ok(next.run(request).await)
+}
+
 /// Shared API context
 pub struct ApiContext {
     pub state: AppState,
     pub event_bus: EventBus,
     pub config: AppConfig,
+    /// PIN checks for disarming, sharing the loaded credentials between requests
+    pub pins: PinGuard,
+    /// Local event history; `None` when the journal is disabled
+    pub journal: Option<Arc<EventJournal>>,
+    /// Events that failed processing; `None` when dead-lettering is disabled
+    pub dead_letters: Option<Arc<DeadLetterStore>>,
+    /// The BLE service; `None` where it was not set up, as in tests
+    pub ble: Option<BleService>,
+    /// Where learned RF codes are saved; `None` in tests that do not persist
+    pub config_store: Option<ConfigStore>,
+    /// Record of control requests; `None` when the audit log is disabled
+    pub audit: Option<Arc<AuditLog>>,
+    /// Nonces of recent WebSocket commands; `None` when replay checks are off
+    pub replay: Option<ReplayGuard>,
+    /// Local API tokens under rotation; `None` when rotation is off and only `system.api_key` is accepted
+    pub api_tokens: Option<ApiTokens>,
+    /// Counters kept by the agent; `None` in tests, where `/metrics` has only the current readings
+    pub metrics: Option<Metrics>,
+    /// Usage history for `/v1/history`; `None` when it is disabled
+    pub history: Option<Arc<HistoryStore>>,
+}
+
+impl ApiContext {
+    /// Context without any of the optional stores and services, for tests to fill in what they use
+    pub fn for_test(state: AppState, event_bus: EventBus, config: AppConfig) -> Self {
+        Self {
+            pins: PinGuard::new(&config, state.clone(), event_bus.clone()),
+            state,
+            event_bus,
+            config,
+            journal: None,
+            dead_letters: None,
+            ble: None,
+            config_store: None,
+            audit: None,
+            replay: None,
+            api_tokens: None,
+            metrics: None,
+            history: None,
+        }
+    }
 }
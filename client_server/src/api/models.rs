@@ -2,9 +2,13 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::state::ArmMode;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ArmRequest {
     pub exit_delay_s: Option<u64>,
+    #[serde(default)]
+    pub mode: ArmMode,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -0,0 +1,270 @@
+//! Occupancy from iBeacon and Eddystone-UID beacons
+//!
+//! The scanner turns advertisements into beacon ids (`ibeacon:<uuid>:<major>:<minor>`
+//! or `eddystone:<namespace>:<instance>`) and feeds them to [`PresenceTracker`],
+//! which decides when a registered beacon arrived or left.
+
+use crate::config::PresenceConfig;
+use crate::events::{Event, EventBus, EventSource};
+use crate::security::{decode_hex, encode_hex};
+use crate::state::{AlarmState, AppState, ArmMode};
+use anyhow::{bail, Context, Result};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Company identifier Apple uses for iBeacon manufacturer data
+pub const APPLE_COMPANY_ID: u16 = 0x004c;
+/// Service data UUID of Eddystone frames
+pub const EDDYSTONE_UUID: Uuid = Uuid::from_u128(0x0000_feaa_0000_1000_8000_0080_5f9b_34fb);
+
+/// Beacon id of iBeacon manufacturer data (after the company id)
+pub fn ibeacon_id(data: &[u8]) -> Option<String> {
+    // Type 0x02, length 0x15, UUID, major, minor, measured power
+    if data.len() < 23 || data[0] != 0x02 || data[1] != 0x15 {
+        return None;
+    }
+    let uuid = Uuid::from_slice(&data[2..18]).ok()?;
+    let major = u16::from_be_bytes([data[18], data[19]]);
+    let minor = u16::from_be_bytes([data[20], data[21]]);
+    Some(format!("ibeacon:{}:{}:{}", uuid.hyphenated(), major, minor))
+}
+
+/// Beacon id of an Eddystone-UID frame; other Eddystone frames carry no identity
+pub fn eddystone_id(data: &[u8]) -> Option<String> {
+    // Frame type 0x00, TX power, 10-byte namespace, 6-byte instance
+    if data.len() < 18 || data[0] != 0x00 {
+        return None;
+    }
+    Some(format!("eddystone:{}:{}", encode_hex(&data[2..12]), encode_hex(&data[12..18])))
+}
+
+/// Canonical form of a configured beacon id, so it can be prefix-matched against sightings
+pub fn normalize_beacon_id(id: &str) -> Result<String> {
+    let parts: Vec<&str> = id.split(':').collect();
+    match parts.as_slice() {
+        ["ibeacon", uuid, rest @ ..] if rest.len() <= 2 => {
+            let uuid = Uuid::parse_str(uuid).context("Invalid iBeacon UUID")?;
+            let mut normalized = format!("ibeacon:{}", uuid.hyphenated());
+            for part in rest {
+                let value: u16 = part.parse().context("iBeacon major and minor must be 0-65535")?;
+                normalized.push_str(&format!(":{}", value));
+            }
+            Ok(normalized)
+        }
+        ["eddystone", namespace, rest @ ..] if rest.len() <= 1 => {
+            let mut normalized = format!("eddystone:{}", hex_id(namespace, 10)?);
+            if let Some(instance) = rest.first() {
+                normalized.push_str(&format!(":{}", hex_id(instance, 6)?));
+            }
+            Ok(normalized)
+        }
+        _ => bail!("Beacon id must be ibeacon:<uuid>[:<major>[:<minor>]] or eddystone:<namespace>[:<instance>]"),
+    }
+}
+
+fn hex_id(value: &str, len: usize) -> Result<String> {
+    let bytes = decode_hex(value).context("Eddystone ids are hex")?;
+    if bytes.len() != len {
+        bail!("Eddystone {}-byte id expected, got {} bytes", len, bytes.len());
+    }
+    Ok(encode_hex(&bytes))
+}
+
+struct TrackedBeacon {
+    name: String,
+    id: String,
+    last_seen: Option<Instant>,
+    present: bool,
+}
+
+impl TrackedBeacon {
+    fn matches(&self, sighting: &str) -> bool {
+        sighting
+            .strip_prefix(self.id.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(':'))
+    }
+}
+
+/// Presence of the registered beacons, with RSSI and timing hysteresis so
+/// a beacon at the edge of range does not flap
+pub struct PresenceTracker {
+    beacons: Vec<TrackedBeacon>,
+    away_after: Duration,
+    min_rssi: Option<i16>,
+    hysteresis_db: i16,
+}
+
+impl PresenceTracker {
+    pub fn new(config: &PresenceConfig) -> Self {
+        let beacons = config
+            .beacons
+            .iter()
+            .filter_map(|beacon| match normalize_beacon_id(&beacon.id) {
+                Ok(id) => Some(TrackedBeacon {
+                    name: beacon.name.clone(),
+                    id,
+                    last_seen: None,
+                    present: false,
+                }),
+                Err(e) => {
+                    warn!(beacon = %beacon.name, error = %e, "Ignoring beacon");
+                    None
+                }
+            })
+            .collect();
+        Self {
+            beacons,
+            away_after: Duration::from_secs(config.away_after_s),
+            min_rssi: config.min_rssi,
+            hysteresis_db: config.rssi_hysteresis_db,
+        }
+    }
+
+    fn occupied(&self) -> bool {
+        self.beacons.iter().any(|beacon| beacon.present)
+    }
+
+    /// Feed a sighting and return the presence events it causes
+    pub fn seen(&mut self, sighting: &str, rssi: Option<i16>, now: Instant) -> Vec<Event> {
+        let was_occupied = self.occupied();
+        let mut events = Vec::new();
+        for beacon in self.beacons.iter_mut().filter(|beacon| beacon.matches(sighting)) {
+            // A present beacon may fade a little before its sightings stop counting
+            let threshold = self
+                .min_rssi
+                .map(|min| if beacon.present { min - self.hysteresis_db } else { min });
+            if let (Some(threshold), Some(rssi)) = (threshold, rssi) {
+                if rssi < threshold {
+                    continue;
+                }
+            }
+            beacon.last_seen = Some(now);
+            if !beacon.present {
+                beacon.present = true;
+                events.push(Event::BeaconPresence {
+                    beacon: beacon.name.clone(),
+                    present: true,
+                });
+            }
+        }
+        if !was_occupied && self.occupied() {
+            events.push(Event::OccupancyChanged { occupied: true });
+        }
+        events
+    }
+
+    /// Mark beacons unseen for `away_after_s` as away and return the events it causes
+    pub fn expire(&mut self, now: Instant) -> Vec<Event> {
+        let was_occupied = self.occupied();
+        let mut events = Vec::new();
+        for beacon in self.beacons.iter_mut().filter(|beacon| beacon.present) {
+            if beacon.last_seen.is_some_and(|seen| now.duration_since(seen) >= self.away_after) {
+                beacon.present = false;
+                events.push(Event::BeaconPresence {
+                    beacon: beacon.name.clone(),
+                    present: false,
+                });
+            }
+        }
+        if was_occupied && !self.occupied() {
+            events.push(Event::OccupancyChanged { occupied: false });
+        }
+        events
+    }
+}
+
+/// Emit presence events, arming away once everyone left while disarmed if `auto_arm` is set
+pub fn publish(events: Vec<Event>, auto_arm: bool, state: &AppState, event_bus: &EventBus) -> Result<()> {
+    for event in events {
+        let left = matches!(event, Event::OccupancyChanged { occupied: false });
+        event_bus.emit(event)?;
+        if left && auto_arm && state.read().alarm_state == AlarmState::Disarmed {
+            info!("All beacons left, arming away");
+            event_bus.emit(Event::UserArm {
+                source: EventSource::System,
+                exit_delay_s: None,
+                mode: ArmMode::Away,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BeaconConfig;
+    use crate::state::new_app_state;
+
+    const KEYS: &str = "ibeacon:f7826da6-4fa2-4e98-8024-bc5b71e0893e:100:1";
+
+    fn tracker() -> PresenceTracker {
+        PresenceTracker::new(&PresenceConfig {
+            beacons: vec![
+                BeaconConfig {
+                    name: "keys".to_string(),
+                    id: "ibeacon:F7826DA6-4FA2-4E98-8024-BC5B71E0893E:100".to_string(),
+                },
+                BeaconConfig {
+                    name: "car".to_string(),
+                    id: "eddystone:00112233445566778899:aabbccddeeff".to_string(),
+                },
+            ],
+            away_after_s: 60,
+            min_rssi: Some(-80),
+            rssi_hysteresis_db: 6,
+            auto_arm: true,
+        })
+    }
+
+    #[test]
+    fn test_parse_advertisements() {
+        let mut ibeacon = vec![0x02, 0x15];
+        ibeacon.extend_from_slice(Uuid::parse_str("f7826da6-4fa2-4e98-8024-bc5b71e0893e").unwrap().as_bytes());
+        ibeacon.extend_from_slice(&[0x00, 0x64, 0x00, 0x01, 0xc5]);
+        assert_eq!(ibeacon_id(&ibeacon).as_deref(), Some(KEYS));
+        assert_eq!(ibeacon_id(&ibeacon[..10]), None);
+
+        let mut eddystone = vec![0x00, 0xee];
+        eddystone.extend_from_slice(&decode_hex("00112233445566778899aabbccddeeff").unwrap());
+        assert_eq!(
+            eddystone_id(&eddystone).as_deref(),
+            Some("eddystone:00112233445566778899:aabbccddeeff")
+        );
+        // URL frames carry no identity
+        assert_eq!(eddystone_id(&[0x10, 0xee, 0x03, b'a']), None);
+
+        assert!(normalize_beacon_id("ibeacon:not-a-uuid").is_err());
+        assert!(normalize_beacon_id("eddystone:0011").is_err());
+        assert!(normalize_beacon_id("altbeacon:1").is_err());
+    }
+
+    #[test]
+    fn test_presence_hysteresis() {
+        let mut tracker = tracker();
+        let start = Instant::now();
+
+        // Too weak to count as arriving
+        assert!(tracker.seen(KEYS, Some(-84), start).is_empty());
+        let events = tracker.seen(KEYS, Some(-70), start);
+        assert!(matches!(&events[..], [Event::BeaconPresence { present: true, .. }, Event::OccupancyChanged { occupied: true }]));
+
+        // Fading but within the hysteresis band keeps it present
+        let later = start + Duration::from_secs(50);
+        assert!(tracker.seen(KEYS, Some(-84), later).is_empty());
+        assert!(tracker.expire(start + Duration::from_secs(100)).is_empty());
+
+        let events = tracker.expire(later + Duration::from_secs(60));
+        assert!(matches!(&events[..], [Event::BeaconPresence { present: false, .. }, Event::OccupancyChanged { occupied: false }]));
+    }
+
+    #[tokio::test]
+    async fn test_auto_arm_when_everyone_left() {
+        let state = new_app_state();
+        let (bus, mut rx) = EventBus::new();
+        publish(vec![Event::OccupancyChanged { occupied: false }], true, &state, &bus).unwrap();
+        assert!(matches!(rx.recv().await.unwrap(), Event::OccupancyChanged { occupied: false }));
+        assert!(matches!(rx.recv().await.unwrap(), Event::UserArm { source: EventSource::System, .. }));
+    }
+}
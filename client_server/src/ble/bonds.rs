@@ -0,0 +1,256 @@
+//! Phones bonded during a pairing window
+//!
+//! The bond list doubles as the BLE whitelist: control writes from any other
+//! device are rejected, and each bond carries the commands it may send.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// What a bonded device may do over the control characteristic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlePermission {
+    Arm,
+    Disarm,
+    Ack,
+}
+
+impl BlePermission {
+    /// Permission needed for a control command, `None` if it is not available over BLE
+    pub fn for_command(command: &str) -> Option<Self> {
+        match command {
+            "arm" => Some(Self::Arm),
+            "disarm" => Some(Self::Disarm),
+            "ack" => Some(Self::Ack),
+            _ => None,
+        }
+    }
+}
+
+/// New bonds may arm and acknowledge; disarming has to be granted explicitly
+pub fn default_permissions() -> Vec<BlePermission> {
+    vec![BlePermission::Arm, BlePermission::Ack]
+}
+
+/// A phone that completed pairing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BondedDevice {
+    /// Identity address, e.g. `AA:BB:CC:DD:EE:FF`
+    pub address: String,
+    /// Identity resolving key (hex) the phone uses for its private addresses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub irk: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default = "default_permissions")]
+    pub permissions: Vec<BlePermission>,
+    pub paired_at: DateTime<Utc>,
+    /// Last time the device was connected or heard advertising
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Signal strength (dBm) of the last sighting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rssi: Option<i16>,
+    /// Battery level (%) for devices exposing the battery service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery: Option<u8>,
+}
+
+impl BondedDevice {
+    pub fn allows(&self, permission: BlePermission) -> bool {
+        self.permissions.contains(&permission)
+    }
+
+    /// Whether the device has gone unseen for longer than `after`, counting from pairing if it never was
+    pub fn missing(&self, now: DateTime<Utc>, after: chrono::Duration) -> bool {
+        now - self.last_seen.unwrap_or(self.paired_at) > after
+    }
+}
+
+/// Bonded devices, kept in a JSON file under the data directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BondStore {
+    #[serde(skip)]
+    path: PathBuf,
+    /// Oldest sighting not yet written to disk
+    #[serde(skip)]
+    unsaved_since: Option<DateTime<Utc>>,
+    devices: Vec<BondedDevice>,
+}
+
+impl BondStore {
+    /// An empty store that will be written to `path`
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            unsaved_since: None,
+            devices: Vec::new(),
+        }
+    }
+
+    /// Load the store, starting empty if the file does not exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut store: Self = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid bond file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        store.path = path.to_path_buf();
+        Ok(store)
+    }
+
+    /// Write the store atomically so the reader never sees a partial file
+    pub fn save(&mut self) -> Result<()> {
+        self.unsaved_since = None;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path).with_context(|| format!("Failed to replace {}", self.path.display()))
+    }
+
+    /// Record a bond; a device bonding again keeps the permissions it had
+    pub fn insert(&mut self, mut device: BondedDevice) {
+        if let Some(known) = self.get(&device.address) {
+            device.permissions = known.permissions.clone();
+        }
+        self.remove(&device.address);
+        self.devices.push(device);
+    }
+
+    pub fn get(&self, address: &str) -> Option<&BondedDevice> {
+        self.devices.iter().find(|known| known.address.eq_ignore_ascii_case(address))
+    }
+
+    /// Record a sighting of a bonded device; false if it is not bonded
+    ///
+    /// Sightings are frequent, so they stay in memory until [`Self::due`] asks for a save.
+    pub fn seen(&mut self, address: &str, rssi: Option<i16>, battery: Option<u8>, now: DateTime<Utc>) -> bool {
+        let Some(device) = self.devices.iter_mut().find(|known| known.address.eq_ignore_ascii_case(address)) else {
+            return false;
+        };
+        device.last_seen = Some(now);
+        device.rssi = rssi.or(device.rssi);
+        device.battery = battery.or(device.battery);
+        self.unsaved_since.get_or_insert(now);
+        true
+    }
+
+    /// Whether unsaved sightings have waited `save_after` to be written
+    pub fn due(&self, now: DateTime<Utc>, save_after: chrono::Duration) -> bool {
+        self.unsaved_since.is_some_and(|since| now - since >= save_after)
+    }
+
+    /// Replace the permissions of a bonded device; false if it is not bonded
+    pub fn set_permissions(&mut self, address: &str, permissions: Vec<BlePermission>) -> bool {
+        match self.devices.iter_mut().find(|known| known.address.eq_ignore_ascii_case(address)) {
+            Some(device) => {
+                device.permissions = permissions;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forget a device; false if it was not bonded
+    pub fn remove(&mut self, address: &str) -> bool {
+        let before = self.devices.len();
+        self.devices.retain(|known| !known.address.eq_ignore_ascii_case(address));
+        self.devices.len() != before
+    }
+
+    pub fn devices(&self) -> &[BondedDevice] {
+        &self.devices
+    }
+}
+
+/// Identity resolving key from a BlueZ device `info` file
+/// (`/var/lib/bluetooth/<adapter>/<device>/info`)
+pub fn parse_irk(info: &str) -> Option<String> {
+    let mut in_section = false;
+    for line in info.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_section = line == "[IdentityResolvingKey]";
+        } else if in_section {
+            if let Some(key) = line.strip_prefix("Key=") {
+                return Some(key.to_ascii_lowercase());
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn device(address: &str) -> BondedDevice {
+        BondedDevice {
+            address: address.to_string(),
+            irk: None,
+            name: None,
+            permissions: default_permissions(),
+            paired_at: Utc::now(),
+            last_seen: None,
+            rssi: None,
+            battery: None,
+        }
+    }
+
+    #[test]
+    fn test_permissions_survive_rebonding() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("ble_bonds.json");
+        let mut store = BondStore::load(&path).unwrap();
+        store.insert(device("AA:BB:CC:DD:EE:FF"));
+        assert!(!store.get("aa:bb:cc:dd:ee:ff").unwrap().allows(BlePermission::Disarm));
+
+        assert!(store.set_permissions("AA:BB:CC:DD:EE:FF", vec![BlePermission::Disarm]));
+        store.insert(device("AA:BB:CC:DD:EE:FF"));
+        store.save().unwrap();
+
+        let store = BondStore::load(&path).unwrap();
+        assert_eq!(store.devices().len(), 1);
+        assert_eq!(store.devices()[0].permissions, vec![BlePermission::Disarm]);
+        assert!(!BondStore::load(&path).unwrap().set_permissions("11:22:33:44:55:66", Vec::new()));
+    }
+
+    #[test]
+    fn test_sightings() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("ble_bonds.json");
+        let mut store = BondStore::load(&path).unwrap();
+        store.insert(device("AA:BB:CC:DD:EE:FF"));
+        let now = Utc::now();
+        let week = chrono::Duration::days(7);
+        assert!(store.devices()[0].missing(now + chrono::Duration::days(8), week));
+
+        assert!(!store.seen("11:22:33:44:55:66", Some(-60), None, now));
+        assert!(store.seen("aa:bb:cc:dd:ee:ff", Some(-60), Some(80), now));
+        // A connection without RSSI keeps the last known values
+        assert!(store.seen("aa:bb:cc:dd:ee:ff", None, None, now + chrono::Duration::minutes(10)));
+        let device = &store.devices()[0];
+        assert_eq!((device.rssi, device.battery), (Some(-60), Some(80)));
+        assert!(!device.missing(now + chrono::Duration::days(7), week));
+
+        let save_after = chrono::Duration::minutes(15);
+        assert!(!store.due(now + chrono::Duration::minutes(10), save_after));
+        assert!(store.due(now + save_after, save_after));
+        store.save().unwrap();
+        assert!(!store.due(now + chrono::Duration::hours(1), save_after));
+        assert_eq!(BondStore::load(&path).unwrap().devices()[0].battery, Some(80));
+    }
+
+    #[test]
+    fn test_parse_irk() {
+        let info = "[General]\nName=Phone\n\n[IdentityResolvingKey]\nKey=00112233445566778899AABBCCDDEEFF\n\n[LinkKey]\nKey=FFFF\n";
+        assert_eq!(parse_irk(info).as_deref(), Some("00112233445566778899aabbccddeeff"));
+        assert_eq!(parse_irk("[LinkKey]\nKey=FFFF\n"), None);
+    }
+}
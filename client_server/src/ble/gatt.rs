@@ -0,0 +1,265 @@
+//! GATT application served through BlueZ
+
+use super::{parse_irk, AdapterInfo, BleService, CONTROL_UUID, MANUFACTURER_ID, PAIRING_UUID, SERVICE_UUID, STATUS_UUID};
+use anyhow::{bail, Context, Result};
+use bluer::adv::Advertisement;
+use bluer::agent::{Agent, ReqError as AgentError, RequestConfirmation};
+use bluer::gatt::local::{
+    Application, Characteristic, CharacteristicNotify, CharacteristicNotifyMethod, CharacteristicRead,
+    CharacteristicWrite, CharacteristicWriteMethod, ReqError, Service,
+};
+use futures::FutureExt;
+use tokio::sync::broadcast;
+use tokio::task::JoinSet;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// How long BlueZ gets to finish bonding after the passkey is confirmed
+const BOND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often connected devices and the adapter are checked
+const CONNECTION_POLL: Duration = Duration::from_secs(10);
+
+/// Advertise the service and serve it until the adapter goes away
+pub async fn serve(ble: BleService) -> Result<()> {
+    let session = bluer::Session::new().await.context("BlueZ is not available")?;
+    let adapter = session.default_adapter().await.context("No Bluetooth adapter")?;
+    adapter.set_powered(true).await?;
+    let address = adapter.address().await?;
+    info!(adapter = %adapter.name(), %address, "BLE adapter ready");
+
+    // Dropped with this future when the service is switched off, which stops the tasks
+    let mut tasks = JoinSet::new();
+    tasks.spawn({
+        let ble = ble.clone();
+        let adapter = adapter.clone();
+        async move {
+            if let Err(e) = advertise(ble, adapter).await {
+                warn!(error = %e, "BLE advertising stopped");
+            }
+        }
+    });
+    tasks.spawn({
+        let ble = ble.clone();
+        let adapter = adapter.clone();
+        async move {
+            if let Err(e) = super::scan::run(ble, adapter).await {
+                warn!(error = %e, "BLE scan stopped");
+            }
+        }
+    });
+
+    // Bonding goes through our agent so it only succeeds inside the pairing window
+    let agent = Agent {
+        request_default: true,
+        request_confirmation: Some(Box::new({
+            let ble = ble.clone();
+            let adapter = adapter.clone();
+            move |request: RequestConfirmation| {
+                let ble = ble.clone();
+                let adapter = adapter.clone();
+                async move {
+                    let address = request.device;
+                    if let Err(e) = ble.request_confirmation(address.to_string(), request.passkey).await {
+                        warn!(device = %address, error = %e, "BLE pairing refused");
+                        return Err(AgentError::Rejected);
+                    }
+                    tokio::spawn(async move {
+                        if let Err(e) = record_bond(&ble, &adapter, address).await {
+                            warn!(device = %address, error = %e, "BLE bond not recorded");
+                        }
+                    });
+                    Ok(())
+                }
+                .boxed()
+            }
+        })),
+        ..Default::default()
+    };
+    let _agent = session.register_agent(agent).await.context("Failed to register pairing agent")?;
+
+    let status = {
+        let ble = ble.clone();
+        Characteristic {
+            uuid: STATUS_UUID,
+            read: Some(CharacteristicRead {
+                read: true,
+                fun: Box::new(move |_| {
+                    let value = ble.status();
+                    async move { Ok(value) }.boxed()
+                }),
+                ..Default::default()
+            }),
+            notify: Some(CharacteristicNotify {
+                notify: true,
+                method: CharacteristicNotifyMethod::Fun(Box::new({
+                    let ble = ble.clone();
+                    move |mut notifier| {
+                        let ble = ble.clone();
+                        let mut events = ble.event_bus.subscribe();
+                        async move {
+                            let mut last = ble.status();
+                            if notifier.notify(last.clone()).await.is_err() {
+                                return;
+                            }
+                            loop {
+                                match events.recv().await {
+                                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                                    Err(broadcast::error::RecvError::Closed) => break,
+                                }
+                                let value = ble.status();
+                                if value == last {
+                                    continue;
+                                }
+                                if notifier.is_stopped() || notifier.notify(value.clone()).await.is_err() {
+                                    break;
+                                }
+                                last = value;
+                            }
+                            debug!("BLE status subscriber left");
+                        }
+                        .boxed()
+                    }
+                })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    };
+
+    let control = {
+        let ble = ble.clone();
+        Characteristic {
+            uuid: CONTROL_UUID,
+            write: Some(CharacteristicWrite {
+                write: true,
+                // Only bonded phones may arm or disarm
+                encrypt_authenticated_write: true,
+                method: CharacteristicWriteMethod::Fun(Box::new(move |value, request| {
+                    let ble = ble.clone();
+                    let device = request.device_address;
+                    async move {
+                        // A disarm checks the PIN, and argon2 takes a while; keep it off the async workers
+                        let control = tokio::task::spawn_blocking(move || ble.control(&device.to_string(), &value));
+                        let result = match control.await {
+                            Ok(result) => result,
+                            Err(e) => Err(e.into()),
+                        };
+                        result.map_err(|e| {
+                            warn!(%device, error = %e, "BLE control write rejected");
+                            ReqError::Failed
+                        })
+                    }
+                    .boxed()
+                })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    };
+
+    let pairing = {
+        let ble = ble.clone();
+        Characteristic {
+            uuid: PAIRING_UUID,
+            read: Some(CharacteristicRead {
+                read: true,
+                fun: Box::new(move |_| {
+                    let value = ble.pairing();
+                    async move { Ok(value) }.boxed()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    };
+
+    let application = Application {
+        services: vec![Service {
+            uuid: SERVICE_UUID,
+            primary: true,
+            characteristics: vec![status, control, pairing],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let _serving = adapter
+        .serve_gatt_application(application)
+        .await
+        .context("Failed to register GATT application")?;
+    info!(name = %ble.name(), "BLE GATT service running");
+    ble.set_adapter(AdapterInfo {
+        name: adapter.name().to_string(),
+        address: address.to_string(),
+        powered: adapter.is_powered().await?,
+    });
+
+    // Registration lasts as long as the handles above are alive
+    let mut poll = tokio::time::interval(CONNECTION_POLL);
+    loop {
+        poll.tick().await;
+        if !adapter.is_powered().await? {
+            bail!("Bluetooth adapter powered off");
+        }
+        let mut connections = Vec::new();
+        for address in adapter.device_addresses().await? {
+            let device = adapter.device(address)?;
+            let connected = device.is_connected().await?;
+            // BlueZ only keeps an RSSI while the device is being heard
+            let rssi = device.rssi().await?;
+            if connected || rssi.is_some() {
+                let battery = device.battery_percentage().await.ok().flatten();
+                ble.device_seen(&address.to_string(), rssi, battery);
+            }
+            if connected {
+                connections.push(address.to_string());
+            }
+        }
+        ble.set_connections(connections);
+    }
+}
+
+/// Advertise the service with the state as manufacturer data, re-advertising whenever it changes
+async fn advertise(ble: BleService, adapter: bluer::Adapter) -> Result<()> {
+    let mut events = ble.event_bus.subscribe();
+    loop {
+        let data = ble.advertisement();
+        let advertisement = Advertisement {
+            service_uuids: [SERVICE_UUID].into_iter().collect(),
+            manufacturer_data: [(MANUFACTURER_ID, data.clone())].into_iter().collect(),
+            discoverable: Some(true),
+            local_name: Some(ble.name().to_string()),
+            ..Default::default()
+        };
+        let _advertising = adapter.advertise(advertisement).await.context("Failed to advertise")?;
+
+        // Events are broadcast once the state machine applied them
+        loop {
+            match events.recv().await {
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            }
+            if ble.advertisement() != data {
+                break;
+            }
+        }
+        debug!("BLE state changed, updating advertisement");
+    }
+}
+
+/// Wait for BlueZ to finish bonding, then trust and keep the device
+async fn record_bond(ble: &BleService, adapter: &bluer::Adapter, address: bluer::Address) -> Result<()> {
+    let device = adapter.device(address)?;
+    let deadline = tokio::time::Instant::now() + BOND_TIMEOUT;
+    while !device.is_paired().await? {
+        if tokio::time::Instant::now() >= deadline {
+            bail!("Bonding did not complete");
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    device.set_trusted(true).await?;
+    // BlueZ keeps the keys it exchanged in its own storage
+    let info = format!("/var/lib/bluetooth/{}/{}/info", adapter.address().await?, address);
+    let irk = std::fs::read_to_string(info).ok().as_deref().and_then(parse_irk);
+    ble.record_bond(address.to_string(), device.name().await?, irk)
+}
@@ -1,4 +1,752 @@
-//! BLE GATT service module
-//! TODO: Implement BLE GATT service with secure pairing
+//! BLE GATT service for phones near the door
+//!
+//! One primary service with three characteristics:
+//! - status: compact JSON of the alarm state, readable and notified on change
+//! - control: `{"command": "arm" | "disarm" | "ack", ...args}`, written only
+//!   over an authenticated link by a bonded device holding the permission
+//! - pairing: whether the pairing window is open and for how long
+//!
+//! The advertisement carries the state as manufacturer data (see
+//! [`advertisement_payload`]) so displays and keyfobs can show it without
+//! connecting; it is refreshed on every state transition.
+//!
+//! With `ble.presence.beacons` configured, the adapter also scans for those
+//! iBeacon/Eddystone beacons and reports who is home.
+//!
+//! Phones can only bond while the pairing window opened through
+//! `/v1/ble/pairing` lasts, and each bond needs its passkey confirmed through
+//! `/v1/ble/pairing/confirm`. Bonded devices are kept in
+//! `<data_dir>/ble_bonds.json`.
+//!
+//! The GATT server itself needs BlueZ and the `ble` feature; this module
+//! holds what it serves so it works the same without a radio. It can be
+//! switched on and off at runtime through `/v1/ble`.
 
-pub struct BleService;
+pub mod beacons;
+mod bonds;
+#[cfg(feature = "ble")]
+mod gatt;
+#[cfg(feature = "ble")]
+mod scan;
+
+pub use beacons::{normalize_beacon_id, PresenceTracker};
+pub use bonds::{default_permissions, parse_irk, BlePermission, BondStore, BondedDevice};
+
+use crate::access::PinGuard;
+use crate::config::{AppConfig, PresenceConfig};
+use crate::events::{command_to_event, Event, EventBus, EventSource};
+use crate::health::{Component, HealthStatus};
+use crate::security::{AuditEntry, AuditLog, AuditSource};
+use crate::state::{AlarmState, AppState, ArmMode, SharedState};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Utc;
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, watch};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+pub const SERVICE_UUID: Uuid = Uuid::from_u128(0x6e0a_0001_6b8e_4d6c_9a8e_3f1c_2b5d_7a10);
+pub const STATUS_UUID: Uuid = Uuid::from_u128(0x6e0a_0002_6b8e_4d6c_9a8e_3f1c_2b5d_7a10);
+pub const CONTROL_UUID: Uuid = Uuid::from_u128(0x6e0a_0003_6b8e_4d6c_9a8e_3f1c_2b5d_7a10);
+pub const PAIRING_UUID: Uuid = Uuid::from_u128(0x6e0a_0004_6b8e_4d6c_9a8e_3f1c_2b5d_7a10);
+
+/// Company identifier of the manufacturer data (0xFFFF is reserved for unassigned use)
+pub const MANUFACTURER_ID: u16 = 0xffff;
+/// First byte of the manufacturer data, bumped when the layout changes
+const ADVERTISEMENT_VERSION: u8 = 1;
+
+/// How long a pairing request waits for its passkey to be confirmed
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Wait before starting the GATT service again after it failed
+const RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// How often bonded devices are checked for having gone missing
+const MISSING_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Longest a sighting of a bonded device stays unsaved
+const SIGHTING_SAVE_AFTER: chrono::Duration = chrono::Duration::minutes(15);
+
+/// What the BLE subsystem is doing, for `/v1/ble` and `/v1/health`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BleStatus {
+    pub enabled: bool,
+    pub running: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adapter: Option<AdapterInfo>,
+    /// Addresses of connected devices
+    pub connections: Vec<String>,
+    /// Why the service last stopped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdapterInfo {
+    pub name: String,
+    pub address: String,
+    pub powered: bool,
+}
+
+/// State behind the GATT characteristics
+#[derive(Clone)]
+pub struct BleService {
+    state: AppState,
+    event_bus: EventBus,
+    /// Name advertised to phones
+    name: String,
+    pairing_window: Duration,
+    presence: PresenceConfig,
+    pairing: Arc<Mutex<Pairing>>,
+    bonds: Arc<Mutex<BondStore>>,
+    enabled: Arc<watch::Sender<bool>>,
+    runtime: Arc<Mutex<BleStatus>>,
+    /// Bonded devices warned about until they are seen again; none when 0
+    missing_after_days: u64,
+    missing: Arc<Mutex<HashSet<String>>>,
+    /// Checks the PIN a disarm carries when `access.require_pin_to_disarm` is
+    /// set, and refuses disarming during lockdown
+    pins: PinGuard,
+    audit: Option<Arc<AuditLog>>,
+}
+
+#[derive(Default)]
+struct Pairing {
+    /// Pairing is accepted until then
+    until: Option<Instant>,
+    /// Bumped on every open so the timer of an earlier window leaves a newer one alone
+    window: u64,
+    pending: Option<PendingConfirmation>,
+}
+
+/// A bonding request waiting for its passkey to be confirmed
+struct PendingConfirmation {
+    device: String,
+    passkey: u32,
+    reply: oneshot::Sender<bool>,
+}
+
+impl BleService {
+    pub fn new(config: &AppConfig, state: AppState, event_bus: EventBus) -> Self {
+        let path = config.ble_bonds_path();
+        let bonds = BondStore::load(&path).unwrap_or_else(|e| {
+            warn!(error = %e, "Bonded BLE devices unreadable, starting without them");
+            BondStore::new(path.clone())
+        });
+        Self {
+            pins: PinGuard::new(config, state.clone(), event_bus.clone()),
+            state,
+            event_bus,
+            name: format!("pi-door {}", config.system.client_id),
+            pairing_window: Duration::from_secs(config.ble.pairing_window_s),
+            presence: config.ble.presence.clone(),
+            pairing: Arc::new(Mutex::new(Pairing::default())),
+            bonds: Arc::new(Mutex::new(bonds)),
+            enabled: Arc::new(watch::Sender::new(config.ble.enabled)),
+            runtime: Arc::new(Mutex::new(BleStatus::default())),
+            missing_after_days: config.ble.missing_after_days,
+            missing: Arc::new(Mutex::new(HashSet::new())),
+            audit: None,
+        }
+    }
+
+    /// Record control writes, accepted or rejected, in the audit log
+    pub fn set_audit(&mut self, audit: Arc<AuditLog>) {
+        self.audit = Some(audit);
+    }
+
+    /// Serve the GATT application while the service is enabled, restarting it after failures
+    pub async fn run(self) {
+        let mut enabled = self.enabled.subscribe();
+        loop {
+            // The sender lives in `self`, so waiting cannot fail
+            let _ = enabled.wait_for(|on| *on).await;
+            info!("BLE service starting");
+            let result = tokio::select! {
+                result = self.serve() => result,
+                _ = enabled.wait_for(|on| !*on) => Ok(()),
+            };
+            let failed = result.is_err();
+            let error = result.err().map(|e| format!("{:#}", e));
+            {
+                let mut runtime = self.runtime.lock();
+                runtime.running = false;
+                runtime.connections.clear();
+                runtime.error = error.clone();
+            }
+            match error {
+                Some(error) => {
+                    warn!(%error, "BLE service unavailable");
+                    self.state.write().health.report_error(Component::Ble, HealthStatus::Degraded, error);
+                }
+                None => {
+                    info!("BLE service stopped");
+                    self.state.write().health.remove(Component::Ble);
+                }
+            }
+            if failed {
+                tokio::select! {
+                    _ = tokio::time::sleep(RESTART_DELAY) => {}
+                    _ = enabled.changed() => {}
+                }
+            }
+        }
+    }
+
+    async fn serve(&self) -> Result<()> {
+        #[cfg(feature = "ble")]
+        {
+            gatt::serve(self.clone()).await
+        }
+        #[cfg(not(feature = "ble"))]
+        {
+            bail!("Built without the `ble` feature")
+        }
+    }
+
+    /// Start or stop the GATT service, advertising and beacon scan
+    pub fn set_enabled(&self, enabled: bool) {
+        info!(enabled, "BLE service switched");
+        self.enabled.send_replace(enabled);
+        if !enabled {
+            self.close_pairing();
+        }
+    }
+
+    pub fn service_status(&self) -> BleStatus {
+        BleStatus {
+            enabled: *self.enabled.borrow(),
+            ..self.runtime.lock().clone()
+        }
+    }
+
+    /// Enabled but not running means the service failed or is still coming up
+    pub fn healthy(&self) -> bool {
+        let status = self.service_status();
+        !status.enabled || status.running
+    }
+
+    /// Called by the GATT server once the adapter is up
+    pub fn set_adapter(&self, adapter: AdapterInfo) {
+        let mut runtime = self.runtime.lock();
+        runtime.adapter = Some(adapter);
+        runtime.running = true;
+        runtime.error = None;
+        // Not under the runtime lock: the health endpoint takes it under the state lock
+        drop(runtime);
+        self.state.write().health.report_ok(Component::Ble);
+    }
+
+    /// Called by the GATT server with the devices currently connected
+    pub fn set_connections(&self, connections: Vec<String>) {
+        self.runtime.lock().connections = connections;
+    }
+
+    /// Name advertised to phones
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Beacons to scan for
+    pub fn presence(&self) -> &PresenceConfig {
+        &self.presence
+    }
+
+    /// Value of the status characteristic
+    pub fn status(&self) -> Vec<u8> {
+        status_payload(&self.state.read())
+    }
+
+    /// Manufacturer data of the advertisement
+    pub fn advertisement(&self) -> Vec<u8> {
+        advertisement_payload(&self.state.read(), self.pairing_remaining().is_some())
+    }
+
+    /// Handle a write to the control characteristic from `device`
+    pub fn control(&self, device: &str, value: &[u8]) -> Result<()> {
+        let Some(bond) = self.bonds.lock().get(device).cloned() else {
+            return self.reject(device, None, "device is not bonded");
+        };
+        let mut args: Value = serde_json::from_slice(value).context("Control write is not JSON")?;
+        let name = args
+            .as_object_mut()
+            .and_then(|fields| fields.remove("command"))
+            .and_then(|name| name.as_str().map(str::to_string))
+            .ok_or_else(|| anyhow!("Control write needs a \"command\""))?;
+        let permission = BlePermission::for_command(&name)
+            .ok_or_else(|| anyhow!("Command {} is not available over BLE", name))?;
+        if !bond.allows(permission) {
+            return self.reject(device, Some(name), "device lacks the permission");
+        }
+        if permission == BlePermission::Disarm && self.pins.guards_disarm() {
+            let pin = args.get("pin").and_then(|pin| pin.as_str());
+            if let Err(rejection) = self.pins.check_disarm(pin, None, EventSource::Ble) {
+                return self.reject(device, Some(name), &rejection.to_string());
+            }
+        }
+        let event = command_to_event(&name, &args, EventSource::Ble)?;
+        info!(%device, command = %name, "BLE command received");
+        let result = self.event_bus.emit(event);
+        self.audit_command(device, &name, &result);
+        result
+    }
+
+    fn reject(&self, device: &str, command: Option<String>, reason: &str) -> Result<()> {
+        warn!(%device, ?command, reason, "BLE command rejected");
+        self.audit_command(device, command.as_deref().unwrap_or("control"), &Err::<(), _>(reason));
+        self.emit(Event::BleCommandRejected {
+            device: device.to_string(),
+            command,
+            reason: reason.to_string(),
+        });
+        bail!("BLE command rejected: {}", reason)
+    }
+
+    fn audit_command<T, E: std::fmt::Display>(&self, device: &str, command: &str, result: &Result<T, E>) {
+        if let Some(audit) = &self.audit {
+            let source = AuditSource::Ble { device: device.to_string() };
+            audit.record(AuditEntry::new(command, source, result));
+        }
+    }
+
+    /// Value of the pairing characteristic
+    pub fn pairing(&self) -> Vec<u8> {
+        let remaining = self.pairing_remaining();
+        serde_json::to_vec(&json!({
+            "open": remaining.is_some(),
+            "expires_in_s": remaining.map(|left| left.as_secs()),
+        }))
+        .unwrap_or_default()
+    }
+
+    /// Accept pairing for `duration`, or `ble.pairing_window_s`; the window closes by itself
+    pub fn open_pairing(&self, duration: Option<Duration>) -> Duration {
+        let duration = duration.unwrap_or(self.pairing_window);
+        let window = {
+            let mut pairing = self.pairing.lock();
+            pairing.until = Some(Instant::now() + duration);
+            pairing.window += 1;
+            pairing.window
+        };
+        info!(duration_s = duration.as_secs(), "BLE pairing window open");
+        self.emit(Event::BlePairingWindow {
+            open: true,
+            expires_in_s: Some(duration.as_secs()),
+        });
+
+        let ble = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            if ble.pairing.lock().window == window {
+                ble.close_pairing();
+            }
+        });
+        duration
+    }
+
+    /// Stop accepting pairing, turning down a request still waiting for confirmation
+    pub fn close_pairing(&self) {
+        let was_open = {
+            let mut pairing = self.pairing.lock();
+            pairing.pending = None;
+            pairing.until.take().is_some()
+        };
+        if was_open {
+            info!("BLE pairing window closed");
+            self.emit(Event::BlePairingWindow {
+                open: false,
+                expires_in_s: None,
+            });
+        }
+    }
+
+    /// Time left in the pairing window, if it is open
+    pub fn pairing_remaining(&self) -> Option<Duration> {
+        let until = self.pairing.lock().until?;
+        until.checked_duration_since(Instant::now()).filter(|left| !left.is_zero())
+    }
+
+    /// Device and passkey of the request waiting for confirmation
+    pub fn pending_passkey(&self) -> Option<(String, u32)> {
+        let pairing = self.pairing.lock();
+        pairing.pending.as_ref().map(|pending| (pending.device.clone(), pending.passkey))
+    }
+
+    /// Called by the BlueZ agent: hold a bonding request until its passkey is confirmed
+    pub async fn request_confirmation(&self, device: String, passkey: u32) -> Result<()> {
+        if self.pairing_remaining().is_none() {
+            bail!("Pairing window is closed");
+        }
+        let (reply, confirmed) = oneshot::channel();
+        self.pairing.lock().pending = Some(PendingConfirmation {
+            device: device.clone(),
+            passkey,
+            reply,
+        });
+        info!(%device, "BLE pairing requested, waiting for passkey confirmation");
+        self.emit(Event::BlePairingRequested { device, passkey });
+
+        match tokio::time::timeout(CONFIRM_TIMEOUT, confirmed).await {
+            Ok(Ok(true)) => Ok(()),
+            Ok(Ok(false)) => bail!("Passkey rejected"),
+            Ok(Err(_)) => bail!("Pairing request superseded or window closed"),
+            Err(_) => {
+                self.pairing.lock().pending = None;
+                bail!("Passkey not confirmed in time")
+            }
+        }
+    }
+
+    /// Accept or turn down the waiting request showing `passkey`
+    pub fn confirm_passkey(&self, passkey: u32, accept: bool) -> Result<()> {
+        let pending = {
+            let mut pairing = self.pairing.lock();
+            match &pairing.pending {
+                Some(pending) if pending.passkey == passkey => pairing.pending.take(),
+                _ => None,
+            }
+        };
+        let pending = pending.ok_or_else(|| anyhow!("No pairing request with passkey {:06}", passkey))?;
+        info!(device = %pending.device, accept, "BLE passkey confirmation");
+        pending
+            .reply
+            .send(accept)
+            .map_err(|_| anyhow!("Pairing request is no longer waiting"))
+    }
+
+    /// Keep a device that finished bonding, then close the window
+    pub fn record_bond(&self, address: String, name: Option<String>, irk: Option<String>) -> Result<()> {
+        {
+            let mut bonds = self.bonds.lock();
+            bonds.insert(BondedDevice {
+                address: address.clone(),
+                irk,
+                name: name.clone(),
+                permissions: default_permissions(),
+                paired_at: Utc::now(),
+                last_seen: None,
+                rssi: None,
+                battery: None,
+            });
+            bonds.save()?;
+        }
+        info!(device = %address, "BLE device paired");
+        self.emit(Event::BleDevicePaired { device: address, name });
+        self.close_pairing();
+        Ok(())
+    }
+
+    /// Record that a device was connected or heard; devices that are not bonded are ignored
+    pub fn device_seen(&self, address: &str, rssi: Option<i16>, battery: Option<u8>) {
+        let now = Utc::now();
+        let mut bonds = self.bonds.lock();
+        if !bonds.seen(address, rssi, battery, now) {
+            return;
+        }
+        if self.missing.lock().remove(&address.to_ascii_uppercase()) {
+            info!(device = %address, "Missing BLE device seen again");
+        }
+        if bonds.due(now, SIGHTING_SAVE_AFTER) {
+            if let Err(e) = bonds.save() {
+                warn!(error = %e, "Failed to save BLE device sightings");
+            }
+        }
+    }
+
+    /// Bonded devices that just went past `ble.missing_after_days` unseen, reported once each
+    pub fn missing_devices(&self, now: chrono::DateTime<Utc>) -> Vec<Event> {
+        if self.missing_after_days == 0 {
+            return Vec::new();
+        }
+        let after = chrono::Duration::days(self.missing_after_days as i64);
+        let mut missing = self.missing.lock();
+        self.bonds
+            .lock()
+            .devices()
+            .iter()
+            .filter(|device| device.missing(now, after) && missing.insert(device.address.to_ascii_uppercase()))
+            .map(|device| Event::BleDeviceMissing {
+                device: device.address.clone(),
+                name: device.name.clone(),
+                last_seen: device.last_seen,
+            })
+            .collect()
+    }
+
+    /// Warn about bonded devices that stopped showing up, while the service runs to see them
+    pub async fn watch_devices(self) {
+        let mut interval = tokio::time::interval(MISSING_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !self.service_status().running {
+                continue;
+            }
+            for event in self.missing_devices(Utc::now()) {
+                warn!(?event, "BLE device missing");
+                self.emit(event);
+            }
+        }
+    }
+
+    pub fn bonded_devices(&self) -> Vec<BondedDevice> {
+        self.bonds.lock().devices().to_vec()
+    }
+
+    /// Replace what a bonded device may do; false if it is not bonded
+    pub fn set_permissions(&self, address: &str, permissions: Vec<BlePermission>) -> Result<bool> {
+        let mut bonds = self.bonds.lock();
+        if !bonds.set_permissions(address, permissions) {
+            return Ok(false);
+        }
+        bonds.save()?;
+        Ok(true)
+    }
+
+    /// Drop a device from the whitelist; false if it was not bonded
+    pub fn forget(&self, address: &str) -> Result<bool> {
+        let mut bonds = self.bonds.lock();
+        if !bonds.remove(address) {
+            return Ok(false);
+        }
+        bonds.save()?;
+        info!(device = %address, "BLE device removed");
+        Ok(true)
+    }
+
+    fn emit(&self, event: Event) {
+        if let Err(e) = self.event_bus.emit(event) {
+            warn!(error = %e, "Failed to emit BLE event");
+        }
+    }
+}
+
+/// Compact JSON kept well under a typical ATT MTU
+fn status_payload(state: &SharedState) -> Vec<u8> {
+    serde_json::to_vec(&json!({
+        "state": state.alarm_state,
+        "mode": state.arm_mode,
+        "door": if state.door_open { "open" } else { "closed" },
+        "alarm_memory": state.alarm_memory,
+        "exit_s": state.timers.exit_s,
+        "entry_s": state.timers.entry_s,
+        "setup_ap": state.connectivity.setup_ap,
+    }))
+    .unwrap_or_default()
+}
+
+/// Four bytes small enough for a legacy advertisement:
+/// - version
+/// - alarm state: 0 disarmed, 1 exit delay, 2 armed, 3 entry delay, 4 alarm
+/// - arm mode: 0 away, 1 stay, 2 night
+/// - flags: bit 0 door open, bit 1 alarm memory, bit 2 pairing window open,
+///   bit 3 Wi-Fi setup access point up
+pub fn advertisement_payload(state: &SharedState, pairing: bool) -> Vec<u8> {
+    let alarm_state = match state.alarm_state {
+        AlarmState::Disarmed => 0,
+        AlarmState::ExitDelay => 1,
+        AlarmState::Armed => 2,
+        AlarmState::EntryDelay => 3,
+        AlarmState::Alarm => 4,
+    };
+    let mode = match state.arm_mode {
+        ArmMode::Away => 0,
+        ArmMode::Stay => 1,
+        ArmMode::Night => 2,
+    };
+    let flags = u8::from(state.door_open)
+        | u8::from(state.alarm_memory) << 1
+        | u8::from(pairing) << 2
+        | u8::from(state.connectivity.setup_ap.is_some()) << 3;
+    vec![ADVERTISEMENT_VERSION, alarm_state, mode, flags]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::events::Event;
+    use crate::state::new_app_state;
+
+    fn service(dir: &tempfile::TempDir) -> (BleService, crate::events::EventReceiver) {
+        let (bus, rx) = EventBus::new();
+        let mut config = AppConfig::test_default();
+        config.system.data_dir = dir.path().to_path_buf();
+        (BleService::new(&config, new_app_state(), bus), rx)
+    }
+
+    #[tokio::test]
+    async fn test_control_writes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (ble, mut rx) = service(&dir);
+        let phone = "AA:BB:CC:DD:EE:FF";
+        ble.bonds.lock().insert(BondedDevice {
+            address: phone.to_string(),
+            irk: None,
+            name: None,
+            permissions: default_permissions(),
+            paired_at: Utc::now(),
+            last_seen: None,
+            rssi: None,
+            battery: None,
+        });
+
+        ble.control(phone, br#"{"command": "arm", "mode": "stay"}"#).unwrap();
+        match rx.recv().await.unwrap() {
+            Event::UserArm { source, .. } => assert_eq!(source, EventSource::Ble),
+            other => panic!("Unexpected event {other:?}"),
+        }
+
+        assert!(ble.control(phone, br#"{"command": "siren", "on": true}"#).is_err());
+        assert!(ble.control(phone, br#"{"mode": "stay"}"#).is_err());
+        assert!(ble.control(phone, b"arm").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_whitelist() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (ble, mut rx) = service(&dir);
+        let phone = "AA:BB:CC:DD:EE:FF";
+
+        assert!(ble.control(phone, br#"{"command": "arm"}"#).is_err());
+        assert!(matches!(rx.recv().await.unwrap(), Event::BleCommandRejected { command: None, .. }));
+
+        ble.bonds.lock().insert(BondedDevice {
+            address: phone.to_string(),
+            irk: None,
+            name: None,
+            permissions: default_permissions(),
+            paired_at: Utc::now(),
+            last_seen: None,
+            rssi: None,
+            battery: None,
+        });
+        assert!(ble.control(phone, br#"{"command": "disarm"}"#).is_err());
+        assert!(matches!(rx.recv().await.unwrap(), Event::BleCommandRejected { command: Some(_), .. }));
+
+        assert!(ble.set_permissions(phone, vec![BlePermission::Disarm]).unwrap());
+        ble.control(phone, br#"{"command": "disarm"}"#).unwrap();
+        assert!(matches!(rx.recv().await.unwrap(), Event::UserDisarm { .. }));
+
+        assert!(ble.forget(phone).unwrap());
+        assert!(ble.control(phone, br#"{"command": "disarm"}"#).is_err());
+    }
+
+    #[test]
+    fn test_advertisement_payload() {
+        let mut state = SharedState::new();
+        assert_eq!(advertisement_payload(&state, false), vec![1, 0, 0, 0]);
+
+        state.alarm_state = AlarmState::Alarm;
+        state.arm_mode = ArmMode::Night;
+        state.door_open = true;
+        state.alarm_memory = true;
+        assert_eq!(advertisement_payload(&state, true), vec![1, 4, 2, 0b111]);
+        state.set_setup_ap(Some("pi-door-setup".to_string()));
+        assert_eq!(advertisement_payload(&state, true), vec![1, 4, 2, 0b1111]);
+    }
+
+    #[tokio::test]
+    async fn test_missing_devices() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (ble, _rx) = service(&dir);
+        ble.record_bond("AA:BB:CC:DD:EE:FF".to_string(), Some("Keyfob".to_string()), None).unwrap();
+        let now = Utc::now();
+        assert!(ble.missing_devices(now).is_empty());
+
+        let later = now + chrono::Duration::days(8);
+        assert!(matches!(&ble.missing_devices(later)[..], [Event::BleDeviceMissing { .. }]));
+        // Reported once until it shows up again
+        assert!(ble.missing_devices(later).is_empty());
+
+        ble.device_seen("aa:bb:cc:dd:ee:ff", Some(-70), Some(55));
+        assert_eq!(ble.bonded_devices()[0].battery, Some(55));
+        assert!(ble.missing_devices(now + chrono::Duration::days(6)).is_empty());
+        assert_eq!(ble.missing_devices(later).len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_runtime_switch() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (ble, _rx) = service(&dir);
+        ble.set_enabled(false);
+        tokio::spawn(ble.clone().run());
+        tokio::task::yield_now().await;
+        assert!(ble.healthy());
+
+        ble.open_pairing(None);
+        ble.set_enabled(true);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let status = ble.service_status();
+        assert!(status.enabled);
+        #[cfg(not(feature = "ble"))]
+        {
+            assert!(!status.running);
+            assert!(status.error.unwrap().contains("ble"));
+            assert!(!ble.healthy());
+        }
+
+        // Switching off also ends the pairing window
+        ble.set_enabled(false);
+        assert!(ble.pairing_remaining().is_none());
+        assert!(ble.healthy());
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (ble, _rx) = service(&dir);
+        ble.state.write().door_open = true;
+        let status: Value = serde_json::from_slice(&ble.status()).unwrap();
+        assert_eq!(status["state"], "disarmed");
+        assert_eq!(status["door"], "open");
+    }
+
+    #[tokio::test]
+    async fn test_pairing_flow() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (ble, mut rx) = service(&dir);
+
+        // Nothing bonds while the window is closed
+        assert!(ble.request_confirmation("AA:BB:CC:DD:EE:FF".to_string(), 123456).await.is_err());
+
+        ble.open_pairing(None);
+        assert!(matches!(rx.recv().await.unwrap(), Event::BlePairingWindow { open: true, expires_in_s: Some(120) }));
+        let pairing: Value = serde_json::from_slice(&ble.pairing()).unwrap();
+        assert_eq!(pairing["open"], true);
+
+        let request = tokio::spawn({
+            let ble = ble.clone();
+            async move { ble.request_confirmation("AA:BB:CC:DD:EE:FF".to_string(), 123456).await }
+        });
+        assert!(matches!(rx.recv().await.unwrap(), Event::BlePairingRequested { passkey: 123456, .. }));
+        assert_eq!(ble.pending_passkey(), Some(("AA:BB:CC:DD:EE:FF".to_string(), 123456)));
+        assert!(ble.confirm_passkey(654321, true).is_err());
+        ble.confirm_passkey(123456, true).unwrap();
+        request.await.unwrap().unwrap();
+
+        ble.record_bond("AA:BB:CC:DD:EE:FF".to_string(), Some("Phone".to_string()), None).unwrap();
+        assert!(matches!(rx.recv().await.unwrap(), Event::BleDevicePaired { .. }));
+        assert!(matches!(rx.recv().await.unwrap(), Event::BlePairingWindow { open: false, .. }));
+        assert!(ble.pairing_remaining().is_none());
+
+        // The bond survives a restart
+        let (restarted, _rx) = service(&dir);
+        assert_eq!(restarted.bonded_devices()[0].name.as_deref(), Some("Phone"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pairing_window_closes_itself() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (ble, mut rx) = service(&dir);
+        ble.open_pairing(Some(Duration::from_secs(30)));
+        rx.recv().await.unwrap();
+
+        tokio::time::sleep(Duration::from_secs(31)).await;
+        assert!(matches!(rx.recv().await.unwrap(), Event::BlePairingWindow { open: false, .. }));
+    }
+}
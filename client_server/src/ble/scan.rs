@@ -0,0 +1,61 @@
+//! Scanning for presence beacons and bonded devices through BlueZ
+
+use super::beacons::{eddystone_id, ibeacon_id, publish, APPLE_COMPANY_ID, EDDYSTONE_UUID};
+use super::{BleService, PresenceTracker};
+use anyhow::{bail, Context, Result};
+use bluer::{Adapter, AdapterEvent, Address};
+use futures::{pin_mut, StreamExt};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// How often beacons are checked for having gone away
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Feed advertisements to the presence tracker and the bonded device sightings until discovery stops
+pub async fn run(ble: BleService, adapter: Adapter) -> Result<()> {
+    let config = ble.presence().clone();
+    let mut tracker = PresenceTracker::new(&config);
+    // Property changes come back as DeviceAdded, so every new advertisement is seen
+    let discovery = adapter
+        .discover_devices_with_changes()
+        .await
+        .context("Failed to start BLE discovery")?;
+    pin_mut!(discovery);
+    let mut expiry = tokio::time::interval(EXPIRY_INTERVAL);
+    info!(beacons = config.beacons.len(), "BLE scan started");
+
+    loop {
+        let events = tokio::select! {
+            event = discovery.next() => match event {
+                Some(AdapterEvent::DeviceAdded(address)) => {
+                    let rssi = adapter.device(address)?.rssi().await.ok().flatten();
+                    ble.device_seen(&address.to_string(), rssi, None);
+                    let mut events = Vec::new();
+                    for (id, rssi) in sightings(&adapter, address).await.unwrap_or_default() {
+                        debug!(%address, %id, ?rssi, "Beacon seen");
+                        events.extend(tracker.seen(&id, rssi, Instant::now()));
+                    }
+                    events
+                }
+                Some(_) => continue,
+                None => bail!("BLE discovery stopped"),
+            },
+            _ = expiry.tick() => tracker.expire(Instant::now()),
+        };
+        publish(events, config.auto_arm, &ble.state, &ble.event_bus)?;
+    }
+}
+
+/// Beacon ids a device currently advertises, with its signal strength
+async fn sightings(adapter: &Adapter, address: Address) -> Result<Vec<(String, Option<i16>)>> {
+    let device = adapter.device(address)?;
+    let rssi = device.rssi().await?;
+    let mut ids = Vec::new();
+    if let Some(data) = device.manufacturer_data().await?.and_then(|data| data.get(&APPLE_COMPANY_ID).cloned()) {
+        ids.extend(ibeacon_id(&data));
+    }
+    if let Some(data) = device.service_data().await?.and_then(|data| data.get(&EDDYSTONE_UUID).cloned()) {
+        ids.extend(eddystone_id(&data));
+    }
+    Ok(ids.into_iter().map(|id| (id, rssi)).collect())
+}
@@ -1,115 +1,606 @@
 //! Cloud WebSocket client with TLS 1.3
+//!
+//! Messages follow the versioned schema in [`super::protocol`]. Endpoints in
+//! `cloud.url` are tried in order; while on a fallback the first one is probed
+//! every `cloud.primary_probe_s` and the link moves back once it answers.
+//! With a queue attached, every forwarded envelope is written to disk first
+//! and only removed once the cloud acknowledges it with an `ack` for the
+//! envelope id, so events raised during an outage are delivered, in order,
+//! after the next reconnect.
 
-use crate::events::{EventBus, EventEnvelope};
-use anyhow::{Context, Result};
-use futures::{SinkExt, StreamExt};
-use serde::{Deserialize, Serialize};
+use super::protocol::{Capability, CloudMessage, Session};
+use super::{LinkMetrics, MasterToken, QueueManager, ReconnectManager, RestFallback};
+use crate::access::{Lockdown, LIFT_LOCKDOWN, LOCKDOWN};
+use crate::actuators::OutputRegistry;
+use crate::config::{CloudConfig, ConfigStore, MeteredConfig, ProxyConfig, ValidationErrors};
+use crate::events::{
+    command_to_event, correlated, correlated_sync, Event, EventBus, EventEnvelope, EventSource, Severity,
+};
+use crate::health::{Component, HealthStatus};
+use crate::network::connect_via;
+use crate::observability::Metrics;
+use crate::security::{decode_hex, AuditEntry, AuditLog, AuditSource, CommandVerifier, ReplayGuard, ROTATE_COMMAND_KEY};
+use crate::state::{AppState, CloudStatus};
+use crate::update::{self, UpdateRequest, UpdateStage, Updater};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use futures::{Sink, SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::time::{interval, sleep};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use tokio::time::{sleep, sleep_until, timeout, Instant};
 use tokio_tungstenite::{
-    connect_async_tls_with_config,
-    tungstenite::{client::IntoClientRequest, protocol::Message},
+    client_async_tls_with_config, Connector, MaybeTlsStream, WebSocketStream,
+    tungstenite::{self, client::IntoClientRequest, protocol::Message},
 };
 use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+type CloudStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
-#[derive(Serialize, Deserialize)]
-struct CloudMessage {
-    #[serde(rename = "type")]
-    msg_type: String,
-    #[serde(flatten)]
-    data: serde_json::Value,
+/// Command asking the agent to fetch and apply its configuration from the master
+const CONFIG_PULL: &str = "config_pull";
+/// Command asking the agent to update itself, see [`crate::update`]
+const UPDATE: &str = "update";
+
+/// Why a cloud session ended without an error
+#[derive(Debug, PartialEq)]
+enum LinkEnd {
+    Closed,
+    /// The preferred endpoint is reachable again
+    Failback,
+    /// The uplink moved to another interface
+    Rebind,
 }
 
+#[derive(Clone)]
 pub struct CloudClient {
-    url: String,
+    /// Endpoints in order of preference
+    urls: Vec<String>,
+    primary_probe: Duration,
     heartbeat_interval: Duration,
+    ack_timeout: Duration,
+    clock_skew_threshold: Duration,
+    backoff_min_s: u64,
+    backoff_max_s: u64,
+    min_severity: Severity,
+    lte_min_severity: Severity,
     event_bus: EventBus,
+    state: AppState,
+    queue: Option<Arc<QueueManager>>,
+    connector: Option<Connector>,
+    token: MasterToken,
+    rest: Option<RestFallback>,
+    proxy: Option<ProxyConfig>,
+    metered: MeteredConfig,
+    bind_interface: bool,
+    config: Option<ConfigStore>,
+    updater: Option<Updater>,
+    command_verifier: Option<CommandVerifier>,
+    audit: Option<Arc<AuditLog>>,
+    replay: Option<ReplayGuard>,
+    lockdown: Option<Lockdown>,
+    outputs: Option<OutputRegistry>,
+    metrics: Option<Metrics>,
+    /// Set once a run starts queueing events; a restarted run leaves that task to it
+    queueing: Arc<AtomicBool>,
 }
 
 impl CloudClient {
-    pub fn new(url: String, heartbeat_s: u64, event_bus: EventBus) -> Self {
+    pub fn new(urls: Vec<String>, config: &CloudConfig, event_bus: EventBus, state: AppState) -> Self {
         Self {
-            url,
-            heartbeat_interval: Duration::from_secs(heartbeat_s),
+            urls,
+            primary_probe: Duration::from_secs(config.primary_probe_s),
+            heartbeat_interval: Duration::from_secs(config.heartbeat_s),
+            ack_timeout: Duration::from_secs(config.ack_timeout_s),
+            clock_skew_threshold: Duration::from_secs(config.clock_skew_threshold_s),
+            backoff_min_s: config.backoff_min_s,
+            backoff_max_s: config.backoff_max_s,
+            min_severity: config.min_severity,
+            lte_min_severity: config.lte_min_severity,
             event_bus,
+            state,
+            queue: None,
+            connector: None,
+            token: MasterToken::default(),
+            rest: None,
+            proxy: config.proxy.clone(),
+            metered: config.metered.clone(),
+            bind_interface: config.bind_interface,
+            config: None,
+            updater: None,
+            command_verifier: None,
+            audit: None,
+            replay: None,
+            lockdown: None,
+            outputs: None,
+            metrics: None,
+            queueing: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Keep events on disk until the cloud acknowledges them; without a queue
+    /// only events raised while connected are sent
+    pub fn set_queue(&mut self, queue: QueueManager) {
+        self.queue = Some(Arc::new(queue));
+    }
+
+    /// TLS connector to use instead of the system default, e.g. one enforcing SPKI pins
+    pub fn set_connector(&mut self, connector: Connector) {
+        self.connector = Some(connector);
+    }
+
+    /// Token from registration, sent as a bearer token when connecting; shared
+    /// with the HTTPS fallback so a rotated one is used on the next connection
+    pub fn set_token(&mut self, token: MasterToken) {
+        self.token = token;
+    }
+
+    /// Deliver queued events over HTTPS while the WebSocket cannot be established
+    pub fn set_rest_fallback(&mut self, rest: RestFallback) {
+        self.rest = Some(rest);
+    }
+
+    /// Accept `config_pull` commands, applying the master's document to `store`;
+    /// needs the REST fallback to reach the master
+    pub fn set_config_store(&mut self, store: ConfigStore) {
+        self.config = Some(store);
+    }
+
+    /// Accept `update` commands, staging signed updates and restarting into them
+    pub fn set_updater(&mut self, updater: Updater) {
+        self.updater = Some(updater);
+    }
+
+    /// Only act on commands signed with the master's command key, and accept
+    /// `rotate_command_key`
+    pub fn set_command_verifier(&mut self, verifier: CommandVerifier) {
+        self.command_verifier = Some(verifier);
+    }
+
+    /// Record every command and its result in the audit log
+    pub fn set_audit(&mut self, audit: Arc<AuditLog>) {
+        self.audit = Some(audit);
+    }
+
+    /// Refuse expired commands and command ids seen before
+    pub fn set_replay_guard(&mut self, replay: ReplayGuard) {
+        self.replay = Some(replay);
+    }
+
+    /// Accept `lockdown` and `lift_lockdown` commands
+    pub fn set_lockdown(&mut self, lockdown: Lockdown) {
+        self.lockdown = Some(lockdown);
+    }
+
+    /// Reject `output` commands for anything but these outputs
+    pub fn set_outputs(&mut self, outputs: OutputRegistry) {
+        self.outputs = Some(outputs);
+    }
+
+    /// Summarize these metrics in heartbeats to a cloud that offers `metrics`
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
+    }
+
     pub async fn run(&self) -> Result<()> {
-        loop {
-            match self.connect_and_run().await {
-                Ok(_) => {
-                    info!("Cloud connection closed normally");
-                    break;
+        // Queue events from now on, connected or not
+        if let Some(queue) = self.queue.as_ref().filter(|_| !self.queueing.swap(true, Ordering::SeqCst)) {
+            let mut event_rx = self.event_bus.subscribe();
+            let client = self.clone();
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                loop {
+                    match event_rx.recv().await {
+                        Ok(envelope) => {
+                            if client.should_forward(&envelope) {
+                                match queue.enqueue(envelope).await {
+                                    Ok(()) => {
+                                        if !client.state.read().health.is_ok(Component::Queue) {
+                                            client.state.write().health.report_ok(Component::Queue);
+                                        }
+                                    }
+                                    Err(e) => {
+                                        error!(error = %e, "Failed to queue event for the cloud");
+                                        client.state.write().health.report_error(
+                                            Component::Queue,
+                                            HealthStatus::Failed,
+                                            format!("{:#}", e),
+                                        );
+                                    }
+                                }
+                                client.record_queue_depth(&queue).await;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            client.event_bus.note_lagged("cloud_queue", skipped);
+                            warn!(skipped, "Cloud queue fell behind the event bus, events lost");
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
                 }
+            });
+        }
+
+        let mut reconnect = ReconnectManager::new(self.backoff_min_s, self.backoff_max_s);
+        let mut endpoint = 0;
+        loop {
+            self.report_status(CloudStatus::Connecting, reconnect.attempt());
+            let started = Instant::now();
+            let primary = (endpoint > 0).then(|| self.urls[0].as_str());
+            let end = self.connect_and_run(&self.urls[endpoint], primary).await;
+            match &end {
+                Ok(LinkEnd::Closed) => info!("Cloud connection closed"),
+                Ok(LinkEnd::Failback) => info!(url = %self.urls[0], "Primary cloud endpoint is back, switching over"),
+                Ok(LinkEnd::Rebind) => info!("Uplink moved to another interface, reconnecting over it"),
                 Err(e) => {
                     error!(error = %e, "Cloud connection error");
-                    // Exponential backoff handled by reconnect logic
-                    sleep(Duration::from_secs(5)).await;
+                    self.state.write().health.report_error(Component::Cloud, HealthStatus::Degraded, format!("{:#}", e));
+                }
+            }
+            reconnect.connection_ended(started.elapsed());
+            let was_online = self.state.read().connectivity.cloud == CloudStatus::Online;
+            self.report_status(CloudStatus::Offline, reconnect.attempt());
+            if was_online {
+                self.record(|link| link.disconnects += 1);
+                self.emit(Event::CloudDisconnected);
+            } else {
+                self.record(|link| link.connect_failures += 1);
+            }
+
+            if matches!(end, Ok(LinkEnd::Failback)) {
+                endpoint = 0;
+                continue;
+            }
+            if matches!(end, Ok(LinkEnd::Rebind)) {
+                continue;
+            }
+            // An endpoint that cannot be reached hands over to the next one
+            if !was_online {
+                endpoint = (endpoint + 1) % self.urls.len();
+            }
+
+            match (&self.rest, &self.queue) {
+                // The WebSocket never came up: keep delivering over HTTPS until the next attempt
+                (Some(rest), Some(queue)) if !was_online => {
+                    tokio::select! {
+                        _ = reconnect.backoff() => {}
+                        _ = self.rest_fallback(rest, queue) => {}
+                    }
+                }
+                _ => reconnect.backoff().await,
+            }
+        }
+    }
+
+    /// Post heartbeats and newly queued events to the master's REST endpoints
+    async fn rest_fallback(&self, rest: &RestFallback, queue: &QueueManager) {
+        let mut next_heartbeat = Instant::now();
+        loop {
+            tokio::select! {
+                _ = sleep_until(next_heartbeat) => {
+                    next_heartbeat = Instant::now() + self.heartbeat_period();
+                    let uptime_ms = self.state.read().uptime_s() * 1000;
+                    match rest.post_heartbeat(uptime_ms).await {
+                        Ok(Some(server_time)) => self.check_clock(server_time),
+                        Ok(None) => {}
+                        Err(e) => debug!(error = %e, "HTTPS heartbeat failed"),
+                    }
                 }
+                _ = queue.wait_for_events() => {}
+            }
+            if let Err(e) = rest.drain(queue).await {
+                debug!(error = %e, "HTTPS event delivery failed, events stay queued");
+            }
+        }
+    }
+
+    /// Compare the local clock with the master's and warn once it drifts too far
+    fn check_clock(&self, server_time: DateTime<Utc>) {
+        let skew_ms = (Utc::now() - server_time).num_milliseconds();
+        let threshold_ms = self.clock_skew_threshold.as_millis() as i64;
+        let newly_skewed = self.state.write().set_clock_skew(skew_ms, threshold_ms);
+        debug!(skew_ms, "Checked clock against the master");
+        if newly_skewed {
+            self.emit(Event::ClockSkewDetected { skew_ms });
+        }
+    }
+
+    /// Record the link status and let local subscribers follow reconnect attempts
+    fn report_status(&self, status: CloudStatus, attempt: u32) {
+        self.state.write().set_cloud_status(status);
+        self.emit(Event::CloudStatusChanged { status, attempt });
+    }
+
+    fn record(&self, update: impl FnOnce(&mut LinkMetrics)) {
+        update(&mut self.state.write().cloud_link);
+    }
+
+    async fn record_queue_depth(&self, queue: &QueueManager) {
+        match queue.size().await {
+            Ok(depth) => self.record(|link| link.queue_depth = depth as u64),
+            Err(e) => debug!(error = %e, "Failed to read the offline queue size"),
+        }
+    }
+
+    /// Send a frame, counting it or the failure in the link metrics
+    async fn send<S>(&self, write: &mut S, frame: Message) -> Result<()>
+    where
+        S: Sink<Message, Error = tungstenite::Error> + Unpin,
+    {
+        match write.send(frame).await {
+            Ok(()) => {
+                self.record(|link| link.messages_sent += 1);
+                Ok(())
+            }
+            Err(e) => {
+                self.record(|link| link.send_errors += 1);
+                Err(e.into())
             }
         }
-        Ok(())
     }
 
-    async fn connect_and_run(&self) -> Result<()> {
-        info!(url = %self.url, "Connecting to cloud");
+    fn emit(&self, event: Event) {
+        if let Err(e) = self.event_bus.emit(event) {
+            debug!(error = %e, "Failed to report cloud status");
+        }
+    }
 
-        // Create request without additional authentication headers
-        let request = self.url.clone().into_client_request()?;
+    /// Interface the WebSocket is tied to, if binding is on and one is selected
+    fn uplink(&self) -> Option<String> {
+        if !self.bind_interface {
+            return None;
+        }
+        self.state.read().connectivity.interface.clone()
+    }
+
+    /// Open the WebSocket to one endpoint over `interface` and complete the handshake
+    async fn connect(&self, url: &str, interface: Option<&str>) -> Result<(CloudStream, Session)> {
+        let mut request = url.into_client_request()?;
+        if let Some(token) = self.token.get() {
+            request
+                .headers_mut()
+                .insert("Authorization", format!("Bearer {}", token).parse()?);
+        }
 
         // Connect with TLS
-        let (ws_stream, _) = connect_async_tls_with_config(request, None, false, None)
+        let uri = request.uri();
+        let host = uri.host().context("Cloud URL has no host")?;
+        let host = host.trim_start_matches('[').trim_end_matches(']').to_string();
+        let port = uri
+            .port_u16()
+            .unwrap_or(if uri.scheme_str() == Some("wss") { 443 } else { 80 });
+        let stream = match &self.proxy {
+            Some(proxy) => super::proxy::connect(proxy, interface, &host, port).await?,
+            None => connect_via(interface, (host.as_str(), port))
+                .await
+                .with_context(|| format!("Failed to reach {}:{}", host, port))?,
+        };
+        let (mut ws_stream, _) = client_async_tls_with_config(request, stream, None, self.connector.clone())
             .await
             .context("Failed to connect to cloud")?;
 
-        info!("Connected to cloud successfully");
+        // Agree on a protocol version before anything else is sent
+        self.send(&mut ws_stream, CloudMessage::hello().to_frame()?).await?;
+        let welcome = timeout(self.ack_timeout, async {
+            loop {
+                match ws_stream.next().await {
+                    Some(Ok(Message::Binary(bytes))) => {
+                        self.record(|link| link.messages_received += 1);
+                        return CloudMessage::decode(&bytes);
+                    }
+                    Some(Ok(Message::Close(_))) | None => return Err(anyhow!("Cloud closed the connection during handshake")),
+                    Some(Err(e)) => return Err(e.into()),
+                    Some(Ok(_)) => {}
+                }
+            }
+        })
+        .await
+        .context("Timed out waiting for the cloud handshake")??;
+        Ok((ws_stream, Session::negotiate(welcome)?))
+    }
+
+    /// Check every `primary_probe` whether the preferred endpoint answers again
+    async fn probe_primary(&self, primary: &str) {
+        loop {
+            sleep(self.primary_probe).await;
+            match timeout(self.ack_timeout, self.connect(primary, self.uplink().as_deref())).await {
+                Ok(Ok((mut probe, _))) => {
+                    probe.close(None).await.ok();
+                    return;
+                }
+                Ok(Err(e)) => debug!(error = %e, "Primary cloud endpoint still unreachable"),
+                Err(_) => debug!("Primary cloud endpoint did not answer in time"),
+            }
+        }
+    }
 
+    /// Run one session; with `primary` set this is a fallback endpoint, left
+    /// as soon as the primary answers again
+    async fn connect_and_run(&self, url: &str, primary: Option<&str>) -> Result<LinkEnd> {
+        let interface = self.uplink();
+        info!(%url, ?interface, "Connecting to cloud");
+        let (ws_stream, session) = self.connect(url, interface.as_deref()).await?;
+        if let Some(server_time) = session.server_time {
+            self.check_clock(server_time);
+        }
         let (mut write, mut read) = ws_stream.split();
 
+        info!(version = session.version, capabilities = ?session.capabilities, "Connected to cloud successfully");
+        self.state.write().set_cloud_status(CloudStatus::Online);
+        self.record(|link| link.connects += 1);
+        self.emit(Event::CloudConnected);
+
         // Subscribe to local events
         let mut event_rx = self.event_bus.subscribe();
 
-        // Heartbeat timer
-        let mut heartbeat = interval(self.heartbeat_interval);
+        // First heartbeat right away, then at an interval that follows the uplink
+        let mut next_heartbeat = Instant::now();
+        // Payload and send time of the ping waiting for its pong
+        let mut ping_count: u64 = 0;
+        let mut pending_ping: Option<(u64, Instant)> = None;
+
+        // Queued events sent and waiting for their ack, and when to give up on them
+        let mut in_flight: HashMap<Uuid, EventEnvelope> = HashMap::new();
+        let mut ack_deadline = Instant::now();
+
+        // On a metered uplink routine events wait for the next flush, unless
+        // something more urgent is queued with them
+        let mut next_flush = Instant::now();
+        let mut holding = false;
+
+        let failback = async {
+            match primary {
+                Some(primary) => self.probe_primary(primary).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(failback);
 
         loop {
+            if let Some(queue) = &self.queue {
+                if in_flight.is_empty() {
+                    let mut batch = queue.next_batch().await?;
+                    holding = self.metered()
+                        && Instant::now() < next_flush
+                        && !batch.is_empty()
+                        && batch.iter().all(|envelope| envelope.severity < Severity::Warn);
+                    if holding {
+                        batch.clear();
+                    } else if !batch.is_empty() {
+                        next_flush = Instant::now() + Duration::from_secs(self.metered.batch_s);
+                    }
+                    for envelope in batch {
+                        let frame = CloudMessage::Event { envelope: envelope.clone() }.to_frame()?;
+                        if let Err(e) = self.send(&mut write, frame).await {
+                            error!(error = %e, "Failed to send queued event to cloud");
+                            return Err(e);
+                        }
+                        self.record(|link| link.events_forwarded += 1);
+                        // A cloud that does not ack events gets them at most once
+                        if session.supports(Capability::EventAck) {
+                            in_flight.insert(envelope.id, envelope);
+                        } else {
+                            queue.acknowledge(&envelope).await?;
+                            self.record_queue_depth(queue).await;
+                        }
+                    }
+                    if !in_flight.is_empty() {
+                        debug!(count = in_flight.len(), "Sent queued events, awaiting ack");
+                        ack_deadline = Instant::now() + self.ack_timeout;
+                    }
+                }
+            }
+
             tokio::select! {
                 // Send heartbeat ping
-                _ = heartbeat.tick() => {
+                _ = sleep_until(next_heartbeat) => {
+                    next_heartbeat = Instant::now() + self.heartbeat_period();
                     debug!("Sending cloud heartbeat");
-                    if let Err(e) = write.send(Message::Ping(vec![])).await {
+                    ping_count += 1;
+                    if let Err(e) = write.send(Message::Ping(ping_count.to_be_bytes().to_vec())).await {
                         error!(error = %e, "Failed to send ping");
+                        self.record(|link| link.send_errors += 1);
                         return Err(e.into());
                     }
+                    pending_ping = Some((ping_count, Instant::now()));
+                    self.record(|link| link.pings_sent += 1);
+
+                    let frame = self.heartbeat_message(&session).to_frame()?;
+                    if let Err(e) = self.send(&mut write, frame).await {
+                        error!(error = %e, "Failed to send heartbeat");
+                        return Err(e);
+                    }
                 }
 
-                // Forward local events to cloud
-                Ok(envelope) = event_rx.recv() => {
-                    let msg = self.envelope_to_message(&envelope);
-                    let json = serde_json::to_string(&msg)?;
+                // More events were queued
+                _ = async {
+                    if let Some(queue) = &self.queue {
+                        queue.wait_for_events().await
+                    }
+                }, if self.queue.is_some() && in_flight.is_empty() => {}
+
+                // Time to send the routine events held back on a metered uplink
+                _ = sleep_until(next_flush), if holding => {}
+
+                // Unacknowledged events are resent on the primary
+                _ = &mut failback => {
+                    write.close().await.ok();
+                    return Ok(LinkEnd::Failback);
+                }
 
-                    if let Err(e) = write.send(Message::Text(json)).await {
+                // The cloud never acknowledged the batch; it is resent after reconnecting
+                _ = sleep_until(ack_deadline), if !in_flight.is_empty() => {
+                    warn!(count = in_flight.len(), "Cloud did not acknowledge queued events");
+                    return Err(anyhow!("Timed out waiting for event acknowledgments"));
+                }
+
+                // Follow the uplink to its new interface; without a queue, forward live events as they happen
+                Ok(envelope) = event_rx.recv() => {
+                    if let Event::NetworkInterfaceChanged { interface: Some(moved), .. } = &envelope.event {
+                        if self.bind_interface && interface.as_ref() != Some(moved) {
+                            write.close().await.ok();
+                            return Ok(LinkEnd::Rebind);
+                        }
+                    }
+                    if self.queue.is_some() || !self.should_forward(&envelope) {
+                        continue;
+                    }
+                    let frame = CloudMessage::Event { envelope }.to_frame()?;
+                    if let Err(e) = self.send(&mut write, frame).await {
                         error!(error = %e, "Failed to send event to cloud");
-                        return Err(e.into());
+                        return Err(e);
                     }
+                    self.record(|link| link.events_forwarded += 1);
                 }
 
                 // Receive messages from cloud
                 msg = read.next() => {
                     match msg {
-                        Some(Ok(Message::Text(text))) => {
-                            debug!(text, "Received message from cloud");
-                            if let Err(e) = self.handle_cloud_message(&text) {
-                                warn!(error = %e, "Failed to handle cloud message");
+                        Some(Ok(Message::Binary(bytes))) => {
+                            let msg = match CloudMessage::decode(&bytes) {
+                                Ok(msg) => msg,
+                                Err(e) => {
+                                    warn!(error = %e, "Failed to decode cloud message");
+                                    self.record(|link| link.decode_errors += 1);
+                                    continue;
+                                }
+                            };
+                            self.record(|link| link.messages_received += 1);
+                            debug!(?msg, "Received message from cloud");
+                            if let CloudMessage::Ack { id } = msg {
+                                if let (Some(envelope), Some(queue)) = (in_flight.remove(&id), &self.queue) {
+                                    queue.acknowledge(&envelope).await?;
+                                    self.record_queue_depth(queue).await;
+                                }
+                                continue;
+                            }
+                            if let Some(result) = self.handle_cloud_message(msg) {
+                                if let Err(e) = self.send(&mut write, result.to_frame()?).await {
+                                    error!(error = %e, "Failed to send command result");
+                                    return Err(e);
+                                }
                             }
                         }
+                        Some(Ok(Message::Text(_))) => {
+                            warn!("Ignoring text frame, the cloud protocol is binary CBOR");
+                            self.record(|link| link.decode_errors += 1);
+                        }
                         Some(Ok(Message::Close(_))) => {
                             info!("Cloud connection closed by server");
-                            return Ok(());
+                            return Ok(LinkEnd::Closed);
                         }
-                        Some(Ok(Message::Pong(_))) => {
+                        Some(Ok(Message::Pong(payload))) => {
                             debug!("Received pong from cloud");
+                            if let Some((count, sent)) = pending_ping {
+                                if payload == count.to_be_bytes() {
+                                    pending_ping = None;
+                                    self.record(|link| link.record_rtt(sent.elapsed()));
+                                }
+                            }
                         }
                         Some(Err(e)) => {
                             error!(error = %e, "WebSocket error");
@@ -117,7 +608,7 @@ impl CloudClient {
                         }
                         None => {
                             warn!("Cloud connection stream ended");
-                            return Ok(());
+                            return Ok(LinkEnd::Closed);
                         }
                         _ => {}
                     }
@@ -126,31 +617,224 @@ impl CloudClient {
         }
     }
 
-    fn envelope_to_message(&self, envelope: &EventEnvelope) -> CloudMessage {
-        CloudMessage {
-            msg_type: "event".to_string(),
-            data: serde_json::to_value(envelope).unwrap_or(serde_json::Value::Null),
+    /// Whether to save data: metered mode is on and the uplink is LTE
+    fn metered(&self) -> bool {
+        self.metered.enabled && self.state.read().connectivity.on_lte()
+    }
+
+    fn heartbeat_period(&self) -> Duration {
+        if self.metered() {
+            Duration::from_secs(self.metered.heartbeat_s)
+        } else {
+            self.heartbeat_interval
+        }
+    }
+
+    /// Whether an event is worth sending, given the link it would go over
+    fn should_forward(&self, envelope: &EventEnvelope) -> bool {
+        let min = if self.state.read().connectivity.on_lte() {
+            self.lte_min_severity
+        } else {
+            self.min_severity
+        };
+        envelope.severity >= min
+    }
+
+    /// Heartbeat carrying uptime, the uplink interface and radio signal, and power readings and link metrics if the cloud wants them
+    fn heartbeat_message(&self, session: &Session) -> CloudMessage {
+        let state = self.state.read();
+        CloudMessage::Heartbeat {
+            uptime_ms: state.uptime_s() * 1000,
+            power: session
+                .supports(Capability::PowerTelemetry)
+                .then(|| state.power.clone()),
+            link: session
+                .supports(Capability::LinkMetrics)
+                .then(|| state.cloud_link.clone()),
+            interface: state.connectivity.interface.clone(),
+            radio: state.link_quality.clone(),
+            metrics: self
+                .metrics
+                .as_ref()
+                .filter(|_| session.supports(Capability::Metrics))
+                .map(|metrics| metrics.summary(&state)),
+        }
+    }
+
+    /// Fetch the master's configuration document, apply it and report the outcome
+    ///
+    /// The command carries the document's SHA-256 as `sha256` (hex), so the
+    /// signature on the command also covers the document.
+    async fn pull_config(&self, rest: &RestFallback, store: &ConfigStore, args: &serde_json::Value, cmd_id: &str) {
+        let result = async {
+            let digest = args
+                .get("sha256")
+                .and_then(|digest| digest.as_str())
+                .context("config_pull needs the document's sha256")?;
+            let digest = decode_hex(digest).context("Invalid sha256 in config_pull")?;
+            let document = rest.fetch_config(&digest).await?;
+            store.run_blocking(move |store| store.apply(&document)).await
+        }
+        .await;
+        let (event, error, errors) = match result {
+            Ok(change) => (
+                Event::ConfigApplied {
+                    restart_required: change.restart_required(),
+                    timers: change.timers(),
+                    changed: change.changed,
+                },
+                None,
+                Vec::new(),
+            ),
+            Err(e) => {
+                let error = format!("{:#}", e);
+                let errors = ValidationErrors::of(&e);
+                (Event::ConfigRejected { error: error.clone(), errors: errors.clone() }, Some(error), errors)
+            }
+        };
+        if let Err(e) = self.event_bus.emit(event) {
+            warn!(error = %e, "Failed to report configuration outcome");
+        }
+        self.audit_command(cmd_id, CONFIG_PULL, error.as_ref().map_or(Ok(()), Err));
+        rest.ack_config(cmd_id, error, &errors).await;
+    }
+
+    /// Stage the requested update, report it and restart into it
+    async fn run_update(&self, updater: &Updater, args: serde_json::Value, cmd_id: &str) {
+        let request = match serde_json::from_value::<UpdateRequest>(args) {
+            Ok(request) => request,
+            Err(e) => {
+                let error = format!("Invalid update command: {}", e);
+                warn!(%error, "Update rejected");
+                self.audit_command(cmd_id, UPDATE, Err(&error));
+                if let Some(rest) = &self.rest {
+                    rest.ack_command(cmd_id, false, Some(error)).await;
+                }
+                return;
+            }
+        };
+        let result = updater.stage(&request).await;
+        let error = result.as_ref().err().map(|e| format!("{:#}", e));
+        let stage = if result.is_ok() { UpdateStage::Staged } else { UpdateStage::Failed };
+        if let Err(e) = self.event_bus.emit(Event::AgentUpdate {
+            version: request.version.clone(),
+            stage,
+            error: error.clone(),
+        }) {
+            warn!(error = %e, "Failed to report update progress");
+        }
+        self.audit_command(cmd_id, UPDATE, error.as_ref().map_or(Ok(()), Err));
+        if let Some(rest) = &self.rest {
+            rest.ack_command(cmd_id, error.is_none(), error).await;
+        }
+        if result.is_ok() {
+            info!(version = %request.version, "Restarting to install update");
+            if let Err(e) = update::restart() {
+                error!(error = %e, "Update staged but the agent could not restart");
+            }
+        }
+    }
+
+    /// Record a command in the audit log, in the command's correlation scope
+    fn audit_command<E: std::fmt::Display>(&self, id: &str, name: &str, result: Result<(), E>) {
+        if let Some(audit) = &self.audit {
+            let source = AuditSource::Cloud { command_id: id.to_string() };
+            correlated_sync(Some(id.to_string()), || audit.record(AuditEntry::new(name, source, &result)));
         }
     }
 
-    fn handle_cloud_message(&self, text: &str) -> Result<()> {
-        let msg: CloudMessage = serde_json::from_str(text)?;
+    /// Close a command on the master, and the `command_result` answering it
+    fn command_result(&self, id: String, result: Result<()>) -> CloudMessage {
+        let ok = result.is_ok();
+        let error = result.err().map(|e| e.to_string());
+
+        // Close the command on the master too, retrying in the background
+        if let Some(rest) = self.rest.clone() {
+            let (id, error) = (id.clone(), error.clone());
+            tokio::spawn(async move { rest.ack_command(&id, ok, error).await });
+        }
+
+        CloudMessage::CommandResult {
+            correlation_id: id.clone(),
+            id,
+            ok,
+            error,
+        }
+    }
 
-        match msg.msg_type.as_str() {
-            "cmd" => {
-                debug!("Received command from cloud");
-                // Parse and emit command events
-                // TODO: Implement command handling
+    /// Act on a message from the cloud, returning the result to send back for commands
+    fn handle_cloud_message(&self, msg: CloudMessage) -> Option<CloudMessage> {
+        match msg {
+            CloudMessage::Command { id, name, args, signature, expires_at } => {
+                // The master's command id doubles as the correlation ID and the nonce
+                let correlation_id = id.clone();
+                if let Some(verifier) = &self.command_verifier {
+                    if let Err(e) = verifier.verify(&id, &name, &args, expires_at, signature.as_deref()) {
+                        error!(command = %name, %correlation_id, error = %e, "Refusing unverified cloud command");
+                        self.audit_command(&id, &name, Err(&e));
+                        return Some(self.command_result(id, Err(e)));
+                    }
+                }
+                // Checked once the signature holds, so forged frames cannot burn an id
+                if let Some(replay) = &self.replay {
+                    let checked = correlated_sync(Some(correlation_id.clone()), || {
+                        replay.check(EventSource::Cloud, &id, &name, &args, Some(&id), expires_at)
+                    });
+                    if let Err(e) = checked {
+                        self.audit_command(&id, &name, Err(&e));
+                        return Some(self.command_result(id, Err(e)));
+                    }
+                }
+                if name == CONFIG_PULL {
+                    if let (Some(rest), Some(store)) = (self.rest.clone(), self.config.clone()) {
+                        // Reported to the master once the document is applied or rejected
+                        let client = self.clone();
+                        tokio::spawn(correlated(Some(correlation_id), async move {
+                            client.pull_config(&rest, &store, &args, &id).await
+                        }));
+                        return None;
+                    }
+                }
+                if name == UPDATE {
+                    if let Some(updater) = self.updater.clone() {
+                        let client = self.clone();
+                        tokio::spawn(correlated(Some(correlation_id), async move {
+                            client.run_update(&updater, args, &id).await
+                        }));
+                        return None;
+                    }
+                }
+                let result = correlated_sync(Some(correlation_id.clone()), || {
+                    if let (ROTATE_COMMAND_KEY, Some(verifier)) = (name.as_str(), &self.command_verifier) {
+                        return verifier.rotate(&args);
+                    }
+                    match (name.as_str(), &self.lockdown) {
+                        (LOCKDOWN, Some(lockdown)) => return lockdown.engage(&args),
+                        (LIFT_LOCKDOWN, Some(lockdown)) => return lockdown.lift(),
+                        _ => {}
+                    }
+                    let event = command_to_event(&name, &args, EventSource::Cloud)?;
+                    if let Some(outputs) = &self.outputs {
+                        outputs.check_command(&event)?;
+                    }
+                    self.event_bus.emit(event)
+                });
+                match &result {
+                    Ok(()) => info!(command = %name, %correlation_id, "Cloud command executed"),
+                    Err(e) => warn!(command = %name, %correlation_id, error = %e, "Cloud command failed"),
+                }
+                self.audit_command(&id, &name, result.as_ref().map(|_| ()));
+                Some(self.command_result(id, result))
             }
-            "ack" => {
+            CloudMessage::Ack { .. } => {
                 debug!("Received acknowledgment from cloud");
+                None
             }
-            _ => {
-                warn!(msg_type = %msg.msg_type, "Unknown message type from cloud");
+            other => {
+                warn!(msg = ?other, "Unexpected message from cloud");
+                None
             }
         }
-
-        Ok(())
     }
 }
 
@@ -159,14 +843,420 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_envelope_to_message() {
+    fn test_heartbeat_includes_power() {
+        let (bus, _) = EventBus::new();
+        let state = crate::state::new_app_state();
+        state.write().set_analog("supply", 13.6);
+        state.write().set_interface(Some("wlan0".to_string()));
+        let config = crate::config::AppConfig::test_default().cloud;
+        let client = CloudClient::new(vec!["wss://example.com/client".to_string()], &config, bus, state);
+
+        let mut session = Session {
+            version: crate::cloud::protocol::PROTOCOL_VERSION,
+            capabilities: vec![Capability::PowerTelemetry],
+            server_time: None,
+        };
+        match client.heartbeat_message(&session) {
+            CloudMessage::Heartbeat { power: Some(power), interface, .. } => {
+                assert_eq!(power.analog["supply"], 13.6);
+                assert!(!power.on_battery);
+                assert_eq!(interface.as_deref(), Some("wlan0"));
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+
+        // Left out when the cloud did not ask for power telemetry
+        session.capabilities.clear();
+        assert!(matches!(
+            client.heartbeat_message(&session),
+            CloudMessage::Heartbeat { power: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_clock_skew_reported_once() {
+        let (bus, mut rx) = EventBus::new();
+        let state = crate::state::new_app_state();
+        let config = crate::config::AppConfig::test_default().cloud;
+        let client = CloudClient::new(vec!["wss://example.com/client".to_string()], &config, bus, state.clone());
+
+        client.check_clock(Utc::now());
+        assert!(!state.read().connectivity.clock_skewed);
+        assert!(rx.try_recv_queued().is_err());
+
+        // The master is two minutes behind, so this clock runs ahead
+        for _ in 0..2 {
+            client.check_clock(Utc::now() - chrono::Duration::minutes(2));
+        }
+        assert!(state.read().connectivity.clock_skewed);
+        match rx.try_recv_queued().unwrap().event {
+            Event::ClockSkewDetected { skew_ms } => assert!(skew_ms >= 120_000),
+            other => panic!("Unexpected event: {:?}", other),
+        }
+        assert!(rx.try_recv_queued().is_err());
+    }
+
+    #[test]
+    fn test_metered_link_slows_heartbeat() {
         let (bus, _) = EventBus::new();
-        let client = CloudClient::new("wss://example.com/client".to_string(), 20, bus);
+        let state = crate::state::new_app_state();
+        let mut config = crate::config::AppConfig::test_default().cloud;
+        let client = CloudClient::new(vec!["wss://example.com/client".to_string()], &config, bus.clone(), state.clone());
+
+        assert_eq!(client.heartbeat_period(), Duration::from_secs(config.heartbeat_s));
+        state.write().set_interface(Some("wwan0".to_string()));
+        assert!(client.metered());
+        assert_eq!(client.heartbeat_period(), Duration::from_secs(config.metered.heartbeat_s));
+
+        config.metered.enabled = false;
+        let client = CloudClient::new(vec!["wss://example.com/client".to_string()], &config, bus, state);
+        assert_eq!(client.heartbeat_period(), Duration::from_secs(config.heartbeat_s));
+    }
+
+    #[test]
+    fn test_lte_forwards_only_warnings() {
+        let (bus, _) = EventBus::new();
+        let state = crate::state::new_app_state();
+        let config = crate::config::AppConfig::test_default().cloud;
+        let client = CloudClient::new(vec!["wss://example.com/client".to_string()], &config, bus, state.clone());
+        let door = EventEnvelope::new(crate::events::Event::DoorOpen, "test".to_string());
+        let power = EventEnvelope::new(crate::events::Event::PowerLost { voltage: 12.6 }, "test".to_string());
+
+        assert!(client.should_forward(&door));
+        state.write().connectivity.interface = Some("wwan0".to_string());
+        assert!(!client.should_forward(&door));
+        assert!(client.should_forward(&power));
+    }
+
+    #[tokio::test]
+    async fn test_queued_events_removed_only_when_acked() {
+        use crate::events::{Event, EventQueue};
+        use tokio_tungstenite::accept_async;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = QueueManager::new(EventQueue::new(dir.path(), 100, 7).unwrap(), 2);
+        for event in [Event::DoorOpen, Event::DoorClose, Event::GlassBreak] {
+            queue.enqueue(EventEnvelope::new(event, "test".to_string())).await.unwrap();
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (bus, _rx) = EventBus::new();
+        let config = crate::config::AppConfig::test_default().cloud;
+        let state = crate::state::new_app_state();
+        let mut client = CloudClient::new(vec![url.clone()], &config, bus, state.clone());
+        client.set_queue(queue);
+        let queue = client.queue.clone().unwrap();
+        let connection = tokio::spawn(async move { client.connect_and_run(&url, None).await });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut cloud = accept_async(stream).await.unwrap();
+        async fn next_message<S>(cloud: &mut S) -> CloudMessage
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            loop {
+                if let Message::Binary(bytes) = cloud.next().await.unwrap().unwrap() {
+                    return CloudMessage::decode(&bytes).unwrap();
+                }
+            }
+        }
+        async fn next_event<S>(cloud: &mut S) -> EventEnvelope
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        {
+            loop {
+                if let CloudMessage::Event { envelope } = next_message(cloud).await {
+                    return envelope;
+                }
+            }
+        }
+
+        // Nothing is sent before the handshake completes
+        assert!(matches!(next_message(&mut cloud).await, CloudMessage::Hello { .. }));
+        let welcome = CloudMessage::Welcome {
+            version: crate::cloud::protocol::PROTOCOL_VERSION,
+            capabilities: vec![Capability::EventAck],
+            server_time: None,
+        };
+        cloud.send(welcome.to_frame().unwrap()).await.unwrap();
+
+        // The first batch arrives oldest first and the next waits for its acks
+        let first = next_event(&mut cloud).await;
+        let second = next_event(&mut cloud).await;
+        assert_eq!(state.read().connectivity.cloud, CloudStatus::Online);
+        assert!(state.read().connectivity.cloud_last_connected.is_some());
+        assert!(matches!(first.event, Event::DoorOpen));
+        assert!(matches!(second.event, Event::DoorClose));
+        for event in [&first, &second] {
+            let ack = CloudMessage::Ack { id: event.id };
+            cloud.send(ack.to_frame().unwrap()).await.unwrap();
+        }
+        assert!(matches!(next_event(&mut cloud).await.event, Event::GlassBreak));
+
+        // Drop the link before acking the last one; it stays queued for the next connection
+        cloud.close(None).await.unwrap();
+        drop(cloud);
+        connection.await.unwrap().ok();
+        let remaining = queue.next_batch().await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        let link = state.read().cloud_link.clone();
+        assert_eq!(link.connects, 1);
+        // hello, at least one heartbeat and the three events
+        assert!(link.messages_sent >= 5, "{link:?}");
+        // welcome and two acks
+        assert_eq!(link.messages_received, 3);
+        assert!(matches!(remaining[0].event, Event::GlassBreak));
+    }
+
+    /// Accept one connection and answer the client's hello
+    async fn serve_handshake(listener: &tokio::net::TcpListener) -> WebSocketStream<TcpStream> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut cloud = tokio_tungstenite::accept_async(stream).await.unwrap();
+        loop {
+            if let Message::Binary(bytes) = cloud.next().await.unwrap().unwrap() {
+                assert!(matches!(CloudMessage::decode(&bytes).unwrap(), CloudMessage::Hello { .. }));
+                break;
+            }
+        }
+        let welcome = CloudMessage::Welcome {
+            version: crate::cloud::protocol::PROTOCOL_VERSION,
+            capabilities: vec![],
+            server_time: None,
+        };
+        cloud.send(welcome.to_frame().unwrap()).await.unwrap();
+        cloud
+    }
+
+    #[tokio::test]
+    async fn test_fallback_returns_to_primary() {
+        let primary = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let fallback = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let primary_url = format!("ws://{}", primary.local_addr().unwrap());
+        let fallback_url = format!("ws://{}", fallback.local_addr().unwrap());
+        let (bus, _rx) = EventBus::new();
+        let config = crate::config::AppConfig::test_default().cloud;
+        let mut client = CloudClient::new(
+            vec![primary_url.clone(), fallback_url.clone()],
+            &config,
+            bus,
+            crate::state::new_app_state(),
+        );
+        client.primary_probe = Duration::from_millis(50);
+        let connection = tokio::spawn(async move { client.connect_and_run(&fallback_url, Some(&primary_url)).await });
+
+        // The fallback session stays up until the primary answers a probe
+        let _fallback = serve_handshake(&fallback).await;
+        let _probe = serve_handshake(&primary).await;
+        let end = timeout(Duration::from_secs(5), connection).await.unwrap().unwrap();
+        assert_eq!(end.unwrap(), LinkEnd::Failback);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_uplink_change_reconnects() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (bus, _rx) = EventBus::new();
+        let state = crate::state::new_app_state();
+        state.write().set_interface(Some("lo".to_string()));
+        let client = CloudClient::new(vec![url.clone()], &crate::config::AppConfig::test_default().cloud, bus.clone(), state);
+        let connection = tokio::spawn(async move { client.connect_and_run(&url, None).await });
+        let (_cloud, _) = tokio::join!(serve_handshake(&listener), async {
+            // Let the session subscribe before the uplink moves
+            sleep(Duration::from_millis(200)).await;
+        });
+
+        // Staying on the bound interface keeps the session
+        let changed = |interface: &str| Event::NetworkInterfaceChanged {
+            previous: None,
+            interface: Some(interface.to_string()),
+        };
+        bus.broadcast(EventEnvelope::new(changed("lo"), "c-1".to_string())).unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert!(!connection.is_finished());
+
+        bus.broadcast(EventEnvelope::new(changed("wwan0"), "c-1".to_string())).unwrap();
+        let end = timeout(Duration::from_secs(5), connection).await.unwrap().unwrap();
+        assert_eq!(end.unwrap(), LinkEnd::Rebind);
+    }
+
+    #[tokio::test]
+    async fn test_config_pull_applies_and_acks() {
+        use axum::{extract::Path, routing::{get, post}, Json, Router};
+        use sha2::{Digest, Sha256};
+        use std::sync::Mutex;
+
+        let acks = Arc::new(Mutex::new(Vec::new()));
+        let log = acks.clone();
+        const INVALID: &str = r#"{"timers": {"exit_delay_s": 0}}"#;
+        const DOCUMENT: &str = r#"{"timers": {"exit_delay_s": 45}}"#;
+        let served = Arc::new(Mutex::new(INVALID));
+        let document = served.clone();
+        let master = Router::new()
+            .route("/clients/:client_id/config", get(move || async move { *document.lock().unwrap() }))
+            .route(
+                "/clients/:client_id/commands/:cmd_id/ack",
+                post(move |Path((_, cmd_id)): Path<(String, String)>, Json(body): Json<serde_json::Value>| async move {
+                    log.lock().unwrap().push((cmd_id, body));
+                    axum::http::StatusCode::OK
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, master).await.unwrap() });
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        let config = crate::config::AppConfig::test_default();
+        let (bus, mut rx) = EventBus::new();
+        let mut client = CloudClient::new(vec![], &config.cloud, bus, crate::state::new_app_state());
+        client.set_rest_fallback(RestFallback::new(&format!("http://{}", addr), "c-1", MasterToken::default(), None).unwrap());
+        let store = ConfigStore::new(config, &path);
+        client.set_config_store(store.clone());
+
+        let pull = |id: &str, document: &str| CloudMessage::Command {
+            id: id.to_string(),
+            name: CONFIG_PULL.to_string(),
+            args: serde_json::json!({"sha256": crate::security::encode_hex(&Sha256::digest(document))}),
+            signature: None,
+            expires_at: None,
+        };
+
+        // A document other than the one the command names is refused
+        assert!(client.handle_cloud_message(pull("cfg-0", r#"{"timers": {"exit_delay_s": 1}}"#)).is_none());
+        let queued = timeout(Duration::from_secs(5), rx.recv_queued()).await.unwrap().unwrap();
+        assert!(matches!(queued.event, Event::ConfigRejected { .. }), "{:?}", queued.event);
+        assert!(!path.exists());
+
+        // One that fails validation is refused with each setting at fault
+        assert!(client.handle_cloud_message(pull("cfg-1", INVALID)).is_none());
+        let queued = timeout(Duration::from_secs(5), rx.recv_queued()).await.unwrap().unwrap();
+        match queued.event {
+            Event::ConfigRejected { errors, .. } => assert_eq!(errors[0].field, "timers.exit_delay_s"),
+            other => panic!("Unexpected event {other:?}"),
+        }
+
+        *served.lock().unwrap() = DOCUMENT;
+        assert!(client.handle_cloud_message(pull("cfg-2", DOCUMENT)).is_none());
+        let queued = timeout(Duration::from_secs(5), rx.recv_queued()).await.unwrap().unwrap();
+        assert_eq!(queued.correlation_id.as_deref(), Some("cfg-2"));
+        match queued.event {
+            Event::ConfigApplied { changed, restart_required, timers } => {
+                assert_eq!(changed, vec!["timers"]);
+                assert!(!restart_required);
+                assert_eq!(timers.unwrap().exit_delay_s, 45);
+            }
+            other => panic!("Unexpected event {other:?}"),
+        }
+        assert_eq!(store.current().timers.exit_delay_s, 45);
+        assert!(path.exists());
+
+        for _ in 0..50 {
+            if acks.lock().unwrap().len() >= 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let mut acks = acks.lock().unwrap();
+        acks.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(acks[0].0, "cfg-0");
+        assert_eq!(acks[0].1["success"], false);
+        assert!(acks[0].1.get("errors").is_none());
+        assert_eq!(acks[1].0, "cfg-1");
+        assert_eq!(acks[1].1["errors"][0]["field"], "timers.exit_delay_s");
+        assert_eq!(acks[2].0, "cfg-2");
+        assert_eq!(acks[2].1["success"], true);
+    }
+
+    #[test]
+    fn test_cloud_command_emits_event() {
+        let (bus, mut rx) = EventBus::new();
+        let client = CloudClient::new(
+            vec!["wss://example.com/client".to_string()],
+            &crate::config::AppConfig::test_default().cloud,
+            bus,
+            crate::state::new_app_state(),
+        );
+
+        let command = CloudMessage::Command {
+            id: "c123".to_string(),
+            name: "output".to_string(),
+            args: serde_json::json!({"output": "gate", "on": true}),
+            signature: None,
+            expires_at: None,
+        };
+        match client.handle_cloud_message(command) {
+            Some(CloudMessage::CommandResult { ok, correlation_id, .. }) => {
+                assert!(ok);
+                assert_eq!(correlation_id, "c123");
+            }
+            other => panic!("Unexpected reply: {:?}", other),
+        }
+
+        let queued = rx.try_recv_queued().unwrap();
+        assert_eq!(queued.correlation_id.as_deref(), Some("c123"));
+        match queued.event {
+            crate::events::Event::OutputControl { name, on, .. } => {
+                assert_eq!(name, "gate");
+                assert!(on);
+            }
+            other => panic!("Unexpected event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unverified_command_refused() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut config = crate::config::AppConfig::test_default();
+        config.system.data_dir = dir.path().to_path_buf();
+        let disarm = |signature: Option<&str>| CloudMessage::Command {
+            id: "c124".to_string(),
+            name: "disarm".to_string(),
+            args: serde_json::Value::Null,
+            signature: signature.map(str::to_string),
+            expires_at: None,
+        };
+
+        // Without a key and without requiring signatures, an unsigned disarm is still refused
+        for (required, signature) in [(false, None), (true, Some("c2lnbmVk"))] {
+            config.cloud.require_signed_commands = required;
+            let (bus, mut rx) = EventBus::new();
+            let mut client = CloudClient::new(vec![], &config.cloud, bus, crate::state::new_app_state());
+            client.set_command_verifier(CommandVerifier::open(&config).unwrap());
+            match client.handle_cloud_message(disarm(signature)) {
+                Some(CloudMessage::CommandResult { ok, error, .. }) => {
+                    assert!(!ok);
+                    assert!(error.unwrap().contains("command key"));
+                }
+                other => panic!("Unexpected reply: {:?}", other),
+            }
+            assert!(rx.try_recv_queued().is_err());
+        }
+    }
+
+    #[test]
+    fn test_replayed_command_refused() {
+        let config = crate::config::AppConfig::test_default();
+        let (bus, mut rx) = EventBus::new();
+        let mut client = CloudClient::new(vec![], &config.cloud, bus.clone(), crate::state::new_app_state());
+        client.set_replay_guard(ReplayGuard::new(&config.replay, bus));
 
-        let envelope =
-            EventEnvelope::new(crate::events::Event::DoorOpen, "test-client".to_string());
+        let siren_off = |expires_at| CloudMessage::Command {
+            id: "c125".to_string(),
+            name: "siren".to_string(),
+            args: serde_json::json!({"on": false}),
+            signature: None,
+            expires_at,
+        };
+        let ok = |reply| matches!(reply, Some(CloudMessage::CommandResult { ok: true, .. }));
+        assert!(ok(client.handle_cloud_message(siren_off(Some(Utc::now().timestamp() + 60)))));
+        assert!(matches!(rx.try_recv_queued().unwrap().event, crate::events::Event::SirenControl { .. }));
 
-        let msg = client.envelope_to_message(&envelope);
-        assert_eq!(msg.msg_type, "event");
+        assert!(!ok(client.handle_cloud_message(siren_off(Some(Utc::now().timestamp() + 60)))));
+        let refused = rx.try_recv_queued().unwrap();
+        assert_eq!(refused.correlation_id.as_deref(), Some("c125"));
+        assert!(matches!(refused.event, crate::events::Event::CommandReplayRejected { .. }));
+        assert!(rx.try_recv_queued().is_err());
     }
 }
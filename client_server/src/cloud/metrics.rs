@@ -0,0 +1,94 @@
+//! Latency and reliability figures for the cloud WebSocket
+//!
+//! Kept in the shared state so `/v1/health` can show them, and sent along
+//! with heartbeats when the cloud asks for them.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Weight of the newest sample in the RTT moving average
+const RTT_SMOOTHING: f64 = 0.2;
+
+/// Counters since the agent started, and the recent ping round trip
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LinkMetrics {
+    /// Round trip of the last answered ping
+    pub rtt_ms: Option<u64>,
+    /// Exponential moving average of the round trip
+    pub rtt_avg_ms: Option<f64>,
+    pub rtt_max_ms: Option<u64>,
+    pub pings_sent: u64,
+    pub pongs_received: u64,
+    /// Connections that completed the handshake
+    pub connects: u64,
+    /// Attempts that never got that far
+    pub connect_failures: u64,
+    /// Established connections that were lost
+    pub disconnects: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub send_errors: u64,
+    /// Frames from the cloud that were not a valid message
+    pub decode_errors: u64,
+    /// Events sent to the cloud, live or from the queue
+    #[serde(default)]
+    pub events_forwarded: u64,
+    /// Events waiting in the offline queue, as of the last change
+    #[serde(default)]
+    pub queue_depth: u64,
+}
+
+impl LinkMetrics {
+    pub fn record_rtt(&mut self, rtt: Duration) {
+        let rtt_ms = rtt.as_millis() as u64;
+        self.pongs_received += 1;
+        self.rtt_ms = Some(rtt_ms);
+        self.rtt_max_ms = self.rtt_max_ms.max(Some(rtt_ms));
+        self.rtt_avg_ms = Some(match self.rtt_avg_ms {
+            Some(avg) => avg + RTT_SMOOTHING * (rtt_ms as f64 - avg),
+            None => rtt_ms as f64,
+        });
+    }
+
+    /// Connections made after the first
+    pub fn reconnects(&self) -> u64 {
+        self.connects.saturating_sub(1)
+    }
+
+    /// Share of sent and received messages that failed, 0.0 to 1.0
+    pub fn error_rate(&self) -> f64 {
+        let errors = self.send_errors + self.decode_errors;
+        let total = self.messages_sent + self.messages_received + errors;
+        if total == 0 {
+            0.0
+        } else {
+            errors as f64 / total as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtt_and_error_rate() {
+        let mut metrics = LinkMetrics::default();
+        assert_eq!(metrics.error_rate(), 0.0);
+
+        metrics.record_rtt(Duration::from_millis(100));
+        metrics.record_rtt(Duration::from_millis(600));
+        metrics.record_rtt(Duration::from_millis(100));
+        assert_eq!(metrics.rtt_ms, Some(100));
+        assert_eq!(metrics.rtt_max_ms, Some(600));
+        assert_eq!(metrics.pongs_received, 3);
+        let avg = metrics.rtt_avg_ms.unwrap();
+        assert!((avg - 180.0).abs() < 0.01, "{avg}");
+
+        metrics.messages_sent = 7;
+        metrics.messages_received = 1;
+        metrics.send_errors = 1;
+        metrics.decode_errors = 1;
+        assert!((metrics.error_rate() - 0.2).abs() < f64::EPSILON);
+    }
+}
@@ -3,7 +3,21 @@
 mod client;
 mod reconnect;
 mod queue_manager;
+mod metrics;
+mod pinning;
+mod protocol;
+mod provision;
+mod proxy;
+mod report;
+mod rest;
 
 pub use client::CloudClient;
 pub use reconnect::ReconnectManager;
 pub use queue_manager::QueueManager;
+pub use metrics::LinkMetrics;
+pub use pinning::{pinned_connector, pinned_tls_config, spki_hash, SpkiPinVerifier};
+pub use protocol::{Capability, CloudMessage, Session, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+pub use provision::{provision, stored_registration, Registration};
+pub use proxy::{http_client, pinned_http_client};
+pub use report::{AddressReporter, NetworkReport};
+pub use rest::{MasterToken, RestFallback};
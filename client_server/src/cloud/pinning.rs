@@ -0,0 +1,290 @@
+//! SPKI pinning for the cloud TLS connection
+//!
+//! The server chain is validated against the Mozilla roots as usual, then at
+//! least one certificate on a validated path from the leaf to a root (leaf,
+//! intermediate or root) must carry a public key whose SHA-256 is listed in
+//! `cloud.spki_pins`. Certificates the server sent that are not on that path
+//! do not count. Listing the next key next to the current one lets the
+//! server rotate certificates without locking clients out.
+
+use crate::events::{Event, EventBus};
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::cell::Cell;
+use std::fmt;
+use std::sync::Arc;
+use tokio_tungstenite::Connector;
+use tracing::{error, info};
+
+/// Certificate verifier that also requires a pinned public key in the chain
+pub struct SpkiPinVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    roots: Arc<RootCertStore>,
+    /// In order of preference; anything after the first is a rotation backup
+    pins: Vec<[u8; 32]>,
+    event_bus: EventBus,
+}
+
+impl SpkiPinVerifier {
+    /// Verifier trusting the bundled Mozilla roots
+    pub fn new(pins: &[String], event_bus: EventBus) -> Result<Self> {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        Self::with_roots(roots, pins, event_bus)
+    }
+
+    pub fn with_roots(roots: RootCertStore, pins: &[String], event_bus: EventBus) -> Result<Self> {
+        if pins.is_empty() {
+            bail!("No SPKI pins configured");
+        }
+        let pins = pins.iter().map(|pin| parse_pin(pin)).collect::<Result<_>>()?;
+        let roots = Arc::new(roots);
+        let inner = WebPkiServerVerifier::builder_with_provider(roots.clone(), provider())
+            .build()
+            .context("Failed to build certificate verifier")?;
+        Ok(Self { inner, roots, pins, event_bus })
+    }
+
+    /// Best pin found on a path from the leaf to a trusted root, if any path carries one
+    fn pinned_path(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Option<usize> {
+        let cert = webpki::EndEntityCert::try_from(end_entity).ok()?;
+        let matched = Cell::new(None);
+        // Path building tries the next candidate path when this one carries no pin
+        let check = |path: &webpki::VerifiedPath<'_>| {
+            let hashes = std::iter::once(hash_spki(&path.end_entity().subject_public_key_info()))
+                .chain(path.intermediate_certificates().map(|cert| hash_spki(&cert.subject_public_key_info())))
+                .chain(std::iter::once(hash_spki(&sequence(&path.anchor().subject_public_key_info))));
+            let index = hashes.filter_map(|hash| self.pins.iter().position(|pin| *pin == hash)).min();
+            matched.set(index);
+            index.map(|_| ()).ok_or(webpki::Error::UnknownIssuer)
+        };
+        cert.verify_for_usage(
+            provider().signature_verification_algorithms.all,
+            &self.roots.roots,
+            intermediates,
+            now,
+            webpki::KeyUsage::server_auth(),
+            None,
+            Some(&check),
+        )
+        .ok()?;
+        matched.get()
+    }
+}
+
+impl fmt::Debug for SpkiPinVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpkiPinVerifier").field("pins", &self.pins.len()).finish()
+    }
+}
+
+impl ServerCertVerifier for SpkiPinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+
+        match self.pinned_path(end_entity, intermediates, now) {
+            Some(0) => Ok(ServerCertVerified::assertion()),
+            Some(index) => {
+                info!(pin = index, "Cloud certificate matched a backup SPKI pin, the server has rotated keys");
+                Ok(ServerCertVerified::assertion())
+            }
+            None => {
+                let host = server_name.to_str().into_owned();
+                error!(%host, "Cloud certificate matches no SPKI pin, refusing connection");
+                if let Err(e) = self.event_bus.emit(Event::CertificatePinMismatch { host }) {
+                    error!(error = %e, "Failed to report SPKI pin mismatch");
+                }
+                Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure))
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// TLS 1.3 client configuration that enforces the pins
+pub fn pinned_tls_config(pins: &[String], event_bus: EventBus) -> Result<ClientConfig> {
+    let verifier = SpkiPinVerifier::new(pins, event_bus)?;
+    Ok(ClientConfig::builder_with_provider(provider())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .context("Failed to configure TLS")?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
+/// TLS 1.3 connector for the cloud WebSocket that enforces the pins
+pub fn pinned_connector(pins: &[String], event_bus: EventBus) -> Result<Connector> {
+    Ok(Connector::Rustls(Arc::new(pinned_tls_config(pins, event_bus)?)))
+}
+
+/// SHA-256 of a certificate's DER SubjectPublicKeyInfo, as used in pins
+pub fn spki_hash(cert: &CertificateDer<'_>) -> Result<[u8; 32]> {
+    let cert = webpki::EndEntityCert::try_from(cert).context("Failed to parse certificate")?;
+    Ok(hash_spki(&cert.subject_public_key_info()))
+}
+
+fn hash_spki(spki: &[u8]) -> [u8; 32] {
+    Sha256::digest(spki).into()
+}
+
+/// DER SEQUENCE around `contents`; trust anchors keep their SPKI unwrapped
+fn sequence(contents: &[u8]) -> Vec<u8> {
+    let len = contents.len().to_be_bytes();
+    let significant = &len[len.iter().take_while(|byte| **byte == 0).count()..];
+    let mut der = vec![0x30];
+    if contents.len() < 0x80 {
+        der.push(contents.len() as u8);
+    } else {
+        der.push(0x80 | significant.len() as u8);
+        der.extend_from_slice(significant);
+    }
+    der.extend_from_slice(contents);
+    der
+}
+
+/// Parse a pin given as base64, optionally prefixed with `sha256/`
+fn parse_pin(pin: &str) -> Result<[u8; 32]> {
+    let encoded = pin.trim().strip_prefix("sha256/").unwrap_or(pin.trim());
+    let bytes = STANDARD
+        .decode(encoded)
+        .with_context(|| format!("SPKI pin is not base64: {pin}"))?;
+    bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("SPKI pin is not a SHA-256 hash: {pin}"))
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+    /// A CA and a `localhost` leaf it signed
+    fn chain() -> (CertificateDer<'static>, CertificateDer<'static>) {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![]).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key).unwrap();
+        let leaf_key = KeyPair::generate().unwrap();
+        let leaf = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&leaf_key, &ca, &ca_key)
+            .unwrap();
+        (ca.der().clone(), leaf.der().clone())
+    }
+
+    fn pin(cert: &CertificateDer<'_>) -> String {
+        format!("sha256/{}", STANDARD.encode(spki_hash(cert).unwrap()))
+    }
+
+    fn verify(
+        verifier: &SpkiPinVerifier,
+        (ca, leaf): &(CertificateDer<'static>, CertificateDer<'static>),
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let name = ServerName::try_from("localhost").unwrap();
+        verifier.verify_server_cert(leaf, std::slice::from_ref(ca), &name, &[], UnixTime::now())
+    }
+
+    #[test]
+    fn test_pins_checked_after_chain() {
+        let chain = chain();
+        let (ca, leaf) = &chain;
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.clone()).unwrap();
+        let (bus, mut rx) = EventBus::new();
+        let stale = format!("sha256/{}", STANDARD.encode([7u8; 32]));
+
+        // The intermediate's key as a backup pin is enough while the leaf key rotates
+        let verifier = SpkiPinVerifier::with_roots(roots.clone(), &[stale.clone(), pin(ca)], bus.clone()).unwrap();
+        assert!(verify(&verifier, &chain).is_ok());
+        let verifier = SpkiPinVerifier::with_roots(roots.clone(), &[pin(leaf)], bus.clone()).unwrap();
+        assert!(verify(&verifier, &chain).is_ok());
+        assert!(rx.try_recv().is_err());
+
+        // A valid chain with no pinned key is refused and reported
+        let verifier = SpkiPinVerifier::with_roots(roots, &[stale], bus).unwrap();
+        assert!(verify(&verifier, &chain).is_err());
+        match rx.try_recv().unwrap() {
+            Event::CertificatePinMismatch { host } => assert_eq!(host, "localhost"),
+            other => panic!("Unexpected event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pins_only_count_on_the_verified_path() {
+        let (pinned_ca, _) = chain();
+        let chain = chain();
+        let (other_ca, leaf) = &chain;
+        let mut roots = RootCertStore::empty();
+        roots.add(pinned_ca.clone()).unwrap();
+        roots.add(other_ca.clone()).unwrap();
+        let (bus, _rx) = EventBus::new();
+        let verifier = SpkiPinVerifier::with_roots(roots, &[pin(&pinned_ca)], bus).unwrap();
+
+        // A leaf from another CA does not pass by also sending the pinned CA
+        let name = ServerName::try_from("localhost").unwrap();
+        let sent = [pinned_ca.clone(), other_ca.clone()];
+        assert!(verifier.verify_server_cert(leaf, &sent, &name, &[], UnixTime::now()).is_err());
+    }
+
+    #[test]
+    fn test_der_sequence() {
+        assert_eq!(sequence(&[1, 2]), [0x30, 2, 1, 2]);
+        let long = sequence(&[0; 300]);
+        assert_eq!(long[..4], [0x30, 0x82, 0x01, 0x2c]);
+        assert_eq!(long.len(), 304);
+    }
+
+    #[test]
+    fn test_parse_pin() {
+        let hash = STANDARD.encode([1u8; 32]);
+        assert_eq!(parse_pin(&hash).unwrap(), [1u8; 32]);
+        assert_eq!(parse_pin(&format!("sha256/{hash}")).unwrap(), [1u8; 32]);
+        assert!(parse_pin("sha256/not base64").is_err());
+        assert!(parse_pin(&STANDARD.encode([1u8; 16])).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Versioned message schema for the cloud WebSocket
+//!
+//! Every frame is a binary WebSocket message holding one CBOR-encoded
+//! [`CloudMessage`]. Right after connecting the client sends `hello` with the
+//! range of protocol versions it speaks and its capabilities; the cloud picks
+//! a version and answers `welcome` with the capabilities it supports. Nothing
+//! else is exchanged before that, and features only one side supports are off
+//! for the session.
+
+use super::LinkMetrics;
+use crate::events::EventEnvelope;
+use crate::network::LinkQuality;
+use crate::observability::MetricsSummary;
+use crate::state::PowerState;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::protocol::Message;
+use uuid::Uuid;
+
+/// Newest protocol version this client speaks
+pub const PROTOCOL_VERSION: u16 = 1;
+/// Oldest protocol version this client still accepts
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Optional protocol features, agreed on during the handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// The cloud acknowledges each event, so queued events wait for `ack`
+    EventAck,
+    /// The cloud sends `command` messages and expects a `command_result`
+    Commands,
+    /// Heartbeats carry ADC readings and backup power status
+    PowerTelemetry,
+    /// Heartbeats carry round-trip times and error counts of the link
+    LinkMetrics,
+    /// Heartbeats carry totals of the agent's metrics
+    Metrics,
+    /// A capability newer than this client
+    #[serde(other)]
+    Unknown,
+}
+
+/// Capabilities this client offers in its `hello`
+pub const CAPABILITIES: &[Capability] = &[
+    Capability::EventAck,
+    Capability::Commands,
+    Capability::PowerTelemetry,
+    Capability::LinkMetrics,
+    Capability::Metrics,
+];
+
+/// Every message either side may send
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CloudMessage {
+    /// Client → cloud, first message on every connection
+    Hello {
+        min_version: u16,
+        max_version: u16,
+        capabilities: Vec<Capability>,
+        /// Client software version, for the master's records
+        agent: String,
+    },
+    /// Cloud → client, answer to `hello`
+    Welcome {
+        version: u16,
+        capabilities: Vec<Capability>,
+        /// The cloud's clock, for checking the client's
+        #[serde(default)]
+        server_time: Option<DateTime<Utc>>,
+    },
+    /// Client → cloud
+    Event { envelope: EventEnvelope },
+    /// Cloud → client, the event with this envelope id is stored
+    Ack { id: Uuid },
+    /// Client → cloud
+    Heartbeat {
+        uptime_ms: i64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        power: Option<PowerState>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        link: Option<LinkMetrics>,
+        /// Interface the uplink currently runs over
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        interface: Option<String>,
+        /// Signal readings of the Wi-Fi and LTE interfaces
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        radio: Vec<LinkQuality>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metrics: Option<MetricsSummary>,
+    },
+    /// Cloud → client; `name` and `args` are as for local WebSocket commands
+    Command {
+        id: String,
+        name: String,
+        #[serde(default)]
+        args: serde_json::Value,
+        /// Base64 Ed25519 signature from the master's command key
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+        /// Unix seconds after which the command is refused; covered by the signature
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<i64>,
+    },
+    /// Client → cloud, outcome of a `command`
+    CommandResult {
+        id: String,
+        ok: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        correlation_id: String,
+    },
+}
+
+impl CloudMessage {
+    /// The `hello` this client opens every connection with
+    pub fn hello() -> Self {
+        Self::Hello {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES.to_vec(),
+            agent: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(self, &mut bytes).context("Failed to encode cloud message")?;
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        ciborium::from_reader(bytes).context("Invalid cloud message")
+    }
+
+    /// Binary WebSocket frame carrying this message
+    pub fn to_frame(&self) -> Result<Message> {
+        Ok(Message::Binary(self.encode()?))
+    }
+}
+
+/// What was agreed on for one connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub version: u16,
+    pub capabilities: Vec<Capability>,
+    pub server_time: Option<DateTime<Utc>>,
+}
+
+impl Session {
+    /// Check the cloud's `welcome` and keep the capabilities both sides support
+    pub fn negotiate(welcome: CloudMessage) -> Result<Self> {
+        let CloudMessage::Welcome {
+            version,
+            capabilities,
+            server_time,
+        } = welcome
+        else {
+            bail!("Expected welcome from cloud, got {:?}", welcome);
+        };
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+            bail!(
+                "Cloud chose protocol v{}, this client speaks v{} to v{}",
+                version,
+                MIN_PROTOCOL_VERSION,
+                PROTOCOL_VERSION
+            );
+        }
+        let capabilities = CAPABILITIES
+            .iter()
+            .copied()
+            .filter(|capability| capabilities.contains(capability))
+            .collect();
+        Ok(Self {
+            version,
+            capabilities,
+            server_time,
+        })
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+
+    #[test]
+    fn test_roundtrip() {
+        let envelope = EventEnvelope::new(Event::DoorOpen, "test".to_string());
+        let id = envelope.id;
+        match CloudMessage::decode(&CloudMessage::Event { envelope }.encode().unwrap()).unwrap() {
+            CloudMessage::Event { envelope } => {
+                assert_eq!(envelope.id, id);
+                assert!(matches!(envelope.event, Event::DoorOpen));
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+        assert!(CloudMessage::decode(br#"{"type":"ack"}"#).is_err());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let welcome = CloudMessage::Welcome {
+            version: PROTOCOL_VERSION,
+            capabilities: vec![Capability::EventAck, Capability::Unknown],
+            server_time: None,
+        };
+        let session = Session::negotiate(CloudMessage::decode(&welcome.encode().unwrap()).unwrap()).unwrap();
+        assert_eq!(session.version, PROTOCOL_VERSION);
+        assert!(session.supports(Capability::EventAck));
+        assert!(!session.supports(Capability::Commands));
+        assert!(!session.supports(Capability::Unknown));
+
+        let future = CloudMessage::Welcome {
+            version: PROTOCOL_VERSION + 1,
+            capabilities: vec![],
+            server_time: None,
+        };
+        assert!(Session::negotiate(future).is_err());
+        assert!(Session::negotiate(CloudMessage::Ack { id: Uuid::new_v4() }).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! First-boot registration with the master
+//!
+//! Started with `--provision-key`, the agent registers itself at the master's
+//! `POST /clients/register`, reporting its interface addresses, service port
+//! and event signing key. The client id and token it gets back are kept in the
+//! secret store, so later starts skip registration and the key is never needed
+//! again, along with the master's command key when it sends one.
+
+use super::http_client;
+use crate::config::AppConfig;
+use crate::network::interface_ipv4;
+use crate::security::{decode_command_key, key_hardware, SecretStore, API_TOKEN, CLIENT_ID, COMMAND_KEYS};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Identity the master assigned to this client
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Registration {
+    pub client_id: String,
+    pub api_token: String,
+}
+
+/// What the master answers a registration with
+#[derive(Debug, Deserialize)]
+struct RegisterResponse {
+    client_id: String,
+    api_token: String,
+    /// Base64 Ed25519 key the master signs commands with
+    #[serde(default)]
+    command_key: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct RegisterRequest<'a> {
+    provision_key: &'a str,
+    eth0_ip: Option<String>,
+    wlan0_ip: Option<String>,
+    service_port: Option<u16>,
+    signing_key: Option<String>,
+}
+
+/// Registration from an earlier start, if there was one
+pub fn stored_registration(secrets: &SecretStore) -> Result<Option<Registration>> {
+    let (Some(client_id), Some(api_token)) = (secrets.get(CLIENT_ID)?, secrets.get(API_TOKEN)?) else {
+        return Ok(None);
+    };
+    Ok(Some(Registration {
+        client_id: String::from_utf8(client_id).context("Stored client id is not UTF-8")?,
+        api_token: String::from_utf8(api_token).context("Stored API token is not UTF-8")?,
+    }))
+}
+
+/// Reuse a stored registration, or register with the provision key when there is none
+pub async fn provision(config: &AppConfig, provision_key: Option<&str>) -> Result<Option<Registration>> {
+    let mut secrets = SecretStore::load(&config.secrets_path())?;
+    if let Some(registration) = stored_registration(&secrets)? {
+        if provision_key.is_some() {
+            info!("Already registered with the master, ignoring --provision-key");
+        }
+        return Ok(Some(registration));
+    }
+    let Some(provision_key) = provision_key else {
+        return Ok(None);
+    };
+    let Some(master_url) = config.system.master_url.as_deref() else {
+        bail!("system.master_url must be set to register with a provision key");
+    };
+
+    let signing_key = if config.signing.enabled {
+        if let Some(hardware) = key_hardware(&config.signing)? {
+            secrets.set_hardware(hardware);
+        }
+        Some(crate::security::encode_hex(&secrets.share_device_key()?))
+    } else {
+        None
+    };
+    let request = RegisterRequest {
+        provision_key,
+        eth0_ip: interface_ipv4("eth0").map(|ip| ip.to_string()),
+        wlan0_ip: interface_ipv4("wlan0").map(|ip| ip.to_string()),
+        service_port: config.http.listen_addr.parse::<SocketAddr>().ok().map(|addr| addr.port()),
+        signing_key,
+    };
+    let http = http_client(config.cloud.proxy.as_ref(), Duration::from_secs(30))?;
+    let response = register(&http, master_url, &request).await?;
+
+    match response.command_key.as_deref() {
+        Some(key) => secrets.set(COMMAND_KEYS, &decode_command_key(key)?)?,
+        None => warn!("Master sent no command key, its commands cannot be verified"),
+    }
+    secrets.set(CLIENT_ID, response.client_id.as_bytes())?;
+    secrets.set(API_TOKEN, response.api_token.as_bytes())?;
+    info!(client_id = %response.client_id, "Registered with the master");
+    Ok(Some(Registration {
+        client_id: response.client_id,
+        api_token: response.api_token,
+    }))
+}
+
+async fn register(http: &reqwest::Client, master_url: &str, request: &RegisterRequest<'_>) -> Result<RegisterResponse> {
+    let url = format!("{}/clients/register", master_url.trim_end_matches('/'));
+    let response = http
+        .post(&url)
+        .json(request)
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach {}", url))?;
+    let status = response.status();
+    if !status.is_success() {
+        bail!("Registration rejected by the master: {}", status);
+    }
+    response.json().await.context("Invalid registration response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Json, Router};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_registers_once_and_keeps_identity() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let master = Router::new().route(
+            "/clients/register",
+            post(move |Json(body): Json<serde_json::Value>| async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                assert_eq!(body["provision_key"], "pk-1");
+                assert_eq!(body["service_port"], 8080);
+                Json(serde_json::json!({"client_id": "c-42", "api_token": "t0k3n", "command_key": STANDARD.encode([7; 32])}))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, master).await.unwrap() });
+
+        let dir = TempDir::new().unwrap();
+        let mut config = AppConfig::test_default();
+        config.system.data_dir = dir.path().to_path_buf();
+        config.system.master_url = Some(format!("http://{}/", addr));
+        config.http.listen_addr = "0.0.0.0:8080".to_string();
+
+        assert_eq!(provision(&config, None).await.unwrap(), None);
+
+        let expected = Registration {
+            client_id: "c-42".to_string(),
+            api_token: "t0k3n".to_string(),
+        };
+        assert_eq!(provision(&config, Some("pk-1")).await.unwrap(), Some(expected.clone()));
+        // The provision key is single use; later starts rely on the stored identity
+        assert_eq!(provision(&config, Some("pk-1")).await.unwrap(), Some(expected.clone()));
+        assert_eq!(provision(&config, None).await.unwrap(), Some(expected));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let secrets = SecretStore::load(&config.secrets_path()).unwrap();
+        assert_eq!(secrets.get(COMMAND_KEYS).unwrap(), Some(vec![7; 32]));
+    }
+}
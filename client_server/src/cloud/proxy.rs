@@ -0,0 +1,215 @@
+//! Proxy support for the connections to the cloud and the master
+//!
+//! HTTP proxies are asked to `CONNECT` to the cloud host and SOCKS5 proxies
+//! get a SOCKS connect; either way the TLS and WebSocket handshakes then run
+//! over the tunnel as if connected directly. Requests to the master's REST
+//! API go through the same proxy. The connection to the proxy leaves through
+//! the selected uplink, like a direct one would.
+
+use crate::config::ProxyConfig;
+use crate::network::connect_via;
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream};
+use tokio_socks::tcp::Socks5Stream;
+use tracing::debug;
+
+/// Longest CONNECT response head accepted from an HTTP proxy
+const MAX_RESPONSE_HEAD: usize = 8192;
+
+enum Scheme {
+    Http,
+    /// `socks5h` leaves name resolution to the proxy
+    Socks5 { remote_dns: bool },
+}
+
+/// Open a TCP tunnel to `host:port` through the proxy, reaching the proxy over `interface`
+pub async fn connect(proxy: &ProxyConfig, interface: Option<&str>, host: &str, port: u16) -> Result<TcpStream> {
+    let (scheme, proxy_addr) = endpoint(proxy)?;
+    debug!(proxy = %proxy_addr, %host, port, "Connecting through proxy");
+    match scheme {
+        Scheme::Http => http_connect(proxy, interface, &proxy_addr, host, port).await,
+        Scheme::Socks5 { remote_dns } => {
+            let username = proxy.username.as_deref();
+            let password = proxy.password.as_deref().unwrap_or("");
+            let stream = if remote_dns {
+                socks5_connect(interface, &proxy_addr, (host, port), username, password).await
+            } else {
+                let target = lookup_host((host, port))
+                    .await?
+                    .next()
+                    .with_context(|| format!("Failed to resolve {}", host))?;
+                socks5_connect(interface, &proxy_addr, target, username, password).await
+            };
+            stream.with_context(|| format!("SOCKS proxy {} refused connection to {}:{}", proxy_addr, host, port))
+        }
+    }
+}
+
+/// HTTP client for the master, going through the proxy when one is configured
+pub fn http_client(proxy: Option<&ProxyConfig>, timeout: Duration) -> Result<reqwest::Client> {
+    client_builder(proxy, timeout)?.build().context("Failed to build HTTP client")
+}
+
+/// HTTP client for the master that verifies it with `tls`, e.g. a configuration enforcing SPKI pins
+pub fn pinned_http_client(
+    proxy: Option<&ProxyConfig>,
+    timeout: Duration,
+    tls: rustls::ClientConfig,
+) -> Result<reqwest::Client> {
+    client_builder(proxy, timeout)?
+        .use_preconfigured_tls(tls)
+        .build()
+        .context("Failed to build HTTP client")
+}
+
+fn client_builder(proxy: Option<&ProxyConfig>, timeout: Duration) -> Result<reqwest::ClientBuilder> {
+    let mut builder = reqwest::Client::builder().timeout(timeout);
+    if let Some(proxy) = proxy {
+        let mut settings = reqwest::Proxy::all(&proxy.url).context("Invalid cloud.proxy.url")?;
+        if let Some(username) = &proxy.username {
+            settings = settings.basic_auth(username, proxy.password.as_deref().unwrap_or(""));
+        }
+        builder = builder.proxy(settings);
+    }
+    Ok(builder)
+}
+
+/// Scheme and `host:port` of the proxy
+fn endpoint(proxy: &ProxyConfig) -> Result<(Scheme, String)> {
+    let url = reqwest::Url::parse(&proxy.url).with_context(|| format!("Invalid proxy URL: {}", proxy.url))?;
+    let scheme = match url.scheme() {
+        "http" => Scheme::Http,
+        "socks5" => Scheme::Socks5 { remote_dns: false },
+        "socks5h" => Scheme::Socks5 { remote_dns: true },
+        other => bail!("Unsupported proxy scheme: {}", other),
+    };
+    let host = url.host_str().context("Proxy URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(1080);
+    Ok((scheme, format!("{}:{}", host, port)))
+}
+
+async fn socks5_connect<'a>(
+    interface: Option<&str>,
+    proxy_addr: &str,
+    target: impl tokio_socks::IntoTargetAddr<'a>,
+    username: Option<&str>,
+    password: &str,
+) -> Result<TcpStream> {
+    let socket = connect_via(interface, proxy_addr)
+        .await
+        .with_context(|| format!("Failed to reach proxy {}", proxy_addr))?;
+    let stream = match username {
+        Some(username) => Socks5Stream::connect_with_password_and_socket(socket, target, username, password).await?,
+        None => Socks5Stream::connect_with_socket(socket, target).await?,
+    };
+    Ok(stream.into_inner())
+}
+
+async fn http_connect(
+    proxy: &ProxyConfig,
+    interface: Option<&str>,
+    proxy_addr: &str,
+    host: &str,
+    port: u16,
+) -> Result<TcpStream> {
+    let mut stream = connect_via(interface, proxy_addr)
+        .await
+        .with_context(|| format!("Failed to reach proxy {}", proxy_addr))?;
+
+    let target = format!("{}:{}", host, port);
+    let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+    if let Some(username) = &proxy.username {
+        let credentials = format!("{}:{}", username, proxy.password.as_deref().unwrap_or(""));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", STANDARD.encode(credentials)));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read only the response head; everything after it belongs to the tunnel
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_RESPONSE_HEAD {
+            bail!("Proxy {} sent an oversized CONNECT response", proxy_addr);
+        }
+        head.push(stream.read_u8().await.context("Proxy closed the connection")?);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let status_line = head.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        bail!("Proxy {} refused CONNECT to {}: {}", proxy_addr, target, status_line);
+    }
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_http_connect_tunnel() {
+        // Echo server standing in for the cloud
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = target.accept().await.unwrap();
+            let (mut read, mut write) = socket.split();
+            tokio::io::copy(&mut read, &mut write).await.unwrap();
+        });
+
+        // Proxy that checks the credentials and splices the two connections
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = proxy.accept().await.unwrap();
+            let mut client = BufReader::new(socket);
+            let mut lines = Vec::new();
+            loop {
+                let mut line = String::new();
+                client.read_line(&mut line).await.unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                lines.push(line);
+            }
+            assert_eq!(lines[0], format!("CONNECT {} HTTP/1.1\r\n", target_addr));
+            let auth = format!("Proxy-Authorization: Basic {}\r\n", STANDARD.encode("alice:s3cret"));
+            assert!(lines.contains(&auth));
+
+            let mut client = client.into_inner();
+            client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await.unwrap();
+            let mut upstream = TcpStream::connect(target_addr).await.unwrap();
+            tokio::io::copy_bidirectional(&mut client, &mut upstream).await.ok();
+        });
+
+        let config = ProxyConfig {
+            url: format!("http://{}", proxy_addr),
+            username: Some("alice".to_string()),
+            password: Some("s3cret".to_string()),
+        };
+        let mut tunnel = connect(&config, None, "127.0.0.1", target_addr.port()).await.unwrap();
+        tunnel.write_all(b"ping").await.unwrap();
+        let mut reply = [0u8; 4];
+        tunnel.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"ping");
+    }
+
+    #[test]
+    fn test_endpoint() {
+        let proxy = |url: &str| ProxyConfig {
+            url: url.to_string(),
+            username: None,
+            password: None,
+        };
+        assert!(matches!(endpoint(&proxy("http://proxy.lan")).unwrap(), (Scheme::Http, addr) if addr == "proxy.lan:80"));
+        assert!(matches!(
+            endpoint(&proxy("socks5h://10.0.0.1")).unwrap(),
+            (Scheme::Socks5 { remote_dns: true }, addr) if addr == "10.0.0.1:1080"
+        ));
+        assert!(endpoint(&proxy("ftp://proxy.lan:21")).is_err());
+    }
+}
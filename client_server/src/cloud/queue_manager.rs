@@ -3,13 +3,14 @@
 use crate::events::{EventEnvelope, EventQueue};
 use anyhow::Result;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
 use tokio::time::{sleep, Duration};
 use tracing::{debug, info, warn};
 
 pub struct QueueManager {
     queue: Arc<Mutex<EventQueue>>,
     batch_size: usize,
+    queued: Notify,
 }
 
 impl QueueManager {
@@ -17,6 +18,7 @@ impl QueueManager {
         Self {
             queue: Arc::new(Mutex::new(queue)),
             batch_size,
+            queued: Notify::new(),
         }
     }
 
@@ -24,9 +26,27 @@ impl QueueManager {
     pub async fn enqueue(&self, envelope: EventEnvelope) -> Result<()> {
         let queue = self.queue.lock().await;
         queue.enqueue(envelope)?;
+        self.queued.notify_one();
         Ok(())
     }
 
+    /// Wait until an event is enqueued
+    pub async fn wait_for_events(&self) {
+        self.queued.notified().await
+    }
+
+    /// Oldest queued events, up to the batch size, left in the queue until acknowledged
+    pub async fn next_batch(&self) -> Result<Vec<EventEnvelope>> {
+        let queue = self.queue.lock().await;
+        queue.dequeue_batch(self.batch_size)
+    }
+
+    /// Remove an event the cloud has acknowledged
+    pub async fn acknowledge(&self, envelope: &EventEnvelope) -> Result<()> {
+        let queue = self.queue.lock().await;
+        queue.remove(std::slice::from_ref(envelope))
+    }
+
     /// Replay queued events (call when connection is established)
     pub async fn replay<F>(&self, mut send_fn: F) -> Result<usize>
     where
@@ -127,4 +147,27 @@ mod tests {
         assert_eq!(sent_count, 5);
         assert_eq!(mgr.size().await.unwrap(), 0);
     }
+
+    #[tokio::test]
+    async fn test_batch_stays_until_acknowledged() {
+        let temp_dir = TempDir::new().unwrap();
+        let queue = EventQueue::new(temp_dir.path(), 100, 7).unwrap();
+        let mgr = QueueManager::new(queue, 2);
+
+        for event in [Event::DoorOpen, Event::DoorClose, Event::GlassBreak] {
+            mgr.enqueue(EventEnvelope::new(event, "test".to_string())).await.unwrap();
+        }
+        // The enqueue left a wakeup behind even though nobody was waiting
+        tokio::time::timeout(Duration::from_secs(1), mgr.wait_for_events()).await.unwrap();
+
+        let batch = mgr.next_batch().await.unwrap();
+        assert_eq!(batch.len(), 2);
+        assert!(matches!(batch[0].event, Event::DoorOpen));
+        assert_eq!(mgr.next_batch().await.unwrap()[0].id, batch[0].id);
+
+        mgr.acknowledge(&batch[0]).await.unwrap();
+        let batch = mgr.next_batch().await.unwrap();
+        assert!(matches!(batch[0].event, Event::DoorClose));
+        assert_eq!(mgr.size().await.unwrap(), 2);
+    }
 }
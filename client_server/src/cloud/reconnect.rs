@@ -1,5 +1,12 @@
-//! Reconnection manager with exponential backoff
+//! Reconnection manager with decorrelated-jitter backoff
+//!
+//! Each wait is drawn between the minimum and three times the previous wait,
+//! capped at the maximum, so a fleet of clients losing the same server does
+//! not come back in lockstep. The backoff only resets after a connection has
+//! stayed up for a while; a server that accepts and then drops connections
+//! straight away is retried at the slow end of the range.
 
+use rand::Rng;
 use std::time::Duration;
 use tokio::time::sleep;
 use tracing::{debug, info};
@@ -8,8 +15,10 @@ pub struct ReconnectManager {
     min_backoff: Duration,
     max_backoff: Duration,
     current_backoff: Duration,
-    #[allow(dead_code)] // Reserved for future stable connection detection
+    /// How long a connection must last before the backoff resets
     stable_connection_threshold: Duration,
+    /// Reconnect attempts since the last stable connection
+    attempt: u32,
 }
 
 impl ReconnectManager {
@@ -17,44 +26,58 @@ impl ReconnectManager {
         let min = Duration::from_secs(min_backoff_s);
         Self {
             min_backoff: min,
-            max_backoff: Duration::from_secs(max_backoff_s),
+            max_backoff: Duration::from_secs(max_backoff_s).max(min),
             current_backoff: min,
             stable_connection_threshold: Duration::from_secs(60),
+            attempt: 0,
         }
     }
 
-    /// Wait for the current backoff duration, then increase for next time
+    /// Wait for the current backoff duration, then pick the next one
     pub async fn backoff(&mut self) {
+        let wait = self.current_backoff;
         info!(
-            backoff_s = self.current_backoff.as_secs(),
+            backoff_ms = wait.as_millis() as u64,
+            attempt = self.attempt + 1,
             "Backing off before reconnect"
         );
-        sleep(self.current_backoff).await;
-        
-        // Double the backoff
-        let next = self.current_backoff * 2;
-        
-        // Add jitter (0-50% of backoff) before capping
-        let jitter = next / 4;
-        let jitter_amount = (rand::random::<f64>() * jitter.as_secs_f64()) as u64;
-        let with_jitter = next + Duration::from_secs(jitter_amount);
-        
-        // Cap at max backoff
-        self.current_backoff = with_jitter.min(self.max_backoff);
-        
-        debug!(next_backoff_s = self.current_backoff.as_secs(), "Next backoff calculated");
+        self.advance();
+        sleep(wait).await;
+    }
+
+    /// Draw the next backoff between the minimum and three times the current one
+    fn advance(&mut self) {
+        self.attempt = self.attempt.saturating_add(1);
+        let low = self.min_backoff.as_millis() as u64;
+        let high = (self.current_backoff.as_millis() as u64).saturating_mul(3).max(low);
+        let next = Duration::from_millis(rand::thread_rng().gen_range(low..=high));
+        self.current_backoff = next.min(self.max_backoff);
+        debug!(next_backoff_ms = self.current_backoff.as_millis() as u64, "Next backoff calculated");
+    }
+
+    /// Note how long a connection lasted; only a stable one resets the backoff
+    pub fn connection_ended(&mut self, uptime: Duration) {
+        if uptime >= self.stable_connection_threshold {
+            self.reset();
+        }
     }
 
     /// Reset backoff after a stable connection
     pub fn reset(&mut self) {
         info!("Resetting backoff after stable connection");
         self.current_backoff = self.min_backoff;
+        self.attempt = 0;
     }
 
     /// Get current backoff duration
     pub fn current(&self) -> Duration {
         self.current_backoff
     }
+
+    /// Reconnect attempts since the last stable connection
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
 }
 
 impl Default for ReconnectManager {
@@ -67,42 +90,62 @@ impl Default for ReconnectManager {
 mod tests {
     use super::*;
 
+    fn manager_ms(min_ms: u64, max_ms: u64) -> ReconnectManager {
+        let mut mgr = ReconnectManager::new(0, 0);
+        mgr.min_backoff = Duration::from_millis(min_ms);
+        mgr.max_backoff = Duration::from_millis(max_ms);
+        mgr.current_backoff = Duration::from_millis(min_ms);
+        mgr
+    }
+
     #[test]
     fn test_backoff_increases() {
         let mut mgr = ReconnectManager::new(1, 60);
         
         assert_eq!(mgr.current().as_secs(), 1);
         
-        // Backoff should roughly double (with jitter)
-        tokio_test::block_on(mgr.backoff());
-        assert!(mgr.current().as_secs() >= 2 && mgr.current().as_secs() <= 3);
+        // The next wait is drawn from [min, 3 × current]
+        mgr.advance();
+        assert!(mgr.current() >= Duration::from_secs(1) && mgr.current() <= Duration::from_secs(3));
+        assert_eq!(mgr.attempt(), 1);
     }
 
     #[test]
     fn test_backoff_caps_at_max() {
-        // Use millisecond values for fast testing
-        let min_ms = 10;
-        let max_ms = 50;
-        let mut mgr = ReconnectManager::new(0, 0);
-        mgr.min_backoff = Duration::from_millis(min_ms);
-        mgr.max_backoff = Duration::from_millis(max_ms);
-        mgr.current_backoff = Duration::from_millis(min_ms);
+        let mut mgr = manager_ms(10, 50);
         
         // Should cap at max after multiple backoffs
-        tokio_test::block_on(mgr.backoff());
-        tokio_test::block_on(mgr.backoff());
-        tokio_test::block_on(mgr.backoff());
-        assert!(mgr.current().as_millis() <= max_ms as u128);
+        for _ in 0..20 {
+            tokio_test::block_on(mgr.backoff());
+            assert!(mgr.current().as_millis() <= 50);
+            assert!(mgr.current().as_millis() >= 10);
+        }
     }
 
     #[test]
     fn test_reset() {
-        let mut mgr = ReconnectManager::new(1, 60);
+        let mut mgr = manager_ms(1000, 60_000);
         
-        tokio_test::block_on(mgr.backoff());
-        assert!(mgr.current().as_secs() > 1);
+        mgr.advance();
+        mgr.advance();
+        assert_eq!(mgr.attempt(), 2);
         
         mgr.reset();
         assert_eq!(mgr.current().as_secs(), 1);
+        assert_eq!(mgr.attempt(), 0);
+    }
+
+    #[test]
+    fn test_only_stable_connections_reset() {
+        let mut mgr = manager_ms(1000, 60_000);
+        mgr.advance();
+
+        // A connection dropped right after it was accepted keeps backing off
+        mgr.connection_ended(Duration::from_secs(2));
+        assert_eq!(mgr.attempt(), 1);
+
+        mgr.connection_ended(Duration::from_secs(60));
+        assert_eq!(mgr.attempt(), 0);
+        assert_eq!(mgr.current(), Duration::from_secs(1));
     }
 }
@@ -0,0 +1,159 @@
+//! Keeps the master's record of this client's addresses current
+//!
+//! Registration reports eth0 and wlan0 addresses once; after that they go
+//! stale with every DHCP lease or failover. The reporter checks them every
+//! `network.report_s` and right after the uplink changes, and sends any
+//! change to the master's `PATCH /clients/:client_id/network`. A report the
+//! master does not take is retried at the next check.
+
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use super::RestFallback;
+use crate::events::{Event, EventEnvelope};
+use crate::network::interface_ipv4;
+
+/// Addresses the master keeps for a client
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NetworkReport {
+    pub eth0_ip: Option<String>,
+    pub wlan0_ip: Option<String>,
+    pub service_port: Option<u16>,
+}
+
+impl NetworkReport {
+    /// Addresses the interfaces have right now
+    pub fn current(service_port: Option<u16>) -> Self {
+        Self {
+            eth0_ip: interface_ipv4("eth0").map(|ip| ip.to_string()),
+            wlan0_ip: interface_ipv4("wlan0").map(|ip| ip.to_string()),
+            service_port,
+        }
+    }
+}
+
+/// Sends address changes to the master
+pub struct AddressReporter {
+    rest: RestFallback,
+    service_port: Option<u16>,
+    interval: Duration,
+    reported: Option<NetworkReport>,
+}
+
+impl AddressReporter {
+    pub fn new(rest: RestFallback, service_port: Option<u16>, interval: Duration) -> Self {
+        Self {
+            rest,
+            service_port,
+            interval,
+            reported: None,
+        }
+    }
+
+    pub fn spawn(self, rx: broadcast::Receiver<EventEnvelope>) -> JoinHandle<()> {
+        tokio::spawn(self.run(rx))
+    }
+
+    async fn run(mut self, mut rx: broadcast::Receiver<EventEnvelope>) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                received = rx.recv() => match received {
+                    Ok(envelope) if changes_addresses(&envelope.event) => {}
+                    Ok(_) => continue,
+                    // A missed uplink change is caught by the next check anyway
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+            let report = NetworkReport::current(self.service_port);
+            self.report(report).await;
+        }
+    }
+
+    /// Send `report` unless the master already has it
+    async fn report(&mut self, report: NetworkReport) {
+        if self.reported.as_ref() == Some(&report) {
+            return;
+        }
+        match self.rest.report_network(&report).await {
+            Ok(()) => {
+                info!(eth0 = ?report.eth0_ip, wlan0 = ?report.wlan0_ip, "Reported addresses to master");
+                self.reported = Some(report);
+            }
+            Err(e) => warn!(error = %e, "Failed to report addresses to master"),
+        }
+    }
+}
+
+/// Events after which the addresses are likely different
+fn changes_addresses(event: &Event) -> bool {
+    let changed = matches!(
+        event,
+        Event::NetworkInterfaceChanged { .. }
+            | Event::NetworkOnline { .. }
+            | Event::NetworkConfigChanged { .. }
+            | Event::NetworkConfigRolledBack { .. }
+    );
+    if changed {
+        debug!(event = %event.type_name(), "Checking addresses after network change");
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloud::MasterToken;
+    use axum::{extract::Path, routing::patch, Json, Router};
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_reports_only_changes() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let master = Router::new().route(
+            "/clients/:client_id/network",
+            patch(move |Path(client_id): Path<String>, Json(body): Json<serde_json::Value>| async move {
+                assert_eq!(client_id, "c-42");
+                let mut received = log.lock().unwrap();
+                received.push(body);
+                // The first report arrives while the master is restarting
+                if received.len() == 1 {
+                    axum::http::StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    axum::http::StatusCode::OK
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, master).await.unwrap() });
+
+        let rest = RestFallback::new(&format!("http://{}", addr), "c-42", MasterToken::default(), None).unwrap();
+        let mut reporter = AddressReporter::new(rest, Some(8080), Duration::from_secs(60));
+        let report = |eth0: &str| NetworkReport {
+            eth0_ip: Some(eth0.to_string()),
+            wlan0_ip: None,
+            service_port: Some(8080),
+        };
+
+        reporter.report(report("192.168.1.20")).await;
+        reporter.report(report("192.168.1.20")).await;
+        reporter.report(report("192.168.1.20")).await;
+        reporter.report(report("192.168.1.31")).await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[1]["eth0_ip"], "192.168.1.20");
+        assert_eq!(received[1]["wlan0_ip"], serde_json::Value::Null);
+        assert_eq!(received[1]["service_port"], 8080);
+        assert_eq!(received[2]["eth0_ip"], "192.168.1.31");
+        assert!(changes_addresses(&Event::NetworkOnline { interface: "wlan0".to_string() }));
+        assert!(!changes_addresses(&Event::DoorOpen));
+    }
+}
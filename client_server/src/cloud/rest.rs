@@ -0,0 +1,342 @@
+//! HTTPS fallback for when the cloud WebSocket cannot be established
+//!
+//! Queued envelopes are posted one by one, oldest first, to the master's
+//! `/clients/:client_id/events`, each removed from the queue once the master
+//! accepts it; heartbeats go to `/clients/:client_id/heartbeat`. Outcomes of
+//! cloud commands are reported to `/clients/:client_id/commands/:cmd_id/ack`
+//! whichever way the command arrived, so the master can close it. A pushed
+//! configuration is fetched from `/clients/:client_id/config` and must hash to
+//! the digest in the signed command that asked for it, address
+//! changes are sent to `/clients/:client_id/network`, and the API token is
+//! replaced through `/clients/:client_id/token/rotate`.
+
+use super::{http_client, pinned_http_client, pinned_tls_config, NetworkReport, QueueManager};
+use crate::config::{ConfigError, ProxyConfig};
+use crate::events::{EventBus, EventEnvelope, Severity};
+use crate::health::HealthReport;
+use crate::observability::CrashReport;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+/// Longest a request to the master may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+/// Attempts at acknowledging a command before giving up on it
+const ACK_ATTEMPTS: u32 = 10;
+/// Wait before the first retry, doubled after each failure
+const ACK_RETRY_MIN: Duration = Duration::from_secs(1);
+const ACK_RETRY_MAX: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Serialize)]
+struct EventRequest<'a> {
+    level: &'static str,
+    kind: String,
+    message: String,
+    meta: &'a EventEnvelope,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RotateTokenResponse {
+    api_token: String,
+}
+
+/// Bearer token for the master, shared so a rotated one reaches every connection
+#[derive(Clone, Default)]
+pub struct MasterToken(Arc<RwLock<Option<String>>>);
+
+impl MasterToken {
+    pub fn new(token: Option<String>) -> Self {
+        Self(Arc::new(RwLock::new(token)))
+    }
+
+    pub fn get(&self) -> Option<String> {
+        self.0.read().clone()
+    }
+
+    pub fn set(&self, token: String) {
+        *self.0.write() = Some(token);
+    }
+}
+
+/// Client for the master's telemetry endpoints
+#[derive(Clone)]
+pub struct RestFallback {
+    http: reqwest::Client,
+    base_url: String,
+    token: MasterToken,
+    proxy: Option<ProxyConfig>,
+}
+
+impl RestFallback {
+    pub fn new(master_url: &str, client_id: &str, token: MasterToken, proxy: Option<&ProxyConfig>) -> Result<Self> {
+        let http = http_client(proxy, REQUEST_TIMEOUT)?;
+        Ok(Self {
+            http,
+            base_url: format!("{}/clients/{}", master_url.trim_end_matches('/'), client_id),
+            token,
+            proxy: proxy.cloned(),
+        })
+    }
+
+    /// Only talk to a master whose certificate chain carries one of `pins`, as the cloud WebSocket does
+    pub fn set_pins(&mut self, pins: &[String], event_bus: EventBus) -> Result<()> {
+        let tls = pinned_tls_config(pins, event_bus)?;
+        self.http = pinned_http_client(self.proxy.as_ref(), REQUEST_TIMEOUT, tls)?;
+        Ok(())
+    }
+
+    /// Post one envelope; the master answers repeats by `seq` without storing them twice
+    pub async fn post_event(&self, envelope: &EventEnvelope) -> Result<()> {
+        let request = EventRequest {
+            level: match envelope.severity {
+                Severity::Debug | Severity::Info => "info",
+                Severity::Warn => "warn",
+                Severity::Critical => "error",
+            },
+            kind: envelope.event.type_name(),
+            message: format!("{:?}", envelope.event),
+            meta: envelope,
+            seq: (envelope.seq > 0).then_some(envelope.seq),
+            signature: envelope.signature.as_deref(),
+        };
+        self.post("events", &request).await.map(|_| ())
+    }
+
+    /// Post a heartbeat, returning the master's clock from its `Date` header
+    pub async fn post_heartbeat(&self, uptime_ms: i64) -> Result<Option<DateTime<Utc>>> {
+        let response = self.post("heartbeat", &serde_json::json!({ "uptime_ms": uptime_ms })).await?;
+        Ok(response
+            .headers()
+            .get(reqwest::header::DATE)
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+            .map(|date| date.with_timezone(&Utc)))
+    }
+
+    /// Report a command's outcome, retrying with backoff until the master takes it;
+    /// returns whether it was delivered
+    pub async fn ack_command(&self, cmd_id: &str, success: bool, error: Option<String>) -> bool {
+        self.ack(cmd_id, serde_json::json!({ "success": success, "error": error })).await
+    }
+
+    /// Acknowledge a `config_pull`, listing in `errors` each setting that failed validation
+    pub async fn ack_config(&self, cmd_id: &str, error: Option<String>, errors: &[ConfigError]) -> bool {
+        let mut body = serde_json::json!({ "success": error.is_none(), "error": error });
+        if !errors.is_empty() {
+            body["errors"] = serde_json::json!(errors);
+        }
+        self.ack(cmd_id, body).await
+    }
+
+    async fn ack(&self, cmd_id: &str, body: serde_json::Value) -> bool {
+        let path = format!("commands/{}/ack", cmd_id);
+        let mut delay = ACK_RETRY_MIN;
+        for attempt in 1..=ACK_ATTEMPTS {
+            match self.post(&path, &body).await {
+                Ok(_) => {
+                    debug!(%cmd_id, success = %body["success"], "Command acknowledged to master");
+                    return true;
+                }
+                Err(e) if attempt < ACK_ATTEMPTS => {
+                    warn!(%cmd_id, attempt, error = %e, "Failed to acknowledge command, retrying");
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(ACK_RETRY_MAX);
+                }
+                Err(e) => error!(%cmd_id, error = %e, "Giving up acknowledging command"),
+            }
+        }
+        false
+    }
+
+    /// Fetch the configuration document the master holds for this client, refusing it unless its SHA-256 is `sha256`
+    pub async fn fetch_config(&self, sha256: &[u8]) -> Result<serde_json::Value> {
+        let response = self.send(self.http.get(format!("{}/config", self.base_url))).await?;
+        let document = response.bytes().await.context("Failed to read the configuration document")?;
+        if Sha256::digest(&document)[..] != *sha256 {
+            bail!("Configuration document does not match the digest in the command");
+        }
+        serde_json::from_slice(&document).context("Master sent an invalid configuration document")
+    }
+
+    /// Update the interface addresses the master holds for this client
+    pub async fn report_network(&self, report: &NetworkReport) -> Result<()> {
+        self.send(self.http.patch(format!("{}/network", self.base_url)).json(report)).await.map(|_| ())
+    }
+
+    /// Hand over a crash report left by an earlier run
+    pub async fn post_crash_report(&self, report: &CrashReport) -> Result<()> {
+        self.post("crash_reports", report).await.map(|_| ())
+    }
+
+    /// Hand over a health report for the fleet dashboards
+    pub async fn post_health_report(&self, report: &HealthReport) -> Result<()> {
+        self.post("health_reports", report).await.map(|_| ())
+    }
+
+    /// Post everything queued, oldest first, stopping at the first failure
+    pub async fn drain(&self, queue: &QueueManager) -> Result<usize> {
+        let mut sent = 0;
+        loop {
+            let batch = queue.next_batch().await?;
+            if batch.is_empty() {
+                break;
+            }
+            for envelope in &batch {
+                self.post_event(envelope).await?;
+                queue.acknowledge(envelope).await?;
+                sent += 1;
+            }
+        }
+        if sent > 0 {
+            info!(count = sent, "Delivered queued events over HTTPS");
+        }
+        Ok(sent)
+    }
+
+    /// Trade the token for a new one from the master, used from then on by every clone
+    ///
+    /// The master still accepts the old token for its grace period, so
+    /// requests already under way are not refused.
+    pub async fn rotate_token(&self) -> Result<String> {
+        let response = self.post("token/rotate", &serde_json::json!({})).await?;
+        let rotated: RotateTokenResponse = response.json().await.context("Invalid token rotation response")?;
+        self.token.set(rotated.api_token.clone());
+        Ok(rotated.api_token)
+    }
+
+    async fn post<T: Serialize + ?Sized>(&self, path: &str, body: &T) -> Result<reqwest::Response> {
+        self.send(self.http.post(format!("{}/{}", self.base_url, path)).json(body)).await
+    }
+
+    async fn send(&self, mut request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        if let Some(token) = self.token.get() {
+            request = request.bearer_auth(token);
+        }
+        let request = request.build()?;
+        let (method, url) = (request.method().clone(), request.url().clone());
+        let response = self
+            .http
+            .execute(request)
+            .await
+            .with_context(|| format!("Failed to reach {}", url))?;
+        let status = response.status();
+        if !status.is_success() {
+            bail!("Master answered {} for {}", status, url);
+        }
+        debug!(%method, %url, "Request to master succeeded");
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{Event, EventQueue};
+    use axum::{extract::Path, routing::post, Json, Router};
+    use std::sync::{Arc, Mutex};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_drain_posts_in_order() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let master = Router::new().route(
+            "/clients/:client_id/:kind",
+            post(move |Path((client_id, kind)): Path<(String, String)>, Json(body): Json<serde_json::Value>| async move {
+                assert_eq!(client_id, "c-42");
+                log.lock().unwrap().push((kind, body));
+                axum::http::StatusCode::ACCEPTED
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, master).await.unwrap() });
+
+        let dir = TempDir::new().unwrap();
+        let queue = QueueManager::new(EventQueue::new(dir.path(), 100, 7).unwrap(), 1);
+        queue.enqueue(EventEnvelope::new(Event::DoorOpen, "c-42".to_string())).await.unwrap();
+        let mut alarm = EventEnvelope::new(Event::GlassBreak, "c-42".to_string());
+        alarm.seq = 7;
+        queue.enqueue(alarm).await.unwrap();
+
+        let rest = RestFallback::new(&format!("http://{}", addr), "c-42", MasterToken::default(), None).unwrap();
+        assert_eq!(rest.drain(&queue).await.unwrap(), 2);
+        assert_eq!(queue.size().await.unwrap(), 0);
+        // axum stamps every response with a Date header
+        assert!(rest.post_heartbeat(5000).await.unwrap().is_some());
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0].1["kind"], "door_open");
+        assert!(received[0].1.get("seq").is_none());
+        assert_eq!(received[1].1["kind"], "glass_break");
+        assert_eq!(received[1].1["level"], "error");
+        assert_eq!(received[1].1["seq"], 7);
+        assert_eq!(received[1].1["meta"]["event"]["type"], "glass_break");
+        assert_eq!(received[2].0, "heartbeat");
+        assert_eq!(received[2].1["uptime_ms"], 5000);
+    }
+
+    #[tokio::test]
+    async fn test_command_ack_retried_until_accepted() {
+        let acks = Arc::new(Mutex::new(Vec::new()));
+        let log = acks.clone();
+        let master = Router::new().route(
+            "/clients/:client_id/commands/:cmd_id/ack",
+            post(move |Path((_, cmd_id)): Path<(String, String)>, Json(body): Json<serde_json::Value>| async move {
+                let mut acks = log.lock().unwrap();
+                acks.push((cmd_id, body));
+                // The master is briefly unavailable for the first attempt
+                if acks.len() == 1 {
+                    axum::http::StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    axum::http::StatusCode::NO_CONTENT
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, master).await.unwrap() });
+
+        let rest = RestFallback::new(&format!("http://{}", addr), "c-42", MasterToken::default(), None).unwrap();
+        assert!(rest.ack_command("cmd-1", false, Some("No such output".to_string())).await);
+
+        let acks = acks.lock().unwrap();
+        assert_eq!(acks.len(), 2);
+        assert_eq!(acks[1].0, "cmd-1");
+        assert_eq!(acks[1].1["success"], false);
+        assert_eq!(acks[1].1["error"], "No such output");
+    }
+
+    #[tokio::test]
+    async fn test_failed_post_keeps_event_queued() {
+        let dir = TempDir::new().unwrap();
+        let queue = QueueManager::new(EventQueue::new(dir.path(), 100, 7).unwrap(), 10);
+        queue.enqueue(EventEnvelope::new(Event::DoorOpen, "c-42".to_string())).await.unwrap();
+
+        // Nothing listens on port 9 of localhost
+        let rest = RestFallback::new("http://127.0.0.1:9", "c-42", MasterToken::default(), None).unwrap();
+        assert!(rest.drain(&queue).await.is_err());
+        assert_eq!(queue.size().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pins_must_parse() {
+        let mut rest = RestFallback::new("https://master.example", "c-42", MasterToken::default(), None).unwrap();
+        let (bus, _rx) = EventBus::new();
+        assert!(rest.set_pins(&["sha256/not base64".to_string()], bus.clone()).is_err());
+        assert!(rest.set_pins(&[], bus.clone()).is_err());
+
+        let pin = format!("sha256/{}", base64::Engine::encode(&base64::engine::general_purpose::STANDARD, [7u8; 32]));
+        rest.set_pins(&[pin], bus).unwrap();
+    }
+}
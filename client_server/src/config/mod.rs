@@ -1,9 +1,14 @@
 //! Configuration management module
 
+mod reload;
 mod schema;
+mod update;
 mod validation;
 
+pub use reload::*;
 pub use schema::*;
+pub use update::*;
+pub use validation::{ConfigError, ValidationErrors};
 
 use anyhow::Result;
 
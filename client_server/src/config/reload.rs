@@ -0,0 +1,239 @@
+//! Reloading the configuration file on SIGHUP
+//!
+//! `systemctl reload` (or `kill -HUP`) makes the agent read its configuration
+//! file again. The whole file must pass validation; if it does not, nothing
+//! changes and `config_rejected` is raised. Otherwise the settings in
+//! [`RELOADABLE`] take effect together: timers, the log level,
+//! `rf433.mappings` and the event sinks. `config_reloaded` lists every setting
+//! that differs from what the agent runs with, and those of them that wait for
+//! a restart; a file saved by `PUT /v1/config`, a master push or a rollback is
+//! applied the same way.
+
+use super::{AppConfig, ConfigStore, ValidationErrors};
+use crate::events::{spawn_sinks, Event, EventBus, SinkHandle};
+use crate::observability::LogHandle;
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Settings a reload applies without restarting the agent
+pub const RELOADABLE: &[&str] = &["timers", "system.log_level", "rf433.mappings", "sinks"];
+
+/// A configuration read again from file, and how it differs from the one running
+#[derive(Debug, Clone)]
+pub struct ConfigReload {
+    pub config: AppConfig,
+    /// Changed sections, or settings within sections that hold a reloadable one, e.g. `system.log_level`
+    pub changed: Vec<String>,
+}
+
+impl ConfigReload {
+    pub fn changed(&self, setting: &str) -> bool {
+        self.changed.iter().any(|changed| changed == setting)
+    }
+
+    /// Changed settings that are only read at startup
+    pub fn restart_required(&self) -> Vec<String> {
+        self.changed
+            .iter()
+            .filter(|changed| !RELOADABLE.contains(&changed.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    /// Announces the reload, with the new settings the running tasks take up
+    pub fn event(&self) -> Event {
+        Event::ConfigReloaded {
+            changed: self.changed.clone(),
+            restart_required: self.restart_required(),
+            timers: self.changed("timers").then(|| self.config.timers.clone()),
+        }
+    }
+}
+
+/// Settings that differ between two configurations
+pub fn changed_settings(before: &AppConfig, after: &AppConfig) -> Result<Vec<String>> {
+    let (before, after) = (serde_json::to_value(before)?, serde_json::to_value(after)?);
+    let mut changed = Vec::new();
+    for section in keys(&before, &after) {
+        let (old, new) = (&before[section.as_str()], &after[section.as_str()]);
+        if old == new {
+            continue;
+        }
+        // Sections holding a reloadable setting are listed key by key, so the rest of them still asks for a restart
+        let prefix = format!("{}.", section);
+        if RELOADABLE.iter().any(|setting| setting.starts_with(&prefix)) {
+            changed.extend(
+                keys(old, new)
+                    .into_iter()
+                    .filter(|key| old[key.as_str()] != new[key.as_str()])
+                    .map(|key| format!("{}{}", prefix, key)),
+            );
+        } else {
+            changed.push(section);
+        }
+    }
+    Ok(changed)
+}
+
+fn keys(a: &Value, b: &Value) -> BTreeSet<String> {
+    [a, b]
+        .into_iter()
+        .filter_map(Value::as_object)
+        .flat_map(|object| object.keys().cloned())
+        .collect()
+}
+
+/// Reloads the configuration on SIGHUP and applies what can change while running
+pub struct ConfigManager {
+    store: ConfigStore,
+    event_bus: EventBus,
+    logging: Option<LogHandle>,
+    sinks: Vec<SinkHandle>,
+    /// What the agent runs with: the startup configuration plus the reloaded settings
+    running: AppConfig,
+}
+
+impl ConfigManager {
+    pub fn new(store: ConfigStore, event_bus: EventBus) -> Self {
+        let running = store.current();
+        Self { store, event_bus, logging: None, sinks: Vec::new(), running }
+    }
+
+    /// Change the log level when `system.log_level` changes
+    pub fn set_logging(&mut self, logging: LogHandle) {
+        self.logging = Some(logging);
+    }
+
+    /// Restart the event sinks when `sinks` changes
+    pub fn set_sinks(&mut self, sinks: Vec<SinkHandle>) {
+        self.sinks = sinks;
+    }
+
+    pub fn spawn(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            #[cfg(unix)]
+            {
+                use tokio::signal::unix::{signal, SignalKind};
+                let mut hangup = match signal(SignalKind::hangup()) {
+                    Ok(hangup) => hangup,
+                    Err(e) => {
+                        warn!(error = %e, "SIGHUP handler unavailable, configuration reload disabled");
+                        return;
+                    }
+                };
+                while hangup.recv().await.is_some() {
+                    info!("SIGHUP received, reloading configuration");
+                    self.reload().await;
+                }
+            }
+            #[cfg(not(unix))]
+            warn!("No SIGHUP on this platform, configuration reload disabled");
+        })
+    }
+
+    /// Read the file again and apply it, raising `config_reloaded` or `config_rejected`
+    pub async fn reload(&mut self) {
+        let reload = self.store.run_blocking(|store| store.reload()).await.and_then(|config| {
+            let changed = changed_settings(&self.running, &config)?;
+            Ok(ConfigReload { config, changed })
+        });
+        let event = match reload {
+            Ok(reload) => {
+                self.apply(&reload);
+                info!(changed = ?reload.changed, restart_required = ?reload.restart_required(), "Configuration reloaded");
+                reload.event()
+            }
+            Err(e) => {
+                let error = format!("{:#}", e);
+                warn!(%error, "Configuration reload rejected, keeping the running configuration");
+                Event::ConfigRejected { error, errors: ValidationErrors::of(&e) }
+            }
+        };
+        if let Err(e) = self.event_bus.emit(event) {
+            warn!(error = %e, "Failed to report configuration reload");
+        }
+    }
+
+    /// Settings applied here; timers reach their tasks through the event, RF mappings through the store
+    fn apply(&mut self, reload: &ConfigReload) {
+        let config = &reload.config;
+        self.running.timers = config.timers.clone();
+        self.running.system.log_level = config.system.log_level.clone();
+        self.running.rf433.mappings = config.rf433.mappings.clone();
+        self.running.sinks = config.sinks.clone();
+
+        if reload.changed("system.log_level") {
+            if let Some(logging) = &self.logging {
+                if let Err(e) = logging.set_level(&config.system.log_level) {
+                    warn!(error = %e, "Failed to change the log level");
+                }
+            }
+        }
+        if reload.changed("sinks") {
+            for sink in self.sinks.drain(..) {
+                sink.stop();
+            }
+            self.sinks = spawn_sinks(&config.sinks, &self.event_bus, &config.system.client_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reload_applies_valid_file_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let running = AppConfig::test_default();
+        let store = ConfigStore::new(running.clone(), &path);
+        let (event_bus, mut rx) = EventBus::new();
+        let mut manager = ConfigManager::new(store.clone(), event_bus);
+
+        let mut edited = running.clone();
+        edited.timers.exit_delay_s = 45;
+        edited.system.log_level = "warn".to_string();
+        edited.http.listen_addr = "127.0.0.1:9090".to_string();
+        edited.system.client_id = "from-file".to_string();
+        std::fs::write(&path, toml::to_string(&edited).unwrap()).unwrap();
+
+        manager.reload().await;
+        match rx.recv().await.unwrap() {
+            Event::ConfigReloaded { changed, restart_required, timers } => {
+                assert_eq!(changed, ["http", "system.log_level", "timers"]);
+                assert_eq!(restart_required, ["http"]);
+                assert_eq!(timers.unwrap().exit_delay_s, 45);
+            }
+            other => panic!("Unexpected event: {:?}", other),
+        }
+        // The identity given at startup outlives the file
+        assert_eq!(store.current().system.client_id, "test-client");
+        assert_eq!(store.current().timers.exit_delay_s, 45);
+
+        edited.timers.exit_delay_s = 0;
+        edited.timers.entry_delay_s = 0;
+        std::fs::write(&path, toml::to_string(&edited).unwrap()).unwrap();
+        manager.reload().await;
+        match rx.recv().await.unwrap() {
+            Event::ConfigRejected { error, errors } => {
+                assert!(error.contains("timers.entry_delay_s"), "{}", error);
+                assert!(errors.iter().any(|e| e.field == "timers.entry_delay_s"), "{:?}", errors);
+            }
+            other => panic!("Unexpected event: {:?}", other),
+        }
+        assert_eq!(store.current().timers.exit_delay_s, 45);
+
+        // Settings that wait for a restart are listed until it happens
+        edited.timers = store.current().timers;
+        std::fs::write(&path, toml::to_string(&edited).unwrap()).unwrap();
+        manager.reload().await;
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::ConfigReloaded { changed, timers: None, .. } if changed == ["http"]
+        ));
+    }
+}
@@ -1,7 +1,16 @@
 //! Configuration data structures
 
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use anyhow::Context;
+use chrono::NaiveTime;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+
+use crate::events::{AlarmKind, OverflowPolicy, Severity, ZoneType};
+
+/// Where the configuration is read from; owned by root, so the agent does not write it
+pub const CONFIG_PATH: &str = "/etc/pi-door-client/config.toml";
 
 /// Main application configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,14 +24,73 @@ pub struct AppConfig {
     pub timers: TimerConfig,
     pub ble: BleConfig,
     pub rf433: Rf433Config,
+    #[serde(default)]
+    pub rtl433: Rtl433Config,
+    #[serde(default)]
+    pub display: DisplayConfig,
+    #[serde(default)]
+    pub onewire: OneWireConfig,
+    #[serde(default)]
+    pub adc: AdcConfig,
+    #[serde(default)]
+    pub thermal: ThermalConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub memory: MemoryConfig,
+    #[serde(default)]
+    pub self_test: SelfTestConfig,
+    #[serde(default)]
+    pub health_report: HealthReportConfig,
+    #[serde(default)]
+    pub access: AccessConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub journal: JournalConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub replay: ReplayConfig,
+    #[serde(default)]
+    pub rotation: RotationConfig,
+    #[serde(default)]
+    pub event_bus: EventBusConfig,
+    #[serde(default)]
+    pub signing: SigningConfig,
+    #[serde(default)]
+    pub dead_letter: DeadLetterConfig,
+    #[serde(default)]
+    pub update: UpdateConfig,
+    #[serde(default)]
+    pub privileges: PrivilegesConfig,
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    /// Integrations every envelope is fanned out to
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
 }
 
 impl AppConfig {
-    /// Load configuration from default paths
+    /// Load configuration from default paths, with the settings saved at runtime over the file
     pub fn load() -> anyhow::Result<Self> {
-        let config_path = "/etc/pi-door-client/config.toml";
+        let path = Path::new(CONFIG_PATH);
+        Self::load_layered(path, Some(&Self::load_from(path)?.override_path()))
+    }
+
+    /// Load configuration from `path` over the built-in defaults
+    pub fn load_from(path: &Path) -> anyhow::Result<Self> {
+        Self::load_layered(path, None)
+    }
 
-        let settings = config::Config::builder()
+    /// Load configuration from `path`, with the settings saved at runtime in `overrides` over it
+    ///
+    /// Protected settings in `overrides` are ignored, so only `path` decides them.
+    pub fn load_layered(path: &Path, overrides: Option<&Path>) -> anyhow::Result<Self> {
+        let overrides = overrides.map(super::update::read_overrides).transpose()?.flatten();
+        let mut settings = config::Config::builder()
             // Start with defaults
             .set_default("system.client_id", "pi001")?
             .set_default("system.data_dir", "/var/lib/pi-door-client")?
@@ -36,28 +104,142 @@ impl AppConfig {
             .set_default("cloud.backoff_max_s", 60)?
             .set_default("cloud.queue_max_events", 10000)?
             .set_default("cloud.queue_max_age_days", 7)?
+            .set_default("gpio.chip", "/dev/gpiochip0")?
+            .set_default("gpio.file_dir", "/tmp/pi-door-gpio")?
             .set_default("gpio.reed_in", 17)?
             .set_default("gpio.reed_active_low", true)?
             .set_default("gpio.siren_out", 27)?
             .set_default("gpio.floodlight_out", 22)?
             .set_default("gpio.radio433_rx_in", 23)?
             .set_default("gpio.debounce_ms", 50)?
+            .set_default("gpio.siren_pwm_duty", 0.5)?
+            .set_default("gpio.buzzer_duty", 0.5)?
+            .set_default("gpio.self_test_on_startup", true)?
+            .set_default("gpio.vibration_pulses", 3)?
+            .set_default("gpio.vibration_window_ms", 2000)?
             .set_default("timers.exit_delay_s", 30)?
             .set_default("timers.entry_delay_s", 30)?
             .set_default("timers.auto_rearm_s", 120)?
             .set_default("timers.siren_max_s", 120)?
+            .set_default("timers.motion_floodlight_s", 60)?
             .set_default("ble.enabled", true)?
             .set_default("ble.pairing_window_s", 120)?
+            .set_default("ble.missing_after_days", 7)?
             .set_default("rf433.enabled", true)?
             .set_default("rf433.allow_disarm", false)?
             .set_default("rf433.debounce_ms", 500)?
             // Try to load from file (may not exist)
-            .add_source(config::File::with_name(config_path).required(false))
-            .build()?;
+            // Earlier versions are named `config.toml.1` and so on, so the format is not left to the extension
+            .add_source(config::File::from(path).format(config::FileFormat::Toml).required(false));
+        if let Some(overrides) = overrides {
+            settings = settings.add_source(config::File::from_str(&overrides, config::FileFormat::Toml));
+        }
+        let settings = settings.build()?;
 
         let config: AppConfig = settings.try_deserialize()?;
         Ok(config)
     }
+
+    /// File holding enrolled cards and PINs
+    pub fn credentials_path(&self) -> PathBuf {
+        self.access
+            .credentials_path
+            .clone()
+            .unwrap_or_else(|| self.system.data_dir.join("credentials.json"))
+    }
+
+    /// Directory of the rotated JSON log files
+    pub fn log_dir(&self) -> PathBuf {
+        self.logging
+            .dir
+            .clone()
+            .unwrap_or_else(|| self.system.data_dir.join("logs"))
+    }
+
+    /// Directory of crash reports waiting to be sent to the master
+    pub fn crash_dir(&self) -> PathBuf {
+        self.system.data_dir.join("crash")
+    }
+
+    /// Directory of the local event journal database
+    pub fn journal_path(&self) -> PathBuf {
+        self.journal
+            .path
+            .clone()
+            .unwrap_or_else(|| self.system.data_dir.join("journal"))
+    }
+
+    /// SQLite database of the usage history
+    pub fn history_path(&self) -> PathBuf {
+        self.history
+            .path
+            .clone()
+            .unwrap_or_else(|| self.system.data_dir.join("history.db"))
+    }
+
+    /// Directory of the audit log database
+    pub fn audit_path(&self) -> PathBuf {
+        self.audit
+            .path
+            .clone()
+            .unwrap_or_else(|| self.system.data_dir.join("audit"))
+    }
+
+    /// Directory of the dead-letter database
+    pub fn dead_letter_path(&self) -> PathBuf {
+        self.dead_letter
+            .path
+            .clone()
+            .unwrap_or_else(|| self.system.data_dir.join("dead_letter"))
+    }
+
+    /// File holding the device key and other local secrets
+    pub fn secrets_path(&self) -> PathBuf {
+        self.signing
+            .secrets_path
+            .clone()
+            .unwrap_or_else(|| self.system.data_dir.join("secrets.json"))
+    }
+
+    /// Directory of the offline queue of events awaiting cloud delivery
+    pub fn queue_path(&self) -> PathBuf {
+        self.system.data_dir.join("events.db")
+    }
+
+    /// Directory of the persisted event sequence counter
+    pub fn sequence_path(&self) -> PathBuf {
+        self.system.data_dir.join("sequence")
+    }
+
+    /// Directory where agent updates are staged
+    pub fn update_dir(&self) -> PathBuf {
+        self.system.data_dir.join("update")
+    }
+
+    /// File holding phones bonded over BLE
+    pub fn ble_bonds_path(&self) -> PathBuf {
+        self.system.data_dir.join("ble_bonds.json")
+    }
+
+    /// Directory of the database of command nonces seen recently
+    pub fn replay_path(&self) -> PathBuf {
+        self.system.data_dir.join("replay")
+    }
+
+    /// File holding the settings changed at runtime, layered over the configuration file
+    pub fn override_path(&self) -> PathBuf {
+        self.system.data_dir.join("config.override.toml")
+    }
+
+    /// File holding the last counter accepted from each rolling-code remote
+    pub fn rf433_counters_path(&self) -> PathBuf {
+        self.system.data_dir.join("rf433_counters.json")
+    }
+
+    /// File holding recent self-test results and when the schedule last ran one
+    pub fn self_test_path(&self) -> PathBuf {
+        self.system.data_dir.join("self_test.json")
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -67,6 +249,19 @@ pub struct SystemConfig {
     pub log_level: String,
     #[serde(default)]
     pub api_key: Option<String>,
+    /// Master REST base URL, used to register with `--provision-key`
+    #[serde(default)]
+    pub master_url: Option<String>,
+    /// Enables developer-only endpoints such as event replay; never on in the field
+    #[serde(default)]
+    pub developer_mode: bool,
+    /// Earlier versions of the configuration file kept beside it, for rollback
+    #[serde(default = "default_config_versions")]
+    pub config_versions: usize,
+}
+
+fn default_config_versions() -> usize {
+    5
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -75,6 +270,162 @@ pub struct NetworkConfig {
     pub prefer: Vec<String>,
     #[serde(default)]
     pub enable_lte: bool,
+    #[serde(default)]
+    pub ap_fallback: ApFallbackConfig,
+    /// What Wi-Fi and addressing changes from the API are applied through
+    #[serde(default)]
+    pub backend: NetworkBackend,
+    /// Wi-Fi interface the API configures
+    #[serde(default = "default_wifi_interface")]
+    pub wifi_interface: String,
+    /// wpa_supplicant configuration Wi-Fi networks are saved to
+    #[serde(default = "default_wpa_supplicant_conf")]
+    pub wpa_supplicant_conf: PathBuf,
+    /// dhcpcd configuration static addresses are saved to with the wpa_supplicant backend
+    #[serde(default = "default_dhcpcd_conf")]
+    pub dhcpcd_conf: PathBuf,
+    /// Seconds after a change by which connectivity must be back, or the change is undone
+    #[serde(default = "default_network_rollback_s")]
+    pub rollback_s: u64,
+    /// Check the interface addresses this often and send changes to the master
+    #[serde(default = "default_network_report_s")]
+    pub report_s: u64,
+    #[serde(default)]
+    pub link_quality: LinkQualityConfig,
+    #[serde(default)]
+    pub failover: FailoverConfig,
+    #[serde(default)]
+    pub captive_portal: CaptivePortalConfig,
+}
+
+/// Damping of interface selection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FailoverConfig {
+    /// Seconds an interface must stay up before traffic moves over to it
+    pub hold_down_s: u64,
+    /// Window in which up/down changes are counted
+    pub flap_window_s: u64,
+    /// Changes within the window that mark an interface as flapping
+    pub flap_threshold: u32,
+}
+
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            hold_down_s: 30,
+            flap_window_s: 300,
+            flap_threshold: 4,
+        }
+    }
+}
+
+/// Captive portal probing of the Wi-Fi interfaces in `prefer`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptivePortalConfig {
+    pub enabled: bool,
+    /// Plain http URL answering `204 No Content` when the internet is reachable
+    pub probe_url: String,
+    /// Seconds between probes of an interface that stays up
+    pub interval_s: u64,
+}
+
+impl Default for CaptivePortalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            probe_url: "http://connectivitycheck.gstatic.com/generate_204".to_string(),
+            interval_s: 120,
+        }
+    }
+}
+
+/// Radio link quality sampling of the Wi-Fi and LTE interfaces in `prefer`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LinkQualityConfig {
+    pub interval_s: u64,
+    /// Wi-Fi signal below this many dBm is marginal
+    pub marginal_wifi_dbm: f64,
+    /// LTE RSRP below this many dBm is marginal
+    pub marginal_lte_rsrp_dbm: f64,
+}
+
+impl Default for LinkQualityConfig {
+    fn default() -> Self {
+        Self {
+            interval_s: 30,
+            marginal_wifi_dbm: -75.0,
+            marginal_lte_rsrp_dbm: -110.0,
+        }
+    }
+}
+
+/// How network settings are applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkBackend {
+    /// Edit wpa_supplicant.conf and dhcpcd.conf, as on Raspberry Pi OS before Bookworm
+    #[default]
+    WpaSupplicant,
+    /// Use NetworkManager through nmcli
+    Nmcli,
+}
+
+fn default_wifi_interface() -> String {
+    "wlan0".to_string()
+}
+
+fn default_wpa_supplicant_conf() -> PathBuf {
+    PathBuf::from("/etc/wpa_supplicant/wpa_supplicant.conf")
+}
+
+fn default_dhcpcd_conf() -> PathBuf {
+    PathBuf::from("/etc/dhcpcd.conf")
+}
+
+fn default_network_rollback_s() -> u64 {
+    120
+}
+
+fn default_network_report_s() -> u64 {
+    60
+}
+
+/// Setup access point started when no configured network can be reached
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApFallbackConfig {
+    pub enabled: bool,
+    /// Seconds without a usable interface before the access point starts
+    pub after_s: u64,
+    /// Seconds the access point stays up before the configured networks are tried again
+    pub retry_s: u64,
+    /// Wi-Fi interface taken over by hostapd while the access point runs
+    pub interface: String,
+    /// Network name; `{client_id}` is replaced with the client id
+    pub ssid: String,
+    /// WPA2 passphrase of 8-63 characters; the network is open when unset
+    pub passphrase: Option<String>,
+    pub channel: u8,
+    /// Address of the agent on the setup network, which uses a /24
+    pub address: Ipv4Addr,
+}
+
+impl Default for ApFallbackConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            after_s: 300,
+            retry_s: 900,
+            interface: "wlan0".to_string(),
+            ssid: "pi-door-{client_id}".to_string(),
+            passphrase: None,
+            channel: 6,
+            address: Ipv4Addr::new(192, 168, 4, 1),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -89,8 +440,12 @@ pub struct WsLocalConfig {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CloudConfig {
-    #[serde(default)]
-    pub url: Option<String>,
+    /// Cloud endpoints in order of preference; a single URL is accepted too
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub url: Vec<String>,
+    /// While on a fallback endpoint, check this often whether the first one is back
+    #[serde(default = "default_primary_probe_s")]
+    pub primary_probe_s: u64,
     #[serde(default)]
     pub spki_pins: Vec<String>,
     pub heartbeat_s: u64,
@@ -98,16 +453,352 @@ pub struct CloudConfig {
     pub backoff_max_s: u64,
     pub queue_max_events: usize,
     pub queue_max_age_days: u32,
+    /// Least severe event forwarded to the cloud
+    #[serde(default)]
+    pub min_severity: Severity,
+    /// Stricter threshold while the uplink is LTE, to save metered data
+    #[serde(default = "default_lte_min_severity")]
+    pub lte_min_severity: Severity,
+    /// Queued events sent before waiting for the cloud to acknowledge them
+    #[serde(default = "default_queue_batch")]
+    pub queue_batch: usize,
+    /// Reconnect if a sent batch is not acknowledged within this time
+    #[serde(default = "default_ack_timeout_s")]
+    pub ack_timeout_s: u64,
+    /// Warn when the local clock is off from the master's by more than this
+    #[serde(default = "default_clock_skew_threshold_s")]
+    pub clock_skew_threshold_s: u64,
+    /// Shift event timestamps by the measured skew while it is beyond the threshold
+    #[serde(default)]
+    pub correct_timestamps: bool,
+    /// Reach the cloud and the master through this proxy
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+    /// Bandwidth savings while the uplink is LTE
+    #[serde(default)]
+    pub metered: MeteredConfig,
+    /// Tie the WebSocket to the interface the network manager selected, and reconnect when it changes
+    #[serde(default = "default_enabled")]
+    pub bind_interface: bool,
+    /// Refuse unsigned commands even before the master's command key is known
+    #[serde(default)]
+    pub require_signed_commands: bool,
+}
+
+/// How the cloud link saves data on a metered (LTE) uplink; events below
+/// `lte_min_severity` are not forwarded at all
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MeteredConfig {
+    pub enabled: bool,
+    /// Heartbeat interval replacing `heartbeat_s`
+    pub heartbeat_s: u64,
+    /// Queued events below warn severity are held and sent together this often
+    pub batch_s: u64,
+}
+
+impl Default for MeteredConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            heartbeat_s: 300,
+            batch_s: 120,
+        }
+    }
+}
+
+/// Proxy for outbound connections to the cloud and the master
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    /// `http://host:port`, or `socks5://host:port` (`socks5h://` to resolve names at the proxy)
+    pub url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+/// Accept a single string where a list is expected
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        None => Vec::new(),
+        Some(OneOrMany::One(url)) => vec![url],
+        Some(OneOrMany::Many(urls)) => urls,
+    })
+}
+
+fn default_primary_probe_s() -> u64 {
+    300
+}
+
+fn default_lte_min_severity() -> Severity {
+    Severity::Warn
+}
+
+fn default_queue_batch() -> usize {
+    50
+}
+
+fn default_ack_timeout_s() -> u64 {
+    30
+}
+
+fn default_clock_skew_threshold_s() -> u64 {
+    30
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GpioConfig {
+    /// GPIO backend to drive the pins with
+    #[serde(default)]
+    pub backend: GpioBackend,
+    /// GPIO character device used by the cdev backend
+    pub chip: PathBuf,
+    /// Directory of pin level files used by the file backend
+    pub file_dir: PathBuf,
     pub reed_in: u8,
     pub reed_active_low: bool,
     pub siren_out: u8,
     pub floodlight_out: u8,
     pub radio433_rx_in: u8,
+    /// Data pin of a 433 MHz transmitter module driving `rf433.outputs`
+    #[serde(default)]
+    pub radio433_tx_out: Option<u8>,
     pub debounce_ms: u64,
+    /// Drive the siren with a PWM tone at this frequency instead of a steady level.
+    /// Hardware PWM is used when `siren_out` is BCM 12, 13, 18 or 19.
+    #[serde(default)]
+    pub siren_pwm_hz: Option<f64>,
+    /// PWM duty cycle for the siren tone (0.0-1.0)
+    pub siren_pwm_duty: f64,
+    /// Strobe light output, flashing from alarm until acknowledged
+    #[serde(default)]
+    pub strobe_out: Option<u8>,
+    /// Status LED output, blink pattern encodes the system state
+    #[serde(default)]
+    pub status_led_out: Option<u8>,
+    /// Piezo buzzer output beeping during entry and exit delays
+    #[serde(default)]
+    pub buzzer_out: Option<u8>,
+    /// Tone for passive piezo buzzers; unset drives an active buzzer on/off
+    #[serde(default)]
+    pub buzzer_hz: Option<f64>,
+    /// Buzzer PWM duty cycle (0.0-1.0), acting as volume
+    pub buzzer_duty: f64,
+    /// Keep the buzzer silent while armed in night mode
+    #[serde(default)]
+    pub buzzer_quiet_at_night: bool,
+    /// Run the wiring self-test (brief output pulses) when the agent starts
+    pub self_test_on_startup: bool,
+    /// PIR motion sensor input (active high), unset if no sensor is fitted
+    #[serde(default)]
+    pub motion_in: Option<u8>,
+    /// Zone the motion sensor belongs to
+    #[serde(default)]
+    pub motion_zone: ZoneType,
+    /// Vibration/glass-break sensor input, unset if no sensor is fitted
+    #[serde(default)]
+    pub vibration_in: Option<u8>,
+    /// Pulses required within the window to report a glass break
+    pub vibration_pulses: u32,
+    /// Sliding window for counting vibration pulses
+    pub vibration_window_ms: u64,
+    /// Wiegand keypad/card reader data lines; configure both as active-low
+    #[serde(default)]
+    pub wiegand_d0_in: Option<u8>,
+    #[serde(default)]
+    pub wiegand_d1_in: Option<u8>,
+    /// Additional named zone inputs (e.g. water leak, freezer door)
+    #[serde(default)]
+    pub zones: Vec<ZoneConfig>,
+    /// Auxiliary relay outputs (gate opener, porch light, ...)
+    #[serde(default)]
+    pub outputs: Vec<OutputConfig>,
+    /// Pulse output that releases the door lock strike
+    #[serde(default)]
+    pub lock_output: Option<String>,
+    /// Pulse output wired to the garage opener's push-button input
+    #[serde(default)]
+    pub garage_output: Option<String>,
+    /// Zone whose input is active while the garage door is open
+    #[serde(default)]
+    pub garage_zone: Option<String>,
+    /// Electrical options keyed by pin name ("reed_in", "siren_out", a zone name, ...)
+    #[serde(default)]
+    pub pin_options: BTreeMap<String, PinOptions>,
+    /// Hard on-time and cooldown limits keyed by output pin name ("siren_out", an output name, ...)
+    #[serde(default)]
+    pub limits: BTreeMap<String, ActuatorLimits>,
+}
+
+impl GpioConfig {
+    /// Electrical options for a named pin, falling back to the built-in defaults.
+    ///
+    /// The reed switch defaults to a pull-up with `reed_active_low` polarity;
+    /// everything else defaults to active-high push-pull without a pull resistor.
+    pub fn options_for(&self, name: &str) -> PinOptions {
+        if let Some(options) = self.pin_options.get(name) {
+            return *options;
+        }
+
+        match name {
+            "reed_in" => PinOptions {
+                pull: Pull::Up,
+                active_low: self.reed_active_low,
+                open_drain: false,
+            },
+            _ => PinOptions::default(),
+        }
+    }
+
+    /// Safety limits for an output; the siren is capped at 15 minutes unless configured
+    pub fn limits_for(&self, name: &str) -> Option<ActuatorLimits> {
+        if let Some(limits) = self.limits.get(name) {
+            return Some(*limits);
+        }
+
+        match name {
+            "siren_out" => Some(ActuatorLimits {
+                max_on_s: Some(900),
+                min_off_s: None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Names and BCM numbers of every input pin the GPIO backend drives
+    pub fn input_pins(&self) -> Vec<(String, u8)> {
+        let mut pins = vec![("reed_in".to_string(), self.reed_in)];
+        if let Some(pin) = self.motion_in {
+            pins.push(("motion_in".to_string(), pin));
+        }
+        if let Some(pin) = self.vibration_in {
+            pins.push(("vibration_in".to_string(), pin));
+        }
+        if let Some(pin) = self.wiegand_d0_in {
+            pins.push(("wiegand_d0_in".to_string(), pin));
+        }
+        if let Some(pin) = self.wiegand_d1_in {
+            pins.push(("wiegand_d1_in".to_string(), pin));
+        }
+        for zone in &self.zones {
+            pins.push((zone.name.clone(), zone.pin));
+        }
+        pins
+    }
+
+    /// Names and BCM numbers of every output pin the GPIO backend drives
+    pub fn output_pins(&self) -> Vec<(String, u8)> {
+        let mut pins = vec![
+            ("siren_out".to_string(), self.siren_out),
+            ("floodlight_out".to_string(), self.floodlight_out),
+        ];
+        if let Some(pin) = self.strobe_out {
+            pins.push(("strobe_out".to_string(), pin));
+        }
+        if let Some(pin) = self.status_led_out {
+            pins.push(("status_led_out".to_string(), pin));
+        }
+        if let Some(pin) = self.buzzer_out {
+            pins.push(("buzzer_out".to_string(), pin));
+        }
+        if let Some(pin) = self.radio433_tx_out {
+            pins.push(("radio433_tx_out".to_string(), pin));
+        }
+        for output in &self.outputs {
+            pins.push((output.name.clone(), output.pin));
+        }
+        pins
+    }
+}
+
+/// GPIO backend selection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GpioBackend {
+    /// Best backend compiled in: rppal, then cdev, then mock
+    #[default]
+    Auto,
+    /// In-memory simulation
+    Mock,
+    /// rppal register access (Raspberry Pi 1-4), requires the `real-gpio` feature
+    Rppal,
+    /// Character device `/dev/gpiochipN` (Raspberry Pi 5 and others), requires the `cdev-gpio` feature
+    Cdev,
+    /// One level file per pin under `file_dir`, for development hosts
+    File,
+}
+
+/// A named auxiliary relay output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputConfig {
+    pub name: String,
+    pub pin: u8,
+    /// Pulse mode: each activation closes the relay for this long, then releases it
+    #[serde(default)]
+    pub pulse_ms: Option<u64>,
+}
+
+/// Internal pull resistor for an input pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pull {
+    Up,
+    Down,
+    #[default]
+    None,
+}
+
+/// Duty-cycle protection for a single output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ActuatorLimits {
+    /// Longest the output may stay on before it is forced off
+    #[serde(default)]
+    pub max_on_s: Option<u64>,
+    /// Shortest time the output must rest before switching on again
+    #[serde(default)]
+    pub min_off_s: Option<u64>,
+}
+
+/// Electrical behaviour of a single pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct PinOptions {
+    /// Pull resistor (inputs only)
+    #[serde(default)]
+    pub pull: Pull,
+    /// Logical "on"/"active" corresponds to a low level
+    #[serde(default)]
+    pub active_low: bool,
+    /// Drive low for active and float (high impedance) otherwise (outputs only)
+    #[serde(default)]
+    pub open_drain: bool,
+}
+
+impl PinOptions {
+    /// Translate an electrical level into the logical active state
+    pub fn is_active(&self, level_high: bool) -> bool {
+        level_high != self.active_low
+    }
+
+    /// Translate a logical active state into the electrical level to drive
+    pub fn level_for(&self, active: bool) -> bool {
+        active != self.active_low
+    }
+}
+
+/// A named input wired to its own GPIO pin
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneConfig {
+    pub name: String,
+    pub pin: u8,
+    #[serde(rename = "type")]
+    pub zone_type: ZoneType,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -116,29 +807,1067 @@ pub struct TimerConfig {
     pub entry_delay_s: u64,
     pub auto_rearm_s: u64,
     pub siren_max_s: u64,
+    /// Siren cut-off per alarm cause, overriding `siren_max_s`
+    #[serde(default)]
+    pub siren_max_by_kind: BTreeMap<AlarmKind, u64>,
+    /// How long motion while armed keeps the floodlight on (0 = disabled)
+    #[serde(default = "default_motion_floodlight_s")]
+    pub motion_floodlight_s: u64,
+    /// Courtesy light: how long motion or the door at night lights the floodlight
+    /// while disarmed or armed-stay (0 = disabled)
+    #[serde(default)]
+    pub courtesy_light_s: u64,
+    /// How long a manual floodlight "off" suppresses automatic lighting
+    #[serde(default = "default_courtesy_override_s")]
+    pub courtesy_override_s: u64,
+    /// Local time window counted as night, "HH:MM"; equal times mean all day
+    #[serde(default = "default_night_start")]
+    pub night_start: String,
+    #[serde(default = "default_night_end")]
+    pub night_end: String,
+}
+
+fn default_motion_floodlight_s() -> u64 {
+    60
+}
+
+fn default_courtesy_override_s() -> u64 {
+    900
+}
+
+fn default_night_start() -> String {
+    "18:00".to_string()
+}
+
+fn default_night_end() -> String {
+    "07:00".to_string()
+}
+
+impl TimerConfig {
+    /// Siren cut-off for an alarm cause
+    pub fn siren_max_for(&self, kind: AlarmKind) -> u64 {
+        self.siren_max_by_kind.get(&kind).copied().unwrap_or(self.siren_max_s)
+    }
+
+    /// Parsed night window start and end
+    pub fn night_window(&self) -> anyhow::Result<(NaiveTime, NaiveTime)> {
+        Ok((parse_time(&self.night_start)?, parse_time(&self.night_end)?))
+    }
+
+    /// Whether a local time falls in the night window
+    pub fn is_night(&self, now: NaiveTime) -> bool {
+        self.night_window().is_ok_and(|(start, end)| in_window(start, end, now))
+    }
+}
+
+/// A local time of day written "HH:MM"
+fn parse_time(s: &str) -> anyhow::Result<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M").with_context(|| format!("Invalid time {:?}, expected HH:MM", s))
+}
+
+/// Whether a time falls in a window that may wrap past midnight; equal ends mean all day
+pub fn in_window(start: NaiveTime, end: NaiveTime, now: NaiveTime) -> bool {
+    if start <= end {
+        start == end || (start <= now && now < end)
+    } else {
+        now >= start || now < end
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BleConfig {
     pub enabled: bool,
     pub pairing_window_s: u64,
+    /// Warn when a bonded device has not been seen for this many days (0 = never)
+    pub missing_after_days: u64,
+    #[serde(default)]
+    pub presence: PresenceConfig,
 }
 
+/// Occupancy from registered iBeacon/Eddystone beacons seen by the BLE scanner
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Rf433Config {
-    pub enabled: bool,
-    pub allow_disarm: bool,
-    pub debounce_ms: u64,
-    #[serde(default)]
-    pub mappings: Vec<Rf433Mapping>,
+#[serde(default)]
+pub struct PresenceConfig {
+    pub beacons: Vec<BeaconConfig>,
+    /// Seconds a present beacon may go unseen before it counts as away
+    pub away_after_s: u64,
+    /// Weakest signal (dBm) that counts as arriving; none accepts any sighting
+    pub min_rssi: Option<i16>,
+    /// How much weaker than `min_rssi` a present beacon may get and still count as seen
+    pub rssi_hysteresis_db: i16,
+    /// Arm away when the last present beacon leaves while disarmed
+    pub auto_arm: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            beacons: vec![],
+            away_after_s: 300,
+            min_rssi: None,
+            rssi_hysteresis_db: 6,
+            auto_arm: false,
+        }
+    }
+}
+
+/// A beacon whose presence counts as someone being home
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BeaconConfig {
+    pub name: String,
+    /// `ibeacon:<uuid>[:<major>[:<minor>]]` or `eddystone:<namespace>[:<instance>]`;
+    /// leaving out the trailing parts matches every beacon sharing the rest
+    pub id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rf433Config {
+    pub enabled: bool,
+    /// Let fixed-code remotes disarm; rolling-code remotes always may
+    pub allow_disarm: bool,
+    /// Quiet time that ends a press; frames repeated until then are one press
+    pub debounce_ms: u64,
+    /// Fixed-code remotes and the commands their codes run
+    #[serde(default)]
+    pub mappings: Vec<Rf433Mapping>,
+    /// Counter steps a rolling-code remote may skip (presses out of range) before it must resync
+    #[serde(default = "default_counter_window")]
+    pub counter_window: u16,
+    /// Rolling-code (KeeLoq) remotes; their keys live in the secret store
+    #[serde(default)]
+    pub remotes: Vec<RollingRemoteConfig>,
+    /// Seconds the receiver must stay saturated before `rf_jamming` is raised (0 disables)
+    #[serde(default = "default_jamming_s")]
+    pub jamming_s: u64,
+    /// Share of the time the data line is high that counts as saturated
+    #[serde(default = "default_jamming_duty")]
+    pub jamming_duty: f64,
+    /// Presses of one fixed code within `replay_window_s` that look like a replay (0 disables)
+    #[serde(default = "default_replay_presses")]
+    pub replay_presses: usize,
+    #[serde(default = "default_replay_window_s")]
+    pub replay_window_s: u64,
+    /// Raise a tamper alarm on jamming or a suspected replay while armed
+    #[serde(default)]
+    pub attack_alarm: bool,
+    /// Wireless sirens, chimes and sockets switched through `gpio.radio433_tx_out`
+    #[serde(default)]
+    pub outputs: Vec<RfOutputConfig>,
+    /// Fixed codes of panic buttons, which sound a panic alarm armed or not
+    #[serde(default)]
+    pub panic_codes: Vec<String>,
+    /// Only raise the panic alarm on a second press within `panic_window_ms`
+    #[serde(default)]
+    pub panic_double_press: bool,
+    #[serde(default = "default_panic_window_ms")]
+    pub panic_window_ms: u64,
+    /// Fixed-code door contacts and PIRs reported as zones, usually bound through learn mode
+    #[serde(default)]
+    pub sensors: Vec<RfSensorConfig>,
+}
+
+/// A fixed-code wireless sensor and the zone it reports as
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RfSensorConfig {
+    pub zone: String,
+    /// Human-readable name, e.g. `Back door`
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(rename = "type")]
+    pub zone_type: ZoneType,
+    /// Codes the sensor sends when triggered; several sensors may share a zone
+    pub codes: Vec<String>,
+    /// Seconds the zone stays triggered after the last code
+    #[serde(default = "default_restore_s")]
+    pub restore_s: u64,
+}
+
+fn default_panic_window_ms() -> u64 {
+    3000
+}
+
+fn default_counter_window() -> u16 {
+    16
+}
+
+fn default_jamming_s() -> u64 {
+    5
+}
+
+fn default_jamming_duty() -> f64 {
+    0.8
+}
+
+fn default_replay_presses() -> usize {
+    5
+}
+
+fn default_replay_window_s() -> u64 {
+    60
+}
+
+/// A 433 MHz receiver switched like an auxiliary output, by sending its codes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RfOutputConfig {
+    pub name: String,
+    /// 24-bit EV1527/PT2262 code sent to switch on, `0x...` or decimal
+    pub on_code: String,
+    /// Code sent to switch off; chimes and other one-shot receivers have none
+    #[serde(default)]
+    pub off_code: Option<String>,
+    /// Pulse length in microseconds the receiver was paired with
+    #[serde(default = "default_rf_pulse_us")]
+    pub pulse_us: u64,
+    /// Times each code is sent; receivers expect a few repeats
+    #[serde(default = "default_rf_repeats")]
+    pub repeats: usize,
+    /// Pulse mode: each activation switches off again after this long
+    #[serde(default)]
+    pub pulse_ms: Option<u64>,
+    /// Switch on and off with the alarm siren
+    #[serde(default)]
+    pub follow_siren: bool,
+}
+
+fn default_rf_pulse_us() -> u64 {
+    350
+}
+
+fn default_rf_repeats() -> usize {
+    8
+}
+
+/// Wireless sensors decoded by an external `rtl_433` with an SDR dongle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rtl433Config {
+    pub enabled: bool,
+    pub source: Rtl433Source,
+    /// Command line for the process source; it must print one JSON object per line
+    pub command: Vec<String>,
+    /// Broker for the mqtt source, as passed to `rtl_433 -F mqtt://host:port`
+    pub mqtt_host: String,
+    pub mqtt_port: u16,
+    /// Topic filter rtl_433 publishes decoded events on
+    pub mqtt_topic: String,
+    pub mqtt_username: Option<String>,
+    pub mqtt_password: Option<String>,
+    /// Devices reported as zones; every other decoded device is ignored
+    pub sensors: Vec<Rtl433SensorConfig>,
+}
+
+impl Default for Rtl433Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            source: Rtl433Source::Process,
+            command: vec!["rtl_433".to_string(), "-F".to_string(), "json".to_string()],
+            mqtt_host: "localhost".to_string(),
+            mqtt_port: 1883,
+            mqtt_topic: "rtl_433/+/events".to_string(),
+            mqtt_username: None,
+            mqtt_password: None,
+            sensors: vec![],
+        }
+    }
+}
+
+/// Where decoded rtl_433 messages come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rtl433Source {
+    /// Run `rtl433.command` and read its output
+    #[default]
+    Process,
+    /// Subscribe to an rtl_433 already publishing to a broker
+    Mqtt,
+}
+
+/// A wireless sensor, identified by its decoded `model` and `id`, and the zone it reports as
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rtl433SensorConfig {
+    pub zone: String,
+    #[serde(rename = "type")]
+    pub zone_type: ZoneType,
+    /// Decoder model, e.g. `Honeywell-Security`
+    pub model: String,
+    /// Device id as decoded, numeric or not
+    pub id: String,
+    /// Message field holding the sensor's state (e.g. `reed_open`); unset for
+    /// sensors that only transmit when triggered
+    #[serde(default)]
+    pub field: Option<String>,
+    /// Seconds a sensor without `field` stays triggered after its last message
+    #[serde(default = "default_restore_s")]
+    pub restore_s: u64,
+    /// Seconds of silence after which the sensor counts as lost; set for sensors
+    /// sending periodic supervision frames, with room for a few missed ones
+    #[serde(default)]
+    pub supervision_s: Option<u64>,
+}
+
+pub(crate) fn default_restore_s() -> u64 {
+    10
+}
+
+/// A KeeLoq remote and what its buttons do
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RollingRemoteConfig {
+    pub name: String,
+    /// 28-bit serial number the remote sends in clear, `0x...` or decimal
+    pub serial: String,
+    #[serde(default)]
+    pub buttons: Vec<RollingButtonConfig>,
+}
+
+/// Command sent by one button of a rolling-code remote
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RollingButtonConfig {
+    /// Button bits (1-15) as sent by the remote
+    pub button: u8,
+    pub action: String,
+    #[serde(default)]
+    pub args: serde_json::Value,
+}
+
+/// SSD1306 OLED status display on an I2C bus
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplayConfig {
+    pub enabled: bool,
+    /// I2C bus device node
+    pub i2c_bus: PathBuf,
+    /// 7-bit display address, 0x3C or 0x3D on most modules
+    pub address: u8,
+    /// Panel is 128x32 instead of 128x64
+    pub short_panel: bool,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            i2c_bus: PathBuf::from("/dev/i2c-1"),
+            address: 0x3C,
+            short_panel: false,
+        }
+    }
+}
+
+/// DS18B20 temperature sensors on the kernel 1-Wire bus
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OneWireConfig {
+    pub enabled: bool,
+    /// Where the w1 driver lists slave devices
+    pub devices_dir: PathBuf,
+    /// Seconds between readings
+    pub interval_s: u64,
+    /// Degrees a reading must move back inside a limit before the alert clears
+    pub hysteresis_c: f64,
+    pub sensors: Vec<TemperatureSensorConfig>,
+}
+
+impl Default for OneWireConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            devices_dir: PathBuf::from("/sys/bus/w1/devices"),
+            interval_s: 60,
+            hysteresis_c: 0.5,
+            sensors: vec![],
+        }
+    }
+}
+
+/// A named DS18B20 and its alert thresholds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemperatureSensorConfig {
+    pub name: String,
+    /// 1-Wire device id, e.g. `28-0316a2791cff`
+    pub id: String,
+    #[serde(default)]
+    pub high_c: Option<f64>,
+    #[serde(default)]
+    pub low_c: Option<f64>,
+}
+
+/// SoC temperature and firmware throttling, read from sysfs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThermalConfig {
+    pub enabled: bool,
+    /// Seconds between readings
+    pub interval_s: u64,
+    /// Thermal zone of the SoC, in millidegrees Celsius
+    pub temp_path: PathBuf,
+    /// Firmware throttling flags in hex; `vcgencmd get_throttled` is asked when it is missing
+    pub throttled_path: PathBuf,
+    /// Temperature counted as overheating; the firmware starts throttling at 80
+    pub warn_c: f64,
+    /// How long the SoC must stay at `warn_c` or above before `soc_overheating` is raised
+    pub sustained_s: u64,
+    /// Degrees below `warn_c` the SoC must cool to before `soc_cooled` is raised
+    pub hysteresis_c: f64,
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_s: 30,
+            temp_path: PathBuf::from("/sys/class/thermal/thermal_zone0/temp"),
+            throttled_path: PathBuf::from("/sys/devices/platform/soc/soc:firmware/get_throttled"),
+            warn_c: 75.0,
+            sustained_s: 300,
+            hysteresis_c: 5.0,
+        }
+    }
+}
+
+/// Free space on the data partition and size of the offline queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub enabled: bool,
+    /// Seconds between checks
+    pub interval_s: u64,
+    /// Free space below which `disk_space_low` is raised
+    pub warn_free_mb: u64,
+    /// Free space below which the offline queue is pruned down to `critical_keep_events`
+    pub critical_free_mb: u64,
+    /// Offline queue size on disk above which `disk_space_low` is raised
+    pub queue_warn_mb: u64,
+    /// Newest queued events kept while space is critically low
+    pub critical_keep_events: usize,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_s: 60,
+            warn_free_mb: 256,
+            critical_free_mb: 64,
+            queue_warn_mb: 128,
+            critical_keep_events: 1000,
+        }
+    }
+}
+
+/// Resident memory of the agent, watched for leaks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemoryConfig {
+    pub enabled: bool,
+    /// Seconds between readings
+    pub interval_s: u64,
+    /// Hours over which the lowest readings are compared to spot a leak
+    pub growth_window_h: u64,
+    /// Rise of the lowest reading over the window that raises `memory_growing`
+    pub growth_warn_mb: u64,
+    /// Resident memory above which the agent restarts itself; 0 never restarts
+    pub ceiling_mb: u64,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_s: 60,
+            growth_window_h: 24,
+            growth_warn_mb: 32,
+            ceiling_mb: 0,
+        }
+    }
+}
+
+/// Unattended wiring self-test and siren chirp on a schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SelfTestConfig {
+    pub enabled: bool,
+    /// Days between scheduled runs
+    pub interval_days: u64,
+    /// Local time window a run may start in, "HH:MM"; never inside the night window
+    pub window_start: String,
+    pub window_end: String,
+    /// Chirp the siren after the wiring test so a dead siren is noticed
+    pub chirp: bool,
+    /// Seconds between checks whether a run is due
+    pub check_s: u64,
+    /// Results kept in the local self-test log
+    pub keep_results: usize,
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_days: 7,
+            window_start: "10:00".to_string(),
+            window_end: "16:00".to_string(),
+            chirp: true,
+            check_s: 600,
+            keep_results: 20,
+        }
+    }
+}
+
+impl SelfTestConfig {
+    /// Parsed run window start and end
+    pub fn window(&self) -> anyhow::Result<(NaiveTime, NaiveTime)> {
+        Ok((parse_time(&self.window_start)?, parse_time(&self.window_end)?))
+    }
+}
+
+/// Health reports sent to the master, with `system.master_url` set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthReportConfig {
+    pub enabled: bool,
+    /// Hours between scheduled reports
+    pub interval_h: u64,
+    /// Seconds between checks for a due report or a changed subsystem
+    pub check_s: u64,
+    /// Least time between two reports sent for changes
+    pub min_gap_s: u64,
+}
+
+impl Default for HealthReportConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_h: 24,
+            check_s: 60,
+            min_gap_s: 300,
+        }
+    }
+}
+
+/// MCP3008 SPI ADC for analog sensors and supply voltage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdcConfig {
+    pub enabled: bool,
+    /// SPI device node the ADC's chip select is wired to
+    pub spi_dev: PathBuf,
+    pub spi_hz: u32,
+    /// Reference voltage on the VREF pin
+    pub vref: f64,
+    /// Seconds between readings
+    pub interval_s: u64,
+    pub channels: Vec<AnalogChannelConfig>,
+    /// Backup battery monitoring on one of the channels
+    pub battery: Option<BatteryConfig>,
+}
+
+impl Default for AdcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            spi_dev: PathBuf::from("/dev/spidev0.0"),
+            spi_hz: 1_000_000,
+            vref: 3.3,
+            interval_s: 10,
+            channels: vec![],
+            battery: None,
+        }
+    }
+}
+
+/// Door keypad and card reader authentication
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessConfig {
+    /// Enrolled credentials, `<data_dir>/credentials.json` when unset
+    pub credentials_path: Option<PathBuf>,
+    /// Seconds of keypad inactivity before a partly entered PIN is discarded
+    pub keypad_timeout_s: u64,
+    /// Consecutive failed attempts, wherever made, before credentials are locked out
+    pub max_failures: u32,
+    /// Seconds credentials are ignored after too many failures
+    pub lockout_s: u64,
+    /// Disarming over BLE and the HTTP API needs an enrolled user's PIN
+    pub require_pin_to_disarm: bool,
+}
+
+impl Default for AccessConfig {
+    fn default() -> Self {
+        Self {
+            credentials_path: None,
+            keypad_timeout_s: 10,
+            max_failures: 5,
+            lockout_s: 60,
+            require_pin_to_disarm: false,
+        }
+    }
+}
+
+/// JSON log files next to the console output, for when journald keeps little
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Also write logs to files
+    pub file: bool,
+    /// Log file directory, `<data_dir>/logs` when unset
+    pub dir: Option<PathBuf>,
+    /// Start a new file once the current one reaches this size
+    pub max_file_mb: u64,
+    /// Start a new file once the current one is this old (0 = by size only)
+    pub rotate_hours: u64,
+    /// Rotated files kept, oldest deleted first
+    pub max_files: usize,
+    /// Rotated files older than this are deleted (0 = kept up to `max_files`)
+    pub max_age_days: u64,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            file: false,
+            dir: None,
+            max_file_mb: 10,
+            rotate_hours: 24,
+            max_files: 10,
+            max_age_days: 30,
+        }
+    }
+}
+
+/// Local history of every event, independent of cloud delivery
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JournalConfig {
+    pub enabled: bool,
+    /// Journal database directory, `<data_dir>/journal` when unset
+    pub path: Option<PathBuf>,
+    pub max_events: usize,
+    pub max_age_days: u32,
+    /// Least severe event recorded; `debug` also keeps telemetry
+    pub min_severity: Severity,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+            max_events: 100_000,
+            max_age_days: 30,
+            min_severity: Severity::Info,
+        }
+    }
+}
+
+/// Usage history in SQLite for the `/v1/history` aggregates; needs the `history` feature
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    /// Database file, `<data_dir>/history.db` when unset
+    pub path: Option<PathBuf>,
+    /// Records older than this are deleted (0 = kept forever)
+    pub max_age_days: u32,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            max_age_days: 365,
+        }
+    }
+}
+
+/// Record of every control request, kept apart from and longer than the journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// Audit database directory, `<data_dir>/audit` when unset
+    pub path: Option<PathBuf>,
+    pub max_entries: usize,
+    pub max_age_days: u32,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+            max_entries: 100_000,
+            max_age_days: 365,
+        }
+    }
+}
+
+/// Refusing stale and repeated WebSocket and cloud commands
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplayConfig {
+    pub enabled: bool,
+    /// How long the nonce of a command without `expires_at` is remembered
+    pub window_s: u64,
+    /// Furthest ahead an `expires_at` may be, so no command stays valid for long
+    pub max_ttl_s: u64,
+    /// Clock difference allowed on `expires_at`
+    pub skew_s: u64,
+    /// Refuse commands without `expires_at`, and WebSocket commands without a `nonce`
+    pub require_expiry: bool,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_s: 600,
+            max_ttl_s: 900,
+            skew_s: 30,
+            require_expiry: false,
+        }
+    }
+}
+
+/// Scheduled rotation of the local API token and the token issued by the master
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RotationConfig {
+    pub enabled: bool,
+    /// Days between new local API tokens (0 = never)
+    pub api_token_days: u64,
+    /// Days between new master tokens (0 = never)
+    pub master_token_days: u64,
+    /// How long a replaced local API token is still accepted
+    pub grace_s: u64,
+    /// How often the scheduler checks whether a token is due
+    pub check_s: u64,
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            api_token_days: 30,
+            master_token_days: 30,
+            grace_s: 3600,
+            check_s: 3600,
+        }
+    }
+}
+
+/// Event bus queue sizes and what happens when the state machine falls behind
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventBusConfig {
+    /// Events waiting for the state machine
+    pub capacity: usize,
+    /// Envelopes a slow subscriber may fall behind by before it misses some
+    pub broadcast_capacity: usize,
+    pub overflow: OverflowPolicy,
+    /// How often bus metrics are logged (0 = never)
+    pub metrics_log_s: u64,
+    /// Queue wait beyond which the state machine counts as lagging (0 = never)
+    pub lag_warn_ms: u64,
+    /// How long the wait must stay over `lag_warn_ms` before `/v1/health` reports it
+    pub lag_sustained_s: u64,
+}
+
+impl Default for EventBusConfig {
+    fn default() -> Self {
+        Self {
+            capacity: crate::events::DEFAULT_BUS_CAPACITY,
+            broadcast_capacity: crate::events::DEFAULT_BROADCAST_CAPACITY,
+            overflow: OverflowPolicy::default(),
+            metrics_log_s: 60,
+            lag_warn_ms: 500,
+            lag_sustained_s: 30,
+        }
+    }
+}
+
+/// Retries for events the state machine fails to process, and where the rest go
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeadLetterConfig {
+    pub enabled: bool,
+    /// Dead-letter database directory, `<data_dir>/dead_letter` when unset
+    pub path: Option<PathBuf>,
+    /// Attempts per event, including the first
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for each one after
+    pub retry_backoff_ms: u64,
+    /// Oldest entries are evicted beyond this
+    pub max_entries: usize,
+}
+
+impl Default for DeadLetterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+            max_attempts: 3,
+            retry_backoff_ms: 100,
+            max_entries: 1000,
+        }
+    }
+}
+
+/// Signed over-the-air updates of the agent itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpdateConfig {
+    pub enabled: bool,
+    /// How long a new version must run before it is kept
+    pub confirm_after_s: u64,
+    /// Starts of an unconfirmed version before rolling back to the previous one
+    pub max_boot_attempts: u32,
+    /// Largest download accepted
+    pub max_size_mb: u64,
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            confirm_after_s: 120,
+            max_boot_attempts: 3,
+            max_size_mb: 64,
+        }
+    }
+}
+
+/// Who the agent runs as once GPIO and its listening socket are open
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrivilegesConfig {
+    /// Drop root after startup; health reports degraded while still root
+    pub drop: bool,
+    pub user: String,
+    /// Primary group; the user's own when unset
+    pub group: Option<String>,
+    /// Supplementary groups kept for device access, e.g. `gpio` for /dev/gpiochip*
+    pub groups: Vec<String>,
+}
+
+impl Default for PrivilegesConfig {
+    fn default() -> Self {
+        Self {
+            drop: false,
+            user: "pi-client".to_string(),
+            group: None,
+            groups: ["gpio", "i2c", "spi", "bluetooth", "netdev", "tss"].map(String::from).to_vec(),
+        }
+    }
+}
+
+/// Kernel restrictions on what the agent can reach once it is running
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    pub enabled: bool,
+    /// Landlock: files limited to the agent's own paths, devices and system directories
+    pub landlock: bool,
+    /// Seccomp: refuse syscalls the agent never makes, such as mount or ptrace
+    pub seccomp: bool,
+    /// Further paths the agent may read, e.g. for scripts it runs
+    pub read_paths: Vec<PathBuf>,
+    /// Further paths the agent may write
+    pub write_paths: Vec<PathBuf>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            landlock: true,
+            seccomp: true,
+            read_paths: vec![],
+            write_paths: vec![],
+        }
+    }
+}
+
+/// One event sink and how much it may fall behind
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SinkConfig {
+    #[serde(flatten)]
+    pub kind: SinkKind,
+    /// Least severe event delivered to the sink
+    #[serde(default)]
+    pub min_severity: Severity,
+    /// Envelopes buffered for the sink before new ones are dropped
+    #[serde(default = "default_sink_buffer")]
+    pub buffer: usize,
+}
+
+fn default_sink_buffer() -> usize {
+    256
+}
+
+/// Built-in sink types
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkKind {
+    /// Append NDJSON to a local file
+    File { path: PathBuf },
+    /// POST each envelope as JSON
+    Webhook {
+        url: String,
+        /// Sent as a bearer token when set
+        #[serde(default)]
+        token: Option<String>,
+    },
+    /// Publish each envelope at QoS 0 to an MQTT 3.1.1 broker
+    Mqtt {
+        host: String,
+        #[serde(default = "default_mqtt_port")]
+        port: u16,
+        /// `{client_id}` and `{type}` are filled in per event
+        #[serde(default = "default_mqtt_topic")]
+        topic: String,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+    },
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_topic() -> String {
+    "pi-door/{client_id}/events/{type}".to_string()
+}
+
+/// HMAC signing of event envelopes with the device key
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SigningConfig {
+    pub enabled: bool,
+    /// Secret store holding the device key, `<data_dir>/secrets.json` when unset
+    pub secrets_path: Option<PathBuf>,
+    /// Where the device key is kept
+    pub key_storage: KeyStorage,
+    pub tpm: TpmConfig,
+    pub secure_element: SecureElementConfig,
+}
+
+/// Home of the device key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStorage {
+    /// In the secrets file
+    #[default]
+    File,
+    /// Persistent HMAC key in a TPM 2.0
+    Tpm,
+    /// Secret slot of an ATECC608 on I2C, requires the `secure-element` feature
+    Atecc608,
+}
+
+/// TPM holding the device key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TpmConfig {
+    /// Kernel resource manager device
+    pub device: PathBuf,
+    /// Persistent handle the key is stored at, 0x81000000-0x81ffffff
+    pub handle: u32,
+}
+
+impl Default for TpmConfig {
+    fn default() -> Self {
+        Self {
+            device: PathBuf::from("/dev/tpmrm0"),
+            handle: 0x8101_0001,
+        }
+    }
+}
+
+/// ATECC608 holding the device key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecureElementConfig {
+    pub bus: PathBuf,
+    pub address: u16,
+    /// Data slot configured as a secret that allows clear writes
+    pub slot: u8,
+}
+
+impl Default for SecureElementConfig {
+    fn default() -> Self {
+        Self {
+            bus: PathBuf::from("/dev/i2c-1"),
+            address: 0x60,
+            slot: 4,
+        }
+    }
+}
+
+/// A named ADC input
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnalogChannelConfig {
+    pub name: String,
+    /// MCP3008 input 0-7
+    pub channel: u8,
+    /// Multiplier from pin voltage to reported value, e.g. a voltage divider ratio
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+/// Supply voltage thresholds for battery backup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatteryConfig {
+    /// Name of the ADC channel measuring the supply
+    pub channel: String,
+    /// Supply below this means mains power is gone and the battery is in use
+    pub on_battery_below_v: f64,
+    /// Supply below this means the battery is nearly flat
+    pub low_below_v: f64,
+    /// Volts the supply must recover past a threshold before it clears
+    #[serde(default = "default_hysteresis_v")]
+    pub hysteresis_v: f64,
+}
+
+fn default_hysteresis_v() -> f64 {
+    0.2
+}
+
+/// A fixed-code remote: its codes, what it may do, and whether it is honoured at all
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Rf433Mapping {
+    /// Remote identity recorded with every command it sends
+    pub name: String,
+    /// Unset to ignore a lost or stolen remote without forgetting its codes
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Commands the remote may run; empty allows all (disarm still needs `rf433.allow_disarm`)
+    #[serde(default)]
+    pub allowed_actions: Vec<String>,
+    pub codes: Vec<Rf433Code>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Command run when a remote sends one of its codes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rf433Code {
     pub code: String,
     pub action: String,
     #[serde(default)]
     pub args: serde_json::Value,
+    /// Quiet time that ends a press of this code, overriding `rf433.debounce_ms`
+    #[serde(default)]
+    pub debounce_ms: Option<u64>,
 }
 
 impl Default for NetworkConfig {
@@ -146,6 +1875,16 @@ impl Default for NetworkConfig {
         Self {
             prefer: vec!["eth0".to_string(), "wlan0".to_string()],
             enable_lte: false,
+            ap_fallback: ApFallbackConfig::default(),
+            backend: NetworkBackend::default(),
+            wifi_interface: default_wifi_interface(),
+            wpa_supplicant_conf: default_wpa_supplicant_conf(),
+            dhcpcd_conf: default_dhcpcd_conf(),
+            rollback_s: default_network_rollback_s(),
+            report_s: default_network_report_s(),
+            link_quality: LinkQualityConfig::default(),
+            failover: FailoverConfig::default(),
+            captive_portal: CaptivePortalConfig::default(),
         }
     }
 }
@@ -159,6 +1898,9 @@ impl AppConfig {
                 data_dir: std::env::temp_dir().join("pi-door-test"),
                 log_level: "debug".to_string(),
                 api_key: None,
+                master_url: None,
+                developer_mode: false,
+                config_versions: 5,
             },
             network: NetworkConfig::default(),
             http: HttpConfig {
@@ -166,38 +1908,119 @@ impl AppConfig {
             },
             ws_local: WsLocalConfig { enabled: true },
             cloud: CloudConfig {
-                url: None,
+                url: vec![],
+                primary_probe_s: 300,
                 spki_pins: vec![],
                 heartbeat_s: 20,
                 backoff_min_s: 1,
                 backoff_max_s: 60,
                 queue_max_events: 10000,
                 queue_max_age_days: 7,
+                min_severity: Severity::Info,
+                lte_min_severity: Severity::Warn,
+                queue_batch: 50,
+                ack_timeout_s: 30,
+                clock_skew_threshold_s: 30,
+                correct_timestamps: false,
+                proxy: None,
+                metered: MeteredConfig::default(),
+                bind_interface: true,
+                require_signed_commands: false,
             },
             gpio: GpioConfig {
+                backend: GpioBackend::Mock,
+                chip: PathBuf::from("/dev/gpiochip0"),
+                file_dir: std::env::temp_dir().join("pi-door-gpio"),
                 reed_in: 17,
                 reed_active_low: true,
                 siren_out: 27,
                 floodlight_out: 22,
                 radio433_rx_in: 23,
+                radio433_tx_out: None,
                 debounce_ms: 50,
+                siren_pwm_hz: None,
+                siren_pwm_duty: 0.5,
+                strobe_out: None,
+                status_led_out: None,
+                buzzer_out: None,
+                buzzer_hz: None,
+                buzzer_duty: 0.5,
+                buzzer_quiet_at_night: false,
+                self_test_on_startup: false,
+                motion_in: None,
+                motion_zone: ZoneType::Interior,
+                vibration_in: None,
+                vibration_pulses: 3,
+                vibration_window_ms: 2000,
+                wiegand_d0_in: None,
+                wiegand_d1_in: None,
+                zones: vec![],
+                outputs: vec![],
+                lock_output: None,
+                garage_output: None,
+                garage_zone: None,
+                pin_options: BTreeMap::new(),
+                limits: BTreeMap::new(),
             },
             timers: TimerConfig {
                 exit_delay_s: 30,
                 entry_delay_s: 30,
                 auto_rearm_s: 120,
                 siren_max_s: 120,
+                siren_max_by_kind: BTreeMap::new(),
+                motion_floodlight_s: 60,
+                courtesy_light_s: 0,
+                courtesy_override_s: 900,
+                night_start: "18:00".to_string(),
+                night_end: "07:00".to_string(),
             },
             ble: BleConfig {
                 enabled: true,
                 pairing_window_s: 120,
+                missing_after_days: 7,
+                presence: PresenceConfig::default(),
             },
             rf433: Rf433Config {
                 enabled: true,
                 allow_disarm: false,
                 debounce_ms: 500,
                 mappings: vec![],
+                counter_window: 16,
+                remotes: vec![],
+                jamming_s: 5,
+                jamming_duty: 0.8,
+                replay_presses: 5,
+                replay_window_s: 60,
+                attack_alarm: false,
+                outputs: vec![],
+                panic_codes: vec![],
+                panic_double_press: false,
+                panic_window_ms: 3000,
+                sensors: vec![],
             },
+            rtl433: Rtl433Config::default(),
+            display: DisplayConfig::default(),
+            onewire: OneWireConfig::default(),
+            adc: AdcConfig::default(),
+            thermal: ThermalConfig::default(),
+            storage: StorageConfig::default(),
+            memory: MemoryConfig::default(),
+            self_test: SelfTestConfig::default(),
+            health_report: HealthReportConfig::default(),
+            access: AccessConfig::default(),
+            logging: LoggingConfig::default(),
+            journal: JournalConfig::default(),
+            history: HistoryConfig::default(),
+            audit: AuditConfig::default(),
+            replay: ReplayConfig::default(),
+            rotation: RotationConfig::default(),
+            event_bus: EventBusConfig::default(),
+            signing: SigningConfig::default(),
+            dead_letter: DeadLetterConfig::default(),
+            update: UpdateConfig::default(),
+            privileges: PrivilegesConfig::default(),
+            sandbox: SandboxConfig::default(),
+            sinks: vec![],
         }
     }
 }
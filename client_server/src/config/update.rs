@@ -0,0 +1,501 @@
+//! Applying configuration updates at runtime
+//!
+//! An update is a partial document merged over the running configuration:
+//! objects merge key by key, anything else replaces what was there. The
+//! result must pass [`AppConfig::validate`] before it is used, whether it
+//! came through `PUT /v1/config` or was pulled from the master.
+//!
+//! The configuration file belongs to root. What changes at runtime is saved
+//! to [`AppConfig::override_path`] under the data directory, holding only the
+//! settings that differ from the file, and layered over it when loading.
+//! [`PROTECTED_SETTINGS`] can neither be changed at runtime nor taken from
+//! that layer, so an agent that was taken over cannot lift its own
+//! restrictions.
+//!
+//! Writes go to a synced temporary file that then replaces the saved one
+//! atomically, so a power cut leaves either the old or the new file whole. The
+//! replaced file is kept as `config.override.toml.1`, earlier ones as `.2` and
+//! up to `system.config_versions`; [`ConfigStore::rollback`] puts `.1` back.
+//!
+//! Writers take turns, and readers only wait for the new configuration to be
+//! swapped in, never for the disk. The writes block on syncing, so async code
+//! runs them through [`ConfigStore::run_blocking`].
+
+use super::{AppConfig, TimerConfig};
+use anyhow::{bail, Context, Result};
+use parking_lot::{Mutex, RwLock};
+use serde_json::Value;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Sections that take effect without restarting the agent
+pub const HOT_RELOADABLE: &[&str] = &["timers"];
+
+/// Settings only the configuration file sets: they decide what the agent may do and where it writes
+pub const PROTECTED_SETTINGS: &[&str] = &["privileges", "sandbox", "update", "system.data_dir"];
+
+/// Settings never saved at runtime, as they come from the command line and registration
+const IDENTITY: &[&str] = &["system.client_id", "system.api_key"];
+
+/// Outcome of merging an update over the running configuration
+#[derive(Debug, Clone)]
+pub struct ConfigChange {
+    pub config: AppConfig,
+    /// Top-level sections that differ, e.g. `timers`
+    pub changed: Vec<String>,
+}
+
+impl ConfigChange {
+    /// Whether a changed section is only read at startup
+    pub fn restart_required(&self) -> bool {
+        self.changed.iter().any(|section| !HOT_RELOADABLE.contains(&section.as_str()))
+    }
+
+    /// New timers, when they changed
+    pub fn timers(&self) -> Option<TimerConfig> {
+        self.changed
+            .iter()
+            .any(|section| section == "timers")
+            .then(|| self.config.timers.clone())
+    }
+}
+
+/// Merge `update` over `current` and validate the result
+pub fn merge_update(current: &AppConfig, update: &Value) -> Result<ConfigChange> {
+    if !update.is_object() {
+        bail!("Configuration update must be an object");
+    }
+    let before = serde_json::to_value(current)?;
+    let mut after = before.clone();
+    merge(&mut after, update);
+    let config: AppConfig = serde_json::from_value(after.clone()).context("Invalid configuration")?;
+    config.validate()?;
+    for setting in PROTECTED_SETTINGS {
+        if lookup(&before, setting) != lookup(&after, setting) {
+            bail!("{} can only be changed in the configuration file", setting);
+        }
+    }
+
+    let changed = after
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(section, value)| before.get(section.as_str()) != Some(*value))
+        .map(|(section, _)| section.clone())
+        .collect();
+    Ok(ConfigChange { config, changed })
+}
+
+fn merge(target: &mut Value, update: &Value) {
+    match (target, update) {
+        (Value::Object(target), Value::Object(update)) => {
+            for (key, value) in update {
+                merge(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (target, update) => *target = update.clone(),
+    }
+}
+
+fn lookup<'a>(value: &'a Value, setting: &str) -> Option<&'a Value> {
+    setting.split('.').try_fold(value, |value, key| value.get(key))
+}
+
+/// Take `setting` out of a TOML document, returning whether it was there
+fn remove(value: &mut toml::Value, setting: &str) -> bool {
+    match setting.split_once('.') {
+        Some((section, rest)) => value.get_mut(section).is_some_and(|section| remove(section, rest)),
+        None => value.as_table_mut().is_some_and(|table| table.remove(setting).is_some()),
+    }
+}
+
+/// What `config` sets differently from `base`, table by table
+fn difference(base: &toml::Value, config: &toml::Value) -> toml::Value {
+    let (Some(base), Some(config)) = (base.as_table(), config.as_table()) else {
+        return config.clone();
+    };
+    let table = config
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = match base.get(key) {
+                Some(old) if old == value => return None,
+                Some(old) => difference(old, value),
+                None => value.clone(),
+            };
+            (!value.as_table().is_some_and(|table| table.is_empty())).then(|| (key.clone(), value))
+        })
+        .collect();
+    toml::Value::Table(table)
+}
+
+/// The settings saved at runtime in `path`, as TOML without the protected ones
+pub(super) fn read_overrides(path: &Path) -> Result<Option<String>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let mut overrides: toml::Value = toml::from_str(&contents).with_context(|| format!("Invalid {}", path.display()))?;
+    for setting in PROTECTED_SETTINGS {
+        if remove(&mut overrides, setting) {
+            warn!(setting, file = %path.display(), "Ignoring protected setting saved at runtime");
+        }
+    }
+    Ok(Some(toml::to_string(&overrides)?))
+}
+
+/// The running configuration and the file it is persisted to
+#[derive(Clone)]
+pub struct ConfigStore {
+    path: PathBuf,
+    /// Root-owned file `path` is layered over, when it only holds the differences
+    base: Option<PathBuf>,
+    /// Earlier versions of the file kept
+    keep: usize,
+    current: Arc<RwLock<AppConfig>>,
+    /// Held by a writer from reading `current` until its file is in place
+    writer: Arc<Mutex<()>>,
+}
+
+impl ConfigStore {
+    pub fn new(config: AppConfig, path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            base: None,
+            keep: config.system.config_versions,
+            current: Arc::new(RwLock::new(config)),
+            writer: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Save only what differs from `base`, loading the saved file over it
+    pub fn set_base(&mut self, base: impl Into<PathBuf>) {
+        self.base = Some(base.into());
+    }
+
+    pub fn current(&self) -> AppConfig {
+        self.current.read().clone()
+    }
+
+    /// Validate `update`, write the result to disk and make it current
+    pub fn apply(&self, update: &Value) -> Result<ConfigChange> {
+        let _writer = self.writer.lock();
+        let change = merge_update(&self.current(), update)?;
+        if !change.changed.is_empty() {
+            self.write(&change.config)?;
+            *self.current.write() = change.config.clone();
+        }
+        Ok(change)
+    }
+
+    /// Read the file again and make it current if it passes validation
+    pub fn reload(&self) -> Result<AppConfig> {
+        let _writer = self.writer.lock();
+        let config = self.read(&self.path, &self.current())?;
+        *self.current.write() = config.clone();
+        Ok(config)
+    }
+
+    /// Put the version before the last write back in place and make it current
+    ///
+    /// The replaced file is dropped rather than kept, so each rollback goes one version further back.
+    pub fn rollback(&self) -> Result<AppConfig> {
+        let _writer = self.writer.lock();
+        let previous = version_path(&self.path, 1);
+        if !previous.exists() {
+            bail!("No earlier configuration to roll back to");
+        }
+        let config = self.read(&previous, &self.current())?;
+
+        std::fs::rename(&previous, &self.path).with_context(|| format!("Failed to restore {}", previous.display()))?;
+        let mut n = 2;
+        while version_path(&self.path, n).exists() {
+            std::fs::rename(version_path(&self.path, n), version_path(&self.path, n - 1))?;
+            n += 1;
+        }
+        sync_dir(&self.path)?;
+        *self.current.write() = config.clone();
+        Ok(config)
+    }
+
+    /// The configuration in `path`, over the base file if there is one, if it passes validation
+    fn read(&self, path: &Path, current: &AppConfig) -> Result<AppConfig> {
+        let file = self.base.as_deref().unwrap_or(path);
+        if !file.exists() {
+            bail!("{} does not exist", file.display());
+        }
+        let mut config = AppConfig::load_layered(file, self.base.is_some().then_some(path))
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        // The identity comes from the command line and registration, not from the file
+        config.system.client_id = current.system.client_id.clone();
+        config.system.api_key = current.system.api_key.clone();
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Change the running configuration in place, then validate, persist and make it current
+    pub fn edit(&self, edit: impl FnOnce(&mut AppConfig) -> Result<()>) -> Result<ConfigChange> {
+        let _writer = self.writer.lock();
+        let current = self.current();
+        let mut edited = current.clone();
+        edit(&mut edited)?;
+        let change = merge_update(&current, &serde_json::to_value(&edited)?)?;
+        if !change.changed.is_empty() {
+            self.write(&change.config)?;
+            *self.current.write() = change.config.clone();
+        }
+        Ok(change)
+    }
+
+    /// Persist `config`: all of it, or over a base file only the settings that differ from it
+    fn write(&self, config: &AppConfig) -> Result<()> {
+        let Some(base) = &self.base else {
+            return save(config, &self.path, self.keep);
+        };
+        let base = AppConfig::load_from(base).with_context(|| format!("Failed to read {}", base.display()))?;
+        let mut overrides = difference(&toml::Value::try_from(&base)?, &toml::Value::try_from(config)?);
+        for setting in PROTECTED_SETTINGS.iter().chain(IDENTITY) {
+            remove(&mut overrides, setting);
+        }
+        let contents = toml::to_string(&overrides).context("Failed to serialize configuration")?;
+        replace(&self.path, contents.as_bytes(), self.keep)
+    }
+
+    /// Run `f` on the blocking pool, for the writes above from async code
+    pub async fn run_blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&ConfigStore) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || f(&store)).await.unwrap_or_else(|e| Err(e.into()))
+    }
+}
+
+/// Write the configuration as TOML, replacing the file atomically and keeping `keep` earlier versions
+///
+/// The master token is left out: it comes from the command line, registration
+/// or rotation, and is never read back from the file.
+pub fn save(config: &AppConfig, path: &Path, keep: usize) -> Result<()> {
+    let mut config = config.clone();
+    config.system.api_key = None;
+    let contents = toml::to_string(&config).context("Failed to serialize configuration")?;
+    replace(path, contents.as_bytes(), keep)
+}
+
+/// Replace the file at `path` with `contents`, keeping `keep` earlier versions
+fn replace(path: &Path, contents: &[u8], keep: usize) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("toml.tmp");
+    write_synced(&tmp, contents)?;
+
+    let mut n = keep.max(1);
+    while version_path(path, n).exists() {
+        std::fs::remove_file(version_path(path, n))?;
+        n += 1;
+    }
+    if keep > 0 && path.exists() {
+        for n in (1..keep).rev() {
+            let from = version_path(path, n);
+            if from.exists() {
+                std::fs::rename(&from, version_path(path, n + 1))?;
+            }
+        }
+        let replaced = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        write_synced(&version_path(path, 1), &replaced)?;
+    }
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    sync_dir(path)
+}
+
+/// Earlier version `n` of the file at `path`, e.g. `config.toml.1`
+pub fn version_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Write and sync a file readable by the owner only
+fn write_synced(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // A file left over from an interrupted write keeps its old mode otherwise
+        if path.exists() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+                .with_context(|| format!("Failed to restrict {}", path.display()))?;
+        }
+    }
+    let mut file = options.open(path).with_context(|| format!("Failed to write {}", path.display()))?;
+    file.write_all(contents)?;
+    file.sync_all().with_context(|| format!("Failed to sync {}", path.display()))
+}
+
+/// Make the renames in the directory of `path` durable
+fn sync_dir(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Failed to sync {}", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_merge_update() {
+        let current = AppConfig::test_default();
+        let change = merge_update(&current, &json!({"timers": {"exit_delay_s": 45}})).unwrap();
+        assert_eq!(change.config.timers.exit_delay_s, 45);
+        assert_eq!(change.config.timers.entry_delay_s, current.timers.entry_delay_s);
+        assert_eq!(change.changed, vec!["timers"]);
+        assert!(!change.restart_required());
+        assert_eq!(change.timers().unwrap().exit_delay_s, 45);
+
+        let change = merge_update(&current, &json!({"http": {"listen_addr": "0.0.0.0:9090"}})).unwrap();
+        assert!(change.restart_required());
+        assert!(change.timers().is_none());
+
+        // Rejected by the same checks as at startup
+        assert!(merge_update(&current, &json!({"timers": {"exit_delay_s": 0}})).is_err());
+        assert!(merge_update(&current, &json!({"timers": {"exit_delay_s": "soon"}})).is_err());
+        assert!(merge_update(&current, &json!([1, 2])).is_err());
+    }
+
+    #[test]
+    fn test_store_persists_applied_config() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        let store = ConfigStore::new(AppConfig::test_default(), &path);
+
+        assert!(store.apply(&json!({"timers": {"exit_delay_s": 0}})).is_err());
+        assert!(!path.exists());
+
+        store.apply(&json!({"timers": {"auto_rearm_s": 300}})).unwrap();
+        assert_eq!(store.current().timers.auto_rearm_s, 300);
+        let saved: AppConfig = toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved.timers.auto_rearm_s, 300);
+        assert_eq!(saved.system.client_id, "test-client");
+    }
+
+    #[test]
+    fn test_runtime_changes_stay_out_of_the_base_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let base = dir.path().join("config.toml");
+        let overrides = dir.path().join("config.override.toml");
+        let mut config = AppConfig::test_default();
+        config.privileges.drop = true;
+        config.sandbox.enabled = true;
+        save(&config, &base, 0).unwrap();
+        let written = std::fs::read(&base).unwrap();
+        let mut store = ConfigStore::new(config, &overrides);
+        store.set_base(&base);
+
+        store.apply(&json!({"timers": {"auto_rearm_s": 300}})).unwrap();
+        assert_eq!(std::fs::read(&base).unwrap(), written);
+        assert_eq!(std::fs::read_to_string(&overrides).unwrap(), "[timers]\nauto_rearm_s = 300\n");
+        for update in [json!({"privileges": {"drop": false}}), json!({"sandbox": {"enabled": false}})] {
+            let error = store.apply(&update).unwrap_err().to_string();
+            assert!(error.contains("can only be changed in the configuration file"), "{}", error);
+        }
+
+        // Protected settings written into the saved file behind the agent's back are ignored
+        std::fs::write(&overrides, "[timers]\nauto_rearm_s = 400\n[privileges]\ndrop = false\n[sandbox]\nenabled = false\n")
+            .unwrap();
+        let reloaded = store.reload().unwrap();
+        assert_eq!(reloaded.timers.auto_rearm_s, 400);
+        assert!(reloaded.privileges.drop);
+        assert!(reloaded.sandbox.enabled);
+        let loaded = AppConfig::load_layered(&base, Some(&overrides)).unwrap();
+        assert_eq!((loaded.timers.auto_rearm_s, loaded.privileges.drop), (400, true));
+
+        store.apply(&json!({"timers": {"auto_rearm_s": 500}})).unwrap();
+        assert_eq!(store.rollback().unwrap().timers.auto_rearm_s, 400);
+    }
+
+    #[tokio::test]
+    async fn test_readers_do_not_wait_for_writers() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = ConfigStore::new(AppConfig::test_default(), dir.path().join("config.toml"));
+
+        let writer = store.writer.lock();
+        assert_eq!(store.current().timers.exit_delay_s, 30);
+        drop(writer);
+
+        let change = store.run_blocking(|store| store.apply(&json!({"timers": {"exit_delay_s": 45}}))).await.unwrap();
+        assert_eq!(change.changed, ["timers"]);
+        assert_eq!(store.current().timers.exit_delay_s, 45);
+    }
+
+    #[test]
+    fn test_saved_file_keeps_master_token_out() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        let mut config = AppConfig::test_default();
+        config.system.api_key = Some("master-bearer-token".to_string());
+        let store = ConfigStore::new(config, &path);
+
+        store.apply(&json!({"timers": {"auto_rearm_s": 300}})).unwrap();
+        store.apply(&json!({"timers": {"auto_rearm_s": 400}})).unwrap();
+        for file in [path.clone(), version_path(&path, 1)] {
+            let contents = std::fs::read_to_string(&file).unwrap();
+            assert!(!contents.contains("api_key") && !contents.contains("master-bearer-token"), "{}", contents);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+            }
+        }
+        // The running configuration still has it
+        assert_eq!(store.current().system.api_key.as_deref(), Some("master-bearer-token"));
+    }
+
+    #[test]
+    fn test_store_keeps_versions_and_rolls_back() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        let mut config = AppConfig::test_default();
+        config.system.config_versions = 2;
+        let store = ConfigStore::new(config, &path);
+        let saved = |path: &Path| -> AppConfig { toml::from_str(&std::fs::read_to_string(path).unwrap()).unwrap() };
+
+        for rearm in [100, 200, 300, 400] {
+            store.apply(&json!({"timers": {"auto_rearm_s": rearm}})).unwrap();
+        }
+        assert_eq!(saved(&path).timers.auto_rearm_s, 400);
+        assert_eq!(saved(&version_path(&path, 1)).timers.auto_rearm_s, 300);
+        assert_eq!(saved(&version_path(&path, 2)).timers.auto_rearm_s, 200);
+        assert!(!version_path(&path, 3).exists());
+        assert!(!path.with_extension("toml.tmp").exists());
+
+        assert_eq!(store.rollback().unwrap().timers.auto_rearm_s, 300);
+        assert_eq!(store.current().timers.auto_rearm_s, 300);
+        assert_eq!(saved(&path).timers.auto_rearm_s, 300);
+        assert_eq!(saved(&version_path(&path, 1)).timers.auto_rearm_s, 200);
+        assert!(!version_path(&path, 2).exists());
+
+        // A version that no longer validates is left alone
+        let mut invalid = saved(&version_path(&path, 1));
+        invalid.timers.exit_delay_s = 0;
+        std::fs::write(version_path(&path, 1), toml::to_string(&invalid).unwrap()).unwrap();
+        assert!(store.rollback().is_err());
+        assert_eq!(store.current().timers.auto_rearm_s, 300);
+        assert_eq!(saved(&path).timers.auto_rearm_s, 300);
+
+        std::fs::remove_file(version_path(&path, 1)).unwrap();
+        assert!(store.rollback().is_err());
+    }
+}
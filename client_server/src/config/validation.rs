@@ -1,78 +1,639 @@
 //! Configuration validation
+//!
+//! Every check runs, so one pass reports every problem in a configuration
+//! rather than only the first. Each problem names the setting it concerns.
 
-use super::AppConfig;
-use anyhow::{bail, Result};
+use super::{AppConfig, KeyStorage, Pull, Rtl433Source};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+
+/// Highest BCM GPIO number on the Raspberry Pi header
+const MAX_BCM_PIN: u8 = 27;
+/// Longest exit or entry delay, so a forgotten setting cannot leave the premises unguarded
+const MAX_DELAY_S: u64 = 600;
+/// Longest the siren may be set to sound
+const MAX_SIREN_S: u64 = 3600;
+
+/// One problem found in the configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigError {
+    /// Dotted path of the setting, e.g. `timers.exit_delay_s` or `rf433.mappings.keyfob`
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Every problem found in a configuration, in the order checked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct ValidationErrors(pub Vec<ConfigError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl ValidationErrors {
+    /// The problems behind `error`, or none when it is not a failed validation
+    pub fn of(error: &anyhow::Error) -> Vec<ConfigError> {
+        error.downcast_ref::<Self>().map(|errors| errors.0.clone()).unwrap_or_default()
+    }
+}
+
+/// Collects problems instead of stopping at the first
+#[derive(Default)]
+struct Checker {
+    errors: Vec<ConfigError>,
+}
+
+impl Checker {
+    fn fail(&mut self, field: impl Into<String>, message: impl fmt::Display) {
+        self.errors.push(ConfigError { field: field.into(), message: message.to_string() });
+    }
+
+    /// Record `message` against `field` unless `ok`
+    fn ensure(&mut self, ok: bool, field: impl Into<String>, message: impl fmt::Display) {
+        if !ok {
+            self.fail(field, message);
+        }
+    }
+
+    /// The parsed value, or nothing with the parse error recorded against `field`
+    fn parse<T>(&mut self, field: impl Into<String>, result: Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.fail(field, format!("{:#}", e));
+                None
+            }
+        }
+    }
+}
+
+/// Whether an address `TcpListener::bind` takes: an IP and port, or a host name and port
+fn valid_listen_addr(addr: &str) -> bool {
+    addr.parse::<SocketAddr>().is_ok()
+        || addr.rsplit_once(':').is_some_and(|(host, port)| {
+            !host.is_empty() && !host.contains([':', '/', ' ']) && port.parse::<u16>().is_ok_and(|port| port > 0)
+        })
+}
 
 impl AppConfig {
-    /// Validate configuration values
-    pub fn validate(&self) -> Result<()> {
-        // Validate client_id
-        if self.system.client_id.is_empty() {
-            bail!("system.client_id cannot be empty");
-        }
-
-        // Validate listen address
-        if self.http.listen_addr.is_empty() {
-            bail!("http.listen_addr cannot be empty");
-        }
-
-        // Validate GPIO pins (must be different)
-        let pins = vec![
-            ("reed_in", self.gpio.reed_in),
-            ("siren_out", self.gpio.siren_out),
-            ("floodlight_out", self.gpio.floodlight_out),
-            ("radio433_rx_in", self.gpio.radio433_rx_in),
-        ];
-
-        for i in 0..pins.len() {
-            for j in (i + 1)..pins.len() {
-                if pins[i].1 == pins[j].1 {
-                    bail!(
-                        "GPIO pin conflict: {} and {} both use pin {}",
-                        pins[i].0,
-                        pins[j].0,
-                        pins[i].1
-                    );
+    /// Check every setting, reporting all problems found
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut check = Checker::default();
+        self.check_system(&mut check);
+        self.check_gpio(&mut check);
+        self.check_sensors(&mut check);
+        self.check_rf433(&mut check);
+        self.check_services(&mut check);
+        self.check_timers(&mut check);
+        self.check_cloud(&mut check);
+        self.check_network(&mut check);
+        if check.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(check.errors))
+        }
+    }
+
+    fn check_system(&self, check: &mut Checker) {
+        check.ensure(!self.system.client_id.is_empty(), "system.client_id", "cannot be empty");
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.system.log_level) {
+            check.fail("system.log_level", e);
+        }
+        check.ensure(
+            valid_listen_addr(&self.http.listen_addr),
+            "http.listen_addr",
+            format!("{:?} is not an address and port, e.g. 0.0.0.0:8080", self.http.listen_addr),
+        );
+        check.ensure(
+            !self.privileges.drop || !(self.privileges.user.is_empty() || self.privileges.user == "root"),
+            "privileges.user",
+            "must name an unprivileged user",
+        );
+        match self.signing.key_storage {
+            KeyStorage::Tpm if !(0x8100_0000..=0x81FF_FFFF).contains(&self.signing.tpm.handle) => {
+                check.fail("signing.tpm.handle", "must be a persistent handle, 0x81000000-0x81ffffff");
+            }
+            KeyStorage::Atecc608 if self.signing.secure_element.slot > 15 => {
+                check.fail("signing.secure_element.slot", "must be within 0-15");
+            }
+            _ => {}
+        }
+    }
+
+    fn check_gpio(&self, check: &mut Checker) {
+        let gpio = &self.gpio;
+        check.ensure(!gpio.zones.iter().any(|zone| zone.name.is_empty()), "gpio.zones", "entries must have a name");
+        check.ensure(
+            !gpio.outputs.iter().any(|output| output.name.is_empty()),
+            "gpio.outputs",
+            "entries must have a name",
+        );
+        for output in gpio.outputs.iter().filter(|output| output.pulse_ms == Some(0)) {
+            check.fail(format!("gpio.outputs.{}.pulse_ms", output.name), "must be greater than 0");
+        }
+        for (key, name) in [("lock_output", &gpio.lock_output), ("garage_output", &gpio.garage_output)] {
+            let Some(name) = name else { continue };
+            match gpio.outputs.iter().find(|output| &output.name == name) {
+                Some(output) if output.pulse_ms.is_some() => {}
+                Some(_) => check.fail(format!("gpio.{}", key), format!("output {} must set pulse_ms", name)),
+                None => check.fail(format!("gpio.{}", key), format!("no output named {}", name)),
+            }
+        }
+        if let Some(zone) = &gpio.garage_zone {
+            check.ensure(gpio.zones.iter().any(|z| &z.name == zone), "gpio.garage_zone", format!("no zone named {}", zone));
+        }
+
+        // Every pin must exist on the header and be used once
+        let inputs = gpio.input_pins();
+        let outputs = gpio.output_pins();
+        let mut pins: Vec<(&str, u8)> = inputs.iter().chain(outputs.iter()).map(|(name, pin)| (name.as_str(), *pin)).collect();
+        pins.push(("radio433_rx_in", gpio.radio433_rx_in));
+        let field = |name: &str| {
+            if gpio.zones.iter().any(|zone| zone.name == name) {
+                format!("gpio.zones.{}", name)
+            } else if gpio.outputs.iter().any(|output| output.name == name) {
+                format!("gpio.outputs.{}", name)
+            } else {
+                format!("gpio.{}", name)
+            }
+        };
+        for (i, (name, pin)) in pins.iter().enumerate() {
+            check.ensure(*pin <= MAX_BCM_PIN, field(name), format!("BCM pin {} does not exist, expected 0-{}", pin, MAX_BCM_PIN));
+            for (other, other_pin) in &pins[..i] {
+                if other == name {
+                    check.fail(field(name), "duplicate GPIO name");
+                } else if other_pin == pin {
+                    check.fail(field(name), format!("pin {} is already used by {}", pin, other));
                 }
             }
         }
 
-        // Validate timer values (must be positive)
-        if self.timers.exit_delay_s == 0 {
-            bail!("timers.exit_delay_s must be greater than 0");
+        // Per-pin electrical options
+        for (name, options) in &gpio.pin_options {
+            let field = format!("gpio.pin_options.{}", name);
+            let is_input = inputs.iter().any(|(n, _)| n == name);
+            let is_output = outputs.iter().any(|(n, _)| n == name);
+            if !is_input && !is_output {
+                check.fail(field, "does not match any configured pin");
+                continue;
+            }
+            check.ensure(!(is_input && options.open_drain), field.clone(), "open_drain only applies to outputs");
+            check.ensure(!(is_output && options.pull != Pull::None), field, "pull only applies to inputs");
         }
-        if self.timers.entry_delay_s == 0 {
-            bail!("timers.entry_delay_s must be greater than 0");
+
+        // Actuator safety limits
+        for (name, limits) in &gpio.limits {
+            let field = format!("gpio.limits.{}", name);
+            let is_rf = self.rf433.outputs.iter().any(|output| &output.name == name);
+            check.ensure(
+                is_rf || outputs.iter().any(|(n, _)| n == name),
+                field.clone(),
+                "does not match any configured output",
+            );
+            check.ensure(limits.max_on_s != Some(0), format!("{}.max_on_s", field), "must be greater than 0");
         }
-        if self.timers.siren_max_s == 0 {
-            bail!("timers.siren_max_s must be greater than 0");
+
+        // Siren and buzzer tones
+        check.ensure(gpio.siren_pwm_hz.is_none_or(|hz| hz > 0.0), "gpio.siren_pwm_hz", "must be greater than 0");
+        check.ensure(
+            gpio.siren_pwm_duty > 0.0 && gpio.siren_pwm_duty <= 1.0,
+            "gpio.siren_pwm_duty",
+            "must be within (0.0, 1.0]",
+        );
+        check.ensure(gpio.buzzer_hz.is_none_or(|hz| hz > 0.0), "gpio.buzzer_hz", "must be greater than 0");
+        check.ensure(gpio.buzzer_duty > 0.0 && gpio.buzzer_duty <= 1.0, "gpio.buzzer_duty", "must be within (0.0, 1.0]");
+
+        // Vibration pulse detector and Wiegand reader
+        check.ensure(gpio.vibration_pulses > 0, "gpio.vibration_pulses", "must be greater than 0");
+        check.ensure(gpio.vibration_window_ms > 0, "gpio.vibration_window_ms", "must be greater than 0");
+        check.ensure(
+            gpio.wiegand_d0_in.is_some() == gpio.wiegand_d1_in.is_some(),
+            "gpio.wiegand_d0_in",
+            "must be set together with gpio.wiegand_d1_in",
+        );
+    }
+
+    fn check_sensors(&self, check: &mut Checker) {
+        // 1-Wire temperature sensors
+        check.ensure(self.onewire.interval_s > 0, "onewire.interval_s", "must be greater than 0");
+        check.ensure(self.onewire.hysteresis_c >= 0.0, "onewire.hysteresis_c", "cannot be negative");
+        for (i, sensor) in self.onewire.sensors.iter().enumerate() {
+            if sensor.name.is_empty() || sensor.id.is_empty() {
+                check.fail("onewire.sensors", "entries must have a name and id");
+                continue;
+            }
+            let field = format!("onewire.sensors.{}", sensor.name);
+            check.ensure(
+                !self.onewire.sensors[..i].iter().any(|other| other.name == sensor.name),
+                field.clone(),
+                "duplicate sensor name",
+            );
+            if let (Some(low), Some(high)) = (sensor.low_c, sensor.high_c) {
+                check.ensure(low < high, field, "low_c must be below high_c");
+            }
         }
 
-        // Validate cloud config if URL is provided
-        if let Some(url) = &self.cloud.url {
-            if !url.starts_with("wss://") && !url.starts_with("ws://") {
-                bail!("cloud.url must start with ws:// or wss://");
+        // rtl_433 sensors
+        let inputs = self.gpio.input_pins();
+        if self.rtl433.enabled {
+            match self.rtl433.source {
+                Rtl433Source::Process if self.rtl433.command.is_empty() => check.fail("rtl433.command", "cannot be empty"),
+                Rtl433Source::Mqtt if self.rtl433.mqtt_host.is_empty() || self.rtl433.mqtt_topic.is_empty() => {
+                    check.fail("rtl433.mqtt_host", "rtl433.mqtt_host and rtl433.mqtt_topic cannot be empty")
+                }
+                _ => {}
             }
         }
+        for (i, sensor) in self.rtl433.sensors.iter().enumerate() {
+            if sensor.zone.is_empty() || sensor.model.is_empty() || sensor.id.is_empty() {
+                check.fail("rtl433.sensors", "entries must have a zone, model and id");
+                continue;
+            }
+            let field = format!("rtl433.sensors.{}", sensor.zone);
+            let earlier = &self.rtl433.sensors[..i];
+            check.ensure(
+                !earlier.iter().any(|other| other.zone == sensor.zone) && !inputs.iter().any(|(name, _)| name == &sensor.zone),
+                field.clone(),
+                "duplicate zone name",
+            );
+            check.ensure(
+                !earlier.iter().any(|other| other.model == sensor.model && other.id == sensor.id),
+                field.clone(),
+                format!("{} {} is already mapped", sensor.model, sensor.id),
+            );
+            check.ensure(
+                sensor.field.is_some() || sensor.restore_s > 0,
+                format!("{}.restore_s", field),
+                "must be greater than 0",
+            );
+            check.ensure(sensor.supervision_s != Some(0), format!("{}.supervision_s", field), "must be greater than 0");
+        }
+
+        // BLE presence beacons
+        let presence = &self.ble.presence;
+        check.ensure(presence.away_after_s > 0, "ble.presence.away_after_s", "must be greater than 0");
+        check.ensure(presence.rssi_hysteresis_db >= 0, "ble.presence.rssi_hysteresis_db", "cannot be negative");
+        for (i, beacon) in presence.beacons.iter().enumerate() {
+            if beacon.name.is_empty() {
+                check.fail("ble.presence.beacons", "entries must have a name");
+                continue;
+            }
+            let field = format!("ble.presence.beacons.{}", beacon.name);
+            check.ensure(
+                !presence.beacons[..i].iter().any(|other| other.name == beacon.name),
+                field.clone(),
+                "duplicate beacon name",
+            );
+            check.parse(format!("{}.id", field), crate::ble::normalize_beacon_id(&beacon.id));
+        }
 
-        // Validate backoff values
-        if self.cloud.backoff_min_s > self.cloud.backoff_max_s {
-            bail!(
-                "cloud.backoff_min_s ({}) must be <= cloud.backoff_max_s ({})",
-                self.cloud.backoff_min_s,
-                self.cloud.backoff_max_s
+        // ADC channels and battery thresholds
+        check.ensure(self.adc.interval_s > 0, "adc.interval_s", "must be greater than 0");
+        check.ensure(self.adc.vref > 0.0, "adc.vref", "must be greater than 0");
+        for (i, channel) in self.adc.channels.iter().enumerate() {
+            if channel.name.is_empty() {
+                check.fail("adc.channels", "entries must have a name");
+                continue;
+            }
+            let field = format!("adc.channels.{}", channel.name);
+            check.ensure(channel.channel <= 7, format!("{}.channel", field), "must be 0-7");
+            check.ensure(
+                !self.adc.channels[..i].iter().any(|other| other.name == channel.name),
+                field,
+                "duplicate channel name",
+            );
+        }
+        if let Some(battery) = &self.adc.battery {
+            check.ensure(
+                self.adc.channels.iter().any(|channel| channel.name == battery.channel),
+                "adc.battery.channel",
+                format!("{} does not match any adc channel", battery.channel),
+            );
+            check.ensure(
+                battery.low_below_v < battery.on_battery_below_v,
+                "adc.battery.low_below_v",
+                "must be below on_battery_below_v",
             );
+            check.ensure(battery.hysteresis_v >= 0.0, "adc.battery.hysteresis_v", "cannot be negative");
         }
+    }
+
+    fn check_rf433(&self, check: &mut Checker) {
+        let rf = &self.rf433;
+        check.ensure(rf.debounce_ms >= 100, "rf433.debounce_ms", "must be at least 100");
 
-        // Validate queue limits
-        if self.cloud.queue_max_events == 0 {
-            bail!("cloud.queue_max_events must be greater than 0");
+        // A code identifies one remote, sensor or socket, so it can be used only once
+        let mut used_codes = Vec::new();
+        for (i, mapping) in rf.mappings.iter().enumerate() {
+            if mapping.name.is_empty() {
+                check.fail("rf433.mappings", "entries must have a name");
+                continue;
+            }
+            let field = format!("rf433.mappings.{}", mapping.name);
+            check.ensure(
+                !rf.mappings[..i].iter().any(|other| other.name == mapping.name),
+                field.clone(),
+                "duplicate mapping name",
+            );
+            for entry in &mapping.codes {
+                if let Some(code) = check.parse(field.clone(), crate::rf433::parse_code(&entry.code)) {
+                    if used_codes.contains(&code) {
+                        check.fail(field.clone(), format!("code {} is mapped twice", crate::rf433::format_code(code)));
+                    }
+                    used_codes.push(code);
+                }
+                check.ensure(
+                    entry.debounce_ms.is_none_or(|ms| ms >= 100),
+                    format!("{}.debounce_ms", field),
+                    "must be at least 100",
+                );
+                check.parse(
+                    format!("{}.action", field),
+                    crate::events::command_to_event(&entry.action, &entry.args, crate::events::EventSource::Rf),
+                );
+            }
         }
-        if self.cloud.queue_max_age_days == 0 {
-            bail!("cloud.queue_max_age_days must be greater than 0");
+        for code in &rf.panic_codes {
+            let Some(code) = check.parse("rf433.panic_codes", crate::rf433::parse_code(code)) else { continue };
+            if used_codes.contains(&code) {
+                check.fail(
+                    "rf433.panic_codes",
+                    format!("code {} is also mapped to a command", crate::rf433::format_code(code)),
+                );
+            }
+            used_codes.push(code);
         }
 
-        Ok(())
+        let inputs = self.gpio.input_pins();
+        for (i, sensor) in rf.sensors.iter().enumerate() {
+            if sensor.zone.is_empty() || sensor.codes.is_empty() {
+                check.fail("rf433.sensors", "entries must have a zone and codes");
+                continue;
+            }
+            let field = format!("rf433.sensors.{}", sensor.zone);
+            let taken = rf.sensors[..i].iter().any(|other| other.zone == sensor.zone)
+                || inputs.iter().any(|(name, _)| name == &sensor.zone)
+                || self.rtl433.sensors.iter().any(|other| other.zone == sensor.zone);
+            check.ensure(!taken, field.clone(), "duplicate zone name");
+            for code in &sensor.codes {
+                let Some(code) = check.parse(field.clone(), crate::rf433::parse_code(code)) else { continue };
+                if used_codes.contains(&code) {
+                    check.fail(field.clone(), format!("code {} is already in use", crate::rf433::format_code(code)));
+                }
+                used_codes.push(code);
+            }
+            check.ensure(sensor.restore_s > 0, format!("{}.restore_s", field), "must be greater than 0");
+        }
+
+        // The first press has to end before the second can start
+        check.ensure(
+            !rf.panic_double_press || rf.panic_window_ms > rf.debounce_ms,
+            "rf433.panic_window_ms",
+            "must be longer than rf433.debounce_ms",
+        );
+        check.ensure(
+            rf.counter_window > 0 && rf.counter_window < 0x8000,
+            "rf433.counter_window",
+            "must be within 1-32767",
+        );
+        check.ensure(rf.jamming_duty > 0.0 && rf.jamming_duty <= 1.0, "rf433.jamming_duty", "must be within (0.0, 1.0]");
+        check.ensure(
+            rf.replay_presses == 0 || rf.replay_window_s > 0,
+            "rf433.replay_window_s",
+            "must be greater than 0",
+        );
+
+        for (i, remote) in rf.remotes.iter().enumerate() {
+            if remote.name.is_empty() {
+                check.fail("rf433.remotes", "entries must have a name");
+                continue;
+            }
+            let field = format!("rf433.remotes.{}", remote.name);
+            check.ensure(
+                !rf.remotes[..i].iter().any(|other| other.name == remote.name),
+                field.clone(),
+                "duplicate remote name",
+            );
+            check.parse(format!("{}.serial", field), crate::rf433::parse_serial(&remote.serial));
+            for button in &remote.buttons {
+                check.ensure((1..=15).contains(&button.button), format!("{}.buttons", field), "must be 1-15");
+                check.parse(
+                    format!("{}.action", field),
+                    crate::events::command_to_event(&button.action, &button.args, crate::events::EventSource::Rf),
+                );
+            }
+        }
+
+        for (i, output) in rf.outputs.iter().enumerate() {
+            if output.name.is_empty() {
+                check.fail("rf433.outputs", "entries must have a name");
+                continue;
+            }
+            let field = format!("rf433.outputs.{}", output.name);
+            let duplicate = rf.outputs[..i].iter().any(|other| other.name == output.name)
+                || self.gpio.outputs.iter().any(|other| other.name == output.name);
+            check.ensure(!duplicate, field.clone(), "duplicate output name");
+            check.ensure(self.gpio.radio433_tx_out.is_some(), field.clone(), "gpio.radio433_tx_out is not set");
+            for code in std::iter::once(&output.on_code).chain(&output.off_code) {
+                let Some(code) = check.parse(field.clone(), crate::rf433::parse_code(code)) else { continue };
+                // The receiver hears our own transmissions
+                if used_codes.contains(&code) {
+                    check.fail(field.clone(), format!("code {} is also mapped to a command", crate::rf433::format_code(code)));
+                }
+            }
+            check.ensure((100..=1000).contains(&output.pulse_us), format!("{}.pulse_us", field), "must be within 100-1000");
+            check.ensure(output.repeats > 0, format!("{}.repeats", field), "must be greater than 0");
+            match (&output.off_code, output.pulse_ms) {
+                (_, Some(0)) => check.fail(format!("{}.pulse_ms", field), "must be greater than 0"),
+                (None, None) => check.fail(field, "set off_code or pulse_ms"),
+                _ => {}
+            }
+        }
+    }
+
+    fn check_services(&self, check: &mut Checker) {
+        check.ensure(self.access.max_failures > 0, "access.max_failures", "must be greater than 0");
+        check.ensure(self.access.keypad_timeout_s > 0, "access.keypad_timeout_s", "must be greater than 0");
+        check.ensure(
+            self.event_bus.capacity > 0 && self.event_bus.broadcast_capacity > 0,
+            "event_bus.capacity",
+            "event_bus.capacity and event_bus.broadcast_capacity must be greater than 0",
+        );
+        check.ensure(
+            !self.journal.enabled || (self.journal.max_events > 0 && self.journal.max_age_days > 0),
+            "journal.max_events",
+            "journal.max_events and journal.max_age_days must be greater than 0",
+        );
+        check.ensure(
+            !self.logging.file || (self.logging.max_file_mb > 0 && self.logging.max_files > 0),
+            "logging.max_file_mb",
+            "logging.max_file_mb and logging.max_files must be greater than 0",
+        );
+        check.ensure(
+            !self.audit.enabled || (self.audit.max_entries > 0 && self.audit.max_age_days > 0),
+            "audit.max_entries",
+            "audit.max_entries and audit.max_age_days must be greater than 0",
+        );
+        check.ensure(
+            !self.replay.enabled || (self.replay.window_s > 0 && self.replay.max_ttl_s > 0),
+            "replay.window_s",
+            "replay.window_s and replay.max_ttl_s must be greater than 0",
+        );
+        check.ensure(
+            !self.rotation.enabled || (self.rotation.grace_s > 0 && self.rotation.check_s > 0),
+            "rotation.grace_s",
+            "rotation.grace_s and rotation.check_s must be greater than 0",
+        );
+        if self.self_test.enabled {
+            check.ensure(
+                self.self_test.interval_days > 0 && self.self_test.check_s > 0,
+                "self_test.interval_days",
+                "self_test.interval_days and self_test.check_s must be greater than 0",
+            );
+            check.parse("self_test.window_start", self.self_test.window());
+        }
+        check.ensure(
+            !self.health_report.enabled || (self.health_report.interval_h > 0 && self.health_report.check_s > 0),
+            "health_report.interval_h",
+            "health_report.interval_h and health_report.check_s must be greater than 0",
+        );
+        check.ensure(
+            !self.update.enabled
+                || (self.update.confirm_after_s > 0 && self.update.max_boot_attempts > 0 && self.update.max_size_mb > 0),
+            "update.confirm_after_s",
+            "update.confirm_after_s, update.max_boot_attempts and update.max_size_mb must be greater than 0",
+        );
+    }
+
+    fn check_timers(&self, check: &mut Checker) {
+        let timers = &self.timers;
+        let delay = format!("must be within 1-{}", MAX_DELAY_S);
+        check.ensure((1..=MAX_DELAY_S).contains(&timers.exit_delay_s), "timers.exit_delay_s", &delay);
+        check.ensure((1..=MAX_DELAY_S).contains(&timers.entry_delay_s), "timers.entry_delay_s", &delay);
+        let siren = format!("must be within 1-{}", MAX_SIREN_S);
+        check.ensure((1..=MAX_SIREN_S).contains(&timers.siren_max_s), "timers.siren_max_s", &siren);
+        for (kind, siren_max_s) in &timers.siren_max_by_kind {
+            check.ensure((1..=MAX_SIREN_S).contains(siren_max_s), format!("timers.siren_max_by_kind.{}", kind), &siren);
+        }
+        check.parse("timers.night_start", timers.night_window());
+    }
+
+    fn check_cloud(&self, check: &mut Checker) {
+        let cloud = &self.cloud;
+        for url in &cloud.url {
+            check.ensure(
+                url.starts_with("wss://") || url.starts_with("ws://"),
+                "cloud.url",
+                format!("{} must start with ws:// or wss://", url),
+            );
+        }
+        check.ensure(
+            cloud.url.len() < 2 || cloud.primary_probe_s > 0,
+            "cloud.primary_probe_s",
+            "must be greater than 0",
+        );
+        if let Some(proxy) = &cloud.proxy {
+            check.ensure(
+                ["http://", "socks5://", "socks5h://"].iter().any(|scheme| proxy.url.starts_with(scheme)),
+                "cloud.proxy.url",
+                "must start with http://, socks5:// or socks5h://",
+            );
+            check.ensure(
+                proxy.password.is_none() || proxy.username.is_some(),
+                "cloud.proxy.password",
+                "requires cloud.proxy.username",
+            );
+        }
+        check.ensure(cloud.heartbeat_s > 0, "cloud.heartbeat_s", "must be greater than 0");
+        check.ensure(
+            !cloud.metered.enabled || cloud.metered.heartbeat_s > 0,
+            "cloud.metered.heartbeat_s",
+            "must be greater than 0",
+        );
+        check.ensure(
+            cloud.backoff_min_s <= cloud.backoff_max_s,
+            "cloud.backoff_min_s",
+            format!("({}) must be <= cloud.backoff_max_s ({})", cloud.backoff_min_s, cloud.backoff_max_s),
+        );
+
+        // Queue limits
+        check.ensure(cloud.queue_max_events > 0, "cloud.queue_max_events", "must be greater than 0");
+        check.ensure(cloud.queue_max_age_days > 0, "cloud.queue_max_age_days", "must be greater than 0");
+        check.ensure(
+            (1..=cloud.queue_max_events.max(1)).contains(&cloud.queue_batch),
+            "cloud.queue_batch",
+            "must be within 1 and cloud.queue_max_events",
+        );
+    }
+
+    fn check_network(&self, check: &mut Checker) {
+        let network = &self.network;
+        check.ensure(!network.wifi_interface.is_empty(), "network.wifi_interface", "cannot be empty");
+        check.ensure(network.rollback_s > 0, "network.rollback_s", "must be greater than 0");
+        check.ensure(network.report_s > 0, "network.report_s", "must be greater than 0");
+        check.ensure(
+            network.link_quality.interval_s > 0,
+            "network.link_quality.interval_s",
+            "must be greater than 0",
+        );
+        check.ensure(
+            network.failover.flap_window_s > 0 && network.failover.flap_threshold >= 2,
+            "network.failover.flap_window_s",
+            "must be greater than 0 and flap_threshold at least 2",
+        );
+
+        let captive = &network.captive_portal;
+        if captive.enabled {
+            check.ensure(captive.interval_s > 0, "network.captive_portal.interval_s", "must be greater than 0");
+            check.ensure(
+                captive.probe_url.starts_with("http://"),
+                "network.captive_portal.probe_url",
+                "must be a plain http URL",
+            );
+        }
+
+        let ap = &network.ap_fallback;
+        if ap.enabled {
+            check.ensure(
+                ap.after_s > 0 && ap.retry_s > 0,
+                "network.ap_fallback.after_s",
+                "network.ap_fallback.after_s and network.ap_fallback.retry_s must be greater than 0",
+            );
+            check.ensure(!ap.interface.is_empty(), "network.ap_fallback.interface", "cannot be empty");
+            let ssid = ap.ssid.replace("{client_id}", &self.system.client_id);
+            check.ensure(
+                !ssid.is_empty() && ssid.len() <= 32 && !ssid.contains(char::is_control),
+                "network.ap_fallback.ssid",
+                "must be 1-32 bytes without control characters",
+            );
+            if let Some(passphrase) = &ap.passphrase {
+                check.ensure(
+                    (8..=63).contains(&passphrase.len()) && passphrase.chars().all(|c| c.is_ascii_graphic() || c == ' '),
+                    "network.ap_fallback.passphrase",
+                    "must be 8-63 printable ASCII characters",
+                );
+            }
+            check.ensure((1..=13).contains(&ap.channel), "network.ap_fallback.channel", "must be within 1-13");
+        }
     }
 }
 
@@ -93,10 +654,230 @@ mod tests {
         assert!(config.validate().is_err());
     }
 
+    #[test]
+    fn test_validation_fails_with_motion_pin_conflict() {
+        let mut config = AppConfig::load().unwrap();
+        config.gpio.motion_in = Some(config.gpio.siren_out);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_fails_with_duplicate_zone_names() {
+        let mut config = AppConfig::load().unwrap();
+        for pin in [5, 6] {
+            config.gpio.zones.push(crate::config::ZoneConfig {
+                name: "water_leak".to_string(),
+                pin,
+                zone_type: crate::events::ZoneType::Environmental,
+            });
+        }
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_checks_rtl433_sensors() {
+        let mut config = AppConfig::load().unwrap();
+        let sensor = |zone: &str, id: &str| crate::config::Rtl433SensorConfig {
+            zone: zone.to_string(),
+            zone_type: crate::events::ZoneType::Perimeter,
+            model: "Honeywell-Security".to_string(),
+            id: id.to_string(),
+            field: Some("reed_open".to_string()),
+            restore_s: 10,
+            supervision_s: None,
+        };
+        config.rtl433.sensors = vec![sensor("patio_door", "1"), sensor("window", "2")];
+        assert!(config.validate().is_ok());
+
+        config.rtl433.sensors[1].id = "1".to_string();
+        assert!(config.validate().is_err());
+        config.rtl433.sensors[1] = sensor(&config.gpio.input_pins()[0].0.to_string(), "2");
+        assert!(config.validate().is_err());
+
+        config.rtl433.sensors.pop();
+        config.rtl433.enabled = true;
+        config.rtl433.command.clear();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_checks_ap_fallback() {
+        let mut config = AppConfig::load().unwrap();
+        config.network.ap_fallback.enabled = true;
+        assert!(config.validate().is_ok());
+
+        config.network.ap_fallback.passphrase = Some("short".to_string());
+        assert!(config.validate().is_err());
+        config.network.ap_fallback.passphrase = Some("door-setup-2024".to_string());
+        assert!(config.validate().is_ok());
+
+        config.network.ap_fallback.ssid = "setup-{client_id}-with-a-name-far-too-long".to_string();
+        assert!(config.validate().is_err());
+        config.network.ap_fallback.ssid = "setup\nchannel=1".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_checks_pin_options() {
+        let mut config = AppConfig::load().unwrap();
+        let open_drain = crate::config::PinOptions {
+            open_drain: true,
+            ..Default::default()
+        };
+
+        config.gpio.pin_options.insert("siren_out".to_string(), open_drain);
+        assert!(config.validate().is_ok());
+
+        config.gpio.pin_options.insert("reed_in".to_string(), open_drain);
+        assert!(config.validate().is_err());
+
+        config.gpio.pin_options.clear();
+        config.gpio.pin_options.insert("no_such_pin".to_string(), Default::default());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_checks_rf_outputs() {
+        let mut config = AppConfig::load().unwrap();
+        config.rf433.outputs.push(crate::config::RfOutputConfig {
+            name: "chime".to_string(),
+            on_code: "0xA1B2C3".to_string(),
+            off_code: None,
+            pulse_us: 350,
+            repeats: 8,
+            pulse_ms: Some(1000),
+            follow_siren: false,
+        });
+        // Needs a transmitter pin
+        assert!(config.validate().is_err());
+        config.gpio.radio433_tx_out = Some(24);
+        assert!(config.validate().is_ok());
+
+        config.rf433.mappings.push(crate::config::Rf433Mapping {
+            name: "keyfob".to_string(),
+            enabled: true,
+            allowed_actions: vec![],
+            codes: vec![crate::config::Rf433Code {
+                code: "0xA1B2C3".to_string(),
+                action: "arm".to_string(),
+                args: serde_json::Value::Null,
+                debounce_ms: None,
+            }],
+        });
+        assert!(config.validate().is_err());
+        config.rf433.mappings.clear();
+
+        // Panic buttons share the code space too
+        config.rf433.panic_codes = vec!["0xA1B2C3".to_string()];
+        assert!(config.validate().is_err());
+        config.rf433.panic_codes = vec!["0xA1B2C9".to_string()];
+        config.rf433.panic_double_press = true;
+        config.rf433.panic_window_ms = config.rf433.debounce_ms;
+        assert!(config.validate().is_err());
+        config.rf433.panic_window_ms = 3000;
+        assert!(config.validate().is_ok());
+
+        config.rf433.outputs[0].pulse_ms = None;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_checks_siren_pwm() {
+        let mut config = AppConfig::load().unwrap();
+        config.gpio.siren_pwm_hz = Some(2800.0);
+        assert!(config.validate().is_ok());
+
+        config.gpio.siren_pwm_duty = 1.5;
+        assert!(config.validate().is_err());
+
+        config.gpio.siren_pwm_duty = 0.5;
+        config.gpio.siren_pwm_hz = Some(0.0);
+        assert!(config.validate().is_err());
+
+        config.gpio.siren_pwm_hz = None;
+        config.gpio.buzzer_duty = 0.0;
+        assert!(config.validate().is_err());
+    }
+
     #[test]
     fn test_validation_fails_with_invalid_timers() {
         let mut config = AppConfig::load().unwrap();
         config.timers.exit_delay_s = 0;
         assert!(config.validate().is_err());
+
+        config.timers.exit_delay_s = 30;
+        config.timers.night_start = "25:00".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_reports_every_error() {
+        let mut config = AppConfig::load().unwrap();
+        config.timers.entry_delay_s = 900;
+        config.gpio.siren_out = 40;
+        config.http.listen_addr = "8080".to_string();
+        config.cloud.queue_batch = 0;
+        config.rf433.mappings.push(crate::config::Rf433Mapping {
+            name: "keyfob".to_string(),
+            enabled: true,
+            allowed_actions: vec![],
+            codes: vec![crate::config::Rf433Code {
+                code: "0xZZ".to_string(),
+                action: "arm".to_string(),
+                args: serde_json::Value::Null,
+                debounce_ms: None,
+            }],
+        });
+
+        let errors = config.validate().unwrap_err();
+        let fields: Vec<&str> = errors.0.iter().map(|error| error.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            ["http.listen_addr", "gpio.siren_out", "rf433.mappings.keyfob", "timers.entry_delay_s", "cloud.queue_batch"]
+        );
+        assert!(errors.to_string().contains("timers.entry_delay_s: must be within 1-600; "));
+        assert_eq!(serde_json::to_value(&errors).unwrap()[1]["message"], "BCM pin 40 does not exist, expected 0-27");
+    }
+
+    #[test]
+    fn test_listen_addr_accepts_ip_or_host() {
+        for addr in ["0.0.0.0:8080", "[::1]:8080", "localhost:8080"] {
+            assert!(valid_listen_addr(addr), "{}", addr);
+        }
+        for addr in ["", "8080", ":8080", "localhost", "localhost:99999", "::1:8080"] {
+            assert!(!valid_listen_addr(addr), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn test_cloud_url_accepts_one_or_many() {
+        let mut config = AppConfig::load().unwrap();
+        let with_url = |url: toml::Value| {
+            let mut cloud = toml::Value::try_from(&config.cloud).unwrap();
+            cloud.as_table_mut().unwrap().insert("url".to_string(), url);
+            cloud.try_into::<crate::config::CloudConfig>().unwrap()
+        };
+        let one = with_url("wss://a.example.com/client".into());
+        assert_eq!(one.url, vec!["wss://a.example.com/client"]);
+        let many = with_url(vec!["wss://a.example.com/client", "wss://b.example.com/client"].into());
+        assert_eq!(many.url.len(), 2);
+
+        config.cloud = many;
+        assert!(config.validate().is_ok());
+        config.cloud.primary_probe_s = 0;
+        assert!(config.validate().is_err());
+        config.cloud.primary_probe_s = 300;
+        config.cloud.url.push("https://c.example.com/client".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_night_window_wraps_midnight() {
+        let timers = AppConfig::test_default().timers;
+        let at = |s: &str| chrono::NaiveTime::parse_from_str(s, "%H:%M").unwrap();
+        assert!(timers.is_night(at("23:30")));
+        assert!(timers.is_night(at("06:59")));
+        assert!(!timers.is_night(at("07:00")));
+        assert!(!timers.is_night(at("12:00")));
     }
 }
@@ -0,0 +1,199 @@
+//! Status display for wall-mounted installations
+//!
+//! Renders alarm state, countdowns, cloud connectivity and the IP address
+//! as a few lines of text and redraws them whenever an event is broadcast.
+
+#[cfg(feature = "oled-display")]
+mod ssd1306;
+
+#[cfg(feature = "oled-display")]
+pub use self::ssd1306::OledPanel;
+
+use crate::config::DisplayConfig;
+use crate::events::EventEnvelope;
+use crate::state::{AlarmState, AppState, CloudStatus, SharedState};
+use anyhow::Result;
+use std::net::{IpAddr, UdpSocket};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, warn};
+
+/// Redraw at least this often so the IP address and uptime-driven state stay fresh
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Text shown on the display, most important line first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusScreen {
+    pub lines: Vec<String>,
+}
+
+impl StatusScreen {
+    /// Compose the screen for the given state
+    pub fn render(state: &SharedState, ip: Option<IpAddr>) -> Self {
+        let headline = match state.alarm_state {
+            AlarmState::Disarmed if state.alarm_memory => "DISARMED  ALARM MEM".to_string(),
+            AlarmState::Disarmed => "DISARMED".to_string(),
+            AlarmState::ExitDelay => format!("EXIT {}s", state.timers.exit_s),
+            AlarmState::Armed => format!("ARMED {}", state.arm_mode.to_string().to_uppercase()),
+            AlarmState::EntryDelay => format!("ENTRY {}s", state.timers.entry_s),
+            AlarmState::Alarm => match state.alarm_kind {
+                Some(kind) => format!("ALARM {}", kind.to_string().to_uppercase()),
+                None => "ALARM".to_string(),
+            },
+        };
+        let door = if state.door_open { "open" } else { "closed" };
+        let cloud = match state.connectivity.cloud {
+            CloudStatus::Online => "online",
+            CloudStatus::Offline => "offline",
+            CloudStatus::Connecting => "connecting",
+        };
+        let ip = ip.map_or_else(|| "--".to_string(), |ip| ip.to_string());
+
+        Self {
+            lines: vec![
+                headline,
+                format!("Door: {}", door),
+                format!("Cloud: {}", cloud),
+                format!("IP: {}", ip),
+            ],
+        }
+    }
+}
+
+/// A panel able to show a status screen; drawing may block on the bus
+pub trait Panel: Send {
+    fn show(&mut self, screen: &StatusScreen) -> Result<()>;
+}
+
+/// Address of the interface the default route leaves through, if any
+pub fn local_ip() -> Option<IpAddr> {
+    // Connecting a UDP socket only selects a route; nothing is sent
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// Keeps a panel in sync with the shared state
+pub struct DisplayController {
+    state: AppState,
+    panel: Option<Box<dyn Panel>>,
+    shown: Option<StatusScreen>,
+}
+
+impl DisplayController {
+    pub fn new(state: AppState, panel: Box<dyn Panel>) -> Self {
+        Self {
+            state,
+            panel: Some(panel),
+            shown: None,
+        }
+    }
+
+    /// Open the panel described by the configuration
+    pub fn open(config: &DisplayConfig, state: AppState) -> Result<Self> {
+        #[cfg(feature = "oled-display")]
+        {
+            let panel = OledPanel::open(config)?;
+            Ok(Self::new(state, Box::new(panel)))
+        }
+
+        #[cfg(not(feature = "oled-display"))]
+        {
+            let _ = (config, state);
+            anyhow::bail!("Display support is not compiled in; rebuild with the oled-display feature")
+        }
+    }
+
+    /// Redraw on every broadcast event and periodically until the bus closes
+    pub async fn run(mut self, mut rx: broadcast::Receiver<EventEnvelope>) {
+        info!("Status display started");
+        let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = refresh.tick() => {}
+                received = rx.recv() => match received {
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+
+            if let Err(e) = self.update().await {
+                warn!(error = %e, "Failed to update status display");
+            }
+        }
+    }
+
+    /// Draw the current state if it differs from what is shown
+    pub async fn update(&mut self) -> Result<()> {
+        let ip = tokio::task::spawn_blocking(local_ip).await?;
+        let screen = StatusScreen::render(&self.state.read(), ip);
+        if self.shown.as_ref() == Some(&screen) {
+            return Ok(());
+        }
+
+        let Some(mut panel) = self.panel.take() else {
+            error!("Status display panel lost after a failed draw");
+            return Ok(());
+        };
+        let (panel, result, screen) = tokio::task::spawn_blocking(move || {
+            let result = panel.show(&screen);
+            (panel, result, screen)
+        })
+        .await?;
+        self.panel = Some(panel);
+
+        result?;
+        debug!(lines = ?screen.lines, "Status display updated");
+        self.shown = Some(screen);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::new_app_state;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    struct RecordingPanel(Arc<Mutex<Vec<StatusScreen>>>);
+
+    impl Panel for RecordingPanel {
+        fn show(&mut self, screen: &StatusScreen) -> Result<()> {
+            self.0.lock().push(screen.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_render_countdown() {
+        let mut state = SharedState::new();
+        state.set_alarm_state(AlarmState::ExitDelay);
+        state.timers.exit_s = 25;
+        state.door_open = true;
+
+        let screen = StatusScreen::render(&state, Some("192.168.1.20".parse().unwrap()));
+        assert_eq!(
+            screen.lines,
+            vec!["EXIT 25s", "Door: open", "Cloud: offline", "IP: 192.168.1.20"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redraws_only_on_change() {
+        let state = new_app_state();
+        let drawn = Arc::new(Mutex::new(Vec::new()));
+        let mut display = DisplayController::new(state.clone(), Box::new(RecordingPanel(drawn.clone())));
+
+        display.update().await.unwrap();
+        display.update().await.unwrap();
+        assert_eq!(drawn.lock().len(), 1);
+
+        state.write().set_alarm_state(AlarmState::Alarm);
+        display.update().await.unwrap();
+        let drawn = drawn.lock();
+        assert_eq!(drawn.len(), 2);
+        assert_eq!(drawn[1].lines[0], "ALARM");
+    }
+}
@@ -0,0 +1,129 @@
+//! SSD1306 OLED panel on a Linux I2C bus
+
+use super::{Panel, StatusScreen};
+use crate::config::DisplayConfig;
+use anyhow::{anyhow, Context, Result};
+use embedded_graphics::{
+    mono_font::{ascii::FONT_5X8, ascii::FONT_6X10, MonoFont, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    text::{Baseline, Text},
+};
+use i2cdev::core::I2CDevice;
+use i2cdev::linux::{LinuxI2CDevice, LinuxI2CError};
+use ssd1306::mode::{BasicMode, BufferedGraphicsMode};
+use ssd1306::prelude::*;
+// Named like our config struct, so bring the driver trait in anonymously
+use ssd1306::prelude::DisplayConfig as _;
+use ssd1306::size::{DisplaySize, DisplaySize128x32, DisplaySize128x64};
+use ssd1306::{I2CDisplayInterface, Ssd1306};
+use tracing::info;
+
+/// Adapts an i2cdev device node to the embedded-hal I2C trait the driver expects
+struct I2cBus(LinuxI2CDevice);
+
+/// The driver folds bus errors into a generic write error, so no detail is kept
+#[derive(Debug)]
+struct I2cError;
+
+impl From<LinuxI2CError> for I2cError {
+    fn from(_: LinuxI2CError) -> Self {
+        I2cError
+    }
+}
+
+impl embedded_hal::i2c::Error for I2cError {
+    fn kind(&self) -> embedded_hal::i2c::ErrorKind {
+        embedded_hal::i2c::ErrorKind::Other
+    }
+}
+
+impl embedded_hal::i2c::ErrorType for I2cBus {
+    type Error = I2cError;
+}
+
+impl embedded_hal::i2c::I2c for I2cBus {
+    fn transaction(
+        &mut self,
+        _address: u8,
+        operations: &mut [embedded_hal::i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        // The device node is already bound to the display address
+        for operation in operations {
+            match operation {
+                embedded_hal::i2c::Operation::Write(bytes) => self.0.write(bytes)?,
+                embedded_hal::i2c::Operation::Read(buffer) => self.0.read(buffer)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+type Display<S> = Ssd1306<I2CInterface<I2cBus>, S, BufferedGraphicsMode<S>>;
+
+/// Both supported panel heights behind one type
+enum Oled {
+    Tall(Box<Display<DisplaySize128x64>>),
+    Short(Box<Display<DisplaySize128x32>>),
+}
+
+/// SSD1306 panel driven through `/dev/i2c-N`
+pub struct OledPanel {
+    oled: Oled,
+}
+
+impl OledPanel {
+    /// Open and initialize the display
+    pub fn open(config: &DisplayConfig) -> Result<Self> {
+        info!(bus = %config.i2c_bus.display(), address = config.address, "Opening OLED display");
+
+        let device = LinuxI2CDevice::new(&config.i2c_bus, config.address as u16)
+            .with_context(|| format!("Failed to open I2C bus {}", config.i2c_bus.display()))?;
+        let interface = I2CDisplayInterface::new_custom_address(I2cBus(device), config.address);
+
+        let oled = if config.short_panel {
+            Oled::Short(init(Ssd1306::new(interface, DisplaySize128x32, DisplayRotation::Rotate0))?)
+        } else {
+            Oled::Tall(init(Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0))?)
+        };
+
+        Ok(Self { oled })
+    }
+}
+
+fn init<S: DisplaySize>(display: Ssd1306<I2CInterface<I2cBus>, S, BasicMode>) -> Result<Box<Display<S>>> {
+    let mut display = Box::new(display.into_buffered_graphics_mode());
+    display
+        .init()
+        .map_err(|e| anyhow!("Failed to initialize OLED display: {:?}", e))?;
+    Ok(display)
+}
+
+/// Draw the screen lines top to bottom, dropping those that do not fit
+fn draw<S: DisplaySize>(display: &mut Display<S>, font: &MonoFont<'_>, screen: &StatusScreen) -> Result<()> {
+    display.clear_buffer();
+    let style = MonoTextStyle::new(font, BinaryColor::On);
+    let line_height = font.character_size.height as i32;
+    let rows = display.bounding_box().size.height as i32 / line_height;
+
+    for (row, line) in screen.lines.iter().take(rows as usize).enumerate() {
+        let origin = Point::new(0, row as i32 * line_height);
+        Text::with_baseline(line, origin, style, Baseline::Top)
+            .draw(display)
+            .map_err(|e| anyhow!("Failed to draw OLED text: {:?}", e))?;
+    }
+
+    display
+        .flush()
+        .map_err(|e| anyhow!("Failed to flush OLED display: {:?}", e))
+}
+
+impl Panel for OledPanel {
+    fn show(&mut self, screen: &StatusScreen) -> Result<()> {
+        match &mut self.oled {
+            Oled::Tall(display) => draw(display, &FONT_6X10, screen),
+            // Four 8-pixel rows fit the 32-pixel panel
+            Oled::Short(display) => draw(display, &FONT_5X8, screen),
+        }
+    }
+}
@@ -1,36 +1,177 @@
 //! Event bus for distributing events across the application
+//!
+//! Producers feed a bounded queue drained by the state machine, which then
+//! broadcasts envelopes to subscribers. When the queue is full the overflow
+//! policy decides whether the oldest pending event is dropped or the producer
+//! waits; either way the bus counts it so backpressure shows up in metrics.
 
-use super::{Event, EventEnvelope};
-use tokio::sync::{broadcast, mpsc};
-use tracing::{debug, error};
+use super::{current_correlation_id, Event, EventEnvelope};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{broadcast, mpsc::error::TryRecvError, Notify};
+use tracing::{debug, error, warn};
+
+/// Default number of events waiting for the state machine
+pub const DEFAULT_BUS_CAPACITY: usize = 1024;
+
+/// Default number of envelopes a slow subscriber may fall behind by
+pub const DEFAULT_BROADCAST_CAPACITY: usize = 256;
+
+/// What happens to a new event when the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Discard the oldest pending event to make room
+    #[default]
+    DropOldest,
+    /// `send` waits for room; `emit`, which cannot wait, rejects the new event
+    Block,
+}
+
+/// Point-in-time bus counters
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BusMetrics {
+    /// Events waiting for the state machine
+    pub queue_depth: usize,
+    /// Deepest the queue has been since startup
+    pub peak_depth: usize,
+    pub capacity: usize,
+    /// Envelopes not yet taken by the slowest subscriber
+    pub broadcast_depth: usize,
+    pub emitted: u64,
+    /// Events lost to the overflow policy
+    pub dropped: u64,
+    /// Envelopes each subscriber missed by falling behind the broadcast channel
+    #[serde(default)]
+    pub lagged: BTreeMap<String, u64>,
+}
+
+/// An event waiting for the state machine, with the command it stems from
+#[derive(Debug, Clone)]
+pub struct QueuedEvent {
+    pub event: Event,
+    pub correlation_id: Option<String>,
+    /// When it entered the queue, to measure how long it waited
+    pub queued_at: Instant,
+}
+
+impl QueuedEvent {
+    /// Tag an event with the correlation scope it was emitted in
+    fn new(event: Event) -> Self {
+        Self {
+            event,
+            correlation_id: current_correlation_id(),
+            queued_at: Instant::now(),
+        }
+    }
+}
+
+struct Shared {
+    queue: Mutex<VecDeque<QueuedEvent>>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    /// Wakes the receiver when an event arrives or the last sender leaves
+    item: Notify,
+    /// Wakes blocked senders when the receiver makes room or goes away
+    space: Notify,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+    peak_depth: AtomicUsize,
+    emitted: AtomicU64,
+    dropped: AtomicU64,
+    lagged: Mutex<BTreeMap<String, u64>>,
+}
 
 /// Event bus for distributing events
-#[derive(Clone)]
 pub struct EventBus {
-    /// Sender for new events
-    tx: mpsc::UnboundedSender<Event>,
+    shared: Arc<Shared>,
     /// Broadcast channel for subscribers
     broadcast_tx: broadcast::Sender<EventEnvelope>,
 }
 
+/// Receiving end of the bus queue, owned by the state machine loop
+pub struct EventReceiver {
+    shared: Arc<Shared>,
+}
+
 impl EventBus {
-    /// Create a new event bus
-    pub fn new() -> (Self, mpsc::UnboundedReceiver<Event>) {
-        let (tx, rx) = mpsc::unbounded_channel();
-        let (broadcast_tx, _) = broadcast::channel(100);
-        
-        let bus = Self { tx, broadcast_tx };
-        
-        (bus, rx)
+    /// Create a new event bus with the default capacity and policy
+    pub fn new() -> (Self, EventReceiver) {
+        Self::bounded(DEFAULT_BUS_CAPACITY, DEFAULT_BROADCAST_CAPACITY, OverflowPolicy::default())
     }
 
-    /// Emit an event to the bus
+    /// Create a new event bus holding at most `capacity` pending events
+    pub fn bounded(capacity: usize, broadcast_capacity: usize, policy: OverflowPolicy) -> (Self, EventReceiver) {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(VecDeque::with_capacity(capacity.min(DEFAULT_BUS_CAPACITY))),
+            capacity: capacity.max(1),
+            policy,
+            item: Notify::new(),
+            space: Notify::new(),
+            senders: AtomicUsize::new(1),
+            receiver_alive: AtomicBool::new(true),
+            peak_depth: AtomicUsize::new(0),
+            emitted: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            lagged: Mutex::new(BTreeMap::new()),
+        });
+        let (broadcast_tx, _) = broadcast::channel(broadcast_capacity.max(1));
+
+        let bus = Self {
+            shared: shared.clone(),
+            broadcast_tx,
+        };
+        (bus, EventReceiver { shared })
+    }
+
+    /// Emit an event to the bus without waiting
     pub fn emit(&self, event: Event) -> anyhow::Result<()> {
         debug!(?event, "Emitting event to bus");
-        self.tx.send(event).map_err(|e| {
-            error!("Failed to send event to bus: {}", e);
-            anyhow::anyhow!("Event bus send failed: {}", e)
-        })
+        self.ensure_receiver()?;
+
+        let mut queue = self.shared.queue.lock();
+        if queue.len() >= self.shared.capacity {
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+            match self.shared.policy {
+                OverflowPolicy::DropOldest => {
+                    let oldest = queue.pop_front().map(|queued| queued.event);
+                    warn!(dropped = ?oldest, "Event bus full, dropped oldest event");
+                }
+                OverflowPolicy::Block => {
+                    warn!(?event, "Event bus full, rejected event");
+                    return Err(anyhow::anyhow!("Event bus full"));
+                }
+            }
+        }
+        self.push(&mut queue, QueuedEvent::new(event));
+        Ok(())
+    }
+
+    /// Emit an event, waiting for room when the queue is full and the policy is `Block`
+    pub async fn send(&self, event: Event) -> anyhow::Result<()> {
+        if self.shared.policy == OverflowPolicy::DropOldest {
+            return self.emit(event);
+        }
+        let event = QueuedEvent::new(event);
+        loop {
+            let space = self.shared.space.notified();
+            tokio::pin!(space);
+            space.as_mut().enable();
+
+            self.ensure_receiver()?;
+            {
+                let mut queue = self.shared.queue.lock();
+                if queue.len() < self.shared.capacity {
+                    self.push(&mut queue, event.clone());
+                    return Ok(());
+                }
+            }
+            space.await;
+        }
     }
 
     /// Subscribe to all events
@@ -46,14 +187,65 @@ impl EventBus {
             subscribers = subscriber_count,
             "Broadcasting event envelope"
         );
-        
+
         // Ignore send error if there are no subscribers
         if subscriber_count > 0 {
             let _ = self.broadcast_tx.send(envelope);
         }
-        
+
         Ok(())
     }
+
+    /// Current queue depth and overflow counters
+    pub fn metrics(&self) -> BusMetrics {
+        BusMetrics {
+            queue_depth: self.shared.queue.lock().len(),
+            peak_depth: self.shared.peak_depth.load(Ordering::Relaxed),
+            capacity: self.shared.capacity,
+            broadcast_depth: self.broadcast_tx.len(),
+            emitted: self.shared.emitted.load(Ordering::Relaxed),
+            dropped: self.shared.dropped.load(Ordering::Relaxed),
+            lagged: self.shared.lagged.lock().clone(),
+        }
+    }
+
+    /// Count envelopes a subscriber missed because it fell behind
+    pub fn note_lagged(&self, subscriber: &str, missed: u64) {
+        *self.shared.lagged.lock().entry(subscriber.to_string()).or_default() += missed;
+    }
+
+    fn ensure_receiver(&self) -> anyhow::Result<()> {
+        if self.shared.receiver_alive.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        error!("Failed to send event to bus: receiver closed");
+        Err(anyhow::anyhow!("Event bus send failed: receiver closed"))
+    }
+
+    fn push(&self, queue: &mut VecDeque<QueuedEvent>, event: QueuedEvent) {
+        queue.push_back(event);
+        self.shared.peak_depth.fetch_max(queue.len(), Ordering::Relaxed);
+        self.shared.emitted.fetch_add(1, Ordering::Relaxed);
+        self.shared.item.notify_one();
+    }
+}
+
+impl Clone for EventBus {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+            broadcast_tx: self.broadcast_tx.clone(),
+        }
+    }
+}
+
+impl Drop for EventBus {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.item.notify_one();
+        }
+    }
 }
 
 impl Default for EventBus {
@@ -62,10 +254,55 @@ impl Default for EventBus {
     }
 }
 
+impl EventReceiver {
+    /// Next event, or `None` once the queue is empty and every bus handle is gone
+    pub async fn recv(&mut self) -> Option<Event> {
+        self.recv_queued().await.map(|queued| queued.event)
+    }
+
+    /// Next event with its correlation ID
+    pub async fn recv_queued(&mut self) -> Option<QueuedEvent> {
+        loop {
+            match self.try_recv_queued() {
+                Ok(queued) => return Some(queued),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => self.shared.item.notified().await,
+            }
+        }
+    }
+
+    /// Next event if one is waiting
+    pub fn try_recv(&mut self) -> Result<Event, TryRecvError> {
+        self.try_recv_queued().map(|queued| queued.event)
+    }
+
+    /// Next event with its correlation ID, if one is waiting
+    pub fn try_recv_queued(&mut self) -> Result<QueuedEvent, TryRecvError> {
+        if let Some(event) = self.shared.queue.lock().pop_front() {
+            self.shared.space.notify_waiters();
+            return Ok(event);
+        }
+        if self.shared.senders.load(Ordering::Acquire) == 0 {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::Release);
+        self.shared.space.notify_waiters();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::events::EventSource;
+    use crate::state::ArmMode;
+    use std::time::Duration;
 
     #[tokio::test]
     async fn test_event_bus_emit() {
@@ -107,6 +344,7 @@ mod tests {
             Event::UserArm {
                 source: EventSource::Local,
                 exit_delay_s: Some(30),
+                mode: ArmMode::Away,
             },
             "test".to_string()
         );
@@ -119,4 +357,67 @@ mod tests {
         assert_eq!(received1.id, envelope.id);
         assert_eq!(received2.id, envelope.id);
     }
+
+    #[tokio::test]
+    async fn test_drop_oldest_when_full() {
+        let (bus, mut rx) = EventBus::bounded(2, 16, OverflowPolicy::DropOldest);
+        bus.emit(Event::DoorOpen).unwrap();
+        bus.emit(Event::DoorClose).unwrap();
+        bus.emit(Event::GlassBreak).unwrap();
+
+        let metrics = bus.metrics();
+        assert_eq!(metrics.queue_depth, 2);
+        assert_eq!(metrics.peak_depth, 2);
+        assert_eq!(metrics.emitted, 3);
+        assert_eq!(metrics.dropped, 1);
+
+        assert!(matches!(rx.recv().await, Some(Event::DoorClose)));
+        assert!(matches!(rx.recv().await, Some(Event::GlassBreak)));
+        assert_eq!(bus.metrics().queue_depth, 0);
+
+        bus.note_lagged("journal", 2);
+        bus.note_lagged("journal", 1);
+        assert_eq!(bus.metrics().lagged.get("journal"), Some(&3));
+    }
+
+    #[tokio::test]
+    async fn test_emit_carries_correlation_scope() {
+        let (bus, mut rx) = EventBus::new();
+        crate::events::correlated_sync(Some("c123".to_string()), || bus.emit(Event::DoorOpen)).unwrap();
+        bus.emit(Event::DoorClose).unwrap();
+
+        assert_eq!(rx.recv_queued().await.unwrap().correlation_id.as_deref(), Some("c123"));
+        assert_eq!(rx.recv_queued().await.unwrap().correlation_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_block_waits_for_room() {
+        let (bus, mut rx) = EventBus::bounded(1, 16, OverflowPolicy::Block);
+        bus.emit(Event::DoorOpen).unwrap();
+
+        // Non-waiting emit refuses, waiting send resumes once the receiver drains
+        assert!(bus.emit(Event::DoorClose).is_err());
+        let sender = bus.clone();
+        let blocked = tokio::spawn(async move { sender.send(Event::GlassBreak).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!blocked.is_finished());
+
+        assert!(matches!(rx.recv().await, Some(Event::DoorOpen)));
+        blocked.await.unwrap().unwrap();
+        assert!(matches!(rx.recv().await, Some(Event::GlassBreak)));
+        assert_eq!(bus.metrics().dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn test_closed_ends() {
+        let (bus, mut rx) = EventBus::new();
+        bus.emit(Event::DoorOpen).unwrap();
+        drop(bus);
+        assert!(rx.recv().await.is_some());
+        assert!(rx.recv().await.is_none());
+
+        let (bus, rx) = EventBus::new();
+        drop(rx);
+        assert!(bus.emit(Event::DoorOpen).is_err());
+    }
 }
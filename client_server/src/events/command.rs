@@ -0,0 +1,155 @@
+//! Mapping of named remote commands onto events
+
+use super::{Event, EventSource};
+use crate::actuators::SirenPattern;
+use crate::state::ArmMode;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+/// Translate a named command and its arguments into an event.
+///
+/// Shared by the local WebSocket and the cloud link so both accept the same
+/// command vocabulary.
+pub fn command_to_event(name: &str, args: &Value, source: EventSource) -> Result<Event> {
+    let event = match name {
+        "arm" => {
+            let exit_delay = args.get("exit_delay_s").and_then(|v| v.as_u64());
+            let mode = args
+                .get("mode")
+                .cloned()
+                .map(serde_json::from_value::<ArmMode>)
+                .transpose()?
+                .unwrap_or_default();
+            Event::UserArm {
+                source,
+                exit_delay_s: exit_delay,
+                mode,
+            }
+        }
+        "disarm" => {
+            let auto_rearm = args.get("auto_rearm_s").and_then(|v| v.as_u64());
+            Event::UserDisarm {
+                source,
+                auto_rearm_s: auto_rearm,
+            }
+        }
+        "ack" => Event::AlarmAcknowledged { source },
+        "panic" => Event::Panic { source },
+        "siren" => {
+            let on = args.get("on").and_then(|v| v.as_bool()).unwrap_or(false);
+            let duration = args.get("duration_s").and_then(|v| v.as_u64());
+            let pattern = args
+                .get("pattern")
+                .cloned()
+                .map(serde_json::from_value::<SirenPattern>)
+                .transpose()?;
+            Event::SirenControl {
+                on,
+                duration_s: duration,
+                pattern,
+            }
+        }
+        "floodlight" => {
+            let on = args.get("on").and_then(|v| v.as_bool()).unwrap_or(false);
+            let duration = args.get("duration_s").and_then(|v| v.as_u64());
+            Event::FloodlightControl {
+                on,
+                duration_s: duration,
+            }
+        }
+        "output" => {
+            let output = args
+                .get("output")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| anyhow!("output command requires an 'output' name"))?;
+            let on = args.get("on").and_then(|v| v.as_bool()).unwrap_or(false);
+            let duration = args.get("duration_s").and_then(|v| v.as_u64());
+            Event::OutputControl {
+                name: output.to_string(),
+                on,
+                duration_s: duration,
+            }
+        }
+        "bypass" => {
+            let zone = args
+                .get("zone")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| anyhow!("bypass command requires a 'zone' name"))?;
+            let bypassed = args.get("bypassed").and_then(|v| v.as_bool()).unwrap_or(true);
+            Event::ZoneBypassed {
+                zone: zone.to_string(),
+                bypassed,
+            }
+        }
+        _ => {
+            return Err(anyhow!("Unknown command: {}", name));
+        }
+    };
+
+    Ok(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_arm_command() {
+        let event = command_to_event(
+            "arm",
+            &json!({"exit_delay_s": 10, "mode": "night"}),
+            EventSource::Cloud,
+        )
+        .unwrap();
+
+        match event {
+            Event::UserArm { source, exit_delay_s, mode } => {
+                assert_eq!(source, EventSource::Cloud);
+                assert_eq!(exit_delay_s, Some(10));
+                assert_eq!(mode, ArmMode::Night);
+            }
+            _ => panic!("Wrong event type"),
+        }
+    }
+
+    #[test]
+    fn test_output_command() {
+        let event = command_to_event(
+            "output",
+            &json!({"output": "gate", "on": true, "duration_s": 2}),
+            EventSource::Ws,
+        )
+        .unwrap();
+
+        match event {
+            Event::OutputControl { name, on, duration_s } => {
+                assert_eq!(name, "gate");
+                assert!(on);
+                assert_eq!(duration_s, Some(2));
+            }
+            _ => panic!("Wrong event type"),
+        }
+
+        assert!(command_to_event("output", &json!({"on": true}), EventSource::Ws).is_err());
+    }
+
+    #[test]
+    fn test_bypass_command() {
+        let event = command_to_event("bypass", &json!({"zone": "garage"}), EventSource::Ws).unwrap();
+        match event {
+            Event::ZoneBypassed { zone, bypassed } => {
+                assert_eq!(zone, "garage");
+                assert!(bypassed);
+            }
+            _ => panic!("Wrong event type"),
+        }
+
+        assert!(command_to_event("bypass", &json!({}), EventSource::Ws).is_err());
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        assert!(command_to_event("reboot", &json!({}), EventSource::Ws).is_err());
+    }
+}
@@ -0,0 +1,49 @@
+//! Correlation IDs tying events back to the command that caused them
+//!
+//! Entry points run each command inside a correlation scope. Anything emitted
+//! on the bus within it, including events the state machine and its timers
+//! derive later, carries the same ID through to envelopes and acks.
+
+use std::future::Future;
+use uuid::Uuid;
+
+/// Header carrying a caller-chosen ID on HTTP requests and responses
+pub const CORRELATION_HEADER: &str = "x-correlation-id";
+
+tokio::task_local! {
+    static CORRELATION_ID: Option<String>;
+}
+
+/// Fresh ID for a command that arrived without one
+pub fn new_correlation_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// ID of the command being handled on this task, if any
+pub fn current_correlation_id() -> Option<String> {
+    CORRELATION_ID.try_with(Clone::clone).ok().flatten()
+}
+
+/// Run a future with `id` as the current correlation ID
+pub async fn correlated<F: Future>(id: Option<String>, future: F) -> F::Output {
+    CORRELATION_ID.scope(id, future).await
+}
+
+/// Run a closure with `id` as the current correlation ID
+pub fn correlated_sync<R>(id: Option<String>, f: impl FnOnce() -> R) -> R {
+    CORRELATION_ID.sync_scope(id, f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scope_is_visible_only_inside() {
+        assert_eq!(current_correlation_id(), None);
+        let inside = correlated(Some("c123".to_string()), async { current_correlation_id() }).await;
+        assert_eq!(inside.as_deref(), Some("c123"));
+        assert_eq!(correlated_sync(None, current_correlation_id), None);
+        assert_eq!(current_correlation_id(), None);
+    }
+}
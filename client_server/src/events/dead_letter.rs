@@ -0,0 +1,252 @@
+//! Dead-letter store for events the state machine failed to process
+//!
+//! A failing event is retried a few times with a growing backoff; if it still
+//! fails it lands here instead of vanishing into the log, where the API can
+//! inspect it and put it back on the bus.
+
+use super::{Event, QueuedEvent};
+use crate::config::DeadLetterConfig;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// How often and how patiently a failing event is retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first
+    pub max_attempts: u32,
+    /// Wait before the second attempt, doubled for each one after
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &DeadLetterConfig) -> Self {
+        Self {
+            max_attempts: config.max_attempts.max(1),
+            backoff: Duration::from_millis(config.retry_backoff_ms),
+        }
+    }
+
+    /// Wait before attempt number `attempt` (1-based)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.backoff * 2u32.saturating_pow(attempt.saturating_sub(2))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::from_config(&DeadLetterConfig::default())
+    }
+}
+
+/// An event that exhausted its retries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// Increasing with every entry, so ids also order entries by age
+    pub id: u64,
+    pub event: Event,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Error from the last attempt
+    pub error: String,
+    pub attempts: u32,
+    pub failed_at: DateTime<Utc>,
+}
+
+impl DeadLetter {
+    /// The event as it was queued, ready to go back on the bus
+    pub fn into_queued(self) -> QueuedEvent {
+        QueuedEvent {
+            event: self.event,
+            correlation_id: self.correlation_id,
+            queued_at: Instant::now(),
+        }
+    }
+}
+
+/// Dead-letter counters since startup
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeadLetterMetrics {
+    /// Entries waiting for inspection
+    pub pending: usize,
+    /// Processing attempts that failed, retries included
+    pub failures: u64,
+    /// Events that succeeded on a retry
+    pub recovered: u64,
+    pub dead_lettered: u64,
+    pub requeued: u64,
+    /// Entries discarded because the store was full
+    pub evicted: u64,
+}
+
+/// Failed events in their own sled database, oldest first
+pub struct DeadLetterStore {
+    db: sled::Db,
+    max_entries: usize,
+    failures: AtomicU64,
+    recovered: AtomicU64,
+    dead_lettered: AtomicU64,
+    requeued: AtomicU64,
+    evicted: AtomicU64,
+}
+
+impl DeadLetterStore {
+    /// Open or create the store
+    pub fn open<P: AsRef<Path>>(path: P, config: &DeadLetterConfig) -> Result<Self> {
+        let db = sled::open(path.as_ref()).context("Failed to open dead-letter database")?;
+        Ok(Self {
+            db,
+            max_entries: config.max_entries.max(1),
+            failures: AtomicU64::new(0),
+            recovered: AtomicU64::new(0),
+            dead_lettered: AtomicU64::new(0),
+            requeued: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+        })
+    }
+
+    /// Count a failed processing attempt
+    pub fn note_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an event that went through on a retry
+    pub fn note_recovered(&self) {
+        self.recovered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Store an event that exhausted its retries, evicting the oldest when full
+    pub fn record(&self, queued: QueuedEvent, error: &str, attempts: u32) -> Result<DeadLetter> {
+        let letter = DeadLetter {
+            id: self.db.generate_id().context("Failed to allocate dead-letter id")?,
+            event: queued.event,
+            correlation_id: queued.correlation_id,
+            error: error.to_string(),
+            attempts,
+            failed_at: Utc::now(),
+        };
+        let value = serde_json::to_vec(&letter).context("Failed to serialize dead letter")?;
+        self.db
+            .insert(letter.id.to_be_bytes(), value)
+            .context("Failed to write dead letter")?;
+        self.dead_lettered.fetch_add(1, Ordering::Relaxed);
+
+        let excess = self.len().saturating_sub(self.max_entries);
+        for entry in self.db.iter().keys().take(excess) {
+            let key = entry.context("Failed to read dead letters")?;
+            self.db.remove(key).context("Failed to evict dead letter")?;
+            self.evicted.fetch_add(1, Ordering::Relaxed);
+            warn!("Dead-letter store full, evicted oldest entry");
+        }
+        Ok(letter)
+    }
+
+    /// Every entry, oldest first
+    pub fn list(&self) -> Result<Vec<DeadLetter>> {
+        self.db
+            .iter()
+            .values()
+            .map(|value| {
+                let value = value.context("Failed to read dead letters")?;
+                serde_json::from_slice(&value).context("Failed to deserialize dead letter")
+            })
+            .collect()
+    }
+
+    /// Remove an entry so it can be requeued; `None` if there is no such entry
+    pub fn take(&self, id: u64) -> Result<Option<DeadLetter>> {
+        let Some(value) = self.db.remove(id.to_be_bytes()).context("Failed to remove dead letter")? else {
+            return Ok(None);
+        };
+        let letter = serde_json::from_slice(&value).context("Failed to deserialize dead letter")?;
+        self.requeued.fetch_add(1, Ordering::Relaxed);
+        Ok(Some(letter))
+    }
+
+    /// Drop an entry for good; returns false if there was no such entry
+    pub fn discard(&self, id: u64) -> Result<bool> {
+        Ok(self
+            .db
+            .remove(id.to_be_bytes())
+            .context("Failed to remove dead letter")?
+            .is_some())
+    }
+
+    pub fn len(&self) -> usize {
+        self.db.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.db.is_empty()
+    }
+
+    pub fn metrics(&self) -> DeadLetterMetrics {
+        DeadLetterMetrics {
+            pending: self.len(),
+            failures: self.failures.load(Ordering::Relaxed),
+            recovered: self.recovered.load(Ordering::Relaxed),
+            dead_lettered: self.dead_lettered.load(Ordering::Relaxed),
+            requeued: self.requeued.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn queued(event: Event) -> QueuedEvent {
+        QueuedEvent {
+            event,
+            correlation_id: Some("c123".to_string()),
+            queued_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_record_take_and_evict() {
+        let dir = TempDir::new().unwrap();
+        let config = DeadLetterConfig {
+            max_entries: 2,
+            ..DeadLetterConfig::default()
+        };
+        let store = DeadLetterStore::open(dir.path(), &config).unwrap();
+
+        let first = store.record(queued(Event::DoorOpen), "timer task gone", 3).unwrap();
+        store.record(queued(Event::DoorClose), "timer task gone", 3).unwrap();
+        store.record(queued(Event::GlassBreak), "timer task gone", 3).unwrap();
+
+        // The oldest entry made room for the newest
+        let letters = store.list().unwrap();
+        assert_eq!(letters.len(), 2);
+        assert!(matches!(letters[0].event, Event::DoorClose));
+        assert!(store.take(first.id).unwrap().is_none());
+
+        let requeued = store.take(letters[1].id).unwrap().unwrap().into_queued();
+        assert!(matches!(requeued.event, Event::GlassBreak));
+        assert_eq!(requeued.correlation_id.as_deref(), Some("c123"));
+
+        let metrics = store.metrics();
+        assert_eq!(metrics.pending, 1);
+        assert_eq!(metrics.dead_lettered, 3);
+        assert_eq!(metrics.evicted, 1);
+        assert_eq!(metrics.requeued, 1);
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            backoff: Duration::from_millis(100),
+        };
+        assert_eq!(policy.delay(2), Duration::from_millis(100));
+        assert_eq!(policy.delay(3), Duration::from_millis(200));
+        assert_eq!(policy.delay(4), Duration::from_millis(400));
+    }
+}
@@ -0,0 +1,317 @@
+//! Local journal of event envelopes, kept for forensics and the events API
+//!
+//! Unlike the cloud queue, nothing is removed once delivered: entries only
+//! leave the journal when they fall outside the retention limits.
+
+use super::{EventBus, EventEnvelope, Severity};
+use crate::config::JournalConfig;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Retention is enforced after this many new entries rather than on every write
+const PRUNE_EVERY: usize = 100;
+
+/// Most entries a single query returns
+pub const MAX_QUERY_LIMIT: usize = 1000;
+
+/// Filter for journal queries; results are newest first
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EventQuery {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Event `type` name, e.g. `door_open`
+    #[serde(rename = "type")]
+    pub event_type: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl EventQuery {
+    /// Whether an envelope passes the filter
+    pub fn matches(&self, envelope: &EventEnvelope) -> bool {
+        self.since.is_none_or(|since| envelope.timestamp >= since)
+            && self.until.is_none_or(|until| envelope.timestamp < until)
+            && self
+                .event_type
+                .as_deref()
+                .is_none_or(|wanted| envelope.event.type_name() == wanted)
+    }
+
+    /// Requested limit, capped at `MAX_QUERY_LIMIT`
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(100).min(MAX_QUERY_LIMIT)
+    }
+}
+
+/// Append-only event history in its own sled database
+pub struct EventJournal {
+    db: sled::Db,
+    path: PathBuf,
+    min_severity: Severity,
+    max_events: usize,
+    max_age: Duration,
+    since_prune: AtomicUsize,
+}
+
+impl EventJournal {
+    /// Open or create the journal and apply retention to what is already there
+    pub fn open<P: AsRef<Path>>(path: P, config: &JournalConfig) -> Result<Self> {
+        let db = sled::open(path.as_ref()).context("Failed to open event journal database")?;
+        let journal = Self {
+            db,
+            path: path.as_ref().to_path_buf(),
+            min_severity: config.min_severity,
+            max_events: config.max_events,
+            max_age: Duration::days(config.max_age_days as i64),
+            since_prune: AtomicUsize::new(0),
+        };
+        journal.prune()?;
+        Ok(journal)
+    }
+
+    /// Append an envelope unless it is below the journal's minimum severity
+    pub fn record(&self, envelope: &EventEnvelope) -> Result<()> {
+        if envelope.severity < self.min_severity {
+            return Ok(());
+        }
+        let value = serde_json::to_vec(envelope).context("Failed to serialize event envelope")?;
+        self.db
+            .insert(make_key(&envelope.timestamp, &envelope.id), value)
+            .context("Failed to write event to journal")?;
+
+        if self.since_prune.fetch_add(1, Ordering::Relaxed) + 1 >= PRUNE_EVERY {
+            self.since_prune.store(0, Ordering::Relaxed);
+            self.prune()?;
+        }
+        Ok(())
+    }
+
+    /// Entries matching the query, newest first
+    pub fn query(&self, query: &EventQuery) -> Result<Vec<EventEnvelope>> {
+        let mut events = Vec::new();
+        for envelope in self.scan(query).rev() {
+            let envelope = envelope?;
+            if query.matches(&envelope) {
+                events.push(envelope);
+                if events.len() >= query.limit() {
+                    break;
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    /// Write every entry matching the query as NDJSON, oldest first, ignoring the limit
+    pub fn export<W: Write>(&self, query: &EventQuery, out: &mut W) -> Result<usize> {
+        let mut count = 0;
+        for envelope in self.scan(query) {
+            let envelope = envelope?;
+            if query.matches(&envelope) {
+                serde_json::to_writer(&mut *out, &envelope)?;
+                out.write_all(b"\n")?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Entries within the query's time range, oldest first
+    fn scan(&self, query: &EventQuery) -> impl DoubleEndedIterator<Item = Result<EventEnvelope>> {
+        let start = query.since.map(|since| time_key(&since)).unwrap_or_default();
+        let entries = match query.until {
+            Some(until) => self.db.range(start..time_key(&until)),
+            None => self.db.range(start..),
+        };
+        entries.map(|entry| {
+            let (_key, value) = entry.context("Failed to read from journal")?;
+            serde_json::from_slice(&value).context("Failed to deserialize journal entry")
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.db.len()
+    }
+
+    /// Flush outstanding writes and release the database, e.g. before reopening it
+    pub fn close(self) -> Result<()> {
+        close_db(self.db, &self.path).context("Failed to close event journal")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.db.is_empty()
+    }
+
+    /// Drop entries older than the maximum age, then the oldest beyond the maximum count
+    pub fn prune(&self) -> Result<()> {
+        let cutoff = time_key(&(Utc::now() - self.max_age));
+        let mut removed = 0;
+        for entry in self.db.range(..cutoff) {
+            let (key, _) = entry.context("Failed to read from journal during pruning")?;
+            self.db.remove(key).context("Failed to remove old journal entry")?;
+            removed += 1;
+        }
+
+        let excess = self.len().saturating_sub(self.max_events);
+        for entry in self.db.iter().take(excess) {
+            let (key, _) = entry.context("Failed to read from journal during pruning")?;
+            self.db.remove(key).context("Failed to remove excess journal entry")?;
+            removed += 1;
+        }
+
+        if removed > 0 {
+            debug!(removed, remaining = self.len(), "Pruned event journal");
+        }
+        Ok(())
+    }
+
+    /// Record every broadcast envelope until the bus closes
+    pub fn spawn(self: std::sync::Arc<Self>, event_bus: &EventBus) -> JoinHandle<()> {
+        let mut rx = event_bus.subscribe();
+        let event_bus = event_bus.clone();
+        tokio::spawn(async move {
+            info!(entries = self.len(), "Event journal started");
+            loop {
+                match rx.recv().await {
+                    Ok(envelope) => {
+                        if let Err(e) = self.record(&envelope) {
+                            warn!(error = %e, "Failed to journal event");
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        event_bus.note_lagged("journal", missed);
+                        warn!(missed, "Event journal fell behind, events not recorded");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
+
+/// Flush a sled database and return once its file lock is released
+///
+/// sled finishes background writes on its own threads after the last handle
+/// is dropped, and only then lets go of the lock; waiting for it means the
+/// database can be opened again straight away.
+pub(crate) fn close_db(db: sled::Db, path: &Path) -> Result<()> {
+    db.flush()?;
+    drop(db);
+    let file = std::fs::File::open(path.join("db"))?;
+    file.lock()?;
+    Ok(())
+}
+
+/// Key prefix sorting entries by time
+pub(crate) fn time_key(timestamp: &DateTime<Utc>) -> Vec<u8> {
+    timestamp.timestamp_nanos_opt().unwrap_or(0).to_be_bytes().to_vec()
+}
+
+/// Timestamp followed by the envelope id, so equal timestamps stay distinct
+pub(crate) fn make_key(timestamp: &DateTime<Utc>, id: &uuid::Uuid) -> Vec<u8> {
+    let mut key = time_key(timestamp);
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+    use tempfile::TempDir;
+
+    fn config(max_events: usize) -> JournalConfig {
+        JournalConfig {
+            max_events,
+            ..JournalConfig::default()
+        }
+    }
+
+    fn envelope_at(event: Event, timestamp: DateTime<Utc>) -> EventEnvelope {
+        let mut envelope = EventEnvelope::new(event, "test".to_string());
+        envelope.timestamp = timestamp;
+        envelope
+    }
+
+    #[test]
+    fn test_query_filters_newest_first() {
+        let dir = TempDir::new().unwrap();
+        let journal = EventJournal::open(dir.path(), &config(100)).unwrap();
+        let start = Utc::now() - Duration::minutes(10);
+        for minute in 0..6 {
+            let event = if minute % 2 == 0 { Event::DoorOpen } else { Event::DoorClose };
+            journal.record(&envelope_at(event, start + Duration::minutes(minute))).unwrap();
+        }
+
+        let all = journal.query(&EventQuery::default()).unwrap();
+        assert_eq!(all.len(), 6);
+        assert!(all[0].timestamp > all[5].timestamp);
+
+        let opens = journal
+            .query(&EventQuery {
+                since: Some(start + Duration::minutes(1)),
+                event_type: Some("door_open".to_string()),
+                ..EventQuery::default()
+            })
+            .unwrap();
+        assert_eq!(opens.len(), 2);
+        assert!(opens.iter().all(|e| matches!(e.event, Event::DoorOpen)));
+
+        let window = journal
+            .query(&EventQuery {
+                until: Some(start + Duration::minutes(3)),
+                limit: Some(2),
+                ..EventQuery::default()
+            })
+            .unwrap();
+        assert_eq!(window.len(), 2);
+        assert_eq!(window[0].timestamp, start + Duration::minutes(2));
+    }
+
+    #[test]
+    fn test_export_ndjson() {
+        let dir = TempDir::new().unwrap();
+        let journal = EventJournal::open(dir.path(), &config(100)).unwrap();
+        let start = Utc::now() - Duration::minutes(5);
+        journal.record(&envelope_at(Event::DoorOpen, start)).unwrap();
+        journal.record(&envelope_at(Event::DoorClose, start + Duration::minutes(1))).unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(journal.export(&EventQuery::default(), &mut out).unwrap(), 2);
+        let lines: Vec<EventEnvelope> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(matches!(lines[0].event, Event::DoorOpen));
+        assert!(matches!(lines[1].event, Event::DoorClose));
+    }
+
+    #[test]
+    fn test_retention() {
+        let dir = TempDir::new().unwrap();
+        let journal = EventJournal::open(dir.path(), &config(5)).unwrap();
+        journal
+            .record(&envelope_at(Event::DoorOpen, Utc::now() - Duration::days(60)))
+            .unwrap();
+        for _ in 0..8 {
+            journal.record(&EventEnvelope::new(Event::DoorOpen, "test".to_string())).unwrap();
+        }
+        assert_eq!(journal.len(), 9);
+
+        // Telemetry stays out at the default minimum severity
+        let reading = Event::TemperatureReading { sensor: "attic".to_string(), celsius: 20.5 };
+        journal.record(&EventEnvelope::new(reading, "test".to_string())).unwrap();
+        assert_eq!(journal.len(), 9);
+        journal.close().unwrap();
+
+        // Retention is applied again when the journal is reopened
+        let journal = EventJournal::open(dir.path(), &config(5)).unwrap();
+        assert_eq!(journal.len(), 5);
+    }
+}
@@ -3,7 +3,25 @@
 mod types;
 mod bus;
 mod queue;
+mod journal;
+mod sequence;
+mod command;
+mod correlation;
+mod dead_letter;
+mod sink;
 
 pub use types::*;
-pub use bus::EventBus;
-pub use queue::EventQueue;
+pub use bus::{
+    BusMetrics, EventBus, EventReceiver, OverflowPolicy, QueuedEvent, DEFAULT_BROADCAST_CAPACITY, DEFAULT_BUS_CAPACITY,
+};
+pub use queue::{EventQueue, QueueRecovery};
+pub use journal::{EventJournal, EventQuery, MAX_QUERY_LIMIT};
+pub(crate) use journal::{close_db, make_key, time_key};
+pub use sequence::SequenceCounter;
+pub use command::command_to_event;
+pub(crate) use sink::mqtt;
+pub use sink::{build_sink, spawn_sink, spawn_sinks, EventSink, FileSink, MqttSink, SinkHandle, SinkStats, WebhookSink};
+pub use dead_letter::{DeadLetter, DeadLetterMetrics, DeadLetterStore, RetryPolicy};
+pub use correlation::{
+    correlated, correlated_sync, current_correlation_id, new_correlation_id, CORRELATION_HEADER,
+};
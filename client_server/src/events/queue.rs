@@ -1,12 +1,41 @@
 //! Disk-backed event queue for offline persistence
 
-use super::EventEnvelope;
+use super::{Event, EventEnvelope};
 use anyhow::{Context, Result};
 use chrono::{DateTime, Duration, Utc};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tracing::{debug, warn};
 
-/// Event queue with disk persistence
+/// What was lost when a corrupt queue database was replaced
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueRecovery {
+    /// Why the database was given up
+    pub error: String,
+    /// Where the corrupt database was moved
+    pub moved_to: PathBuf,
+    /// Size of the corrupt database in bytes
+    pub bytes: u64,
+    /// Readable events copied into the new queue
+    pub salvaged: usize,
+    /// Entries that could not be decoded; events past a storage error are not counted
+    pub unreadable: usize,
+}
+
+impl QueueRecovery {
+    /// The `queue_recovered` event reporting this recovery
+    pub fn event(&self) -> Event {
+        Event::QueueRecovered {
+            error: self.error.clone(),
+            moved_to: self.moved_to.display().to_string(),
+            bytes: self.bytes,
+            salvaged: self.salvaged,
+            unreadable: self.unreadable,
+        }
+    }
+}
+
+/// Event queue with disk persistence; clones share the same database
+#[derive(Clone)]
 pub struct EventQueue {
     db: sled::Db,
     max_events: usize,
@@ -23,13 +52,80 @@ impl EventQueue {
         let db = sled::open(path.as_ref())
             .context("Failed to open event queue database")?;
 
-        let max_age = Duration::days(max_age_days as i64);
+        Ok(Self::new_with_db(db, max_events, max_age_days))
+    }
+
+    /// Open the queue at `path`, replacing it with a fresh one if it is corrupt
+    ///
+    /// A database that fails to open or holds entries that cannot be read is
+    /// moved aside to `<path>.corrupt-<timestamp>`, and the events still readable
+    /// are carried over, so the agent boots with a working queue.
+    pub fn open_or_recover<P: AsRef<Path>>(
+        path: P,
+        max_events: usize,
+        max_age_days: u32,
+    ) -> Result<(Self, Option<QueueRecovery>)> {
+        let path = path.as_ref();
+        let (db, error) = match sled::open(path) {
+            Ok(db) => match Self::verify(&db) {
+                Ok(()) => return Ok((Self::new_with_db(db, max_events, max_age_days), None)),
+                Err(e) => (Some(db), e),
+            },
+            Err(e) => (None, anyhow::Error::new(e).context("Failed to open event queue database")),
+        };
+        warn!(error = %format!("{:#}", error), path = %path.display(), "Event queue database corrupt, replacing it");
+
+        // Keep what can still be read before letting go of the database
+        let mut salvaged = Vec::new();
+        let mut unreadable = 0;
+        for result in db.iter().flat_map(|db| db.iter()) {
+            let Ok((key, value)) = result else { break };
+            if serde_json::from_slice::<EventEnvelope>(&value).is_ok() {
+                salvaged.push((key, value));
+            } else {
+                unreadable += 1;
+            }
+        }
+        drop(db);
+
+        let bytes = dir_size(path);
+        let mut moved_to = path.as_os_str().to_owned();
+        moved_to.push(format!(".corrupt-{}", Utc::now().format("%Y%m%dT%H%M%S")));
+        let moved_to = PathBuf::from(moved_to);
+        std::fs::rename(path, &moved_to)
+            .with_context(|| format!("Failed to move corrupt event queue to {}", moved_to.display()))?;
+
+        let queue = Self::new(path, max_events, max_age_days)?;
+        for (key, value) in &salvaged {
+            queue.db.insert(key, value).context("Failed to carry over a salvaged event")?;
+        }
+        queue.db.flush().context("Failed to flush recovered queue")?;
+
+        let recovery = QueueRecovery {
+            error: format!("{:#}", error),
+            moved_to,
+            bytes,
+            salvaged: salvaged.len(),
+            unreadable,
+        };
+        Ok((queue, Some(recovery)))
+    }
 
-        Ok(Self {
+    fn new_with_db(db: sled::Db, max_events: usize, max_age_days: u32) -> Self {
+        Self {
             db,
             max_events,
-            max_age,
-        })
+            max_age: Duration::days(max_age_days as i64),
+        }
+    }
+
+    /// Read every entry back, failing on the first that cannot be read or decoded
+    fn verify(db: &sled::Db) -> Result<()> {
+        for result in db.iter() {
+            let (_key, value) = result.context("Failed to read from queue")?;
+            serde_json::from_slice::<EventEnvelope>(&value).context("Failed to deserialize event envelope")?;
+        }
+        Ok(())
     }
 
     /// Enqueue an event envelope
@@ -90,6 +186,26 @@ impl EventQueue {
         Ok(self.len()? == 0)
     }
 
+    /// Space the database takes on disk, in bytes
+    pub fn size_on_disk(&self) -> Result<u64> {
+        self.db.size_on_disk().context("Failed to read queue size on disk")
+    }
+
+    /// Drop the oldest events so at most `keep` remain, returning how many were dropped
+    pub fn prune_oldest(&self, keep: usize) -> Result<usize> {
+        let excess = self.len()?.saturating_sub(keep);
+        let mut removed = 0;
+        for result in self.db.iter().take(excess) {
+            let (key, _) = result.context("Failed to read during count pruning")?;
+            self.db.remove(key).context("Failed to remove excess event")?;
+            removed += 1;
+        }
+        if removed > 0 {
+            self.db.flush().context("Failed to flush queue after pruning")?;
+        }
+        Ok(removed)
+    }
+
     /// Clear all events from the queue
     pub fn clear(&self) -> Result<()> {
         self.db.clear().context("Failed to clear queue")?;
@@ -126,24 +242,13 @@ impl EventQueue {
         }
 
         // Prune by count (keep only max_events newest)
-        let after_age_prune = self.len()?;
-        if after_age_prune > self.max_events {
-            let to_remove = after_age_prune - self.max_events;
-            let mut removed = 0;
-
-            for result in self.db.iter().take(to_remove) {
-                let (key, _) = result.context("Failed to read during count pruning")?;
-                self.db.remove(key).context("Failed to remove excess event")?;
-                removed += 1;
-            }
-
-            if removed > 0 {
-                warn!(
-                    removed,
-                    max_events = self.max_events,
-                    "Pruned excess events from queue"
-                );
-            }
+        let removed = self.prune_oldest(self.max_events)?;
+        if removed > 0 {
+            warn!(
+                removed,
+                max_events = self.max_events,
+                "Pruned excess events from queue"
+            );
         }
 
         Ok(())
@@ -159,10 +264,17 @@ impl EventQueue {
     }
 }
 
+/// Total size of the files under `path`, as far as they can be read
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return std::fs::metadata(path).map(|meta| meta.len()).unwrap_or_default();
+    };
+    entries.flatten().map(|entry| dir_size(&entry.path())).sum()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::events::Event;
     use tempfile::TempDir;
 
     #[test]
@@ -208,6 +320,69 @@ mod tests {
         assert_eq!(queue.len().unwrap(), 5);
     }
 
+    #[test]
+    fn test_queue_prune_oldest() {
+        let temp_dir = TempDir::new().unwrap();
+        let queue = EventQueue::new(temp_dir.path(), 100, 7).unwrap();
+
+        let envelopes: Vec<_> = (0..5)
+            .map(|i| {
+                let mut envelope = EventEnvelope::new(Event::DoorOpen, "test".to_string());
+                envelope.timestamp = Utc::now() - Duration::seconds(10 - i);
+                envelope
+            })
+            .collect();
+        for envelope in &envelopes {
+            queue.enqueue(envelope.clone()).unwrap();
+        }
+
+        assert_eq!(queue.clone().prune_oldest(2).unwrap(), 3);
+        let batch = queue.dequeue_batch(10).unwrap();
+        assert_eq!(batch.iter().map(|e| e.id).collect::<Vec<_>>(), [envelopes[3].id, envelopes[4].id]);
+        assert_eq!(queue.prune_oldest(2).unwrap(), 0);
+        assert!(queue.size_on_disk().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_corrupt_queue_recovered() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("queue");
+
+        let envelope = EventEnvelope::new(Event::DoorOpen, "test".to_string());
+        {
+            let queue = EventQueue::new(&path, 100, 7).unwrap();
+            queue.enqueue(envelope.clone()).unwrap();
+            queue.db.insert(b"garbage", b"not an envelope".to_vec()).unwrap();
+            queue.db.flush().unwrap();
+        }
+
+        let (queue, recovery) = EventQueue::open_or_recover(&path, 100, 7).unwrap();
+        let recovery = recovery.unwrap();
+        assert_eq!((recovery.salvaged, recovery.unreadable), (1, 1));
+        assert!(recovery.moved_to.exists() && recovery.bytes > 0);
+        assert_eq!(queue.dequeue_batch(10).unwrap()[0].id, envelope.id);
+        assert_eq!(queue.len().unwrap(), 1);
+
+        // A healthy queue opens as it is
+        drop(queue);
+        assert!(EventQueue::open_or_recover(&path, 100, 7).unwrap().1.is_none());
+    }
+
+    #[test]
+    fn test_unopenable_queue_replaced() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("queue");
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("conf"), b"\xff\xfe not a sled config").unwrap();
+        std::fs::write(path.join("db"), b"garbage").unwrap();
+
+        let (queue, recovery) = EventQueue::open_or_recover(&path, 100, 7).unwrap();
+        let recovery = recovery.unwrap();
+        assert_eq!(recovery.salvaged, 0);
+        assert!(recovery.moved_to.join("db").exists());
+        assert!(queue.is_empty().unwrap());
+    }
+
     #[test]
     fn test_queue_persistence() {
         let temp_dir = TempDir::new().unwrap();
@@ -0,0 +1,103 @@
+//! Per-client event sequence numbers
+//!
+//! Every envelope that enters the event history gets the next number, so the
+//! master can spot a gap (lost events) or a repeat (redelivery). The
+//! counter is flushed to disk on each step so numbers are never reused after
+//! a restart or power cut.
+
+use super::close_db;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const COUNTER_KEY: &[u8] = b"seq";
+
+enum Backing {
+    Memory(AtomicU64),
+    Sled(sled::Db, PathBuf),
+}
+
+/// Monotonic counter; the first number handed out is 1, 0 means unsequenced
+pub struct SequenceCounter {
+    backing: Backing,
+}
+
+impl SequenceCounter {
+    /// Counter that starts again from 1 on every run
+    pub fn in_memory() -> Self {
+        Self {
+            backing: Backing::Memory(AtomicU64::new(0)),
+        }
+    }
+
+    /// Open or create a counter persisted in its own sled database
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let db = sled::open(path.as_ref()).context("Failed to open sequence database")?;
+        Ok(Self {
+            backing: Backing::Sled(db, path.as_ref().to_path_buf()),
+        })
+    }
+
+    /// Last number handed out
+    pub fn current(&self) -> Result<u64> {
+        match &self.backing {
+            Backing::Memory(counter) => Ok(counter.load(Ordering::SeqCst)),
+            Backing::Sled(db, _) => Ok(db
+                .get(COUNTER_KEY)
+                .context("Failed to read sequence counter")?
+                .map(|value| decode(&value))
+                .unwrap_or(0)),
+        }
+    }
+
+    /// Advance the counter and return the new value
+    pub fn next(&self) -> Result<u64> {
+        match &self.backing {
+            Backing::Memory(counter) => Ok(counter.fetch_add(1, Ordering::SeqCst) + 1),
+            Backing::Sled(db, _) => {
+                let value = db
+                    .update_and_fetch(COUNTER_KEY, |old| {
+                        let next = old.map(decode).unwrap_or(0) + 1;
+                        Some(next.to_be_bytes().to_vec())
+                    })
+                    .context("Failed to advance sequence counter")?
+                    .map(|value| decode(&value))
+                    .unwrap_or(0);
+                db.flush().context("Failed to persist sequence counter")?;
+                Ok(value)
+            }
+        }
+    }
+
+    /// Release the database, e.g. before reopening it
+    pub fn close(self) -> Result<()> {
+        match self.backing {
+            Backing::Memory(_) => Ok(()),
+            Backing::Sled(db, path) => close_db(db, &path).context("Failed to close sequence database"),
+        }
+    }
+}
+
+fn decode(bytes: &[u8]) -> u64 {
+    bytes.try_into().map(u64::from_be_bytes).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_persisted_counter_survives_reopen() {
+        let dir = TempDir::new().unwrap();
+        let counter = SequenceCounter::open(dir.path()).unwrap();
+        assert_eq!(counter.current().unwrap(), 0);
+        assert_eq!(counter.next().unwrap(), 1);
+        assert_eq!(counter.next().unwrap(), 2);
+        counter.close().unwrap();
+
+        let counter = SequenceCounter::open(dir.path()).unwrap();
+        assert_eq!(counter.current().unwrap(), 2);
+        assert_eq!(counter.next().unwrap(), 3);
+    }
+}
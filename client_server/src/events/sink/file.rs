@@ -0,0 +1,61 @@
+//! Sink appending envelopes to a local NDJSON file
+
+use super::EventSink;
+use crate::events::EventEnvelope;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl EventSink for FileSink {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    async fn deliver(&self, envelope: &EventEnvelope) -> Result<()> {
+        let mut line = serde_json::to_vec(envelope)?;
+        line.push(b'\n');
+        // Reopened per event so log rotation just works
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        file.write_all(&line).await?;
+        // tokio finishes the write in the background unless flushed
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_appends_ndjson() {
+        let dir = TempDir::new().unwrap();
+        let sink = FileSink::new(dir.path().join("events.ndjson"));
+        sink.deliver(&EventEnvelope::new(Event::DoorOpen, "test".to_string())).await.unwrap();
+        sink.deliver(&EventEnvelope::new(Event::DoorClose, "test".to_string())).await.unwrap();
+
+        let contents = std::fs::read_to_string(dir.path().join("events.ndjson")).unwrap();
+        let lines: Vec<EventEnvelope> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(matches!(lines[1].event, Event::DoorClose));
+    }
+}
@@ -0,0 +1,246 @@
+//! Pluggable event sinks
+//!
+//! A sink receives every broadcast envelope at or above its severity. Each one
+//! gets its own buffer and delivery task, so a slow or unreachable sink only
+//! drops its own envelopes and never holds up the bus or the other sinks.
+
+mod file;
+pub(crate) mod mqtt;
+mod webhook;
+
+pub use file::FileSink;
+pub use mqtt::MqttSink;
+pub use webhook::WebhookSink;
+
+use super::{EventBus, EventEnvelope, Severity};
+use crate::config::{SinkConfig, SinkKind};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Destination for event envelopes
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    /// Short name used in logs and metrics
+    fn name(&self) -> &str;
+
+    /// Deliver one envelope; an error is logged and the envelope skipped
+    async fn deliver(&self, envelope: &EventEnvelope) -> Result<()>;
+}
+
+/// Delivery counters for one sink
+#[derive(Debug, Default)]
+pub struct SinkMetrics {
+    delivered: AtomicU64,
+    failed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Point-in-time copy of a sink's counters
+#[derive(Debug, Clone, Serialize)]
+pub struct SinkStats {
+    pub name: String,
+    pub delivered: u64,
+    pub failed: u64,
+    /// Envelopes lost because the sink's buffer was full
+    pub dropped: u64,
+}
+
+/// A running sink
+pub struct SinkHandle {
+    name: String,
+    metrics: Arc<SinkMetrics>,
+    forward: JoinHandle<()>,
+}
+
+impl SinkHandle {
+    /// Stop taking envelopes from the bus; those already buffered are still delivered
+    pub fn stop(&self) {
+        self.forward.abort();
+    }
+
+    pub fn stats(&self) -> SinkStats {
+        SinkStats {
+            name: self.name.clone(),
+            delivered: self.metrics.delivered.load(Ordering::Relaxed),
+            failed: self.metrics.failed.load(Ordering::Relaxed),
+            dropped: self.metrics.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Create the built-in sink described by a config entry
+pub fn build_sink(config: &SinkConfig, client_id: &str) -> Result<Arc<dyn EventSink>> {
+    Ok(match &config.kind {
+        SinkKind::File { path } => Arc::new(FileSink::new(path.clone())),
+        SinkKind::Webhook { url, token } => Arc::new(WebhookSink::new(url.clone(), token.clone())?),
+        SinkKind::Mqtt { host, port, topic, username, password } => Arc::new(MqttSink::new(
+            host.clone(),
+            *port,
+            topic.replace("{client_id}", client_id),
+            client_id.to_string(),
+            username.clone(),
+            password.clone(),
+        )),
+    })
+}
+
+/// Start every configured sink, skipping any that cannot be created
+pub fn spawn_sinks(configs: &[SinkConfig], event_bus: &EventBus, client_id: &str) -> Vec<SinkHandle> {
+    configs
+        .iter()
+        .filter_map(|config| match build_sink(config, client_id) {
+            Ok(sink) => Some(spawn_sink(sink, event_bus, config.min_severity, config.buffer)),
+            Err(e) => {
+                warn!(error = %e, ?config, "Event sink not started");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Feed a sink from the bus through a buffer of `buffer` envelopes
+pub fn spawn_sink(
+    sink: Arc<dyn EventSink>,
+    event_bus: &EventBus,
+    min_severity: Severity,
+    buffer: usize,
+) -> SinkHandle {
+    let mut rx = event_bus.subscribe();
+    let event_bus = event_bus.clone();
+    let name = sink.name().to_string();
+    let metrics = Arc::new(SinkMetrics::default());
+    let (tx, mut queue) = mpsc::channel::<EventEnvelope>(buffer.max(1));
+
+    let forward_metrics = metrics.clone();
+    let forward_name = name.clone();
+    let forward = tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(envelope) if envelope.severity >= min_severity => {
+                    if tx.try_send(envelope).is_err() {
+                        forward_metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                        warn!(sink = %forward_name, "Event sink buffer full, envelope dropped");
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    forward_metrics.dropped.fetch_add(missed, Ordering::Relaxed);
+                    event_bus.note_lagged(&format!("sink:{}", forward_name), missed);
+                    warn!(sink = %forward_name, missed, "Event sink fell behind the bus");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    let deliver_metrics = metrics.clone();
+    tokio::spawn(async move {
+        info!(sink = sink.name(), "Event sink started");
+        while let Some(envelope) = queue.recv().await {
+            match sink.deliver(&envelope).await {
+                Ok(()) => {
+                    deliver_metrics.delivered.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    deliver_metrics.failed.fetch_add(1, Ordering::Relaxed);
+                    warn!(sink = sink.name(), event_id = %envelope.id, error = %e, "Event sink delivery failed");
+                }
+            }
+        }
+    });
+
+    SinkHandle { name, metrics, forward }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+    use parking_lot::Mutex;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Recorder {
+        seen: Mutex<Vec<EventEnvelope>>,
+    }
+
+    #[async_trait]
+    impl EventSink for Recorder {
+        fn name(&self) -> &str {
+            "recorder"
+        }
+
+        async fn deliver(&self, envelope: &EventEnvelope) -> Result<()> {
+            self.seen.lock().push(envelope.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sink_receives_filtered_envelopes() {
+        let (bus, _rx) = EventBus::new();
+        let recorder = Arc::new(Recorder::default());
+        let handle = spawn_sink(recorder.clone(), &bus, Severity::Info, 16);
+
+        bus.broadcast(EventEnvelope::new(Event::DoorOpen, "test".to_string())).unwrap();
+        let reading = Event::TemperatureReading { sensor: "attic".to_string(), celsius: 20.0 };
+        bus.broadcast(EventEnvelope::new(reading, "test".to_string())).unwrap();
+        bus.broadcast(EventEnvelope::new(Event::DoorClose, "test".to_string())).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        {
+            let seen = recorder.seen.lock();
+            assert_eq!(seen.len(), 2);
+            assert!(matches!(seen[1].event, Event::DoorClose));
+            assert_eq!(handle.stats().delivered, 2);
+        }
+
+        // A stopped sink takes nothing more from the bus
+        handle.stop();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        bus.broadcast(EventEnvelope::new(Event::DoorOpen, "test".to_string())).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(recorder.seen.lock().len(), 2);
+    }
+
+    #[test]
+    fn test_sink_config_parses() {
+        let toml = r#"
+            [[sinks]]
+            type = "file"
+            path = "/tmp/events.ndjson"
+
+            [[sinks]]
+            type = "mqtt"
+            host = "broker.lan"
+            min_severity = "warn"
+        "#;
+        #[derive(serde::Deserialize)]
+        struct Sinks {
+            sinks: Vec<SinkConfig>,
+        }
+        let parsed: Sinks = config::Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        assert!(matches!(parsed.sinks[0].kind, SinkKind::File { .. }));
+        assert_eq!(parsed.sinks[0].buffer, 256);
+        match &parsed.sinks[1].kind {
+            SinkKind::Mqtt { port, topic, .. } => {
+                assert_eq!(*port, 1883);
+                assert_eq!(topic, "pi-door/{client_id}/events/{type}");
+            }
+            other => panic!("Unexpected sink: {:?}", other),
+        }
+        assert_eq!(parsed.sinks[1].min_severity, Severity::Warn);
+    }
+}
@@ -0,0 +1,279 @@
+//! Sink publishing envelopes to an MQTT broker
+//!
+//! Speaks just enough MQTT 3.1.1 to publish at QoS 0: CONNECT with keep-alive
+//! disabled, then one PUBLISH per envelope. A failed write drops the
+//! connection and the next envelope reconnects. The codec also subscribes,
+//! for integrations that read from a broker.
+
+use super::EventSink;
+use crate::events::EventEnvelope;
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct MqttSink {
+    host: String,
+    port: u16,
+    /// Topic with `{type}` still to be filled in
+    topic: String,
+    client_id: String,
+    username: Option<String>,
+    password: Option<String>,
+    stream: Mutex<Option<TcpStream>>,
+}
+
+impl MqttSink {
+    pub fn new(
+        host: String,
+        port: u16,
+        topic: String,
+        client_id: String,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Self {
+        Self {
+            host,
+            port,
+            topic,
+            client_id,
+            username,
+            password,
+            stream: Mutex::new(None),
+        }
+    }
+
+    async fn connect(&self) -> Result<TcpStream> {
+        connect(
+            &self.host,
+            self.port,
+            &self.client_id,
+            self.username.as_deref(),
+            self.password.as_deref(),
+        )
+        .await
+    }
+}
+
+/// Open a session with the broker and wait for it to be accepted
+pub(crate) async fn connect(
+    host: &str,
+    port: u16,
+    client_id: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+) -> Result<TcpStream> {
+    let mut stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect((host, port)))
+        .await
+        .context("Timed out connecting to MQTT broker")?
+        .with_context(|| format!("Failed to connect to MQTT broker {}:{}", host, port))?;
+
+    stream.write_all(&connect_packet(client_id, username, password)).await?;
+
+    let mut connack = [0u8; 4];
+    tokio::time::timeout(CONNECT_TIMEOUT, stream.read_exact(&mut connack))
+        .await
+        .context("Timed out waiting for MQTT CONNACK")??;
+    if connack[0] != 0x20 || connack[3] != 0 {
+        bail!("MQTT broker refused connection (return code {})", connack[3]);
+    }
+    Ok(stream)
+}
+
+/// Subscribe to a topic filter at QoS 0
+pub(crate) async fn subscribe(stream: &mut TcpStream, topic: &str) -> Result<()> {
+    stream.write_all(&subscribe_packet(1, topic)).await?;
+    Ok(())
+}
+
+/// Read the next packet as its first header byte and body
+pub(crate) async fn read_packet(stream: &mut TcpStream) -> Result<(u8, Vec<u8>)> {
+    let kind = stream.read_u8().await?;
+    let mut len = 0usize;
+    for shift in (0..28).step_by(7) {
+        let byte = stream.read_u8().await?;
+        len |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            let mut body = vec![0u8; len];
+            stream.read_exact(&mut body).await?;
+            return Ok((kind, body));
+        }
+    }
+    bail!("Malformed MQTT remaining length")
+}
+
+/// Topic and payload of a PUBLISH packet; `None` for other packets
+pub(crate) fn parse_publish(kind: u8, body: &[u8]) -> Option<(&str, &[u8])> {
+    if kind >> 4 != 3 {
+        return None;
+    }
+    let len = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let topic = std::str::from_utf8(body.get(2..2 + len)?).ok()?;
+    // QoS 1 and 2 carry a packet identifier before the payload
+    let offset = if (kind >> 1) & 0x03 == 0 { 2 + len } else { 4 + len };
+    Some((topic, body.get(offset..)?))
+}
+
+#[async_trait]
+impl EventSink for MqttSink {
+    fn name(&self) -> &str {
+        "mqtt"
+    }
+
+    async fn deliver(&self, envelope: &EventEnvelope) -> Result<()> {
+        let topic = self.topic.replace("{type}", &envelope.event.type_name());
+        let packet = publish_packet(&topic, &serde_json::to_vec(envelope)?);
+
+        let mut stream = self.stream.lock().await;
+        if stream.is_none() {
+            *stream = Some(self.connect().await?);
+        }
+        if let Some(connection) = stream.as_mut() {
+            if let Err(e) = connection.write_all(&packet).await {
+                *stream = None;
+                return Err(e).context("Failed to publish to MQTT broker");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn connect_packet(client_id: &str, username: Option<&str>, password: Option<&str>) -> Vec<u8> {
+    // Clean session, plus the credential flags in use
+    let mut flags = 0x02;
+    if username.is_some() {
+        flags |= 0x80;
+    }
+    if password.is_some() {
+        flags |= 0x40;
+    }
+
+    let mut body = Vec::new();
+    push_str(&mut body, "MQTT");
+    body.push(4); // Protocol level 3.1.1
+    body.push(flags);
+    body.extend_from_slice(&0u16.to_be_bytes()); // Keep-alive off
+    push_str(&mut body, client_id);
+    for field in [username, password].into_iter().flatten() {
+        push_str(&mut body, field);
+    }
+    packet(0x10, body)
+}
+
+fn subscribe_packet(packet_id: u16, topic: &str) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    push_str(&mut body, topic);
+    body.push(0); // Requested QoS
+    packet(0x82, body)
+}
+
+fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_str(&mut body, topic);
+    body.extend_from_slice(payload);
+    packet(0x30, body)
+}
+
+/// Length-prefixed UTF-8 string
+fn push_str(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Fixed header with the variable-length remaining length, then the body
+fn packet(kind: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut out = vec![kind];
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    out.extend(body);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_remaining_length_encoding() {
+        assert_eq!(&packet(0x30, vec![0; 127])[..2], &[0x30, 0x7f]);
+        assert_eq!(&packet(0x30, vec![0; 128])[..3], &[0x30, 0x80, 0x01]);
+        assert_eq!(&packet(0x30, vec![0; 16_384])[..4], &[0x30, 0x80, 0x80, 0x01]);
+    }
+
+    #[tokio::test]
+    async fn test_publishes_to_broker() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let expected = connect_packet("door-1", Some("user"), Some("secret"));
+            let mut connect = vec![0u8; expected.len()];
+            socket.read_exact(&mut connect).await.unwrap();
+            assert_eq!(connect, expected);
+            socket.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+
+            let mut received = vec![0u8; 512];
+            let n = socket.read(&mut received).await.unwrap();
+            received.truncate(n);
+            received
+        });
+
+        let sink = MqttSink::new(
+            "127.0.0.1".to_string(),
+            port,
+            "pi-door/door-1/events/{type}".to_string(),
+            "door-1".to_string(),
+            Some("user".to_string()),
+            Some("secret".to_string()),
+        );
+        let envelope = EventEnvelope::new(Event::DoorOpen, "door-1".to_string());
+        sink.deliver(&envelope).await.unwrap();
+
+        let received = broker.await.unwrap();
+        assert_eq!(received, publish_packet("pi-door/door-1/events/door_open", &serde_json::to_vec(&envelope).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_and_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut connect = vec![0u8; connect_packet("door-1", None, None).len()];
+            socket.read_exact(&mut connect).await.unwrap();
+            socket.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+
+            let expected = subscribe_packet(1, "rtl_433/+/events");
+            let mut subscribe = vec![0u8; expected.len()];
+            socket.read_exact(&mut subscribe).await.unwrap();
+            assert_eq!(subscribe, expected);
+            socket.write_all(&[0x90, 0x03, 0x00, 0x01, 0x00]).await.unwrap();
+            socket.write_all(&publish_packet("rtl_433/pi/events", &[b'x'; 200])).await.unwrap();
+        });
+
+        let mut stream = connect("127.0.0.1", port, "door-1", None, None).await.unwrap();
+        subscribe(&mut stream, "rtl_433/+/events").await.unwrap();
+        let (kind, body) = read_packet(&mut stream).await.unwrap();
+        assert_eq!((kind, body.as_slice()), (0x90, &[0x00, 0x01, 0x00][..]));
+        assert_eq!(parse_publish(kind, &body), None);
+        let (kind, body) = read_packet(&mut stream).await.unwrap();
+        assert_eq!(parse_publish(kind, &body), Some(("rtl_433/pi/events", &[b'x'; 200][..])));
+        broker.await.unwrap();
+    }
+}
@@ -0,0 +1,41 @@
+//! Sink posting envelopes to an HTTP endpoint
+
+use super::EventSink;
+use crate::events::EventEnvelope;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct WebhookSink {
+    url: String,
+    token: Option<String>,
+    client: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new(url: String, token: Option<String>) -> Result<Self> {
+        let client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        Ok(Self { url, token, client })
+    }
+}
+
+#[async_trait]
+impl EventSink for WebhookSink {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn deliver(&self, envelope: &EventEnvelope) -> Result<()> {
+        let mut request = self.client.post(&self.url).json(envelope);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            bail!("Webhook returned {}", response.status());
+        }
+        Ok(())
+    }
+}
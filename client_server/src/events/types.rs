@@ -4,8 +4,14 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::actuators::SirenPattern;
+use crate::config::{ConfigError, TimerConfig};
+use crate::gpio::WiringReport;
+use crate::state::{ArmMode, CloudStatus};
+use crate::update::UpdateStage;
+
 /// Source of an event
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum EventSource {
     Local,
@@ -13,9 +19,177 @@ pub enum EventSource {
     Cloud,
     Ble,
     Rf,
+    Keypad,
     System,
 }
 
+/// Zone classification for an input sensor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZoneType {
+    /// Doors and windows on the building shell, armed in every mode
+    Perimeter,
+    /// Sensors inside the building, bypassed in stay and night modes
+    #[default]
+    Interior,
+    /// 24/7 non-security inputs (water leak, freezer door): always reported, never sound the siren
+    Environmental,
+    /// 24/7 smoke/heat detectors: alarm immediately, armed or not
+    Fire,
+    /// 24/7 enclosure and siren-box tamper switches: alarm immediately, armed or not
+    Tamper,
+}
+
+impl ZoneType {
+    /// Whether a sensor in this zone should trip the alarm in the given arm mode
+    pub fn is_armed_in(self, mode: ArmMode) -> bool {
+        match self {
+            ZoneType::Perimeter | ZoneType::Fire | ZoneType::Tamper => true,
+            ZoneType::Interior => mode == ArmMode::Away,
+            ZoneType::Environmental => false,
+        }
+    }
+
+    /// Whether this zone guards against intrusion (as opposed to environmental monitoring)
+    pub fn is_intrusion(self) -> bool {
+        matches!(self, ZoneType::Perimeter | ZoneType::Interior)
+    }
+
+    /// Alarm a 24/7 zone raises straight away, skipping the entry delay
+    pub fn instant_alarm(self) -> Option<AlarmKind> {
+        match self {
+            ZoneType::Fire => Some(AlarmKind::Fire),
+            ZoneType::Tamper => Some(AlarmKind::Tamper),
+            _ => None,
+        }
+    }
+}
+
+/// How much attention an event deserves; decides where it is kept and forwarded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Telemetry and countdowns
+    Debug,
+    #[default]
+    Info,
+    /// Needs looking at, but nobody is in danger
+    Warn,
+    /// An alarm or an attack on the system
+    Critical,
+}
+
+/// Cause of an alarm, which decides how it sounds and how long
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlarmKind {
+    Burglar,
+    Fire,
+    Tamper,
+    Panic,
+}
+
+impl AlarmKind {
+    /// Siren cadence for this alarm; fire uses the standard temporal-3 evacuation signal
+    pub fn siren_pattern(self) -> SirenPattern {
+        match self {
+            AlarmKind::Burglar => SirenPattern::Pulsed,
+            AlarmKind::Fire => SirenPattern::Temporal3,
+            AlarmKind::Tamper => SirenPattern::Chirp,
+            AlarmKind::Panic => SirenPattern::Steady,
+        }
+    }
+
+    /// Whether the floodlight comes on; a tamper alarm leaves it alone
+    pub fn lights_floodlight(self) -> bool {
+        !matches!(self, AlarmKind::Tamper)
+    }
+
+    /// Rank when alarms overlap: a higher one takes over the siren
+    pub fn priority(self) -> u8 {
+        match self {
+            AlarmKind::Tamper => 0,
+            AlarmKind::Burglar => 1,
+            AlarmKind::Panic => 2,
+            AlarmKind::Fire => 3,
+        }
+    }
+}
+
+impl std::fmt::Display for AlarmKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlarmKind::Burglar => write!(f, "burglar"),
+            AlarmKind::Fire => write!(f, "fire"),
+            AlarmKind::Tamper => write!(f, "tamper"),
+            AlarmKind::Panic => write!(f, "panic"),
+        }
+    }
+}
+
+/// Which temperature limit a reading crossed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TemperatureLimit {
+    High,
+    Low,
+}
+
+/// How a user identified themselves at the door reader
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessMethod {
+    Card,
+    Pin,
+}
+
+/// Encoder chip family a 433 MHz remote uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RfProtocol {
+    /// 20-bit learning code and 4 button bits
+    Ev1527,
+    /// 12 tri-state address and data symbols
+    Pt2262,
+    /// Rolling code: encrypted counter, serial number and buttons
+    Keeloq,
+}
+
+/// What a code heard in RF learn mode is bound as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RfSensorKind {
+    /// Door or window contact, a perimeter zone
+    Door,
+    /// PIR detector, an interior zone
+    Motion,
+    /// Panic button, added to `rf433.panic_codes`
+    Panic,
+}
+
+impl RfSensorKind {
+    /// Type of the zone the code reports as; panic buttons have none
+    pub fn zone_type(self) -> Option<ZoneType> {
+        match self {
+            RfSensorKind::Door => Some(ZoneType::Perimeter),
+            RfSensorKind::Motion => Some(ZoneType::Interior),
+            RfSensorKind::Panic => None,
+        }
+    }
+}
+
+/// What a network settings change set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkChange {
+    /// Wi-Fi network and passphrase
+    Wifi,
+    /// Addressing by DHCP
+    Dhcp,
+    /// Static address, gateway and DNS servers
+    Static,
+}
+
 /// Main event type that drives the state machine
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -24,6 +198,8 @@ pub enum Event {
     UserArm {
         source: EventSource,
         exit_delay_s: Option<u64>,
+        #[serde(default)]
+        mode: ArmMode,
     },
     
     /// User initiated disarm command
@@ -32,11 +208,36 @@ pub enum Event {
         auto_rearm_s: Option<u64>,
     },
     
+    /// User acknowledged the alarm memory, silencing the strobe
+    AlarmAcknowledged {
+        source: EventSource,
+    },
+    
     /// Door opened
     DoorOpen,
     
     /// Door closed
     DoorClose,
+
+    /// PIR motion sensor triggered
+    MotionDetected {
+        zone: ZoneType,
+    },
+
+    /// Vibration sensor reported enough pulses to indicate breaking glass
+    GlassBreak,
+
+    /// A configured zone input became active
+    ZoneTriggered {
+        zone: String,
+        zone_type: ZoneType,
+    },
+
+    /// A configured zone input returned to normal
+    ZoneRestored {
+        zone: String,
+        zone_type: ZoneType,
+    },
     
     /// Exit delay timer expired
     TimerExitExpired,
@@ -44,22 +245,103 @@ pub enum Event {
     /// Entry delay timer expired
     TimerEntryExpired,
     
+    /// One second elapsed on a countdown timer
+    TimerTick {
+        id: TimerId,
+        remaining_s: u64,
+    },
+    
     /// Auto-rearm timer expired
     TimerAutoRearmExpired,
     
     /// Siren timer expired
     TimerSirenExpired,
     
+    /// Automatic floodlight timer expired
+    TimerFloodlightExpired,
+    
     /// Cloud connectivity restored
     ConnectivityOnline,
     
     /// Cloud connectivity lost
     ConnectivityOffline,
     
+    /// The uplink moved to another interface; `None` when no interface is usable
+    NetworkInterfaceChanged {
+        previous: Option<String>,
+        interface: Option<String>,
+    },
+    
+    /// An interface came up after none was usable
+    NetworkOnline {
+        interface: String,
+    },
+    
+    /// No configured interface is up with a carrier
+    NetworkOffline,
+    
+    /// An interface changed state `network.failover.flap_threshold` times in the flap window
+    NetworkInterfaceFlapping {
+        interface: String,
+        changes: u32,
+    },
+    
+    /// A Wi-Fi interface is up, but its internet access is behind a login page
+    CaptivePortalDetected {
+        interface: String,
+        /// Where the portal redirected the probe
+        location: Option<String>,
+    },
+    
+    /// The interface reaches the internet again
+    CaptivePortalCleared {
+        interface: String,
+    },
+    
+    /// Network settings were changed and wait for connectivity to confirm them
+    NetworkConfigChanged {
+        interface: String,
+        change: NetworkChange,
+        source: EventSource,
+    },
+    
+    /// A network settings change was undone because connectivity did not come back
+    NetworkConfigRolledBack {
+        interface: String,
+        change: NetworkChange,
+    },
+    
+    /// The Wi-Fi setup access point came up after a long outage
+    SetupApStarted {
+        ssid: String,
+    },
+    
+    /// The Wi-Fi setup access point went down
+    SetupApStopped {
+        /// A network was saved on the setup page
+        network_saved: bool,
+    },
+    
+    /// The cloud connection came up
+    CloudConnected,
+    
+    /// An established cloud connection was lost
+    CloudDisconnected,
+    
+    /// The cloud client is connecting, or lost the link; sent on every attempt
+    CloudStatusChanged {
+        status: CloudStatus,
+        /// Reconnect attempts since the last stable connection
+        attempt: u32,
+    },
+    
     /// Manual siren control
     SirenControl {
         on: bool,
         duration_s: Option<u64>,
+        /// Cadence to play, steady if unset
+        #[serde(default)]
+        pattern: Option<SirenPattern>,
     },
     
     /// Manual floodlight control
@@ -68,10 +350,400 @@ pub enum Event {
         duration_s: Option<u64>,
     },
     
-    /// RF code received
+    /// Auxiliary relay output control
+    OutputControl {
+        name: String,
+        on: bool,
+        duration_s: Option<u64>,
+    },
+    
+    /// GPIO wiring self-test requested
+    SelfTestRequested {
+        source: EventSource,
+    },
+    
+    /// GPIO wiring self-test finished
+    SelfTestCompleted {
+        report: WiringReport,
+    },
+    
+    /// Periodic temperature telemetry from a 1-Wire sensor
+    TemperatureReading {
+        sensor: String,
+        celsius: f64,
+    },
+    
+    /// A temperature sensor crossed one of its limits
+    TemperatureAlert {
+        sensor: String,
+        celsius: f64,
+        limit: TemperatureLimit,
+    },
+    
+    /// A temperature sensor returned inside its limits
+    TemperatureRestored {
+        sensor: String,
+        celsius: f64,
+    },
+    
+    /// The SoC stayed at or above `thermal.warn_c` for `thermal.sustained_s`
+    SocOverheating {
+        celsius: f64,
+    },
+    
+    /// The overheated SoC cooled down again
+    SocCooled {
+        celsius: f64,
+    },
+    
+    /// Free space fell below `storage.warn_free_mb` or the offline queue grew past `storage.queue_warn_mb`
+    DiskSpaceLow {
+        free_mb: u64,
+        queue_mb: u64,
+    },
+    
+    /// Free space fell below `storage.critical_free_mb`; the oldest queued events were dropped
+    DiskSpaceCritical {
+        free_mb: u64,
+        pruned: usize,
+    },
+    
+    /// Free space and the offline queue are back within their limits
+    DiskSpaceRestored {
+        free_mb: u64,
+    },
+    
+    /// The agent's lowest resident memory rose by `memory.growth_warn_mb` within `memory.growth_window_h`
+    MemoryGrowing {
+        rss_mb: u64,
+        growth_mb: u64,
+    },
+    
+    /// Resident memory passed `memory.ceiling_mb`; the agent restarts
+    MemoryCeilingExceeded {
+        rss_mb: u64,
+        ceiling_mb: u64,
+    },
+    
+    /// The offline queue database was corrupt at startup and replaced
+    QueueRecovered {
+        error: String,
+        /// Where the corrupt database was kept for inspection
+        moved_to: String,
+        bytes: u64,
+        /// Events carried over into the new queue
+        salvaged: usize,
+        /// Entries lost because they could not be decoded
+        unreadable: usize,
+    },
+    
+    /// Supply voltage dropped: mains power lost, running on the backup battery
+    #[serde(alias = "on_battery")]
+    PowerLost {
+        voltage: f64,
+    },
+    
+    /// Backup battery is nearly flat
+    LowBattery {
+        voltage: f64,
+    },
+    
+    /// Supply voltage recovered: mains power is back
+    #[serde(alias = "mains_restored")]
+    PowerRestored {
+        voltage: f64,
+    },
+    
+    /// The firmware saw the SoC's supply dip below 4.63 V, typically a weak power supply
+    PowerUndervoltage {
+        /// Still under voltage; otherwise it happened since boot, between two readings
+        active: bool,
+    },
+    
+    /// A device reported its tamper switch open (enclosure, siren box, reader)
+    Tamper {
+        device: String,
+    },
+    
+    /// Continuous carrier on the RF band is drowning out sensor transmissions
+    RfJamming {
+        duration_ms: u64,
+    },
+    
+    /// A wireless sensor missed its supervision interval: flat battery, out of range or removed
+    SensorLost {
+        sensor: String,
+        silent_s: u64,
+    },
+    
+    /// A lost wireless sensor was heard from again
+    SensorRestored {
+        sensor: String,
+    },
+    
+    /// The local clock is off from the master's by more than the configured threshold;
+    /// positive when it runs ahead
+    ClockSkewDetected {
+        skew_ms: i64,
+    },
+    
+    /// The cloud presented a certificate chain without any pinned public key
+    CertificatePinMismatch {
+        host: String,
+    },
+    
+    /// A command was refused as expired or already seen, so a captured frame cannot act twice
+    CommandReplayRejected {
+        source: EventSource,
+        id: String,
+        command: String,
+        reason: String,
+    },
+    
+    /// Lockdown was engaged or lifted by the master
+    Lockdown {
+        active: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    
+    /// The local API token (`api`) or the master token (`master`) was replaced;
+    /// the old one is accepted until `previous_valid_until`, in Unix seconds
+    TokenRotated {
+        token: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        previous_valid_until: Option<i64>,
+    },
+    
+    /// A configuration pulled from the master was validated and saved;
+    /// `timers` carries new timer settings for the state machine
+    ConfigApplied {
+        changed: Vec<String>,
+        restart_required: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timers: Option<TimerConfig>,
+    },
+    
+    /// A configuration pulled from the master or reloaded from file could not be read or failed validation;
+    /// `errors` lists each setting that failed
+    ConfigRejected {
+        error: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        errors: Vec<ConfigError>,
+    },
+    
+    /// The configuration file was reloaded on SIGHUP; `changed` lists the settings
+    /// that differ and `restart_required` those of them only read at startup.
+    /// `timers` carries the new timers for the state machine; RF mappings are read from the
+    /// configuration store, so no keyfob code reaches the journal or the sinks
+    ConfigReloaded {
+        changed: Vec<String>,
+        restart_required: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timers: Option<TimerConfig>,
+    },
+    
+    /// Progress of an over-the-air update of the agent
+    AgentUpdate {
+        version: String,
+        stage: UpdateStage,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    
+    /// The BLE pairing window opened or closed
+    BlePairingWindow {
+        open: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_in_s: Option<u64>,
+    },
+    
+    /// A phone asked to bond; it goes ahead once the passkey is confirmed
+    BlePairingRequested {
+        device: String,
+        passkey: u32,
+    },
+    
+    /// A phone bonded during the pairing window
+    BleDevicePaired {
+        device: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    
+    /// A BLE control write came from a device that is not bonded or lacks the permission
+    BleCommandRejected {
+        device: String,
+        command: Option<String>,
+        reason: String,
+    },
+    
+    /// A bonded BLE device (e.g. a keyfob) has not been seen for `ble.missing_after_days`
+    BleDeviceMissing {
+        device: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        last_seen: Option<DateTime<Utc>>,
+    },
+    
+    /// A registered BLE beacon arrived or left
+    BeaconPresence {
+        beacon: String,
+        present: bool,
+    },
+    
+    /// The first registered beacon arrived or the last one left
+    OccupancyChanged {
+        occupied: bool,
+    },
+    
+    /// A zone was excluded from (or returned to) alarm processing until disarm
+    ZoneBypassed {
+        zone: String,
+        bypassed: bool,
+    },
+    
+    /// Close a pulse-mode output briefly, then release it
+    OutputPulse {
+        name: String,
+        duration_ms: u64,
+    },
+    
+    /// An enrolled card or PIN was accepted; `user` tells whose PIN it was
+    AccessGranted {
+        user: String,
+        method: AccessMethod,
+        /// Where it was entered; the door reader when absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source: Option<EventSource>,
+    },
+    
+    /// An unknown card or wrong PIN was presented; `card` helps with enrollment
+    AccessDenied {
+        method: AccessMethod,
+        card: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source: Option<EventSource>,
+    },
+    
+    /// Too many failed attempts: cards and PINs are ignored for `lockout_s`
+    AccessLockout {
+        lockout_s: u64,
+    },
+    
+    /// Panic button pressed: sound the alarm immediately, armed or not
+    Panic {
+        source: EventSource,
+    },
+    
+    /// The system went into alarm; `cause` names the zone or trigger
+    AlarmTriggered {
+        kind: AlarmKind,
+        cause: String,
+    },
+    
+    /// A remote code was received on the 433 MHz receiver, e.g. `0xA1B2C3`
     RfCodeReceived {
         code: String,
+        protocol: RfProtocol,
+    },
+    
+    /// A rolling-code remote sent a code that failed verification
+    RfCodeRejected {
+        remote: String,
+        reason: String,
+    },
+    
+    /// A remote's command was run; together with `RfCommandRejected` this audits every RF command
+    RfCommandAccepted {
+        remote: String,
+        command: String,
+    },
+    
+    /// A remote's command was refused: the remote is disabled or lacks the permission
+    RfCommandRejected {
+        remote: String,
+        command: String,
+        reason: String,
+    },
+    
+    /// A code heard in learn mode was bound and saved to the configuration;
+    /// `zone` is unset for panic buttons
+    RfSensorLearned {
+        code: String,
+        kind: RfSensorKind,
+        zone: Option<String>,
+        label: Option<String>,
     },
+    
+    /// A code looks played back: a rolling code whose counter did not advance,
+    /// or a fixed code pressed suspiciously often; `remote` names the remote the code belongs to
+    RfReplaySuspected {
+        code: String,
+        remote: Option<String>,
+    },
+}
+
+impl Event {
+    /// Severity of this event type
+    pub fn severity(&self) -> Severity {
+        match self {
+            Event::TimerTick { .. }
+            | Event::TemperatureReading { .. }
+            | Event::RfCodeReceived { .. }
+            | Event::CloudStatusChanged { .. } => {
+                Severity::Debug
+            }
+            Event::AccessDenied { .. }
+            | Event::AccessLockout { .. }
+            | Event::BleCommandRejected { .. }
+            | Event::BleDeviceMissing { .. }
+            | Event::RfCodeRejected { .. }
+            | Event::RfCommandRejected { .. }
+            | Event::TemperatureAlert { .. }
+            | Event::SocOverheating { .. }
+            | Event::DiskSpaceLow { .. }
+            | Event::DiskSpaceCritical { .. }
+            | Event::QueueRecovered { .. }
+            | Event::MemoryGrowing { .. }
+            | Event::MemoryCeilingExceeded { .. }
+            | Event::PowerLost { .. }
+            | Event::PowerUndervoltage { .. }
+            | Event::LowBattery { .. }
+            | Event::ConnectivityOffline
+            | Event::NetworkOffline
+            | Event::NetworkInterfaceFlapping { .. }
+            | Event::CaptivePortalDetected { .. }
+            | Event::SetupApStarted { .. }
+            | Event::NetworkConfigRolledBack { .. }
+            | Event::CloudDisconnected
+            | Event::ClockSkewDetected { .. }
+            | Event::ConfigRejected { .. }
+            | Event::RfJamming { .. }
+            | Event::SensorLost { .. }
+            | Event::RfReplaySuspected { .. }
+            | Event::CommandReplayRejected { .. }
+            | Event::ZoneBypassed { .. } => Severity::Warn,
+            Event::SelfTestCompleted { report } if !report.ok => Severity::Warn,
+            Event::Lockdown { active: true, .. } => Severity::Warn,
+            Event::AgentUpdate { stage: UpdateStage::Failed | UpdateStage::RolledBack, .. } => Severity::Warn,
+            Event::AlarmTriggered { .. }
+            | Event::Panic { .. }
+            | Event::Tamper { .. }
+            | Event::CertificatePinMismatch { .. }
+            | Event::GlassBreak
+            | Event::TimerEntryExpired => Severity::Critical,
+            _ => Severity::Info,
+        }
+    }
+
+    /// Serialized `type` name, e.g. `door_open`
+    pub fn type_name(&self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|value| value["type"].as_str().map(str::to_string))
+            .unwrap_or_default()
+    }
 }
 
 /// Event with metadata for transmission and persistence
@@ -81,6 +753,17 @@ pub struct EventEnvelope {
     pub timestamp: DateTime<Utc>,
     pub event: Event,
     pub client_id: String,
+    #[serde(default)]
+    pub severity: Severity,
+    /// Per-client sequence number; 0 for telemetry and envelopes from older clients
+    #[serde(default)]
+    pub seq: u64,
+    /// Hex HMAC-SHA256 over the rest of the envelope, when signing is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Command this event stems from, when it was caused by one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
 }
 
 impl EventEnvelope {
@@ -89,20 +772,37 @@ impl EventEnvelope {
         Self {
             id: Uuid::new_v4(),
             timestamp: Utc::now(),
+            severity: event.severity(),
             event,
             client_id,
+            seq: 0,
+            signature: None,
+            correlation_id: None,
         }
     }
 }
 
 /// Timer identifier for timer management
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TimerId {
     ExitDelay,
     EntryDelay,
     AutoRearm,
     Siren,
     Floodlight,
+    /// Auto-off timer for a named auxiliary output
+    Output(String),
+}
+
+impl TimerId {
+    /// Whether the timer counts down visibly, emitting a tick every second
+    pub fn is_countdown(&self) -> bool {
+        matches!(
+            self,
+            TimerId::ExitDelay | TimerId::EntryDelay | TimerId::AutoRearm | TimerId::Siren
+        )
+    }
 }
 
 #[cfg(test)]
@@ -114,6 +814,7 @@ mod tests {
         let event = Event::UserArm {
             source: EventSource::Local,
             exit_delay_s: Some(30),
+            mode: ArmMode::Stay,
         };
         
         let json = serde_json::to_string(&event).unwrap();
@@ -121,14 +822,71 @@ mod tests {
         
         let deserialized: Event = serde_json::from_str(&json).unwrap();
         match deserialized {
-            Event::UserArm { source, exit_delay_s } => {
+            Event::UserArm { source, exit_delay_s, mode } => {
                 assert_eq!(source, EventSource::Local);
                 assert_eq!(exit_delay_s, Some(30));
+                assert_eq!(mode, ArmMode::Stay);
             }
             _ => panic!("Wrong event type"),
         }
     }
 
+    #[test]
+    fn test_user_arm_mode_defaults_to_away() {
+        let json = r#"{"type":"user_arm","source":"local","exit_delay_s":null}"#;
+        let event: Event = serde_json::from_str(json).unwrap();
+        match event {
+            Event::UserArm { mode, .. } => assert_eq!(mode, ArmMode::Away),
+            _ => panic!("Wrong event type"),
+        }
+    }
+
+    #[test]
+    fn test_wire_names_are_stable() {
+        let cases = [
+            (Event::MotionDetected { zone: ZoneType::Interior }, "motion_detected"),
+            (Event::Tamper { device: "siren_box".to_string() }, "tamper"),
+            (Event::LowBattery { voltage: 11.5 }, "low_battery"),
+            (Event::PowerLost { voltage: 12.6 }, "power_lost"),
+            (Event::PowerRestored { voltage: 13.8 }, "power_restored"),
+            (Event::RfJamming { duration_ms: 5000 }, "rf_jamming"),
+            (Event::SensorLost { sensor: "patio_door".to_string(), silent_s: 7200 }, "sensor_lost"),
+            (Event::NetworkOnline { interface: "eth0".to_string() }, "network_online"),
+            (Event::ZoneBypassed { zone: "garage".to_string(), bypassed: true }, "zone_bypassed"),
+            (Event::SelfTestRequested { source: EventSource::Local }, "self_test_requested"),
+            (Event::Panic { source: EventSource::Keypad }, "panic"),
+        ];
+        for (event, name) in cases {
+            assert_eq!(serde_json::to_value(&event).unwrap()["type"], name);
+        }
+
+        // Events queued before the power events were renamed still load
+        let old: Event = serde_json::from_str(r#"{"type":"on_battery","voltage":12.6}"#).unwrap();
+        assert!(matches!(old, Event::PowerLost { .. }));
+    }
+
+    #[test]
+    fn test_envelope_severity() {
+        let envelope = EventEnvelope::new(Event::Panic { source: EventSource::Local }, "test".to_string());
+        assert_eq!(envelope.severity, Severity::Critical);
+        assert_eq!(serde_json::to_value(&envelope).unwrap()["severity"], "critical");
+
+        assert_eq!(Event::TemperatureReading { sensor: "attic".to_string(), celsius: 21.0 }.severity(), Severity::Debug);
+        assert_eq!(Event::PowerLost { voltage: 12.6 }.severity(), Severity::Warn);
+        assert!(Severity::Warn > Severity::Info);
+    }
+
+    #[test]
+    fn test_zone_armed_in_mode() {
+        assert!(ZoneType::Perimeter.is_armed_in(ArmMode::Away));
+        assert!(ZoneType::Perimeter.is_armed_in(ArmMode::Night));
+        assert!(ZoneType::Interior.is_armed_in(ArmMode::Away));
+        assert!(!ZoneType::Interior.is_armed_in(ArmMode::Stay));
+        assert!(!ZoneType::Interior.is_armed_in(ArmMode::Night));
+        assert!(!ZoneType::Environmental.is_armed_in(ArmMode::Away));
+        assert!(!ZoneType::Environmental.is_intrusion());
+    }
+
     #[test]
     fn test_event_envelope_creation() {
         let event = Event::DoorOpen;
@@ -0,0 +1,400 @@
+//! Character-device GPIO implementation using gpio-cdev
+//!
+//! Talks to `/dev/gpiochipN` through the kernel's GPIO uAPI instead of the
+//! legacy register interface, so it also works on the Raspberry Pi 5 (RP1)
+//! and other boards rppal does not cover.
+
+use anyhow::{anyhow, Context, Result};
+use futures::StreamExt;
+use gpio_cdev::{AsyncLineEventHandle, Chip, EventRequestFlags, EventType, LineHandle, LineRequestFlags};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, error, info, warn};
+
+use super::traits::{Edge, GpioController, LevelChange, LEVEL_BUFFER};
+use crate::config::{GpioConfig, PinOptions, Pull};
+
+/// Consumer label shown by `gpioinfo` for claimed lines
+const CONSUMER: &str = "pi-door-client";
+
+/// Line bias request flags (Linux 5.5+), not yet exposed by gpio-cdev
+const BIAS_PULL_UP: u32 = 1 << 5;
+const BIAS_PULL_DOWN: u32 = 1 << 6;
+const BIAS_DISABLE: u32 = 1 << 7;
+
+/// Request flags for a line with the given electrical options
+fn request_flags(direction: LineRequestFlags, options: &PinOptions) -> LineRequestFlags {
+    let mut flags = direction;
+    if options.active_low {
+        flags |= LineRequestFlags::ACTIVE_LOW;
+    }
+    if options.open_drain {
+        flags |= LineRequestFlags::OPEN_DRAIN;
+    }
+
+    let bias = match options.pull {
+        Pull::Up => BIAS_PULL_UP,
+        Pull::Down => BIAS_PULL_DOWN,
+        Pull::None => BIAS_DISABLE,
+    };
+    if direction.contains(LineRequestFlags::INPUT) {
+        // SAFETY: the bias bits are defined by the kernel uAPI; bitflags only
+        // lacks named constants for them.
+        flags |= unsafe { LineRequestFlags::from_bits_unchecked(bias) };
+    }
+
+    flags
+}
+
+/// An input line; edges are read by a background task once initialized
+struct Input {
+    name: String,
+    handle: Mutex<Option<gpio_cdev::LineEventHandle>>,
+    active: AtomicBool,
+    edges: broadcast::Sender<Edge>,
+}
+
+/// An output line together with its logical state
+struct Output {
+    handle: LineHandle,
+    active: AtomicBool,
+}
+
+impl Output {
+    /// Drive the line; polarity and open-drain are handled by the kernel
+    fn set(&self, active: bool) -> Result<()> {
+        self.handle.set_value(active as u8)?;
+        self.active.store(active, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// Real GPIO controller using the GPIO character device
+#[derive(Clone)]
+pub struct CdevGpio {
+    inputs: Arc<HashMap<u8, Arc<Input>>>,
+    outputs: Arc<HashMap<u8, Output>>,
+    config: GpioConfig,
+}
+
+impl CdevGpio {
+    /// Create a new character-device GPIO controller from the pin configuration
+    pub fn from_config(config: &GpioConfig) -> Result<Self> {
+        info!(chip = %config.chip.display(), "Initializing character-device GPIO controller");
+
+        let mut chip = Chip::new(&config.chip)
+            .with_context(|| format!("Failed to open GPIO chip {}", config.chip.display()))?;
+
+        let mut inputs = HashMap::new();
+        for (name, pin) in config.input_pins() {
+            let options = config.options_for(&name);
+            let handle = chip
+                .get_line(pin as u32)
+                .and_then(|line| {
+                    line.events(
+                        request_flags(LineRequestFlags::INPUT, &options),
+                        EventRequestFlags::BOTH_EDGES,
+                        CONSUMER,
+                    )
+                })
+                .with_context(|| format!("Failed to request {} input line {}", name, pin))?;
+            let active = handle.get_value()? == 1;
+
+            debug!(name, pin, ?options, active, "Input line configured");
+            let (edges, _) = broadcast::channel(16);
+            inputs.insert(
+                pin,
+                Arc::new(Input {
+                    name,
+                    handle: Mutex::new(Some(handle)),
+                    active: AtomicBool::new(active),
+                    edges,
+                }),
+            );
+        }
+
+        let mut outputs = HashMap::new();
+        for (name, pin) in config.output_pins() {
+            let options = config.options_for(&name);
+            // Requesting with a default of 0 drives the line inactive straight away
+            let handle = chip
+                .get_line(pin as u32)
+                .and_then(|line| {
+                    line.request(request_flags(LineRequestFlags::OUTPUT, &options), 0, CONSUMER)
+                })
+                .with_context(|| format!("Failed to request {} output line {}", name, pin))?;
+
+            debug!(name, pin, ?options, "Output line configured");
+            outputs.insert(
+                pin,
+                Output {
+                    handle,
+                    active: AtomicBool::new(false),
+                },
+            );
+        }
+
+        Ok(Self {
+            inputs: Arc::new(inputs),
+            outputs: Arc::new(outputs),
+            config: config.clone(),
+        })
+    }
+
+    /// Forward debounced edges from one input line to its subscribers
+    async fn watch_input(input: Arc<Input>, mut events: AsyncLineEventHandle, debounce: Duration) {
+        while let Some(event) = events.next().await {
+            if let Err(e) = event {
+                error!(input = %input.name, error = %e, "GPIO event stream failed");
+                break;
+            }
+
+            // Let the contacts settle, then report only real level changes
+            tokio::time::sleep(debounce).await;
+            let active = match events.as_ref().get_value() {
+                Ok(value) => value == 1,
+                Err(e) => {
+                    warn!(input = %input.name, error = %e, "Failed to read GPIO line");
+                    continue;
+                }
+            };
+            if input.active.swap(active, Ordering::SeqCst) == active {
+                continue;
+            }
+
+            let edge = if active { Edge::Rising } else { Edge::Falling };
+            let _ = input.edges.send(edge);
+        }
+    }
+
+    fn input(&self, pin: u8) -> Result<Arc<Input>> {
+        self.inputs
+            .get(&pin)
+            .cloned()
+            .ok_or_else(|| anyhow!("GPIO line {} is not configured as an input", pin))
+    }
+
+    fn optional_input(&self, pin: Option<u8>, name: &str) -> Result<Arc<Input>> {
+        let pin = pin.ok_or_else(|| anyhow!("No {} pin configured", name))?;
+        self.input(pin)
+    }
+
+    fn output(&self, pin: u8) -> Result<&Output> {
+        self.outputs
+            .get(&pin)
+            .ok_or_else(|| anyhow!("GPIO line {} is not configured as an output", pin))
+    }
+
+    async fn wait_edge(input: Arc<Input>) -> Result<Edge> {
+        let mut rx = input.edges.subscribe();
+        loop {
+            match rx.recv().await {
+                Ok(edge) => return Ok(edge),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(anyhow!("GPIO event stream for {} closed", input.name))
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl GpioController for CdevGpio {
+    async fn initialize(&mut self) -> Result<()> {
+        for output in self.outputs.values() {
+            output.set(false)?;
+        }
+
+        let debounce = Duration::from_millis(self.config.debounce_ms);
+        for input in self.inputs.values() {
+            let Some(handle) = input.handle.lock().take() else {
+                continue; // already watching
+            };
+            let events = AsyncLineEventHandle::new(handle)?;
+            tokio::spawn(Self::watch_input(input.clone(), events, debounce));
+        }
+
+        let door_open = self.read_door_sensor().await?;
+        info!(door_open, "Initial door state detected");
+        Ok(())
+    }
+
+    async fn read_door_sensor(&self) -> Result<bool> {
+        // The reed switch is "active" when the magnet holds it closed
+        let closed = self.input(self.config.reed_in)?.active.load(Ordering::SeqCst);
+        Ok(!closed)
+    }
+
+    async fn read_motion_sensor(&self) -> Result<bool> {
+        let input = self.optional_input(self.config.motion_in, "motion")?;
+        Ok(input.active.load(Ordering::SeqCst))
+    }
+
+    async fn wait_for_motion_edge(&self) -> Result<Edge> {
+        Self::wait_edge(self.optional_input(self.config.motion_in, "motion")?).await
+    }
+
+    async fn wait_for_vibration_pulse(&self) -> Result<()> {
+        let input = self.optional_input(self.config.vibration_in, "vibration")?;
+        loop {
+            if Self::wait_edge(input.clone()).await? == Edge::Rising {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn read_input(&self, pin: u8) -> Result<bool> {
+        Ok(self.input(pin)?.active.load(Ordering::SeqCst))
+    }
+
+    async fn wait_for_input_edge(&self, pin: u8) -> Result<Edge> {
+        Self::wait_edge(self.input(pin)?).await
+    }
+
+    async fn watch_levels(&self, pin: u8) -> Result<mpsc::Receiver<LevelChange>> {
+        let mut chip = Chip::new(&self.config.chip)
+            .with_context(|| format!("Failed to open GPIO chip {}", self.config.chip.display()))?;
+        let handle = chip
+            .get_line(pin as u32)
+            .and_then(|line| line.events(LineRequestFlags::INPUT, EventRequestFlags::BOTH_EDGES, CONSUMER))
+            .with_context(|| format!("Failed to request line {} for level changes", pin))?;
+        let mut events = AsyncLineEventHandle::new(handle)?;
+
+        // Kernel timestamps keep the pulse timing exact however late the task runs
+        let (tx, rx) = mpsc::channel(LEVEL_BUFFER);
+        tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        error!(pin, error = %e, "GPIO level stream failed");
+                        break;
+                    }
+                };
+                let change = LevelChange {
+                    high: event.event_type() == EventType::RisingEdge,
+                    at_us: event.timestamp() / 1000,
+                };
+                if tx.try_send(change).is_err() && tx.is_closed() {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    async fn set_siren(&self, on: bool) -> Result<()> {
+        debug!(on, "Setting siren");
+        self.output(self.config.siren_out)?.set(on)
+    }
+
+    async fn set_floodlight(&self, on: bool) -> Result<()> {
+        debug!(on, "Setting floodlight");
+        self.output(self.config.floodlight_out)?.set(on)
+    }
+
+    async fn set_siren_tone(&self, frequency_hz: f64, _duty_cycle: f64) -> Result<()> {
+        // The GPIO uAPI has no PWM; sound the siren steadily instead
+        debug!(frequency_hz, "PWM not available on cdev backend, driving siren steady");
+        self.set_siren(true).await
+    }
+
+    async fn set_output(&self, pin: u8, on: bool) -> Result<()> {
+        debug!(pin, on, "Setting output");
+        self.output(pin)?.set(on)
+    }
+
+    async fn set_output_tone(&self, pin: u8, frequency_hz: f64, _duty_cycle: f64) -> Result<()> {
+        debug!(pin, frequency_hz, "PWM not available on cdev backend, driving output steady");
+        self.set_output(pin, true).await
+    }
+
+    async fn send_pulses(&self, pin: u8, durations_us: &[u64]) -> Result<()> {
+        debug!(pin, pulses = durations_us.len(), "Sending pulse train");
+        self.output(pin)?;
+        let outputs = self.outputs.clone();
+        let durations = durations_us.to_vec();
+        tokio::task::spawn_blocking(move || super::drive_pulses(&durations, |high| outputs[&pin].set(high))).await?
+    }
+
+    async fn get_output_state(&self, pin: u8) -> Result<bool> {
+        Ok(self.output(pin)?.active.load(Ordering::SeqCst))
+    }
+
+    async fn wait_for_door_edge(&self) -> Result<Edge> {
+        let edge = Self::wait_edge(self.input(self.config.reed_in)?).await?;
+        // Reed active (closed) maps to a falling door edge
+        Ok(match edge {
+            Edge::Rising => Edge::Falling,
+            Edge::Falling => Edge::Rising,
+            Edge::Both => Edge::Both,
+        })
+    }
+
+    fn emergency_shutdown(&self) {
+        warn!("Emergency GPIO shutdown initiated");
+
+        // Line writes are single ioctls with no locks involved, so this never blocks
+        for (pin, output) in self.outputs.iter() {
+            if let Err(e) = output.set(false) {
+                warn!(pin, error = %e, "Failed to release output during emergency shutdown");
+            }
+        }
+
+        info!("Emergency GPIO shutdown complete");
+    }
+
+    async fn get_siren_state(&self) -> Result<bool> {
+        Ok(self.output(self.config.siren_out)?.active.load(Ordering::SeqCst))
+    }
+
+    async fn get_floodlight_state(&self) -> Result<bool> {
+        Ok(self.output(self.config.floodlight_out)?.active.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    #[test]
+    fn test_request_flags() {
+        let options = PinOptions {
+            pull: Pull::Up,
+            active_low: true,
+            open_drain: false,
+        };
+        let flags = request_flags(LineRequestFlags::INPUT, &options);
+        assert!(flags.contains(LineRequestFlags::ACTIVE_LOW));
+        assert_eq!(flags.bits() & BIAS_PULL_UP, BIAS_PULL_UP);
+
+        let options = PinOptions {
+            open_drain: true,
+            ..Default::default()
+        };
+        let flags = request_flags(LineRequestFlags::OUTPUT, &options);
+        assert!(flags.contains(LineRequestFlags::OPEN_DRAIN));
+        assert_eq!(flags.bits() & (BIAS_PULL_UP | BIAS_PULL_DOWN | BIAS_DISABLE), 0);
+    }
+
+    // Note: These tests require a GPIO character device and will fail in CI
+    // They are marked as ignored and should be run manually on target hardware
+
+    #[tokio::test]
+    #[ignore = "requires a GPIO character device"]
+    async fn test_gpio_initialization() {
+        let mut gpio = CdevGpio::from_config(&AppConfig::test_default().gpio).unwrap();
+        gpio.initialize().await.unwrap();
+
+        gpio.set_siren(true).await.unwrap();
+        assert!(gpio.get_siren_state().await.unwrap());
+        gpio.emergency_shutdown();
+        assert!(!gpio.get_siren_state().await.unwrap());
+    }
+}
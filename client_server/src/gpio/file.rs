@@ -0,0 +1,333 @@
+//! File-backed GPIO implementation for development hosts
+//!
+//! Every pin is a file `gpio<N>` in a directory holding its electrical level
+//! as `0` or `1`. Outputs are written by the agent; inputs are polled, so a
+//! developer (or a script) can open the door with `echo 1 > gpio17`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, info, warn};
+
+use super::traits::{Edge, GpioController, LevelChange};
+use crate::config::{GpioConfig, PinOptions};
+
+/// How often input files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Path of the file backing a pin
+fn pin_path(dir: &Path, pin: u8) -> PathBuf {
+    dir.join(format!("gpio{}", pin))
+}
+
+/// Parse a level file, treating anything but a leading `1` as low
+fn parse_level(contents: &str) -> bool {
+    contents.trim_start().starts_with('1')
+}
+
+/// A polled input file
+struct Input {
+    path: PathBuf,
+    options: PinOptions,
+    active: AtomicBool,
+    edges: broadcast::Sender<Edge>,
+}
+
+impl Input {
+    fn read_active(&self) -> Result<bool> {
+        let contents = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+        Ok(self.options.is_active(parse_level(&contents)))
+    }
+}
+
+/// An output file together with its logical state
+struct Output {
+    path: PathBuf,
+    options: PinOptions,
+    active: AtomicBool,
+}
+
+impl Output {
+    fn set(&self, active: bool) -> Result<()> {
+        let level = if self.options.level_for(active) { "1\n" } else { "0\n" };
+        std::fs::write(&self.path, level)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        self.active.store(active, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// GPIO controller backed by plain files
+#[derive(Clone)]
+pub struct FileGpio {
+    inputs: Arc<HashMap<u8, Arc<Input>>>,
+    outputs: Arc<HashMap<u8, Output>>,
+    config: GpioConfig,
+}
+
+impl FileGpio {
+    /// Create the pin files under `gpio.file_dir`, keeping existing input levels
+    pub fn from_config(config: &GpioConfig) -> Result<Self> {
+        let dir = &config.file_dir;
+        info!(dir = %dir.display(), "Initializing file-backed GPIO controller");
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create GPIO directory {}", dir.display()))?;
+
+        let mut inputs = HashMap::new();
+        for (name, pin) in config.input_pins() {
+            let options = config.options_for(&name);
+            let path = pin_path(dir, pin);
+            if !path.exists() {
+                // Start inactive: level_for(false) is the idle electrical level
+                let level = if options.level_for(false) { "1\n" } else { "0\n" };
+                std::fs::write(&path, level)
+                    .with_context(|| format!("Failed to create {}", path.display()))?;
+            }
+
+            let (edges, _) = broadcast::channel(16);
+            let input = Input {
+                path,
+                options,
+                active: AtomicBool::new(false),
+                edges,
+            };
+            input.active.store(input.read_active()?, Ordering::SeqCst);
+            debug!(name, pin, ?options, "Input file configured");
+            inputs.insert(pin, Arc::new(input));
+        }
+
+        let mut outputs = HashMap::new();
+        for (name, pin) in config.output_pins() {
+            let output = Output {
+                path: pin_path(dir, pin),
+                options: config.options_for(&name),
+                active: AtomicBool::new(false),
+            };
+            output.set(false)?;
+            debug!(name, pin, "Output file configured");
+            outputs.insert(pin, output);
+        }
+
+        Ok(Self {
+            inputs: Arc::new(inputs),
+            outputs: Arc::new(outputs),
+            config: config.clone(),
+        })
+    }
+
+    /// Poll every input file and publish edges when a level changes
+    async fn poll_inputs(inputs: Arc<HashMap<u8, Arc<Input>>>) {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            for (pin, input) in inputs.iter() {
+                let active = match input.read_active() {
+                    Ok(active) => active,
+                    Err(e) => {
+                        warn!(pin, error = %e, "Failed to poll GPIO file");
+                        continue;
+                    }
+                };
+                if input.active.swap(active, Ordering::SeqCst) != active {
+                    let edge = if active { Edge::Rising } else { Edge::Falling };
+                    debug!(pin, ?edge, "GPIO file changed");
+                    let _ = input.edges.send(edge);
+                }
+            }
+        }
+    }
+
+    fn input(&self, pin: u8) -> Result<Arc<Input>> {
+        self.inputs
+            .get(&pin)
+            .cloned()
+            .ok_or_else(|| anyhow!("GPIO pin {} is not configured as an input", pin))
+    }
+
+    fn optional_input(&self, pin: Option<u8>, name: &str) -> Result<Arc<Input>> {
+        let pin = pin.ok_or_else(|| anyhow!("No {} pin configured", name))?;
+        self.input(pin)
+    }
+
+    fn output(&self, pin: u8) -> Result<&Output> {
+        self.outputs
+            .get(&pin)
+            .ok_or_else(|| anyhow!("GPIO pin {} is not configured as an output", pin))
+    }
+
+    async fn wait_edge(input: Arc<Input>) -> Result<Edge> {
+        let mut rx = input.edges.subscribe();
+        loop {
+            match rx.recv().await {
+                Ok(edge) => return Ok(edge),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(anyhow!("GPIO file watcher for {} stopped", input.path.display()))
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl GpioController for FileGpio {
+    async fn initialize(&mut self) -> Result<()> {
+        for output in self.outputs.values() {
+            output.set(false)?;
+        }
+        tokio::spawn(Self::poll_inputs(self.inputs.clone()));
+
+        let door_open = self.read_door_sensor().await?;
+        info!(door_open, "Initial door state detected");
+        Ok(())
+    }
+
+    async fn read_door_sensor(&self) -> Result<bool> {
+        // The reed switch is "active" when the magnet holds it closed
+        let closed = self.input(self.config.reed_in)?.read_active()?;
+        Ok(!closed)
+    }
+
+    async fn read_motion_sensor(&self) -> Result<bool> {
+        self.optional_input(self.config.motion_in, "motion")?.read_active()
+    }
+
+    async fn wait_for_motion_edge(&self) -> Result<Edge> {
+        Self::wait_edge(self.optional_input(self.config.motion_in, "motion")?).await
+    }
+
+    async fn wait_for_vibration_pulse(&self) -> Result<()> {
+        let input = self.optional_input(self.config.vibration_in, "vibration")?;
+        loop {
+            if Self::wait_edge(input.clone()).await? == Edge::Rising {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn read_input(&self, pin: u8) -> Result<bool> {
+        self.input(pin)?.read_active()
+    }
+
+    async fn wait_for_input_edge(&self, pin: u8) -> Result<Edge> {
+        Self::wait_edge(self.input(pin)?).await
+    }
+
+    async fn watch_levels(&self, pin: u8) -> Result<mpsc::Receiver<LevelChange>> {
+        bail!("The file GPIO backend polls too slowly to time pulses on line {}", pin)
+    }
+
+    async fn set_siren(&self, on: bool) -> Result<()> {
+        debug!(on, "Setting siren");
+        self.output(self.config.siren_out)?.set(on)
+    }
+
+    async fn set_floodlight(&self, on: bool) -> Result<()> {
+        debug!(on, "Setting floodlight");
+        self.output(self.config.floodlight_out)?.set(on)
+    }
+
+    async fn set_siren_tone(&self, frequency_hz: f64, _duty_cycle: f64) -> Result<()> {
+        debug!(frequency_hz, "Siren tone written as a steady level");
+        self.set_siren(true).await
+    }
+
+    async fn set_output(&self, pin: u8, on: bool) -> Result<()> {
+        debug!(pin, on, "Setting output");
+        self.output(pin)?.set(on)
+    }
+
+    async fn set_output_tone(&self, pin: u8, frequency_hz: f64, _duty_cycle: f64) -> Result<()> {
+        debug!(pin, frequency_hz, "Output tone written as a steady level");
+        self.set_output(pin, true).await
+    }
+
+    async fn send_pulses(&self, pin: u8, _durations_us: &[u64]) -> Result<()> {
+        bail!("The file GPIO backend cannot time pulses on line {}", pin)
+    }
+
+    async fn get_output_state(&self, pin: u8) -> Result<bool> {
+        Ok(self.output(pin)?.active.load(Ordering::SeqCst))
+    }
+
+    async fn wait_for_door_edge(&self) -> Result<Edge> {
+        let edge = Self::wait_edge(self.input(self.config.reed_in)?).await?;
+        // Reed active (closed) maps to a falling door edge
+        Ok(match edge {
+            Edge::Rising => Edge::Falling,
+            Edge::Falling => Edge::Rising,
+            Edge::Both => Edge::Both,
+        })
+    }
+
+    fn emergency_shutdown(&self) {
+        warn!("Emergency GPIO shutdown initiated");
+        for (pin, output) in self.outputs.iter() {
+            if let Err(e) = output.set(false) {
+                warn!(pin, error = %e, "Failed to release output during emergency shutdown");
+            }
+        }
+        info!("Emergency GPIO shutdown complete");
+    }
+
+    async fn get_siren_state(&self) -> Result<bool> {
+        Ok(self.output(self.config.siren_out)?.active.load(Ordering::SeqCst))
+    }
+
+    async fn get_floodlight_state(&self) -> Result<bool> {
+        Ok(self.output(self.config.floodlight_out)?.active.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use tokio::time::timeout;
+
+    fn test_config(dir: &Path) -> GpioConfig {
+        let mut config = AppConfig::test_default().gpio;
+        config.file_dir = dir.to_path_buf();
+        config
+    }
+
+    #[tokio::test]
+    async fn test_files_created_in_safe_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let gpio = FileGpio::from_config(&test_config(dir.path())).unwrap();
+
+        // Reed is active-low: idle (door open) level is high
+        assert_eq!(std::fs::read_to_string(dir.path().join("gpio17")).unwrap(), "1\n");
+        assert_eq!(std::fs::read_to_string(dir.path().join("gpio27")).unwrap(), "0\n");
+        assert!(gpio.read_door_sensor().await.unwrap());
+
+        gpio.set_siren(true).await.unwrap();
+        assert_eq!(std::fs::read_to_string(dir.path().join("gpio27")).unwrap(), "1\n");
+        gpio.emergency_shutdown();
+        assert_eq!(std::fs::read_to_string(dir.path().join("gpio27")).unwrap(), "0\n");
+    }
+
+    #[tokio::test]
+    async fn test_door_edge_from_file_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut gpio = FileGpio::from_config(&test_config(dir.path())).unwrap();
+        gpio.initialize().await.unwrap();
+
+        let waiter = {
+            let gpio = gpio.clone();
+            tokio::spawn(async move { gpio.wait_for_door_edge().await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Pull the reed low: magnet present, door closed
+        std::fs::write(dir.path().join("gpio17"), "0\n").unwrap();
+        let edge = timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap().unwrap();
+        assert_eq!(edge, Edge::Falling);
+        assert!(!gpio.read_door_sensor().await.unwrap());
+    }
+}
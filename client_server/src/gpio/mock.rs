@@ -1,11 +1,13 @@
 //! Mock GPIO implementation for testing and development
 
-use super::traits::{Edge, GpioController};
+use super::traits::{Edge, GpioController, LevelChange, LEVEL_BUFFER};
+use crate::config::{GpioConfig, PinOptions};
 use anyhow::Result;
 use async_trait::async_trait;
 use parking_lot::RwLock;
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Notify;
+use tokio::sync::{mpsc, Notify};
 use tracing::{debug, info};
 
 /// Mock GPIO controller for testing
@@ -13,12 +15,32 @@ use tracing::{debug, info};
 pub struct MockGpio {
     state: Arc<RwLock<MockGpioState>>,
     door_edge_notify: Arc<Notify>,
+    motion_edge_notify: Arc<Notify>,
+    vibration_notify: Arc<Notify>,
+    input_notify: Arc<RwLock<HashMap<u8, Arc<Notify>>>>,
+    level_watchers: Arc<RwLock<HashMap<u8, mpsc::Sender<LevelChange>>>>,
+    pins: Arc<MockPins>,
+}
+
+/// Electrical options the mock applies when translating levels
+#[derive(Debug, Default)]
+struct MockPins {
+    reed: PinOptions,
+    siren: PinOptions,
+    floodlight: PinOptions,
+    inputs: HashMap<u8, PinOptions>,
 }
 
 #[derive(Debug)]
 struct MockGpioState {
     door_open: bool,
+    motion: bool,
+    inputs: HashMap<u8, bool>,
+    outputs: HashMap<u8, bool>,
+    output_tones: HashMap<u8, (f64, f64)>,
+    pulse_trains: HashMap<u8, Vec<Vec<u64>>>,
     siren: bool,
+    siren_tone: Option<(f64, f64)>,
     floodlight: bool,
     initialized: bool,
 }
@@ -27,7 +49,13 @@ impl Default for MockGpioState {
     fn default() -> Self {
         Self {
             door_open: false,
+            motion: false,
+            inputs: HashMap::new(),
+            outputs: HashMap::new(),
+            output_tones: HashMap::new(),
+            pulse_trains: HashMap::new(),
             siren: false,
+            siren_tone: None,
             floodlight: false,
             initialized: false,
         }
@@ -41,9 +69,67 @@ impl MockGpio {
         Self {
             state: Arc::new(RwLock::new(MockGpioState::default())),
             door_edge_notify: Arc::new(Notify::new()),
+            motion_edge_notify: Arc::new(Notify::new()),
+            vibration_notify: Arc::new(Notify::new()),
+            input_notify: Arc::new(RwLock::new(HashMap::new())),
+            level_watchers: Arc::new(RwLock::new(HashMap::new())),
+            pins: Arc::new(MockPins::default()),
+        }
+    }
+
+    /// Create a mock GPIO controller honouring the configured pin polarities
+    pub fn from_config(config: &GpioConfig) -> Result<Self> {
+        let inputs = config
+            .input_pins()
+            .into_iter()
+            .map(|(name, pin)| (pin, config.options_for(&name)))
+            .collect();
+
+        let mut gpio = Self::new();
+        gpio.pins = Arc::new(MockPins {
+            reed: config.options_for("reed_in"),
+            siren: config.options_for("siren_out"),
+            floodlight: config.options_for("floodlight_out"),
+            inputs,
+        });
+        Ok(gpio)
+    }
+
+    /// Simulate the reed switch line settling at an electrical level (for testing)
+    pub fn simulate_door_level(&self, level_high: bool) {
+        if self.pins.reed.is_active(level_high) {
+            self.simulate_door_close();
+        } else {
+            self.simulate_door_open();
         }
     }
 
+    /// Simulate a generic input line settling at an electrical level (for testing)
+    pub fn simulate_input_level(&self, pin: u8, level_high: bool) {
+        let options = self.pins.inputs.get(&pin).copied().unwrap_or_default();
+        self.simulate_input(pin, options.is_active(level_high));
+    }
+
+    /// Electrical level currently driven on the siren output
+    pub fn siren_level(&self) -> bool {
+        self.pins.siren.level_for(self.state.read().siren)
+    }
+
+    /// PWM tone (frequency, duty cycle) currently driven on the siren output
+    pub fn siren_tone(&self) -> Option<(f64, f64)> {
+        self.state.read().siren_tone
+    }
+
+    /// PWM tone (frequency, duty cycle) currently driven on an auxiliary output
+    pub fn output_tone(&self, pin: u8) -> Option<(f64, f64)> {
+        self.state.read().output_tones.get(&pin).copied()
+    }
+
+    /// Electrical level currently driven on the floodlight output
+    pub fn floodlight_level(&self) -> bool {
+        self.pins.floodlight.level_for(self.state.read().floodlight)
+    }
+
     /// Simulate door opening (for testing)
     pub fn simulate_door_open(&self) {
         debug!("Simulating door open");
@@ -64,6 +150,65 @@ impl MockGpio {
         self.door_edge_notify.notify_waiters();
     }
 
+    /// Simulate the PIR sensor detecting motion (for testing)
+    pub fn simulate_motion(&self) {
+        debug!("Simulating motion detected");
+        {
+            let mut state = self.state.write();
+            state.motion = true;
+        }
+        self.motion_edge_notify.notify_waiters();
+    }
+
+    /// Simulate the PIR sensor returning to idle (for testing)
+    pub fn simulate_motion_clear(&self) {
+        debug!("Simulating motion cleared");
+        {
+            let mut state = self.state.write();
+            state.motion = false;
+        }
+        self.motion_edge_notify.notify_waiters();
+    }
+
+    /// Simulate a single pulse from the vibration sensor (for testing)
+    pub fn simulate_vibration_pulse(&self) {
+        debug!("Simulating vibration pulse");
+        self.vibration_notify.notify_waiters();
+    }
+
+    /// Simulate a generic zone input changing state (for testing)
+    pub fn simulate_input(&self, pin: u8, active: bool) {
+        debug!(pin, active, "Simulating input change");
+        {
+            let mut state = self.state.write();
+            state.inputs.insert(pin, active);
+        }
+        self.input_notifier(pin).notify_waiters();
+    }
+
+    /// Simulate raw level changes on a watched line (for testing)
+    pub fn simulate_levels(&self, pin: u8, changes: &[LevelChange]) {
+        debug!(pin, count = changes.len(), "Simulating level changes");
+        if let Some(tx) = self.level_watchers.read().get(&pin) {
+            for change in changes {
+                let _ = tx.try_send(*change);
+            }
+        }
+    }
+
+    fn input_notifier(&self, pin: u8) -> Arc<Notify> {
+        self.input_notify
+            .write()
+            .entry(pin)
+            .or_insert_with(|| Arc::new(Notify::new()))
+            .clone()
+    }
+
+    /// Pulse trains sent on an output so far (for testing)
+    pub fn pulse_trains(&self, pin: u8) -> Vec<Vec<u64>> {
+        self.state.read().pulse_trains.get(&pin).cloned().unwrap_or_default()
+    }
+
     /// Get current mock state (for testing)
     pub fn get_state(&self) -> (bool, bool, bool) {
         let state = self.state.read();
@@ -85,8 +230,12 @@ impl GpioController for MockGpio {
         
         // Set to safe state
         state.siren = false;
+        state.siren_tone = None;
         state.floodlight = false;
         state.door_open = false;
+        state.motion = false;
+        state.inputs.clear();
+        state.outputs.clear();
         state.initialized = true;
         
         debug!("Mock GPIO initialized successfully");
@@ -98,10 +247,61 @@ impl GpioController for MockGpio {
         Ok(state.door_open)
     }
 
+    async fn read_motion_sensor(&self) -> Result<bool> {
+        let state = self.state.read();
+        Ok(state.motion)
+    }
+
+    async fn wait_for_motion_edge(&self) -> Result<Edge> {
+        self.motion_edge_notify.notified().await;
+
+        let motion = self.read_motion_sensor().await?;
+        let edge = if motion { Edge::Rising } else { Edge::Falling };
+
+        debug!(?edge, "Motion edge detected");
+        Ok(edge)
+    }
+
+    async fn wait_for_vibration_pulse(&self) -> Result<()> {
+        self.vibration_notify.notified().await;
+        debug!("Vibration pulse detected");
+        Ok(())
+    }
+
+    async fn read_input(&self, pin: u8) -> Result<bool> {
+        let state = self.state.read();
+        Ok(state.inputs.get(&pin).copied().unwrap_or(false))
+    }
+
+    async fn wait_for_input_edge(&self, pin: u8) -> Result<Edge> {
+        self.input_notifier(pin).notified().await;
+
+        let active = self.read_input(pin).await?;
+        let edge = if active { Edge::Rising } else { Edge::Falling };
+
+        debug!(pin, ?edge, "Input edge detected");
+        Ok(edge)
+    }
+
+    async fn watch_levels(&self, pin: u8) -> Result<mpsc::Receiver<LevelChange>> {
+        let (tx, rx) = mpsc::channel(LEVEL_BUFFER);
+        self.level_watchers.write().insert(pin, tx);
+        Ok(rx)
+    }
+
     async fn set_siren(&self, on: bool) -> Result<()> {
         debug!(on, "Setting mock siren");
         let mut state = self.state.write();
         state.siren = on;
+        state.siren_tone = None;
+        Ok(())
+    }
+
+    async fn set_siren_tone(&self, frequency_hz: f64, duty_cycle: f64) -> Result<()> {
+        debug!(frequency_hz, duty_cycle, "Setting mock siren tone");
+        let mut state = self.state.write();
+        state.siren = true;
+        state.siren_tone = Some((frequency_hz, duty_cycle));
         Ok(())
     }
 
@@ -112,6 +312,33 @@ impl GpioController for MockGpio {
         Ok(())
     }
 
+    async fn set_output(&self, pin: u8, on: bool) -> Result<()> {
+        debug!(pin, on, "Setting mock output");
+        let mut state = self.state.write();
+        state.outputs.insert(pin, on);
+        state.output_tones.remove(&pin);
+        Ok(())
+    }
+
+    async fn set_output_tone(&self, pin: u8, frequency_hz: f64, duty_cycle: f64) -> Result<()> {
+        debug!(pin, frequency_hz, duty_cycle, "Setting mock output tone");
+        let mut state = self.state.write();
+        state.outputs.insert(pin, true);
+        state.output_tones.insert(pin, (frequency_hz, duty_cycle));
+        Ok(())
+    }
+
+    async fn send_pulses(&self, pin: u8, durations_us: &[u64]) -> Result<()> {
+        debug!(pin, pulses = durations_us.len(), "Sending mock pulse train");
+        self.state.write().pulse_trains.entry(pin).or_default().push(durations_us.to_vec());
+        Ok(())
+    }
+
+    async fn get_output_state(&self, pin: u8) -> Result<bool> {
+        let state = self.state.read();
+        Ok(state.outputs.get(&pin).copied().unwrap_or(false))
+    }
+
     async fn wait_for_door_edge(&self) -> Result<Edge> {
         // Wait for notification
         self.door_edge_notify.notified().await;
@@ -128,7 +355,12 @@ impl GpioController for MockGpio {
         info!("Emergency shutdown - setting mock outputs to safe state");
         let mut state = self.state.write();
         state.siren = false;
+        state.siren_tone = None;
         state.floodlight = false;
+        for on in state.outputs.values_mut() {
+            *on = false;
+        }
+        state.output_tones.clear();
     }
 
     async fn get_siren_state(&self) -> Result<bool> {
@@ -203,6 +435,64 @@ mod tests {
         assert_eq!(edge, Edge::Rising);
     }
 
+    #[tokio::test]
+    async fn test_mock_gpio_motion_simulation() {
+        let mut gpio = MockGpio::new();
+        gpio.initialize().await.unwrap();
+
+        let gpio_clone = gpio.clone();
+        let handle = tokio::spawn(async move {
+            gpio_clone.wait_for_motion_edge().await
+        });
+
+        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        gpio.simulate_motion();
+
+        let edge = handle.await.unwrap().unwrap();
+        assert_eq!(edge, Edge::Rising);
+        assert!(gpio.read_motion_sensor().await.unwrap());
+
+        gpio.simulate_motion_clear();
+        assert!(!gpio.read_motion_sensor().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_mock_gpio_polarity() {
+        let mut config = crate::config::AppConfig::test_default().gpio;
+        config.reed_active_low = true;
+        config.pin_options.insert(
+            "siren_out".to_string(),
+            PinOptions { active_low: true, ..Default::default() },
+        );
+        config.zones.push(crate::config::ZoneConfig {
+            name: "water_leak".to_string(),
+            pin: 6,
+            zone_type: crate::events::ZoneType::Environmental,
+        });
+        config.pin_options.insert(
+            "water_leak".to_string(),
+            PinOptions { active_low: true, ..Default::default() },
+        );
+
+        let mut gpio = MockGpio::from_config(&config).unwrap();
+        gpio.initialize().await.unwrap();
+
+        // Active-low siren idles high and pulls low when on
+        assert!(gpio.siren_level());
+        gpio.set_siren(true).await.unwrap();
+        assert!(!gpio.siren_level());
+        assert!(!gpio.floodlight_level());
+
+        // Reed switch with active-low polarity: low level = closed
+        gpio.simulate_door_level(true);
+        assert!(gpio.read_door_sensor().await.unwrap());
+        gpio.simulate_door_level(false);
+        assert!(!gpio.read_door_sensor().await.unwrap());
+
+        gpio.simulate_input_level(6, false);
+        assert!(gpio.read_input(6).await.unwrap());
+    }
+
     #[test]
     fn test_emergency_shutdown() {
         let gpio = MockGpio::new();
@@ -214,6 +504,8 @@ mod tests {
             state.floodlight = true;
         }
 
+        tokio_test::block_on(gpio.set_output(5, true)).unwrap();
+
         // Emergency shutdown
         gpio.emergency_shutdown();
 
@@ -221,5 +513,6 @@ mod tests {
         let (_, siren, flood) = gpio.get_state();
         assert!(!siren);
         assert!(!flood);
+        assert!(!tokio_test::block_on(gpio.get_output_state(5)).unwrap());
     }
 }
@@ -1,20 +1,81 @@
 //! GPIO abstraction layer
 
 mod traits;
+mod file;
 mod mock;
+mod monitor;
+mod pulse;
+mod schedule;
+mod selftest;
 
 #[cfg(feature = "real-gpio")]
 mod rppal;
 
+#[cfg(feature = "cdev-gpio")]
+mod cdev;
+
 pub use traits::*;
+pub use file::FileGpio;
 pub use mock::MockGpio;
+pub use monitor::SensorMonitor;
+pub use pulse::PulseCounter;
+pub use schedule::{SelfTestLog, SelfTestScheduler};
+pub use selftest::{InputStatus, OutputStatus, PinCheck, SelfTest, WiringReport};
 
 #[cfg(feature = "real-gpio")]
 pub use self::rppal::RppalGpio;
 
-/// Default GPIO implementation based on features
-#[cfg(feature = "mock-gpio")]
+#[cfg(feature = "cdev-gpio")]
+pub use cdev::CdevGpio;
+
+use crate::config::{GpioBackend, GpioConfig};
+use anyhow::{bail, Result};
+use std::sync::Arc;
+use tracing::info;
+
+/// Drive a pulse train through `set`, spinning between edges since sleeps are too coarse
+#[cfg(any(feature = "real-gpio", feature = "cdev-gpio"))]
+fn drive_pulses(durations_us: &[u64], mut set: impl FnMut(bool) -> Result<()>) -> Result<()> {
+    // Deadlines are absolute so time spent setting the line does not accumulate
+    let mut deadline = std::time::Instant::now();
+    for (i, duration) in durations_us.iter().enumerate() {
+        set(i % 2 == 0)?;
+        deadline += std::time::Duration::from_micros(*duration);
+        while std::time::Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+    }
+    set(false)
+}
+
+/// Default GPIO implementation based on features (real hardware wins when enabled)
+#[cfg(not(feature = "real-gpio"))]
 pub type DefaultGpio = MockGpio;
 
 #[cfg(feature = "real-gpio")]
 pub type DefaultGpio = RppalGpio;
+
+/// Open and initialize the GPIO backend selected in the configuration
+pub async fn open(config: &GpioConfig) -> Result<Arc<dyn GpioController>> {
+    let backend = match config.backend {
+        GpioBackend::Auto if cfg!(feature = "real-gpio") => GpioBackend::Rppal,
+        GpioBackend::Auto if cfg!(feature = "cdev-gpio") => GpioBackend::Cdev,
+        GpioBackend::Auto => GpioBackend::Mock,
+        backend => backend,
+    };
+    info!(?backend, "Opening GPIO backend");
+
+    let mut gpio: Box<dyn GpioController> = match backend {
+        GpioBackend::Mock => Box::new(MockGpio::from_config(config)?),
+        GpioBackend::File => Box::new(FileGpio::from_config(config)?),
+        #[cfg(feature = "real-gpio")]
+        GpioBackend::Rppal => Box::new(RppalGpio::from_config(config)?),
+        #[cfg(feature = "cdev-gpio")]
+        GpioBackend::Cdev => Box::new(CdevGpio::from_config(config)?),
+        #[allow(unreachable_patterns)]
+        other => bail!("GPIO backend {:?} is not compiled in; rebuild with its feature enabled", other),
+    };
+
+    gpio.initialize().await?;
+    Ok(Arc::from(gpio))
+}
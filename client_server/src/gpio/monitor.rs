@@ -0,0 +1,249 @@
+//! Sensor monitor that turns GPIO input edges into events
+
+use super::pulse::PulseCounter;
+use super::traits::{Edge, GpioController};
+use crate::config::{GpioConfig, ZoneConfig};
+use crate::events::{Event, EventBus};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+/// Watches the configured input sensors and emits events on the bus
+pub struct SensorMonitor {
+    gpio: Arc<dyn GpioController>,
+    event_bus: EventBus,
+    config: GpioConfig,
+}
+
+impl SensorMonitor {
+    /// Create a new sensor monitor
+    pub fn new(gpio: Arc<dyn GpioController>, event_bus: EventBus, config: GpioConfig) -> Self {
+        Self {
+            gpio,
+            event_bus,
+            config,
+        }
+    }
+
+    /// Spawn one monitoring task per configured input
+    pub fn spawn(self) -> Vec<JoinHandle<()>> {
+        let mut handles = Vec::new();
+
+        let gpio = self.gpio.clone();
+        let bus = self.event_bus.clone();
+        handles.push(tokio::spawn(async move {
+            Self::monitor_door(gpio, bus).await;
+        }));
+
+        if self.config.motion_in.is_some() {
+            let gpio = self.gpio.clone();
+            let bus = self.event_bus.clone();
+            let config = self.config.clone();
+            handles.push(tokio::spawn(async move {
+                Self::monitor_motion(gpio, bus, config).await;
+            }));
+        }
+
+        if self.config.vibration_in.is_some() {
+            let gpio = self.gpio.clone();
+            let bus = self.event_bus.clone();
+            let config = self.config.clone();
+            handles.push(tokio::spawn(async move {
+                Self::monitor_vibration(gpio, bus, config).await;
+            }));
+        }
+
+        for zone in &self.config.zones {
+            let gpio = self.gpio.clone();
+            let bus = self.event_bus.clone();
+            let zone = zone.clone();
+            handles.push(tokio::spawn(async move {
+                Self::monitor_zone(gpio, bus, zone).await;
+            }));
+        }
+
+        handles
+    }
+
+    /// Emit door open/close events on reed switch edges
+    async fn monitor_door(gpio: Arc<dyn GpioController>, event_bus: EventBus) {
+        info!("Door sensor monitor started");
+        loop {
+            let event = match gpio.wait_for_door_edge().await {
+                Ok(Edge::Rising) => Event::DoorOpen,
+                Ok(Edge::Falling) => Event::DoorClose,
+                Ok(Edge::Both) => continue,
+                Err(e) => {
+                    error!(error = %e, "Door sensor monitor failed");
+                    break;
+                }
+            };
+
+            if event_bus.emit(event).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Emit a motion event each time the PIR output goes high
+    async fn monitor_motion(gpio: Arc<dyn GpioController>, event_bus: EventBus, config: GpioConfig) {
+        info!(pin = ?config.motion_in, zone = ?config.motion_zone, "Motion sensor monitor started");
+        loop {
+            match gpio.wait_for_motion_edge().await {
+                Ok(Edge::Rising) => {
+                    let event = Event::MotionDetected {
+                        zone: config.motion_zone,
+                    };
+                    if event_bus.emit(event).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => debug!("Motion cleared"),
+                Err(e) => {
+                    error!(error = %e, "Motion sensor monitor failed");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Emit zone triggered/restored events on a named zone input
+    async fn monitor_zone(gpio: Arc<dyn GpioController>, event_bus: EventBus, zone: ZoneConfig) {
+        info!(zone = %zone.name, pin = zone.pin, zone_type = ?zone.zone_type, "Zone monitor started");
+        loop {
+            let event = match gpio.wait_for_input_edge(zone.pin).await {
+                Ok(Edge::Rising) => Event::ZoneTriggered {
+                    zone: zone.name.clone(),
+                    zone_type: zone.zone_type,
+                },
+                Ok(Edge::Falling) => Event::ZoneRestored {
+                    zone: zone.name.clone(),
+                    zone_type: zone.zone_type,
+                },
+                Ok(Edge::Both) => continue,
+                Err(e) => {
+                    error!(zone = %zone.name, error = %e, "Zone monitor failed");
+                    break;
+                }
+            };
+
+            if event_bus.emit(event).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Count vibration pulses and emit a glass-break event once enough arrive in the window
+    async fn monitor_vibration(gpio: Arc<dyn GpioController>, event_bus: EventBus, config: GpioConfig) {
+        info!(
+            pin = ?config.vibration_in,
+            pulses = config.vibration_pulses,
+            window_ms = config.vibration_window_ms,
+            "Vibration sensor monitor started"
+        );
+        let mut counter = PulseCounter::new(
+            config.vibration_pulses,
+            Duration::from_millis(config.vibration_window_ms),
+        );
+
+        loop {
+            if let Err(e) = gpio.wait_for_vibration_pulse().await {
+                error!(error = %e, "Vibration sensor monitor failed");
+                break;
+            }
+
+            if counter.record(Instant::now()) {
+                if event_bus.emit(Event::GlassBreak).is_err() {
+                    break;
+                }
+            } else {
+                debug!(pending = counter.pending(), "Vibration pulse below threshold");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::events::ZoneType;
+    use crate::gpio::MockGpio;
+    use tokio::time::{sleep, timeout, Duration};
+
+    #[tokio::test]
+    async fn test_motion_edge_emits_event() {
+        let gpio = MockGpio::new();
+        let (bus, mut rx) = EventBus::new();
+        let mut config = AppConfig::test_default().gpio;
+        config.motion_in = Some(24);
+        config.motion_zone = ZoneType::Perimeter;
+
+        let handles = SensorMonitor::new(Arc::new(gpio.clone()), bus, config).spawn();
+        assert_eq!(handles.len(), 2);
+
+        sleep(Duration::from_millis(10)).await;
+        gpio.simulate_motion();
+
+        let event = timeout(Duration::from_secs(1), rx.recv()).await.unwrap().unwrap();
+        match event {
+            Event::MotionDetected { zone } => assert_eq!(zone, ZoneType::Perimeter),
+            other => panic!("Unexpected event: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vibration_pulses_emit_glass_break() {
+        let gpio = MockGpio::new();
+        let (bus, mut rx) = EventBus::new();
+        let mut config = AppConfig::test_default().gpio;
+        config.vibration_in = Some(5);
+        config.vibration_pulses = 2;
+
+        SensorMonitor::new(Arc::new(gpio.clone()), bus, config).spawn();
+        sleep(Duration::from_millis(10)).await;
+
+        gpio.simulate_vibration_pulse();
+        sleep(Duration::from_millis(10)).await;
+        assert!(rx.try_recv().is_err());
+
+        gpio.simulate_vibration_pulse();
+        let event = timeout(Duration::from_secs(1), rx.recv()).await.unwrap().unwrap();
+        assert!(matches!(event, Event::GlassBreak));
+    }
+
+    #[tokio::test]
+    async fn test_zone_input_emits_trigger_and_restore() {
+        let gpio = MockGpio::new();
+        let (bus, mut rx) = EventBus::new();
+        let mut config = AppConfig::test_default().gpio;
+        config.zones.push(ZoneConfig {
+            name: "water_leak".to_string(),
+            pin: 6,
+            zone_type: ZoneType::Environmental,
+        });
+
+        SensorMonitor::new(Arc::new(gpio.clone()), bus, config).spawn();
+        sleep(Duration::from_millis(10)).await;
+
+        gpio.simulate_input(6, true);
+        let event = timeout(Duration::from_secs(1), rx.recv()).await.unwrap().unwrap();
+        assert!(matches!(event, Event::ZoneTriggered { ref zone, .. } if zone == "water_leak"));
+
+        sleep(Duration::from_millis(10)).await;
+        gpio.simulate_input(6, false);
+        let event = timeout(Duration::from_secs(1), rx.recv()).await.unwrap().unwrap();
+        assert!(matches!(event, Event::ZoneRestored { zone_type: ZoneType::Environmental, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_no_motion_task_without_pin() {
+        let gpio = MockGpio::new();
+        let (bus, _rx) = EventBus::new();
+        let config = AppConfig::test_default().gpio;
+
+        let handles = SensorMonitor::new(Arc::new(gpio), bus, config).spawn();
+        assert_eq!(handles.len(), 1);
+    }
+}
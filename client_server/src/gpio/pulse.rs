@@ -0,0 +1,89 @@
+//! Pulse-count-within-window detector for vibration sensors
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Counts short sensor pulses and reports when enough arrive within a sliding window.
+///
+/// Piezo vibration sensors emit a pulse for every knock, so a single pulse is
+/// usually noise (a passing truck, a slammed door). Requiring several pulses in
+/// quick succession filters those out while still catching a window being hit.
+#[derive(Debug)]
+pub struct PulseCounter {
+    threshold: u32,
+    window: Duration,
+    pulses: VecDeque<Instant>,
+}
+
+impl PulseCounter {
+    /// Create a counter that fires after `threshold` pulses within `window`
+    pub fn new(threshold: u32, window: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            window,
+            pulses: VecDeque::new(),
+        }
+    }
+
+    /// Record a pulse at `now`; returns true when the threshold is reached.
+    ///
+    /// The history is cleared after firing so a sustained burst is reported
+    /// once per `threshold` pulses rather than on every pulse.
+    pub fn record(&mut self, now: Instant) -> bool {
+        while let Some(&oldest) = self.pulses.front() {
+            if now.duration_since(oldest) > self.window {
+                self.pulses.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        self.pulses.push_back(now);
+
+        if self.pulses.len() >= self.threshold as usize {
+            self.pulses.clear();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Number of pulses currently inside the window
+    pub fn pending(&self) -> usize {
+        self.pulses.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fires_at_threshold() {
+        let mut counter = PulseCounter::new(3, Duration::from_secs(2));
+        let start = Instant::now();
+
+        assert!(!counter.record(start));
+        assert!(!counter.record(start + Duration::from_millis(200)));
+        assert!(counter.record(start + Duration::from_millis(400)));
+        assert_eq!(counter.pending(), 0);
+    }
+
+    #[test]
+    fn test_old_pulses_expire() {
+        let mut counter = PulseCounter::new(3, Duration::from_secs(1));
+        let start = Instant::now();
+
+        assert!(!counter.record(start));
+        assert!(!counter.record(start + Duration::from_millis(800)));
+        // First pulse has left the window by now
+        assert!(!counter.record(start + Duration::from_millis(1600)));
+        assert_eq!(counter.pending(), 2);
+    }
+
+    #[test]
+    fn test_zero_threshold_fires_on_every_pulse() {
+        let mut counter = PulseCounter::new(0, Duration::from_secs(1));
+        assert!(counter.record(Instant::now()));
+    }
+}
@@ -1,201 +1,496 @@
 //! Real GPIO implementation using rppal crate for Raspberry Pi
 
-use anyhow::{Context, Result};
-use rppal::gpio::{Gpio, InputPin, Level, OutputPin, Trigger};
+use anyhow::{anyhow, Context, Result};
+use parking_lot::Mutex;
+use rppal::gpio::{Gpio, InputPin, IoPin, Level, Mode, Trigger};
+use rppal::pwm::{Channel, Polarity, Pwm};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::mpsc;
 use tracing::{debug, info, warn};
 
-use super::traits::{ActuatorState, DoorState, GpioController};
+use super::traits::{Edge, GpioController, LevelChange, LEVEL_BUFFER};
+use crate::config::{GpioConfig, PinOptions, Pull};
+
+/// How long a blocking interrupt poll waits before releasing the pin lock
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// An input pin together with its electrical options
+struct Input {
+    /// Held by an edge waiter for each interrupt poll
+    pin: Mutex<InputPin>,
+    options: PinOptions,
+    /// Last level seen, kept current by the waiter's interrupts while it holds the pin
+    high: AtomicBool,
+}
+
+impl Input {
+    /// The logical level, without waiting for an edge waiter to release the pin
+    fn is_active(&self) -> bool {
+        let high = match self.pin.try_lock() {
+            Some(pin) => {
+                let high = pin.read() == Level::High;
+                self.high.store(high, Ordering::Relaxed);
+                high
+            }
+            None => self.high.load(Ordering::Relaxed),
+        };
+        self.options.is_active(high)
+    }
+}
+
+/// An output pin together with its electrical options and logical state
+struct Output {
+    pin: IoPin,
+    options: PinOptions,
+    active: bool,
+}
+
+impl Output {
+    /// Drive the pin to the level for the given logical state.
+    ///
+    /// Open-drain outputs are emulated by sinking to low when active and
+    /// switching to a floating input otherwise.
+    fn set(&mut self, active: bool) {
+        let level = if self.options.level_for(active) {
+            Level::High
+        } else {
+            Level::Low
+        };
+
+        if self.options.open_drain {
+            if level == Level::Low {
+                self.pin.write(Level::Low);
+                self.pin.set_mode(Mode::Output);
+            } else {
+                self.pin.set_mode(Mode::Input);
+            }
+        } else {
+            self.pin.write(level);
+        }
+
+        self.active = active;
+    }
+}
+
+/// Hardware PWM channel and pin function for the PWM-capable BCM pins
+fn hardware_pwm(pin: u8) -> Option<(Channel, Mode)> {
+    match pin {
+        12 => Some((Channel::Pwm0, Mode::Alt0)),
+        18 => Some((Channel::Pwm0, Mode::Alt5)),
+        13 => Some((Channel::Pwm1, Mode::Alt0)),
+        19 => Some((Channel::Pwm1, Mode::Alt5)),
+        _ => None,
+    }
+}
+
+/// A hardware PWM channel routed to the siren pin while a tone plays
+struct HardwarePwm {
+    pwm: Pwm,
+    mode: Mode,
+}
 
 /// Real GPIO controller using rppal
+#[derive(Clone)]
 pub struct RppalGpio {
-    reed_pin: Arc<RwLock<InputPin>>,
-    siren_pin: Arc<RwLock<OutputPin>>,
-    floodlight_pin: Arc<RwLock<OutputPin>>,
-    reed_active_low: bool,
-    door_state: Arc<RwLock<DoorState>>,
-    actuator_state: Arc<RwLock<ActuatorState>>,
+    inputs: Arc<HashMap<u8, Arc<Input>>>,
+    siren: Arc<Mutex<Output>>,
+    siren_pwm: Option<Arc<Mutex<HardwarePwm>>>,
+    floodlight: Arc<Mutex<Output>>,
+    outputs: Arc<HashMap<u8, Arc<Mutex<Output>>>>,
+    config: GpioConfig,
 }
 
 impl RppalGpio {
-    /// Create a new real GPIO controller
-    pub fn new(
-        reed_pin_num: u8,
-        siren_pin_num: u8,
-        floodlight_pin_num: u8,
-        reed_active_low: bool,
-    ) -> Result<Self> {
+    /// Create a new real GPIO controller from the pin configuration
+    pub fn from_config(config: &GpioConfig) -> Result<Self> {
         info!(
-            reed = reed_pin_num,
-            siren = siren_pin_num,
-            floodlight = floodlight_pin_num,
-            reed_active_low,
+            reed = config.reed_in,
+            siren = config.siren_out,
+            floodlight = config.floodlight_out,
             "Initializing real GPIO controller"
         );
 
         let gpio = Gpio::new().context("Failed to initialize GPIO")?;
+        let debounce = Some(Duration::from_millis(config.debounce_ms));
+
+        let mut inputs = HashMap::new();
+        for (name, pin_num) in config.input_pins() {
+            let options = config.options_for(&name);
+            let pin = gpio
+                .get(pin_num)
+                .with_context(|| format!("Failed to get {} input pin", name))?;
+            let mut pin = match options.pull {
+                Pull::Up => pin.into_input_pullup(),
+                Pull::Down => pin.into_input_pulldown(),
+                Pull::None => pin.into_input(),
+            };
+            pin.set_interrupt(Trigger::Both, debounce)
+                .with_context(|| format!("Failed to set {} pin interrupt", name))?;
+
+            debug!(name, pin = pin_num, ?options, "Input pin configured");
+            let high = AtomicBool::new(pin.read() == Level::High);
+            inputs.insert(pin_num, Arc::new(Input { pin: Mutex::new(pin), options, high }));
+        }
 
-        // Initialize reed input with pull-up
-        let mut reed_pin = gpio
-            .get(reed_pin_num)
-            .context("Failed to get reed input pin")?
-            .into_input_pullup();
-
-        // Set up interrupt for reed pin
-        reed_pin
-            .set_interrupt(Trigger::Both)
-            .context("Failed to set reed pin interrupt")?;
-
-        // Initialize output pins to safe low state
-        let mut siren_pin = gpio
-            .get(siren_pin_num)
-            .context("Failed to get siren output pin")?
-            .into_output();
-        siren_pin.set_low();
-
-        let mut floodlight_pin = gpio
-            .get(floodlight_pin_num)
-            .context("Failed to get floodlight output pin")?
-            .into_output();
-        floodlight_pin.set_low();
-
-        // Read initial door state
-        let initial_level = reed_pin.read();
-        let door_closed = if reed_active_low {
-            initial_level == Level::Low
-        } else {
-            initial_level == Level::High
+        let siren = Self::open_output(&gpio, config, "siren_out", config.siren_out)?;
+        let siren_pwm = Self::open_siren_pwm(config)?;
+        let floodlight = Self::open_output(&gpio, config, "floodlight_out", config.floodlight_out)?;
+
+        let mut outputs = HashMap::new();
+        let dedicated = [
+            ("strobe_out", config.strobe_out),
+            ("status_led_out", config.status_led_out),
+            ("buzzer_out", config.buzzer_out),
+        ];
+        for (name, pin) in dedicated {
+            if let Some(pin) = pin {
+                let output = Self::open_output(&gpio, config, name, pin)?;
+                outputs.insert(pin, Arc::new(Mutex::new(output)));
+            }
+        }
+        for output in &config.outputs {
+            let aux = Self::open_output(&gpio, config, &output.name, output.pin)?;
+            outputs.insert(output.pin, Arc::new(Mutex::new(aux)));
+        }
+
+        Ok(Self {
+            inputs: Arc::new(inputs),
+            siren: Arc::new(Mutex::new(siren)),
+            siren_pwm: siren_pwm.map(|pwm| Arc::new(Mutex::new(pwm))),
+            floodlight: Arc::new(Mutex::new(floodlight)),
+            outputs: Arc::new(outputs),
+            config: config.clone(),
+        })
+    }
+
+    /// Claim an output pin and drive it to its inactive level straight away
+    fn open_output(gpio: &Gpio, config: &GpioConfig, name: &str, pin_num: u8) -> Result<Output> {
+        let options = config.options_for(name);
+        let pin = gpio
+            .get(pin_num)
+            .with_context(|| format!("Failed to get {} output pin", name))?
+            .into_io(Mode::Output);
+
+        let mut output = Output {
+            pin,
+            options,
+            active: false,
         };
+        output.set(false);
 
-        let initial_door_state = if door_closed {
-            DoorState::Closed
+        debug!(name, pin = pin_num, ?options, "Output pin configured");
+        Ok(output)
+    }
+
+    /// Open a hardware PWM channel when a siren tone is configured on a PWM-capable pin.
+    ///
+    /// Other pins fall back to software PWM on the GPIO output itself.
+    fn open_siren_pwm(config: &GpioConfig) -> Result<Option<HardwarePwm>> {
+        let (frequency_hz, (channel, mode)) = match (config.siren_pwm_hz, hardware_pwm(config.siren_out)) {
+            (Some(frequency_hz), Some(hardware)) => (frequency_hz, hardware),
+            (Some(_), None) => {
+                info!(pin = config.siren_out, "Siren pin has no hardware PWM, using software PWM");
+                return Ok(None);
+            }
+            (None, _) => return Ok(None),
+        };
+
+        let polarity = if config.options_for("siren_out").active_low {
+            Polarity::Inverse
         } else {
-            DoorState::Open
+            Polarity::Normal
         };
+        let pwm = Pwm::with_frequency(channel, frequency_hz, config.siren_pwm_duty, polarity, false)
+            .context("Failed to open hardware PWM channel for siren")?;
 
-        info!(door_state = ?initial_door_state, "Initial door state detected");
+        info!(pin = config.siren_out, ?channel, "Siren using hardware PWM");
+        Ok(Some(HardwarePwm { pwm, mode }))
+    }
 
-        Ok(Self {
-            reed_pin: Arc::new(RwLock::new(reed_pin)),
-            siren_pin: Arc::new(RwLock::new(siren_pin)),
-            floodlight_pin: Arc::new(RwLock::new(floodlight_pin)),
-            reed_active_low,
-            door_state: Arc::new(RwLock::new(initial_door_state)),
-            actuator_state: Arc::new(RwLock::new(ActuatorState {
-                siren: false,
-                floodlight: false,
-            })),
-        })
+    /// Stop any PWM tone and hand the siren pin back to plain GPIO output
+    fn stop_tone(siren: &mut Output, pwm: Option<&mut HardwarePwm>) -> Result<()> {
+        match pwm {
+            Some(hardware) => {
+                hardware.pwm.disable()?;
+                siren.pin.set_mode(Mode::Output);
+            }
+            None => siren.pin.clear_pwm()?,
+        }
+        Ok(())
     }
 
-    /// Poll reed pin for state changes (with debouncing)
-    async fn poll_reed_state(&self) -> Result<DoorState> {
-        let reed_pin = self.reed_pin.read().await;
-        let level = reed_pin.read();
+    fn input(&self, pin: u8) -> Result<Arc<Input>> {
+        self.inputs
+            .get(&pin)
+            .cloned()
+            .ok_or_else(|| anyhow!("GPIO pin {} is not configured as an input", pin))
+    }
 
-        let door_closed = if self.reed_active_low {
-            level == Level::Low
-        } else {
-            level == Level::High
-        };
+    fn output(&self, pin: u8) -> Result<Arc<Mutex<Output>>> {
+        self.outputs
+            .get(&pin)
+            .cloned()
+            .ok_or_else(|| anyhow!("GPIO pin {} is not configured as an output", pin))
+    }
 
-        Ok(if door_closed {
-            DoorState::Closed
-        } else {
-            DoorState::Open
+    fn optional_input(&self, pin: Option<u8>, name: &str) -> Result<Arc<Input>> {
+        let pin = pin.ok_or_else(|| anyhow!("No {} pin configured", name))?;
+        self.input(pin)
+    }
+
+    /// Block (on the blocking pool) until the input changes, returning the logical edge
+    async fn wait_edge(input: Arc<Input>) -> Result<Edge> {
+        tokio::task::spawn_blocking(move || loop {
+            // The pin is locked for one poll at a time; readers meanwhile use the level below
+            let event = input
+                .pin
+                .lock()
+                .poll_interrupt(true, Some(POLL_TIMEOUT))
+                .context("Failed to poll GPIO interrupt")?;
+
+            if let Some(event) = event {
+                let level_high = event.trigger == Trigger::RisingEdge;
+                input.high.store(level_high, Ordering::Relaxed);
+                let edge = if input.options.is_active(level_high) {
+                    Edge::Rising
+                } else {
+                    Edge::Falling
+                };
+                return Ok(edge);
+            }
         })
+        .await
+        .context("GPIO interrupt task failed")?
     }
 }
 
 #[async_trait::async_trait]
 impl GpioController for RppalGpio {
-    async fn read_door_state(&self) -> Result<DoorState> {
-        // Read current state and update cached value
-        let new_state = self.poll_reed_state().await?;
-        let mut door_state = self.door_state.write().await;
-        
-        if *door_state != new_state {
-            debug!(old_state = ?*door_state, new_state = ?new_state, "Door state changed");
-            *door_state = new_state;
+    async fn initialize(&mut self) -> Result<()> {
+        // Outputs were driven inactive when claimed; make sure nothing changed since
+        self.siren.lock().set(false);
+        self.floodlight.lock().set(false);
+        for output in self.outputs.values() {
+            output.lock().set(false);
         }
 
-        Ok(*door_state)
+        let door_open = self.read_door_sensor().await?;
+        info!(door_open, "Initial door state detected");
+        Ok(())
     }
 
-    async fn set_siren(&self, enabled: bool) -> Result<()> {
-        debug!(enabled, "Setting siren");
-        
-        let mut siren_pin = self.siren_pin.write().await;
-        if enabled {
-            siren_pin.set_high();
-        } else {
-            siren_pin.set_low();
-        }
+    async fn read_door_sensor(&self) -> Result<bool> {
+        // The reed switch is "active" when the magnet holds it closed
+        let input = self.input(self.config.reed_in)?;
+        let closed = input.is_active();
+        Ok(!closed)
+    }
 
-        let mut state = self.actuator_state.write().await;
-        state.siren = enabled;
+    async fn read_motion_sensor(&self) -> Result<bool> {
+        let input = self.optional_input(self.config.motion_in, "motion")?;
+        let active = input.is_active();
+        Ok(active)
+    }
+
+    async fn wait_for_motion_edge(&self) -> Result<Edge> {
+        let input = self.optional_input(self.config.motion_in, "motion")?;
+        Self::wait_edge(input).await
+    }
 
+    async fn read_input(&self, pin: u8) -> Result<bool> {
+        let input = self.input(pin)?;
+        let active = input.is_active();
+        Ok(active)
+    }
+
+    async fn wait_for_input_edge(&self, pin: u8) -> Result<Edge> {
+        Self::wait_edge(self.input(pin)?).await
+    }
+
+    async fn watch_levels(&self, pin: u8) -> Result<mpsc::Receiver<LevelChange>> {
+        let mut input = Gpio::new()
+            .context("Failed to initialize GPIO")?
+            .get(pin)
+            .with_context(|| format!("Failed to get pin {}", pin))?
+            .into_input();
+        input
+            .set_interrupt(Trigger::Both, None)
+            .with_context(|| format!("Failed to set pin {} interrupt", pin))?;
+
+        // Pulses are a few hundred microseconds, so poll from a dedicated thread
+        let (tx, rx) = mpsc::channel(LEVEL_BUFFER);
+        std::thread::Builder::new()
+            .name(format!("gpio{}-levels", pin))
+            .spawn(move || {
+                while !tx.is_closed() {
+                    match input.poll_interrupt(false, Some(POLL_TIMEOUT)) {
+                        Ok(Some(event)) => {
+                            let _ = tx.try_send(LevelChange {
+                                high: event.trigger == Trigger::RisingEdge,
+                                at_us: event.timestamp.as_micros() as u64,
+                            });
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            warn!(pin, error = %e, "GPIO level watch failed");
+                            break;
+                        }
+                    }
+                }
+            })
+            .context("Failed to start GPIO level thread")?;
+        Ok(rx)
+    }
+
+    async fn wait_for_vibration_pulse(&self) -> Result<()> {
+        let input = self.optional_input(self.config.vibration_in, "vibration")?;
+        loop {
+            if Self::wait_edge(input.clone()).await? == Edge::Rising {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn set_siren(&self, on: bool) -> Result<()> {
+        debug!(on, "Setting siren");
+        let mut siren = self.siren.lock();
+        let mut pwm = self.siren_pwm.as_ref().map(|pwm| pwm.lock());
+        Self::stop_tone(&mut siren, pwm.as_deref_mut())?;
+        siren.set(on);
         Ok(())
     }
 
-    async fn set_floodlight(&self, enabled: bool) -> Result<()> {
-        debug!(enabled, "Setting floodlight");
-        
-        let mut floodlight_pin = self.floodlight_pin.write().await;
-        if enabled {
-            floodlight_pin.set_high();
-        } else {
-            floodlight_pin.set_low();
+    async fn set_siren_tone(&self, frequency_hz: f64, duty_cycle: f64) -> Result<()> {
+        debug!(frequency_hz, duty_cycle, "Setting siren tone");
+        let mut siren = self.siren.lock();
+        match &self.siren_pwm {
+            Some(hardware) => {
+                let hardware = hardware.lock();
+                hardware.pwm.set_frequency(frequency_hz, duty_cycle)?;
+                siren.pin.set_mode(hardware.mode);
+                hardware.pwm.enable()?;
+            }
+            None => {
+                // Software PWM drives the raw level, so invert the duty for active-low pins
+                let duty_cycle = if siren.options.active_low {
+                    1.0 - duty_cycle
+                } else {
+                    duty_cycle
+                };
+                siren.pin.set_pwm_frequency(frequency_hz, duty_cycle)?;
+            }
         }
+        siren.active = true;
+        Ok(())
+    }
 
-        let mut state = self.actuator_state.write().await;
-        state.floodlight = enabled;
+    async fn set_floodlight(&self, on: bool) -> Result<()> {
+        debug!(on, "Setting floodlight");
+        self.floodlight.lock().set(on);
+        Ok(())
+    }
 
+    async fn set_output(&self, pin: u8, on: bool) -> Result<()> {
+        debug!(pin, on, "Setting output");
+        let output = self.output(pin)?;
+        let mut output = output.lock();
+        output.pin.clear_pwm()?;
+        output.set(on);
         Ok(())
     }
 
-    async fn get_actuator_state(&self) -> ActuatorState {
-        *self.actuator_state.read().await
+    async fn set_output_tone(&self, pin: u8, frequency_hz: f64, duty_cycle: f64) -> Result<()> {
+        debug!(pin, frequency_hz, duty_cycle, "Setting output tone");
+        let output = self.output(pin)?;
+        let mut output = output.lock();
+        let duty_cycle = if output.options.active_low {
+            1.0 - duty_cycle
+        } else {
+            duty_cycle
+        };
+        output.pin.set_pwm_frequency(frequency_hz, duty_cycle)?;
+        output.active = true;
+        Ok(())
+    }
+
+    async fn send_pulses(&self, pin: u8, durations_us: &[u64]) -> Result<()> {
+        debug!(pin, pulses = durations_us.len(), "Sending pulse train");
+        let output = self.output(pin)?;
+        let durations = durations_us.to_vec();
+        tokio::task::spawn_blocking(move || {
+            let mut output = output.lock();
+            output.pin.clear_pwm()?;
+            super::drive_pulses(&durations, |high| {
+                output.set(high);
+                Ok(())
+            })
+        })
+        .await?
+    }
+
+    async fn get_output_state(&self, pin: u8) -> Result<bool> {
+        let output = self.output(pin)?;
+        let active = output.lock().active;
+        Ok(active)
+    }
+
+    async fn wait_for_door_edge(&self) -> Result<Edge> {
+        let edge = Self::wait_edge(self.input(self.config.reed_in)?).await?;
+        // Reed active (closed) maps to a falling door edge
+        Ok(match edge {
+            Edge::Rising => Edge::Falling,
+            Edge::Falling => Edge::Rising,
+            Edge::Both => Edge::Both,
+        })
     }
 
-    async fn emergency_shutdown(&self) -> Result<()> {
+    fn emergency_shutdown(&self) {
         warn!("Emergency GPIO shutdown initiated");
-        
-        // Set all outputs to safe low state
-        {
-            let mut siren_pin = self.siren_pin.write().await;
-            siren_pin.set_low();
+
+        // Never block in a panic handler: skip any pin another thread is holding
+        match self.siren.try_lock() {
+            Some(mut siren) => {
+                let mut pwm = self.siren_pwm.as_ref().and_then(|pwm| pwm.try_lock());
+                if let Err(e) = Self::stop_tone(&mut siren, pwm.as_deref_mut()) {
+                    warn!(error = %e, "Failed to stop siren PWM during emergency shutdown");
+                }
+                siren.set(false);
+            }
+            None => warn!("Siren pin busy during emergency shutdown"),
         }
-        
-        {
-            let mut floodlight_pin = self.floodlight_pin.write().await;
-            floodlight_pin.set_low();
+        match self.floodlight.try_lock() {
+            Some(mut floodlight) => floodlight.set(false),
+            None => warn!("Floodlight pin busy during emergency shutdown"),
+        }
+        for (pin, output) in self.outputs.iter() {
+            match output.try_lock() {
+                Some(mut output) => {
+                    let _ = output.pin.clear_pwm();
+                    output.set(false);
+                }
+                None => warn!(pin, "Output pin busy during emergency shutdown"),
+            }
         }
-
-        let mut state = self.actuator_state.write().await;
-        state.siren = false;
-        state.floodlight = false;
 
         info!("Emergency GPIO shutdown complete");
-        Ok(())
     }
-}
 
-impl Drop for RppalGpio {
-    fn drop(&mut self) {
-        // Emergency shutdown on drop (async not available in Drop)
-        // This is best-effort only
-        warn!("RppalGpio dropped, attempting emergency shutdown");
-        
-        // Note: We can't await in Drop, so this is synchronous and may not complete
-        // The proper shutdown should be done via emergency_shutdown() before dropping
+    async fn get_siren_state(&self) -> Result<bool> {
+        Ok(self.siren.lock().active)
+    }
+
+    async fn get_floodlight_state(&self) -> Result<bool> {
+        Ok(self.floodlight.lock().active)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::AppConfig;
 
     // Note: These tests require actual Raspberry Pi hardware and will fail in CI
     // They are marked as ignored and should be run manually on target hardware
@@ -203,47 +498,43 @@ mod tests {
     #[tokio::test]
     #[ignore = "requires Raspberry Pi hardware"]
     async fn test_gpio_initialization() {
-        let gpio = RppalGpio::new(17, 27, 22, true);
+        let gpio = RppalGpio::from_config(&AppConfig::test_default().gpio);
         assert!(gpio.is_ok(), "GPIO initialization should succeed on Pi");
     }
 
     #[tokio::test]
     #[ignore = "requires Raspberry Pi hardware"]
     async fn test_door_state_reading() {
-        let gpio = RppalGpio::new(17, 27, 22, true).unwrap();
-        let state = gpio.read_door_state().await;
+        let gpio = RppalGpio::from_config(&AppConfig::test_default().gpio).unwrap();
+        let state = gpio.read_door_sensor().await;
         assert!(state.is_ok(), "Should be able to read door state");
     }
 
     #[tokio::test]
     #[ignore = "requires Raspberry Pi hardware"]
     async fn test_actuator_control() {
-        let gpio = RppalGpio::new(17, 27, 22, true).unwrap();
-        
-        // Test siren
+        let gpio = RppalGpio::from_config(&AppConfig::test_default().gpio).unwrap();
+
         gpio.set_siren(true).await.unwrap();
-        let state = gpio.get_actuator_state().await;
-        assert!(state.siren);
-        
+        assert!(gpio.get_siren_state().await.unwrap());
+
         gpio.set_siren(false).await.unwrap();
-        let state = gpio.get_actuator_state().await;
-        assert!(!state.siren);
+        assert!(!gpio.get_siren_state().await.unwrap());
     }
 
     #[tokio::test]
     #[ignore = "requires Raspberry Pi hardware"]
-    async fn test_emergency_shutdown() {
-        let gpio = RppalGpio::new(17, 27, 22, true).unwrap();
-        
-        // Turn on actuators
+    async fn test_active_low_siren() {
+        let mut config = AppConfig::test_default().gpio;
+        config.pin_options.insert(
+            "siren_out".to_string(),
+            PinOptions { active_low: true, ..Default::default() },
+        );
+        let gpio = RppalGpio::from_config(&config).unwrap();
+
         gpio.set_siren(true).await.unwrap();
-        gpio.set_floodlight(true).await.unwrap();
-        
-        // Emergency shutdown
-        gpio.emergency_shutdown().await.unwrap();
-        
-        let state = gpio.get_actuator_state().await;
-        assert!(!state.siren);
-        assert!(!state.floodlight);
+        assert!(gpio.get_siren_state().await.unwrap());
+        gpio.emergency_shutdown();
+        assert!(!gpio.get_siren_state().await.unwrap());
     }
 }
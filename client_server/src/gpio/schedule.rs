@@ -0,0 +1,239 @@
+//! Unattended self-tests on a schedule
+//!
+//! Every `self_test.check_s` the scheduler looks whether
+//! `self_test.interval_days` have passed since its last run. A run only starts
+//! inside the `self_test.window_start`..`window_end` window, never inside the
+//! night window and only while disarmed, so it neither wakes the household nor
+//! touches an armed system. It requests the wiring self-test and, once that has
+//! finished, chirps the siren so a dead one is noticed by someone nearby.
+//! Every self-test result, scheduled or not, is kept in `self_test.json` in the
+//! data directory, and `self_test_completed` carries it to the master.
+
+use super::WiringReport;
+use crate::actuators::SirenPattern;
+use crate::config::{in_window, AppConfig, SelfTestConfig, TimerConfig};
+use crate::events::{Event, EventBus, EventSource};
+use crate::state::{AlarmState, AppState};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Recent self-test results and when the schedule last started one
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SelfTestLog {
+    pub last_scheduled: Option<DateTime<Utc>>,
+    /// Oldest first
+    pub results: VecDeque<WiringReport>,
+}
+
+impl SelfTestLog {
+    /// The log at `path`, empty if there is none yet
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                serde_json::from_str(&contents).with_context(|| format!("Invalid self-test log {}", path.display()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Write the log atomically
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+    }
+
+    fn record(&mut self, report: WiringReport, keep: usize) {
+        self.results.push_back(report);
+        while self.results.len() > keep {
+            self.results.pop_front();
+        }
+    }
+}
+
+/// Starts self-tests when they are due and keeps every result
+pub struct SelfTestScheduler {
+    config: SelfTestConfig,
+    timers: TimerConfig,
+    path: PathBuf,
+    event_bus: EventBus,
+    state: AppState,
+}
+
+impl SelfTestScheduler {
+    pub fn new(config: &AppConfig, event_bus: EventBus, state: AppState) -> Self {
+        Self {
+            config: config.self_test.clone(),
+            timers: config.timers.clone(),
+            path: config.self_test_path(),
+            event_bus,
+            state,
+        }
+    }
+
+    /// Spawn the scheduler; results are recorded even with the schedule disabled
+    pub fn spawn(self) -> JoinHandle<()> {
+        let mut rx = self.event_bus.subscribe();
+        tokio::spawn(async move {
+            let mut log = SelfTestLog::load(&self.path).unwrap_or_else(|e| {
+                warn!(error = %e, "Starting a new self-test log");
+                SelfTestLog::default()
+            });
+            if self.config.enabled {
+                info!(interval_days = self.config.interval_days, "Self-test schedule started");
+            }
+            let mut interval = tokio::time::interval(Duration::from_secs(self.config.check_s.max(1)));
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick(), if self.config.enabled => {
+                        if self.due(log.last_scheduled, Local::now()) {
+                            self.start(&mut log);
+                        }
+                    }
+                    received = rx.recv() => match received {
+                        Ok(envelope) => {
+                            if let Event::SelfTestCompleted { report } = envelope.event {
+                                self.completed(&mut log, report);
+                            }
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                    },
+                }
+            }
+        })
+    }
+
+    /// Whether a scheduled run may start now: counted in calendar days so runs do not drift through the window
+    fn due(&self, last: Option<DateTime<Utc>>, now: DateTime<Local>) -> bool {
+        let days_since = last.map(|last| (now.date_naive() - last.with_timezone(&Local).date_naive()).num_days());
+        if days_since.is_some_and(|days| days < self.config.interval_days as i64) {
+            return false;
+        }
+        let time = now.time();
+        let in_run_window = self.config.window().is_ok_and(|(start, end)| in_window(start, end, time));
+        in_run_window && !self.timers.is_night(time) && self.state.read().alarm_state == AlarmState::Disarmed
+    }
+
+    /// Request a run; it counts as done even if it fails, so a broken pin is not retested every check
+    fn start(&self, log: &mut SelfTestLog) {
+        info!("Starting scheduled self-test");
+        log.last_scheduled = Some(Utc::now());
+        self.save(log);
+        self.emit(Event::SelfTestRequested { source: EventSource::System });
+    }
+
+    fn completed(&self, log: &mut SelfTestLog, report: WiringReport) {
+        let scheduled = report.scheduled;
+        log.record(report, self.config.keep_results);
+        self.save(log);
+
+        // Only chirp if nobody armed the system while the wiring test ran
+        if scheduled && self.config.chirp && self.state.read().alarm_state == AlarmState::Disarmed {
+            self.emit(Event::SirenControl {
+                on: true,
+                duration_s: Some(SirenPattern::Chirp.cycle().as_secs().max(1)),
+                pattern: Some(SirenPattern::Chirp),
+            });
+        }
+    }
+
+    fn save(&self, log: &SelfTestLog) {
+        if let Err(e) = log.save(&self.path) {
+            warn!(error = %e, "Failed to save the self-test log");
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        if let Err(e) = self.event_bus.emit(event) {
+            warn!(error = %e, "Failed to emit scheduled self-test event");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::new_app_state;
+    use chrono::NaiveDateTime;
+
+    fn scheduler(dir: &Path) -> (SelfTestScheduler, crate::events::EventReceiver) {
+        let mut config = AppConfig::test_default();
+        config.system.data_dir = dir.to_path_buf();
+        config.self_test.enabled = true;
+        let (event_bus, rx) = EventBus::new();
+        (SelfTestScheduler::new(&config, event_bus, new_app_state()), rx)
+    }
+
+    fn at(s: &str) -> DateTime<Local> {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap().and_local_timezone(Local).unwrap()
+    }
+
+    #[test]
+    fn test_due_inside_window_while_disarmed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (scheduler, _rx) = scheduler(dir.path());
+
+        assert!(scheduler.due(None, at("2026-03-02 11:00")));
+        assert!(!scheduler.due(None, at("2026-03-02 09:59")));
+        assert!(!scheduler.due(None, at("2026-03-02 16:00")));
+
+        // A window reaching into the night is cut short by it
+        let mut late = scheduler;
+        late.config.window_end = "20:00".to_string();
+        assert!(late.due(None, at("2026-03-02 17:59")));
+        assert!(!late.due(None, at("2026-03-02 18:30")));
+
+        // A week counts in calendar days, so a late run does not push the next one later
+        late.config.window_end = "16:00".to_string();
+        let last = Some(at("2026-03-02 15:50").with_timezone(&Utc));
+        assert!(!late.due(last, at("2026-03-08 11:00")));
+        assert!(late.due(last, at("2026-03-09 10:05")));
+
+        late.state.write().set_alarm_state(AlarmState::Armed);
+        assert!(!late.due(last, at("2026-03-09 10:05")));
+    }
+
+    #[tokio::test]
+    async fn test_results_kept_and_scheduled_run_chirps() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut scheduler, mut rx) = scheduler(dir.path());
+        scheduler.config.keep_results = 2;
+        let mut log = SelfTestLog::default();
+
+        scheduler.start(&mut log);
+        assert!(matches!(rx.recv().await.unwrap(), Event::SelfTestRequested { source: EventSource::System }));
+
+        let mut report: WiringReport = serde_json::from_value(serde_json::json!({
+            "ok": true, "checked_at": Utc::now(), "outputs": [], "inputs": []
+        }))
+        .unwrap();
+        scheduler.completed(&mut log, report.clone());
+        scheduler.completed(&mut log, report.clone());
+        assert!(rx.try_recv().is_err());
+
+        report.scheduled = true;
+        scheduler.completed(&mut log, report);
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::SirenControl { on: true, pattern: Some(SirenPattern::Chirp), .. }
+        ));
+
+        let saved = SelfTestLog::load(&dir.path().join("self_test.json")).unwrap();
+        assert_eq!(saved.results.len(), 2);
+        assert!(saved.results.back().unwrap().scheduled);
+        assert_eq!(saved.last_scheduled, log.last_scheduled);
+    }
+}
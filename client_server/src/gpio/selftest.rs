@@ -0,0 +1,283 @@
+//! GPIO self-test verifying the wiring of every configured pin
+//!
+//! Outputs are pulsed briefly and read back; inputs are sampled for a short
+//! window to catch floating lines (chatter) and lines held active at rest.
+
+use super::traits::GpioController;
+use crate::config::GpioConfig;
+use crate::events::{Event, EventBus, EventSource};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// How long each output is driven during its check
+const OUTPUT_PULSE: Duration = Duration::from_millis(20);
+
+/// Input samples taken per pin, and the spacing between them
+const INPUT_SAMPLES: u32 = 20;
+const INPUT_SAMPLE_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Result of checking one output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputStatus {
+    /// Toggled on and off and read back correctly
+    Ok,
+    /// Already on (e.g. a running alarm), left untouched
+    InUse,
+    /// Read back a different state than was driven
+    Mismatch,
+    /// The backend rejected the pin
+    Error,
+}
+
+/// Result of checking one input
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputStatus {
+    Ok,
+    /// Held active at rest: either stuck or genuinely triggered
+    Active,
+    /// Changed level during sampling, typically an unconnected pin without pull
+    Floating,
+    /// The backend rejected the pin
+    Error,
+}
+
+/// Check outcome for a single pin
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinCheck<S> {
+    pub name: String,
+    pub pin: u8,
+    pub status: S,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Structured wiring-health result of a self-test run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WiringReport {
+    /// False when any output failed or any input floats or errors
+    pub ok: bool,
+    pub checked_at: DateTime<Utc>,
+    /// Started by the self-test schedule rather than at startup or on request
+    #[serde(default)]
+    pub scheduled: bool,
+    pub outputs: Vec<PinCheck<OutputStatus>>,
+    pub inputs: Vec<PinCheck<InputStatus>>,
+}
+
+impl WiringReport {
+    fn new(outputs: Vec<PinCheck<OutputStatus>>, inputs: Vec<PinCheck<InputStatus>>) -> Self {
+        let ok = outputs
+            .iter()
+            .all(|c| matches!(c.status, OutputStatus::Ok | OutputStatus::InUse))
+            && inputs
+                .iter()
+                .all(|c| matches!(c.status, InputStatus::Ok | InputStatus::Active));
+        Self {
+            ok,
+            checked_at: Utc::now(),
+            scheduled: false,
+            outputs,
+            inputs,
+        }
+    }
+}
+
+/// Runs the wiring self-test at startup and whenever one is requested or scheduled
+pub struct SelfTest {
+    gpio: Arc<dyn GpioController>,
+    event_bus: EventBus,
+    config: GpioConfig,
+}
+
+impl SelfTest {
+    pub fn new(gpio: Arc<dyn GpioController>, event_bus: EventBus, config: GpioConfig) -> Self {
+        Self {
+            gpio,
+            event_bus,
+            config,
+        }
+    }
+
+    /// Spawn the self-test task
+    pub fn spawn(self) -> JoinHandle<()> {
+        let mut rx = self.event_bus.subscribe();
+        tokio::spawn(async move {
+            if self.config.self_test_on_startup {
+                self.run_and_report(false).await;
+            }
+
+            loop {
+                match rx.recv().await {
+                    Ok(envelope) => {
+                        if let Event::SelfTestRequested { source } = envelope.event {
+                            self.run_and_report(source == EventSource::System).await;
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    /// The schedule requests its runs with `EventSource::System`
+    async fn run_and_report(&self, scheduled: bool) {
+        let mut report = self.run().await;
+        report.scheduled = scheduled;
+        if report.ok {
+            info!("GPIO self-test passed");
+        } else {
+            warn!(?report, "GPIO self-test found wiring problems");
+        }
+        if let Err(e) = self.event_bus.emit(Event::SelfTestCompleted { report }) {
+            error!(error = %e, "Failed to report GPIO self-test result");
+        }
+    }
+
+    /// Check every configured output and input
+    pub async fn run(&self) -> WiringReport {
+        let mut outputs = Vec::new();
+        for (name, pin) in self.config.output_pins() {
+            let (status, error) = match self.check_output(&name, pin).await {
+                Ok(status) => (status, None),
+                Err(e) => (OutputStatus::Error, Some(e.to_string())),
+            };
+            outputs.push(PinCheck { name, pin, status, error });
+        }
+
+        let mut inputs = Vec::new();
+        for (name, pin) in self.config.input_pins() {
+            let (status, error) = match self.check_input(&name, pin).await {
+                Ok(status) => (status, None),
+                Err(e) => (InputStatus::Error, Some(e.to_string())),
+            };
+            inputs.push(PinCheck { name, pin, status, error });
+        }
+
+        WiringReport::new(outputs, inputs)
+    }
+
+    async fn check_output(&self, name: &str, pin: u8) -> Result<OutputStatus> {
+        if self.output_state(name, pin).await? {
+            return Ok(OutputStatus::InUse);
+        }
+
+        self.set_output(name, pin, true).await?;
+        let driven_on = self.output_state(name, pin).await;
+        tokio::time::sleep(OUTPUT_PULSE).await;
+        self.set_output(name, pin, false).await?;
+
+        let released = !self.output_state(name, pin).await?;
+        Ok(if driven_on? && released {
+            OutputStatus::Ok
+        } else {
+            OutputStatus::Mismatch
+        })
+    }
+
+    async fn check_input(&self, name: &str, pin: u8) -> Result<InputStatus> {
+        let first = self.read_input(name, pin).await?;
+        for _ in 1..INPUT_SAMPLES {
+            tokio::time::sleep(INPUT_SAMPLE_INTERVAL).await;
+            if self.read_input(name, pin).await? != first {
+                return Ok(InputStatus::Floating);
+            }
+        }
+
+        // An open door is an ordinary state, not a stuck contact
+        Ok(if first && name != "reed_in" {
+            InputStatus::Active
+        } else {
+            InputStatus::Ok
+        })
+    }
+
+    async fn read_input(&self, name: &str, pin: u8) -> Result<bool> {
+        match name {
+            "reed_in" => self.gpio.read_door_sensor().await,
+            "motion_in" => self.gpio.read_motion_sensor().await,
+            _ => self.gpio.read_input(pin).await,
+        }
+    }
+
+    async fn set_output(&self, name: &str, pin: u8, on: bool) -> Result<()> {
+        match name {
+            "siren_out" => self.gpio.set_siren(on).await,
+            "floodlight_out" => self.gpio.set_floodlight(on).await,
+            _ => self.gpio.set_output(pin, on).await,
+        }
+    }
+
+    async fn output_state(&self, name: &str, pin: u8) -> Result<bool> {
+        match name {
+            "siren_out" => self.gpio.get_siren_state().await,
+            "floodlight_out" => self.gpio.get_floodlight_state().await,
+            _ => self.gpio.get_output_state(pin).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::gpio::MockGpio;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_healthy_wiring() {
+        let gpio = Arc::new(MockGpio::new());
+        let config = AppConfig::test_default().gpio;
+        let selftest = SelfTest::new(gpio.clone(), EventBus::new().0, config);
+
+        gpio.set_floodlight(true).await.unwrap();
+        let report = selftest.run().await;
+
+        assert!(report.ok);
+        let floodlight = report.outputs.iter().find(|c| c.name == "floodlight_out").unwrap();
+        assert_eq!(floodlight.status, OutputStatus::InUse);
+        assert!(gpio.get_floodlight_state().await.unwrap());
+        assert!(!gpio.get_siren_state().await.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_floating_and_stuck_inputs() {
+        let gpio = Arc::new(MockGpio::new());
+        let mut config = AppConfig::test_default().gpio;
+        config.motion_in = Some(23);
+        let selftest = SelfTest::new(gpio.clone(), EventBus::new().0, config);
+
+        // Motion flips while it is being sampled
+        let toggler = {
+            let gpio = gpio.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                gpio.simulate_motion();
+            })
+        };
+        let status = selftest.check_input("motion_in", 23).await.unwrap();
+        toggler.await.unwrap();
+        assert_eq!(status, InputStatus::Floating);
+
+        // Held active for the whole window
+        let status = selftest.check_input("motion_in", 23).await.unwrap();
+        assert_eq!(status, InputStatus::Active);
+
+        let report = WiringReport::new(
+            Vec::new(),
+            vec![PinCheck {
+                name: "motion_in".to_string(),
+                pin: 23,
+                status: InputStatus::Floating,
+                error: None,
+            }],
+        );
+        assert!(!report.ok);
+    }
+}
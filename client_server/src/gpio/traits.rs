@@ -2,6 +2,7 @@
 
 use anyhow::Result;
 use async_trait::async_trait;
+use tokio::sync::mpsc;
 
 /// GPIO edge detection
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -11,6 +12,18 @@ pub enum Edge {
     Both,
 }
 
+/// Level changes buffered per watched line; further changes are dropped until the reader catches up
+pub const LEVEL_BUFFER: usize = 1024;
+
+/// A raw level change on an input line, for pulse-timing decoders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelChange {
+    /// Electrical level after the change
+    pub high: bool,
+    /// Monotonic timestamp in microseconds
+    pub at_us: u64,
+}
+
 /// GPIO controller trait for hardware abstraction
 #[async_trait]
 pub trait GpioController: Send + Sync {
@@ -20,12 +33,45 @@ pub trait GpioController: Send + Sync {
     /// Read the door sensor state (true = open, false = closed)
     async fn read_door_sensor(&self) -> Result<bool>;
 
+    /// Read the PIR motion sensor state (true = motion detected)
+    async fn read_motion_sensor(&self) -> Result<bool>;
+
+    /// Wait for a motion sensor edge event
+    async fn wait_for_motion_edge(&self) -> Result<Edge>;
+
+    /// Wait for a single pulse from the vibration/glass-break sensor
+    async fn wait_for_vibration_pulse(&self) -> Result<()>;
+
+    /// Read a generic zone input (true = active)
+    async fn read_input(&self, pin: u8) -> Result<bool>;
+
+    /// Wait for an edge on a generic zone input
+    async fn wait_for_input_edge(&self, pin: u8) -> Result<Edge>;
+
+    /// Stream every level change of a line not claimed as a zone input, without debouncing
+    async fn watch_levels(&self, pin: u8) -> Result<mpsc::Receiver<LevelChange>>;
+
     /// Set siren relay state
     async fn set_siren(&self, on: bool) -> Result<()>;
 
     /// Set floodlight relay state
     async fn set_floodlight(&self, on: bool) -> Result<()>;
 
+    /// Sound the siren with a PWM tone; `set_siren(false)` silences it
+    async fn set_siren_tone(&self, frequency_hz: f64, duty_cycle: f64) -> Result<()>;
+
+    /// Set an auxiliary relay output by pin number
+    async fn set_output(&self, pin: u8, on: bool) -> Result<()>;
+
+    /// Drive an auxiliary output with a PWM tone; `set_output(pin, false)` silences it
+    async fn set_output_tone(&self, pin: u8, frequency_hz: f64, duty_cycle: f64) -> Result<()>;
+
+    /// Send a pulse train on an output, starting high and toggling after each duration (µs)
+    async fn send_pulses(&self, pin: u8, durations_us: &[u64]) -> Result<()>;
+
+    /// Get current state of an auxiliary relay output
+    async fn get_output_state(&self, pin: u8) -> Result<bool>;
+
     /// Wait for a door sensor edge event
     async fn wait_for_door_edge(&self) -> Result<Edge>;
 
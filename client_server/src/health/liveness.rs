@@ -0,0 +1,92 @@
+//! Progress marks from the subsystems the watchdog vouches for
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often a healthy subsystem marks progress, busy or idle
+pub const BEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A subsystem whose hang should restart the agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    /// The state machine taking events off the bus
+    EventLoop,
+    /// Reads of the GPIO inputs
+    Gpio,
+    /// The timer manager behind exit and entry delays
+    Timers,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 3] = [Subsystem::EventLoop, Subsystem::Gpio, Subsystem::Timers];
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Subsystem::EventLoop => "event_loop",
+            Subsystem::Gpio => "gpio",
+            Subsystem::Timers => "timers",
+        })
+    }
+}
+
+/// When each subsystem last made progress, shared by all of them
+#[derive(Debug, Clone)]
+pub struct Liveness {
+    started: Instant,
+    /// Milliseconds after `started`, indexed by `Subsystem`
+    beats: Arc<[AtomicU64; 3]>,
+}
+
+impl Liveness {
+    /// Every subsystem counts as having made progress now
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            beats: Arc::new(Default::default()),
+        }
+    }
+
+    /// Mark progress of `subsystem`
+    pub fn beat(&self, subsystem: Subsystem) {
+        let now = self.started.elapsed().as_millis() as u64;
+        self.beats[subsystem as usize].store(now, Ordering::Relaxed);
+    }
+
+    /// Time since `subsystem` last made progress
+    pub fn silent_for(&self, subsystem: Subsystem) -> Duration {
+        let beat = Duration::from_millis(self.beats[subsystem as usize].load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(beat)
+    }
+
+    /// Subsystems without progress for longer than `max_silence`
+    pub fn stalled(&self, max_silence: Duration) -> Vec<Subsystem> {
+        Subsystem::ALL.into_iter().filter(|&subsystem| self.silent_for(subsystem) > max_silence).collect()
+    }
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stalled_subsystems() {
+        let liveness = Liveness::new();
+        assert!(liveness.stalled(Duration::from_millis(50)).is_empty());
+
+        std::thread::sleep(Duration::from_millis(80));
+        liveness.beat(Subsystem::EventLoop);
+        liveness.beat(Subsystem::Timers);
+        assert_eq!(liveness.stalled(Duration::from_millis(50)), vec![Subsystem::Gpio]);
+        assert!(liveness.silent_for(Subsystem::EventLoop) < Duration::from_millis(50));
+    }
+}
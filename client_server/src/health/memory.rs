@@ -0,0 +1,268 @@
+//! Resident memory of the agent and a restart when it runs away
+//!
+//! RSS is read every `memory.interval_s` into `memory` in the shared state. A
+//! leak raises the floor that memory falls back to, so the lowest readings at
+//! the start and end of `memory.growth_window_h` are compared; a rise of
+//! `memory.growth_warn_mb` raises `memory_growing`. With `memory.ceiling_mb`
+//! set, two readings above it raise `memory_ceiling_exceeded`, put the outputs
+//! in their safe state and restart the agent through systemd.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use super::{Component, HealthStatus};
+use crate::config::MemoryConfig;
+use crate::events::{Event, EventBus};
+use crate::gpio::GpioController;
+use crate::state::AppState;
+
+const MB: u64 = 1024 * 1024;
+/// Time the ceiling event gets to reach the journal and the offline queue before the restart
+const RESTART_GRACE: Duration = Duration::from_secs(2);
+
+/// Latest memory readings
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryStatus {
+    pub rss_bytes: Option<u64>,
+    pub peak_bytes: u64,
+    /// Rise of the lowest reading over the growth window, once it has been covered
+    pub growth_bytes: Option<u64>,
+    /// `memory_growing` was raised and the growth has not fallen back since
+    pub growing: bool,
+    pub checked_at: Option<DateTime<Utc>>,
+}
+
+/// Resident memory from a `/proc/<pid>/status` listing
+pub fn parse_vm_rss(status: &str) -> Result<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:")).context("No VmRSS in process status")?;
+    let kb: u64 = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid {:?}", line))?;
+    Ok(kb * 1024)
+}
+
+/// Resident memory of this process in bytes
+pub fn resident_bytes() -> Result<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").context("Failed to read /proc/self/status")?;
+    parse_vm_rss(&status)
+}
+
+/// What a reading calls for
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Steady,
+    Growing { growth: u64 },
+    Restart,
+}
+
+/// Readings over the growth window, and how long memory has been above the ceiling
+#[derive(Debug)]
+struct MemoryTracker {
+    window: Duration,
+    growth_warn: u64,
+    ceiling: Option<u64>,
+    samples: VecDeque<(Instant, u64)>,
+    growing: bool,
+    over_ceiling: u32,
+}
+
+impl MemoryTracker {
+    fn new(config: &MemoryConfig) -> Self {
+        Self {
+            window: Duration::from_secs(config.growth_window_h * 3600),
+            growth_warn: config.growth_warn_mb * MB,
+            ceiling: (config.ceiling_mb > 0).then_some(config.ceiling_mb * MB),
+            samples: VecDeque::new(),
+            growing: false,
+            over_ceiling: 0,
+        }
+    }
+
+    /// Rise from the lowest reading in the oldest quarter of the window to the lowest in the newest
+    fn growth(&self, now: Instant) -> Option<u64> {
+        let (start, _) = *self.samples.front()?;
+        if now.saturating_duration_since(start) < self.window {
+            return None;
+        }
+        let quarter = self.window / 4;
+        let floor = |from: Instant, to: Instant| {
+            self.samples.iter().filter(|(at, _)| (from..=to).contains(at)).map(|(_, rss)| *rss).min()
+        };
+        Some(floor(now - quarter, now)?.saturating_sub(floor(start, start + quarter)?))
+    }
+
+    fn update(&mut self, rss: u64, now: Instant) -> Verdict {
+        self.samples.push_back((now, rss));
+        while self.samples.front().is_some_and(|(at, _)| now.saturating_duration_since(*at) > self.window) {
+            self.samples.pop_front();
+        }
+
+        match self.ceiling {
+            Some(ceiling) if rss > ceiling => self.over_ceiling += 1,
+            _ => self.over_ceiling = 0,
+        }
+        // A passing spike does not restart the agent
+        if self.over_ceiling >= 2 {
+            return Verdict::Restart;
+        }
+
+        let growth = self.growth(now).unwrap_or_default();
+        if !self.growing && growth >= self.growth_warn {
+            self.growing = true;
+            return Verdict::Growing { growth };
+        }
+        if self.growing && growth < self.growth_warn / 2 {
+            self.growing = false;
+        }
+        Verdict::Steady
+    }
+}
+
+/// Periodically reads the agent's memory, restarting it above the ceiling
+pub struct MemoryMonitor {
+    config: MemoryConfig,
+    gpio: Arc<dyn GpioController>,
+    event_bus: EventBus,
+    state: AppState,
+}
+
+impl MemoryMonitor {
+    pub fn new(config: MemoryConfig, gpio: Arc<dyn GpioController>, event_bus: EventBus, state: AppState) -> Self {
+        Self { config, gpio, event_bus, state }
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            info!(ceiling_mb = self.config.ceiling_mb, "Memory monitor started");
+            let mut tracker = MemoryTracker::new(&self.config);
+            let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_s));
+            loop {
+                interval.tick().await;
+                let rss = match resident_bytes() {
+                    Ok(rss) => rss,
+                    Err(e) => {
+                        warn!(error = %e, "Resident memory unavailable, memory monitor stopped");
+                        return;
+                    }
+                };
+                let now = Instant::now();
+                let verdict = tracker.update(rss, now);
+                self.record(rss, tracker.growth(now), tracker.growing);
+
+                match verdict {
+                    Verdict::Steady => {}
+                    Verdict::Growing { growth } => {
+                        warn!(rss_mb = rss / MB, growth_mb = growth / MB, "Resident memory keeps growing, possible leak");
+                        self.emit(Event::MemoryGrowing { rss_mb: rss / MB, growth_mb: growth / MB });
+                    }
+                    Verdict::Restart => {
+                        self.restart(rss).await;
+                        return;
+                    }
+                }
+            }
+        })
+    }
+
+    fn record(&self, rss: u64, growth: Option<u64>, growing: bool) {
+        let mut state = self.state.write();
+        let peak_bytes = state.memory.peak_bytes.max(rss);
+        state.memory = MemoryStatus {
+            rss_bytes: Some(rss),
+            peak_bytes,
+            growth_bytes: growth,
+            growing,
+            checked_at: Some(Utc::now()),
+        };
+        if growing {
+            let growth_mb = growth.unwrap_or_default() / MB;
+            let message = format!("Lowest resident memory rose {} MB in {} h", growth_mb, self.config.growth_window_h);
+            state.health.report_error(Component::Memory, HealthStatus::Degraded, message);
+        } else if !state.health.is_ok(Component::Memory) {
+            state.health.report_ok(Component::Memory);
+        }
+    }
+
+    /// Report the ceiling, leave the outputs safe and have systemd start a fresh agent
+    async fn restart(&self, rss: u64) {
+        let ceiling_mb = self.config.ceiling_mb;
+        error!(rss_mb = rss / MB, ceiling_mb, "Resident memory above the ceiling, restarting the agent");
+        self.state.write().health.report_error(
+            Component::Memory,
+            HealthStatus::Failed,
+            format!("{} MB resident, above the {} MB ceiling", rss / MB, ceiling_mb),
+        );
+        self.emit(Event::MemoryCeilingExceeded { rss_mb: rss / MB, ceiling_mb });
+        tokio::time::sleep(RESTART_GRACE).await;
+
+        self.gpio.emergency_shutdown();
+        if let Err(e) = crate::update::restart() {
+            error!(error = %e, "Failed to restart the agent");
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        if let Err(e) = self.event_bus.emit(event) {
+            warn!(error = %e, "Failed to emit memory event");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vm_rss() {
+        let status = "Name:\tpi-door-client\nVmPeak:\t  81234 kB\nVmRSS:\t   20480 kB\nThreads:\t9\n";
+        assert_eq!(parse_vm_rss(status).unwrap(), 20 * MB);
+        assert!(parse_vm_rss("Name:\tkthreadd\n").is_err());
+        assert!(resident_bytes().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_rising_floor_counts_as_growth() {
+        let config = MemoryConfig { growth_window_h: 4, growth_warn_mb: 10, ..Default::default() };
+        let mut tracker = MemoryTracker::new(&config);
+        let start = Instant::now();
+        let hour = Duration::from_secs(3600);
+
+        // Spikes alone are not growth; the floor rising is
+        for (h, rss_mb) in [(0, 50), (1, 90), (2, 52), (3, 95)] {
+            assert_eq!(tracker.update(rss_mb * MB, start + hour * h), Verdict::Steady);
+        }
+        assert_eq!(tracker.update(55 * MB, start + hour * 4), Verdict::Steady);
+        assert_eq!(tracker.update(63 * MB, start + hour * 5), Verdict::Steady);
+        assert_eq!(tracker.update(70 * MB, start + hour * 6), Verdict::Growing { growth: 11 * MB });
+        assert!(tracker.growing);
+        assert_eq!(tracker.update(71 * MB, start + hour * 7), Verdict::Steady);
+    }
+
+    #[test]
+    fn test_ceiling_needs_two_readings() {
+        let config = MemoryConfig { ceiling_mb: 100, ..Default::default() };
+        let mut tracker = MemoryTracker::new(&config);
+        let start = Instant::now();
+
+        assert_eq!(tracker.update(120 * MB, start), Verdict::Steady);
+        assert_eq!(tracker.update(80 * MB, start + Duration::from_secs(60)), Verdict::Steady);
+        assert_eq!(tracker.update(120 * MB, start + Duration::from_secs(120)), Verdict::Steady);
+        assert_eq!(tracker.update(130 * MB, start + Duration::from_secs(180)), Verdict::Restart);
+
+        // No ceiling configured: never restarts
+        let mut tracker = MemoryTracker::new(&MemoryConfig::default());
+        for s in 0..5 {
+            assert_eq!(tracker.update(u64::MAX / 2, start + Duration::from_secs(s)), Verdict::Steady);
+        }
+    }
+}
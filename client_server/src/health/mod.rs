@@ -1,21 +1,96 @@
 //! Health monitoring and systemd watchdog integration
+//!
+//! The watchdog is only kept alive while the event loop, GPIO reads and the
+//! timer manager all make progress, so a hung subsystem gets the agent
+//! restarted by systemd instead of leaving it up but deaf.
 
+mod liveness;
+mod memory;
+mod registry;
+mod report;
+mod storage;
+mod supervisor;
+mod thermal;
 mod watchdog;
 
+pub use liveness::{Liveness, Subsystem, BEAT_INTERVAL};
+pub use memory::{resident_bytes, MemoryMonitor, MemoryStatus};
+pub use registry::{Component, ComponentHealth, HealthRegistry, HealthStatus};
+pub use report::{HealthReport, HealthReporter, ReportTrigger};
+pub use storage::{free_space, StorageLevel, StorageMonitor, StorageStatus};
+pub use supervisor::{Supervisor, TaskStatus};
+pub use thermal::{parse_throttled, SocStatus, ThermalMonitor, ThrottleFlags};
 pub use watchdog::WatchdogManager;
 
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::gpio::GpioController;
+use crate::state::AppState;
+
 pub struct HealthMonitor {
     watchdog: WatchdogManager,
+    liveness: Liveness,
+    gpio: Arc<dyn GpioController>,
+    state: AppState,
 }
 
 impl HealthMonitor {
-    pub fn new() -> Self {
+    /// Monitor that probes `gpio` and reports the result in `state`
+    pub fn new(liveness: Liveness, gpio: Arc<dyn GpioController>, state: AppState) -> Self {
         Self {
             watchdog: WatchdogManager::new(),
+            liveness,
+            gpio,
+            state,
         }
     }
 
     pub fn watchdog(&self) -> &WatchdogManager {
         &self.watchdog
     }
+
+    /// Probe the GPIO inputs and keep the watchdog alive while nothing is stalled
+    ///
+    /// A subsystem counts as stalled once it has been silent for a whole
+    /// keep-alive interval; the keep-alive is then withheld until it recovers.
+    pub fn spawn(self) -> JoinHandle<()> {
+        let (gpio, liveness, state) = (self.gpio.clone(), self.liveness.clone(), self.state.clone());
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(BEAT_INTERVAL);
+            loop {
+                ticker.tick().await;
+                match gpio.read_door_sensor().await {
+                    Ok(_) => {
+                        liveness.beat(Subsystem::Gpio);
+                        if !state.read().health.is_ok(Component::Gpio) {
+                            state.write().health.report_ok(Component::Gpio);
+                        }
+                    }
+                    Err(e) => state.write().health.report_error(Component::Gpio, HealthStatus::Failed, format!("{:#}", e)),
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            let max_silence = self.watchdog.interval();
+            let mut ticker = tokio::time::interval(max_silence);
+            let mut was_stalled = false;
+            loop {
+                ticker.tick().await;
+                let stalled = self.liveness.stalled(max_silence);
+                if stalled.is_empty() {
+                    if was_stalled {
+                        info!("All subsystems making progress again");
+                    }
+                    self.watchdog.keep_alive();
+                } else {
+                    let stalled: Vec<String> = stalled.iter().map(ToString::to_string).collect();
+                    warn!(?stalled, "Subsystems stalled, withholding the watchdog keep-alive");
+                }
+                was_stalled = !stalled.is_empty();
+            }
+        })
+    }
 }
@@ -0,0 +1,142 @@
+//! Per-subsystem health, reported by each subsystem and served at `/v1/health`
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A subsystem that reports its health
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Component {
+    Gpio,
+    Cloud,
+    Ble,
+    Rf433,
+    Network,
+    Queue,
+    Thermal,
+    Storage,
+    /// Resident memory of the agent
+    Memory,
+    /// The SoC's supply, as the firmware's undervoltage flags report it
+    Power,
+    /// Background tasks restarted by the supervisor
+    Tasks,
+}
+
+/// How well a subsystem works, from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    /// Working with reduced function, e.g. offline from the master
+    Degraded,
+    /// Not working; the agent is not ready
+    Failed,
+}
+
+/// What a subsystem last reported
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    /// When the status last changed
+    pub since: DateTime<Utc>,
+    /// The last error, kept after the subsystem recovers
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+/// Health of each subsystem that has reported; ones not in use never do
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct HealthRegistry {
+    components: BTreeMap<Component, ComponentHealth>,
+}
+
+impl HealthRegistry {
+    /// Record that `component` works
+    pub fn report_ok(&mut self, component: Component) {
+        self.set_status(component, HealthStatus::Ok);
+    }
+
+    /// Record an error and the status it leaves `component` in
+    pub fn report_error(&mut self, component: Component, status: HealthStatus, error: impl fmt::Display) {
+        let health = self.set_status(component, status);
+        health.last_error = Some(error.to_string());
+        health.last_error_at = Some(Utc::now());
+    }
+
+    /// Stop reporting `component`, e.g. once it is switched off
+    pub fn remove(&mut self, component: Component) {
+        self.components.remove(&component);
+    }
+
+    pub fn get(&self, component: Component) -> Option<&ComponentHealth> {
+        self.components.get(&component)
+    }
+
+    /// Whether `component` last reported being ok, so repeated reports can skip the write lock
+    pub fn is_ok(&self, component: Component) -> bool {
+        self.get(component).is_some_and(|health| health.status == HealthStatus::Ok)
+    }
+
+    /// Status of every subsystem that has reported
+    pub fn statuses(&self) -> BTreeMap<Component, HealthStatus> {
+        self.components.iter().map(|(component, health)| (*component, health.status)).collect()
+    }
+
+    /// The worst status reported
+    pub fn status(&self) -> HealthStatus {
+        self.components.values().map(|health| health.status).max().unwrap_or(HealthStatus::Ok)
+    }
+
+    /// Ready to protect the premises: no subsystem has failed
+    pub fn ready(&self) -> bool {
+        self.status() != HealthStatus::Failed
+    }
+
+    fn set_status(&mut self, component: Component, status: HealthStatus) -> &mut ComponentHealth {
+        let now = Utc::now();
+        let health = self.components.entry(component).or_insert(ComponentHealth {
+            status,
+            since: now,
+            last_error: None,
+            last_error_at: None,
+        });
+        if health.status != status {
+            health.status = status;
+            health.since = now;
+        }
+        health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verdict_follows_worst_subsystem() {
+        let mut registry = HealthRegistry::default();
+        assert!(registry.ready() && registry.status() == HealthStatus::Ok);
+
+        registry.report_ok(Component::Gpio);
+        registry.report_error(Component::Cloud, HealthStatus::Degraded, "connection refused");
+        assert_eq!(registry.status(), HealthStatus::Degraded);
+        assert!(registry.ready());
+
+        registry.report_error(Component::Rf433, HealthStatus::Failed, "pin 27 busy");
+        assert!(!registry.ready());
+
+        // Recovering keeps the last error for diagnosis
+        registry.report_ok(Component::Rf433);
+        registry.remove(Component::Cloud);
+        assert!(registry.ready() && registry.status() == HealthStatus::Ok);
+        assert_eq!(registry.get(Component::Rf433).unwrap().last_error.as_deref(), Some("pin 27 busy"));
+
+        let json = serde_json::to_value(&registry).unwrap();
+        assert_eq!(json["rf433"]["status"], "ok");
+        assert_eq!(json["gpio"]["last_error"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,202 @@
+//! Health report sent to the master for fleet dashboards
+//!
+//! The report bundles the status of each subsystem with SoC and sensor
+//! temperatures, disk and memory use, task restarts and network quality. It
+//! is posted to the master's `/clients/:client_id/health_reports` every
+//! `health_report.interval_h`, and as soon as a subsystem changes status, at
+//! most once per `health_report.min_gap_s`. A report the master does not take
+//! is retried at the next check.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use super::{Component, HealthRegistry, HealthStatus, MemoryStatus, SocStatus, StorageStatus, TaskStatus};
+use crate::cloud::{LinkMetrics, RestFallback};
+use crate::config::HealthReportConfig;
+use crate::network::{InterfaceHealth, LinkQuality};
+use crate::state::{AppState, SharedState};
+
+/// Why a report was sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportTrigger {
+    /// Daily, and the first one after start
+    Scheduled,
+    /// A subsystem changed status
+    Change,
+}
+
+/// Snapshot of the agent's health
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub trigger: ReportTrigger,
+    pub generated_at: DateTime<Utc>,
+    pub version: String,
+    pub uptime_s: i64,
+    pub subsystems: HealthRegistry,
+    /// Whether the last wiring self-test passed, if one ran
+    pub wiring_ok: Option<bool>,
+    pub soc: SocStatus,
+    /// 1-Wire sensors in degrees Celsius
+    pub temperatures: BTreeMap<String, f64>,
+    pub storage: StorageStatus,
+    pub memory: MemoryStatus,
+    pub tasks: BTreeMap<String, TaskStatus>,
+    pub link_quality: Vec<LinkQuality>,
+    pub interface_health: Vec<InterfaceHealth>,
+    pub cloud_link: LinkMetrics,
+}
+
+impl HealthReport {
+    pub fn new(state: &SharedState, trigger: ReportTrigger) -> Self {
+        let wiring_ok = state.wiring.as_ref().map(|report| report.ok);
+        let status = match state.health.status() {
+            HealthStatus::Ok if wiring_ok == Some(false) => HealthStatus::Degraded,
+            status => status,
+        };
+        Self {
+            status,
+            trigger,
+            generated_at: Utc::now(),
+            version: crate::VERSION.to_string(),
+            uptime_s: state.uptime_s(),
+            subsystems: state.health.clone(),
+            wiring_ok,
+            soc: state.soc.clone(),
+            temperatures: state.temperatures.clone(),
+            storage: state.storage.clone(),
+            memory: state.memory.clone(),
+            tasks: state.tasks.clone(),
+            link_quality: state.link_quality.clone(),
+            interface_health: state.interface_health.clone(),
+            cloud_link: state.cloud_link.clone(),
+        }
+    }
+}
+
+/// What a change must touch to be reported before the next scheduled report
+#[derive(Debug, Clone, PartialEq)]
+struct Fingerprint {
+    statuses: BTreeMap<Component, HealthStatus>,
+    wiring_ok: Option<bool>,
+}
+
+impl Fingerprint {
+    /// The master sees its own connection come and go, so the cloud link is left out
+    fn of(state: &SharedState) -> Self {
+        let mut statuses = state.health.statuses();
+        statuses.remove(&Component::Cloud);
+        Self { statuses, wiring_ok: state.wiring.as_ref().map(|report| report.ok) }
+    }
+}
+
+/// Sends health reports to the master on a schedule and on change
+pub struct HealthReporter {
+    config: HealthReportConfig,
+    rest: RestFallback,
+    state: AppState,
+    /// When the last scheduled report was taken
+    scheduled_at: Option<Instant>,
+    /// When the last report of either kind was taken, and what it showed
+    sent: Option<(Instant, Fingerprint)>,
+}
+
+impl HealthReporter {
+    pub fn new(config: HealthReportConfig, rest: RestFallback, state: AppState) -> Self {
+        Self { config, rest, state, scheduled_at: None, sent: None }
+    }
+
+    pub fn spawn(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(self.config.check_s));
+            loop {
+                ticker.tick().await;
+                self.check().await;
+            }
+        })
+    }
+
+    async fn check(&mut self) {
+        let now = Instant::now();
+        let (report, fingerprint) = {
+            let state = self.state.read();
+            let fingerprint = Fingerprint::of(&state);
+            let Some(trigger) = self.due(&fingerprint, now) else {
+                return;
+            };
+            (HealthReport::new(&state, trigger), fingerprint)
+        };
+
+        match self.rest.post_health_report(&report).await {
+            Ok(()) => {
+                info!(status = ?report.status, trigger = ?report.trigger, "Sent health report to master");
+                if report.trigger == ReportTrigger::Scheduled {
+                    self.scheduled_at = Some(now);
+                }
+                self.sent = Some((now, fingerprint));
+            }
+            Err(e) => warn!(error = %e, "Failed to send health report to master"),
+        }
+    }
+
+    /// Which report is due, if any
+    fn due(&self, fingerprint: &Fingerprint, now: Instant) -> Option<ReportTrigger> {
+        let interval = Duration::from_secs(self.config.interval_h * 3600);
+        if self.scheduled_at.is_none_or(|at| now.duration_since(at) >= interval) {
+            return Some(ReportTrigger::Scheduled);
+        }
+        let (sent_at, sent) = self.sent.as_ref()?;
+        if sent == fingerprint {
+            return None;
+        }
+        // A flapping subsystem is reported at most once per gap
+        if now.duration_since(*sent_at) < Duration::from_secs(self.config.min_gap_s) {
+            debug!("Health changed, waiting for the report gap to pass");
+            return None;
+        }
+        Some(ReportTrigger::Change)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloud::MasterToken;
+    use crate::state::new_app_state;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reports_daily_and_on_change() {
+        let state = new_app_state();
+        let rest = RestFallback::new("http://127.0.0.1:9", "c-42", MasterToken::default(), None).unwrap();
+        let config = HealthReportConfig { interval_h: 24, min_gap_s: 300, ..Default::default() };
+        let mut reporter = HealthReporter::new(config, rest, state.clone());
+        let start = Instant::now();
+        let fingerprint = || Fingerprint::of(&state.read());
+
+        assert_eq!(reporter.due(&fingerprint(), start), Some(ReportTrigger::Scheduled));
+        reporter.scheduled_at = Some(start);
+        reporter.sent = Some((start, fingerprint()));
+        assert_eq!(reporter.due(&fingerprint(), start), None);
+
+        // The cloud link coming and going is not a change worth a report
+        state.write().health.report_error(Component::Cloud, HealthStatus::Degraded, "connection refused");
+        assert_eq!(reporter.due(&fingerprint(), start + Duration::from_secs(600)), None);
+
+        state.write().health.report_error(Component::Storage, HealthStatus::Degraded, "Only 40 MB free");
+        assert_eq!(reporter.due(&fingerprint(), start + Duration::from_secs(60)), None);
+        assert_eq!(reporter.due(&fingerprint(), start + Duration::from_secs(300)), Some(ReportTrigger::Change));
+
+        let report = HealthReport::new(&state.read(), ReportTrigger::Change);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!((json["status"].as_str(), json["trigger"].as_str()), (Some("degraded"), Some("change")));
+        assert_eq!(json["subsystems"]["storage"]["last_error"], "Only 40 MB free");
+
+        assert_eq!(reporter.due(&fingerprint(), start + Duration::from_secs(24 * 3600)), Some(ReportTrigger::Scheduled));
+    }
+}
@@ -0,0 +1,228 @@
+//! Free space on the data partition and size of the offline queue
+//!
+//! Checked every `storage.interval_s`. Below `storage.warn_free_mb`, or with
+//! the queue past `storage.queue_warn_mb`, `disk_space_low` is raised. Below
+//! `storage.critical_free_mb` the queue is cut down to its newest
+//! `storage.critical_keep_events` on every check, so a long outage cannot fill
+//! the card and leave the agent unable to write its config, journal or logs.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use super::{Component, HealthStatus};
+use crate::config::StorageConfig;
+use crate::events::{Event, EventBus, EventQueue};
+use crate::state::AppState;
+
+const MB: u64 = 1024 * 1024;
+
+/// How close the data partition is to running out, from best to worst
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageLevel {
+    #[default]
+    Ok,
+    Low,
+    Critical,
+}
+
+/// Latest storage readings
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StorageStatus {
+    pub level: StorageLevel,
+    pub free_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+    /// Offline queue database on disk, if the queue is open
+    pub queue_bytes: Option<u64>,
+    /// Queued events dropped for lack of space since the agent started
+    pub pruned: u64,
+    pub checked_at: Option<DateTime<Utc>>,
+}
+
+/// Free and total bytes of the filesystem holding `path`
+pub fn free_space(path: &Path) -> Result<(u64, u64)> {
+    let stat = nix::sys::statvfs::statvfs(path).with_context(|| format!("Failed to stat {}", path.display()))?;
+    let block = stat.fragment_size() as u64;
+    Ok((stat.blocks_available() as u64 * block, stat.blocks() as u64 * block))
+}
+
+/// Periodically checks free space and prunes the offline queue when it runs out
+pub struct StorageMonitor {
+    config: StorageConfig,
+    path: PathBuf,
+    queue: Option<EventQueue>,
+    event_bus: EventBus,
+    state: AppState,
+}
+
+impl StorageMonitor {
+    /// Monitor the filesystem holding `path`
+    pub fn new(config: StorageConfig, path: PathBuf, event_bus: EventBus, state: AppState) -> Self {
+        Self { config, path, queue: None, event_bus, state }
+    }
+
+    /// Watch the size of the offline queue, and prune it when space is critically low
+    pub fn set_queue(&mut self, queue: EventQueue) {
+        self.queue = Some(queue);
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            info!(path = %self.path.display(), "Storage monitor started");
+            let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_s));
+            loop {
+                interval.tick().await;
+                self.check();
+            }
+        })
+    }
+
+    /// Level for the readings, with a 10% margin before leaving the previous one so it does not flap
+    fn classify(&self, free_mb: u64, queue_mb: u64, previous: StorageLevel) -> StorageLevel {
+        let below = |limit: u64, level| free_mb < if previous >= level { limit + limit / 10 } else { limit };
+        let queue_limit = self.config.queue_warn_mb;
+        let queue_large = queue_mb > if previous >= StorageLevel::Low { queue_limit - queue_limit / 10 } else { queue_limit };
+        if below(self.config.critical_free_mb, StorageLevel::Critical) {
+            StorageLevel::Critical
+        } else if below(self.config.warn_free_mb, StorageLevel::Low) || queue_large {
+            StorageLevel::Low
+        } else {
+            StorageLevel::Ok
+        }
+    }
+
+    fn check(&self) {
+        let (free_bytes, total_bytes) = match free_space(&self.path) {
+            Ok(space) => space,
+            Err(e) => {
+                warn!(error = %e, "Free space unavailable");
+                self.state.write().health.report_error(Component::Storage, HealthStatus::Degraded, format!("{:#}", e));
+                return;
+            }
+        };
+        let queue_bytes = self.queue.as_ref().and_then(|queue| match queue.size_on_disk() {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                warn!(error = %e, "Offline queue size unavailable");
+                None
+            }
+        });
+        let (free_mb, queue_mb) = (free_bytes / MB, queue_bytes.unwrap_or_default() / MB);
+        let previous = self.state.read().storage.level;
+        let level = self.classify(free_mb, queue_mb, previous);
+
+        let mut pruned = 0;
+        if level == StorageLevel::Critical {
+            if let Some(queue) = &self.queue {
+                match queue.prune_oldest(self.config.critical_keep_events) {
+                    Ok(removed) => pruned = removed,
+                    Err(e) => warn!(error = %e, "Failed to prune the offline queue"),
+                }
+            }
+            if pruned > 0 {
+                warn!(pruned, free_mb, "Disk space critically low, dropped the oldest queued events");
+            }
+        }
+
+        {
+            let mut state = self.state.write();
+            state.storage = StorageStatus {
+                level,
+                free_bytes: Some(free_bytes),
+                total_bytes: Some(total_bytes),
+                queue_bytes,
+                pruned: state.storage.pruned + pruned as u64,
+                checked_at: Some(Utc::now()),
+            };
+            match level {
+                StorageLevel::Ok => state.health.report_ok(Component::Storage),
+                StorageLevel::Low => state.health.report_error(
+                    Component::Storage,
+                    HealthStatus::Degraded,
+                    format!("{} MB free, offline queue {} MB", free_mb, queue_mb),
+                ),
+                StorageLevel::Critical => state.health.report_error(
+                    Component::Storage,
+                    HealthStatus::Degraded,
+                    format!("Only {} MB free, offline queue pruned", free_mb),
+                ),
+            }
+        }
+
+        let event = match (previous, level) {
+            (previous, StorageLevel::Critical) if previous != StorageLevel::Critical => {
+                Some(Event::DiskSpaceCritical { free_mb, pruned })
+            }
+            (StorageLevel::Ok, StorageLevel::Low) => Some(Event::DiskSpaceLow { free_mb, queue_mb }),
+            (StorageLevel::Low | StorageLevel::Critical, StorageLevel::Ok) => Some(Event::DiskSpaceRestored { free_mb }),
+            _ => None,
+        };
+        if let Some(event) = event {
+            warn!(?event, "Storage level changed");
+            if let Err(e) = self.event_bus.emit(event) {
+                warn!(error = %e, "Failed to emit storage event");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventEnvelope;
+    use crate::state::new_app_state;
+
+    fn monitor(config: StorageConfig, path: &Path) -> (StorageMonitor, crate::events::EventReceiver) {
+        let (event_bus, rx) = EventBus::new();
+        (StorageMonitor::new(config, path.to_path_buf(), event_bus, new_app_state()), rx)
+    }
+
+    #[test]
+    fn test_levels_do_not_flap() {
+        let config = StorageConfig { warn_free_mb: 200, critical_free_mb: 50, queue_warn_mb: 100, ..Default::default() };
+        let (monitor, _rx) = monitor(config, Path::new("/"));
+
+        assert_eq!(monitor.classify(300, 0, StorageLevel::Ok), StorageLevel::Ok);
+        assert_eq!(monitor.classify(199, 0, StorageLevel::Ok), StorageLevel::Low);
+        assert_eq!(monitor.classify(300, 101, StorageLevel::Ok), StorageLevel::Low);
+        assert_eq!(monitor.classify(49, 0, StorageLevel::Low), StorageLevel::Critical);
+
+        // Leaving a level takes a 10% margin
+        assert_eq!(monitor.classify(210, 0, StorageLevel::Low), StorageLevel::Low);
+        assert_eq!(monitor.classify(220, 0, StorageLevel::Low), StorageLevel::Ok);
+        assert_eq!(monitor.classify(54, 0, StorageLevel::Critical), StorageLevel::Critical);
+        assert_eq!(monitor.classify(55, 0, StorageLevel::Critical), StorageLevel::Low);
+        assert_eq!(monitor.classify(300, 95, StorageLevel::Low), StorageLevel::Low);
+        assert_eq!(monitor.classify(300, 90, StorageLevel::Low), StorageLevel::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_critical_space_prunes_queue() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = EventQueue::new(dir.path().join("queue"), 100, 7).unwrap();
+        for _ in 0..5 {
+            queue.enqueue(EventEnvelope::new(Event::DoorOpen, "test".to_string())).unwrap();
+        }
+        // Any real filesystem has less free than this
+        let config = StorageConfig { critical_free_mb: u64::MAX / MB, critical_keep_events: 2, ..Default::default() };
+        let (mut monitor, mut rx) = monitor(config, dir.path());
+        monitor.set_queue(queue.clone());
+
+        monitor.check();
+        assert!(matches!(rx.recv().await.unwrap(), Event::DiskSpaceCritical { pruned: 3, .. }));
+        assert_eq!(queue.len().unwrap(), 2);
+        let storage = monitor.state.read().storage.clone();
+        assert_eq!((storage.level, storage.pruned), (StorageLevel::Critical, 3));
+        assert!(storage.queue_bytes.is_some() && storage.total_bytes.is_some());
+
+        monitor.config = StorageConfig { warn_free_mb: 0, critical_free_mb: 0, queue_warn_mb: u64::MAX, ..Default::default() };
+        monitor.check();
+        assert!(matches!(rx.recv().await.unwrap(), Event::DiskSpaceRestored { .. }));
+        assert!(monitor.state.read().health.is_ok(Component::Storage));
+    }
+}
@@ -0,0 +1,181 @@
+//! Restarts background tasks that panic or return
+//!
+//! Each supervised task runs in its own tokio task. When it ends it is started
+//! again after a backoff that doubles up to a maximum, and resets once a run
+//! has lasted that long. Restarts are counted in `tasks` in the shared state,
+//! which `/v1/health` and `/metrics` report, and the `tasks` subsystem reads
+//! `degraded` until every restarted task has stayed up again.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::future::Future;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Instant};
+use tracing::{error, info};
+
+use super::{Component, HealthStatus};
+use crate::state::AppState;
+
+/// Restarts of a supervised task
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TaskStatus {
+    pub restarts: u64,
+    /// Restarted and not yet up for the maximum backoff
+    pub recovering: bool,
+    /// How the last run ended
+    pub last_exit: Option<String>,
+    pub last_exit_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone)]
+pub struct Supervisor {
+    state: AppState,
+    min_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Supervisor {
+    pub fn new(state: AppState) -> Self {
+        Self {
+            state,
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+
+    /// Wait between restarts; a run lasting `max` counts as recovered
+    pub fn set_backoff(&mut self, min: Duration, max: Duration) {
+        self.min_backoff = min;
+        self.max_backoff = max.max(min);
+    }
+
+    /// Run the task `start` creates, and start it again whenever it ends
+    pub fn supervise<F, Fut>(&self, task: &'static str, mut start: F) -> JoinHandle<()>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        self.state.write().tasks.entry(task.to_string()).or_default();
+        tokio::spawn(async move {
+            let mut backoff = supervisor.min_backoff;
+            loop {
+                let started = Instant::now();
+                let mut run = tokio::spawn(start());
+                let result = tokio::select! {
+                    result = &mut run => result,
+                    _ = sleep(supervisor.max_backoff) => {
+                        supervisor.recovered(task);
+                        run.await
+                    }
+                };
+                let exit = match result {
+                    Ok(()) => "returned".to_string(),
+                    Err(e) if e.is_panic() => format!("panicked: {}", panic_message(e.into_panic())),
+                    // Only cancelled when the runtime shuts down
+                    Err(_) => return,
+                };
+                if started.elapsed() >= supervisor.max_backoff {
+                    backoff = supervisor.min_backoff;
+                }
+                error!(task, exit = %exit, restart_in_ms = backoff.as_millis() as u64, "Background task stopped, restarting");
+                supervisor.stopped(task, exit);
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(supervisor.max_backoff);
+            }
+        })
+    }
+
+    fn stopped(&self, task: &str, exit: String) {
+        let mut state = self.state.write();
+        let status = state.tasks.entry(task.to_string()).or_default();
+        status.restarts += 1;
+        status.recovering = true;
+        status.last_exit = Some(exit.clone());
+        status.last_exit_at = Some(Utc::now());
+        state.health.report_error(Component::Tasks, HealthStatus::Degraded, format!("{} {}", task, exit));
+    }
+
+    fn recovered(&self, task: &str) {
+        let mut state = self.state.write();
+        let Some(status) = state.tasks.get_mut(task).filter(|status| status.recovering) else {
+            return;
+        };
+        status.recovering = false;
+        info!(task, "Restarted task is running steadily");
+        if !state.tasks.values().any(|status| status.recovering) {
+            state.health.report_ok(Component::Tasks);
+        }
+    }
+}
+
+/// The message a panic was raised with
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().map_or("unknown cause", |message| message).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::new_app_state;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    /// Wait for `done`; a panic may take a while to report while its backtrace is captured
+    async fn settle(done: impl Fn() -> bool) {
+        for _ in 0..500 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Timed out waiting for the supervisor");
+    }
+
+    #[tokio::test]
+    async fn test_crashed_task_restarted() {
+        let state = new_app_state();
+        let mut supervisor = Supervisor::new(state.clone());
+        supervisor.set_backoff(Duration::from_millis(10), Duration::from_millis(500));
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let counter = runs.clone();
+        let handle = supervisor.supervise("flaky", move || {
+            let run = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match run {
+                    0 => panic!("sensor gone"),
+                    1 => {}
+                    _ => std::future::pending().await,
+                }
+            }
+        });
+
+        settle(|| runs.load(Ordering::SeqCst) == 3).await;
+        let status = state.read().tasks["flaky"].clone();
+        assert_eq!((status.restarts, status.recovering), (2, true));
+        assert_eq!(status.last_exit.as_deref(), Some("returned"));
+        assert_eq!(state.read().health.get(Component::Tasks).unwrap().status, HealthStatus::Degraded);
+        assert_eq!(
+            state.read().health.get(Component::Tasks).unwrap().last_error.as_deref(),
+            Some("flaky returned")
+        );
+
+        // Up for the maximum backoff: recovered
+        settle(|| !state.read().tasks["flaky"].recovering).await;
+        assert!(state.read().health.is_ok(Component::Tasks));
+        handle.abort();
+    }
+
+    #[test]
+    fn test_panic_message() {
+        assert_eq!(panic_message(Box::new("static")), "static");
+        assert_eq!(panic_message(Box::new(format!("pin {}", 17))), "pin 17");
+        assert_eq!(panic_message(Box::new(17)), "unknown cause");
+    }
+}
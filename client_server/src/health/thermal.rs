@@ -0,0 +1,341 @@
+//! SoC temperature and firmware throttling
+//!
+//! The SoC's thermal zone and the firmware's throttling flags are read every
+//! `thermal.interval_s` into `soc` in the shared state, where `/v1/health`,
+//! `/metrics` and heartbeats pick them up. A SoC that stays at `thermal.warn_c`
+//! for `thermal.sustained_s` raises `soc_overheating`; `soc_cooled` follows once
+//! it is `thermal.hysteresis_c` below. The firmware's undervoltage flags raise
+//! `power_undervoltage` and keep the `power` subsystem degraded until reboot,
+//! since brownouts from a weak supply explain many otherwise odd failures.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use super::{Component, HealthStatus};
+use crate::config::ThermalConfig;
+use crate::events::{Event, EventBus};
+use crate::state::AppState;
+
+/// Flags from the firmware's `get_throttled`, as of now and since boot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThrottleFlags {
+    pub bits: u32,
+    pub under_voltage: bool,
+    pub frequency_capped: bool,
+    pub throttled: bool,
+    pub soft_temp_limit: bool,
+    pub under_voltage_occurred: bool,
+    pub frequency_capped_occurred: bool,
+    pub throttled_occurred: bool,
+    pub soft_temp_limit_occurred: bool,
+}
+
+impl ThrottleFlags {
+    pub fn from_bits(bits: u32) -> Self {
+        let bit = |n: u32| bits & (1 << n) != 0;
+        Self {
+            bits,
+            under_voltage: bit(0),
+            frequency_capped: bit(1),
+            throttled: bit(2),
+            soft_temp_limit: bit(3),
+            under_voltage_occurred: bit(16),
+            frequency_capped_occurred: bit(17),
+            throttled_occurred: bit(18),
+            soft_temp_limit_occurred: bit(19),
+        }
+    }
+
+    /// Whether the SoC is slowed down right now
+    pub fn limited(&self) -> bool {
+        self.frequency_capped || self.throttled || self.soft_temp_limit
+    }
+}
+
+/// Latest SoC readings
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SocStatus {
+    pub celsius: Option<f64>,
+    /// `None` where the firmware does not report throttling
+    pub throttled: Option<ThrottleFlags>,
+    /// `soc_overheating` was raised and the SoC has not cooled since
+    pub overheating: bool,
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+/// Parse `get_throttled` from sysfs (`50005`) or vcgencmd (`throttled=0x50005`)
+pub fn parse_throttled(text: &str) -> Result<ThrottleFlags> {
+    let hex = text.trim().trim_start_matches("throttled=").trim_start_matches("0x");
+    let bits = u32::from_str_radix(hex, 16).with_context(|| format!("Invalid throttling flags {:?}", text.trim()))?;
+    Ok(ThrottleFlags::from_bits(bits))
+}
+
+/// Read a thermal zone, which reports millidegrees Celsius
+pub async fn read_celsius(path: &Path) -> Result<f64> {
+    let text = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let millidegrees: i64 = text.trim().parse().with_context(|| format!("Invalid temperature in {}", path.display()))?;
+    Ok(millidegrees as f64 / 1000.0)
+}
+
+/// Read the throttling flags from sysfs, or from `vcgencmd` where the kernel does not expose them
+pub async fn read_throttled(path: &Path) -> Result<ThrottleFlags> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(text) => parse_throttled(&text),
+        Err(_) => {
+            let output = tokio::process::Command::new("vcgencmd")
+                .arg("get_throttled")
+                .output()
+                .await
+                .context("Neither get_throttled nor vcgencmd available")?;
+            if !output.status.success() {
+                anyhow::bail!("vcgencmd get_throttled failed with {}", output.status);
+            }
+            parse_throttled(&String::from_utf8_lossy(&output.stdout))
+        }
+    }
+}
+
+/// Sustained over-temperature, with hysteresis so readings near the limit do not flap
+#[derive(Debug)]
+struct OverheatTracker {
+    warn_c: f64,
+    sustained: Duration,
+    hysteresis_c: f64,
+    hot_since: Option<Instant>,
+    overheating: bool,
+}
+
+impl OverheatTracker {
+    fn new(config: &ThermalConfig) -> Self {
+        Self {
+            warn_c: config.warn_c,
+            sustained: Duration::from_secs(config.sustained_s),
+            hysteresis_c: config.hysteresis_c,
+            hot_since: None,
+            overheating: false,
+        }
+    }
+
+    /// Feed a reading and return the event it causes, if any
+    fn update(&mut self, celsius: f64, now: Instant) -> Option<Event> {
+        if celsius < self.warn_c {
+            self.hot_since = None;
+            if self.overheating && celsius <= self.warn_c - self.hysteresis_c {
+                self.overheating = false;
+                return Some(Event::SocCooled { celsius });
+            }
+            return None;
+        }
+        let since = *self.hot_since.get_or_insert(now);
+        if !self.overheating && now.saturating_duration_since(since) >= self.sustained {
+            self.overheating = true;
+            return Some(Event::SocOverheating { celsius });
+        }
+        None
+    }
+}
+
+/// Undervoltage as the firmware's flags report it, remembered between readings
+#[derive(Debug, Default)]
+struct UndervoltageTracker {
+    active: bool,
+    occurred: bool,
+}
+
+impl UndervoltageTracker {
+    /// Feed the flags and return the event they cause, if any
+    ///
+    /// The since-boot flag is sticky, so only the first dip that comes and goes
+    /// between two readings is noticed; later ones are seen while they last.
+    fn update(&mut self, flags: &ThrottleFlags) -> Option<Event> {
+        let event = if flags.under_voltage && !self.active {
+            Some(Event::PowerUndervoltage { active: true })
+        } else if flags.under_voltage_occurred && !self.occurred && !flags.under_voltage {
+            Some(Event::PowerUndervoltage { active: false })
+        } else {
+            None
+        };
+        self.active = flags.under_voltage;
+        self.occurred = flags.under_voltage_occurred;
+        event
+    }
+}
+
+/// Periodically reads the SoC temperature and throttling flags
+pub struct ThermalMonitor {
+    config: ThermalConfig,
+    event_bus: EventBus,
+    state: AppState,
+}
+
+impl ThermalMonitor {
+    pub fn new(config: ThermalConfig, event_bus: EventBus, state: AppState) -> Self {
+        Self { config, event_bus, state }
+    }
+
+    /// Spawn the polling task; it stops if the SoC temperature cannot be read at all
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            if let Err(e) = read_celsius(&self.config.temp_path).await {
+                warn!(error = %e, "SoC temperature unavailable, thermal monitor not started");
+                return;
+            }
+            info!(warn_c = self.config.warn_c, "Thermal monitor started");
+            let mut tracker = OverheatTracker::new(&self.config);
+            let mut undervoltage = UndervoltageTracker::default();
+            let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_s));
+            loop {
+                interval.tick().await;
+                self.poll(&mut tracker, &mut undervoltage).await;
+            }
+        })
+    }
+
+    async fn poll(&self, tracker: &mut OverheatTracker, undervoltage: &mut UndervoltageTracker) {
+        let celsius = match read_celsius(&self.config.temp_path).await {
+            Ok(celsius) => Some(celsius),
+            Err(e) => {
+                warn!(error = %e, "SoC temperature reading failed");
+                None
+            }
+        };
+        let throttled = match read_throttled(&self.config.throttled_path).await {
+            Ok(flags) => Some(flags),
+            Err(e) => {
+                debug!(error = %e, "Throttling flags unavailable");
+                None
+            }
+        };
+        let events: Vec<Event> = celsius
+            .and_then(|celsius| tracker.update(celsius, Instant::now()))
+            .into_iter()
+            .chain(throttled.and_then(|flags| undervoltage.update(&flags)))
+            .collect();
+
+        {
+            let mut state = self.state.write();
+            state.soc = SocStatus {
+                celsius,
+                throttled,
+                overheating: tracker.overheating,
+                read_at: Some(Utc::now()),
+            };
+            match (tracker.overheating, throttled.filter(ThrottleFlags::limited)) {
+                (true, _) => state.health.report_error(
+                    Component::Thermal,
+                    HealthStatus::Degraded,
+                    format!("SoC overheating at {:.1} °C", celsius.unwrap_or(tracker.warn_c)),
+                ),
+                (false, Some(flags)) => state.health.report_error(
+                    Component::Thermal,
+                    HealthStatus::Degraded,
+                    format!("SoC throttled by the firmware (flags {:#x})", flags.bits),
+                ),
+                (false, None) => state.health.report_ok(Component::Thermal),
+            }
+            match throttled {
+                Some(flags) if flags.under_voltage => {
+                    state.health.report_error(Component::Power, HealthStatus::Degraded, "SoC supply under voltage")
+                }
+                Some(flags) if flags.under_voltage_occurred => state.health.report_error(
+                    Component::Power,
+                    HealthStatus::Degraded,
+                    "SoC supply fell under voltage since boot; check the power supply",
+                ),
+                Some(_) => state.health.report_ok(Component::Power),
+                None => {}
+            }
+        }
+
+        for event in events {
+            warn!(?event, "SoC limit crossed");
+            if let Err(e) = self.event_bus.emit(event) {
+                warn!(error = %e, "Failed to emit SoC event");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::new_app_state;
+
+    #[test]
+    fn test_parse_throttled() {
+        let flags = parse_throttled("throttled=0x50005\n").unwrap();
+        assert_eq!(flags.bits, 0x50005);
+        assert!(flags.under_voltage && flags.throttled && !flags.frequency_capped);
+        assert!(flags.under_voltage_occurred && flags.throttled_occurred && !flags.soft_temp_limit_occurred);
+        assert!(flags.limited());
+
+        assert_eq!(parse_throttled("0\n").unwrap(), ThrottleFlags::default());
+        assert!(!parse_throttled("80000").unwrap().limited());
+        assert!(parse_throttled("throttled=").is_err());
+    }
+
+    #[test]
+    fn test_overheating_must_be_sustained() {
+        let config = ThermalConfig { warn_c: 75.0, sustained_s: 60, hysteresis_c: 5.0, ..Default::default() };
+        let mut tracker = OverheatTracker::new(&config);
+        let start = Instant::now();
+        let at = |s| start + Duration::from_secs(s);
+
+        // A short spike does not count
+        assert!(tracker.update(78.0, at(0)).is_none());
+        assert!(tracker.update(70.0, at(30)).is_none());
+        assert!(tracker.update(78.0, at(40)).is_none());
+        assert!(tracker.update(80.0, at(90)).is_none());
+        assert!(matches!(tracker.update(79.0, at(100)), Some(Event::SocOverheating { celsius }) if celsius == 79.0));
+        assert!(tracker.update(81.0, at(200)).is_none());
+
+        // Cooled, but within the hysteresis
+        assert!(tracker.update(72.0, at(210)).is_none());
+        assert!(matches!(tracker.update(69.5, at(220)), Some(Event::SocCooled { .. })));
+    }
+
+    #[test]
+    fn test_undervoltage_reported_once_per_dip() {
+        let mut tracker = UndervoltageTracker::default();
+        let update = |tracker: &mut UndervoltageTracker, bits| tracker.update(&ThrottleFlags::from_bits(bits));
+
+        // Dipped before the first reading
+        assert!(matches!(update(&mut tracker, 0x10000), Some(Event::PowerUndervoltage { active: false })));
+        assert!(update(&mut tracker, 0x10000).is_none());
+        assert!(matches!(update(&mut tracker, 0x10005), Some(Event::PowerUndervoltage { active: true })));
+        assert!(update(&mut tracker, 0x10001).is_none());
+        assert!(update(&mut tracker, 0x10000).is_none());
+        assert!(matches!(update(&mut tracker, 0x10001), Some(Event::PowerUndervoltage { active: true })));
+    }
+
+    #[tokio::test]
+    async fn test_monitor_reports_readings() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ThermalConfig {
+            temp_path: dir.path().join("temp"),
+            throttled_path: dir.path().join("get_throttled"),
+            sustained_s: 0,
+            ..Default::default()
+        };
+        std::fs::write(&config.temp_path, "81234\n").unwrap();
+        std::fs::write(&config.throttled_path, "30009\n").unwrap();
+        let state = new_app_state();
+        let (event_bus, mut rx) = EventBus::new();
+        let handle = ThermalMonitor::new(config, event_bus, state.clone()).spawn();
+
+        assert!(matches!(rx.recv().await.unwrap(), Event::SocOverheating { celsius } if celsius == 81.234));
+        assert!(matches!(rx.recv().await.unwrap(), Event::PowerUndervoltage { active: true }));
+        let soc = state.read().soc.clone();
+        assert!(soc.overheating && soc.throttled.unwrap().soft_temp_limit);
+        assert_eq!(state.read().health.get(Component::Thermal).unwrap().status, HealthStatus::Degraded);
+        assert_eq!(state.read().health.get(Component::Power).unwrap().status, HealthStatus::Degraded);
+        handle.abort();
+    }
+}
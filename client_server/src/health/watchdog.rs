@@ -1,7 +1,8 @@
 //! Systemd watchdog integration
 
 use std::time::Duration;
-use tokio::time::interval;
+#[cfg(feature = "systemd")]
+use tracing::{debug, info};
 
 pub struct WatchdogManager {
     #[cfg(feature = "systemd")]
@@ -40,20 +41,16 @@ impl WatchdogManager {
         }
     }
 
-    /// Start watchdog notification loop
-    pub async fn run(&self) {
-        #[cfg(feature = "systemd")]
-        if !self.enabled {
-            return;
-        }
-
-        let mut ticker = interval(self.interval);
+    /// How often systemd expects a keep-alive
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
 
-        loop {
-            ticker.tick().await;
-            
-            #[cfg(feature = "systemd")]
-            {
+    /// Tell systemd the agent is still alive; only call once it is known to be
+    pub fn keep_alive(&self) {
+        #[cfg(feature = "systemd")]
+        {
+            if self.enabled {
                 if let Err(e) = sd_notify::notify(false, &[sd_notify::NotifyState::Watchdog]) {
                     tracing::error!(error = %e, "Failed to notify systemd watchdog");
                 }
@@ -0,0 +1,331 @@
+//! Usage history in SQLite, for insights without the master
+//!
+//! State transitions, door openings with how long the door stayed open, and
+//! alarms are kept in `<data_dir>/history.db`. The state machine reports
+//! transitions; door openings and alarms are taken from the event bus. The
+//! `/v1/history` endpoints aggregate them by day in UTC.
+//!
+//! SQLite comes with the `history` feature. Without it the store cannot be
+//! opened, and the endpoints answer that history is disabled.
+
+#[cfg(feature = "history")]
+mod sqlite;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::config::HistoryConfig;
+use crate::events::{AlarmKind, Event, EventBus, EventEnvelope};
+use crate::state::AlarmState;
+
+#[cfg(feature = "history")]
+use sqlite::Database;
+
+/// How often records past `history.max_age_days` are deleted
+const PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Most days an aggregate looks back
+pub const MAX_DAYS: u32 = 3660;
+
+/// Something worth keeping in the history
+#[derive(Debug, Clone, PartialEq)]
+pub enum HistoryRecord {
+    Transition { at: DateTime<Utc>, from: AlarmState, to: AlarmState },
+    /// The door opened at `opened_at` and closed again at `closed_at`
+    DoorOpening { opened_at: DateTime<Utc>, closed_at: DateTime<Utc> },
+    Alarm { at: DateTime<Utc>, kind: AlarmKind, cause: String },
+}
+
+/// A state transition read back from the history
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub at: DateTime<Utc>,
+    pub from: Option<AlarmState>,
+    pub to: Option<AlarmState>,
+}
+
+/// Door openings on one day
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DoorDay {
+    /// `YYYY-MM-DD` in UTC
+    pub day: String,
+    pub opens: u64,
+    pub average_open_s: f64,
+    pub longest_open_s: f64,
+}
+
+/// Alarms of one kind on one day
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AlarmDay {
+    pub day: String,
+    pub kind: String,
+    pub count: u64,
+}
+
+/// Time spent armed, from reaching `armed` until the next disarm
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ArmedSummary {
+    /// Armed periods that started in the window, including one still going on
+    pub periods: u64,
+    pub total_armed_s: f64,
+    pub average_armed_s: Option<f64>,
+    pub armed_now: bool,
+}
+
+/// Sends transitions from the state machine to the history writer
+#[derive(Clone)]
+pub struct HistoryRecorder {
+    tx: mpsc::UnboundedSender<HistoryRecord>,
+}
+
+impl HistoryRecorder {
+    pub fn record(&self, record: HistoryRecord) {
+        if self.tx.send(record).is_err() {
+            debug!("History writer gone, record dropped");
+        }
+    }
+}
+
+/// Pairs door opens with the closes that follow them
+#[derive(Debug, Default)]
+pub struct DoorTracker {
+    opened_at: Option<DateTime<Utc>>,
+}
+
+impl DoorTracker {
+    /// The record an envelope completes, if any
+    pub fn observe(&mut self, envelope: &EventEnvelope) -> Option<HistoryRecord> {
+        match &envelope.event {
+            Event::DoorOpen => {
+                self.opened_at.get_or_insert(envelope.timestamp);
+                None
+            }
+            Event::DoorClose => self.opened_at.take().map(|opened_at| HistoryRecord::DoorOpening {
+                opened_at,
+                closed_at: envelope.timestamp,
+            }),
+            Event::AlarmTriggered { kind, cause } => Some(HistoryRecord::Alarm {
+                at: envelope.timestamp,
+                kind: *kind,
+                cause: cause.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The history database, shared by its writer and the API
+pub struct HistoryStore {
+    db: Mutex<Database>,
+    max_age: Option<Duration>,
+}
+
+impl HistoryStore {
+    /// Open or create the database at `path`
+    pub fn open(path: &Path, config: &HistoryConfig) -> Result<Self> {
+        Ok(Self {
+            db: Mutex::new(Database::open(path)?),
+            max_age: (config.max_age_days > 0).then(|| Duration::days(i64::from(config.max_age_days))),
+        })
+    }
+
+    /// Write transitions sent to the returned recorder, and door openings and
+    /// alarms from the bus, until the bus closes
+    pub fn spawn(self: Arc<Self>, event_bus: &EventBus) -> (HistoryRecorder, JoinHandle<()>) {
+        let (tx, mut records) = mpsc::unbounded_channel();
+        let mut rx = event_bus.subscribe();
+        let event_bus = event_bus.clone();
+        let handle = tokio::spawn(async move {
+            info!("Usage history started");
+            let mut doors = DoorTracker::default();
+            let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+            loop {
+                let record = tokio::select! {
+                    received = rx.recv() => match received {
+                        Ok(envelope) => doors.observe(&envelope),
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            event_bus.note_lagged("history", missed);
+                            warn!(missed, "Usage history fell behind, door openings may be missing");
+                            None
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    Some(record) = records.recv() => Some(record),
+                    _ = prune.tick() => {
+                        self.prune(Utc::now());
+                        None
+                    }
+                };
+                if let Some(record) = record {
+                    if let Err(e) = self.db.lock().insert(&record) {
+                        warn!(error = %e, ?record, "Failed to record history");
+                    }
+                }
+            }
+        });
+        (HistoryRecorder { tx }, handle)
+    }
+
+    /// Door openings per day over the last `days`
+    pub fn door_days(&self, days: u32) -> Result<Vec<DoorDay>> {
+        self.db.lock().door_days(window_start(days))
+    }
+
+    /// Alarms per day and kind over the last `days`
+    pub fn alarm_days(&self, days: u32) -> Result<Vec<AlarmDay>> {
+        self.db.lock().alarm_days(window_start(days))
+    }
+
+    /// Time armed over the last `days`
+    pub fn armed(&self, days: u32) -> Result<ArmedSummary> {
+        let transitions = self.db.lock().transitions(window_start(days))?;
+        Ok(armed_summary(&transitions, Utc::now()))
+    }
+
+    fn prune(&self, now: DateTime<Utc>) {
+        let Some(max_age) = self.max_age else { return };
+        match self.db.lock().prune(now - max_age) {
+            Ok(0) => {}
+            Ok(removed) => debug!(removed, "Pruned usage history"),
+            Err(e) => warn!(error = %e, "Failed to prune usage history"),
+        }
+    }
+}
+
+fn window_start(days: u32) -> DateTime<Utc> {
+    Utc::now() - Duration::days(i64::from(days.min(MAX_DAYS)))
+}
+
+/// Armed periods in transitions ordered oldest first; one not yet disarmed runs until `now`
+pub fn armed_summary(transitions: &[Transition], now: DateTime<Utc>) -> ArmedSummary {
+    let mut summary = ArmedSummary::default();
+    let mut armed_since = None;
+    for transition in transitions {
+        match transition.to {
+            Some(AlarmState::Armed) if armed_since.is_none() => armed_since = Some(transition.at),
+            Some(AlarmState::Disarmed) => {
+                if let Some(since) = armed_since.take() {
+                    summary.periods += 1;
+                    summary.total_armed_s += seconds(transition.at - since);
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(since) = armed_since {
+        summary.periods += 1;
+        summary.total_armed_s += seconds(now - since);
+        summary.armed_now = true;
+    }
+    summary.average_armed_s = (summary.periods > 0).then(|| summary.total_armed_s / summary.periods as f64);
+    summary
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.num_milliseconds() as f64 / 1000.0
+}
+
+/// State named as `AlarmState` displays it, as the history stores it
+#[cfg(any(feature = "history", test))]
+fn parse_state(name: &str) -> Option<AlarmState> {
+    [
+        AlarmState::Disarmed,
+        AlarmState::ExitDelay,
+        AlarmState::Armed,
+        AlarmState::EntryDelay,
+        AlarmState::Alarm,
+    ]
+    .into_iter()
+    .find(|state| state.to_string() == name)
+}
+
+/// Stands in for SQLite in builds without the `history` feature; it cannot be opened
+#[cfg(not(feature = "history"))]
+struct Database(std::convert::Infallible);
+
+#[cfg(not(feature = "history"))]
+impl Database {
+    fn open(path: &Path) -> Result<Self> {
+        anyhow::bail!("Built without the history feature, {} not opened", path.display())
+    }
+
+    fn insert(&self, _record: &HistoryRecord) -> Result<()> {
+        match self.0 {}
+    }
+
+    fn prune(&self, _before: DateTime<Utc>) -> Result<usize> {
+        match self.0 {}
+    }
+
+    fn door_days(&self, _since: DateTime<Utc>) -> Result<Vec<DoorDay>> {
+        match self.0 {}
+    }
+
+    fn alarm_days(&self, _since: DateTime<Utc>) -> Result<Vec<AlarmDay>> {
+        match self.0 {}
+    }
+
+    fn transitions(&self, _since: DateTime<Utc>) -> Result<Vec<Transition>> {
+        match self.0 {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_door_openings_and_alarms() {
+        let mut doors = DoorTracker::default();
+        let at = |event| EventEnvelope::new(event, "test".to_string());
+
+        // A close without an open, as after a restart, is not an opening
+        assert_eq!(doors.observe(&at(Event::DoorClose)), None);
+        let opened = at(Event::DoorOpen);
+        assert_eq!(doors.observe(&opened), None);
+        assert_eq!(doors.observe(&at(Event::DoorOpen)), None);
+        let closed = at(Event::DoorClose);
+        assert_eq!(
+            doors.observe(&closed),
+            Some(HistoryRecord::DoorOpening { opened_at: opened.timestamp, closed_at: closed.timestamp })
+        );
+
+        let alarm = at(Event::AlarmTriggered { kind: AlarmKind::Burglar, cause: "door".to_string() });
+        assert!(matches!(doors.observe(&alarm), Some(HistoryRecord::Alarm { kind: AlarmKind::Burglar, .. })));
+    }
+
+    #[test]
+    fn test_armed_summary() {
+        let start = Utc::now() - Duration::hours(10);
+        let transition = |minutes, from: AlarmState, to: AlarmState| Transition {
+            at: start + Duration::minutes(minutes),
+            from: parse_state(&from.to_string()),
+            to: parse_state(&to.to_string()),
+        };
+        let transitions = [
+            transition(0, AlarmState::Disarmed, AlarmState::ExitDelay),
+            transition(1, AlarmState::ExitDelay, AlarmState::Armed),
+            // Going through an alarm still counts as armed until the disarm
+            transition(30, AlarmState::Armed, AlarmState::EntryDelay),
+            transition(31, AlarmState::EntryDelay, AlarmState::Alarm),
+            transition(61, AlarmState::Alarm, AlarmState::Disarmed),
+            transition(120, AlarmState::ExitDelay, AlarmState::Armed),
+        ];
+
+        let now = start + Duration::minutes(150);
+        let summary = armed_summary(&transitions, now);
+        assert_eq!(summary.periods, 2);
+        assert!(summary.armed_now);
+        assert_eq!(summary.total_armed_s, 90.0 * 60.0);
+        assert_eq!(summary.average_armed_s, Some(45.0 * 60.0));
+        assert_eq!(armed_summary(&[], now), ArmedSummary::default());
+        assert_eq!(parse_state("exit_delay"), Some(AlarmState::ExitDelay));
+    }
+}
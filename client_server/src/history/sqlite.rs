@@ -0,0 +1,175 @@
+//! SQLite storage of the usage history
+//!
+//! Times are kept as Unix milliseconds, so days are grouped with
+//! `date(at / 1000, 'unixepoch')`.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+use super::{parse_state, AlarmDay, DoorDay, HistoryRecord, Transition};
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    CREATE TABLE IF NOT EXISTS transitions (
+        at INTEGER NOT NULL,
+        from_state TEXT NOT NULL,
+        to_state TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS transitions_at ON transitions (at);
+    CREATE TABLE IF NOT EXISTS door_openings (
+        opened_at INTEGER NOT NULL,
+        closed_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS door_openings_opened_at ON door_openings (opened_at);
+    CREATE TABLE IF NOT EXISTS alarms (
+        at INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        cause TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS alarms_at ON alarms (at);
+";
+
+pub(super) struct Database {
+    conn: Connection,
+}
+
+impl Database {
+    pub(super) fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create history directory {}", parent.display()))?;
+        }
+        let conn = Connection::open(path).with_context(|| format!("Failed to open history {}", path.display()))?;
+        conn.execute_batch(SCHEMA).context("Failed to create history tables")?;
+        Ok(Self { conn })
+    }
+
+    pub(super) fn insert(&self, record: &HistoryRecord) -> Result<()> {
+        match record {
+            HistoryRecord::Transition { at, from, to } => self.conn.execute(
+                "INSERT INTO transitions (at, from_state, to_state) VALUES (?1, ?2, ?3)",
+                params![at.timestamp_millis(), from.to_string(), to.to_string()],
+            ),
+            HistoryRecord::DoorOpening { opened_at, closed_at } => self.conn.execute(
+                "INSERT INTO door_openings (opened_at, closed_at) VALUES (?1, ?2)",
+                params![opened_at.timestamp_millis(), closed_at.timestamp_millis()],
+            ),
+            HistoryRecord::Alarm { at, kind, cause } => self.conn.execute(
+                "INSERT INTO alarms (at, kind, cause) VALUES (?1, ?2, ?3)",
+                params![at.timestamp_millis(), kind.to_string(), cause],
+            ),
+        }
+        .context("Failed to write history record")?;
+        Ok(())
+    }
+
+    /// Delete records from before `before`, returning how many went
+    pub(super) fn prune(&self, before: DateTime<Utc>) -> Result<usize> {
+        let before = before.timestamp_millis();
+        let mut removed = 0;
+        for sql in [
+            "DELETE FROM transitions WHERE at < ?1",
+            "DELETE FROM door_openings WHERE opened_at < ?1",
+            "DELETE FROM alarms WHERE at < ?1",
+        ] {
+            removed += self.conn.execute(sql, params![before]).context("Failed to prune history")?;
+        }
+        Ok(removed)
+    }
+
+    pub(super) fn door_days(&self, since: DateTime<Utc>) -> Result<Vec<DoorDay>> {
+        let mut statement = self.conn.prepare(
+            "SELECT date(opened_at / 1000, 'unixepoch') AS day, COUNT(*),
+                    AVG(closed_at - opened_at) / 1000.0, MAX(closed_at - opened_at) / 1000.0
+             FROM door_openings WHERE opened_at >= ?1 GROUP BY day ORDER BY day",
+        )?;
+        let days = statement
+            .query_map(params![since.timestamp_millis()], |row| {
+                Ok(DoorDay {
+                    day: row.get(0)?,
+                    opens: row.get::<_, i64>(1)? as u64,
+                    average_open_s: row.get(2)?,
+                    longest_open_s: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()
+            .context("Failed to read door openings")?;
+        Ok(days)
+    }
+
+    pub(super) fn alarm_days(&self, since: DateTime<Utc>) -> Result<Vec<AlarmDay>> {
+        let mut statement = self.conn.prepare(
+            "SELECT date(at / 1000, 'unixepoch') AS day, kind, COUNT(*)
+             FROM alarms WHERE at >= ?1 GROUP BY day, kind ORDER BY day, kind",
+        )?;
+        let days = statement
+            .query_map(params![since.timestamp_millis()], |row| {
+                Ok(AlarmDay {
+                    day: row.get(0)?,
+                    kind: row.get(1)?,
+                    count: row.get::<_, i64>(2)? as u64,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()
+            .context("Failed to read alarms")?;
+        Ok(days)
+    }
+
+    /// Transitions since `since`, oldest first
+    pub(super) fn transitions(&self, since: DateTime<Utc>) -> Result<Vec<Transition>> {
+        let mut statement =
+            self.conn.prepare("SELECT at, from_state, to_state FROM transitions WHERE at >= ?1 ORDER BY at")?;
+        let rows = statement
+            .query_map(params![since.timestamp_millis()], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .context("Failed to read transitions")?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|(at, from, to)| {
+                Some(Transition {
+                    at: DateTime::from_timestamp_millis(at)?,
+                    from: parse_state(&from),
+                    to: parse_state(&to),
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::AlarmKind;
+    use crate::state::AlarmState;
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn test_aggregates_by_day() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open(&dir.path().join("history.db")).unwrap();
+        let day = Utc.with_ymd_and_hms(2026, 3, 14, 8, 0, 0).unwrap();
+
+        for (offset_h, open_s) in [(0, 10), (2, 30), (25, 5)] {
+            let opened_at = day + Duration::hours(offset_h);
+            let closed_at = opened_at + Duration::seconds(open_s);
+            db.insert(&HistoryRecord::DoorOpening { opened_at, closed_at }).unwrap();
+        }
+        db.insert(&HistoryRecord::Alarm { at: day, kind: AlarmKind::Burglar, cause: "door".to_string() }).unwrap();
+        db.insert(&HistoryRecord::Transition { at: day, from: AlarmState::ExitDelay, to: AlarmState::Armed })
+            .unwrap();
+
+        let doors = db.door_days(day - Duration::days(1)).unwrap();
+        assert_eq!(doors.len(), 2);
+        assert_eq!((doors[0].day.as_str(), doors[0].opens), ("2026-03-14", 2));
+        assert_eq!((doors[0].average_open_s, doors[0].longest_open_s), (20.0, 30.0));
+        assert_eq!(db.alarm_days(day).unwrap()[0].count, 1);
+        assert_eq!(db.transitions(day).unwrap()[0].to, Some(AlarmState::Armed));
+
+        assert_eq!(db.prune(day + Duration::hours(1)).unwrap(), 3);
+        assert_eq!(db.door_days(day - Duration::days(1)).unwrap().len(), 2);
+    }
+}
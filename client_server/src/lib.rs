@@ -9,14 +9,20 @@ pub mod state;
 pub mod timers;
 pub mod gpio;
 pub mod actuators;
+pub mod display;
 pub mod api;
 pub mod cloud;
 pub mod ble;
 pub mod rf433;
+pub mod access;
+pub mod onewire;
+pub mod adc;
 pub mod network;
 pub mod security;
+pub mod history;
 pub mod observability;
 pub mod health;
+pub mod update;
 
 pub use config::AppConfig;
 pub use events::{Event, EventBus};
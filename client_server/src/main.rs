@@ -1,30 +1,78 @@
 //! Pi Door Security Client Agent
 //! Main entry point
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use pi_door_client::{
-    api, config,
-    events::EventBus,
-    gpio::{DefaultGpio, GpioController},
-    network::NetworkManager,
-    observability,
+    access::{AccessControl, Lockdown, PinGuard, WiegandReader},
+    actuators::{ActuatorController, OutputRegistry},
+    adc::AdcMonitor,
+    api,
+    ble::BleService,
+    cloud::{self, pinned_connector, AddressReporter, CloudClient, MasterToken, QueueManager, RestFallback},
+    config::{self, ConfigManager, ConfigStore, CONFIG_PATH},
+    display::DisplayController,
+    events::{self, DeadLetterStore, EventBus, EventJournal, EventQueue, RetryPolicy, SequenceCounter},
+    gpio::{self, GpioController, SelfTest, SelfTestScheduler, SensorMonitor},
+    health::{self, HealthMonitor, Liveness, Subsystem, Supervisor},
+    history::HistoryStore,
+    network::{LinkMonitor, NetworkManager, SetupAp},
+    onewire::TemperatureMonitor,
+    observability::{self, CrashReporter, Metrics},
+    rf433::{Rf433Receiver, RollingCodes, Rtl433Bridge},
+    security::{self, ApiTokens, AuditLog, CommandVerifier, ReplayGuard, EnvelopeSigner, SecretStore, TokenRotator},
     state::{new_app_state, StateMachine},
+    update::{self, Updater},
 };
-use std::{env, process, sync::Arc};
+use std::{env, net::SocketAddr, process, sync::Arc, time::Duration};
 use tokio::signal;
 use tracing::{error, info, warn};
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<()> {
     // Initialize logging
-    observability::init_logging()?;
+    let logging = observability::init_logging()?;
     info!("Pi Door Security Client Agent v{}", pi_door_client::VERSION);
 
     // Parse CLI arguments
     let cli = CliArgs::parse()?;
 
     // Load configuration
-    let mut config = config::load_config()?;
+    let config = config::load_config()?;
+    if let Err(e) = logging.add_file(&config) {
+        warn!(error = %e, "Logging to stdout only");
+    }
+    if let Err(e) = logging.set_level(&config.system.log_level) {
+        warn!(error = %e, "Keeping the default log level");
+    }
+
+    // Landlock binds only threads started after it, so it goes on before the runtime's
+    if config.sandbox.enabled && config.sandbox.landlock && !cli.install_update {
+        security::restrict_paths(&config).context("Refusing to start without the configured sandbox")?;
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(cli, config, logging))
+}
+
+async fn run(cli: CliArgs, mut config: config::AppConfig, logging: observability::LogHandle) -> anyhow::Result<()> {
+
+    // Run as root by systemd before each start: install or roll back agent updates
+    if cli.install_update {
+        if config.update.enabled {
+            let key = update::embedded_key()
+                .map_err(|e| warn!(error = %e, "Staged updates will not be installed"))
+                .ok();
+            let install_path = env::current_exe()?;
+            // Never keep the agent from starting over a failed update
+            if let Err(e) =
+                update::prepare_start(&config.update_dir(), &install_path, key.as_deref(), config.update.max_boot_attempts)
+            {
+                error!(error = %e, "Agent update step failed");
+            }
+        }
+        return Ok(());
+    }
 
     // Apply CLI-provided API key if present
     if let Some(api_key) = cli.api_key {
@@ -33,7 +81,28 @@ async fn main() -> anyhow::Result<()> {
     } else if config.system.api_key.is_some() {
         warn!("Ignoring api_key from configuration file; provide --api-key at startup");
         config.system.api_key = None;
-    } else {
+    }
+
+    // Register with the master on first start; later starts reuse the stored identity
+    match cloud::provision(&config, cli.provision_key.as_deref()).await {
+        Ok(Some(registration)) => {
+            config.system.client_id = registration.client_id;
+            if config.system.api_key.is_none() {
+                config.system.api_key = Some(registration.api_token);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => error!(error = %e, "Registration with the master failed"),
+    }
+    // A rotated master token takes over from the one given at startup
+    if config.rotation.enabled {
+        match security::rotated_master_token(&config) {
+            Ok(Some(token)) => config.system.api_key = Some(token),
+            Ok(None) => {}
+            Err(e) => warn!(error = %e, "Rotated master token unreadable"),
+        }
+    }
+    if config.system.api_key.is_none() {
         info!("No API key provided at startup");
     }
     info!(client_id = %config.system.client_id, "Configuration loaded");
@@ -42,21 +111,221 @@ async fn main() -> anyhow::Result<()> {
     let app_state = new_app_state();
 
     // Initialize event bus
-    let (event_bus, mut event_rx) = EventBus::new();
+    let (event_bus, event_rx) = EventBus::bounded(
+        config.event_bus.capacity,
+        config.event_bus.broadcast_capacity,
+        config.event_bus.overflow,
+    );
+    if config.event_bus.metrics_log_s > 0 {
+        observability::spawn_bus_metrics(event_bus.clone(), Duration::from_secs(config.event_bus.metrics_log_s));
+    }
+
+    // Counters for /metrics and heartbeats, registered once for the life of the agent
+    let metrics = Metrics::new().context("Failed to register metrics")?;
+
+    // A lockdown outlives restarts until the master lifts it
+    let lockdown = Lockdown::new(&config, app_state.clone(), event_bus.clone());
+    if let Err(e) = lockdown.restore() {
+        error!(error = %e, "Stored lockdown unreadable");
+    }
+
+    // Journal every event locally, subscribed before any producer starts
+    let journal = if config.journal.enabled {
+        match EventJournal::open(config.journal_path(), &config.journal) {
+            Ok(journal) => {
+                let journal = Arc::new(journal);
+                journal.clone().spawn(&event_bus);
+                Some(journal)
+            }
+            Err(e) => {
+                warn!(error = %e, "Event journal unavailable");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Usage history for local analytics, also subscribed before any producer starts
+    let history = if config.history.enabled {
+        match HistoryStore::open(&config.history_path(), &config.history) {
+            Ok(history) => {
+                let history = Arc::new(history);
+                let (recorder, _) = history.clone().spawn(&event_bus);
+                Some((history, recorder))
+            }
+            Err(e) => {
+                warn!(error = %e, "Usage history unavailable");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Control requests are audited apart from the event stream
+    let audit = if config.audit.enabled {
+        match AuditLog::open(config.audit_path(), &config.audit) {
+            Ok(audit) => {
+                info!(entries = audit.len(), "Audit log opened");
+                Some(Arc::new(audit))
+            }
+            Err(e) => {
+                warn!(error = %e, "Audit log unavailable, control requests will not be audited");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // WebSocket and cloud commands are refused when stale or seen before
+    let replay = config.replay.enabled.then(|| {
+        let guard = ReplayGuard::new(&config.replay, event_bus.clone());
+        if let Err(e) = guard.set_store(&config.replay_path()) {
+            warn!(error = %e, "Replay database unavailable, seen nonces are forgotten on restart");
+        }
+        guard
+    });
+
+    // Fan events out to configured integrations
+    let sinks = events::spawn_sinks(&config.sinks, &event_bus, &config.system.client_id);
+
+    // Events the state machine keeps failing on are kept for inspection
+    let dead_letters = if config.dead_letter.enabled {
+        match DeadLetterStore::open(config.dead_letter_path(), &config.dead_letter) {
+            Ok(store) => Some(Arc::new(store)),
+            Err(e) => {
+                warn!(error = %e, "Dead-letter store unavailable, failed events will be dropped");
+                None
+            }
+        }
+    } else {
+        None
+    };
 
     // Initialize GPIO
-    let mut gpio = DefaultGpio::new();
-    gpio.initialize().await?;
+    let gpio_arc = gpio::open(&config.gpio).await?;
     info!("GPIO initialized");
 
-    // Set up panic hook for emergency shutdown
-    let gpio_clone = gpio.clone();
+    // Bind the listener while still root, then give root up for good
+    let listener = tokio::net::TcpListener::bind(&config.http.listen_addr).await?;
+    if config.privileges.drop {
+        if config.network.ap_fallback.enabled {
+            warn!("The setup access point needs root and will not start after dropping privileges");
+        }
+        security::drop_privileges(&config.privileges, &config.system.data_dir)
+            .context("Refusing to run as root with privileges.drop set")?;
+    } else if security::privilege_status().root {
+        info!("Running as root; set privileges.drop to switch to an unprivileged user");
+    }
+
+    // Set up panic hook for emergency shutdown, then leave a report for the master
+    let gpio_clone = gpio_arc.clone();
+    let crash_reporter = CrashReporter::new(&config, app_state.clone());
+    let panic_reporter = crash_reporter.clone();
     std::panic::set_hook(Box::new(move |panic_info| {
         error!("PANIC: {:?}", panic_info);
         gpio_clone.emergency_shutdown();
+        panic_reporter.record_panic(panic_info);
     }));
 
-    let gpio_arc: Arc<dyn GpioController> = Arc::new(gpio);
+    // Spawn sensor monitoring tasks
+    SensorMonitor::new(gpio_arc.clone(), event_bus.clone(), config.gpio.clone()).spawn();
+    info!("Sensor monitor started");
+
+    // Spawn 1-Wire temperature polling
+    if config.onewire.enabled {
+        TemperatureMonitor::new(config.onewire.clone(), event_bus.clone()).spawn();
+        info!("Temperature monitor started");
+    }
+
+    // Spawn ADC sampling for analog sensors and battery backup
+    if config.adc.enabled {
+        match AdcMonitor::open(config.adc.clone(), app_state.clone(), event_bus.clone()) {
+            Ok(adc) => {
+                adc.spawn();
+                info!("ADC monitor started");
+            }
+            Err(e) => warn!(error = %e, "ADC unavailable"),
+        }
+    }
+
+    // Spawn SoC temperature and throttling polling
+    if config.thermal.enabled {
+        health::ThermalMonitor::new(config.thermal.clone(), event_bus.clone(), app_state.clone()).spawn();
+    }
+
+    // Watch the agent's own memory for leaks
+    if config.memory.enabled {
+        health::MemoryMonitor::new(config.memory.clone(), gpio_arc.clone(), event_bus.clone(), app_state.clone()).spawn();
+    }
+
+    // Door keypad / card reader
+    if let (Some(d0), Some(d1)) = (config.gpio.wiegand_d0_in, config.gpio.wiegand_d1_in) {
+        let mut access = AccessControl::new(&config, app_state.clone(), event_bus.clone());
+        if let Some(audit) = &audit {
+            access.set_audit(audit.clone());
+        }
+        WiegandReader::new(gpio_arc.clone(), d0, d1, access, config.access.keypad_timeout_s).spawn();
+        info!("Wiegand reader started");
+    }
+
+    // Configuration changed at runtime, by the master or by learning RF codes, saved under the data
+    // directory: the file in /etc stays root's
+    let mut config_store = ConfigStore::new(config.clone(), config.override_path());
+    config_store.set_base(CONFIG_PATH);
+
+    // 433 MHz remotes
+    if config.rf433.enabled {
+        let mut receiver = Rf433Receiver::new(gpio_arc.clone(), config.gpio.radio433_rx_in, &config.rf433, event_bus.clone());
+        receiver.set_state(app_state.clone());
+        receiver.set_config_store(config_store.clone());
+        if let Some(audit) = &audit {
+            receiver.set_audit(audit.clone());
+        }
+        if !config.rf433.remotes.is_empty() {
+            let rolling = SecretStore::load(&config.secrets_path())
+                .and_then(|secrets| RollingCodes::load(&config.rf433, &secrets, &config.rf433_counters_path()));
+            match rolling {
+                Ok(rolling) => receiver.set_rolling_codes(rolling),
+                Err(e) => warn!(error = %e, "Rolling-code remotes unavailable"),
+            }
+        }
+        receiver.spawn();
+    }
+
+    // Commodity wireless sensors through an SDR dongle
+    if config.rtl433.enabled {
+        Rtl433Bridge::new(&config.rtl433, &config.system.client_id, event_bus.clone()).spawn();
+    }
+
+    // Verify wiring at startup, on request and on schedule, keeping the results
+    SelfTest::new(gpio_arc.clone(), event_bus.clone(), config.gpio.clone()).spawn();
+    SelfTestScheduler::new(&config, event_bus.clone(), app_state.clone()).spawn();
+
+    // Spawn actuator task to mirror state onto the outputs
+    let actuators = ActuatorController::new(gpio_arc.clone(), app_state.clone(), &config);
+    tokio::spawn(actuators.run(event_bus.subscribe()));
+
+    // The status display is a convenience; the agent runs without it
+    if config.display.enabled {
+        match DisplayController::open(&config.display, app_state.clone()) {
+            Ok(display) => {
+                tokio::spawn(display.run(event_bus.subscribe()));
+            }
+            Err(e) => warn!(error = %e, "Status display unavailable"),
+        }
+    }
+
+    // Phones near the door talk to the GATT service; it exists even while
+    // disabled so the API can switch it on
+    let mut ble = BleService::new(&config, app_state.clone(), event_bus.clone());
+    if let Some(audit) = &audit {
+        ble.set_audit(audit.clone());
+    }
+    tokio::spawn(ble.clone().run());
+    tokio::spawn(ble.clone().watch_devices());
 
     // Initialize state machine
     let mut state_machine = StateMachine::new(
@@ -65,35 +334,288 @@ async fn main() -> anyhow::Result<()> {
         config.timers.clone(),
         config.system.client_id.clone(),
     );
+    state_machine.set_metrics(metrics.clone());
+    state_machine.set_outputs(OutputRegistry::from_config(&config));
+    state_machine.set_lag_threshold(
+        Duration::from_millis(config.event_bus.lag_warn_ms),
+        Duration::from_secs(config.event_bus.lag_sustained_s),
+    );
+    match SequenceCounter::open(config.sequence_path()) {
+        Ok(sequence) => state_machine.set_sequence(sequence),
+        Err(e) => warn!(error = %e, "Event sequence not persisted, numbering restarts each run"),
+    }
+    if config.signing.enabled {
+        match SecretStore::open(&config).and_then(|mut secrets| secrets.device_key()) {
+            Ok(key) => {
+                info!(key = %key.location(), "Event signing enabled");
+                state_machine.set_signer(EnvelopeSigner::from_key(key));
+            }
+            Err(e) => warn!(error = %e, "Device key unavailable, events will not be signed"),
+        }
+    }
+
+    state_machine.set_retry_policy(RetryPolicy::from_config(&config.dead_letter), dead_letters.clone());
+    state_machine.set_timestamp_correction(config.cloud.correct_timestamps);
+    if let Some((_, recorder)) = &history {
+        state_machine.set_history(recorder.clone());
+    }
 
     // Spawn state machine event processing task
-    tokio::spawn(async move {
-        while let Some(event) = event_rx.recv().await {
-            if let Err(e) = state_machine.process_event(event).await {
-                error!(error = %e, "Failed to process event");
+    // The watchdog is only kept alive while the loop, GPIO reads and timers make progress
+    let liveness = Liveness::new();
+    state_machine.set_liveness(liveness.clone());
+    let loop_liveness = liveness.clone();
+    // Background tasks that must keep running are restarted should they panic or return
+    let supervisor = Supervisor::new(app_state.clone());
+    let event_loop = Arc::new(tokio::sync::Mutex::new((state_machine, event_rx)));
+    supervisor.supervise("event_loop", move || {
+        let (event_loop, loop_liveness) = (event_loop.clone(), loop_liveness.clone());
+        async move {
+            let mut event_loop = event_loop.lock().await;
+            let (state_machine, event_rx) = &mut *event_loop;
+            // Beats on a ticker too, so an idle loop still counts as alive
+            let mut beat = tokio::time::interval(health::BEAT_INTERVAL);
+            loop {
+                tokio::select! {
+                    queued = event_rx.recv_queued() => match queued {
+                        Some(queued) => state_machine.process_with_retry(queued).await,
+                        None => break,
+                    },
+                    _ = beat.tick() => {}
+                }
+                loop_liveness.beat(Subsystem::EventLoop);
             }
+            info!("State machine event loop terminated");
         }
-        info!("State machine event loop terminated");
     });
+    let health_monitor = HealthMonitor::new(liveness, gpio_arc.clone(), app_state.clone());
+
+    // Keep a freshly installed update once it has stayed up long enough
+    if config.update.enabled {
+        let (dir, confirm_after) = (config.update_dir(), Duration::from_secs(config.update.confirm_after_s));
+        let bus = event_bus.clone();
+        tokio::spawn(async move {
+            if let Err(e) = update::confirm_boot(dir, confirm_after, bus).await {
+                warn!(error = %e, "Failed to confirm agent update");
+            }
+        });
+    }
 
     // Initialize network manager
     let mut network_manager = NetworkManager::new(config.network.prefer.clone());
+    network_manager.set_state(app_state.clone());
+    network_manager.set_event_bus(event_bus.clone());
+    network_manager.set_failover(&config.network.failover);
+    if config.network.captive_portal.enabled {
+        network_manager.set_captive_portal(&config.network.captive_portal);
+    }
+    if config.network.ap_fallback.enabled {
+        network_manager.set_setup_ap(SetupAp::new(
+            &config.network.ap_fallback,
+            config.network.wpa_supplicant_conf.clone(),
+            &config.system.client_id,
+            config.system.data_dir.join("setup-ap"),
+        ));
+    }
     info!("Network manager initialized");
 
     // Spawn network monitoring task
-    tokio::spawn(async move {
-        network_manager.start_monitoring().await;
+    let network_manager = Arc::new(tokio::sync::Mutex::new(network_manager));
+    supervisor.supervise("network", move || {
+        let network_manager = network_manager.clone();
+        async move { network_manager.lock().await.start_monitoring().await }
+    });
+    LinkMonitor::new(&config.network, app_state.clone()).spawn();
+
+    // Reload the configuration file on SIGHUP
+    let mut config_manager = ConfigManager::new(config_store.clone(), event_bus.clone());
+    config_manager.set_logging(logging);
+    config_manager.set_sinks(sinks);
+    config_manager.spawn();
+
+    // Shared by the cloud WebSocket and the HTTPS fallback, so a rotated token reaches both
+    let master_token = MasterToken::new(config.system.api_key.clone());
+
+    // Network routes accept rotated local API tokens once rotation is on
+    let api_tokens = if config.rotation.enabled {
+        match ApiTokens::open(&config) {
+            Ok(tokens) => Some(tokens),
+            Err(e) => {
+                warn!(error = %e, "Stored API tokens unreadable, tokens will not be rotated");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut rotator = api_tokens.clone().map(|tokens| {
+        let mut rotator = TokenRotator::new(&config, tokens, event_bus.clone());
+        if let Some(audit) = &audit {
+            rotator.set_audit(audit.clone());
+        }
+        rotator
     });
 
+    // Free space on the data partition; the offline queue is added once it is open
+    let mut storage_monitor = config.storage.enabled.then(|| {
+        health::StorageMonitor::new(config.storage.clone(), config.system.data_dir.clone(), event_bus.clone(), app_state.clone())
+    });
+
+    // Forward events to the master, keeping them on disk until acknowledged
+    if !config.cloud.url.is_empty() {
+        let mut cloud = CloudClient::new(config.cloud.url.clone(), &config.cloud, event_bus.clone(), app_state.clone());
+        cloud.set_token(master_token.clone());
+        if let Some(audit) = &audit {
+            cloud.set_audit(audit.clone());
+        }
+        if let Some(replay) = &replay {
+            cloud.set_replay_guard(replay.clone());
+        }
+        cloud.set_lockdown(lockdown);
+        cloud.set_outputs(OutputRegistry::from_config(&config));
+        cloud.set_metrics(metrics.clone());
+        if let Some(master_url) = &config.system.master_url {
+            match RestFallback::new(
+                master_url,
+                &config.system.client_id,
+                master_token.clone(),
+                config.cloud.proxy.as_ref(),
+            )
+            .and_then(|mut rest| {
+                // Otherwise blocking the pinned WebSocket would move everything onto an unpinned connection
+                if !config.cloud.spki_pins.is_empty() {
+                    rest.set_pins(&config.cloud.spki_pins, event_bus.clone())?;
+                }
+                Ok(rest)
+            }) {
+                Ok(rest) => {
+                    if let Some(rotator) = &mut rotator {
+                        rotator.set_rest_fallback(rest.clone());
+                    }
+                    let service_port = config.http.listen_addr.parse::<SocketAddr>().ok().map(|addr| addr.port());
+                    AddressReporter::new(rest.clone(), service_port, Duration::from_secs(config.network.report_s))
+                        .spawn(event_bus.subscribe());
+                    if config.health_report.enabled {
+                        health::HealthReporter::new(config.health_report.clone(), rest.clone(), app_state.clone()).spawn();
+                    }
+                    let (crashes, upload) = (crash_reporter.clone(), rest.clone());
+                    tokio::spawn(async move {
+                        if let Err(e) = crashes.upload(&upload).await {
+                            warn!(error = %e, "Crash reports kept for the next start");
+                        }
+                    });
+                    cloud.set_rest_fallback(rest);
+                    cloud.set_config_store(config_store.clone());
+                }
+                Err(e) => warn!(error = %e, "HTTPS fallback unavailable"),
+            }
+        }
+        if config.update.enabled {
+            match update::embedded_key()
+                .and_then(|key| Updater::new(config.update_dir(), key, &config.update, config.cloud.proxy.as_ref()))
+            {
+                Ok(updater) => cloud.set_updater(updater),
+                Err(e) => warn!(error = %e, "Agent updates unavailable"),
+            }
+        }
+        let mut secured = true;
+        match CommandVerifier::open(&config) {
+            Ok(verifier) => {
+                if !verifier.has_key() {
+                    warn!("No master command key provisioned, cloud commands are not verified");
+                }
+                cloud.set_command_verifier(verifier);
+            }
+            Err(e) => {
+                error!(error = %e, "Command keys unreadable, not connecting to the cloud");
+                secured = false;
+            }
+        }
+        if !config.cloud.spki_pins.is_empty() {
+            match pinned_connector(&config.cloud.spki_pins, event_bus.clone()) {
+                Ok(connector) => cloud.set_connector(connector),
+                Err(e) => {
+                    // Fail closed rather than connect without the pins
+                    error!(error = %e, "Invalid cloud.spki_pins, not connecting to the cloud");
+                    secured = false;
+                }
+            }
+        }
+        match EventQueue::open_or_recover(config.queue_path(), config.cloud.queue_max_events, config.cloud.queue_max_age_days) {
+            Ok((queue, recovery)) => {
+                if let Some(recovery) = recovery {
+                    warn!(
+                        moved_to = %recovery.moved_to.display(),
+                        salvaged = recovery.salvaged,
+                        unreadable = recovery.unreadable,
+                        "Offline queue was corrupt and has been recreated"
+                    );
+                    app_state.write().health.report_error(
+                        health::Component::Queue,
+                        health::HealthStatus::Degraded,
+                        format!("Recreated corrupt queue: {}", recovery.error),
+                    );
+                    if let Err(e) = event_bus.emit(recovery.event()) {
+                        warn!(error = %e, "Failed to emit queue recovery event");
+                    }
+                }
+                if let Some(monitor) = &mut storage_monitor {
+                    monitor.set_queue(queue.clone());
+                }
+                cloud.set_queue(QueueManager::new(queue, config.cloud.queue_batch));
+            }
+            Err(e) => warn!(error = %e, "Offline queue unavailable, events raised while disconnected will be lost"),
+        }
+        if secured {
+            supervisor.supervise("cloud", move || {
+                let cloud = cloud.clone();
+                async move {
+                    if let Err(e) = cloud.run().await {
+                        error!(error = %e, "Cloud client stopped");
+                    }
+                }
+            });
+        }
+    }
+
+    if let Some(rotator) = rotator {
+        tokio::spawn(rotator.run());
+    }
+
+    if let Some(monitor) = storage_monitor {
+        monitor.spawn();
+    }
+
     // Create HTTP API router
-    let app = api::create_router(app_state.clone(), event_bus.clone(), config.clone());
+    let app = api::create_router(api::ApiContext {
+        state: app_state.clone(),
+        event_bus: event_bus.clone(),
+        config: config.clone(),
+        pins: PinGuard::new(&config, app_state.clone(), event_bus.clone()),
+        journal,
+        dead_letters,
+        ble: Some(ble),
+        config_store: Some(config_store),
+        audit,
+        replay,
+        api_tokens,
+        metrics: Some(metrics),
+        history: history.map(|(history, _)| history),
+    });
+
+    // Startup is done; from here on the agent never needs the syscalls the filter refuses
+    if config.sandbox.enabled && config.sandbox.seccomp {
+        security::restrict_syscalls().context("Refusing to start without the configured sandbox")?;
+    }
 
     // Start HTTP server
-    let listener = tokio::net::TcpListener::bind(&config.http.listen_addr).await?;
     info!(addr = %config.http.listen_addr, "HTTP server listening");
+    health_monitor.watchdog().notify_ready();
+    health_monitor.spawn();
 
     // Run server with graceful shutdown
-    axum::serve(listener, app)
+    // Client addresses go into the audit log
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
         .with_graceful_shutdown(shutdown_signal(gpio_arc))
         .await?;
 
@@ -104,11 +626,16 @@ async fn main() -> anyhow::Result<()> {
 /// Command-line arguments parsed for the client agent.
 struct CliArgs {
     api_key: Option<String>,
+    provision_key: Option<String>,
+    /// Only install or roll back agent updates, then exit
+    install_update: bool,
 }
 
 impl CliArgs {
     fn parse() -> anyhow::Result<Self> {
         let mut api_key = None;
+        let mut provision_key = None;
+        let mut install_update = false;
         let mut args = env::args().skip(1);
 
         while let Some(arg) = args.next() {
@@ -119,6 +646,13 @@ impl CliArgs {
                         .ok_or_else(|| anyhow!("--api-key requires a value"))?;
                     api_key = Some(value);
                 }
+                "--provision-key" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--provision-key requires a value"))?;
+                    provision_key = Some(value);
+                }
+                "--install-update" => install_update = true,
                 "--help" | "-h" => {
                     print_usage();
                     process::exit(0);
@@ -133,12 +667,16 @@ impl CliArgs {
             }
         }
 
-        Ok(Self { api_key })
+        Ok(Self {
+            api_key,
+            provision_key,
+            install_update,
+        })
     }
 }
 
 fn print_usage() {
-    println!("Usage: pi-door-client [--api-key <uuid>]");
+    println!("Usage: pi-door-client [--api-key <uuid>] [--provision-key <uuid>] [--install-update]");
 }
 
 /// Wait for shutdown signal
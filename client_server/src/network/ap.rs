@@ -0,0 +1,348 @@
+//! Wi-Fi setup access point for installation and recovery
+//!
+//! When no configured network has been usable for `network.ap_fallback.after_s`,
+//! hostapd turns the Wi-Fi interface into an access point. dnsmasq hands out
+//! addresses on it and answers every name with the agent's address, so phones
+//! open the setup page as a captive portal. A network saved there is added to
+//! the wpa_supplicant configuration, and wpa_supplicant gets the interface back
+//! once the access point stops.
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Form, State},
+    response::Html,
+    routing::{get, post},
+    Router,
+};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use super::run;
+use super::settings::{save_wpa_network, WifiSettings};
+use crate::config::ApFallbackConfig;
+
+/// The setup page listens on this port of the access point address
+const PORTAL_PORT: u16 = 80;
+
+/// The setup access point and the processes behind it
+pub struct SetupAp {
+    config: ApFallbackConfig,
+    wpa_supplicant_conf: PathBuf,
+    ssid: String,
+    client_id: String,
+    /// Where hostapd and dnsmasq configurations are written
+    dir: PathBuf,
+    /// When the access point came up, while it runs
+    started: Option<Instant>,
+    running: Option<Running>,
+    network_saved: Arc<AtomicBool>,
+}
+
+struct Running {
+    hostapd: Child,
+    dnsmasq: Child,
+    portal: JoinHandle<()>,
+}
+
+impl SetupAp {
+    pub fn new(config: &ApFallbackConfig, wpa_supplicant_conf: PathBuf, client_id: &str, dir: PathBuf) -> Self {
+        Self {
+            config: config.clone(),
+            wpa_supplicant_conf,
+            ssid: config.ssid.replace("{client_id}", client_id),
+            client_id: client_id.to_string(),
+            dir,
+            started: None,
+            running: None,
+            network_saved: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn ssid(&self) -> &str {
+        &self.ssid
+    }
+
+    /// Interface taken over while the access point runs
+    pub fn interface(&self) -> &str {
+        &self.config.interface
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// Whether the access point should be up, given since when no uplink has been usable
+    pub fn wanted(&self, offline_since: Option<Instant>, now: Instant) -> bool {
+        let Some(offline_since) = offline_since else {
+            return false;
+        };
+        match self.started {
+            Some(started) => {
+                !self.network_saved.load(Ordering::SeqCst)
+                    && now.duration_since(started) < Duration::from_secs(self.config.retry_s)
+            }
+            None => now.duration_since(offline_since) >= Duration::from_secs(self.config.after_s),
+        }
+    }
+
+    /// Bring the access point up on the Wi-Fi interface
+    pub async fn start(&mut self) -> Result<()> {
+        if self.running.is_some() {
+            return Ok(());
+        }
+        self.network_saved.store(false, Ordering::SeqCst);
+        match self.launch().await {
+            Ok(running) => {
+                info!(ssid = %self.ssid, interface = %self.config.interface, "Wi-Fi setup access point started");
+                self.running = Some(running);
+                self.started = Some(Instant::now());
+                Ok(())
+            }
+            Err(e) => {
+                self.release_interface().await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn launch(&self) -> Result<Running> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let hostapd_conf = self.dir.join("hostapd.conf");
+        tokio::fs::write(&hostapd_conf, self.hostapd_conf())
+            .await
+            .with_context(|| format!("Failed to write {}", hostapd_conf.display()))?;
+        let dnsmasq_conf = self.dir.join("dnsmasq.conf");
+        tokio::fs::write(&dnsmasq_conf, self.dnsmasq_conf())
+            .await
+            .with_context(|| format!("Failed to write {}", dnsmasq_conf.display()))?;
+
+        let iface = self.config.interface.as_str();
+        // wpa_supplicant would fight hostapd over the interface
+        if let Err(e) = run("wpa_cli", &["-i", iface, "terminate"]).await {
+            debug!(error = %e, "wpa_supplicant was not running");
+        }
+        run("ip", &["addr", "flush", "dev", iface]).await?;
+        run("ip", &["addr", "add", &format!("{}/24", self.config.address), "dev", iface]).await?;
+        run("ip", &["link", "set", iface, "up"]).await?;
+
+        let hostapd = spawn("hostapd", &[&hostapd_conf.display().to_string()])?;
+        let dnsmasq = spawn(
+            "dnsmasq",
+            &["--keep-in-foreground", &format!("--conf-file={}", dnsmasq_conf.display())],
+        )?;
+        let listener = tokio::net::TcpListener::bind((self.config.address, PORTAL_PORT))
+            .await
+            .with_context(|| format!("Failed to listen on {}:{}", self.config.address, PORTAL_PORT))?;
+        let portal = Arc::new(Portal {
+            client_id: self.client_id.clone(),
+            ssid: self.ssid.clone(),
+            wpa_supplicant_conf: self.wpa_supplicant_conf.clone(),
+            network_saved: self.network_saved.clone(),
+        });
+        let portal = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, portal_router(portal)).await {
+                warn!(error = %e, "Setup page stopped");
+            }
+        });
+
+        Ok(Running { hostapd, dnsmasq, portal })
+    }
+
+    /// Take the access point down and give the interface back to wpa_supplicant,
+    /// returning whether a network was saved on the setup page meanwhile
+    pub async fn stop(&mut self) -> bool {
+        self.started = None;
+        let Some(mut running) = self.running.take() else {
+            return false;
+        };
+        running.portal.abort();
+        for child in [&mut running.hostapd, &mut running.dnsmasq] {
+            if let Err(e) = child.kill().await {
+                warn!(error = %e, "Failed to stop setup access point process");
+            }
+        }
+        self.release_interface().await;
+        let network_saved = self.network_saved.swap(false, Ordering::SeqCst);
+        info!(network_saved, "Wi-Fi setup access point stopped");
+        network_saved
+    }
+
+    async fn release_interface(&self) {
+        let iface = self.config.interface.as_str();
+        if let Err(e) = run("ip", &["addr", "flush", "dev", iface]).await {
+            warn!(error = %e, "Failed to remove setup access point address");
+        }
+        let conf = self.wpa_supplicant_conf.display().to_string();
+        if let Err(e) = run("wpa_supplicant", &["-B", "-i", iface, "-c", &conf]).await {
+            warn!(error = %e, "Failed to restart wpa_supplicant");
+        }
+    }
+
+    fn hostapd_conf(&self) -> String {
+        let mut conf = format!(
+            "interface={}\ndriver=nl80211\nssid={}\nhw_mode=g\nchannel={}\nauth_algs=1\nwmm_enabled=0\n",
+            self.config.interface, self.ssid, self.config.channel
+        );
+        if let Some(passphrase) = &self.config.passphrase {
+            conf.push_str(&format!(
+                "wpa=2\nwpa_key_mgmt=WPA-PSK\nrsn_pairwise=CCMP\nwpa_passphrase={}\n",
+                passphrase
+            ));
+        }
+        conf
+    }
+
+    /// Clients get addresses .100-.199 of the /24, and every name resolves to the agent
+    fn dnsmasq_conf(&self) -> String {
+        let [a, b, c, _] = self.config.address.octets();
+        format!(
+            "interface={iface}\nbind-interfaces\nexcept-interface=lo\nno-resolv\nno-hosts\n\
+             dhcp-range={a}.{b}.{c}.100,{a}.{b}.{c}.199,255.255.255.0,10m\n\
+             dhcp-option=option:router,{addr}\ndhcp-option=option:dns-server,{addr}\naddress=/#/{addr}\n",
+            iface = self.config.interface,
+            addr = self.config.address,
+        )
+    }
+}
+
+fn spawn(program: &str, args: &[&str]) -> Result<Child> {
+    Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start {}", program))
+}
+
+struct Portal {
+    client_id: String,
+    ssid: String,
+    wpa_supplicant_conf: PathBuf,
+    network_saved: Arc<AtomicBool>,
+}
+
+#[derive(Deserialize)]
+struct WifiForm {
+    ssid: String,
+    #[serde(default)]
+    psk: String,
+}
+
+/// Every path shows the setup page, which is what makes phones treat the network as a captive portal
+fn portal_router(portal: Arc<Portal>) -> Router {
+    Router::new()
+        .route("/wifi", post(save_wifi))
+        .fallback(get(setup_page))
+        .with_state(portal)
+}
+
+async fn setup_page(State(portal): State<Arc<Portal>>) -> Html<String> {
+    Html(page(
+        &portal,
+        "<form method=\"post\" action=\"/wifi\">\
+         <label>Wi-Fi network<br><input name=\"ssid\" maxlength=\"32\" required></label><br>\
+         <label>Password<br><input name=\"psk\" type=\"password\" maxlength=\"63\"></label><br>\
+         <button type=\"submit\">Connect</button></form>",
+    ))
+}
+
+async fn save_wifi(State(portal): State<Arc<Portal>>, Form(form): Form<WifiForm>) -> Html<String> {
+    let wifi = WifiSettings {
+        ssid: form.ssid,
+        psk: Some(form.psk).filter(|psk| !psk.is_empty()),
+        hidden: false,
+    };
+    let body = match save_wpa_network(&portal.wpa_supplicant_conf, &wifi).await {
+        Ok(_) => {
+            info!(ssid = %wifi.ssid, "Wi-Fi network saved on the setup page");
+            portal.network_saved.store(true, Ordering::SeqCst);
+            format!(
+                "<p>Saved. {} closes this network now and joins {}.</p>",
+                escape(&portal.client_id),
+                escape(&wifi.ssid)
+            )
+        }
+        Err(e) => format!("<p>Not saved: {}</p><p><a href=\"/\">Back</a></p>", escape(&format!("{:#}", e))),
+    };
+    Html(page(&portal, &body))
+}
+
+fn page(portal: &Portal, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{ssid}</title></head><body><h1>Door controller setup</h1>\
+         <p>Client {client}</p>{body}</body></html>",
+        ssid = escape(&portal.ssid),
+        client = escape(&portal.client_id),
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_ap(passphrase: Option<&str>) -> SetupAp {
+        let config = ApFallbackConfig {
+            enabled: true,
+            passphrase: passphrase.map(str::to_string),
+            ..Default::default()
+        };
+        SetupAp::new(&config, PathBuf::from("/tmp/wpa_supplicant.conf"), "door-1", PathBuf::from("/tmp/setup-ap"))
+    }
+
+    #[test]
+    fn test_generated_configs() {
+        let ap = setup_ap(None);
+        assert_eq!(ap.ssid(), "pi-door-door-1");
+        assert!(ap.hostapd_conf().contains("ssid=pi-door-door-1\n"));
+        assert!(!ap.hostapd_conf().contains("wpa="));
+        assert!(setup_ap(Some("door-setup-2024")).hostapd_conf().contains("wpa_passphrase=door-setup-2024\n"));
+
+        let dnsmasq = ap.dnsmasq_conf();
+        assert!(dnsmasq.contains("dhcp-range=192.168.4.100,192.168.4.199,255.255.255.0,10m\n"));
+        assert!(dnsmasq.contains("address=/#/192.168.4.1\n"));
+    }
+
+    #[test]
+    fn test_start_and_stop_timing() {
+        let mut ap = setup_ap(None);
+        let now = Instant::now();
+        let at = |s| now + Duration::from_secs(s);
+
+        assert!(!ap.wanted(None, at(1000)));
+        assert!(!ap.wanted(Some(now), at(299)));
+        assert!(ap.wanted(Some(now), at(300)));
+
+        // Up until an uplink returns, the retry time passes or a network is saved
+        ap.started = Some(at(300));
+        assert!(ap.wanted(Some(now), at(600)));
+        assert!(!ap.wanted(None, at(600)));
+        assert!(!ap.wanted(Some(now), at(1200)));
+        ap.network_saved.store(true, Ordering::SeqCst);
+        assert!(!ap.wanted(Some(now), at(600)));
+    }
+
+    #[test]
+    fn test_page_escapes_input() {
+        assert_eq!(escape("<b>\"a\" & 'b'</b>"), "&lt;b&gt;&quot;a&quot; &amp; &#39;b&#39;&lt;/b&gt;");
+    }
+}
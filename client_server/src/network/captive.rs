@@ -0,0 +1,220 @@
+//! Captive portal detection on the Wi-Fi interfaces
+//!
+//! A hotel or guest Wi-Fi is up with a carrier, yet answers everything with
+//! its login page. Every `network.captive_portal.interval_s` each Wi-Fi
+//! interface that is up fetches `probe_url` over itself; anything but the
+//! expected `204 No Content` marks it as behind a portal, which keeps it from
+//! carrying the uplink while another interface is up and raises
+//! `captive_portal_detected`, so it is clear why the cloud is offline.
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+use super::{connect_via, LinkKind};
+use crate::config::CaptivePortalConfig;
+use crate::events::Event;
+
+/// Longest response head read from the probe URL
+const MAX_RESPONSE_HEAD: usize = 8192;
+/// Time allowed for one probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the probe URL answered over an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Probe {
+    Online,
+    /// Intercepted, with where the portal redirected to if it did
+    Portal { location: Option<String> },
+}
+
+impl Probe {
+    /// Judge a response by its status line and `Location` header
+    pub fn classify(status: u16, location: Option<&str>) -> Self {
+        match status {
+            204 => Probe::Online,
+            _ => Probe::Portal {
+                location: location.map(str::to_string),
+            },
+        }
+    }
+}
+
+/// Fetch `url` over `interface`, without following redirects
+pub async fn probe(interface: &str, url: &str) -> Result<Probe> {
+    let url = reqwest::Url::parse(url).with_context(|| format!("Invalid probe URL: {}", url))?;
+    if url.scheme() != "http" {
+        bail!("Probe URL must be plain http, a portal cannot answer https");
+    }
+    let host = url.host_str().context("Probe URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let mut stream = connect_via(Some(interface), (host, port)).await?;
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: pi-door-client\r\nConnection: close\r\n\r\n",
+        url.path(),
+        host
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_RESPONSE_HEAD {
+            bail!("Oversized response to the probe");
+        }
+        head.push(stream.read_u8().await.context("Connection closed during the probe")?);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse().ok())
+        .context("Invalid status line in the probe response")?;
+    let location = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim().eq_ignore_ascii_case("location").then(|| value.trim())
+    });
+    Ok(Probe::classify(status, location))
+}
+
+#[derive(Debug)]
+struct Verdict {
+    captive: bool,
+    probed: Instant,
+}
+
+/// Latest probe outcome of each Wi-Fi interface that is up
+pub struct CaptivePortalDetector {
+    url: String,
+    interval: Duration,
+    verdicts: BTreeMap<String, Verdict>,
+}
+
+impl CaptivePortalDetector {
+    pub fn new(config: &CaptivePortalConfig) -> Self {
+        Self {
+            url: config.probe_url.clone(),
+            interval: Duration::from_secs(config.interval_s),
+            verdicts: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_captive(&self, interface: &str) -> bool {
+        self.verdicts.get(interface).is_some_and(|verdict| verdict.captive)
+    }
+
+    /// Probe the Wi-Fi interfaces among `up` that are due, returning events for portals found or gone
+    pub async fn check(&mut self, up: &[String], now: Instant) -> Vec<Event> {
+        // An interface that went down is probed afresh when it returns
+        self.verdicts.retain(|interface, _| up.contains(interface));
+
+        let mut events = Vec::new();
+        for interface in up.iter().filter(|name| LinkKind::of(name) == Some(LinkKind::Wifi)) {
+            let fresh = self
+                .verdicts
+                .get(interface)
+                .is_some_and(|verdict| now.duration_since(verdict.probed) < self.interval);
+            if fresh {
+                continue;
+            }
+            let result = match timeout(PROBE_TIMEOUT, probe(interface, &self.url)).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow::anyhow!("Probe timed out")),
+            };
+            match result {
+                Ok(probe) => events.extend(self.record(interface, probe, now)),
+                Err(e) => {
+                    // No answer at all is not a portal; failover handles a dead link
+                    debug!(%interface, error = %e, "Captive portal probe failed");
+                    if let Some(verdict) = self.verdicts.get_mut(interface) {
+                        verdict.probed = now;
+                    }
+                }
+            }
+        }
+        events
+    }
+
+    fn record(&mut self, interface: &str, probe: Probe, now: Instant) -> Option<Event> {
+        let captive = probe != Probe::Online;
+        let was_captive = self.is_captive(interface);
+        self.verdicts.insert(interface.to_string(), Verdict { captive, probed: now });
+        match (was_captive, probe) {
+            (false, Probe::Portal { location }) => {
+                warn!(%interface, ?location, "Interface is behind a captive portal");
+                Some(Event::CaptivePortalDetected {
+                    interface: interface.to_string(),
+                    location,
+                })
+            }
+            (true, Probe::Online) => {
+                info!(%interface, "Captive portal cleared");
+                Some(Event::CaptivePortalCleared {
+                    interface: interface.to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    async fn serve(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).await;
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://{}/generate_204", addr)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_probe_spots_redirect() {
+        let online = serve("HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n").await;
+        assert_eq!(probe("lo", &online).await.unwrap(), Probe::Online);
+
+        let portal = serve("HTTP/1.1 302 Found\r\nlocation: http://login.hotel/\r\nContent-Length: 0\r\n\r\n").await;
+        assert_eq!(
+            probe("lo", &portal).await.unwrap(),
+            Probe::Portal {
+                location: Some("http://login.hotel/".to_string())
+            }
+        );
+        assert!(probe("lo", "https://example.com/").await.is_err());
+    }
+
+    #[test]
+    fn test_verdict_changes_raise_events() {
+        let mut detector = CaptivePortalDetector::new(&CaptivePortalConfig::default());
+        let now = Instant::now();
+        let portal = || Probe::Portal { location: None };
+
+        assert!(detector.record("wlan0", Probe::Online, now).is_none());
+        assert!(matches!(
+            detector.record("wlan0", portal(), now),
+            Some(Event::CaptivePortalDetected { .. })
+        ));
+        assert!(detector.record("wlan0", portal(), now).is_none());
+        assert!(detector.is_captive("wlan0"));
+        assert!(matches!(
+            detector.record("wlan0", Probe::Online, now),
+            Some(Event::CaptivePortalCleared { .. })
+        ));
+        assert!(!detector.is_captive("wlan0"));
+        assert_eq!(Probe::classify(200, None), portal());
+    }
+}
@@ -0,0 +1,257 @@
+//! Scripted network checks for installers
+//!
+//! `POST /v1/network/diagnose` walks the path to the master one step at a
+//! time: the interfaces, the default route, the gateway, name resolution,
+//! a connection to the master and its latency. Each step reports pass, fail
+//! or skip with a readable detail, so "it says offline" can be narrowed down
+//! from a phone instead of on site.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::lookup_host;
+use tokio::time::{timeout, Instant};
+
+use super::{connect_via, interface_ipv4, output};
+use crate::config::AppConfig;
+
+/// Longest any single check may take
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Connections opened to measure latency to the master
+const LATENCY_SAMPLES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    /// An earlier step failed or the check does not apply
+    Skip,
+}
+
+/// Outcome of one step
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticReport {
+    /// No check failed
+    pub ok: bool,
+    /// Interface the uplink currently uses
+    pub uplink: Option<String>,
+    /// Host and port the master checks ran against
+    pub target: Option<String>,
+    pub started: DateTime<Utc>,
+    pub checks: Vec<Check>,
+}
+
+/// Run `check` with a deadline, turning its result into a [`Check`]
+async fn run_check(name: impl Into<String>, check: impl Future<Output = Result<String>>) -> Check {
+    let started = Instant::now();
+    let (status, detail) = match timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(detail)) => (CheckStatus::Pass, detail),
+        Ok(Err(e)) => (CheckStatus::Fail, format!("{:#}", e)),
+        Err(_) => (CheckStatus::Fail, format!("No answer within {} s", CHECK_TIMEOUT.as_secs())),
+    };
+    Check {
+        name: name.into(),
+        status,
+        detail,
+        duration_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+fn skipped(name: &str, detail: &str) -> Check {
+    Check {
+        name: name.to_string(),
+        status: CheckStatus::Skip,
+        detail: detail.to_string(),
+        duration_ms: 0,
+    }
+}
+
+/// Default route in the text of `/proc/net/route`: interface and gateway
+pub fn parse_default_route(text: &str) -> Option<(String, Ipv4Addr)> {
+    text.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (interface, destination, gateway, flags, mask) =
+            (fields.first()?, fields.get(1)?, fields.get(2)?, fields.get(3)?, fields.get(7)?);
+        let up = u16::from_str_radix(flags, 16).ok()? & 0x1 != 0;
+        if !up || *destination != "00000000" || *mask != "00000000" {
+            return None;
+        }
+        // Addresses are printed as the kernel's native-endian u32
+        let gateway = u32::from_str_radix(gateway, 16).ok()?;
+        Some((interface.to_string(), Ipv4Addr::from(gateway.to_ne_bytes())))
+    })
+}
+
+/// Host and port of the master, or of the first cloud endpoint without one
+pub fn target(config: &AppConfig) -> Option<(String, u16)> {
+    let url = config.system.master_url.as_deref().or(config.cloud.url.first().map(String::as_str))?;
+    let url = reqwest::Url::parse(url).ok()?;
+    let port = url.port_or_known_default().or(match url.scheme() {
+        "wss" => Some(443),
+        "ws" => Some(80),
+        _ => None,
+    })?;
+    Some((url.host_str()?.trim_start_matches('[').trim_end_matches(']').to_string(), port))
+}
+
+async fn interface_check(name: &str) -> Result<String> {
+    let read = |file: &str| std::fs::read_to_string(format!("/sys/class/net/{}/{}", name, file));
+    let operstate = read("operstate").context("No such interface")?;
+    let carrier = read("carrier").map(|carrier| carrier.trim() == "1").unwrap_or(false);
+    let address = interface_ipv4(name).map_or("no IPv4 address".to_string(), |ip| ip.to_string());
+    let detail = format!("{}, {}, {}", operstate.trim(), if carrier { "carrier" } else { "no carrier" }, address);
+    if operstate.trim() != "up" || !carrier {
+        bail!(detail);
+    }
+    Ok(detail)
+}
+
+/// Run every check against the configured master, over `uplink` when one is selected
+pub async fn diagnose(config: &AppConfig, uplink: Option<String>) -> DiagnosticReport {
+    let started = Utc::now();
+    let mut checks = Vec::new();
+
+    for name in &config.network.prefer {
+        checks.push(run_check(format!("interface {}", name), interface_check(name)).await);
+    }
+
+    let route = tokio::fs::read_to_string("/proc/net/route")
+        .await
+        .ok()
+        .and_then(|text| parse_default_route(&text));
+    checks.push(
+        run_check("default_route", async {
+            let (interface, gateway) = route.clone().context("No default route")?;
+            Ok(format!("via {} dev {}", gateway, interface))
+        })
+        .await,
+    );
+    match &route {
+        Some((_, gateway)) => {
+            let gateway = gateway.to_string();
+            checks.push(
+                run_check("gateway", async {
+                    output("ping", &["-c", "1", "-W", "2", &gateway]).await?;
+                    Ok(format!("{} answers ping", gateway))
+                })
+                .await,
+            );
+        }
+        None => checks.push(skipped("gateway", "No default route")),
+    }
+
+    let target = target(config);
+    let resolved = match &target {
+        Some((host, port)) => {
+            let mut addresses: Vec<SocketAddr> = Vec::new();
+            let check = run_check("dns", async {
+                addresses = lookup_host((host.as_str(), *port))
+                    .await
+                    .with_context(|| format!("Failed to resolve {}", host))?
+                    .collect();
+                let list: Vec<String> = addresses.iter().map(|addr| addr.ip().to_string()).collect();
+                Ok(format!("{} is {}", host, list.join(", ")))
+            })
+            .await;
+            checks.push(check);
+            addresses.first().copied()
+        }
+        None => {
+            checks.push(skipped("dns", "Neither system.master_url nor cloud.url is set"));
+            None
+        }
+    };
+
+    match resolved {
+        Some(addr) => {
+            let interface = uplink.as_deref();
+            let mut samples = Vec::new();
+            checks.push(
+                run_check("master", async {
+                    for _ in 0..LATENCY_SAMPLES {
+                        let started = Instant::now();
+                        connect_via(interface, addr).await?;
+                        samples.push(started.elapsed().as_secs_f64() * 1000.0);
+                    }
+                    Ok(format!("{} accepts connections", addr))
+                })
+                .await,
+            );
+            if samples.is_empty() {
+                checks.push(skipped("latency", "No connection to the master"));
+            } else {
+                checks.push(
+                    run_check("latency", async {
+                        let min = samples.iter().copied().fold(f64::INFINITY, f64::min);
+                        let max = samples.iter().copied().fold(0.0, f64::max);
+                        let avg = samples.iter().sum::<f64>() / samples.len() as f64;
+                        Ok(format!("connect min/avg/max {:.1}/{:.1}/{:.1} ms", min, avg, max))
+                    })
+                    .await,
+                );
+            }
+        }
+        None => {
+            checks.push(skipped("master", "Master address unknown"));
+            checks.push(skipped("latency", "Master address unknown"));
+        }
+    }
+
+    DiagnosticReport {
+        ok: checks.iter().all(|check| check.status != CheckStatus::Fail),
+        uplink,
+        target: target.map(|(host, port)| format!("{}:{}", host, port)),
+        started,
+        checks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_default_route() {
+        let table = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+            eth0\t0001A8C0\t00000000\t0001\t0\t0\t202\t00FFFFFF\t0\t0\t0\n\
+            wlan0\t00000000\t0101A8C0\t0003\t0\t0\t303\t00000000\t0\t0\t0\n";
+        let gateway = Ipv4Addr::from(u32::from_str_radix("0101A8C0", 16).unwrap().to_ne_bytes());
+        assert_eq!(parse_default_route(table), Some(("wlan0".to_string(), gateway)));
+        if cfg!(target_endian = "little") {
+            assert_eq!(gateway, Ipv4Addr::new(192, 168, 1, 1));
+        }
+        assert_eq!(parse_default_route(table.lines().take(2).collect::<Vec<_>>().join("\n").as_str()), None);
+
+        let mut config = AppConfig::test_default();
+        assert_eq!(target(&config), None);
+        config.cloud.url = vec!["wss://cloud.example.com/client".to_string()];
+        assert_eq!(target(&config), Some(("cloud.example.com".to_string(), 443)));
+        config.system.master_url = Some("http://10.0.0.5:8000/".to_string());
+        assert_eq!(target(&config), Some(("10.0.0.5".to_string(), 8000)));
+    }
+
+    #[tokio::test]
+    async fn test_failed_step_skips_the_rest() {
+        let mut config = AppConfig::test_default();
+        config.network.prefer = vec!["no-such-if0".to_string()];
+        let report = diagnose(&config, None).await;
+        assert!(!report.ok);
+        assert_eq!(report.checks[0].name, "interface no-such-if0");
+        assert_eq!(report.checks[0].status, CheckStatus::Fail);
+        let dns = report.checks.iter().find(|check| check.name == "dns").unwrap();
+        assert_eq!(dns.status, CheckStatus::Skip);
+        assert!(report.checks.iter().any(|check| check.name == "latency" && check.status == CheckStatus::Skip));
+    }
+}
@@ -0,0 +1,186 @@
+//! Up/down history of the interfaces, for failover that does not chase flapping links
+//!
+//! An interface that just came up is `settling` until it has stayed up for
+//! `network.failover.hold_down_s`, and one that changed state
+//! `flap_threshold` times within `flap_window_s` is `flapping`. Only healthy
+//! interfaces take traffic over from the current one, so a bouncing wlan0
+//! does not cause a failover and a cloud reconnect every few seconds. One
+//! behind a captive portal has no internet at all and is only used when
+//! nothing else is up.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::config::FailoverConfig;
+use crate::events::Event;
+
+/// How usable an interface is for failover
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterfaceStatus {
+    Down,
+    /// Up, but the internet is behind a login page
+    CaptivePortal,
+    /// Changed state too often lately
+    Flapping,
+    /// Up, but not for `hold_down_s` yet
+    Settling,
+    Healthy,
+}
+
+/// Health of one interface, as published in the shared state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InterfaceHealth {
+    pub interface: String,
+    pub status: InterfaceStatus,
+    /// 0 when down up to 100 for a healthy interface without recent changes
+    pub score: u8,
+    /// Up/down changes within `flap_window_s`
+    pub recent_changes: usize,
+    /// Times the interface went down since the agent started
+    pub flaps: u64,
+}
+
+#[derive(Debug, Default)]
+struct History {
+    observed: bool,
+    up_since: Option<Instant>,
+    changes: VecDeque<Instant>,
+    flaps: u64,
+}
+
+/// Up/down history of every interface in `network.prefer`
+#[derive(Debug)]
+pub struct InterfaceTracker {
+    hold_down: Duration,
+    flap_window: Duration,
+    flap_threshold: usize,
+    history: BTreeMap<String, History>,
+    captive: BTreeSet<String>,
+}
+
+impl InterfaceTracker {
+    pub fn new(config: &FailoverConfig) -> Self {
+        Self {
+            hold_down: Duration::from_secs(config.hold_down_s),
+            flap_window: Duration::from_secs(config.flap_window_s),
+            flap_threshold: config.flap_threshold as usize,
+            history: BTreeMap::new(),
+            captive: BTreeSet::new(),
+        }
+    }
+
+    /// Mark `interface` as behind a captive portal, or no longer
+    pub fn set_captive(&mut self, interface: &str, captive: bool) {
+        if captive {
+            self.captive.insert(interface.to_string());
+        } else {
+            self.captive.remove(interface);
+        }
+    }
+
+    /// Record whether `interface` is up, returning an event when it starts flapping
+    pub fn observe(&mut self, interface: &str, up: bool, now: Instant) -> Option<Event> {
+        let history = self.history.entry(interface.to_string()).or_default();
+        let was_flapping = history.changes.len() >= self.flap_threshold;
+        match (history.up_since.is_some(), up) {
+            // The state found at startup is not a change
+            (false, true) if !history.observed => history.up_since = Some(now),
+            (false, true) => {
+                history.up_since = Some(now);
+                history.changes.push_back(now);
+            }
+            (true, false) => {
+                history.up_since = None;
+                history.changes.push_back(now);
+                history.flaps += 1;
+            }
+            _ => {}
+        }
+        history.observed = true;
+        while history
+            .changes
+            .front()
+            .is_some_and(|change| now.duration_since(*change) > self.flap_window)
+        {
+            history.changes.pop_front();
+        }
+
+        let flapping = history.changes.len() >= self.flap_threshold;
+        (flapping && !was_flapping).then(|| Event::NetworkInterfaceFlapping {
+            interface: interface.to_string(),
+            changes: history.changes.len() as u32,
+        })
+    }
+
+    pub fn status(&self, interface: &str, now: Instant) -> InterfaceStatus {
+        let Some(history) = self.history.get(interface) else {
+            return InterfaceStatus::Down;
+        };
+        match history.up_since {
+            None => InterfaceStatus::Down,
+            Some(_) if self.captive.contains(interface) => InterfaceStatus::CaptivePortal,
+            Some(_) if history.changes.len() >= self.flap_threshold => InterfaceStatus::Flapping,
+            Some(since) if now.duration_since(since) < self.hold_down => InterfaceStatus::Settling,
+            Some(_) => InterfaceStatus::Healthy,
+        }
+    }
+
+    /// Health of `interfaces`, in the order given
+    pub fn report(&self, interfaces: &[String], now: Instant) -> Vec<InterfaceHealth> {
+        interfaces
+            .iter()
+            .map(|interface| {
+                let history = self.history.get(interface);
+                let recent_changes = history.map_or(0, |history| history.changes.len());
+                let status = self.status(interface, now);
+                let score = match status {
+                    InterfaceStatus::Down => 0,
+                    InterfaceStatus::CaptivePortal => 10,
+                    InterfaceStatus::Flapping => 25,
+                    InterfaceStatus::Settling => 50,
+                    InterfaceStatus::Healthy => 100u8.saturating_sub(10 * recent_changes.min(4) as u8),
+                };
+                InterfaceHealth {
+                    interface: interface.clone(),
+                    status,
+                    score,
+                    recent_changes,
+                    flaps: history.map_or(0, |history| history.flaps),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flapping_interface_detected() {
+        let mut tracker = InterfaceTracker::new(&FailoverConfig::default());
+        let start = Instant::now();
+        let at = |s| start + Duration::from_secs(s);
+
+        assert!(tracker.observe("wlan0", true, at(0)).is_none());
+        // Up at startup is not a change, but still settles first
+        assert_eq!(tracker.status("wlan0", at(10)), InterfaceStatus::Settling);
+        assert_eq!(tracker.status("wlan0", at(30)), InterfaceStatus::Healthy);
+
+        let mut events = Vec::new();
+        for (i, up) in [false, true, false, true].into_iter().enumerate() {
+            events.extend(tracker.observe("wlan0", up, at(40 + 5 * i as u64)));
+        }
+        assert!(matches!(&events[..], [Event::NetworkInterfaceFlapping { changes: 4, .. }]));
+        assert_eq!(tracker.status("wlan0", at(60)), InterfaceStatus::Flapping);
+
+        // Changes age out of the window, then the hold-down still applies
+        tracker.observe("wlan0", true, at(400));
+        let report = tracker.report(&["wlan0".to_string(), "eth0".to_string()], at(400));
+        assert_eq!(report[0].status, InterfaceStatus::Healthy);
+        assert_eq!((report[0].flaps, report[0].recent_changes), (2, 0));
+        assert_eq!((report[1].status, report[1].score), (InterfaceStatus::Down, 0));
+    }
+}
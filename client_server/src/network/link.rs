@@ -0,0 +1,265 @@
+//! Radio link quality of the Wi-Fi and LTE interfaces
+//!
+//! Wi-Fi signal and bitrate come from `iw dev <if> link`, LTE signal from
+//! ModemManager (`mmcli`). Readings land in the shared state, from where they
+//! are served by `/v1/network` and `/metrics` and sent in heartbeats, so the
+//! master can flag clients whose radio link is marginal.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+use super::output;
+use crate::config::{LinkQualityConfig, NetworkConfig};
+use crate::state::AppState;
+
+/// Radio technology of a link
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    Wifi,
+    Lte,
+}
+
+impl LinkKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkKind::Wifi => "wifi",
+            LinkKind::Lte => "lte",
+        }
+    }
+
+    /// Kind of a radio interface by its name; `None` for wired ones
+    pub fn of(interface: &str) -> Option<Self> {
+        if interface.starts_with("wl") {
+            Some(LinkKind::Wifi)
+        } else if interface.starts_with("wwan") || interface.starts_with("ppp") {
+            Some(LinkKind::Lte)
+        } else {
+            None
+        }
+    }
+}
+
+/// Latest reading of one radio interface
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkQuality {
+    pub interface: String,
+    pub kind: LinkKind,
+    /// Associated with an access point or registered with a cell
+    pub connected: bool,
+    /// Wi-Fi signal, or LTE RSSI, in dBm
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal_dbm: Option<f64>,
+    /// Wi-Fi transmit bitrate in Mbit/s
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitrate_mbps: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_mhz: Option<u32>,
+    /// LTE signal quality from 0 to 100, as ModemManager reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality_pct: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rsrp_dbm: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rsrq_db: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snr_db: Option<f64>,
+    /// Access technology of the cell, e.g. `lte`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_tech: Option<String>,
+    /// Below the `network.link_quality` thresholds
+    pub marginal: bool,
+    pub updated: DateTime<Utc>,
+}
+
+impl LinkQuality {
+    fn new(interface: &str, kind: LinkKind) -> Self {
+        Self {
+            interface: interface.to_string(),
+            kind,
+            connected: false,
+            signal_dbm: None,
+            bitrate_mbps: None,
+            ssid: None,
+            frequency_mhz: None,
+            quality_pct: None,
+            rsrp_dbm: None,
+            rsrq_db: None,
+            snr_db: None,
+            access_tech: None,
+            marginal: false,
+            updated: Utc::now(),
+        }
+    }
+
+    /// Judge the reading against the thresholds; a link that is down is not marginal, just down
+    fn assess(mut self, config: &LinkQualityConfig) -> Self {
+        self.marginal = self.connected
+            && match self.kind {
+                LinkKind::Wifi => self.signal_dbm.is_some_and(|dbm| dbm < config.marginal_wifi_dbm),
+                LinkKind::Lte => self.rsrp_dbm.is_some_and(|dbm| dbm < config.marginal_lte_rsrp_dbm),
+            };
+        self
+    }
+}
+
+/// First number in a value such as `-52 dBm` or `72.2 MBit/s MCS 7`
+fn leading_number(value: &str) -> Option<f64> {
+    value.split_whitespace().next()?.parse().ok()
+}
+
+/// Parse the output of `iw dev <if> link`
+///
+/// ```text
+/// Connected to aa:bb:cc:dd:ee:ff (on wlan0)
+///     SSID: Home
+///     freq: 2437
+///     signal: -52 dBm
+///     tx bitrate: 72.2 MBit/s MCS 7 short GI
+/// ```
+pub fn parse_iw_link(interface: &str, text: &str) -> LinkQuality {
+    let mut link = LinkQuality::new(interface, LinkKind::Wifi);
+    link.connected = text.trim_start().starts_with("Connected to");
+    for line in text.lines() {
+        let Some((key, value)) = line.trim().split_once(": ") else {
+            continue;
+        };
+        match key {
+            "SSID" => link.ssid = Some(value.to_string()),
+            "freq" => link.frequency_mhz = leading_number(value).map(|mhz| mhz as u32),
+            "signal" => link.signal_dbm = leading_number(value),
+            "tx bitrate" => link.bitrate_mbps = leading_number(value),
+            _ => {}
+        }
+    }
+    link
+}
+
+/// Parse `mmcli -m any -K` and `mmcli -m any --signal-get -K`, key-value lines such as
+/// `modem.signal.lte.rsrp : -95.00`, where `--` means unknown
+pub fn parse_mmcli(interface: &str, modem: &str, signal: &str) -> LinkQuality {
+    let mut link = LinkQuality::new(interface, LinkKind::Lte);
+    for line in modem.lines().chain(signal.lines()) {
+        let Some((key, value)) = line.split_once(" : ") else {
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if value == "--" || value.is_empty() {
+            continue;
+        }
+        match key {
+            "modem.generic.state" => link.connected = matches!(value, "registered" | "connected"),
+            "modem.generic.signal-quality.value" => link.quality_pct = value.parse().ok(),
+            "modem.generic.access-technologies.value[1]" => link.access_tech = Some(value.to_string()),
+            "modem.signal.lte.rssi" => link.signal_dbm = value.parse().ok(),
+            "modem.signal.lte.rsrp" => link.rsrp_dbm = value.parse().ok(),
+            "modem.signal.lte.rsrq" => link.rsrq_db = value.parse().ok(),
+            "modem.signal.lte.snr" => link.snr_db = value.parse().ok(),
+            _ => {}
+        }
+    }
+    link
+}
+
+/// Periodically reads the radio interfaces in `network.prefer`
+pub struct LinkMonitor {
+    interfaces: Vec<(String, LinkKind)>,
+    config: LinkQualityConfig,
+    state: AppState,
+}
+
+impl LinkMonitor {
+    pub fn new(config: &NetworkConfig, state: AppState) -> Self {
+        let interfaces = config
+            .prefer
+            .iter()
+            .filter_map(|name| Some((name.clone(), LinkKind::of(name)?)))
+            .collect();
+        Self {
+            interfaces,
+            config: config.link_quality.clone(),
+            state,
+        }
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move { self.run().await })
+    }
+
+    async fn run(self) {
+        if self.interfaces.is_empty() {
+            return;
+        }
+        info!(interfaces = ?self.interfaces, "Link quality monitor started");
+        let interval_s = self.config.interval_s;
+        if self.interfaces.iter().any(|(_, kind)| *kind == LinkKind::Lte) {
+            // ModemManager only reports RSRP and the like once polling is set up
+            let refresh = format!("--signal-setup={}", interval_s);
+            if let Err(e) = output("mmcli", &["-m", "any", &refresh]).await {
+                debug!(error = %e, "Failed to set up modem signal polling");
+            }
+        }
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_s));
+        loop {
+            ticker.tick().await;
+            let mut links = Vec::new();
+            for (interface, kind) in &self.interfaces {
+                match self.read(interface, *kind).await {
+                    Ok(link) => links.push(link.assess(&self.config)),
+                    Err(e) => debug!(%interface, error = %e, "Failed to read link quality"),
+                }
+            }
+            self.state.write().link_quality = links;
+        }
+    }
+
+    async fn read(&self, interface: &str, kind: LinkKind) -> anyhow::Result<LinkQuality> {
+        match kind {
+            LinkKind::Wifi => Ok(parse_iw_link(interface, &output("iw", &["dev", interface, "link"]).await?)),
+            LinkKind::Lte => {
+                let modem = output("mmcli", &["-m", "any", "-K"]).await?;
+                let signal = output("mmcli", &["-m", "any", "--signal-get", "-K"]).await.unwrap_or_default();
+                Ok(parse_mmcli(interface, &modem, &signal))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_iw_link() {
+        let text = "Connected to aa:bb:cc:dd:ee:ff (on wlan0)\n\tSSID: Home Net\n\tfreq: 2437\n\tRX: 1234 bytes (10 packets)\n\tsignal: -78 dBm\n\trx bitrate: 65.0 MBit/s\n\ttx bitrate: 72.2 MBit/s MCS 7 short GI\n";
+        let link = parse_iw_link("wlan0", text).assess(&LinkQualityConfig::default());
+        assert!(link.connected);
+        assert_eq!(link.ssid.as_deref(), Some("Home Net"));
+        assert_eq!(link.frequency_mhz, Some(2437));
+        assert_eq!(link.signal_dbm, Some(-78.0));
+        assert_eq!(link.bitrate_mbps, Some(72.2));
+        assert!(link.marginal);
+
+        let link = parse_iw_link("wlan0", "Not connected.\n").assess(&LinkQualityConfig::default());
+        assert!(!link.connected && !link.marginal);
+    }
+
+    #[test]
+    fn test_parse_mmcli() {
+        let modem = "modem.generic.state                         : connected\nmodem.generic.signal-quality.value          : 67\nmodem.generic.access-technologies.value[1]  : lte\n";
+        let signal = "modem.signal.lte.rssi : -65.00\nmodem.signal.lte.rsrq : -10.00\nmodem.signal.lte.rsrp : -95.00\nmodem.signal.lte.snr  : --\n";
+        let link = parse_mmcli("wwan0", modem, signal).assess(&LinkQualityConfig::default());
+        assert!(link.connected);
+        assert_eq!(link.quality_pct, Some(67));
+        assert_eq!(link.access_tech.as_deref(), Some("lte"));
+        assert_eq!((link.signal_dbm, link.rsrp_dbm, link.rsrq_db, link.snr_db), (Some(-65.0), Some(-95.0), Some(-10.0), None));
+        assert!(!link.marginal);
+        assert_eq!(LinkKind::of("eth0"), None);
+    }
+}
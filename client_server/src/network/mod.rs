@@ -1,9 +1,41 @@
 //! Network redundancy manager for interface selection and failover
 
-use std::time::Duration;
+pub mod ap;
+pub mod captive;
+pub mod diagnose;
+pub mod health;
+pub mod link;
+#[cfg(target_os = "linux")]
+pub mod netlink;
+pub mod settings;
+
+pub use ap::SetupAp;
+pub use captive::CaptivePortalDetector;
+pub use diagnose::{diagnose, DiagnosticReport};
+pub use health::{InterfaceHealth, InterfaceStatus, InterfaceTracker};
+pub use link::{LinkKind, LinkMonitor, LinkQuality};
+pub use settings::{confirm_or_roll_back, IpSettings, NetworkSettings, PendingNetworkChange, WifiSettings};
+
+use crate::config::{CaptivePortalConfig, FailoverConfig};
+use crate::events::{Event, EventBus};
+use crate::state::AppState;
+use anyhow::{bail, Context, Result};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::net::{lookup_host, TcpSocket, TcpStream, ToSocketAddrs};
+use tokio::process::Command;
 use tokio::time::{interval, sleep};
 use tracing::{debug, info, warn};
 
+/// Interface check period without netlink
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Interface check period between netlink notifications
+#[cfg(target_os = "linux")]
+const RESCAN_INTERVAL: Duration = Duration::from_secs(15);
+/// Wait after a notification for the rest of its burst
+#[cfg(target_os = "linux")]
+const SETTLE_DELAY: Duration = Duration::from_millis(250);
+
 /// Network interface information
 #[derive(Debug, Clone, PartialEq)]
 pub struct NetworkInterface {
@@ -25,6 +57,18 @@ pub struct NetworkManager {
     preferred_interfaces: Vec<String>,
     current_interface: Option<String>,
     connectivity_status: ConnectivityStatus,
+    /// Where the active interface is published for the rest of the agent
+    state: Option<AppState>,
+    /// Where interface changes are reported
+    event_bus: Option<EventBus>,
+    /// Access point started after a long outage, when enabled
+    setup_ap: Option<SetupAp>,
+    /// Since when no interface has been usable
+    offline_since: Option<Instant>,
+    /// Up/down history that keeps flapping interfaces from taking over
+    tracker: InterfaceTracker,
+    /// Probes the Wi-Fi interfaces for captive portals, when enabled
+    captive_portal: Option<CaptivePortalDetector>,
 }
 
 impl NetworkManager {
@@ -39,52 +83,240 @@ impl NetworkManager {
             preferred_interfaces,
             current_interface: None,
             connectivity_status: ConnectivityStatus::Offline,
+            state: None,
+            event_bus: None,
+            setup_ap: None,
+            offline_since: None,
+            tracker: InterfaceTracker::new(&FailoverConfig::default()),
+            captive_portal: None,
         }
     }
 
-    /// Start monitoring network interfaces
+    /// Replace the default hold-down and flap damping
+    pub fn set_failover(&mut self, config: &FailoverConfig) {
+        self.tracker = InterfaceTracker::new(config);
+    }
+
+    /// Keep Wi-Fi interfaces behind a captive portal from carrying the uplink
+    pub fn set_captive_portal(&mut self, config: &CaptivePortalConfig) {
+        self.captive_portal = Some(CaptivePortalDetector::new(config));
+    }
+
+    /// Keep `connectivity.interface` in the shared state up to date
+    pub fn set_state(&mut self, state: AppState) {
+        self.state = Some(state);
+    }
+
+    /// Report interface changes and going on- or offline as events
+    pub fn set_event_bus(&mut self, event_bus: EventBus) {
+        self.event_bus = Some(event_bus);
+    }
+
+    /// Start the Wi-Fi setup access point when no interface has been usable for a while
+    pub fn set_setup_ap(&mut self, setup_ap: SetupAp) {
+        self.setup_ap = Some(setup_ap);
+    }
+
+    /// Start monitoring network interfaces, woken by netlink where available and polling otherwise
     pub async fn start_monitoring(&mut self) {
-        let mut check_interval = interval(Duration::from_secs(5));
-        
+        #[cfg(target_os = "linux")]
+        match netlink::LinkWatcher::open() {
+            Ok(watcher) => {
+                info!("Watching interfaces through netlink");
+                let e = self.watch(watcher).await;
+                warn!(error = %e, "Netlink notifications failed, polling interfaces instead");
+            }
+            Err(e) => warn!(error = %e, "Netlink unavailable, polling interfaces instead"),
+        }
+
+        let mut check_interval = interval(POLL_INTERVAL);
         loop {
             check_interval.tick().await;
             self.check_and_update_interface().await;
         }
     }
 
+    /// Re-check on every netlink notification, returning once the socket fails
+    #[cfg(target_os = "linux")]
+    async fn watch(&mut self, mut watcher: netlink::LinkWatcher) -> anyhow::Error {
+        // Hold-down timers and the setup AP still need time-driven checks
+        let mut rescan = interval(RESCAN_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = rescan.tick() => {}
+                changes = watcher.next() => match changes {
+                    Ok(changes) if changes.is_empty() => continue,
+                    Ok(changes) => {
+                        debug!(?changes, "Interface change notified");
+                        // A change comes as a burst of messages; let it finish
+                        sleep(SETTLE_DELAY).await;
+                        rescan.reset();
+                    }
+                    Err(e) => return e,
+                },
+            }
+            self.check_and_update_interface().await;
+        }
+    }
+
     /// Check interfaces and select the best available one
     async fn check_and_update_interface(&mut self) {
+        let now = Instant::now();
         let available_interfaces = self.get_available_interfaces().await;
         
-        // Find the highest priority available interface
-        let best_interface = self.select_best_interface(&available_interfaces);
-        
-        // Update current interface if changed
-        if best_interface != self.current_interface {
-            match &best_interface {
-                Some(iface) => {
-                    info!(
-                        old = ?self.current_interface,
-                        new = iface,
-                        "Network interface changed"
-                    );
-                    self.current_interface = Some(iface.clone());
-                    self.connectivity_status = ConnectivityStatus::Online;
+        let mut events = self.check_captive_portals(&available_interfaces, now).await;
+        events.extend(self.observe(&available_interfaces, now));
+        let best_interface = self.select_interface(&available_interfaces, now);
+        events.extend(self.switch_interface(best_interface));
+        events.extend(self.update_setup_ap(now).await);
+        for event in events {
+            if let Some(event_bus) = &self.event_bus {
+                if let Err(e) = event_bus.emit(event) {
+                    warn!(error = %e, "Failed to report network change");
                 }
-                None => {
-                    warn!("No network interfaces available");
-                    self.current_interface = None;
-                    self.connectivity_status = ConnectivityStatus::Offline;
+            }
+        }
+    }
+
+    /// Probe the interfaces that are up for captive portals, returning events for portals found or gone
+    async fn check_captive_portals(&mut self, available: &[NetworkInterface], now: Instant) -> Vec<Event> {
+        let Some(detector) = &mut self.captive_portal else {
+            return Vec::new();
+        };
+        let up: Vec<String> = available.iter().map(|interface| interface.name.clone()).collect();
+        let events = detector.check(&up, now).await;
+        for name in &self.preferred_interfaces {
+            self.tracker.set_captive(name, detector.is_captive(name));
+        }
+        events
+    }
+
+    /// Record which interfaces are up, publishing their health and returning flapping events
+    fn observe(&mut self, available: &[NetworkInterface], now: Instant) -> Vec<Event> {
+        let mut events = Vec::new();
+        for name in &self.preferred_interfaces {
+            let up = available.iter().any(|interface| &interface.name == name);
+            events.extend(self.tracker.observe(name, up, now));
+        }
+        if let Some(state) = &self.state {
+            state.write().interface_health = self.tracker.report(&self.preferred_interfaces, now);
+        }
+        events
+    }
+
+    /// The interface to use: the best healthy one, else the current one while it is up,
+    /// else the best one that is up, flapping ones and then those behind a captive portal last
+    fn select_interface(&self, available: &[NetworkInterface], now: Instant) -> Option<String> {
+        let online: Vec<NetworkInterface> = available
+            .iter()
+            .filter(|interface| self.tracker.status(&interface.name, now) != InterfaceStatus::CaptivePortal)
+            .cloned()
+            .collect();
+        if online.is_empty() {
+            return self.select_best_interface(available);
+        }
+        let available = &online;
+        let with_status = |wanted: &dyn Fn(InterfaceStatus) -> bool| -> Vec<NetworkInterface> {
+            available
+                .iter()
+                .filter(|interface| wanted(self.tracker.status(&interface.name, now)))
+                .cloned()
+                .collect()
+        };
+        if let Some(best) = self.select_best_interface(&with_status(&|status| status == InterfaceStatus::Healthy)) {
+            return Some(best);
+        }
+        if let Some(current) = &self.current_interface {
+            if available.iter().any(|interface| &interface.name == current) {
+                return Some(current.clone());
+            }
+        }
+        self.select_best_interface(&with_status(&|status| status != InterfaceStatus::Flapping))
+            .or_else(|| self.select_best_interface(available))
+    }
+
+    /// Make `best` the current interface, returning the events for the change
+    fn switch_interface(&mut self, best: Option<String>) -> Vec<Event> {
+        if best == self.current_interface {
+            return Vec::new();
+        }
+
+        let previous = self.current_interface.take();
+        let mut events = vec![Event::NetworkInterfaceChanged {
+            previous: previous.clone(),
+            interface: best.clone(),
+        }];
+        match &best {
+            Some(iface) => {
+                info!(old = ?previous, new = iface, "Network interface changed");
+                if previous.is_none() {
+                    events.push(Event::NetworkOnline { interface: iface.clone() });
                 }
+                self.connectivity_status = ConnectivityStatus::Online;
+            }
+            None => {
+                warn!("No network interfaces available");
+                events.push(Event::NetworkOffline);
+                self.connectivity_status = ConnectivityStatus::Offline;
             }
         }
+        self.current_interface = best;
+        if let Some(state) = &self.state {
+            state.write().set_interface(self.current_interface.clone());
+        }
+        events
+    }
+
+    /// Start or stop the setup access point, returning the events for the change
+    async fn update_setup_ap(&mut self, now: Instant) -> Vec<Event> {
+        let Some(setup_ap) = &mut self.setup_ap else {
+            return Vec::new();
+        };
+        if self.current_interface.is_some() {
+            self.offline_since = None;
+        } else {
+            self.offline_since.get_or_insert(now);
+        }
+
+        let wanted = setup_ap.wanted(self.offline_since, now);
+        if wanted == setup_ap.is_running() {
+            return Vec::new();
+        }
+        if wanted {
+            if let Err(e) = setup_ap.start().await {
+                warn!(error = %e, "Failed to start Wi-Fi setup access point");
+                // Tried again once the outage has lasted `after_s` more
+                self.offline_since = Some(now);
+                return Vec::new();
+            }
+            let ssid = setup_ap.ssid().to_string();
+            if let Some(state) = &self.state {
+                state.write().set_setup_ap(Some(ssid.clone()));
+            }
+            return vec![Event::SetupApStarted { ssid }];
+        }
+
+        let network_saved = setup_ap.stop().await;
+        // The configured networks get `after_s` before the access point comes back
+        if self.offline_since.is_some() {
+            self.offline_since = Some(now);
+        }
+        if let Some(state) = &self.state {
+            state.write().set_setup_ap(None);
+        }
+        vec![Event::SetupApStopped { network_saved }]
     }
 
     /// Get list of available interfaces
     async fn get_available_interfaces(&self) -> Vec<NetworkInterface> {
         let mut interfaces = Vec::new();
+        // hostapd brings the interface up, but it carries no uplink while the access point runs
+        let setup_ap = self.setup_ap.as_ref().filter(|ap| ap.is_running()).map(SetupAp::interface);
         
         for (priority, name) in self.preferred_interfaces.iter().enumerate() {
+            if setup_ap == Some(name.as_str()) {
+                continue;
+            }
             let interface = self.check_interface_status(name).await;
             if interface.is_up && interface.has_carrier {
                 interfaces.push(NetworkInterface {
@@ -175,6 +407,89 @@ impl NetworkManager {
     }
 }
 
+/// Run a system networking command to completion
+async fn run(program: &str, args: &[&str]) -> Result<()> {
+    output(program, args).await.map(drop)
+}
+
+/// Run a system networking command, returning what it printed
+async fn output(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .with_context(|| format!("Failed to run {}", program))?;
+    if !output.status.success() {
+        bail!(
+            "{} {} exited with {}: {}",
+            program,
+            args.first().copied().unwrap_or_default(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// First IPv4 address assigned to an interface, if it has one
+#[cfg(unix)]
+pub fn interface_ipv4(name: &str) -> Option<std::net::Ipv4Addr> {
+    nix::ifaddrs::getifaddrs()
+        .ok()?
+        .filter(|ifaddr| ifaddr.interface_name == name)
+        .find_map(|ifaddr| ifaddr.address?.as_sockaddr_in().map(|addr| addr.ip()))
+}
+
+#[cfg(not(unix))]
+pub fn interface_ipv4(_name: &str) -> Option<std::net::Ipv4Addr> {
+    None
+}
+
+/// Open a TCP connection that leaves through `interface` rather than wherever the default route points
+///
+/// The socket is tied to the device with `SO_BINDTODEVICE` where permitted and
+/// always takes the interface's address as its source, so a stale default
+/// route over a dead link cannot capture it.
+pub async fn connect_via(interface: Option<&str>, addr: impl ToSocketAddrs) -> Result<TcpStream> {
+    let Some(interface) = interface else {
+        return TcpStream::connect(addr).await.context("Failed to connect");
+    };
+    let source = interface_ipv4(interface);
+    let mut last_error = None;
+    for target in lookup_host(addr).await.context("Failed to resolve address")? {
+        // The source address pins IPv4 targets only
+        if source.is_some() && !target.is_ipv4() {
+            continue;
+        }
+        let socket = if target.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+        #[cfg(target_os = "linux")]
+        let bound = match socket.bind_device(Some(interface.as_bytes())) {
+            Ok(()) => true,
+            Err(e) => {
+                // Needs CAP_NET_RAW; the source address still selects the interface with policy routing
+                debug!(%interface, error = %e, "Failed to bind socket to device");
+                false
+            }
+        };
+        #[cfg(not(target_os = "linux"))]
+        let bound = false;
+        if !bound && source.is_none() {
+            bail!("Interface {} has neither a usable device binding nor an IPv4 address", interface);
+        }
+        if let Some(source) = source {
+            socket
+                .bind((source, 0).into())
+                .with_context(|| format!("Failed to bind to {} on {}", source, interface))?;
+        }
+        match socket.connect(target).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(anyhow::Error::new(e).context(format!("Failed to connect to {}", target))),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No address reachable over {}", interface)))
+}
+
 impl Default for NetworkManager {
     fn default() -> Self {
         Self::new(vec!["eth0".to_string(), "wlan0".to_string()])
@@ -185,6 +500,25 @@ impl Default for NetworkManager {
 mod tests {
     use super::*;
 
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_loopback_address() {
+        assert_eq!(interface_ipv4("lo"), Some(std::net::Ipv4Addr::LOCALHOST));
+        assert_eq!(interface_ipv4("no-such-if0"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_connect_via_interface() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = connect_via(Some("lo"), addr).await.unwrap();
+        let (_, peer) = listener.accept().await.unwrap();
+        assert_eq!(stream.local_addr().unwrap(), peer);
+        assert_eq!(peer.ip(), std::net::Ipv4Addr::LOCALHOST);
+        assert!(connect_via(Some("no-such-if0"), addr).await.is_err());
+    }
+
     #[tokio::test]
     async fn test_network_manager_creation() {
         let manager = NetworkManager::new(vec!["eth0".to_string(), "wlan0".to_string()]);
@@ -228,6 +562,92 @@ mod tests {
         assert_eq!(manager.current_interface(), Some("eth0"));
     }
 
+    #[test]
+    fn test_interface_changes_reported() {
+        let mut manager = NetworkManager::new(vec!["eth0".to_string(), "wlan0".to_string()]);
+        let state = crate::state::new_app_state();
+        manager.set_state(state.clone());
+
+        assert!(matches!(
+            &manager.switch_interface(Some("wlan0".to_string()))[..],
+            [Event::NetworkInterfaceChanged { previous: None, .. }, Event::NetworkOnline { interface }] if interface == "wlan0"
+        ));
+        assert!(manager.switch_interface(Some("wlan0".to_string())).is_empty());
+        assert!(matches!(
+            &manager.switch_interface(Some("eth0".to_string()))[..],
+            [Event::NetworkInterfaceChanged { previous: Some(previous), interface: Some(_) }] if previous == "wlan0"
+        ));
+        assert_eq!(state.read().connectivity.interface.as_deref(), Some("eth0"));
+
+        assert!(matches!(
+            &manager.switch_interface(None)[..],
+            [Event::NetworkInterfaceChanged { interface: None, .. }, Event::NetworkOffline]
+        ));
+        assert_eq!(manager.connectivity_status(), ConnectivityStatus::Offline);
+        assert_eq!(state.read().connectivity.interface, None);
+    }
+
+    #[test]
+    fn test_failover_damped() {
+        let mut manager = NetworkManager::new(vec!["eth0".to_string(), "wlan0".to_string()]);
+        let start = Instant::now();
+        let at = |s| start + Duration::from_secs(s);
+        let up = |names: &[&str]| -> Vec<NetworkInterface> {
+            names
+                .iter()
+                .map(|name| NetworkInterface {
+                    name: name.to_string(),
+                    priority: manager.preferred_interfaces.iter().position(|n| n == name).unwrap(),
+                    is_up: true,
+                    has_carrier: true,
+                })
+                .collect()
+        };
+        let (wlan, both) = (up(&["wlan0"]), up(&["eth0", "wlan0"]));
+        let mut step = |available: &[NetworkInterface], s| {
+            manager.observe(available, at(s));
+            let best = manager.select_interface(available, at(s));
+            manager.switch_interface(best);
+            manager.current_interface.clone()
+        };
+
+        assert_eq!(step(&wlan, 0).as_deref(), Some("wlan0"));
+        // eth0 takes over only once it has been up for the hold-down
+        assert_eq!(step(&both, 60).as_deref(), Some("wlan0"));
+        assert_eq!(step(&both, 85).as_deref(), Some("wlan0"));
+        assert_eq!(step(&both, 90).as_deref(), Some("eth0"));
+
+        // A bouncing eth0 is left for wlan0 until it settles again
+        for s in [95, 100, 105, 110] {
+            let available = if s % 10 == 5 { &wlan } else { &both };
+            step(available, s);
+        }
+        assert_eq!(step(&both, 115).as_deref(), Some("wlan0"));
+        assert_eq!(step(&both, 300).as_deref(), Some("wlan0"));
+        assert_eq!(step(&both, 420).as_deref(), Some("eth0"));
+    }
+
+    #[test]
+    fn test_captive_portal_avoided() {
+        let mut manager = NetworkManager::new(vec!["eth0".to_string(), "wlan0".to_string()]);
+        let now = Instant::now();
+        let interface = |name: &str, priority| NetworkInterface {
+            name: name.to_string(),
+            priority,
+            is_up: true,
+            has_carrier: true,
+        };
+        let both = [interface("wlan0", 1), interface("eth0", 0)];
+        manager.observe(&both, now);
+        manager.current_interface = Some("wlan0".to_string());
+        manager.tracker.set_captive("wlan0", true);
+
+        // eth0 is still settling, yet beats the portal
+        assert_eq!(manager.select_interface(&both, now).as_deref(), Some("eth0"));
+        assert_eq!(manager.select_interface(&both[..1], now).as_deref(), Some("wlan0"));
+        assert_eq!(manager.tracker.report(&["wlan0".to_string()], now)[0].status, InterfaceStatus::CaptivePortal);
+    }
+
     #[tokio::test]
     async fn test_connectivity_check() {
         let mut manager = NetworkManager::new(vec!["eth0".to_string()]);
@@ -0,0 +1,136 @@
+//! Interface and address notifications from the kernel
+//!
+//! A `NETLINK_ROUTE` socket subscribed to the link and address multicast
+//! groups wakes the network manager as soon as a cable is pulled, Wi-Fi
+//! associates or a DHCP lease lands, instead of it reading sysfs every few
+//! seconds. Only message headers are looked at; what changed is then read
+//! the same way the polling path reads it.
+
+use anyhow::{Context, Result};
+use nix::sys::socket::{bind, recv, socket, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType};
+use std::os::fd::{AsRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+
+const RTMGRP_LINK: u32 = 0x1;
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const RTM_NEWADDR: u16 = 20;
+const RTM_DELADDR: u16 = 21;
+
+/// Length of `struct nlmsghdr`
+const HEADER_LEN: usize = 16;
+
+/// What a notification was about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteChange {
+    Link { index: u32, removed: bool },
+    Address { index: u32, removed: bool },
+}
+
+/// Subscription to link and address changes
+pub struct LinkWatcher {
+    socket: AsyncFd<OwnedFd>,
+    buffer: Vec<u8>,
+}
+
+impl LinkWatcher {
+    pub fn open() -> Result<Self> {
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Raw,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            SockProtocol::NetlinkRoute,
+        )
+        .context("Failed to open netlink socket")?;
+        let groups = RTMGRP_LINK | RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR;
+        bind(fd.as_raw_fd(), &NetlinkAddr::new(0, groups)).context("Failed to join netlink groups")?;
+        Ok(Self {
+            socket: AsyncFd::new(fd).context("Failed to register netlink socket")?,
+            buffer: vec![0; 8192],
+        })
+    }
+
+    /// Wait for the next batch of notifications
+    pub async fn next(&mut self) -> Result<Vec<RouteChange>> {
+        loop {
+            let mut ready = self.socket.readable().await?;
+            let buffer = &mut self.buffer;
+            match ready.try_io(|fd| recv(fd.as_raw_fd(), buffer, MsgFlags::empty()).map_err(std::io::Error::from)) {
+                Ok(read) => return Ok(parse_messages(&self.buffer[..read.context("Failed to read netlink socket")?])),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// Link and address changes in a datagram of netlink messages
+pub fn parse_messages(mut data: &[u8]) -> Vec<RouteChange> {
+    let mut changes = Vec::new();
+    while data.len() >= HEADER_LEN {
+        let len = u32::from_ne_bytes(data[0..4].try_into().unwrap()) as usize;
+        if len < HEADER_LEN || len > data.len() {
+            break;
+        }
+        let kind = u16::from_ne_bytes(data[4..6].try_into().unwrap());
+        let payload = &data[HEADER_LEN..len];
+        // Both ifinfomsg and ifaddrmsg carry the interface index at offset 4
+        let index = payload.get(4..8).map(|index| u32::from_ne_bytes(index.try_into().unwrap()));
+        match (kind, index) {
+            (RTM_NEWLINK | RTM_DELLINK, Some(index)) => changes.push(RouteChange::Link {
+                index,
+                removed: kind == RTM_DELLINK,
+            }),
+            (RTM_NEWADDR | RTM_DELADDR, Some(index)) => changes.push(RouteChange::Address {
+                index,
+                removed: kind == RTM_DELADDR,
+            }),
+            _ => {}
+        }
+        // Messages are aligned to four bytes
+        data = &data[((len + 3) & !3).min(data.len())..];
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(kind: u16, payload: &[u8]) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend(((HEADER_LEN + payload.len()) as u32).to_ne_bytes());
+        message.extend(kind.to_ne_bytes());
+        message.extend([0; 10]);
+        message.extend(payload);
+        message.resize((message.len() + 3) & !3, 0);
+        message
+    }
+
+    #[test]
+    fn test_parse_link_and_address_messages() {
+        // ifinfomsg: family, pad, type, index, flags, change
+        let mut link = vec![0, 0, 1, 0];
+        link.extend(3u32.to_ne_bytes());
+        link.extend([0; 8]);
+        // ifaddrmsg: family, prefix length, flags, scope, index
+        let mut address = vec![2, 24, 0, 0];
+        address.extend(2u32.to_ne_bytes());
+
+        let mut data = message(RTM_NEWLINK, &link);
+        data.extend(message(RTM_DELADDR, &address[..7]));
+        data.extend(message(RTM_DELADDR, &address));
+        // Truncated trailing message
+        data.extend(&message(RTM_NEWADDR, &address)[..10]);
+
+        assert_eq!(
+            parse_messages(&data),
+            vec![
+                RouteChange::Link { index: 3, removed: false },
+                RouteChange::Address { index: 2, removed: true },
+            ]
+        );
+    }
+}
@@ -0,0 +1,553 @@
+//! Wi-Fi and addressing changes made through the API
+//!
+//! A change is applied through wpa_supplicant and dhcpcd, or through nmcli,
+//! and remembered together with how to undo it. Unless an uplink (and the
+//! cloud, when it was connected) is back `network.rollback_s` after the change,
+//! it is undone so a typo cannot cut the device off for good.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use super::{output, run};
+use crate::config::{NetworkBackend, NetworkConfig};
+use crate::events::{Event, EventBus, NetworkChange};
+use crate::security::decode_hex;
+use crate::state::{AppState, CloudStatus, SharedState};
+
+/// Name of the NetworkManager connection the agent creates for a Wi-Fi network
+const NMCLI_PREFIX: &str = "pi-door-";
+
+/// Wi-Fi network to join
+#[derive(Debug, Clone, Deserialize)]
+pub struct WifiSettings {
+    pub ssid: String,
+    /// WPA2 passphrase; the network is open when unset
+    #[serde(default)]
+    pub psk: Option<String>,
+    /// The network does not broadcast its SSID
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+impl WifiSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.ssid.is_empty() || self.ssid.len() > 32 {
+            bail!("ssid must be 1-32 bytes");
+        }
+        if let Some(psk) = &self.psk {
+            if !(8..=63).contains(&psk.len()) || !psk.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+                bail!("psk must be 8-63 printable ASCII characters");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// IPv4 addressing of an interface
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum IpSettings {
+    Dhcp,
+    Static {
+        /// Address with prefix length, e.g. `192.168.1.50/24`
+        address: String,
+        #[serde(default)]
+        gateway: Option<Ipv4Addr>,
+        #[serde(default)]
+        dns: Vec<Ipv4Addr>,
+    },
+}
+
+impl IpSettings {
+    pub fn change(&self) -> NetworkChange {
+        match self {
+            IpSettings::Dhcp => NetworkChange::Dhcp,
+            IpSettings::Static { .. } => NetworkChange::Static,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let IpSettings::Static { address, gateway, dns } = self else {
+            return Ok(());
+        };
+        let (address, prefix) = parse_cidr(address)?;
+        if let Some(gateway) = gateway {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            if u32::from(*gateway) & mask != u32::from(address) & mask {
+                bail!("gateway {} is outside {}/{}", gateway, address, prefix);
+            }
+        }
+        if dns.len() > 3 {
+            bail!("At most 3 DNS servers can be set");
+        }
+        Ok(())
+    }
+}
+
+/// Parse `a.b.c.d/n`
+fn parse_cidr(value: &str) -> Result<(Ipv4Addr, u8)> {
+    let (address, prefix) = value
+        .split_once('/')
+        .with_context(|| format!("address {} needs a prefix length, as in 192.168.1.50/24", value))?;
+    let address: Ipv4Addr = address.parse().with_context(|| format!("Invalid address {}", address))?;
+    let prefix: u8 = prefix.parse().ok().filter(|prefix| (1..=32).contains(prefix)).with_context(|| {
+        format!("Invalid prefix length {}", prefix)
+    })?;
+    Ok((address, prefix))
+}
+
+/// A change that was applied but not yet confirmed by connectivity
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingNetworkChange {
+    pub interface: String,
+    pub change: NetworkChange,
+    pub applied_at: DateTime<Utc>,
+    /// When the change is undone unless connectivity is back
+    pub confirm_by: DateTime<Utc>,
+    /// The cloud was connected before the change, so it has to reconnect too
+    pub needs_cloud: bool,
+}
+
+impl PendingNetworkChange {
+    /// Whether the state shows the connectivity this change has to keep
+    pub fn confirmed(&self, state: &SharedState) -> bool {
+        state.connectivity.interface.is_some() && (!self.needs_cloud || state.connectivity.cloud == CloudStatus::Online)
+    }
+}
+
+/// How to undo an applied change
+#[derive(Debug, Default)]
+pub struct Rollback {
+    /// File to put back as it was; `None` contents remove it
+    restore: Option<(PathBuf, Option<String>)>,
+    /// Commands run after restoring the file
+    commands: Vec<Vec<String>>,
+}
+
+impl Rollback {
+    /// Undo the change, carrying on past failures and reporting the first
+    pub async fn apply(self) -> Result<()> {
+        let mut result = Ok(());
+        if let Some((path, previous)) = self.restore {
+            let restored = match previous {
+                Some(contents) => write_atomic(&path, &contents).await,
+                None => tokio::fs::remove_file(&path)
+                    .await
+                    .with_context(|| format!("Failed to remove {}", path.display())),
+            };
+            result = result.and(restored);
+        }
+        for command in self.commands {
+            let args: Vec<&str> = command[1..].iter().map(String::as_str).collect();
+            result = result.and(run(&command[0], &args).await);
+        }
+        result
+    }
+}
+
+fn command(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// Applies network changes through the configured backend
+pub struct NetworkSettings {
+    backend: NetworkBackend,
+    wifi_interface: String,
+    wpa_supplicant_conf: PathBuf,
+    dhcpcd_conf: PathBuf,
+}
+
+impl NetworkSettings {
+    pub fn new(config: &NetworkConfig) -> Self {
+        Self {
+            backend: config.backend,
+            wifi_interface: config.wifi_interface.clone(),
+            wpa_supplicant_conf: config.wpa_supplicant_conf.clone(),
+            dhcpcd_conf: config.dhcpcd_conf.clone(),
+        }
+    }
+
+    pub fn wifi_interface(&self) -> &str {
+        &self.wifi_interface
+    }
+
+    /// Join a Wi-Fi network, returning how to undo it
+    pub async fn set_wifi(&self, wifi: &WifiSettings) -> Result<Rollback> {
+        wifi.validate()?;
+        let iface = self.wifi_interface.as_str();
+        match self.backend {
+            NetworkBackend::WpaSupplicant => {
+                let previous = save_wpa_network(&self.wpa_supplicant_conf, wifi).await?;
+                let rollback = Rollback {
+                    restore: Some((self.wpa_supplicant_conf.clone(), previous)),
+                    commands: vec![command(&["wpa_cli", "-i", iface, "reconfigure"])],
+                };
+                reload(rollback, &[command(&["wpa_cli", "-i", iface, "reconfigure"])]).await
+            }
+            NetworkBackend::Nmcli => {
+                let name = format!("{}{}", NMCLI_PREFIX, wifi.ssid);
+                let previous = active_connection(iface).await?;
+                // Replaces a connection saved for the same network earlier
+                let _ = run("nmcli", &["connection", "delete", "id", &name]).await;
+                let mut add = command(&["nmcli", "connection", "add", "type", "wifi", "ifname", iface, "con-name", &name]);
+                add.extend(command(&["ssid", &wifi.ssid]));
+                if let Some(psk) = &wifi.psk {
+                    add.extend(command(&["wifi-sec.key-mgmt", "wpa-psk", "wifi-sec.psk", psk]));
+                }
+                if wifi.hidden {
+                    add.extend(command(&["802-11-wireless.hidden", "yes"]));
+                }
+                let mut rollback = Rollback {
+                    restore: None,
+                    commands: vec![command(&["nmcli", "connection", "delete", "id", &name])],
+                };
+                if let Some(previous) = previous.filter(|previous| *previous != name) {
+                    rollback.commands.push(command(&["nmcli", "connection", "up", "id", &previous]));
+                }
+                reload(rollback, &[add, command(&["nmcli", "--wait", "30", "connection", "up", "id", &name])]).await
+            }
+        }
+    }
+
+    /// Set how `interface` gets its address, returning how to undo it
+    pub async fn set_ip(&self, interface: &str, ip: &IpSettings) -> Result<Rollback> {
+        ip.validate()?;
+        match self.backend {
+            NetworkBackend::WpaSupplicant => {
+                let previous = read_optional(&self.dhcpcd_conf).await?;
+                let contents = set_dhcpcd_interface(previous.as_deref().unwrap_or_default(), interface, ip);
+                write_atomic(&self.dhcpcd_conf, &contents).await?;
+                let rebind = command(&["dhcpcd", "-n", interface]);
+                let rollback = Rollback {
+                    restore: Some((self.dhcpcd_conf.clone(), previous)),
+                    commands: vec![rebind.clone()],
+                };
+                reload(rollback, &[rebind]).await
+            }
+            NetworkBackend::Nmcli => {
+                let Some(connection) = active_connection(interface).await? else {
+                    bail!("{} has no active NetworkManager connection", interface);
+                };
+                let previous = output(
+                    "nmcli",
+                    &["-g", "ipv4.method,ipv4.addresses,ipv4.gateway,ipv4.dns", "connection", "show", "id", &connection],
+                )
+                .await?;
+                let mut previous = previous.lines().map(str::to_string);
+                let mut field = || previous.next().unwrap_or_default();
+                let (method, addresses, gateway, dns) = (field(), field(), field(), field());
+
+                let modify = |args: [&str; 4]| {
+                    let mut modify = command(&["nmcli", "connection", "modify", "id", &connection]);
+                    for (name, value) in ["ipv4.method", "ipv4.addresses", "ipv4.gateway", "ipv4.dns"].into_iter().zip(args) {
+                        modify.extend(command(&[name, value]));
+                    }
+                    modify
+                };
+                let up = command(&["nmcli", "--wait", "30", "connection", "up", "id", &connection]);
+                let rollback = Rollback {
+                    restore: None,
+                    commands: vec![
+                        modify([method.as_str(), addresses.as_str(), gateway.as_str(), &dns.replace(' ', "")]),
+                        up.clone(),
+                    ],
+                };
+                let [method, addresses, gateway, dns] = nmcli_ipv4(ip);
+                reload(rollback, &[modify([method.as_str(), addresses.as_str(), gateway.as_str(), dns.as_str()]), up]).await
+            }
+        }
+    }
+}
+
+/// Run the commands that put a change into effect, undoing it when one fails
+async fn reload(rollback: Rollback, commands: &[Vec<String>]) -> Result<Rollback> {
+    for command in commands {
+        let args: Vec<&str> = command[1..].iter().map(String::as_str).collect();
+        if let Err(e) = run(&command[0], &args).await {
+            if let Err(undo) = rollback.apply().await {
+                error!(error = %undo, "Failed to undo network change");
+            }
+            return Err(e);
+        }
+    }
+    Ok(rollback)
+}
+
+/// Connection NetworkManager has active on `interface`
+async fn active_connection(interface: &str) -> Result<Option<String>> {
+    let name = output("nmcli", &["-g", "GENERAL.CONNECTION", "device", "show", interface]).await?;
+    let name = name.trim();
+    Ok((!name.is_empty() && name != "--").then(|| name.to_string()))
+}
+
+/// Values for ipv4.method, ipv4.addresses, ipv4.gateway and ipv4.dns
+fn nmcli_ipv4(ip: &IpSettings) -> [String; 4] {
+    match ip {
+        IpSettings::Dhcp => ["auto".to_string(), String::new(), String::new(), String::new()],
+        IpSettings::Static { address, gateway, dns } => [
+            "manual".to_string(),
+            address.clone(),
+            gateway.map(|gateway| gateway.to_string()).unwrap_or_default(),
+            dns.iter().map(Ipv4Addr::to_string).collect::<Vec<_>>().join(","),
+        ],
+    }
+}
+
+/// Replace the `interface` stanza of a dhcpcd configuration; DHCP needs none
+fn set_dhcpcd_interface(contents: &str, interface: &str, ip: &IpSettings) -> String {
+    let mut lines = Vec::new();
+    let mut in_stanza = false;
+    for line in contents.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("interface") => in_stanza = words.next() == Some(interface),
+            Some("profile" | "ssid") => in_stanza = false,
+            _ => {}
+        }
+        if !in_stanza {
+            lines.push(line);
+        }
+    }
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+    let mut contents = lines.join("\n");
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+    if let IpSettings::Static { address, gateway, dns } = ip {
+        contents.push_str(&format!("\ninterface {}\nstatic ip_address={}\n", interface, address));
+        if let Some(gateway) = gateway {
+            contents.push_str(&format!("static routers={}\n", gateway));
+        }
+        if !dns.is_empty() {
+            let dns: Vec<_> = dns.iter().map(Ipv4Addr::to_string).collect();
+            contents.push_str(&format!("static domain_name_servers={}\n", dns.join(" ")));
+        }
+    }
+    contents
+}
+
+/// wpa_supplicant network block; the SSID is hex encoded so any bytes are safe
+fn wpa_network_block(wifi: &WifiSettings) -> String {
+    let ssid: String = wifi.ssid.bytes().map(|b| format!("{:02x}", b)).collect();
+    let mut block = format!("network={{\n\tssid={}\n", ssid);
+    match &wifi.psk {
+        Some(psk) => block.push_str(&format!("\tpsk=\"{}\"\n", psk)),
+        None => block.push_str("\tkey_mgmt=NONE\n"),
+    }
+    if wifi.hidden {
+        block.push_str("\tscan_ssid=1\n");
+    }
+    block.push_str("}\n");
+    block
+}
+
+/// SSID of a wpa_supplicant `ssid=` value, quoted or hex
+fn wpa_ssid(value: &str) -> Option<Vec<u8>> {
+    match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+        Some(quoted) => Some(quoted.as_bytes().to_vec()),
+        None => decode_hex(value).ok(),
+    }
+}
+
+/// Put `wifi` into a wpa_supplicant configuration, replacing blocks for the same SSID
+fn set_wpa_network(contents: &str, wifi: &WifiSettings) -> String {
+    let mut kept = Vec::new();
+    let mut block: Option<Vec<&str>> = None;
+    for line in contents.lines() {
+        match &mut block {
+            None if line.trim_start().starts_with("network={") => block = Some(vec![line]),
+            None => kept.push(line),
+            Some(lines) => {
+                lines.push(line);
+                if line.trim() == "}" {
+                    let same_ssid = lines.iter().any(|line| {
+                        line.trim().strip_prefix("ssid=").and_then(wpa_ssid).as_deref() == Some(wifi.ssid.as_bytes())
+                    });
+                    if !same_ssid {
+                        kept.extend(lines.iter());
+                    }
+                    block = None;
+                }
+            }
+        }
+    }
+    // An unterminated block is kept as it was
+    kept.extend(block.unwrap_or_default());
+    while kept.last().is_some_and(|line| line.trim().is_empty()) {
+        kept.pop();
+    }
+    let mut contents = kept.join("\n");
+    if !contents.is_empty() {
+        contents.push_str("\n\n");
+    }
+    contents.push_str(&wpa_network_block(wifi));
+    contents
+}
+
+/// Save a network to a wpa_supplicant configuration, returning its previous contents
+pub(crate) async fn save_wpa_network(conf: &Path, wifi: &WifiSettings) -> Result<Option<String>> {
+    wifi.validate()?;
+    let previous = read_optional(conf).await?;
+    write_atomic(conf, &set_wpa_network(previous.as_deref().unwrap_or_default(), wifi)).await?;
+    Ok(previous)
+}
+
+async fn read_optional(path: &Path) -> Result<Option<String>> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+async fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, contents)
+        .await
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Keep the change if connectivity is back once `wait` has passed, otherwise undo it
+pub async fn confirm_or_roll_back(
+    state: AppState,
+    event_bus: EventBus,
+    change: PendingNetworkChange,
+    rollback: Rollback,
+    wait: Duration,
+) {
+    tokio::time::sleep(wait).await;
+    let confirmed = {
+        let mut state = state.write();
+        state.network_change = None;
+        change.confirmed(&state)
+    };
+    if confirmed {
+        info!(interface = %change.interface, change = ?change.change, "Network change confirmed");
+        return;
+    }
+
+    warn!(interface = %change.interface, change = ?change.change, "Connectivity lost after network change, rolling back");
+    if let Err(e) = rollback.apply().await {
+        error!(error = %e, "Failed to roll back network change");
+    }
+    let event = Event::NetworkConfigRolledBack {
+        interface: change.interface,
+        change: change.change,
+    };
+    if let Err(e) = event_bus.emit(event) {
+        warn!(error = %e, "Failed to report network rollback");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn wifi(ssid: &str, psk: Option<&str>) -> WifiSettings {
+        WifiSettings {
+            ssid: ssid.to_string(),
+            psk: psk.map(str::to_string),
+            hidden: false,
+        }
+    }
+
+    fn static_ip(address: &str, gateway: &str) -> IpSettings {
+        IpSettings::Static {
+            address: address.to_string(),
+            gateway: Some(gateway.parse().unwrap()),
+            dns: vec![Ipv4Addr::new(1, 1, 1, 1), Ipv4Addr::new(9, 9, 9, 9)],
+        }
+    }
+
+    #[test]
+    fn test_settings_validation() {
+        assert!(wifi("Home", Some("correct horse")).validate().is_ok());
+        assert!(wifi("Cafe", None).validate().is_ok());
+        assert!(wifi("Home", Some("short")).validate().is_err());
+        assert!(wifi("", None).validate().is_err());
+
+        assert!(static_ip("192.168.1.50/24", "192.168.1.1").validate().is_ok());
+        assert!(static_ip("192.168.1.50/24", "192.168.2.1").validate().is_err());
+        assert!(static_ip("192.168.1.50", "192.168.1.1").validate().is_err());
+        assert!(static_ip("192.168.1.50/33", "192.168.1.1").validate().is_err());
+    }
+
+    #[test]
+    fn test_wpa_network_replaced() {
+        let contents = "ctrl_interface=DIR=/var/run/wpa_supplicant\n\nnetwork={\n\tssid=\"Home\"\n\tpsk=\"old password\"\n}\n\nnetwork={\n\tssid=\"Office\"\n\tpsk=\"office password\"\n}\n";
+        let updated = set_wpa_network(contents, &wifi("Home", Some("new password")));
+        assert!(updated.starts_with("ctrl_interface="));
+        assert!(!updated.contains("old password"));
+        assert!(updated.contains("ssid=\"Office\""));
+        assert!(updated.ends_with("network={\n\tssid=486f6d65\n\tpsk=\"new password\"\n}\n"));
+
+        // Hex encoded SSIDs are recognised too
+        let updated = set_wpa_network(&updated, &wifi("Home", None));
+        assert_eq!(updated.matches("ssid=486f6d65").count(), 1);
+        assert!(updated.contains("key_mgmt=NONE"));
+    }
+
+    #[test]
+    fn test_dhcpcd_interface_replaced() {
+        let contents = "hostname\nclientid\n\ninterface eth0\nstatic ip_address=10.0.0.5/24\nstatic routers=10.0.0.1\n\ninterface wlan0\nmetric 300\n";
+        let updated = set_dhcpcd_interface(contents, "eth0", &static_ip("192.168.1.50/24", "192.168.1.1"));
+        assert!(!updated.contains("10.0.0.5"));
+        assert!(updated.contains("interface wlan0\nmetric 300\n"));
+        assert!(updated.ends_with(
+            "\ninterface eth0\nstatic ip_address=192.168.1.50/24\nstatic routers=192.168.1.1\nstatic domain_name_servers=1.1.1.1 9.9.9.9\n"
+        ));
+
+        let updated = set_dhcpcd_interface(&updated, "eth0", &IpSettings::Dhcp);
+        assert_eq!(updated, "hostname\nclientid\n\ninterface wlan0\nmetric 300\n");
+        assert_eq!(
+            nmcli_ipv4(&static_ip("192.168.1.50/24", "192.168.1.1")),
+            ["manual", "192.168.1.50/24", "192.168.1.1", "1.1.1.1,9.9.9.9"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rolled_back_without_connectivity() {
+        let dir = TempDir::new().unwrap();
+        let conf = dir.path().join("wpa_supplicant.conf");
+        tokio::fs::write(&conf, "ctrl_interface=DIR=/var/run/wpa_supplicant\n").await.unwrap();
+        let previous = save_wpa_network(&conf, &wifi("Home", Some("correct horse"))).await.unwrap();
+
+        let (event_bus, mut rx) = EventBus::new();
+        let state = crate::state::new_app_state();
+        let now = Utc::now();
+        let change = PendingNetworkChange {
+            interface: "wlan0".to_string(),
+            change: NetworkChange::Wifi,
+            applied_at: now,
+            confirm_by: now,
+            needs_cloud: false,
+        };
+        state.write().network_change = Some(change.clone());
+        let rollback = Rollback {
+            restore: Some((conf.clone(), previous)),
+            commands: Vec::new(),
+        };
+        confirm_or_roll_back(state.clone(), event_bus, change, rollback, Duration::ZERO).await;
+
+        assert_eq!(
+            tokio::fs::read_to_string(&conf).await.unwrap(),
+            "ctrl_interface=DIR=/var/run/wpa_supplicant\n"
+        );
+        assert!(state.read().network_change.is_none());
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::NetworkConfigRolledBack { change: NetworkChange::Wifi, .. }
+        ));
+    }
+}
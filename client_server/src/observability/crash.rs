@@ -0,0 +1,258 @@
+//! Crash reports, written by the panic hook and sent to the master on the next start
+//!
+//! A panic leaves `crash-<UTC time>.json` in `<data_dir>/crash` with the panic
+//! message and location, a backtrace, the recent events and a snapshot of the
+//! state. The next start posts each report to the master and deletes the ones
+//! it took, so a crash in the field is not lost with the console output.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::backtrace::Backtrace;
+use std::fs;
+use std::panic::PanicHookInfo;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::cloud::RestFallback;
+use crate::config::AppConfig;
+use crate::events::EventEnvelope;
+use crate::state::{AppState, SharedState};
+
+const REPORT_PREFIX: &str = "crash-";
+const REPORT_SUFFIX: &str = ".json";
+/// Reports kept while the master cannot be reached; older ones are deleted
+const MAX_REPORTS: usize = 10;
+/// How long the hook waits for the state; a panic under its write lock would wait forever
+const STATE_WAIT: Duration = Duration::from_millis(100);
+
+/// What the agent knew when it panicked
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrashReport {
+    pub time: DateTime<Utc>,
+    pub version: String,
+    pub client_id: String,
+    pub message: String,
+    pub location: Option<String>,
+    pub thread: Option<String>,
+    pub backtrace: String,
+    /// Recent events, oldest first
+    pub recent_events: Vec<EventEnvelope>,
+    /// Alarm state, sensors, outputs and connectivity; absent if the state was locked
+    pub state: Option<Value>,
+}
+
+/// Writes crash reports and sends the ones earlier runs left behind
+#[derive(Clone)]
+pub struct CrashReporter {
+    dir: PathBuf,
+    client_id: String,
+    state: AppState,
+}
+
+impl CrashReporter {
+    pub fn new(config: &AppConfig, state: AppState) -> Self {
+        Self {
+            dir: config.crash_dir(),
+            client_id: config.system.client_id.clone(),
+            state,
+        }
+    }
+
+    /// Write a report of the panic being handled, from the panic hook
+    pub fn record_panic(&self, info: &PanicHookInfo) {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => info.payload().downcast_ref::<String>().cloned().unwrap_or_else(|| "Box<dyn Any>".to_string()),
+        };
+        let report = self.capture(message, info.location().map(ToString::to_string));
+        match self.write(&report) {
+            Ok(path) => error!(path = %path.display(), "Crash report written"),
+            Err(e) => error!(error = %e, "Failed to write crash report"),
+        }
+    }
+
+    /// A report of a panic on the current thread
+    pub fn capture(&self, message: String, location: Option<String>) -> CrashReport {
+        let state = self.state.try_read_for(STATE_WAIT);
+        CrashReport {
+            time: Utc::now(),
+            version: crate::VERSION.to_string(),
+            client_id: self.client_id.clone(),
+            message,
+            location,
+            thread: std::thread::current().name().map(str::to_string),
+            backtrace: Backtrace::force_capture().to_string(),
+            recent_events: state.as_ref().map(|state| state.last_events.iter().cloned().collect()).unwrap_or_default(),
+            state: state.as_deref().map(snapshot),
+        }
+    }
+
+    /// Save a report, deleting the oldest past the limit
+    pub fn write(&self, report: &CrashReport) -> Result<PathBuf> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create crash report directory {}", self.dir.display()))?;
+        let name = format!("{}{}{}", REPORT_PREFIX, report.time.format("%Y%m%dT%H%M%S%.3fZ"), REPORT_SUFFIX);
+        let path = self.dir.join(name);
+        fs::write(&path, serde_json::to_vec_pretty(report)?)
+            .with_context(|| format!("Failed to write crash report {}", path.display()))?;
+
+        let reports = self.report_paths()?;
+        for old in &reports[..reports.len().saturating_sub(MAX_REPORTS)] {
+            fs::remove_file(old).with_context(|| format!("Failed to delete crash report {}", old.display()))?;
+        }
+        Ok(path)
+    }
+
+    /// Reports left by earlier runs, oldest first; unreadable ones are skipped
+    pub fn pending(&self) -> Result<Vec<(PathBuf, CrashReport)>> {
+        Ok(self
+            .report_paths()?
+            .into_iter()
+            .filter_map(|path| match read_report(&path) {
+                Ok(report) => Some((path, report)),
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "Skipping unreadable crash report");
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// Post pending reports to the master, deleting each it took; the rest wait for the next start
+    pub async fn upload(&self, rest: &RestFallback) -> Result<usize> {
+        let mut sent = 0;
+        for (path, report) in self.pending()? {
+            rest.post_crash_report(&report)
+                .await
+                .with_context(|| format!("Master did not take crash report {}", path.display()))?;
+            fs::remove_file(&path).with_context(|| format!("Failed to delete crash report {}", path.display()))?;
+            info!(time = %report.time, message = %report.message, "Crash report sent to master");
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    fn report_paths(&self) -> Result<Vec<PathBuf>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut paths: Vec<PathBuf> = fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to list crash reports in {}", self.dir.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(REPORT_PREFIX) && name.ends_with(REPORT_SUFFIX))
+            })
+            .collect();
+        // The timestamps sort oldest first
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+fn read_report(path: &Path) -> Result<CrashReport> {
+    let json = fs::read(path).context("Failed to read crash report")?;
+    serde_json::from_slice(&json).context("Crash report is not valid JSON")
+}
+
+/// The parts of the state that explain what the agent was doing
+fn snapshot(state: &SharedState) -> Value {
+    json!({
+        "alarm_state": state.alarm_state,
+        "arm_mode": state.arm_mode,
+        "alarm_kind": state.alarm_kind,
+        "door_open": state.door_open,
+        "zones": state.zones,
+        "bypassed_zones": state.bypassed_zones,
+        "actuators": state.actuators,
+        "outputs": state.outputs,
+        "timers": state.timers,
+        "connectivity": state.connectivity,
+        "power": state.power,
+        "lockdown": state.lockdown,
+        "event_loop": state.event_loop,
+        "health": state.health,
+        "uptime_s": state.uptime_s(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloud::MasterToken;
+    use crate::events::Event;
+    use crate::state::{new_app_state, AlarmState};
+    use axum::{extract::State, routing::post, Json, Router};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_reports_survive_until_master_takes_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::test_default();
+        config.system.data_dir = dir.path().to_path_buf();
+        let state = new_app_state();
+        state.write().alarm_state = AlarmState::Armed;
+        state.write().add_event(EventEnvelope::new(Event::DoorOpen, "test".to_string()));
+        let reporter = CrashReporter::new(&config, state.clone());
+
+        let report = reporter.capture("index out of bounds".to_string(), Some("src/main.rs:1:1".to_string()));
+        assert_eq!(report.recent_events.len(), 1);
+        assert_eq!(report.state.as_ref().unwrap()["alarm_state"], "armed");
+        assert!(!report.backtrace.is_empty());
+        reporter.write(&report).unwrap();
+
+        // A panic under the state's write lock still gets a report
+        let locked = state.write();
+        let blind = reporter.capture("poisoned".to_string(), None);
+        drop(locked);
+        assert!(blind.state.is_none() && blind.recent_events.is_empty());
+
+        // A master that is down leaves the report for the next start
+        let master_token = MasterToken::new(None);
+        let unreachable = RestFallback::new("http://127.0.0.1:1", "c-1", master_token.clone(), None).unwrap();
+        assert!(reporter.upload(&unreachable).await.is_err());
+        assert_eq!(reporter.pending().unwrap().len(), 1);
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let master = Router::new()
+            .route(
+                "/clients/:client_id/crash_reports",
+                post(|State(received): State<Arc<Mutex<Vec<Value>>>>, Json(body): Json<Value>| async move {
+                    received.lock().push(body);
+                }),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, master).await.unwrap() });
+
+        let rest = RestFallback::new(&format!("http://{}", addr), "c-1", master_token, None).unwrap();
+        assert_eq!(reporter.upload(&rest).await.unwrap(), 1);
+        assert_eq!(received.lock()[0]["message"], "index out of bounds");
+        assert!(reporter.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_oldest_reports_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::test_default();
+        config.system.data_dir = dir.path().to_path_buf();
+        let reporter = CrashReporter::new(&config, new_app_state());
+
+        let first = reporter.capture("first".to_string(), None);
+        for offset in 0..=MAX_REPORTS as i64 {
+            let mut report = first.clone();
+            report.time = first.time + chrono::Duration::seconds(offset);
+            report.message = offset.to_string();
+            reporter.write(&report).unwrap();
+        }
+        let pending = reporter.pending().unwrap();
+        assert_eq!(pending.len(), MAX_REPORTS);
+        assert_eq!(pending[0].1.message, "1");
+    }
+}
@@ -0,0 +1,182 @@
+//! JSON log files, rotated by size and age
+//!
+//! Logs go to `agent.log` in the log directory. Once it reaches
+//! `logging.max_file_mb`, or was started `logging.rotate_hours` ago, it is
+//! renamed to `agent-<UTC time>.log` and a new one is started. Rotated files
+//! past `logging.max_files`, or older than `logging.max_age_days`, are
+//! deleted, so the logs leading up to a crash are still there afterwards.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::config::LoggingConfig;
+
+const ACTIVE: &str = "agent.log";
+const ROTATED_PREFIX: &str = "agent-";
+const ROTATED_SUFFIX: &str = ".log";
+
+const MB: u64 = 1024 * 1024;
+const HOUR_S: u64 = 3600;
+const DAY_S: u64 = 86_400;
+
+/// Log file writer that rotates itself as it is written to
+pub struct LogFile {
+    dir: PathBuf,
+    max_bytes: u64,
+    rotate_after: Option<Duration>,
+    max_files: usize,
+    max_age: Option<Duration>,
+    file: File,
+    size: u64,
+    started: SystemTime,
+}
+
+impl LogFile {
+    /// Append to the log in `dir`, deleting rotated files past the limits
+    pub fn open(dir: &Path, config: &LoggingConfig) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create log directory {}", dir.display()))?;
+        let (file, size, started) =
+            open_active(dir).with_context(|| format!("Failed to open log file in {}", dir.display()))?;
+        let log = Self {
+            dir: dir.to_path_buf(),
+            max_bytes: config.max_file_mb * MB,
+            rotate_after: (config.rotate_hours > 0).then(|| Duration::from_secs(config.rotate_hours * HOUR_S)),
+            max_files: config.max_files,
+            max_age: (config.max_age_days > 0).then(|| Duration::from_secs(config.max_age_days * DAY_S)),
+            file,
+            size,
+            started,
+        };
+        log.prune(SystemTime::now())?;
+        Ok(log)
+    }
+
+    /// Whether `len` more bytes at `now` belong in a new file
+    fn due(&self, len: usize, now: SystemTime) -> bool {
+        let aged = self.rotate_after.is_some_and(|after| {
+            now.duration_since(self.started).is_ok_and(|age| age >= after)
+        });
+        self.size > 0 && (self.size + len as u64 > self.max_bytes || aged)
+    }
+
+    fn rotate(&mut self, now: SystemTime) -> io::Result<()> {
+        self.file.flush()?;
+        let stamp = DateTime::<Utc>::from(now).format("%Y%m%dT%H%M%S%.3fZ");
+        let rotated = self.dir.join(format!("{}{}{}", ROTATED_PREFIX, stamp, ROTATED_SUFFIX));
+        fs::rename(self.dir.join(ACTIVE), rotated)?;
+        (self.file, self.size, self.started) = open_active(&self.dir)?;
+        self.prune(now)
+    }
+
+    fn prune(&self, now: SystemTime) -> io::Result<()> {
+        let mut rotated: Vec<PathBuf> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(ROTATED_PREFIX) && name.ends_with(ROTATED_SUFFIX))
+            })
+            .collect();
+        // The timestamps sort oldest first
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.max_files);
+        for (index, path) in rotated.iter().enumerate() {
+            let expired = self.max_age.is_some_and(|max_age| {
+                fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(|modified| now.duration_since(modified).is_ok_and(|age| age > max_age))
+            });
+            if index < excess || expired {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let now = SystemTime::now();
+        if self.due(buf.len(), now) {
+            if let Err(e) = self.rotate(now) {
+                // Logging cannot log its own failure; keep writing and retry after another file's worth
+                eprintln!("Failed to rotate log file: {}", e);
+                self.size = 0;
+                self.started = now;
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// The active file, its size and when it was started
+fn open_active(dir: &Path) -> io::Result<(File, u64, SystemTime)> {
+    let file = OpenOptions::new().create(true).append(true).open(dir.join(ACTIVE))?;
+    let metadata = file.metadata()?;
+    let started = metadata.created().unwrap_or_else(|_| SystemTime::now());
+    Ok((file, metadata.len(), started))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rotated(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name != ACTIVE)
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_rotation_and_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = LoggingConfig { max_files: 2, ..LoggingConfig::default() };
+        let mut log = LogFile::open(dir.path(), &config).unwrap();
+        log.max_bytes = 32;
+
+        // Lines are never split across files
+        let line = b"{\"level\":\"INFO\",\"msg\":\"hello\"}\n";
+        log.write_all(line).unwrap();
+        assert!(rotated(dir.path()).is_empty());
+        log.write_all(line).unwrap();
+        assert_eq!(rotated(dir.path()).len(), 1);
+        assert_eq!(fs::read(dir.path().join(ACTIVE)).unwrap(), line);
+
+        // Only the newest rotated files are kept
+        for _ in 0..3 {
+            std::thread::sleep(Duration::from_millis(2));
+            log.write_all(line).unwrap();
+        }
+        let kept = rotated(dir.path());
+        assert_eq!(kept.len(), 2);
+        assert!(kept.iter().all(|name| name.starts_with(ROTATED_PREFIX)));
+
+        // A file started long enough ago is rotated whatever its size
+        log.max_bytes = MB;
+        let now = SystemTime::now();
+        assert!(!log.due(line.len(), now));
+        log.started = now - Duration::from_secs(config.rotate_hours * HOUR_S);
+        assert!(log.due(line.len(), now));
+
+        // Reopening appends, and old rotated files go
+        log.max_age = Some(Duration::ZERO);
+        log.prune(now + Duration::from_secs(1)).unwrap();
+        assert!(rotated(dir.path()).is_empty());
+        let reopened = LogFile::open(dir.path(), &config).unwrap();
+        assert_eq!(reopened.size, line.len() as u64);
+    }
+}
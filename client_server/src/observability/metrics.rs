@@ -0,0 +1,402 @@
+//! Prometheus metrics, served at `/metrics` with the `metrics` feature and
+//! summarized in cloud heartbeats
+//!
+//! State transitions, alarms and the state machine's per-event timings are
+//! recorded as they happen, in a registry that also holds the standard
+//! `process_*` metrics. The cloud link, event bus, queue and radio readings
+//! are built from the shared state on every scrape, so they always match what
+//! `/v1/health` and `/v1/network` report.
+
+use crate::cloud::LinkMetrics;
+use crate::events::{AlarmKind, BusMetrics};
+use crate::health::{MemoryStatus, SocStatus, StorageStatus, TaskStatus};
+use crate::network::{InterfaceHealth, LinkQuality};
+use crate::state::{AlarmState, EventLoopHealth, SharedState};
+use anyhow::Result;
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Content type of the text exposition format
+pub const CONTENT_TYPE: &str = prometheus::TEXT_FORMAT;
+
+const STATE_TRANSITIONS: &str = "pi_door_state_transitions_total";
+const ALARMS_TRIGGERED: &str = "pi_door_alarms_triggered_total";
+
+/// Seconds from a millisecond to a few seconds, where an alarm panel's events fall
+const TIMING_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Totals sent in heartbeats when the cloud offers `metrics`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSummary {
+    pub state_transitions: u64,
+    pub alarms_triggered: u64,
+    pub events_forwarded: u64,
+    pub queue_depth: u64,
+    pub reconnects: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resident_memory_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_seconds: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soc_celsius: Option<f64>,
+    /// Raw `get_throttled` flags
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soc_throttled: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_free_bytes: Option<u64>,
+}
+
+/// Counters kept for the life of the agent, shared by the tasks that update them
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    transitions: IntCounterVec,
+    alarms: IntCounterVec,
+    events: IntCounterVec,
+    processing: HistogramVec,
+    queue_wait: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let transitions = IntCounterVec::new(
+            Opts::new(STATE_TRANSITIONS, "Alarm state changes since the agent started"),
+            &["from", "to"],
+        )?;
+        let alarms = IntCounterVec::new(Opts::new(ALARMS_TRIGGERED, "Alarms raised since the agent started"), &["kind"])?;
+        let events = IntCounterVec::new(
+            Opts::new("pi_door_events_processed_total", "Events the state machine processed, by type"),
+            &["event"],
+        )?;
+        let processing = HistogramVec::new(
+            HistogramOpts::new("pi_door_event_processing_seconds", "Time the state machine took per event, retries included")
+                .buckets(TIMING_BUCKETS.to_vec()),
+            &["event"],
+        )?;
+        let queue_wait = Histogram::with_opts(
+            HistogramOpts::new("pi_door_event_queue_wait_seconds", "Time events waited for the state machine")
+                .buckets(TIMING_BUCKETS.to_vec()),
+        )?;
+        registry.register(Box::new(transitions.clone()))?;
+        registry.register(Box::new(alarms.clone()))?;
+        registry.register(Box::new(events.clone()))?;
+        registry.register(Box::new(processing.clone()))?;
+        registry.register(Box::new(queue_wait.clone()))?;
+        #[cfg(target_os = "linux")]
+        registry.register(Box::new(prometheus::process_collector::ProcessCollector::for_self()))?;
+        Ok(Self { registry, transitions, alarms, events, processing, queue_wait })
+    }
+
+    pub fn record_transition(&self, from: AlarmState, to: AlarmState) {
+        self.transitions.with_label_values(&[&from.to_string(), &to.to_string()]).inc();
+    }
+
+    pub fn record_alarm(&self, kind: AlarmKind) {
+        self.alarms.with_label_values(&[&kind.to_string()]).inc();
+    }
+
+    /// An event of `event_type` that waited `wait` in the queue and took `took` to process
+    pub fn record_event(&self, event_type: &str, wait: Duration, took: Duration) {
+        self.events.with_label_values(&[event_type]).inc();
+        self.processing.with_label_values(&[event_type]).observe(took.as_secs_f64());
+        self.queue_wait.observe(wait.as_secs_f64());
+    }
+
+    /// Render the counters and current readings in the Prometheus text format
+    pub fn render(&self, state: &SharedState, bus: &BusMetrics) -> Result<String> {
+        let readings = Registry::new();
+        register_event_bus(&readings, bus, &state.event_loop)?;
+        register_cloud_link(&readings, &state.cloud_link)?;
+        register_link_quality(&readings, &state.link_quality)?;
+        register_interface_health(&readings, &state.interface_health)?;
+        register_soc(&readings, &state.soc)?;
+        register_storage(&readings, &state.storage)?;
+        register_tasks(&readings, &state.tasks)?;
+        register_memory(&readings, &state.memory)?;
+
+        let mut families = self.registry.gather();
+        families.extend(readings.gather());
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&families, &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    /// Totals since the agent started, for a heartbeat
+    pub fn summary(&self, state: &SharedState) -> MetricsSummary {
+        let families = self.registry.gather();
+        let link = &state.cloud_link;
+        MetricsSummary {
+            state_transitions: sum(&families, STATE_TRANSITIONS).unwrap_or_default() as u64,
+            alarms_triggered: sum(&families, ALARMS_TRIGGERED).unwrap_or_default() as u64,
+            events_forwarded: link.events_forwarded,
+            queue_depth: link.queue_depth,
+            reconnects: link.reconnects(),
+            resident_memory_bytes: sum(&families, "process_resident_memory_bytes").map(|bytes| bytes as u64),
+            cpu_seconds: sum(&families, "process_cpu_seconds_total"),
+            soc_celsius: state.soc.celsius,
+            soc_throttled: state.soc.throttled.map(|flags| flags.bits),
+            disk_free_bytes: state.storage.free_bytes,
+        }
+    }
+}
+
+/// A family's counters and gauges added up over all labels, if it was gathered
+fn sum(families: &[MetricFamily], name: &str) -> Option<f64> {
+    let family = families.iter().find(|family| family.get_name() == name)?;
+    Some(
+        family
+            .get_metric()
+            .iter()
+            .map(|metric| metric.get_counter().get_value() + metric.get_gauge().get_value())
+            .sum(),
+    )
+}
+
+fn register_event_bus(registry: &Registry, bus: &BusMetrics, event_loop: &EventLoopHealth) -> Result<()> {
+    let depth = IntGauge::new("pi_door_event_bus_depth", "Events waiting for the state machine")?;
+    let dropped = IntCounter::new("pi_door_event_bus_dropped_total", "Events lost to the bus overflow policy")?;
+    let broadcast_depth = IntGauge::new(
+        "pi_door_event_broadcast_depth",
+        "Envelopes the slowest subscriber has yet to take from the broadcast channel",
+    )?;
+    let lagged = IntCounterVec::new(
+        Opts::new("pi_door_event_broadcast_lagged_total", "Envelopes a subscriber missed by falling behind"),
+        &["subscriber"],
+    )?;
+    let lagging = IntGauge::new("pi_door_event_loop_lagging", "The state machine has been falling behind its queue")?;
+    registry.register(Box::new(depth.clone()))?;
+    registry.register(Box::new(dropped.clone()))?;
+    registry.register(Box::new(broadcast_depth.clone()))?;
+    registry.register(Box::new(lagged.clone()))?;
+    registry.register(Box::new(lagging.clone()))?;
+
+    depth.set(bus.queue_depth as i64);
+    dropped.inc_by(bus.dropped);
+    broadcast_depth.set(bus.broadcast_depth as i64);
+    for (subscriber, missed) in &bus.lagged {
+        lagged.with_label_values(&[subscriber]).inc_by(*missed);
+    }
+    lagging.set(i64::from(event_loop.lagging));
+    Ok(())
+}
+
+fn register_cloud_link(registry: &Registry, link: &LinkMetrics) -> Result<()> {
+    let forwarded = IntCounter::new("pi_door_events_forwarded_total", "Events sent to the cloud since the agent started")?;
+    let reconnects = IntCounter::new("pi_door_cloud_reconnects_total", "Cloud connections made after the first")?;
+    let queue_depth = IntGauge::new("pi_door_queue_depth", "Events waiting in the offline queue")?;
+    registry.register(Box::new(forwarded.clone()))?;
+    registry.register(Box::new(reconnects.clone()))?;
+    registry.register(Box::new(queue_depth.clone()))?;
+
+    forwarded.inc_by(link.events_forwarded);
+    reconnects.inc_by(link.reconnects());
+    queue_depth.set(link.queue_depth as i64);
+    Ok(())
+}
+
+fn register_link_quality(registry: &Registry, links: &[LinkQuality]) -> Result<()> {
+    let gauge = |name: &str, help: &str| -> Result<GaugeVec> {
+        let gauge = GaugeVec::new(Opts::new(name, help), &["interface", "kind"])?;
+        registry.register(Box::new(gauge.clone()))?;
+        Ok(gauge)
+    };
+    let connected = gauge("pi_door_link_connected", "Radio link associated or registered")?;
+    let marginal = gauge("pi_door_link_marginal", "Radio link below the configured thresholds")?;
+    let signal = gauge("pi_door_link_signal_dbm", "Wi-Fi signal or LTE RSSI in dBm")?;
+    let bitrate = gauge("pi_door_link_bitrate_mbps", "Wi-Fi transmit bitrate in Mbit/s")?;
+    let quality = gauge("pi_door_link_quality_percent", "LTE signal quality reported by the modem")?;
+    let rsrp = gauge("pi_door_link_rsrp_dbm", "LTE reference signal received power in dBm")?;
+    let rsrq = gauge("pi_door_link_rsrq_db", "LTE reference signal received quality in dB")?;
+    let snr = gauge("pi_door_link_snr_db", "LTE signal to noise ratio in dB")?;
+
+    for link in links {
+        let labels = [link.interface.as_str(), link.kind.as_str()];
+        connected.with_label_values(&labels).set(f64::from(u8::from(link.connected)));
+        marginal.with_label_values(&labels).set(f64::from(u8::from(link.marginal)));
+        for (gauge, value) in [
+            (&signal, link.signal_dbm),
+            (&bitrate, link.bitrate_mbps),
+            (&quality, link.quality_pct.map(f64::from)),
+            (&rsrp, link.rsrp_dbm),
+            (&rsrq, link.rsrq_db),
+            (&snr, link.snr_db),
+        ] {
+            if let Some(value) = value {
+                gauge.with_label_values(&labels).set(value);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn register_interface_health(registry: &Registry, interfaces: &[InterfaceHealth]) -> Result<()> {
+    let score = GaugeVec::new(
+        Opts::new("pi_door_interface_score", "Interface health from 0 (down) to 100 (stable)"),
+        &["interface"],
+    )?;
+    let flaps = IntCounterVec::new(
+        Opts::new("pi_door_interface_flaps_total", "Times the interface went down since the agent started"),
+        &["interface"],
+    )?;
+    registry.register(Box::new(score.clone()))?;
+    registry.register(Box::new(flaps.clone()))?;
+
+    for interface in interfaces {
+        score.with_label_values(&[&interface.interface]).set(f64::from(interface.score));
+        flaps.with_label_values(&[&interface.interface]).inc_by(interface.flaps);
+    }
+    Ok(())
+}
+
+fn register_soc(registry: &Registry, soc: &SocStatus) -> Result<()> {
+    let Some(celsius) = soc.celsius else {
+        return Ok(());
+    };
+    let temperature = Gauge::new("pi_door_soc_temperature_celsius", "SoC temperature in degrees Celsius")?;
+    let overheating = IntGauge::new("pi_door_soc_overheating", "SoC above the warning temperature for the sustained time")?;
+    registry.register(Box::new(temperature.clone()))?;
+    registry.register(Box::new(overheating.clone()))?;
+    temperature.set(celsius);
+    overheating.set(i64::from(soc.overheating));
+
+    if let Some(flags) = soc.throttled {
+        let throttled = IntGaugeVec::new(
+            Opts::new("pi_door_soc_throttled", "Firmware throttling flags set now"),
+            &["flag"],
+        )?;
+        registry.register(Box::new(throttled.clone()))?;
+        for (flag, set) in [
+            ("under_voltage", flags.under_voltage),
+            ("frequency_capped", flags.frequency_capped),
+            ("throttled", flags.throttled),
+            ("soft_temp_limit", flags.soft_temp_limit),
+        ] {
+            throttled.with_label_values(&[flag]).set(i64::from(set));
+        }
+    }
+    Ok(())
+}
+
+fn register_storage(registry: &Registry, storage: &StorageStatus) -> Result<()> {
+    let bytes = |name: &str, help: &str, value: Option<u64>| -> Result<()> {
+        if let Some(value) = value {
+            let gauge = IntGauge::new(name, help)?;
+            registry.register(Box::new(gauge.clone()))?;
+            gauge.set(value as i64);
+        }
+        Ok(())
+    };
+    bytes("pi_door_disk_free_bytes", "Free space on the data partition", storage.free_bytes)?;
+    bytes("pi_door_disk_total_bytes", "Size of the data partition", storage.total_bytes)?;
+    bytes("pi_door_queue_size_bytes", "Space the offline queue takes on disk", storage.queue_bytes)?;
+
+    let pruned = IntCounter::new("pi_door_queue_pruned_total", "Queued events dropped for lack of disk space")?;
+    registry.register(Box::new(pruned.clone()))?;
+    pruned.inc_by(storage.pruned);
+    Ok(())
+}
+
+fn register_tasks(registry: &Registry, tasks: &BTreeMap<String, TaskStatus>) -> Result<()> {
+    let restarts = IntCounterVec::new(
+        Opts::new("pi_door_task_restarts_total", "Times the supervisor restarted a background task"),
+        &["task"],
+    )?;
+    registry.register(Box::new(restarts.clone()))?;
+    for (task, status) in tasks {
+        restarts.with_label_values(&[task]).inc_by(status.restarts);
+    }
+    Ok(())
+}
+
+fn register_memory(registry: &Registry, memory: &MemoryStatus) -> Result<()> {
+    let Some(rss) = memory.rss_bytes else {
+        return Ok(());
+    };
+    let resident = IntGauge::new("pi_door_memory_resident_bytes", "Resident memory of the agent at the last reading")?;
+    let peak = IntGauge::new("pi_door_memory_peak_bytes", "Highest resident memory read since the agent started")?;
+    let growth = IntGauge::new("pi_door_memory_growth_bytes", "Rise of the lowest resident memory over the growth window")?;
+    registry.register(Box::new(resident.clone()))?;
+    registry.register(Box::new(peak.clone()))?;
+    registry.register(Box::new(growth.clone()))?;
+    resident.set(rss as i64);
+    peak.set(memory.peak_bytes as i64);
+    growth.set(memory.growth_bytes.unwrap_or_default() as i64);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::link::parse_iw_link;
+
+    #[test]
+    fn test_link_quality_rendered() {
+        let mut state = SharedState::new();
+        state.link_quality = vec![parse_iw_link("wlan0", "Connected to aa:bb:cc:dd:ee:ff (on wlan0)\n\tsignal: -61 dBm\n")];
+        let text = Metrics::new().unwrap().render(&state, &BusMetrics::default()).unwrap();
+        assert!(text.contains("pi_door_link_signal_dbm{interface=\"wlan0\",kind=\"wifi\"} -61"));
+        assert!(text.contains("pi_door_link_connected{interface=\"wlan0\",kind=\"wifi\"} 1"));
+        assert!(!text.contains("pi_door_link_rsrp_dbm{"));
+        assert!(!text.contains("pi_door_soc_temperature_celsius"));
+
+        state.soc.celsius = Some(62.3);
+        state.soc.throttled = Some(crate::health::parse_throttled("0x50005").unwrap());
+        let text = Metrics::new().unwrap().render(&state, &BusMetrics::default()).unwrap();
+        assert!(text.contains("pi_door_soc_temperature_celsius 62.3"));
+        assert!(text.contains("pi_door_soc_throttled{flag=\"throttled\"} 1"));
+        assert!(text.contains("pi_door_soc_throttled{flag=\"frequency_capped\"} 0"));
+    }
+
+    #[test]
+    fn test_domain_counters_and_summary() {
+        let metrics = Metrics::new().unwrap();
+        let mut state = SharedState::new();
+        assert_eq!(metrics.summary(&state).state_transitions, 0);
+
+        metrics.record_transition(AlarmState::Disarmed, AlarmState::ExitDelay);
+        metrics.record_transition(AlarmState::ExitDelay, AlarmState::Armed);
+        metrics.record_alarm(AlarmKind::Burglar);
+        state.cloud_link.connects = 3;
+        state.cloud_link.events_forwarded = 12;
+        state.cloud_link.queue_depth = 4;
+
+        let text = metrics.render(&state, &BusMetrics::default()).unwrap();
+        assert!(text.contains("pi_door_state_transitions_total{from=\"disarmed\",to=\"exit_delay\"} 1"));
+        assert!(text.contains("pi_door_alarms_triggered_total{kind=\"burglar\"} 1"));
+        assert!(text.contains("pi_door_events_forwarded_total 12"));
+        assert!(text.contains("pi_door_queue_depth 4"));
+
+        let summary = metrics.summary(&state);
+        assert_eq!((summary.state_transitions, summary.alarms_triggered), (2, 1));
+        assert_eq!((summary.events_forwarded, summary.queue_depth, summary.reconnects), (12, 4, 2));
+        #[cfg(target_os = "linux")]
+        assert!(text.contains("process_resident_memory_bytes") && summary.resident_memory_bytes.is_some());
+    }
+
+    #[test]
+    fn test_event_loop_and_bus() {
+        let metrics = Metrics::new().unwrap();
+        let mut state = SharedState::new();
+        metrics.record_event("door_open", Duration::from_millis(2), Duration::from_millis(30));
+        metrics.record_event("door_open", Duration::from_millis(1), Duration::from_millis(3));
+        state.event_loop.lagging = true;
+        let mut bus = BusMetrics { queue_depth: 7, broadcast_depth: 40, ..BusMetrics::default() };
+        bus.lagged.insert("journal".to_string(), 5);
+
+        let text = metrics.render(&state, &bus).unwrap();
+        assert!(text.contains("pi_door_events_processed_total{event=\"door_open\"} 2"));
+        assert!(text.contains("pi_door_event_processing_seconds_bucket{event=\"door_open\",le=\"0.005\"} 1"));
+        assert!(text.contains("pi_door_event_queue_wait_seconds_count 2"));
+        assert!(text.contains("pi_door_event_bus_depth 7"));
+        assert!(text.contains("pi_door_event_broadcast_depth 40"));
+        assert!(text.contains("pi_door_event_broadcast_lagged_total{subscriber=\"journal\"} 5"));
+        assert!(text.contains("pi_door_event_loop_lagging 1"));
+    }
+}
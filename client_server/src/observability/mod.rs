@@ -1,17 +1,101 @@
 //! Observability module for logging and metrics
 
+mod crash;
+mod log_file;
+pub mod metrics;
+
+pub use crash::{CrashReport, CrashReporter};
+pub use log_file::LogFile;
+pub use metrics::{Metrics, MetricsSummary};
+
+use crate::config::AppConfig;
+use crate::events::EventBus;
 use anyhow::Result;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::{reload, util::SubscriberInitExt, EnvFilter, Layer, Registry};
+
+type FileLayer = Option<Box<dyn Layer<Registry> + Send + Sync>>;
+type WithFile = Layered<reload::Layer<FileLayer, Registry>, Registry>;
+
+/// Adds the log file once the configuration has been read, and changes the log level
+#[derive(Clone)]
+pub struct LogHandle {
+    file: reload::Handle<FileLayer, Registry>,
+    filter: reload::Handle<EnvFilter, WithFile>,
+}
+
+impl LogHandle {
+    /// Log at `system.log_level`, unless `RUST_LOG` chose the level
+    pub fn set_level(&self, level: &str) -> Result<()> {
+        if std::env::var_os(EnvFilter::DEFAULT_ENV).is_some() {
+            return Ok(());
+        }
+        let filter = EnvFilter::try_new(level)?;
+        self.filter.reload(filter)?;
+        Ok(())
+    }
+
+    /// Also write logs to rotated files when `logging.file` is set
+    pub fn add_file(&self, config: &AppConfig) -> Result<()> {
+        if !config.logging.file {
+            return Ok(());
+        }
+        let dir = config.log_dir();
+        let file = LogFile::open(&dir, &config.logging)?;
+        let layer = tracing_subscriber::fmt::layer().json().with_ansi(false).with_writer(Mutex::new(file));
+        self.file.reload(Some(layer.boxed()))?;
+        info!(dir = %dir.display(), "Logging to files");
+        Ok(())
+    }
+}
 
 /// Initialize logging system
-pub fn init_logging() -> Result<()> {
+pub fn init_logging() -> Result<LogHandle> {
     let env_filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new("info"));
+    let (file, file_handle) = reload::Layer::new(FileLayer::None);
+    let (filter, filter_handle) = reload::Layer::new(env_filter);
 
     tracing_subscriber::registry()
-        .with(env_filter)
+        .with(file)
+        .with(filter)
         .with(tracing_subscriber::fmt::layer().json())
         .init();
 
-    Ok(())
+    Ok(LogHandle { file: file_handle, filter: filter_handle })
+}
+
+/// Periodically log event bus metrics, as a warning whenever events were dropped since the last report
+pub fn spawn_bus_metrics(event_bus: EventBus, every: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        ticker.tick().await;
+        let mut last_dropped = 0;
+        loop {
+            ticker.tick().await;
+            let metrics = event_bus.metrics();
+            if metrics.dropped > last_dropped {
+                warn!(
+                    queue_depth = metrics.queue_depth,
+                    peak_depth = metrics.peak_depth,
+                    broadcast_depth = metrics.broadcast_depth,
+                    dropped = metrics.dropped - last_dropped,
+                    "Event bus overflowed"
+                );
+            } else {
+                debug!(
+                    queue_depth = metrics.queue_depth,
+                    peak_depth = metrics.peak_depth,
+                    broadcast_depth = metrics.broadcast_depth,
+                    emitted = metrics.emitted,
+                    "Event bus metrics"
+                );
+            }
+            last_dropped = metrics.dropped;
+        }
+    })
 }
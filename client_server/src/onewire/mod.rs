@@ -0,0 +1,225 @@
+//! DS18B20 temperature monitoring over the kernel 1-Wire (w1) sysfs interface
+
+use crate::config::{OneWireConfig, TemperatureSensorConfig};
+use crate::events::{Event, EventBus, TemperatureLimit};
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::Path;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Raw value a DS18B20 reports before its first conversion after power-up
+const POWER_ON_RESET_MILLIDEGREES: i32 = 85_000;
+
+/// Parse the two-line `w1_slave` file into degrees Celsius
+///
+/// ```text
+/// 72 01 4b 46 7f ff 0e 10 57 : crc=57 YES
+/// 72 01 4b 46 7f ff 0e 10 57 t=23125
+/// ```
+pub fn parse_w1_slave(contents: &str) -> Result<f64> {
+    let mut lines = contents.lines();
+    let crc_line = lines.next().ok_or_else(|| anyhow!("Empty w1_slave reading"))?;
+    if !crc_line.trim_end().ends_with("YES") {
+        bail!("w1_slave reading failed CRC check");
+    }
+
+    let data_line = lines.next().ok_or_else(|| anyhow!("w1_slave reading has no data line"))?;
+    let (_, raw) = data_line
+        .split_once("t=")
+        .ok_or_else(|| anyhow!("w1_slave reading has no temperature"))?;
+    let millidegrees: i32 = raw.trim().parse().context("Invalid w1_slave temperature")?;
+    if millidegrees == POWER_ON_RESET_MILLIDEGREES {
+        bail!("Sensor returned its power-on reset value");
+    }
+
+    Ok(millidegrees as f64 / 1000.0)
+}
+
+/// Read one sensor from the w1 devices directory
+pub async fn read_sensor(devices_dir: &Path, id: &str) -> Result<f64> {
+    let path = devices_dir.join(id).join("w1_slave");
+    let contents = tokio::fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_w1_slave(&contents)
+}
+
+/// Limit state of one sensor, with hysteresis so readings near a limit do not flap
+#[derive(Debug, Default)]
+struct ThresholdTracker {
+    exceeded: Option<TemperatureLimit>,
+}
+
+impl ThresholdTracker {
+    /// Feed a reading and return the alert or restore event it causes, if any
+    fn update(&mut self, sensor: &TemperatureSensorConfig, hysteresis_c: f64, celsius: f64) -> Option<Event> {
+        let crossed = match (sensor.high_c, sensor.low_c) {
+            (Some(high), _) if celsius >= high => Some(TemperatureLimit::High),
+            (_, Some(low)) if celsius <= low => Some(TemperatureLimit::Low),
+            _ => None,
+        };
+
+        match (self.exceeded, crossed) {
+            (None, Some(limit)) => {
+                self.exceeded = Some(limit);
+                Some(Event::TemperatureAlert {
+                    sensor: sensor.name.clone(),
+                    celsius,
+                    limit,
+                })
+            }
+            (Some(current), Some(limit)) if current != limit => {
+                self.exceeded = Some(limit);
+                Some(Event::TemperatureAlert {
+                    sensor: sensor.name.clone(),
+                    celsius,
+                    limit,
+                })
+            }
+            (Some(limit), None) => {
+                let cleared = match limit {
+                    TemperatureLimit::High => sensor.high_c.is_none_or(|high| celsius <= high - hysteresis_c),
+                    TemperatureLimit::Low => sensor.low_c.is_none_or(|low| celsius >= low + hysteresis_c),
+                };
+                if !cleared {
+                    return None;
+                }
+                self.exceeded = None;
+                Some(Event::TemperatureRestored {
+                    sensor: sensor.name.clone(),
+                    celsius,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Periodically reads every configured sensor and emits telemetry and alerts
+pub struct TemperatureMonitor {
+    config: OneWireConfig,
+    event_bus: EventBus,
+}
+
+impl TemperatureMonitor {
+    pub fn new(config: OneWireConfig, event_bus: EventBus) -> Self {
+        Self { config, event_bus }
+    }
+
+    /// Spawn the polling task
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            info!(sensors = self.config.sensors.len(), "Temperature monitor started");
+            let mut trackers: Vec<ThresholdTracker> =
+                self.config.sensors.iter().map(|_| ThresholdTracker::default()).collect();
+            let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_s));
+
+            loop {
+                interval.tick().await;
+                for (sensor, tracker) in self.config.sensors.iter().zip(trackers.iter_mut()) {
+                    if let Err(e) = self.poll(sensor, tracker).await {
+                        warn!(sensor = %sensor.name, error = %e, "Temperature reading failed");
+                    }
+                }
+            }
+        })
+    }
+
+    async fn poll(&self, sensor: &TemperatureSensorConfig, tracker: &mut ThresholdTracker) -> Result<()> {
+        let celsius = read_sensor(&self.config.devices_dir, &sensor.id).await?;
+        self.event_bus.emit(Event::TemperatureReading {
+            sensor: sensor.name.clone(),
+            celsius,
+        })?;
+
+        if let Some(event) = tracker.update(sensor, self.config.hysteresis_c, celsius) {
+            warn!(?event, "Temperature limit changed");
+            self.event_bus.emit(event)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sensor() -> TemperatureSensorConfig {
+        TemperatureSensorConfig {
+            name: "garage".to_string(),
+            id: "28-0316a2791cff".to_string(),
+            high_c: Some(40.0),
+            low_c: Some(2.0),
+        }
+    }
+
+    #[test]
+    fn test_parse_w1_slave() {
+        let ok = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert_eq!(parse_w1_slave(ok).unwrap(), 23.125);
+
+        let negative = "5e ff 4b 46 7f ff 02 10 d0 : crc=d0 YES\n5e ff 4b 46 7f ff 02 10 d0 t=-10125\n";
+        assert_eq!(parse_w1_slave(negative).unwrap(), -10.125);
+
+        let bad_crc = "72 01 4b 46 7f ff 0e 10 57 : crc=00 NO\n72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert!(parse_w1_slave(bad_crc).is_err());
+
+        let reset = "50 05 4b 46 7f ff 0c 10 1c : crc=1c YES\n50 05 4b 46 7f ff 0c 10 1c t=85000\n";
+        assert!(parse_w1_slave(reset).is_err());
+    }
+
+    #[test]
+    fn test_threshold_hysteresis() {
+        let sensor = sensor();
+        let mut tracker = ThresholdTracker::default();
+
+        assert!(tracker.update(&sensor, 0.5, 30.0).is_none());
+        assert!(matches!(
+            tracker.update(&sensor, 0.5, 41.0),
+            Some(Event::TemperatureAlert { limit: TemperatureLimit::High, .. })
+        ));
+        assert!(tracker.update(&sensor, 0.5, 42.0).is_none());
+
+        // Inside the limit but within the hysteresis band: still alerting
+        assert!(tracker.update(&sensor, 0.5, 39.8).is_none());
+        assert!(matches!(
+            tracker.update(&sensor, 0.5, 39.0),
+            Some(Event::TemperatureRestored { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_monitor_emits_reading() {
+        let dir = tempfile::tempdir().unwrap();
+        let device = dir.path().join("28-0316a2791cff");
+        std::fs::create_dir(&device).unwrap();
+        std::fs::write(
+            device.join("w1_slave"),
+            "00 00 00 00 00 00 00 00 00 : crc=00 YES\n00 00 00 00 00 00 00 00 00 t=1500\n",
+        )
+        .unwrap();
+
+        let config = OneWireConfig {
+            enabled: true,
+            devices_dir: dir.path().to_path_buf(),
+            sensors: vec![sensor()],
+            ..Default::default()
+        };
+        let (bus, mut rx) = EventBus::new();
+        let handle = TemperatureMonitor::new(config, bus).spawn();
+
+        match rx.recv().await.unwrap() {
+            Event::TemperatureReading { sensor, celsius } => {
+                assert_eq!(sensor, "garage");
+                assert_eq!(celsius, 1.5);
+            }
+            other => panic!("Unexpected event: {:?}", other),
+        }
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::TemperatureAlert { limit: TemperatureLimit::Low, .. }
+        ));
+        handle.abort();
+    }
+}
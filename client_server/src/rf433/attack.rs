@@ -0,0 +1,152 @@
+//! Detection of attacks on the 433 MHz band
+//!
+//! A jammer keeps the receiver's data line saturated so sensor frames never
+//! get through; a replay attack plays back a recorded remote over and over.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Flags a data line that stays high for most of every window
+pub struct JammingDetector {
+    duty: f64,
+    after: Duration,
+    window_start: Instant,
+    high: Duration,
+    high_since: Option<Instant>,
+    jammed_since: Option<Instant>,
+    reported: bool,
+}
+
+impl JammingDetector {
+    /// Report once the line is high for `duty` of the time for `after`
+    pub fn new(duty: f64, after: Duration, now: Instant) -> Self {
+        Self {
+            duty,
+            after,
+            window_start: now,
+            high: Duration::ZERO,
+            high_since: None,
+            jammed_since: None,
+            reported: false,
+        }
+    }
+
+    /// Record a level change
+    pub fn level(&mut self, high: bool, now: Instant) {
+        match (self.high_since, high) {
+            (None, true) => self.high_since = Some(now),
+            (Some(since), false) => {
+                self.high += now.saturating_duration_since(since);
+                self.high_since = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Close the current window; returns how long the band has been jammed when that first exceeds `after`
+    pub fn tick(&mut self, now: Instant) -> Option<Duration> {
+        if let Some(since) = self.high_since.as_mut() {
+            self.high += now.saturating_duration_since(*since);
+            *since = now;
+        }
+        let window = now.saturating_duration_since(self.window_start);
+        let high = std::mem::take(&mut self.high);
+        self.window_start = now;
+        if window.is_zero() {
+            return None;
+        }
+
+        if high.as_secs_f64() < window.as_secs_f64() * self.duty {
+            self.jammed_since = None;
+            self.reported = false;
+            return None;
+        }
+        let jammed = now.saturating_duration_since(*self.jammed_since.get_or_insert(now - window));
+        if self.reported || jammed < self.after {
+            return None;
+        }
+        self.reported = true;
+        Some(jammed)
+    }
+}
+
+/// Flags a fixed code pressed suspiciously often
+pub struct ReplayDetector {
+    presses: usize,
+    window: Duration,
+    seen: HashMap<u32, VecDeque<Instant>>,
+}
+
+impl ReplayDetector {
+    /// Report a code pressed `presses` times within `window`; 0 disables
+    pub fn new(presses: usize, window: Duration) -> Self {
+        Self {
+            presses,
+            window,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Record a press; true when it reaches the threshold
+    pub fn press(&mut self, code: u32, now: Instant) -> bool {
+        if self.presses == 0 {
+            return false;
+        }
+        let window = self.window;
+        self.seen.retain(|_, times| {
+            times.retain(|at| now.saturating_duration_since(*at) < window);
+            !times.is_empty()
+        });
+        let times = self.seen.entry(code).or_default();
+        times.push_back(now);
+        times.len() == self.presses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jamming() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut detector = JammingDetector::new(0.8, Duration::from_secs(3), start);
+
+        // Ordinary noise toggles around half duty
+        for ms in (0..1000).step_by(10) {
+            detector.level(ms % 20 == 0, at(ms));
+        }
+        assert_eq!(detector.tick(at(1000)), None);
+
+        // A carrier holds the line high with no edges at all
+        detector.level(true, at(1000));
+        assert_eq!(detector.tick(at(2000)), None);
+        assert_eq!(detector.tick(at(3000)), None);
+        assert_eq!(detector.tick(at(4000)), Some(Duration::from_secs(3)));
+        assert_eq!(detector.tick(at(5000)), None);
+
+        // Each episode is reported once
+        detector.level(false, at(5100));
+        assert_eq!(detector.tick(at(6000)), None);
+        detector.level(true, at(6000));
+        assert_eq!(detector.tick(at(8000)), None);
+        assert_eq!(detector.tick(at(9000)), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn test_replay() {
+        let start = Instant::now();
+        let at = |s| start + Duration::from_secs(s);
+        let mut detector = ReplayDetector::new(3, Duration::from_secs(60));
+
+        assert!(!detector.press(0xA1B2C3, at(0)));
+        assert!(!detector.press(0xA1B2C3, at(10)));
+        assert!(!detector.press(0x123456, at(20)));
+        assert!(detector.press(0xA1B2C3, at(30)));
+        // Reported once, and old presses expire
+        assert!(!detector.press(0xA1B2C3, at(40)));
+        assert!(!detector.press(0xA1B2C3, at(100)));
+        assert!(!ReplayDetector::new(0, Duration::from_secs(60)).press(1, at(0)));
+    }
+}
@@ -0,0 +1,170 @@
+//! Collapsing repeated frames into button presses
+//!
+//! A remote repeats its frame every ~50 ms for as long as the button is held.
+//! Frames of one code are a burst until the code has been quiet for its
+//! debounce time; a burst becomes a press once a repeat confirms it is not noise.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Remotes repeat a frame every ~50 ms while held; a code counts once a repeat confirms it
+const REPEAT_WINDOW: Duration = Duration::from_millis(250);
+
+/// Frame and press counts of the fixed-code receiver since the agent started
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RfMetrics {
+    /// Fixed-code frames decoded
+    pub frames: u64,
+    /// Button presses reported, one per burst
+    pub presses: u64,
+    /// Repeats dropped as part of a press already reported
+    pub suppressed: u64,
+    /// Lone frames that were never repeated, most likely noise
+    pub unconfirmed: u64,
+    /// Presses whose repeats have ended
+    pub bursts: u64,
+    pub burst_frames_avg: Option<f64>,
+    pub burst_frames_max: Option<u32>,
+    /// How long the longest press kept repeating
+    pub burst_ms_max: Option<u64>,
+}
+
+impl RfMetrics {
+    fn record_burst(&mut self, burst: &Burst) {
+        if !burst.pressed {
+            self.unconfirmed += 1;
+            return;
+        }
+        self.bursts += 1;
+        let frames = burst.frames as f64;
+        self.burst_frames_avg = Some(match self.burst_frames_avg {
+            Some(avg) => avg + (frames - avg) / self.bursts as f64,
+            None => frames,
+        });
+        self.burst_frames_max = self.burst_frames_max.max(Some(burst.frames));
+        let ms = burst.last.duration_since(burst.started).as_millis() as u64;
+        self.burst_ms_max = self.burst_ms_max.max(Some(ms));
+    }
+}
+
+#[derive(Debug)]
+struct Burst {
+    started: Instant,
+    last: Instant,
+    frames: u32,
+    pressed: bool,
+}
+
+/// Turns frames into presses, with a debounce per code
+#[derive(Debug)]
+pub struct PressFilter {
+    debounce: Duration,
+    overrides: HashMap<u32, Duration>,
+    bursts: HashMap<u32, Burst>,
+    metrics: RfMetrics,
+}
+
+impl PressFilter {
+    /// Codes stay held until quiet for `debounce`, or their entry in `overrides`
+    pub fn new(debounce: Duration, overrides: HashMap<u32, Duration>) -> Self {
+        Self {
+            debounce,
+            overrides,
+            bursts: HashMap::new(),
+            metrics: RfMetrics::default(),
+        }
+    }
+
+    /// Replace the per-code debounce times, e.g. after `rf433.mappings` were reloaded
+    pub fn set_overrides(&mut self, overrides: HashMap<u32, Duration>) {
+        self.overrides = overrides;
+    }
+
+    fn debounce_for(&self, code: u32) -> Duration {
+        self.overrides.get(&code).copied().unwrap_or(self.debounce)
+    }
+
+    /// Record a frame; true when it makes a new press
+    pub fn frame(&mut self, code: u32, now: Instant) -> bool {
+        self.metrics.frames += 1;
+        let debounce = self.debounce_for(code);
+        if let Some(burst) = self.bursts.get_mut(&code) {
+            let gap = now.saturating_duration_since(burst.last);
+            if gap < debounce {
+                burst.last = now;
+                burst.frames += 1;
+                if burst.pressed || gap > REPEAT_WINDOW {
+                    self.metrics.suppressed += 1;
+                    return false;
+                }
+                burst.pressed = true;
+                self.metrics.presses += 1;
+                return true;
+            }
+        }
+
+        let burst = Burst {
+            started: now,
+            last: now,
+            frames: 1,
+            pressed: false,
+        };
+        if let Some(ended) = self.bursts.insert(code, burst) {
+            self.metrics.record_burst(&ended);
+        }
+        false
+    }
+
+    /// Close bursts that have gone quiet, so their statistics are counted
+    pub fn expire(&mut self, now: Instant) {
+        let ended: Vec<u32> = self
+            .bursts
+            .iter()
+            .filter(|(code, burst)| now.saturating_duration_since(burst.last) >= self.debounce_for(**code))
+            .map(|(code, _)| *code)
+            .collect();
+        for code in ended {
+            if let Some(burst) = self.bursts.remove(&code) {
+                self.metrics.record_burst(&burst);
+            }
+        }
+    }
+
+    pub fn metrics(&self) -> &RfMetrics {
+        &self.metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_press_per_burst() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let overrides = HashMap::from([(0xB, Duration::from_secs(2))]);
+        let mut filter = PressFilter::new(Duration::from_millis(500), overrides);
+
+        // A held button, interleaved with another remote
+        let presses: Vec<bool> = [(0xA, 0), (0xB, 20), (0xA, 50), (0xB, 70), (0xA, 100), (0xA, 150)]
+            .into_iter()
+            .map(|(code, ms)| filter.frame(code, at(ms)))
+            .collect();
+        assert_eq!(presses, [false, false, true, true, false, false]);
+
+        // A lone frame is noise; B is still held under its longer debounce
+        assert!(!filter.frame(0xC, at(400)));
+        filter.expire(at(1000));
+        assert!(!filter.frame(0xA, at(1100)));
+        assert!(filter.frame(0xA, at(1150)));
+        assert!(!filter.frame(0xB, at(1500)));
+
+        let metrics = filter.metrics();
+        assert_eq!((metrics.frames, metrics.presses, metrics.suppressed), (10, 3, 3));
+        assert_eq!((metrics.unconfirmed, metrics.bursts), (1, 1));
+        assert_eq!(metrics.burst_frames_max, Some(4));
+        assert_eq!(metrics.burst_ms_max, Some(150));
+    }
+}
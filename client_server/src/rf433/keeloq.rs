@@ -0,0 +1,326 @@
+//! KeeLoq rolling-code remotes
+//!
+//! Each code word carries the serial number and buttons in clear and a
+//! hopping part encrypting a 16-bit counter the remote increments on every
+//! press. A code is only accepted if its counter moved forward, so a
+//! recorded transmission cannot be played back later.
+
+use super::parse_number;
+use crate::config::{Rf433Config, RollingButtonConfig};
+use crate::security::SecretStore;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Secret holding the manufacturer key remote keys are derived from (normal learning)
+pub const MANUFACTURER_KEY: &str = "keeloq_manufacturer_key";
+
+/// Secret holding the key of one remote, overriding the derived one
+pub fn remote_key_secret(name: &str) -> String {
+    format!("keeloq_key.{}", name)
+}
+
+const NLF: u32 = 0x3A5C_742E;
+
+fn bit(value: u64, n: u32) -> u32 {
+    ((value >> n) & 1) as u32
+}
+
+fn nlf(x: u32, taps: [u32; 5]) -> u32 {
+    let index = taps
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, &tap)| acc | (bit(x as u64, tap) << i));
+    bit(NLF as u64, index)
+}
+
+pub fn encrypt(data: u32, key: u64) -> u32 {
+    (0..528u32).fold(data, |x, r| {
+        let feedback = bit(x as u64, 0) ^ bit(x as u64, 16) ^ bit(key, r & 63) ^ nlf(x, [1, 9, 20, 26, 31]);
+        (x >> 1) | (feedback << 31)
+    })
+}
+
+pub fn decrypt(data: u32, key: u64) -> u32 {
+    (0..528u32).fold(data, |x, r| {
+        let feedback =
+            bit(x as u64, 31) ^ bit(x as u64, 15) ^ bit(key, 15u32.wrapping_sub(r) & 63) ^ nlf(x, [0, 8, 19, 25, 30]);
+        (x << 1) | feedback
+    })
+}
+
+/// Key of a remote programmed by normal learning from its serial number
+pub fn learning_key(serial: u32, manufacturer_key: u64) -> u64 {
+    let low = decrypt(serial | 0x2000_0000, manufacturer_key);
+    let high = decrypt(serial | 0x6000_0000, manufacturer_key);
+    (high as u64) << 32 | low as u64
+}
+
+/// A received code word, not yet verified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollingFrame {
+    pub hopping: u32,
+    /// 28-bit serial number
+    pub serial: u32,
+    pub buttons: u8,
+    pub low_battery: bool,
+}
+
+impl RollingFrame {
+    /// Code as reported in events, `<serial>:<buttons>`
+    pub fn code(&self) -> String {
+        format!("0x{:07X}:{}", self.serial, self.buttons)
+    }
+}
+
+/// Outcome of checking a code word
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Fresh code from a registered remote
+    Accepted { remote: String, button: u8 },
+    /// The same code word again, as sent while a button is held
+    Repeat,
+    /// The counter jumped past the window; the next press completes the resync
+    Resync { remote: String },
+    /// Not a registered remote
+    Unknown,
+    /// A code word whose counter did not move forward
+    Replayed { remote: String },
+    Rejected { remote: String, reason: &'static str },
+}
+
+/// Last counter accepted from each remote, kept in a JSON file under the data directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CounterStore {
+    #[serde(skip)]
+    path: PathBuf,
+    counters: BTreeMap<String, u16>,
+}
+
+impl CounterStore {
+    fn load(path: &Path) -> Result<Self> {
+        let mut store: Self = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid counter file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        store.path = path.to_path_buf();
+        Ok(store)
+    }
+
+    /// Write the store atomically so a power cut cannot roll a counter back
+    fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path).with_context(|| format!("Failed to replace {}", self.path.display()))
+    }
+
+    fn key(serial: u32) -> String {
+        format!("0x{:07X}", serial)
+    }
+}
+
+struct RollingRemote {
+    name: String,
+    serial: u32,
+    key: u64,
+    buttons: Vec<RollingButtonConfig>,
+}
+
+/// Registered rolling-code remotes and their counters
+pub struct RollingCodes {
+    remotes: Vec<RollingRemote>,
+    window: u16,
+    hold: Duration,
+    counters: CounterStore,
+    /// Counter of the first press of a resync, per serial
+    resync: HashMap<u32, u16>,
+    last_word: Option<(u32, u32, Instant)>,
+}
+
+impl RollingCodes {
+    /// Load the configured remotes with their keys and the persisted counters
+    pub fn load(config: &Rf433Config, secrets: &SecretStore, counters_path: &Path) -> Result<Self> {
+        let manufacturer_key = secrets.get(MANUFACTURER_KEY)?.map(|key| parse_key(&key)).transpose()?;
+        let mut remotes = Vec::new();
+        for remote in &config.remotes {
+            let serial = parse_serial(&remote.serial)?;
+            let key = match secrets.get(&remote_key_secret(&remote.name))? {
+                Some(key) => parse_key(&key)?,
+                None => match manufacturer_key {
+                    Some(manufacturer_key) => learning_key(serial, manufacturer_key),
+                    None => {
+                        warn!(remote = %remote.name, "No KeeLoq key for remote, ignoring it");
+                        continue;
+                    }
+                },
+            };
+            remotes.push(RollingRemote {
+                name: remote.name.clone(),
+                serial,
+                key,
+                buttons: remote.buttons.clone(),
+            });
+        }
+        info!(remotes = remotes.len(), "Rolling-code remotes loaded");
+        Ok(Self {
+            remotes,
+            window: config.counter_window,
+            hold: Duration::from_millis(config.debounce_ms),
+            counters: CounterStore::load(counters_path)?,
+            resync: HashMap::new(),
+            last_word: None,
+        })
+    }
+
+    /// Commands configured for a button of a remote
+    pub fn buttons<'a>(&'a self, remote: &'a str, button: u8) -> impl Iterator<Item = &'a RollingButtonConfig> {
+        self.remotes
+            .iter()
+            .filter(move |known| known.name == remote)
+            .flat_map(|known| known.buttons.iter())
+            .filter(move |config| config.button == button)
+    }
+
+    /// Verify a code word and advance the remote's counter if it is fresh
+    pub fn check(&mut self, frame: &RollingFrame, now: Instant) -> Verdict {
+        if let Some((serial, hopping, at)) = &mut self.last_word {
+            if (*serial, *hopping) == (frame.serial, frame.hopping) && now.duration_since(*at) < self.hold {
+                *at = now;
+                return Verdict::Repeat;
+            }
+        }
+        self.last_word = Some((frame.serial, frame.hopping, now));
+
+        let Some(remote) = self.remotes.iter().find(|known| known.serial == frame.serial) else {
+            return Verdict::Unknown;
+        };
+        let name = remote.name.clone();
+        let plain = decrypt(frame.hopping, remote.key);
+        let counter = plain as u16;
+        // The encrypted part repeats the buttons and the low serial bits
+        if (plain >> 28) as u8 != frame.buttons || (plain >> 16) & 0x3ff != frame.serial & 0x3ff {
+            return Verdict::Rejected {
+                remote: name,
+                reason: "decryption check failed",
+            };
+        }
+
+        let key = CounterStore::key(frame.serial);
+        let ahead = self.counters.counters.get(&key).map(|stored| counter.wrapping_sub(*stored));
+        match ahead {
+            Some(ahead) if ahead >= 1 && ahead <= self.window => {}
+            Some(ahead) if ahead == 0 || ahead >= 0x8000 => return Verdict::Replayed { remote: name },
+            // Never seen or too far ahead: only two consecutive presses prove the remote
+            _ => {
+                if self.resync.get(&frame.serial) != Some(&counter.wrapping_sub(1)) {
+                    self.resync.insert(frame.serial, counter);
+                    return Verdict::Resync { remote: name };
+                }
+                self.resync.remove(&frame.serial);
+                info!(remote = %name, counter, "Rolling-code remote resynchronised");
+            }
+        }
+
+        self.counters.counters.insert(key, counter);
+        if let Err(e) = self.counters.save() {
+            warn!(remote = %name, error = %e, "Failed to save rolling-code counter");
+        }
+        Verdict::Accepted {
+            remote: name,
+            button: frame.buttons,
+        }
+    }
+}
+
+/// Parse a configured serial number
+pub fn parse_serial(serial: &str) -> Result<u32> {
+    let value = parse_number(serial).context("Invalid KeeLoq serial")?;
+    if value > 0x0FFF_FFFF {
+        bail!("KeeLoq serial {} does not fit in 28 bits", serial);
+    }
+    Ok(value)
+}
+
+fn parse_key(key: &[u8]) -> Result<u64> {
+    let key: [u8; 8] = key.try_into().ok().context("KeeLoq keys are 8 bytes")?;
+    Ok(u64::from_be_bytes(key))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::{AppConfig, RollingRemoteConfig};
+    use tempfile::TempDir;
+
+    const MANUFACTURER: u64 = 0x0123_4567_89AB_CDEF;
+    const SERIAL: u32 = 0x1A2_B3C4;
+
+    /// Code word the remote sends for `counter`
+    pub(crate) fn press(key: u64, serial: u32, buttons: u8, counter: u16) -> RollingFrame {
+        let plain = (buttons as u32) << 28 | (serial & 0x3ff) << 16 | counter as u32;
+        RollingFrame {
+            hopping: encrypt(plain, key),
+            serial,
+            buttons,
+            low_battery: false,
+        }
+    }
+
+    fn codes(dir: &TempDir) -> RollingCodes {
+        let config = Rf433Config {
+            remotes: vec![RollingRemoteConfig {
+                name: "keyfob".to_string(),
+                serial: "0x1A2B3C4".to_string(),
+                buttons: vec![],
+            }],
+            ..AppConfig::test_default().rf433
+        };
+        let mut secrets = SecretStore::load(&dir.path().join("secrets.json")).unwrap();
+        secrets.set(MANUFACTURER_KEY, &MANUFACTURER.to_be_bytes()).unwrap();
+        RollingCodes::load(&config, &secrets, &dir.path().join("rf433_counters.json")).unwrap()
+    }
+
+    #[test]
+    fn test_cipher() {
+        assert_eq!(encrypt(0xF741_E2DB, 0x5CEC_6701_B79F_D949), 0xE44F_4CDF);
+        assert_eq!(decrypt(0xE44F_4CDF, 0x5CEC_6701_B79F_D949), 0xF741_E2DB);
+    }
+
+    #[test]
+    fn test_counter_window_and_resync() {
+        let dir = TempDir::new().unwrap();
+        let mut rolling = codes(&dir);
+        let key = learning_key(SERIAL, MANUFACTURER);
+        let start = Instant::now();
+        let at = |s| start + Duration::from_secs(s);
+
+        // A new remote needs two consecutive presses
+        assert!(matches!(rolling.check(&press(key, SERIAL, 2, 100), at(0)), Verdict::Resync { .. }));
+        assert_eq!(rolling.check(&press(key, SERIAL, 2, 100), at(0)), Verdict::Repeat);
+        assert!(matches!(rolling.check(&press(key, SERIAL, 2, 101), at(1)), Verdict::Accepted { button: 2, .. }));
+
+        // Presses out of range within the window are fine, old codes are not
+        assert!(matches!(rolling.check(&press(key, SERIAL, 1, 110), at(2)), Verdict::Accepted { .. }));
+        assert!(matches!(rolling.check(&press(key, SERIAL, 2, 101), at(3)), Verdict::Replayed { .. }));
+        assert!(matches!(
+            rolling.check(&press(key ^ 1, SERIAL, 2, 111), at(4)),
+            Verdict::Rejected { reason: "decryption check failed", .. }
+        ));
+        assert_eq!(rolling.check(&press(key, 0x0FF_FFFF, 2, 1), at(5)), Verdict::Unknown);
+
+        // Far ahead needs a resync, and counters survive a restart
+        assert!(matches!(rolling.check(&press(key, SERIAL, 2, 5000), at(6)), Verdict::Resync { .. }));
+        let mut rolling = codes(&dir);
+        assert!(matches!(rolling.check(&press(key, SERIAL, 2, 110), at(7)), Verdict::Replayed { .. }));
+        assert!(matches!(rolling.check(&press(key, SERIAL, 2, 111), at(8)), Verdict::Accepted { .. }));
+    }
+}
@@ -0,0 +1,102 @@
+//! Learn mode: collecting unknown codes so they can be bound to zones
+//!
+//! While a session is open, the receiver records every fixed code it does not
+//! know yet. A heard code can then be bound as a door contact, a PIR or a panic
+//! button, which saves it to the configuration.
+
+use crate::events::RfProtocol;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Codes heard in one session at most; the rest are noise or neighbours
+const MAX_HEARD: usize = 32;
+
+/// An unknown code heard during learn mode
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeardCode {
+    pub code: String,
+    pub protocol: RfProtocol,
+    /// Presses heard; a sensor being tested shows up more than once
+    pub presses: u32,
+    pub last_heard: DateTime<Utc>,
+}
+
+/// The current or last learn session
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LearnSession {
+    /// When the session closes; `None` when none was opened
+    pub until: Option<DateTime<Utc>>,
+    /// Codes heard, most recent first
+    pub heard: Vec<HeardCode>,
+}
+
+impl LearnSession {
+    /// Open a session for `duration`, forgetting codes heard in earlier ones
+    pub fn start(&mut self, duration: Duration, now: DateTime<Utc>) {
+        self.until = Some(now + duration);
+        self.heard.clear();
+    }
+
+    /// Close the session, keeping what was heard for binding
+    pub fn stop(&mut self, now: DateTime<Utc>) {
+        self.until = self.until.map(|until| until.min(now));
+    }
+
+    pub fn active(&self, now: DateTime<Utc>) -> bool {
+        self.until.is_some_and(|until| now < until)
+    }
+
+    /// Record a press of an unknown code while the session is open
+    pub fn hear(&mut self, code: &str, protocol: RfProtocol, now: DateTime<Utc>) {
+        if !self.active(now) {
+            return;
+        }
+        let mut heard = match self.heard.iter().position(|heard| heard.code == code) {
+            Some(i) => self.heard.remove(i),
+            None => HeardCode {
+                code: code.to_string(),
+                protocol,
+                presses: 0,
+                last_heard: now,
+            },
+        };
+        heard.presses += 1;
+        heard.last_heard = now;
+        self.heard.insert(0, heard);
+        self.heard.truncate(MAX_HEARD);
+    }
+
+    /// Remove a heard code once it has been bound
+    pub fn take(&mut self, code: &str) -> Option<HeardCode> {
+        let i = self.heard.iter().position(|heard| heard.code == code)?;
+        Some(self.heard.remove(i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_collects_codes_while_open() {
+        let start = Utc::now();
+        let at = |s| start + Duration::seconds(s);
+        let mut session = LearnSession::default();
+
+        session.hear("0x00A001", RfProtocol::Ev1527, at(0));
+        assert!(session.heard.is_empty());
+
+        session.start(Duration::seconds(60), at(0));
+        session.hear("0x00A001", RfProtocol::Ev1527, at(1));
+        session.hear("0x00B002", RfProtocol::Pt2262, at(2));
+        session.hear("0x00A001", RfProtocol::Ev1527, at(3));
+        session.hear("0x00C003", RfProtocol::Ev1527, at(61));
+        let codes: Vec<_> = session.heard.iter().map(|heard| (heard.code.as_str(), heard.presses)).collect();
+        assert_eq!(codes, [("0x00A001", 2), ("0x00B002", 1)]);
+
+        // Heard codes stay bindable after the session closes
+        assert!(!session.active(at(61)));
+        assert!(session.take("0x00B002").is_some());
+        assert!(session.take("0x00B002").is_none());
+    }
+}
@@ -1,4 +1,850 @@
-//! 433MHz RF receiver module
-//! TODO: Implement RF433 receiver integration and code decoding
+//! 433MHz RF module
+//!
+//! Decodes remotes on the receiver's data line and turns codes of the remote
+//! profiles in `rf433.mappings`, or buttons of registered rolling-code remotes,
+//! into commands. Each command is recorded as accepted or rejected with the
+//! name of the remote that sent it.
+//! A transmitter on `gpio.radio433_tx_out` switches the receivers in `rf433.outputs`,
+//! and sensors decoded by an external rtl_433 report as zones.
 
-pub struct Rf433Receiver;
+mod attack;
+mod burst;
+mod keeloq;
+mod learn;
+mod ook;
+mod rtl433;
+mod transmit;
+
+pub use attack::{JammingDetector, ReplayDetector};
+pub use burst::{PressFilter, RfMetrics};
+pub use keeloq::{
+    decrypt, encrypt, learning_key, parse_serial, remote_key_secret, RollingCodes, RollingFrame, Verdict, MANUFACTURER_KEY,
+};
+pub use learn::{HeardCode, LearnSession};
+pub use ook::{encode, OokDecoder, RfFrame};
+pub use rtl433::Rtl433Bridge;
+pub use transmit::RfTransmitter;
+
+use crate::config::{ConfigStore, Rf433Config, Rf433Mapping, RfSensorConfig};
+use crate::events::{command_to_event, Event, EventBus, EventSource, RfProtocol, ZoneType};
+use crate::gpio::GpioController;
+use crate::health::{Component, HealthStatus};
+use crate::security::{AuditEntry, AuditLog, AuditSource};
+use crate::state::{AlarmState, AppState};
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+/// Window over which the data line's duty is measured for jamming
+const JAMMING_WINDOW: Duration = Duration::from_secs(1);
+
+/// Parse a configured number, `0x...` or decimal
+fn parse_number(value: &str) -> Result<u32> {
+    let value = value.trim();
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .with_context(|| format!("Invalid number {}", value))
+}
+
+/// Parse a configured code, `0xA1B2C3` or decimal
+pub fn parse_code(code: &str) -> Result<u32> {
+    let value = parse_number(code).context("Invalid RF code")?;
+    if value > 0xFF_FFFF {
+        bail!("RF code {} does not fit in 24 bits", code);
+    }
+    Ok(value)
+}
+
+/// Code as reported in events, matching the configured form
+pub fn format_code(code: u32) -> String {
+    format!("0x{:06X}", code)
+}
+
+/// A fixed-code sensor zone, triggered by any of its codes until they go quiet
+#[derive(Debug)]
+struct SensorZone {
+    zone: String,
+    zone_type: ZoneType,
+    restore: Duration,
+    last: Option<Instant>,
+}
+
+impl SensorZone {
+    fn new(config: &RfSensorConfig) -> Self {
+        Self {
+            zone: config.zone.clone(),
+            zone_type: config.zone_type,
+            restore: Duration::from_secs(config.restore_s),
+            last: None,
+        }
+    }
+}
+
+/// Profile and entry index of every mapped code, and the codes with their own debounce
+fn map_codes(mappings: &[Rf433Mapping]) -> (HashMap<u32, (usize, usize)>, HashMap<u32, Duration>) {
+    let mut codes = HashMap::new();
+    let mut debounce = HashMap::new();
+    for (remote, mapping) in mappings.iter().enumerate() {
+        for (entry, code) in mapping.codes.iter().enumerate() {
+            match parse_code(&code.code) {
+                Ok(value) => {
+                    codes.insert(value, (remote, entry));
+                    if let Some(ms) = code.debounce_ms {
+                        debounce.insert(value, Duration::from_millis(ms));
+                    }
+                }
+                Err(e) => warn!(remote = %mapping.name, error = %e, "Ignoring RF code"),
+            }
+        }
+    }
+    (codes, debounce)
+}
+
+/// Receiver task translating remote codes into events
+pub struct Rf433Receiver {
+    gpio: Arc<dyn GpioController>,
+    pin: u8,
+    allow_disarm: bool,
+    mappings: Vec<Rf433Mapping>,
+    /// Profile and entry index of every mapped code
+    codes: HashMap<u32, (usize, usize)>,
+    event_bus: EventBus,
+    rolling: Option<RollingCodes>,
+    jamming: Option<JammingDetector>,
+    replay: ReplayDetector,
+    attack_alarm: bool,
+    state: Option<AppState>,
+    presses: PressFilter,
+    panic_codes: Vec<u32>,
+    /// How soon a second press must follow, when panic buttons need two
+    panic_window: Option<Duration>,
+    /// First press of a panic button still waiting for its second
+    panic_pending: Option<(u32, Instant)>,
+    sensors: Vec<SensorZone>,
+    /// Sensor zone of every sensor code
+    sensor_codes: HashMap<u32, usize>,
+    audit: Option<Arc<AuditLog>>,
+    config_store: Option<ConfigStore>,
+}
+
+impl Rf433Receiver {
+    pub fn new(gpio: Arc<dyn GpioController>, pin: u8, config: &Rf433Config, event_bus: EventBus) -> Self {
+        let (codes, debounce) = map_codes(&config.mappings);
+        let panic_codes = config
+            .panic_codes
+            .iter()
+            .filter_map(|code| match parse_code(code) {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    warn!(error = %e, "Ignoring RF panic code");
+                    None
+                }
+            })
+            .collect();
+        let mut sensor_codes = HashMap::new();
+        for (zone, sensor) in config.sensors.iter().enumerate() {
+            for code in &sensor.codes {
+                match parse_code(code) {
+                    Ok(value) => {
+                        sensor_codes.insert(value, zone);
+                    }
+                    Err(e) => warn!(zone = %sensor.zone, error = %e, "Ignoring RF sensor code"),
+                }
+            }
+        }
+        Self {
+            gpio,
+            pin,
+            allow_disarm: config.allow_disarm,
+            mappings: config.mappings.clone(),
+            codes,
+            event_bus,
+            rolling: None,
+            jamming: (config.jamming_s > 0)
+                .then(|| JammingDetector::new(config.jamming_duty, Duration::from_secs(config.jamming_s), Instant::now())),
+            replay: ReplayDetector::new(config.replay_presses, Duration::from_secs(config.replay_window_s)),
+            attack_alarm: config.attack_alarm,
+            state: None,
+            presses: PressFilter::new(Duration::from_millis(config.debounce_ms), debounce),
+            panic_codes,
+            panic_window: config
+                .panic_double_press
+                .then(|| Duration::from_millis(config.panic_window_ms)),
+            panic_pending: None,
+            sensors: config.sensors.iter().map(SensorZone::new).collect(),
+            sensor_codes,
+            audit: None,
+            config_store: None,
+        }
+    }
+
+    /// Let `rf433.attack_alarm` see whether the system is armed, and publish metrics there
+    pub fn set_state(&mut self, state: AppState) {
+        self.state = Some(state);
+    }
+
+    /// Accept the registered rolling-code remotes
+    pub fn set_rolling_codes(&mut self, rolling: RollingCodes) {
+        self.rolling = Some(rolling);
+    }
+
+    /// Record remote commands, accepted or rejected, in the audit log
+    pub fn set_audit(&mut self, audit: Arc<AuditLog>) {
+        self.audit = Some(audit);
+    }
+
+    /// Read `rf433.mappings` from the store again when a reload changes them
+    pub fn set_config_store(&mut self, store: ConfigStore) {
+        self.config_store = Some(store);
+    }
+
+    /// Spawn the decoder on the receiver's data line
+    pub fn spawn(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut levels = match self.gpio.watch_levels(self.pin).await {
+                Ok(levels) => levels,
+                Err(e) => {
+                    error!(pin = self.pin, error = %e, "433 MHz receiver unavailable");
+                    self.report_health(Err(format!("{:#}", e)));
+                    return;
+                }
+            };
+            info!(pin = self.pin, codes = self.codes.len(), "433 MHz receiver started");
+            self.report_health(Ok(()));
+
+            let mut decoder = OokDecoder::new();
+            let mut tick = tokio::time::interval(JAMMING_WINDOW);
+            let mut learned = self.event_bus.subscribe();
+            loop {
+                let events = tokio::select! {
+                    change = levels.recv() => {
+                        let Some(change) = change else { break };
+                        if let Some(jamming) = self.jamming.as_mut() {
+                            jamming.level(change.high, Instant::now());
+                        }
+                        match decoder.push(change) {
+                            Some(frame) => self.handle_frame(frame, Instant::now()),
+                            None => continue,
+                        }
+                    }
+                    _ = tick.tick() => self.tick(Instant::now()),
+                    received = learned.recv() => {
+                        match received {
+                            Ok(envelope) => {
+                                self.bind(&envelope.event);
+                                self.remap(&envelope.event);
+                            }
+                            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                                self.event_bus.note_lagged("rf433", missed);
+                                warn!(missed, "RF receiver lagged behind the event bus, codes learned meanwhile apply after a restart");
+                            }
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        }
+                        continue;
+                    }
+                };
+                for event in events {
+                    self.audit_command(&event);
+                    if let Err(e) = self.event_bus.emit(event) {
+                        error!(error = %e, "Failed to emit RF event");
+                    }
+                }
+            }
+            warn!(pin = self.pin, "433 MHz level stream ended");
+            self.report_health(Err("433 MHz level stream ended".to_string()));
+        })
+    }
+
+    fn report_health(&self, result: Result<(), String>) {
+        let Some(state) = &self.state else { return };
+        match result {
+            Ok(()) => state.write().health.report_ok(Component::Rf433),
+            Err(error) => state.write().health.report_error(Component::Rf433, HealthStatus::Failed, error),
+        }
+    }
+
+    fn audit_command(&self, event: &Event) {
+        let Some(audit) = &self.audit else { return };
+        let (remote, command, result) = match event {
+            Event::RfCommandAccepted { remote, command } => (remote, command, Ok(())),
+            Event::RfCommandRejected { remote, command, reason } => (remote, command, Err(reason)),
+            _ => return,
+        };
+        audit.record(AuditEntry::new(command, AuditSource::Rf { remote: remote.clone() }, &result));
+    }
+
+    /// Take up a code bound in learn mode without waiting for a restart
+    fn bind(&mut self, event: &Event) {
+        let Event::RfSensorLearned { code, kind, zone, .. } = event else {
+            return;
+        };
+        let Ok(value) = parse_code(code) else {
+            return;
+        };
+        match (kind.zone_type(), zone) {
+            (Some(zone_type), Some(zone)) => {
+                let index = match self.sensors.iter().position(|sensor| &sensor.zone == zone) {
+                    Some(index) => index,
+                    None => {
+                        self.sensors.push(SensorZone::new(&RfSensorConfig {
+                            zone: zone.clone(),
+                            label: None,
+                            zone_type,
+                            codes: vec![],
+                            restore_s: crate::config::default_restore_s(),
+                        }));
+                        self.sensors.len() - 1
+                    }
+                };
+                self.sensor_codes.insert(value, index);
+            }
+            _ => self.panic_codes.push(value),
+        }
+        info!(%code, ?kind, "Learned RF code active");
+    }
+
+    /// Take up `rf433.mappings` after a configuration reload
+    ///
+    /// The event only names what changed, so the codes themselves never reach the journal or the sinks.
+    fn remap(&mut self, event: &Event) {
+        let Event::ConfigReloaded { changed, .. } = event else {
+            return;
+        };
+        let Some(store) = self.config_store.as_ref().filter(|_| changed.iter().any(|c| c == "rf433.mappings")) else {
+            return;
+        };
+        let mappings = store.current().rf433.mappings;
+        let (codes, debounce) = map_codes(&mappings);
+        self.mappings = mappings;
+        self.codes = codes;
+        self.presses.set_overrides(debounce);
+        info!(codes = self.codes.len(), "RF mappings reloaded");
+    }
+
+    /// Events once a jamming window closes or sensor zones go quiet; also publishes the press metrics
+    fn tick(&mut self, now: Instant) -> Vec<Event> {
+        self.presses.expire(now);
+        let mut events = Vec::new();
+        for sensor in &mut self.sensors {
+            if sensor.last.is_some_and(|last| now.saturating_duration_since(last) >= sensor.restore) {
+                sensor.last = None;
+                events.push(Event::ZoneRestored {
+                    zone: sensor.zone.clone(),
+                    zone_type: sensor.zone_type,
+                });
+            }
+        }
+        if let Some(state) = &self.state {
+            if state.read().rf433 != *self.presses.metrics() {
+                state.write().rf433 = self.presses.metrics().clone();
+            }
+        }
+
+        if let Some(jammed) = self.jamming.as_mut().and_then(|jamming| jamming.tick(now)) {
+            warn!(seconds = jammed.as_secs(), "433 MHz band jammed");
+            events.extend(self.attack(Event::RfJamming {
+                duration_ms: jammed.as_millis() as u64,
+            }));
+        }
+        events
+    }
+
+    /// An attack event, followed by a tamper alarm if armed and `rf433.attack_alarm` is set
+    fn attack(&self, event: Event) -> Vec<Event> {
+        let armed = self.state.as_ref().is_some_and(|state| {
+            matches!(state.read().alarm_state, AlarmState::Armed | AlarmState::EntryDelay)
+        });
+        if self.attack_alarm && armed {
+            vec![event, Event::Tamper { device: "rf433".to_string() }]
+        } else {
+            vec![event]
+        }
+    }
+
+    /// Events for a decoded frame; repeats of a held button are reported once
+    fn handle_frame(&mut self, frame: RfFrame, now: Instant) -> Vec<Event> {
+        match frame {
+            RfFrame::Fixed { code, protocol } => self.handle_fixed(code, protocol, now),
+            RfFrame::Rolling(frame) => self.handle_rolling(&frame, now),
+        }
+    }
+
+    fn handle_fixed(&mut self, code: u32, protocol: RfProtocol, now: Instant) -> Vec<Event> {
+        if !self.presses.frame(code, now) {
+            return Vec::new();
+        }
+
+        let hex = format_code(code);
+        debug!(code = %hex, ?protocol, "RF code received");
+        let mut events = vec![Event::RfCodeReceived {
+            code: hex.clone(),
+            protocol,
+        }];
+        // Sensors trigger as often as people walk by, so they are not checked for replays
+        if let Some(&index) = self.sensor_codes.get(&code) {
+            let sensor = &mut self.sensors[index];
+            if sensor.last.replace(now).is_none() {
+                events.push(Event::ZoneTriggered {
+                    zone: sensor.zone.clone(),
+                    zone_type: sensor.zone_type,
+                });
+            }
+            return events;
+        }
+
+        let mapped = self.codes.get(&code).map(|&(remote, entry)| {
+            let mapping = &self.mappings[remote];
+            (mapping, &mapping.codes[entry])
+        });
+        if self.replay.press(code, now) {
+            warn!(code = %hex, "RF code pressed suspiciously often");
+            events.extend(self.attack(Event::RfReplaySuspected {
+                code: hex.clone(),
+                remote: mapped.map(|(mapping, _)| mapping.name.clone()),
+            }));
+        }
+
+        if self.panic_codes.contains(&code) {
+            events.extend(self.panic(code, now));
+            return events;
+        }
+
+        let Some((mapping, entry)) = mapped else {
+            if let Some(state) = &self.state {
+                state.write().rf_learn.hear(&hex, protocol, chrono::Utc::now());
+            }
+            return events;
+        };
+        let refused = if !mapping.enabled {
+            Some("remote is disabled")
+        } else if !mapping.allowed_actions.is_empty() && !mapping.allowed_actions.contains(&entry.action) {
+            Some("command is not allowed for this remote")
+        } else if entry.action == "disarm" && !self.allow_disarm {
+            Some("rf433.allow_disarm is off")
+        } else if entry.action == "disarm" && self.locked_down() {
+            Some("lockdown is in force")
+        } else {
+            None
+        };
+        events.extend(command(&mapping.name, &entry.action, &entry.args, refused));
+        events
+    }
+
+    /// Whether the master has locked remotes out of disarming
+    fn locked_down(&self) -> bool {
+        self.state.as_ref().is_some_and(|state| state.read().lockdown.is_some())
+    }
+
+    /// A panic alarm for a panic button's press, or its second press within the window when two are required
+    fn panic(&mut self, code: u32, now: Instant) -> Option<Event> {
+        if let Some(window) = self.panic_window {
+            let confirmed = self
+                .panic_pending
+                .take()
+                .is_some_and(|(pending, at)| pending == code && now.saturating_duration_since(at) <= window);
+            if !confirmed {
+                info!(code = %format_code(code), "Panic button pressed, press again to sound the alarm");
+                self.panic_pending = Some((code, now));
+                return None;
+            }
+        }
+        warn!(code = %format_code(code), "RF panic button pressed");
+        Some(Event::Panic { source: EventSource::Rf })
+    }
+
+    /// Verified codes of registered remotes run their button's commands, including disarm
+    fn handle_rolling(&mut self, frame: &RollingFrame, now: Instant) -> Vec<Event> {
+        let received = |code| Event::RfCodeReceived {
+            code,
+            protocol: RfProtocol::Keeloq,
+        };
+        let locked_down = self.locked_down();
+        // Unregistered remotes are reported so they can be added, but never acted on
+        let Some(rolling) = self.rolling.as_mut() else {
+            return vec![received(frame.code())];
+        };
+        match rolling.check(frame, now) {
+            Verdict::Accepted { remote, button } => {
+                debug!(%remote, button, low_battery = frame.low_battery, "Rolling code accepted");
+                let mut events = vec![received(frame.code())];
+                for config in rolling.buttons(&remote, button) {
+                    let refused = (config.action == "disarm" && locked_down).then_some("lockdown is in force");
+                    events.extend(command(&remote, &config.action, &config.args, refused));
+                }
+                events
+            }
+            Verdict::Repeat => Vec::new(),
+            Verdict::Resync { remote } => {
+                info!(%remote, "Rolling-code counter out of window, press again to resync");
+                Vec::new()
+            }
+            Verdict::Unknown => vec![received(frame.code())],
+            Verdict::Replayed { remote } => {
+                warn!(%remote, "Rolling code replayed");
+                let event = Event::RfReplaySuspected {
+                    code: frame.code(),
+                    remote: Some(remote),
+                };
+                self.attack(event)
+            }
+            Verdict::Rejected { remote, reason } => {
+                warn!(%remote, reason, "Rolling code rejected");
+                vec![Event::RfCodeRejected {
+                    remote,
+                    reason: reason.to_string(),
+                }]
+            }
+        }
+    }
+}
+
+/// Events for a remote's command, recording whether it ran and which remote sent it
+fn command(remote: &str, action: &str, args: &Value, refused: Option<&str>) -> Vec<Event> {
+    let rejected = |reason: String| {
+        warn!(%remote, command = action, %reason, "RF command rejected");
+        vec![Event::RfCommandRejected {
+            remote: remote.to_string(),
+            command: action.to_string(),
+            reason,
+        }]
+    };
+    if let Some(reason) = refused {
+        return rejected(reason.to_string());
+    }
+    match command_to_event(action, args, EventSource::Rf) {
+        Ok(event) => {
+            info!(%remote, command = action, "RF command accepted");
+            let accepted = Event::RfCommandAccepted {
+                remote: remote.to_string(),
+                command: action.to_string(),
+            };
+            vec![accepted, event]
+        }
+        Err(e) => rejected(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, Rf433Code, RollingButtonConfig, RollingRemoteConfig};
+    use crate::gpio::MockGpio;
+    use crate::security::SecretStore;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn config() -> Rf433Config {
+        Rf433Config {
+            mappings: vec![
+                Rf433Mapping {
+                    name: "hallway".to_string(),
+                    enabled: true,
+                    allowed_actions: vec![],
+                    codes: vec![
+                        Rf433Code {
+                            code: "0xA1B2C3".to_string(),
+                            action: "arm".to_string(),
+                            args: json!({ "mode": "stay" }),
+                            debounce_ms: None,
+                        },
+                        Rf433Code {
+                            code: "1193046".to_string(),
+                            action: "disarm".to_string(),
+                            args: json!({}),
+                            debounce_ms: None,
+                        },
+                    ],
+                },
+                Rf433Mapping {
+                    name: "guest".to_string(),
+                    enabled: true,
+                    allowed_actions: vec!["floodlight".to_string()],
+                    codes: vec![
+                        Rf433Code {
+                            code: "0x00F001".to_string(),
+                            action: "floodlight".to_string(),
+                            args: json!({ "on": true }),
+                            debounce_ms: None,
+                        },
+                        Rf433Code {
+                            code: "0x00F002".to_string(),
+                            action: "arm".to_string(),
+                            args: json!({}),
+                            debounce_ms: None,
+                        },
+                    ],
+                },
+            ],
+            counter_window: 16,
+            remotes: vec![RollingRemoteConfig {
+                name: "keyfob".to_string(),
+                serial: "0x1A2B3C4".to_string(),
+                buttons: vec![RollingButtonConfig {
+                    button: 2,
+                    action: "disarm".to_string(),
+                    args: json!({}),
+                }],
+            }],
+            replay_presses: 3,
+            attack_alarm: true,
+            ..AppConfig::test_default().rf433
+        }
+    }
+
+    fn frame(code: u32) -> RfFrame {
+        RfFrame::Fixed {
+            code,
+            protocol: RfProtocol::Ev1527,
+        }
+    }
+
+    #[test]
+    fn test_parse_code() {
+        assert_eq!(parse_code("0xA1B2C3").unwrap(), 0xA1B2C3);
+        assert_eq!(parse_code("1193046").unwrap(), 0x123456);
+        assert_eq!(format_code(0x00B2C3), "0x00B2C3");
+        assert!(parse_code("0x1000000").is_err());
+        assert!(parse_code("remote").is_err());
+    }
+
+    #[test]
+    fn test_held_button_and_disarm_gate() {
+        let (bus, _rx) = EventBus::new();
+        let mut receiver = Rf433Receiver::new(Arc::new(MockGpio::new()), 23, &config(), bus);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // A single frame could be noise
+        assert!(receiver.handle_frame(frame(0xA1B2C3), at(0)).is_empty());
+        let events = receiver.handle_frame(frame(0xA1B2C3), at(50));
+        assert!(matches!(
+            &events[..],
+            [
+                Event::RfCodeReceived { .. },
+                Event::RfCommandAccepted { remote, .. },
+                Event::UserArm { source: EventSource::Rf, mode: crate::state::ArmMode::Stay, .. }
+            ] if remote == "hallway"
+        ));
+        // Still held, then pressed again after release
+        assert!(receiver.handle_frame(frame(0xA1B2C3), at(100)).is_empty());
+        assert!(receiver.handle_frame(frame(0xA1B2C3), at(150)).is_empty());
+        assert!(receiver.handle_frame(frame(0xA1B2C3), at(1200)).is_empty());
+        assert_eq!(receiver.handle_frame(frame(0xA1B2C3), at(1250)).len(), 3);
+
+        // Disarm stays off unless allowed
+        receiver.handle_frame(frame(0x123456), at(2000));
+        let events = receiver.handle_frame(frame(0x123456), at(2050));
+        assert!(matches!(&events[..], [Event::RfCodeReceived { .. }, Event::RfCommandRejected { .. }]));
+        receiver.allow_disarm = true;
+        receiver.handle_frame(frame(0x123456), at(3000));
+        let events = receiver.handle_frame(frame(0x123456), at(3050));
+        assert!(matches!(
+            &events[..],
+            [Event::RfCodeReceived { .. }, Event::RfCommandAccepted { .. }, Event::UserDisarm { .. }]
+        ));
+
+        // Lockdown takes disarming away from remotes again
+        let state = crate::state::new_app_state();
+        state.write().lockdown = Some(crate::access::LockdownStatus { since: chrono::Utc::now(), reason: None, local_disarm: false });
+        receiver.set_state(state);
+        receiver.handle_frame(frame(0x123456), at(4000));
+        let events = receiver.handle_frame(frame(0x123456), at(4050));
+        assert!(matches!(events.last(), Some(Event::RfCommandRejected { reason, .. }) if reason == "lockdown is in force"));
+    }
+
+    #[test]
+    fn test_remote_permissions() {
+        let mut config = config();
+        let (bus, _rx) = EventBus::new();
+        let mut receiver = Rf433Receiver::new(Arc::new(MockGpio::new()), 23, &config, bus.clone());
+        let start = Instant::now();
+        let press = |receiver: &mut Rf433Receiver, code, s| {
+            receiver.handle_frame(frame(code), start + Duration::from_secs(s));
+            receiver.handle_frame(frame(code), start + Duration::from_secs(s) + Duration::from_millis(50))
+        };
+
+        let events = press(&mut receiver, 0x00F001, 0);
+        assert!(matches!(&events[..], [_, Event::RfCommandAccepted { .. }, Event::FloodlightControl { on: true, .. }]));
+        let events = press(&mut receiver, 0x00F002, 1);
+        assert!(matches!(
+            &events[..],
+            [_, Event::RfCommandRejected { remote, command, .. }] if remote == "guest" && command == "arm"
+        ));
+        // Unmapped codes are only reported
+        assert!(matches!(&press(&mut receiver, 0x00F003, 2)[..], [Event::RfCodeReceived { .. }]));
+
+        config.mappings[1].enabled = false;
+        let mut receiver = Rf433Receiver::new(Arc::new(MockGpio::new()), 23, &config, bus);
+        let events = press(&mut receiver, 0x00F001, 3);
+        assert!(matches!(&events[..], [_, Event::RfCommandRejected { reason, .. }] if reason == "remote is disabled"));
+
+        // Reloaded mappings apply to the running receiver
+        config.mappings[1].enabled = true;
+        let mut reloaded = AppConfig::test_default();
+        reloaded.rf433 = config.clone();
+        receiver.set_config_store(ConfigStore::new(reloaded, "/nonexistent/config.toml"));
+        receiver.remap(&Event::ConfigReloaded {
+            changed: vec!["rf433.mappings".to_string()],
+            restart_required: vec![],
+            timers: None,
+        });
+        let events = press(&mut receiver, 0x00F001, 4);
+        assert!(matches!(&events[..], [_, Event::RfCommandAccepted { .. }, Event::FloodlightControl { on: true, .. }]));
+    }
+
+    #[test]
+    fn test_panic_button_double_press() {
+        let mut config = config();
+        config.panic_codes = vec!["0x0BAD01".to_string()];
+        let (bus, _rx) = EventBus::new();
+        let mut receiver = Rf433Receiver::new(Arc::new(MockGpio::new()), 23, &config, bus.clone());
+        let start = Instant::now();
+        let press = |receiver: &mut Rf433Receiver, code, ms| {
+            receiver.handle_frame(frame(code), start + Duration::from_millis(ms));
+            receiver.handle_frame(frame(code), start + Duration::from_millis(ms + 50))
+        };
+        let panic = |events: Vec<Event>| matches!(events.last(), Some(Event::Panic { source: EventSource::Rf }));
+
+        assert!(panic(press(&mut receiver, 0x0BAD01, 0)));
+
+        config.panic_double_press = true;
+        let mut receiver = Rf433Receiver::new(Arc::new(MockGpio::new()), 23, &config, bus);
+        assert!(!panic(press(&mut receiver, 0x0BAD01, 0)));
+        // Too late for the first press, so it starts over
+        assert!(!panic(press(&mut receiver, 0x0BAD01, 5000)));
+        assert!(panic(press(&mut receiver, 0x0BAD01, 6000)));
+        assert!(!panic(press(&mut receiver, 0x0BAD01, 7000)));
+    }
+
+    #[test]
+    fn test_rolling_remote_may_disarm_but_not_replay() {
+        let dir = TempDir::new().unwrap();
+        let key = 0x0123_4567_89AB_CDEF;
+        let mut secrets = SecretStore::load(&dir.path().join("secrets.json")).unwrap();
+        secrets.set(&remote_key_secret("keyfob"), &u64::to_be_bytes(key)).unwrap();
+        let (bus, _rx) = EventBus::new();
+        let mut receiver = Rf433Receiver::new(Arc::new(MockGpio::new()), 23, &config(), bus);
+        // Without registered remotes, codes are only reported
+        let press = |counter| RfFrame::Rolling(keeloq::tests::press(key, 0x1A2_B3C4, 2, counter));
+        assert!(matches!(&receiver.handle_frame(press(1), Instant::now())[..], [Event::RfCodeReceived { .. }]));
+
+        let rolling = RollingCodes::load(&config(), &secrets, &dir.path().join("rf433_counters.json")).unwrap();
+        receiver.set_rolling_codes(rolling);
+        let start = Instant::now();
+        let at = |s| start + Duration::from_secs(s);
+        assert!(receiver.handle_frame(press(7), at(0)).is_empty());
+        let events = receiver.handle_frame(press(8), at(1));
+        assert!(matches!(
+            &events[..],
+            [
+                Event::RfCodeReceived { protocol: RfProtocol::Keeloq, .. },
+                Event::RfCommandAccepted { remote, .. },
+                Event::UserDisarm { source: EventSource::Rf, .. }
+            ] if remote == "keyfob"
+        ));
+        let events = receiver.handle_frame(press(7), at(2));
+        assert!(matches!(&events[..], [Event::RfReplaySuspected { remote: Some(remote), .. }] if remote == "keyfob"));
+    }
+
+    #[test]
+    fn test_sensor_zones_and_learning() {
+        let mut config = config();
+        config.sensors = vec![RfSensorConfig {
+            zone: "back_door".to_string(),
+            label: None,
+            zone_type: ZoneType::Perimeter,
+            codes: vec!["0x00C001".to_string()],
+            restore_s: 10,
+        }];
+        let (bus, _rx) = EventBus::new();
+        let mut receiver = Rf433Receiver::new(Arc::new(MockGpio::new()), 23, &config, bus);
+        let state = crate::state::new_app_state();
+        receiver.set_state(state.clone());
+        let start = Instant::now();
+        let press = |receiver: &mut Rf433Receiver, code, s| {
+            receiver.handle_frame(frame(code), start + Duration::from_secs(s));
+            receiver.handle_frame(frame(code), start + Duration::from_secs(s) + Duration::from_millis(50))
+        };
+
+        // Triggered until the sensor has been quiet for restore_s
+        let events = press(&mut receiver, 0x00C001, 0);
+        assert!(matches!(&events[..], [_, Event::ZoneTriggered { zone, .. }] if zone == "back_door"));
+        assert_eq!(press(&mut receiver, 0x00C001, 5).len(), 1);
+        assert!(receiver.tick(start + Duration::from_secs(12)).is_empty());
+        assert!(matches!(&receiver.tick(start + Duration::from_secs(16))[..], [Event::ZoneRestored { .. }]));
+
+        // Unknown codes are only collected while learning
+        press(&mut receiver, 0x00C002, 20);
+        state.write().rf_learn.start(chrono::Duration::seconds(60), chrono::Utc::now());
+        press(&mut receiver, 0x00C002, 21);
+        assert_eq!(state.read().rf_learn.heard[0].code, "0x00C002");
+
+        receiver.bind(&Event::RfSensorLearned {
+            code: "0x00C002".to_string(),
+            kind: crate::events::RfSensorKind::Motion,
+            zone: Some("hall".to_string()),
+            label: None,
+        });
+        let events = press(&mut receiver, 0x00C002, 22);
+        assert!(matches!(
+            &events[..],
+            [_, Event::ZoneTriggered { zone, zone_type: ZoneType::Interior }] if zone == "hall"
+        ));
+    }
+
+    #[test]
+    fn test_attacks_escalate_while_armed() {
+        let (bus, _rx) = EventBus::new();
+        let mut receiver = Rf433Receiver::new(Arc::new(MockGpio::new()), 23, &config(), bus);
+        let state = crate::state::new_app_state();
+        receiver.set_state(state.clone());
+        let start = Instant::now();
+        let at = |s| start + Duration::from_secs(s);
+
+        // The third press within the replay window looks like a replay
+        let mut events = Vec::new();
+        for press in 0..3 {
+            receiver.handle_frame(frame(0xA1B2C3), at(press * 10));
+            events = receiver.handle_frame(frame(0xA1B2C3), at(press * 10) + Duration::from_millis(50));
+        }
+        assert!(matches!(
+            &events[..],
+            [
+                Event::RfCodeReceived { .. },
+                Event::RfReplaySuspected { remote: Some(_), .. },
+                Event::RfCommandAccepted { .. },
+                Event::UserArm { .. }
+            ]
+        ));
+
+        state.write().alarm_state = AlarmState::Armed;
+        let events = receiver.attack(Event::RfJamming { duration_ms: 5000 });
+        assert!(matches!(&events[..], [Event::RfJamming { .. }, Event::Tamper { device }] if device == "rf433"));
+    }
+
+    #[tokio::test]
+    async fn test_receiver_emits_mapped_command() {
+        let gpio = MockGpio::new();
+        let (bus, mut rx) = EventBus::new();
+        Rf433Receiver::new(Arc::new(gpio.clone()), 23, &config(), bus).spawn();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        gpio.simulate_levels(23, &ook::tests::transmit(0xA1B2C3, 350, 4));
+        match rx.recv().await.unwrap() {
+            Event::RfCodeReceived { code, protocol } => {
+                assert_eq!(code, "0xA1B2C3");
+                assert_eq!(protocol, RfProtocol::Ev1527);
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+        assert!(matches!(rx.recv().await.unwrap(), Event::RfCommandAccepted { .. }));
+        assert!(matches!(rx.recv().await.unwrap(), Event::UserArm { source: EventSource::Rf, .. }));
+    }
+}
@@ -0,0 +1,277 @@
+//! Pulse-timing coding for OOK remotes
+//!
+//! EV1527 and PT2262 encoders send 24 bits as pulse pairs of one and three
+//! pulse lengths (T): a short high and long low is a zero, a long high and
+//! short low a one. Each frame ends with a short high and a sync gap of 31 T,
+//! which also tells the decoder the pulse length the remote uses. The same
+//! frames are encoded to switch wireless sirens, chimes and sockets.
+//!
+//! KeeLoq remotes send 66 bits LSB first, each three pulse lengths long but
+//! with the opposite coding (a short high is a one), followed by a guard
+//! gap the same decoder takes as the sync.
+
+use super::keeloq::RollingFrame;
+use crate::events::RfProtocol;
+use crate::gpio::LevelChange;
+use std::collections::VecDeque;
+
+/// Sync gaps accepted, covering pulse lengths of roughly 130-650 µs
+const MIN_SYNC_US: u64 = 4_000;
+const MAX_SYNC_US: u64 = 20_000;
+
+/// Sync gap after a fixed-code frame, in pulse lengths
+const SYNC_PULSES: u64 = 31;
+
+/// Durations in one frame: 24 bit pairs and the short high before the sync
+const FRAME_DURATIONS: usize = 49;
+
+/// Durations in a KeeLoq code word: 65 bit pairs and the high of the last bit,
+/// whose low runs into the guard gap
+const KEELOQ_DURATIONS: usize = 131;
+
+/// A frame decoded from the receiver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RfFrame {
+    /// EV1527 or PT2262 code
+    Fixed { code: u32, protocol: RfProtocol },
+    /// KeeLoq code word, verified by the receiver
+    Rolling(RollingFrame),
+}
+
+/// Turns level changes into frames, one duration at a time
+#[derive(Debug, Default)]
+pub struct OokDecoder {
+    durations: VecDeque<u64>,
+    last: Option<LevelChange>,
+}
+
+impl OokDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a level change; returns a frame when it ends a sync gap after a valid frame
+    pub fn push(&mut self, change: LevelChange) -> Option<RfFrame> {
+        let previous = self.last.replace(change)?;
+        if previous.high == change.high {
+            // An edge was lost, so the buffered durations no longer alternate
+            self.durations.clear();
+            return None;
+        }
+
+        let duration = change.at_us.saturating_sub(previous.at_us);
+        if !previous.high && (MIN_SYNC_US..=MAX_SYNC_US).contains(&duration) {
+            let durations = self.durations.make_contiguous();
+            let frame = decode(durations, duration).or_else(|| decode_keeloq(durations));
+            self.durations.clear();
+            return frame;
+        }
+
+        self.durations.push_back(duration);
+        if self.durations.len() > KEELOQ_DURATIONS {
+            self.durations.pop_front();
+        }
+        None
+    }
+}
+
+/// Decode the durations before a sync gap, starting with a high
+fn decode(durations: &[u64], sync_us: u64) -> Option<RfFrame> {
+    if durations.len() != FRAME_DURATIONS {
+        return None;
+    }
+    let t = sync_us / SYNC_PULSES;
+    let short = |d: u64| d * 2 >= t && d <= t * 2;
+    let long = |d: u64| d > t * 2 && d <= t * 5;
+
+    let mut code = 0u32;
+    for pair in durations[..FRAME_DURATIONS - 1].chunks(2) {
+        let bit = match (pair[0], pair[1]) {
+            (high, low) if short(high) && long(low) => 0,
+            (high, low) if long(high) && short(low) => 1,
+            _ => return None,
+        };
+        code = (code << 1) | bit;
+    }
+    if !short(durations[FRAME_DURATIONS - 1]) {
+        return None;
+    }
+
+    // PT2262 symbols are bit pairs 00, 11 or 01 (floating); 10 never occurs
+    let protocol = if (0..12).all(|symbol| (code >> (symbol * 2)) & 0b11 != 0b10) {
+        RfProtocol::Pt2262
+    } else {
+        RfProtocol::Ev1527
+    };
+    Some(RfFrame::Fixed { code, protocol })
+}
+
+/// Durations of a fixed-code frame sent `repeats` times with pulse length `t`, starting with a high
+pub fn encode(code: u32, t: u64, repeats: usize) -> Vec<u64> {
+    let mut frame = Vec::with_capacity(FRAME_DURATIONS + 1);
+    for bit in (0..24).rev().map(|i| (code >> i) & 1 == 1) {
+        frame.extend(if bit { [3 * t, t] } else { [t, 3 * t] });
+    }
+    frame.extend([t, SYNC_PULSES * t]);
+    frame.repeat(repeats)
+}
+
+/// Decode a KeeLoq code word from the durations before its guard gap
+fn decode_keeloq(durations: &[u64]) -> Option<RfFrame> {
+    // A shorter header gap leaves the preamble in front of the code word
+    let durations = durations.get(durations.len().checked_sub(KEELOQ_DURATIONS)?..)?;
+    // Every bit is three pulse lengths long
+    let te = durations[..KEELOQ_DURATIONS - 1].iter().sum::<u64>() / 195;
+    let short = |d: u64| d * 2 >= te && d * 2 <= te * 3;
+    let long = |d: u64| d * 2 > te * 3 && d <= te * 3;
+
+    let mut bits = 0u128;
+    for (i, pair) in durations[..KEELOQ_DURATIONS - 1].chunks(2).enumerate() {
+        let bit = match (pair[0], pair[1]) {
+            (high, low) if short(high) && long(low) => 1,
+            (high, low) if long(high) && short(low) => 0,
+            _ => return None,
+        };
+        bits |= bit << i;
+    }
+    match durations[KEELOQ_DURATIONS - 1] {
+        high if short(high) => bits |= 1 << 65,
+        high if long(high) => {}
+        _ => return None,
+    }
+
+    Some(RfFrame::Rolling(RollingFrame {
+        hopping: bits as u32,
+        serial: (bits >> 32) as u32 & 0x0FFF_FFFF,
+        buttons: (bits >> 60) as u8 & 0x0F,
+        low_battery: bits >> 64 & 1 == 1,
+    }))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Level changes as seen by a receiver, for durations starting with a high
+    fn levels(durations: &[u64]) -> Vec<LevelChange> {
+        let mut at_us = 1_000;
+        let mut high = true;
+        let mut changes = vec![LevelChange { high, at_us }];
+        for duration in durations {
+            at_us += duration;
+            high = !high;
+            changes.push(LevelChange { high, at_us });
+        }
+        changes
+    }
+
+    /// Level changes of a remote sending `code` `repeats` times with pulse length `t`
+    pub(crate) fn transmit(code: u32, t: u64, repeats: usize) -> Vec<LevelChange> {
+        let mut durations = Vec::new();
+        for _ in 0..repeats {
+            for bit in (0..24).rev().map(|i| (code >> i) & 1 == 1) {
+                // A little jitter, as from a real receiver
+                if bit {
+                    durations.extend([3 * t + 40, t - 30]);
+                } else {
+                    durations.extend([t + 30, 3 * t - 40]);
+                }
+            }
+            durations.extend([t, 31 * t]);
+        }
+
+        levels(&durations)
+    }
+
+    /// Level changes of a KeeLoq remote sending `frame` once with pulse length `te`
+    pub(crate) fn transmit_keeloq(frame: &RollingFrame, te: u64) -> Vec<LevelChange> {
+        let bits = frame.hopping as u128
+            | (frame.serial as u128) << 32
+            | (frame.buttons as u128) << 60
+            | (frame.low_battery as u128) << 64;
+        // Preamble of 12 pulses, then a header gap of 10 pulse lengths
+        let mut durations = vec![te; 23];
+        durations.push(10 * te);
+        for i in 0..66 {
+            if bits >> i & 1 == 1 {
+                durations.extend([te + 20, 2 * te - 20]);
+            } else {
+                durations.extend([2 * te - 20, te + 20]);
+            }
+        }
+        // The last low runs into the guard gap
+        let last = durations.pop().unwrap();
+        durations.push(last + 39 * te);
+
+        levels(&durations)
+    }
+
+    fn decode_all(changes: &[LevelChange]) -> Vec<RfFrame> {
+        let mut decoder = OokDecoder::new();
+        changes.iter().filter_map(|change| decoder.push(*change)).collect()
+    }
+
+    #[test]
+    fn test_decode_ev1527() {
+        let frames = decode_all(&transmit(0xA1B2C3, 350, 3));
+        let expected = RfFrame::Fixed {
+            code: 0xA1B2C3,
+            protocol: RfProtocol::Ev1527,
+        };
+        assert_eq!(frames, vec![expected; 3]);
+    }
+
+    #[test]
+    fn test_decode_pt2262() {
+        // Symbols 0 0 1 1 0 1 0 0 1 0 0 1
+        let frames = decode_all(&transmit(0x0F30C3, 180, 1));
+        assert_eq!(
+            frames,
+            vec![RfFrame::Fixed {
+                code: 0x0F30C3,
+                protocol: RfProtocol::Pt2262,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_decode_keeloq() {
+        let frame = RollingFrame {
+            hopping: 0xE44F_4CDF,
+            serial: 0x1A2_B3C4,
+            buttons: 0b0100,
+            low_battery: true,
+        };
+        // With a header gap long enough to pass for a sync, and one that is not
+        assert_eq!(decode_all(&transmit_keeloq(&frame, 400)), vec![RfFrame::Rolling(frame)]);
+        assert_eq!(decode_all(&transmit_keeloq(&frame, 300)), vec![RfFrame::Rolling(frame)]);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let durations = encode(0x5A0F33, 320, 2);
+        assert_eq!(durations.len(), 2 * (FRAME_DURATIONS + 1));
+        let expected = RfFrame::Fixed {
+            code: 0x5A0F33,
+            protocol: RfProtocol::Ev1527,
+        };
+        assert_eq!(decode_all(&levels(&durations)), vec![expected; 2]);
+    }
+
+    #[test]
+    fn test_rejects_noise() {
+        // Wrong pulse ratios
+        let mut changes = transmit(0xA1B2C3, 350, 1);
+        changes[5].at_us += 500;
+        assert!(decode_all(&changes).is_empty());
+
+        // A missed edge drops the frame in progress
+        let mut changes = transmit(0xA1B2C3, 350, 1);
+        changes.remove(10);
+        assert!(decode_all(&changes).is_empty());
+
+        // Too few bits before the sync gap
+        let changes = transmit(0xA1B2C3, 350, 1);
+        assert!(decode_all(&changes[20..]).is_empty());
+    }
+}
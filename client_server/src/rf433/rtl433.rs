@@ -0,0 +1,355 @@
+//! Wireless sensors decoded by an external `rtl_433`
+//!
+//! rtl_433 turns an SDR dongle into a receiver for hundreds of commodity door,
+//! window and PIR sensors. Its JSON output is read from a child process or
+//! from the topics it publishes to a broker, and the devices listed in
+//! `rtl433.sensors` are reported as zones. Sensors with a supervision interval
+//! are reported lost once they stay silent for longer.
+
+use crate::config::{Rtl433Config, Rtl433SensorConfig, Rtl433Source};
+use crate::events::{mqtt, Event, EventBus};
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+/// Wait before restarting rtl_433 or reconnecting to the broker
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// How often sensors are checked for restore and supervision
+const RESTORE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct Sensor {
+    config: Rtl433SensorConfig,
+    active: bool,
+    /// Last message, or when the bridge started for sensors not heard from yet
+    seen: Instant,
+    lost: bool,
+}
+
+impl Sensor {
+    /// The zone event for a change to `active`, if it is one
+    fn transition(&mut self, active: bool) -> Option<Event> {
+        if self.active == active {
+            return None;
+        }
+        self.active = active;
+        let zone = self.config.zone.clone();
+        let zone_type = self.config.zone_type;
+        Some(if active {
+            Event::ZoneTriggered { zone, zone_type }
+        } else {
+            Event::ZoneRestored { zone, zone_type }
+        })
+    }
+}
+
+/// Reads decoded messages and turns sensor changes into zone events
+pub struct Rtl433Bridge {
+    config: Rtl433Config,
+    client_id: String,
+    event_bus: EventBus,
+    sensors: Vec<Sensor>,
+}
+
+impl Rtl433Bridge {
+    pub fn new(config: &Rtl433Config, client_id: &str, event_bus: EventBus) -> Self {
+        let now = Instant::now();
+        Self {
+            sensors: config
+                .sensors
+                .iter()
+                .map(|sensor| Sensor {
+                    config: sensor.clone(),
+                    active: false,
+                    seen: now,
+                    lost: false,
+                })
+                .collect(),
+            config: config.clone(),
+            client_id: format!("{}-rtl433", client_id),
+            event_bus,
+        }
+    }
+
+    /// Spawn the reader and the task mapping its messages to zones
+    pub fn spawn(mut self) -> JoinHandle<()> {
+        let (tx, mut rx) = mpsc::channel(64);
+        let config = self.config.clone();
+        let client_id = self.client_id.clone();
+        tokio::spawn(async move {
+            loop {
+                let result = match config.source {
+                    Rtl433Source::Process => read_process(&config, &tx).await,
+                    Rtl433Source::Mqtt => read_mqtt(&config, &client_id, &tx).await,
+                };
+                if tx.is_closed() {
+                    break;
+                }
+                if let Err(e) = result {
+                    warn!(error = %e, "rtl_433 input lost, retrying");
+                }
+                tokio::time::sleep(RESTART_DELAY).await;
+            }
+        });
+
+        tokio::spawn(async move {
+            info!(source = ?self.config.source, sensors = self.sensors.len(), "rtl_433 bridge started");
+            let mut restore_check = tokio::time::interval(RESTORE_CHECK_INTERVAL);
+            loop {
+                let events = tokio::select! {
+                    message = rx.recv() => match message {
+                        Some(message) => self.handle(&message, Instant::now()),
+                        None => break,
+                    },
+                    _ = restore_check.tick() => self.expire(Instant::now()),
+                };
+                for event in events {
+                    if let Err(e) = self.event_bus.emit(event) {
+                        error!(error = %e, "Failed to emit rtl_433 event");
+                    }
+                }
+            }
+        })
+    }
+
+    /// Zone events caused by one decoded message
+    fn handle(&mut self, message: &Value, now: Instant) -> Vec<Event> {
+        let model = message.get("model").and_then(Value::as_str).unwrap_or_default();
+        let id = match message.get("id") {
+            Some(Value::String(id)) => id.clone(),
+            Some(Value::Number(id)) => id.to_string(),
+            _ => String::new(),
+        };
+
+        let Some(sensor) = self
+            .sensors
+            .iter_mut()
+            .find(|sensor| sensor.config.model == model && sensor.config.id == id)
+        else {
+            debug!(model, id = %id, "Ignoring unmapped rtl_433 device");
+            return vec![];
+        };
+        sensor.seen = now;
+
+        // Any message, supervision or not, shows the sensor is alive
+        let mut events = vec![];
+        if sensor.lost {
+            info!(zone = %sensor.config.zone, "Wireless sensor heard from again");
+            sensor.lost = false;
+            events.push(Event::SensorRestored {
+                sensor: sensor.config.zone.clone(),
+            });
+        }
+
+        let active = match &sensor.config.field {
+            Some(field) => match message.get(field).and_then(is_active) {
+                Some(value) => value,
+                None => {
+                    debug!(zone = %sensor.config.zone, field = %field, "rtl_433 message without a usable state");
+                    return events;
+                }
+            },
+            None => true,
+        };
+        events.extend(sensor.transition(active));
+        events
+    }
+
+    /// Restore sensors that only report triggers once they have been quiet for
+    /// `restore_s`, and report those silent beyond `supervision_s` as lost
+    fn expire(&mut self, now: Instant) -> Vec<Event> {
+        let mut events = vec![];
+        for sensor in &mut self.sensors {
+            let silent = now.saturating_duration_since(sensor.seen);
+            if sensor.config.field.is_none() && silent >= Duration::from_secs(sensor.config.restore_s) {
+                events.extend(sensor.transition(false));
+            }
+            let overdue = sensor
+                .config
+                .supervision_s
+                .is_some_and(|supervision_s| silent > Duration::from_secs(supervision_s));
+            if overdue && !sensor.lost {
+                warn!(zone = %sensor.config.zone, silent_s = silent.as_secs(), "Wireless sensor lost");
+                sensor.lost = true;
+                events.push(Event::SensorLost {
+                    sensor: sensor.config.zone.clone(),
+                    silent_s: silent.as_secs(),
+                });
+            }
+        }
+        events
+    }
+}
+
+/// Whether a decoded state field means open, alarm or motion
+fn is_active(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(value) => Some(*value),
+        Value::Number(value) => value.as_f64().map(|value| value != 0.0),
+        Value::String(value) => match value.to_ascii_lowercase().as_str() {
+            "open" | "opened" | "on" | "alarm" | "motion" | "true" | "1" => Some(true),
+            "closed" | "close" | "off" | "ok" | "normal" | "false" | "0" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Run rtl_433 and forward each JSON line until it exits
+async fn read_process(config: &Rtl433Config, tx: &mpsc::Sender<Value>) -> Result<()> {
+    let (program, args) = config.command.split_first().context("rtl433.command is empty")?;
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start {}", program))?;
+    info!(program = %program, "rtl_433 started");
+
+    let stdout = child.stdout.take().context("rtl_433 stdout unavailable")?;
+    let mut lines = BufReader::new(stdout).lines();
+    while let Some(line) = lines.next_line().await? {
+        // rtl_433 also prints banners and statistics; only JSON objects are messages
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        if tx.send(message).await.is_err() {
+            return Ok(());
+        }
+    }
+
+    let status = child.wait().await?;
+    bail!("rtl_433 exited ({})", status)
+}
+
+/// Subscribe to rtl_433's event topics and forward each message until the connection drops
+async fn read_mqtt(config: &Rtl433Config, client_id: &str, tx: &mpsc::Sender<Value>) -> Result<()> {
+    let mut stream = mqtt::connect(
+        &config.mqtt_host,
+        config.mqtt_port,
+        client_id,
+        config.mqtt_username.as_deref(),
+        config.mqtt_password.as_deref(),
+    )
+    .await?;
+    mqtt::subscribe(&mut stream, &config.mqtt_topic).await?;
+    info!(host = %config.mqtt_host, topic = %config.mqtt_topic, "Subscribed to rtl_433 events");
+
+    loop {
+        let (kind, body) = mqtt::read_packet(&mut stream).await?;
+        let Some((topic, payload)) = mqtt::parse_publish(kind, &body) else {
+            continue;
+        };
+        match serde_json::from_slice::<Value>(payload) {
+            Ok(message) => {
+                if tx.send(message).await.is_err() {
+                    return Ok(());
+                }
+            }
+            Err(e) => debug!(topic, error = %e, "Ignoring non-JSON rtl_433 message"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::ZoneType;
+    use serde_json::json;
+
+    fn sensor(zone: &str, id: &str, field: Option<&str>) -> Rtl433SensorConfig {
+        Rtl433SensorConfig {
+            zone: zone.to_string(),
+            zone_type: ZoneType::Perimeter,
+            model: "Honeywell-Security".to_string(),
+            id: id.to_string(),
+            field: field.map(str::to_string),
+            restore_s: 10,
+            supervision_s: None,
+        }
+    }
+
+    fn bridge() -> Rtl433Bridge {
+        let config = Rtl433Config {
+            enabled: true,
+            sensors: vec![sensor("patio_door", "123456", Some("reed_open")), sensor("hall_pir", "0x2A", None)],
+            ..Rtl433Config::default()
+        };
+        Rtl433Bridge::new(&config, "door-1", EventBus::new().0)
+    }
+
+    #[test]
+    fn test_state_field_transitions() {
+        let mut bridge = bridge();
+        let now = Instant::now();
+        let message = |open| json!({"model": "Honeywell-Security", "id": 123456, "reed_open": open});
+
+        assert!(matches!(
+            &bridge.handle(&message(1), now)[..],
+            [Event::ZoneTriggered { zone, .. }] if zone == "patio_door"
+        ));
+        // Sensors repeat their state; only changes are events
+        assert!(bridge.handle(&message(1), now).is_empty());
+        assert!(matches!(&bridge.handle(&message(0), now)[..], [Event::ZoneRestored { .. }]));
+
+        // Other devices and messages without the field are ignored
+        assert!(bridge
+            .handle(&json!({"model": "Honeywell-Security", "id": 999, "reed_open": 1}), now)
+            .is_empty());
+        assert!(bridge
+            .handle(&json!({"model": "Honeywell-Security", "id": 123456, "battery_ok": 1}), now)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_trigger_only_sensor_restores() {
+        let mut bridge = bridge();
+        let start = Instant::now();
+        let message = json!({"model": "Honeywell-Security", "id": "0x2A", "event": 128});
+
+        assert_eq!(bridge.handle(&message, start).len(), 1);
+        assert!(bridge.expire(start + Duration::from_secs(5)).is_empty());
+        assert!(bridge.handle(&message, start + Duration::from_secs(8)).is_empty());
+        assert!(bridge.expire(start + Duration::from_secs(15)).is_empty());
+        assert!(matches!(
+            &bridge.expire(start + Duration::from_secs(18))[..],
+            [Event::ZoneRestored { zone, .. }] if zone == "hall_pir"
+        ));
+    }
+
+    #[test]
+    fn test_silent_sensor_lost_and_restored() {
+        let mut bridge = bridge();
+        bridge.sensors[0].config.supervision_s = Some(3600);
+        let start = bridge.sensors[0].seen;
+        let at = |s| start + Duration::from_secs(s);
+        let message = json!({"model": "Honeywell-Security", "id": 123456, "reed_open": 0});
+
+        // Supervision frames reset the silence
+        assert!(bridge.handle(&message, at(3000)).is_empty());
+        assert!(bridge.expire(at(6000)).is_empty());
+        assert!(matches!(
+            &bridge.expire(at(6601))[..],
+            [Event::SensorLost { sensor, silent_s: 3601 }] if sensor == "patio_door"
+        ));
+        // Reported once until heard from again
+        assert!(bridge.expire(at(9000)).is_empty());
+        assert!(matches!(&bridge.handle(&message, at(9500))[..], [Event::SensorRestored { .. }]));
+    }
+
+    #[test]
+    fn test_is_active() {
+        assert_eq!(is_active(&json!(true)), Some(true));
+        assert_eq!(is_active(&json!(0)), Some(false));
+        assert_eq!(is_active(&json!("OPEN")), Some(true));
+        assert_eq!(is_active(&json!("closed")), Some(false));
+        assert_eq!(is_active(&json!("tamper")), None);
+    }
+}
@@ -0,0 +1,117 @@
+//! Switching wireless sirens, chimes and sockets through a 433 MHz transmitter
+
+use super::{format_code, ook, parse_code};
+use crate::config::{Rf433Config, RfOutputConfig};
+use crate::gpio::GpioController;
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing::info;
+
+/// Sends the codes of `rf433.outputs` on the transmitter's data pin
+pub struct RfTransmitter {
+    gpio: Arc<dyn GpioController>,
+    pin: u8,
+    outputs: Vec<RfOutputConfig>,
+    /// Last state sent to each output; receivers give no feedback
+    sent: Mutex<BTreeMap<String, bool>>,
+}
+
+impl RfTransmitter {
+    pub fn new(gpio: Arc<dyn GpioController>, pin: u8, config: &Rf433Config) -> Self {
+        Self {
+            gpio,
+            pin,
+            outputs: config.outputs.clone(),
+            sent: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Configured outputs
+    pub fn outputs(&self) -> &[RfOutputConfig] {
+        &self.outputs
+    }
+
+    /// Whether an output with this name is configured
+    pub fn contains(&self, name: &str) -> bool {
+        self.outputs.iter().any(|output| output.name == name)
+    }
+
+    /// Bring an output to `on`, sending a code only when that changes its state
+    pub async fn switch(&self, name: &str, on: bool) -> Result<()> {
+        let output = self
+            .outputs
+            .iter()
+            .find(|output| output.name == name)
+            .ok_or_else(|| anyhow!("No RF output named {}", name))?;
+        if self.sent.lock().get(name) == Some(&on) {
+            return Ok(());
+        }
+
+        // One-shot receivers have no off code; they simply stop
+        let code = if on { Some(&output.on_code) } else { output.off_code.as_ref() };
+        if let Some(code) = code {
+            let code = parse_code(code)?;
+            info!(output = name, on, code = %format_code(code), "Sending RF code");
+            self.gpio
+                .send_pulses(self.pin, &ook::encode(code, output.pulse_us, output.repeats))
+                .await?;
+        }
+        self.sent.lock().insert(name.to_string(), on);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::gpio::MockGpio;
+
+    #[tokio::test]
+    async fn test_switch_sends_on_change() {
+        let config = Rf433Config {
+            outputs: vec![
+                RfOutputConfig {
+                    name: "socket".to_string(),
+                    on_code: "0x15A001".to_string(),
+                    off_code: Some("0x15A002".to_string()),
+                    pulse_us: 300,
+                    repeats: 4,
+                    pulse_ms: None,
+                    follow_siren: false,
+                },
+                RfOutputConfig {
+                    name: "chime".to_string(),
+                    on_code: "0x15A010".to_string(),
+                    off_code: None,
+                    pulse_us: 300,
+                    repeats: 4,
+                    pulse_ms: Some(1000),
+                    follow_siren: false,
+                },
+            ],
+            ..AppConfig::test_default().rf433
+        };
+        let gpio = Arc::new(MockGpio::new());
+        let transmitter = RfTransmitter::new(gpio.clone(), 24, &config);
+
+        transmitter.switch("socket", true).await.unwrap();
+        transmitter.switch("socket", true).await.unwrap();
+        transmitter.switch("socket", false).await.unwrap();
+        transmitter.switch("chime", true).await.unwrap();
+        transmitter.switch("chime", false).await.unwrap();
+        assert!(transmitter.switch("gate", true).await.is_err());
+
+        let trains = gpio.pulse_trains(24);
+        assert_eq!(
+            trains,
+            vec![
+                ook::encode(0x15A001, 300, 4),
+                ook::encode(0x15A002, 300, 4),
+                ook::encode(0x15A010, 300, 4),
+            ]
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! Device key held in an ATECC608 secure element
+//!
+//! The key is written once into `signing.secure_element.slot`, which must be
+//! configured as a secret slot that allows clear writes but no reads. HMACs
+//! are computed on the chip with its SHA engine, 64 bytes at a time.
+
+use anyhow::{bail, Context, Result};
+use i2cdev::core::I2CDevice;
+use i2cdev::linux::LinuxI2CDevice;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use super::secrets::KeyHardware;
+
+const OP_SHA: u8 = 0x47;
+const OP_WRITE: u8 = 0x12;
+
+const SHA_HMAC_START: u8 = 0x04;
+const SHA_UPDATE: u8 = 0x01;
+/// HMAC end with the digest returned in the response only
+const SHA_HMAC_END: u8 = 0x02 | 0xC0;
+/// 32-byte write to the data zone
+const WRITE_DATA_BLOCK: u8 = 0x82;
+
+/// Word address bytes written ahead of a command, or to idle the chip
+const WORD_COMMAND: u8 = 0x03;
+const WORD_IDLE: u8 = 0x02;
+
+/// What the chip answers right after waking up
+const WAKE_RESPONSE: [u8; 4] = [0x04, 0x11, 0x33, 0x43];
+/// Longest a command runs before its response can be read
+const EXECUTION_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// CRC-16 of the ATECC family: polynomial 0x8005, bits fed LSB first
+fn crc16(data: &[u8]) -> [u8; 2] {
+    let mut crc: u16 = 0;
+    for byte in data {
+        for bit in 0..8 {
+            let data_bit = (byte >> bit) & 1;
+            let crc_bit = (crc >> 15) as u8;
+            crc <<= 1;
+            if data_bit != crc_bit {
+                crc ^= 0x8005;
+            }
+        }
+    }
+    crc.to_le_bytes()
+}
+
+/// Command packet: count, opcode, parameters, data and CRC
+fn packet(opcode: u8, param1: u8, param2: u16, data: &[u8]) -> Vec<u8> {
+    let mut packet = vec![(7 + data.len()) as u8, opcode, param1];
+    packet.extend(param2.to_le_bytes());
+    packet.extend(data);
+    let crc = crc16(&packet);
+    packet.extend(crc);
+    packet
+}
+
+/// Payload of a response, after checking its count and CRC
+fn parse_response(response: &[u8]) -> Result<&[u8]> {
+    let count = *response.first().context("Empty response from secure element")? as usize;
+    if count < 4 || count > response.len() {
+        bail!("Malformed response from secure element");
+    }
+    let (body, crc) = response[..count].split_at(count - 2);
+    if crc16(body) != crc {
+        bail!("Corrupted response from secure element");
+    }
+    let payload = &body[1..];
+    // A one-byte payload is a status; zero means success
+    if let &[status] = payload {
+        if status != 0 {
+            bail!("Secure element returned status 0x{:02x}", status);
+        }
+    }
+    Ok(payload)
+}
+
+/// HMAC key in one slot of the chip
+#[derive(Debug)]
+pub struct SecureElementKey {
+    bus: PathBuf,
+    address: u16,
+    slot: u8,
+}
+
+impl SecureElementKey {
+    /// Check the chip answers at `address` on `bus`
+    pub fn open(bus: &Path, address: u16, slot: u8) -> Result<Self> {
+        if slot > 15 {
+            bail!("Secure element slot {} does not exist", slot);
+        }
+        let key = Self {
+            bus: bus.to_path_buf(),
+            address,
+            slot,
+        };
+        key.session().context("Secure element not found")?;
+        Ok(key)
+    }
+
+    /// Wake the chip and return the device to talk to it through
+    fn session(&self) -> Result<LinuxI2CDevice> {
+        // Holding SDA low long enough wakes it: address 0 at 100 kHz does that
+        if let Ok(mut general_call) = LinuxI2CDevice::new(&self.bus, 0) {
+            let _ = general_call.write(&[0]);
+        }
+        sleep(Duration::from_micros(1500));
+        let mut device = LinuxI2CDevice::new(&self.bus, self.address)
+            .with_context(|| format!("Failed to open {}", self.bus.display()))?;
+        let mut response = [0; 4];
+        device.read(&mut response).context("Secure element did not wake up")?;
+        if response != WAKE_RESPONSE {
+            bail!("Unexpected wake response {:02x?}", response);
+        }
+        Ok(device)
+    }
+
+    /// Send one command and wait for its response of `len` bytes
+    fn command(device: &mut LinuxI2CDevice, packet: &[u8], len: usize) -> Result<Vec<u8>> {
+        let mut bytes = vec![WORD_COMMAND];
+        bytes.extend(packet);
+        device.write(&bytes).context("Failed to send command to secure element")?;
+
+        // The chip does not acknowledge its address until the command is done
+        let deadline = Instant::now() + EXECUTION_TIMEOUT;
+        let mut response = vec![0; len.max(4)];
+        loop {
+            sleep(Duration::from_millis(1));
+            match device.read(&mut response) {
+                Ok(()) => break,
+                Err(_) if Instant::now() < deadline => continue,
+                Err(e) => return Err(e).context("Secure element did not answer"),
+            }
+        }
+        Ok(parse_response(&response)?.to_vec())
+    }
+}
+
+impl KeyHardware for SecureElementKey {
+    fn describe(&self) -> String {
+        format!("ATECC608 slot {} on {}", self.slot, self.bus.display())
+    }
+
+    fn import(&mut self, key: &[u8]) -> Result<()> {
+        if key.len() != 32 {
+            bail!("Secure element slots hold 32-byte keys");
+        }
+        let mut device = self.session()?;
+        let address = u16::from(self.slot) << 3;
+        let written = Self::command(&mut device, &packet(OP_WRITE, WRITE_DATA_BLOCK, address, key), 4);
+        let _ = device.write(&[WORD_IDLE]);
+        written
+            .map(|_| ())
+            .with_context(|| format!("Failed to write key to slot {}", self.slot))
+    }
+
+    fn hmac_sha256(&mut self, message: &[u8]) -> Result<[u8; 32]> {
+        let mut device = self.session()?;
+        let digest = (|| {
+            Self::command(&mut device, &packet(OP_SHA, SHA_HMAC_START, u16::from(self.slot), &[]), 4)?;
+            let mut blocks = message.chunks_exact(64);
+            for block in blocks.by_ref() {
+                Self::command(&mut device, &packet(OP_SHA, SHA_UPDATE, 64, block), 4)?;
+            }
+            let rest = blocks.remainder();
+            let digest = Self::command(&mut device, &packet(OP_SHA, SHA_HMAC_END, rest.len() as u16, rest), 35)?;
+            digest.try_into().map_err(|_| anyhow::anyhow!("Secure element returned a short digest"))
+        })();
+        // Idle keeps the chip from sleeping mid-way next time, and drops its SHA state
+        let _ = device.write(&[WORD_IDLE]);
+        digest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc_and_framing() {
+        // The wake response carries the CRC of its first two bytes
+        assert_eq!(crc16(&WAKE_RESPONSE[..2]), [0x33, 0x43]);
+
+        let command = packet(OP_SHA, SHA_HMAC_START, 5, &[]);
+        assert_eq!(&command[..5], &[7, OP_SHA, SHA_HMAC_START, 5, 0]);
+        assert_eq!(crc16(&command[..5]), [command[5], command[6]]);
+
+        let status = |code: u8| {
+            let mut response = vec![4, code];
+            response.extend(crc16(&response));
+            response
+        };
+        assert_eq!(parse_response(&status(0)).unwrap(), &[0]);
+        assert!(parse_response(&status(0x0F)).is_err());
+        let mut corrupted = status(0);
+        corrupted[2] ^= 1;
+        assert!(parse_response(&corrupted).is_err());
+        assert!(parse_response(&[35, 0, 0]).is_err());
+    }
+}
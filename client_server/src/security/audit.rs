@@ -0,0 +1,273 @@
+//! Append-only audit trail of control actions
+//!
+//! Every arm, disarm, actuator and configuration request is recorded with the
+//! channel it came in on and who sent it (HTTP client address, WebSocket
+//! connection, BLE device, RF remote, cloud command id or keypad user), and
+//! whether it was carried out. Unlike the event journal it holds nothing else,
+//! is kept for longer, and entries are never rewritten; they only leave once
+//! they fall outside the retention limits.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::config::AuditConfig;
+use crate::events::{current_correlation_id, make_key, time_key, MAX_QUERY_LIMIT};
+
+/// Retention is enforced after this many new entries rather than on every write
+const PRUNE_EVERY: usize = 100;
+
+/// Channel a control request arrived on, and who sent it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "channel", rename_all = "snake_case")]
+pub enum AuditSource {
+    Http { ip: Option<IpAddr> },
+    Ws { connection: String, ip: Option<IpAddr> },
+    Ble { device: String },
+    Rf { remote: String },
+    Cloud { command_id: String },
+    /// Door keypad or card reader; the user once a credential matched
+    Keypad { user: Option<String> },
+    /// The agent itself, e.g. a scheduled token rotation
+    System,
+}
+
+impl AuditSource {
+    /// Name of the channel, as in the `channel` field
+    pub fn channel(&self) -> &'static str {
+        match self {
+            Self::Http { .. } => "http",
+            Self::Ws { .. } => "ws",
+            Self::Ble { .. } => "ble",
+            Self::Rf { .. } => "rf",
+            Self::Cloud { .. } => "cloud",
+            Self::Keypad { .. } => "keypad",
+            Self::System => "system",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Accepted,
+    Rejected,
+}
+
+/// One control request and what became of it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    /// Command name, e.g. `disarm`, or `<METHOD> <route>` for HTTP requests
+    pub action: String,
+    pub source: AuditSource,
+    pub outcome: AuditOutcome,
+    /// Why a rejected request was refused
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+}
+
+impl AuditEntry {
+    /// Entry for a request, accepted if `result` is `Ok`, in the current correlation scope
+    pub fn new<T, E: Display>(action: &str, source: AuditSource, result: &Result<T, E>) -> Self {
+        let (outcome, reason) = match result {
+            Ok(_) => (AuditOutcome::Accepted, None),
+            Err(e) => (AuditOutcome::Rejected, Some(e.to_string())),
+        };
+        Self {
+            id: Uuid::new_v4(),
+            timestamp: Utc::now(),
+            action: action.to_string(),
+            source,
+            outcome,
+            reason,
+            correlation_id: current_correlation_id(),
+        }
+    }
+}
+
+/// Filter for audit queries; results are newest first
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditQuery {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub action: Option<String>,
+    /// `http`, `ws`, `ble`, `rf`, `cloud`, `keypad` or `system`
+    pub channel: Option<String>,
+    pub outcome: Option<AuditOutcome>,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    /// Whether an entry passes the filter
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+            && self.action.as_deref().is_none_or(|action| entry.action == action)
+            && self.channel.as_deref().is_none_or(|channel| entry.source.channel() == channel)
+            && self.outcome.is_none_or(|outcome| entry.outcome == outcome)
+    }
+
+    /// Requested limit, capped at `MAX_QUERY_LIMIT`
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(100).min(MAX_QUERY_LIMIT)
+    }
+}
+
+/// Audit log in its own sled database
+pub struct AuditLog {
+    db: sled::Db,
+    max_entries: usize,
+    max_age: Duration,
+    since_prune: AtomicUsize,
+}
+
+impl AuditLog {
+    /// Open or create the log and apply retention to what is already there
+    pub fn open<P: AsRef<Path>>(path: P, config: &AuditConfig) -> Result<Self> {
+        let db = sled::open(path.as_ref()).context("Failed to open audit log database")?;
+        let log = Self {
+            db,
+            max_entries: config.max_entries,
+            max_age: Duration::days(config.max_age_days as i64),
+            since_prune: AtomicUsize::new(0),
+        };
+        log.prune()?;
+        Ok(log)
+    }
+
+    /// Append an entry, flushed to disk before returning
+    pub fn append(&self, entry: &AuditEntry) -> Result<()> {
+        let value = serde_json::to_vec(entry).context("Failed to serialize audit entry")?;
+        self.db
+            .insert(make_key(&entry.timestamp, &entry.id), value)
+            .context("Failed to write audit entry")?;
+        self.db.flush().context("Failed to flush audit log")?;
+
+        if self.since_prune.fetch_add(1, Ordering::Relaxed) + 1 >= PRUNE_EVERY {
+            self.since_prune.store(0, Ordering::Relaxed);
+            self.prune()?;
+        }
+        Ok(())
+    }
+
+    /// Append an entry, logging rather than failing the action it describes
+    pub fn record(&self, entry: AuditEntry) {
+        debug!(action = %entry.action, channel = entry.source.channel(), outcome = ?entry.outcome, "Audited");
+        if let Err(e) = self.append(&entry) {
+            warn!(action = %entry.action, error = %e, "Failed to write audit log");
+        }
+    }
+
+    /// Entries matching the query, newest first
+    pub fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+        let start = query.since.map(|since| time_key(&since)).unwrap_or_default();
+        let entries = match query.until {
+            Some(until) => self.db.range(start..time_key(&until)),
+            None => self.db.range(start..),
+        };
+        let mut matching = Vec::new();
+        for entry in entries.rev() {
+            let (_key, value) = entry.context("Failed to read from audit log")?;
+            let entry: AuditEntry = serde_json::from_slice(&value).context("Failed to deserialize audit entry")?;
+            if query.matches(&entry) {
+                matching.push(entry);
+                if matching.len() >= query.limit() {
+                    break;
+                }
+            }
+        }
+        Ok(matching)
+    }
+
+    pub fn len(&self) -> usize {
+        self.db.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.db.is_empty()
+    }
+
+    /// Drop entries older than the maximum age, then the oldest beyond the maximum count
+    fn prune(&self) -> Result<()> {
+        let cutoff = time_key(&(Utc::now() - self.max_age));
+        let expired = self.db.range(..cutoff).count();
+        let excess = self.len().saturating_sub(expired).saturating_sub(self.max_entries);
+        let mut removed = 0;
+        for entry in self.db.iter().take(expired + excess) {
+            let (key, _) = entry.context("Failed to read from audit log during pruning")?;
+            self.db.remove(key).context("Failed to remove old audit entry")?;
+            removed += 1;
+        }
+        if removed > 0 {
+            debug!(removed, remaining = self.len(), "Pruned audit log");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use tempfile::TempDir;
+
+    fn entry_at(action: &str, source: AuditSource, ok: bool, timestamp: DateTime<Utc>) -> AuditEntry {
+        let result = if ok { Ok(()) } else { Err(anyhow!("Wrong PIN")) };
+        let mut entry = AuditEntry::new(action, source, &result);
+        entry.timestamp = timestamp;
+        entry
+    }
+
+    #[test]
+    fn test_query_and_retention() {
+        let dir = TempDir::new().unwrap();
+        let config = AuditConfig { max_entries: 4, ..AuditConfig::default() };
+        let log = AuditLog::open(dir.path(), &config).unwrap();
+        let start = Utc::now() - Duration::minutes(10);
+        let ble = AuditSource::Ble { device: "AA:BB:CC:DD:EE:FF".to_string() };
+        let http = AuditSource::Http { ip: Some("192.168.1.20".parse().unwrap()) };
+        log.append(&entry_at("arm", http.clone(), true, start)).unwrap();
+        log.append(&entry_at("disarm", ble.clone(), false, start + Duration::minutes(1))).unwrap();
+        log.append(&entry_at("disarm", ble, true, start + Duration::minutes(2))).unwrap();
+
+        let all = log.query(&AuditQuery::default()).unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[2].source, http);
+        assert!(all[0].timestamp > all[1].timestamp);
+
+        let refused = log
+            .query(&AuditQuery {
+                channel: Some("ble".to_string()),
+                outcome: Some(AuditOutcome::Rejected),
+                ..AuditQuery::default()
+            })
+            .unwrap();
+        assert_eq!(refused.len(), 1);
+        assert_eq!(refused[0].reason.as_deref(), Some("Wrong PIN"));
+
+        let json = serde_json::to_value(&refused[0]).unwrap();
+        assert_eq!(json["source"]["channel"], "ble");
+        assert_eq!(json["outcome"], "rejected");
+
+        // Old entries go first, then the oldest beyond the count
+        log.append(&entry_at("arm", AuditSource::Rf { remote: "fob".to_string() }, true, start - Duration::days(400)))
+            .unwrap();
+        log.append(&entry_at("siren", AuditSource::Cloud { command_id: "c-1".to_string() }, true, Utc::now()))
+            .unwrap();
+        log.append(&entry_at("arm", AuditSource::Keypad { user: None }, false, Utc::now())).unwrap();
+        log.prune().unwrap();
+        let kept = log.query(&AuditQuery::default()).unwrap();
+        assert_eq!(kept.len(), 4);
+        assert!(kept.iter().all(|entry| entry.action != "arm" || entry.source.channel() == "keypad"));
+    }
+}
@@ -0,0 +1,255 @@
+//! Ed25519 signatures on commands issued by the master
+//!
+//! The master signs the canonical JSON of `{args, client_id, expires_at, id,
+//! name}` with its command key, so neither a hijacked TLS session nor a leaked API token
+//! is enough to make the agent act. The public key comes with registration
+//! and is replaced by a `rotate_command_key` command signed with the key
+//! being replaced; that one stays trusted until the new key has verified a
+//! command, so commands already on their way still go through. Until a key is
+//! known, commands that lower protection are refused all the same.
+
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use parking_lot::RwLock;
+use ring::signature::{UnparsedPublicKey, ED25519};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use super::secrets::SecretStore;
+use super::signing::sort_keys;
+use crate::access::LOCKDOWN;
+use crate::config::AppConfig;
+
+/// Trusted command keys, newest first, 32 bytes each
+pub const COMMAND_KEYS: &str = "command_keys";
+
+/// Command replacing the master's command key, with `{"public_key": <base64>}`
+pub const ROTATE_COMMAND_KEY: &str = "rotate_command_key";
+
+const KEY_LEN: usize = 32;
+
+/// Decode a base64 Ed25519 public key as the master hands it out
+pub fn decode_command_key(key: &str) -> Result<Vec<u8>> {
+    let key = STANDARD.decode(key.trim()).context("Command key is not valid base64")?;
+    if key.len() != KEY_LEN {
+        bail!("Command key must be {} bytes, got {}", KEY_LEN, key.len());
+    }
+    Ok(key)
+}
+
+/// Checks cloud commands against the master's command keys
+#[derive(Clone)]
+pub struct CommandVerifier {
+    client_id: String,
+    required: bool,
+    path: PathBuf,
+    keys: Arc<RwLock<Vec<Vec<u8>>>>,
+}
+
+impl CommandVerifier {
+    /// Verifier for this client, with the keys provisioned so far
+    pub fn open(config: &AppConfig) -> Result<Self> {
+        let path = config.secrets_path();
+        let keys = SecretStore::load(&path)?
+            .get(COMMAND_KEYS)?
+            .map(|keys| keys.chunks(KEY_LEN).map(<[u8]>::to_vec).collect())
+            .unwrap_or_default();
+        Ok(Self {
+            client_id: config.system.client_id.clone(),
+            required: config.cloud.require_signed_commands,
+            path,
+            keys: Arc::new(RwLock::new(keys)),
+        })
+    }
+
+    /// Whether a command key has been provisioned
+    pub fn has_key(&self) -> bool {
+        !self.keys.read().is_empty()
+    }
+
+    /// Accept the command only if one of the trusted keys signed it
+    ///
+    /// Without any key, unsigned commands pass unless they lower protection
+    /// or `cloud.require_signed_commands` is set.
+    pub fn verify(
+        &self,
+        id: &str,
+        name: &str,
+        args: &serde_json::Value,
+        expires_at: Option<i64>,
+        signature: Option<&str>,
+    ) -> Result<()> {
+        if !self.has_key() {
+            if self.required {
+                bail!("No command key provisioned, refusing commands");
+            }
+            if !only_raises_protection(name, args) {
+                bail!("No command key provisioned, refusing unsigned {} command", name);
+            }
+            return Ok(());
+        }
+        let signature = signature.context("Command is not signed")?;
+        let signature = STANDARD.decode(signature.trim()).context("Invalid command signature encoding")?;
+        let message = signed_bytes(&self.client_id, id, name, args, expires_at)?;
+
+        let position = self
+            .keys
+            .read()
+            .iter()
+            .position(|key| UnparsedPublicKey::new(&ED25519, key).verify(&message, &signature).is_ok());
+        match position {
+            Some(0) => {
+                // The newest key works, so the one it replaced can go
+                if self.keys.read().len() > 1 {
+                    self.keys.write().truncate(1);
+                    self.save()?;
+                    info!("Retired previous command key");
+                }
+                Ok(())
+            }
+            Some(_) => Ok(()),
+            None => bail!("Command signature does not verify"),
+        }
+    }
+
+    /// Trust the key in a verified `rotate_command_key` command from now on
+    pub fn rotate(&self, args: &serde_json::Value) -> Result<()> {
+        let key = args
+            .get("public_key")
+            .and_then(|key| key.as_str())
+            .context("rotate_command_key needs a public_key")?;
+        let key = decode_command_key(key)?;
+        {
+            let mut keys = self.keys.write();
+            if keys.is_empty() {
+                bail!("No command key provisioned to rotate");
+            }
+            if keys[0] != key {
+                keys.truncate(1);
+                keys.insert(0, key);
+            }
+        }
+        self.save()?;
+        warn!("Master command key rotated");
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut store = SecretStore::load(&self.path)?;
+        store.set(COMMAND_KEYS, &self.keys.read().concat())
+    }
+}
+
+/// Whether the command can only make the alarm more alert: arming, panic, sounding the siren,
+/// or lockdown that leaves the TOTP key alone
+///
+/// Anything else, including commands added later, is taken to lower protection.
+pub(crate) fn only_raises_protection(name: &str, args: &serde_json::Value) -> bool {
+    match name {
+        "arm" | "panic" => true,
+        "siren" => args.get("on").and_then(|value| value.as_bool()).unwrap_or(false),
+        LOCKDOWN => args.get("otp_secret").is_none(),
+        _ => false,
+    }
+}
+
+/// Canonical JSON of what the master signs; `expires_at` only when the command has one
+fn signed_bytes(
+    client_id: &str,
+    id: &str,
+    name: &str,
+    args: &serde_json::Value,
+    expires_at: Option<i64>,
+) -> Result<Vec<u8>> {
+    let mut value = serde_json::json!({
+        "args": args,
+        "client_id": client_id,
+        "id": id,
+        "name": name,
+    });
+    if let Some(expires_at) = expires_at {
+        value["expires_at"] = serde_json::json!(expires_at);
+    }
+    sort_keys(&mut value);
+    Ok(serde_json::to_vec(&value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::LIFT_LOCKDOWN;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::json;
+
+    fn sign(key: &Ed25519KeyPair, id: &str, name: &str, args: &serde_json::Value) -> String {
+        STANDARD.encode(key.sign(&signed_bytes("c-1", id, name, args, None).unwrap()).as_ref())
+    }
+
+    #[test]
+    fn test_signatures_and_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::test_default();
+        config.system.data_dir = dir.path().to_path_buf();
+        config.system.client_id = "c-1".to_string();
+        let old = Ed25519KeyPair::from_seed_unchecked(&[1; 32]).unwrap();
+        let new = Ed25519KeyPair::from_seed_unchecked(&[2; 32]).unwrap();
+        let disarm = json!({"pin": "2468"});
+
+        // Nothing provisioned: unsigned commands pass unless they lower protection or signing is required
+        let verifier = CommandVerifier::open(&config).unwrap();
+        assert!(verifier.verify("1", "arm", &json!({}), None, None).is_ok());
+        assert!(verifier.verify("1", "siren", &json!({"on": true}), None, None).is_ok());
+        assert!(verifier.verify("1", "disarm", &disarm, None, None).is_err());
+        assert!(verifier.verify("1", "siren", &json!({"on": false}), None, None).is_err());
+        assert!(verifier.verify("1", "bypass", &json!({"zone": "door"}), None, None).is_err());
+        assert!(verifier.verify("1", "config_pull", &json!({}), None, None).is_err());
+        assert!(verifier.verify("1", LIFT_LOCKDOWN, &json!({}), None, None).is_err());
+        assert!(verifier.verify("1", "output", &json!({"output": "gate", "on": true}), None, None).is_err());
+        config.cloud.require_signed_commands = true;
+        assert!(CommandVerifier::open(&config).unwrap().verify("1", "arm", &json!({}), None, None).is_err());
+
+        let mut store = SecretStore::load(&config.secrets_path()).unwrap();
+        store.set(COMMAND_KEYS, old.public_key().as_ref()).unwrap();
+        let verifier = CommandVerifier::open(&config).unwrap();
+        assert!(verifier.verify("1", "disarm", &disarm, None, None).is_err());
+        assert!(verifier.verify("1", "disarm", &disarm, None, Some(&sign(&old, "1", "disarm", &disarm))).is_ok());
+        // Signatures cover the arguments, the command id and the expiry
+        assert!(verifier.verify("1", "disarm", &json!({}), None, Some(&sign(&old, "1", "disarm", &disarm))).is_err());
+        assert!(verifier.verify("2", "disarm", &disarm, None, Some(&sign(&old, "1", "disarm", &disarm))).is_err());
+        assert!(verifier.verify("1", "disarm", &disarm, None, Some(&sign(&new, "1", "disarm", &disarm))).is_err());
+        let expiring = signed_bytes("c-1", "1", "disarm", &disarm, Some(1_700_000_000)).unwrap();
+        let expiring = STANDARD.encode(old.sign(&expiring).as_ref());
+        assert!(verifier.verify("1", "disarm", &disarm, Some(1_700_000_000), Some(&expiring)).is_ok());
+        assert!(verifier.verify("1", "disarm", &disarm, Some(1_700_000_600), Some(&expiring)).is_err());
+
+        let rotate = json!({"public_key": STANDARD.encode(new.public_key().as_ref())});
+        let signature = sign(&old, "2", ROTATE_COMMAND_KEY, &rotate);
+        verifier.verify("2", ROTATE_COMMAND_KEY, &rotate, None, Some(&signature)).unwrap();
+        verifier.rotate(&rotate).unwrap();
+
+        // Both keys work until the new one is used, and survive a restart
+        let verifier = CommandVerifier::open(&config).unwrap();
+        assert!(verifier.verify("3", "arm", &json!(null), None, Some(&sign(&old, "3", "arm", &json!(null)))).is_ok());
+        assert!(verifier.verify("4", "arm", &json!(null), None, Some(&sign(&new, "4", "arm", &json!(null)))).is_ok());
+        assert!(verifier.verify("5", "arm", &json!(null), None, Some(&sign(&old, "5", "arm", &json!(null)))).is_err());
+        let verifier = CommandVerifier::open(&config).unwrap();
+        assert!(verifier.verify("6", "arm", &json!(null), None, Some(&sign(&old, "6", "arm", &json!(null)))).is_err());
+    }
+
+    #[test]
+    fn test_only_known_commands_raise_protection() {
+        assert!(only_raises_protection("arm", &json!({"mode": "night"})));
+        assert!(only_raises_protection("panic", &json!({})));
+        assert!(only_raises_protection("siren", &json!({"on": true})));
+        assert!(only_raises_protection(LOCKDOWN, &json!({"reason": "intruder"})));
+
+        assert!(!only_raises_protection("siren", &json!({})));
+        assert!(!only_raises_protection(LOCKDOWN, &json!({"otp_secret": "JBSWY3DPEHPK3PXP"})));
+        assert!(!only_raises_protection(LIFT_LOCKDOWN, &json!({})));
+        assert!(!only_raises_protection("output", &json!({"output": "gate", "on": true})));
+        for name in ["disarm", "ack", "bypass", "floodlight", "config_pull", "update", ROTATE_COMMAND_KEY, "new"] {
+            assert!(!only_raises_protection(name, &json!({})), "{}", name);
+        }
+    }
+}
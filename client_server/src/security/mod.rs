@@ -1,4 +1,25 @@
 //! Security utilities module
 
+#[cfg(feature = "secure-element")]
+mod atecc;
+mod audit;
+mod commands;
 mod privileges;
-pub use privileges::drop_privileges;
+mod replay;
+mod rotation;
+mod sandbox;
+mod secrets;
+mod signing;
+mod tpm;
+
+pub use audit::{AuditEntry, AuditLog, AuditOutcome, AuditQuery, AuditSource};
+pub use commands::{decode_command_key, CommandVerifier, COMMAND_KEYS, ROTATE_COMMAND_KEY};
+pub(crate) use commands::only_raises_protection;
+pub use privileges::{drop_privileges, privilege_status, PrivilegeStatus};
+pub use replay::ReplayGuard;
+pub use rotation::{rotated_master_token, ApiTokens, TokenRotator};
+pub use sandbox::{restrict_paths, restrict_syscalls, PathProfile};
+pub use secrets::{key_hardware, KeyHardware, SecretStore, SigningKey, API_TOKEN, CLIENT_ID, DEVICE_KEY};
+pub(crate) use secrets::{decode_hex, encode_hex};
+pub use signing::{hmac_sha256, EnvelopeSigner};
+pub(crate) use signing::constant_time_eq;
@@ -1,34 +1,103 @@
 //! Privilege dropping after socket binding
+//!
+//! The agent starts as root to open GPIO, bind its listener and install
+//! updates, then switches to `privileges.user`. Supplementary groups such as
+//! `gpio` keep device access, and the data directory is handed over first so
+//! the journal, queue and secrets stay writable.
 
-use anyhow::{Context, Result};
-use tracing::info;
+use crate::config::PrivilegesConfig;
+use anyhow::Result;
+#[cfg(unix)]
+use anyhow::Context;
+use serde::Serialize;
+use std::path::Path;
+#[cfg(unix)]
+use tracing::{info, warn};
+
+/// Who the agent runs as, for the health report
+#[derive(Debug, Clone, Serialize)]
+pub struct PrivilegeStatus {
+    pub uid: u32,
+    pub gid: u32,
+    pub root: bool,
+}
 
-/// Drop privileges to specified user
 #[cfg(unix)]
-pub fn drop_privileges(username: &str) -> Result<()> {
-    use nix::unistd::{setgid, setuid, Gid, Uid, User};
+pub fn privilege_status() -> PrivilegeStatus {
+    use nix::unistd::{getegid, geteuid};
+    PrivilegeStatus {
+        uid: geteuid().as_raw(),
+        gid: getegid().as_raw(),
+        root: geteuid().is_root(),
+    }
+}
 
-    let user = User::from_name(username)
+#[cfg(not(unix))]
+pub fn privilege_status() -> PrivilegeStatus {
+    PrivilegeStatus { uid: 0, gid: 0, root: false }
+}
+
+/// Switch to the configured user and groups, handing `data_dir` over to them first
+#[cfg(unix)]
+pub fn drop_privileges(config: &PrivilegesConfig, data_dir: &Path) -> Result<()> {
+    use nix::unistd::{setgid, setgroups, setuid, Gid, Group, Uid, User};
+
+    let user = User::from_name(&config.user)
         .context("Failed to lookup user")?
-        .ok_or_else(|| anyhow::anyhow!("User '{}' not found", username))?;
+        .ok_or_else(|| anyhow::anyhow!("User '{}' not found", config.user))?;
+    let gid = match &config.group {
+        Some(name) => {
+            Group::from_name(name)
+                .context("Failed to lookup group")?
+                .ok_or_else(|| anyhow::anyhow!("Group '{}' not found", name))?
+                .gid
+        }
+        None => user.gid,
+    };
+    let mut groups = vec![gid];
+    for name in &config.groups {
+        match Group::from_name(name) {
+            Ok(Some(group)) => groups.push(group.gid),
+            // Boards without the device have no group for it either
+            _ => warn!(group = %name, "Supplementary group not found, skipping it"),
+        }
+    }
 
-    // Set GID first (must be done before dropping UID)
-    setgid(Gid::from_raw(user.gid.as_raw()))
-        .context("Failed to set GID")?;
+    hand_over(data_dir, user.uid, gid)
+        .with_context(|| format!("Failed to hand {} over to {}", data_dir.display(), config.user))?;
 
-    // Then set UID
-    setuid(Uid::from_raw(user.uid.as_raw()))
-        .context("Failed to set UID")?;
+    // Groups first, while still allowed to change them
+    setgroups(&groups).context("Failed to set supplementary groups")?;
+    setgid(gid).context("Failed to set GID")?;
+    setuid(user.uid).context("Failed to set UID")?;
+    if setuid(Uid::from_raw(0)).is_ok() || setgid(Gid::from_raw(0)).is_ok() {
+        anyhow::bail!("Root could be regained after dropping privileges");
+    }
 
-    info!(user = username, uid = user.uid.as_raw(), gid = user.gid.as_raw(), 
+    info!(user = %config.user, uid = user.uid.as_raw(), gid = gid.as_raw(), groups = groups.len(),
           "Dropped privileges");
 
     Ok(())
 }
 
+/// Give `path` and everything below it to `uid:gid`
+#[cfg(unix)]
+fn hand_over(path: &Path, uid: nix::unistd::Uid, gid: nix::unistd::Gid) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    std::os::unix::fs::lchown(path, Some(uid.as_raw()), Some(gid.as_raw()))?;
+    if path.is_dir() && !path.is_symlink() {
+        for entry in std::fs::read_dir(path)? {
+            hand_over(&entry?.path(), uid, gid)?;
+        }
+    }
+    Ok(())
+}
+
 #[cfg(not(unix))]
-pub fn drop_privileges(_username: &str) -> Result<()> {
-    warn!("Privilege dropping not supported on non-Unix systems");
+pub fn drop_privileges(_config: &PrivilegesConfig, _data_dir: &Path) -> Result<()> {
+    tracing::warn!("Privilege dropping not supported on non-Unix systems");
     Ok(())
 }
 
@@ -40,7 +109,13 @@ mod tests {
     #[cfg(unix)]
     fn test_drop_privileges_requires_valid_user() {
         // This test will fail if run as non-root, which is expected
-        let result = drop_privileges("nonexistent_user_12345");
+        let config = PrivilegesConfig {
+            drop: true,
+            user: "nonexistent_user_12345".to_string(),
+            ..PrivilegesConfig::default()
+        };
+        let result = drop_privileges(&config, Path::new("/nonexistent"));
         assert!(result.is_err());
+        assert_eq!(privilege_status().root, nix::unistd::geteuid().is_root());
     }
 }
@@ -0,0 +1,274 @@
+//! Refusing stale and repeated commands
+//!
+//! Commands may carry `expires_at`, in Unix seconds, and a nonce: the
+//! master's command id for cloud commands, the `nonce` field for WebSocket
+//! ones. A command past its expiry, or whose nonce was already used, is
+//! refused and raises `command_replay_rejected`, so a captured frame cannot
+//! disarm the system or silence the siren a second time. Nonces are kept
+//! until their command expires, or for `replay.window_s` without an expiry.
+//! WebSocket commands that lower protection always need both. Each channel
+//! has its own table, so a flood over the LAN cannot crowd out the master's
+//! commands; when one is full the nonce that would be forgotten soonest
+//! makes room. With a store set the table is kept on disk and survives a
+//! restart.
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::warn;
+
+use super::only_raises_protection;
+use crate::config::ReplayConfig;
+use crate::events::{Event, EventBus, EventSource};
+
+/// Most nonces remembered per channel; past this the one expiring soonest is forgotten
+const MAX_TRACKED: usize = 10_000;
+
+/// Nonces of each channel, with the Unix time each may be forgotten at
+#[derive(Default)]
+struct Seen {
+    nonces: HashMap<EventSource, HashMap<String, i64>>,
+    /// Copy on disk, one tree per channel
+    db: Option<sled::Db>,
+}
+
+impl Seen {
+    fn forget_expired(&mut self, now: i64) {
+        for (source, nonces) in self.nonces.iter_mut() {
+            let expired: Vec<String> = nonces.iter().filter(|(_, at)| **at < now).map(|(n, _)| n.clone()).collect();
+            for nonce in expired {
+                nonces.remove(&nonce);
+                persist(&self.db, *source, |tree| tree.remove(nonce.as_bytes()).map(drop));
+            }
+        }
+    }
+
+    fn remember(&mut self, source: EventSource, nonce: &str, forget_at: i64) {
+        let nonces = self.nonces.entry(source).or_default();
+        if nonces.len() >= MAX_TRACKED {
+            let soonest = nonces.iter().min_by_key(|(_, at)| **at).map(|(n, _)| n.clone());
+            if let Some(soonest) = soonest {
+                nonces.remove(&soonest);
+                persist(&self.db, source, |tree| tree.remove(soonest.as_bytes()).map(drop));
+            }
+        }
+        nonces.insert(nonce.to_string(), forget_at);
+        persist(&self.db, source, |tree| tree.insert(nonce, &forget_at.to_be_bytes()).map(drop));
+    }
+}
+
+/// Apply a change to the channel's tree; the table in memory stays authoritative if the disk fails
+fn persist(db: &Option<sled::Db>, source: EventSource, change: impl FnOnce(&sled::Tree) -> sled::Result<()>) {
+    let Some(db) = db else { return };
+    if let Err(e) = db.open_tree(tree_name(source)).and_then(|tree| change(&tree)) {
+        warn!(error = %e, ?source, "Failed to persist command nonce");
+    }
+}
+
+fn tree_name(source: EventSource) -> String {
+    serde_json::to_value(source)
+        .ok()
+        .and_then(|name| name.as_str().map(str::to_string))
+        .unwrap_or_else(|| format!("{:?}", source))
+}
+
+/// Nonces seen recently, shared by every connection of a channel
+#[derive(Clone)]
+pub struct ReplayGuard {
+    config: ReplayConfig,
+    event_bus: EventBus,
+    seen: Arc<Mutex<Seen>>,
+}
+
+impl ReplayGuard {
+    pub fn new(config: &ReplayConfig, event_bus: EventBus) -> Self {
+        Self {
+            config: config.clone(),
+            event_bus,
+            seen: Arc::new(Mutex::new(Seen::default())),
+        }
+    }
+
+    /// Keep the nonces in a sled database, loading those not yet expired
+    pub fn set_store(&self, path: &Path) -> Result<()> {
+        let db = sled::open(path).context("Failed to open replay database")?;
+        let now = Utc::now().timestamp();
+        let mut seen = self.seen.lock();
+        for name in db.tree_names() {
+            let Ok(source) = serde_json::from_value::<EventSource>(String::from_utf8_lossy(&name).into()) else {
+                continue;
+            };
+            let tree = db.open_tree(&name).context("Failed to open replay database")?;
+            let nonces = seen.nonces.entry(source).or_default();
+            for entry in tree.iter() {
+                let (nonce, forget_at) = entry.context("Failed to read replay database")?;
+                let forget_at = forget_at.as_ref().try_into().map(i64::from_be_bytes).unwrap_or(0);
+                nonces.insert(String::from_utf8_lossy(&nonce).into_owned(), forget_at);
+            }
+        }
+        seen.db = Some(db);
+        seen.forget_expired(now);
+        Ok(())
+    }
+
+    /// Accept a command only if it has not expired and its nonce is new
+    pub fn check(
+        &self,
+        source: EventSource,
+        id: &str,
+        command: &str,
+        args: &serde_json::Value,
+        nonce: Option<&str>,
+        expires_at: Option<i64>,
+    ) -> Result<()> {
+        // Without both, a captured disarm from the LAN could be sent again once its nonce is forgotten
+        let strict =
+            self.config.require_expiry || (source == EventSource::Ws && !only_raises_protection(command, args));
+        let Err(reason) = self.admit(source, nonce, expires_at, strict, Utc::now().timestamp()) else {
+            return Ok(());
+        };
+        warn!(?source, %id, %command, reason, "Refusing stale or replayed command");
+        let event = Event::CommandReplayRejected {
+            source,
+            id: id.to_string(),
+            command: command.to_string(),
+            reason: reason.to_string(),
+        };
+        if let Err(e) = self.event_bus.emit(event) {
+            warn!(error = %e, "Failed to report refused command");
+        }
+        bail!("Command refused: {}", reason)
+    }
+
+    fn admit(
+        &self,
+        source: EventSource,
+        nonce: Option<&str>,
+        expires_at: Option<i64>,
+        strict: bool,
+        now: i64,
+    ) -> Result<(), &'static str> {
+        let skew = self.config.skew_s as i64;
+        match expires_at {
+            Some(at) if at + skew < now => return Err("command expired"),
+            Some(at) if at > now + self.config.max_ttl_s as i64 + skew => return Err("expiry too far ahead"),
+            None if strict => return Err("command has no expiry"),
+            _ => {}
+        }
+        let Some(nonce) = nonce else {
+            return if strict { Err("command has no nonce") } else { Ok(()) };
+        };
+
+        let mut seen = self.seen.lock();
+        seen.forget_expired(now);
+        if seen.nonces.get(&source).is_some_and(|nonces| nonces.contains_key(nonce)) {
+            return Err("command already seen");
+        }
+        // Once expired the command is refused anyway
+        let forget_at = expires_at.map_or(now + self.config.window_s as i64, |at| at + skew);
+        seen.remember(source, nonce, forget_at);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_stale_and_repeated_commands() {
+        let (bus, mut rx) = EventBus::new();
+        let guard = ReplayGuard::new(&ReplayConfig::default(), bus);
+        let now = 1_700_000_000;
+        let admit = |nonce, expires_at, now| guard.admit(EventSource::Cloud, nonce, expires_at, false, now);
+
+        assert!(admit(Some("c-1"), Some(now + 300), now).is_ok());
+        assert_eq!(admit(Some("c-1"), Some(now + 300), now + 10), Err("command already seen"));
+        // Nonces are per channel
+        assert!(guard.admit(EventSource::Ws, Some("c-1"), None, false, now).is_ok());
+        assert_eq!(admit(Some("c-2"), Some(now - 60), now), Err("command expired"));
+        assert!(admit(Some("c-3"), Some(now - 10), now).is_ok());
+        assert_eq!(admit(Some("c-4"), Some(now + 86_400), now), Err("expiry too far ahead"));
+        // Without an expiry the nonce is kept for the window
+        assert!(admit(Some("c-5"), None, now).is_ok());
+        assert!(admit(Some("c-5"), None, now + 300).is_err());
+        assert!(admit(Some("c-5"), None, now + 601).is_ok());
+        assert!(admit(None, None, now).is_ok());
+
+        assert_eq!(guard.admit(EventSource::Ws, Some("n-1"), None, true, now), Err("command has no expiry"));
+        assert_eq!(guard.admit(EventSource::Ws, None, Some(now + 30), true, now), Err("command has no nonce"));
+
+        assert!(guard.check(EventSource::Ws, "7", "arm", &json!({}), Some("n-2"), None).is_ok());
+        assert!(guard.check(EventSource::Ws, "7", "arm", &json!({}), Some("n-2"), None).is_err());
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::CommandReplayRejected { source: EventSource::Ws, command, .. } if command == "arm"
+        ));
+    }
+
+    #[test]
+    fn test_ws_disarm_needs_nonce_and_expiry() {
+        let (bus, _rx) = EventBus::new();
+        let guard = ReplayGuard::new(&ReplayConfig::default(), bus);
+        let expires_at = Some(Utc::now().timestamp() + 60);
+        let siren_off = json!({"on": false});
+
+        assert!(guard.check(EventSource::Ws, "1", "disarm", &json!({}), None, None).is_err());
+        assert!(guard.check(EventSource::Ws, "2", "disarm", &json!({}), Some("n-1"), None).is_err());
+        assert!(guard.check(EventSource::Ws, "3", "siren", &siren_off, None, expires_at).is_err());
+        assert!(guard.check(EventSource::Ws, "4", "siren", &siren_off, Some("n-2"), expires_at).is_ok());
+        assert!(guard.check(EventSource::Ws, "5", "siren", &siren_off, Some("n-2"), expires_at).is_err());
+        assert!(guard.check(EventSource::Ws, "6", "siren", &json!({"on": true}), None, None).is_ok());
+        assert!(guard.check(EventSource::Ws, "8", "lift_lockdown", &json!({}), None, None).is_err());
+        assert!(guard.check(EventSource::Ws, "9", "output", &json!({"output": "gate"}), Some("n-3"), None).is_err());
+        // Cloud commands carry their id as the nonce and may leave out the expiry
+        assert!(guard.check(EventSource::Cloud, "7", "disarm", &json!({}), Some("7"), None).is_ok());
+    }
+
+    #[test]
+    fn test_flood_does_not_block_commands() {
+        let (bus, _rx) = EventBus::new();
+        let guard = ReplayGuard::new(&ReplayConfig::default(), bus);
+        let now = 1_700_000_000;
+        assert!(guard.admit(EventSource::Ws, Some("short"), Some(now + 30), true, now).is_ok());
+        for i in 1..MAX_TRACKED {
+            let nonce = format!("n-{}", i);
+            assert!(guard.admit(EventSource::Ws, Some(&nonce), Some(now + 900), true, now).is_ok());
+        }
+
+        // The channel's own commands still get in, pushing out the nonce that expires soonest
+        let admit = |nonce, expires_at| guard.admit(EventSource::Ws, Some(nonce), Some(expires_at), true, now);
+        assert!(admit("disarm", now + 60).is_ok());
+        assert_eq!(admit("disarm", now + 60), Err("command already seen"));
+        assert_eq!(admit("n-1", now + 900), Err("command already seen"));
+        assert!(!guard.seen.lock().nonces[&EventSource::Ws].contains_key("short"));
+        // Other channels have tables of their own
+        assert!(guard.admit(EventSource::Cloud, Some("c-1"), None, false, now).is_ok());
+        assert_eq!(guard.seen.lock().nonces[&EventSource::Ws].len(), MAX_TRACKED);
+    }
+
+    #[test]
+    fn test_nonces_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("replay");
+        let now = Utc::now().timestamp();
+        let (bus, _rx) = EventBus::new();
+        let guard = ReplayGuard::new(&ReplayConfig::default(), bus.clone());
+        guard.set_store(&path).unwrap();
+        assert!(guard.admit(EventSource::Ws, Some("n-1"), Some(now + 300), true, now).is_ok());
+        assert!(guard.admit(EventSource::Ws, Some("n-2"), Some(now - 20), true, now).is_ok());
+        assert!(guard.admit(EventSource::Cloud, Some("c-1"), None, false, now).is_ok());
+        drop(guard);
+
+        let guard = ReplayGuard::new(&ReplayConfig::default(), bus);
+        guard.set_store(&path).unwrap();
+        assert_eq!(guard.admit(EventSource::Ws, Some("n-1"), Some(now + 300), true, now), Err("command already seen"));
+        assert_eq!(guard.admit(EventSource::Cloud, Some("c-1"), None, false, now), Err("command already seen"));
+        // Forgotten once past its expiry, as it would have been without the restart
+        assert!(guard.admit(EventSource::Ws, Some("n-2"), Some(now + 60), true, now + 20).is_ok());
+    }
+}
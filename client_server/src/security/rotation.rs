@@ -0,0 +1,287 @@
+//! Scheduled rotation of the local API token and the master token
+//!
+//! The local API token guards the network routes. Every
+//! `rotation.api_token_days` a new random one replaces it, and the old one is
+//! still accepted for `rotation.grace_s` so consumers can fetch the new one
+//! from `GET /v1/token` in the meantime. Every `rotation.master_token_days`
+//! the agent trades its master token for a new one, which the cloud
+//! WebSocket and the HTTPS fallback use from then on while the master keeps
+//! accepting the old one for its own grace period. Both are saved in the
+//! secrets file, announced with `token_rotated` and recorded in the audit log.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use parking_lot::RwLock;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use super::audit::{AuditEntry, AuditLog, AuditSource};
+use super::secrets::{encode_hex, SecretStore, API_TOKEN};
+use super::signing::constant_time_eq;
+use crate::cloud::RestFallback;
+use crate::config::{AppConfig, RotationConfig};
+use crate::events::{Event, EventBus};
+
+/// Local API tokens as JSON: current, previous and when they were rotated
+const LOCAL_API_TOKENS: &str = "local_api_tokens";
+/// Unix seconds, in decimal, of the last master token rotation
+const API_TOKEN_ROTATED_AT: &str = "api_token_rotated_at";
+
+const TOKEN_LEN: usize = 32;
+const DAY_S: i64 = 86_400;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct TokenSet {
+    current: Option<String>,
+    /// Token replaced by the last rotation, with the Unix time it stops being accepted
+    previous: Option<(String, i64)>,
+    rotated_at: Option<i64>,
+}
+
+/// Tokens the network routes accept, shared with the rotation scheduler
+#[derive(Clone, Default)]
+pub struct ApiTokens {
+    tokens: Arc<RwLock<TokenSet>>,
+}
+
+impl ApiTokens {
+    /// Tokens saved by earlier rotations, or `system.api_key` before the first one
+    pub fn open(config: &AppConfig) -> Result<Self> {
+        let tokens = match SecretStore::load(&config.secrets_path())?.get(LOCAL_API_TOKENS)? {
+            Some(json) => serde_json::from_slice(&json).context("Stored API tokens are not valid JSON")?,
+            None => TokenSet { current: config.system.api_key.clone(), ..TokenSet::default() },
+        };
+        Ok(Self { tokens: Arc::new(RwLock::new(tokens)) })
+    }
+
+    pub fn current(&self) -> Option<String> {
+        self.tokens.read().current.clone()
+    }
+
+    /// When the replaced token stops being accepted, if it still is
+    pub fn previous_valid_until(&self) -> Option<i64> {
+        let now = Utc::now().timestamp();
+        self.tokens.read().previous.as_ref().map(|(_, until)| *until).filter(|until| *until > now)
+    }
+
+    /// Whether a presented token is the current one or the previous one within its grace period
+    pub fn accepts(&self, token: &str) -> bool {
+        let tokens = self.tokens.read();
+        let now = Utc::now().timestamp();
+        tokens.current.as_deref().is_some_and(|current| constant_time_eq(token.as_bytes(), current.as_bytes()))
+            || tokens
+                .previous
+                .as_ref()
+                .is_some_and(|(previous, until)| *until > now && constant_time_eq(token.as_bytes(), previous.as_bytes()))
+    }
+}
+
+/// Replaces tokens when they are due, on a timer
+pub struct TokenRotator {
+    config: RotationConfig,
+    secrets_path: PathBuf,
+    tokens: ApiTokens,
+    event_bus: EventBus,
+    rest: Option<RestFallback>,
+    audit: Option<Arc<AuditLog>>,
+}
+
+impl TokenRotator {
+    pub fn new(config: &AppConfig, tokens: ApiTokens, event_bus: EventBus) -> Self {
+        Self {
+            config: config.rotation.clone(),
+            secrets_path: config.secrets_path(),
+            tokens,
+            event_bus,
+            rest: None,
+            audit: None,
+        }
+    }
+
+    /// Connection to the master, needed to rotate the master token
+    pub fn set_rest_fallback(&mut self, rest: RestFallback) {
+        self.rest = Some(rest);
+    }
+
+    /// Record rotations in the audit log
+    pub fn set_audit(&mut self, audit: Arc<AuditLog>) {
+        self.audit = Some(audit);
+    }
+
+    /// Check for due rotations every `rotation.check_s`, starting now
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.check_s));
+        loop {
+            interval.tick().await;
+            self.rotate_due().await;
+        }
+    }
+
+    /// Rotate each token whose period has passed; a failed rotation is tried again at the next check
+    pub async fn rotate_due(&self) {
+        let now = Utc::now().timestamp();
+        if due(self.tokens.tokens.read().rotated_at, self.config.api_token_days, now) {
+            let result = self.rotate_api_token(now);
+            self.record("rotate_api_token", &result);
+        }
+        if let Some(rest) = &self.rest {
+            let rotated_at = match self.master_rotated_at() {
+                Ok(rotated_at) => rotated_at,
+                Err(e) => {
+                    warn!(error = %e, "Failed to read master token rotation time");
+                    return;
+                }
+            };
+            if due(rotated_at, self.config.master_token_days, now) {
+                let result = self.rotate_master_token(rest, now).await;
+                self.record("rotate_master_token", &result);
+            }
+        }
+    }
+
+    /// Replace the local API token, keeping the old one for the grace period
+    fn rotate_api_token(&self, now: i64) -> Result<()> {
+        let mut token = [0u8; TOKEN_LEN];
+        rand::thread_rng().fill_bytes(&mut token);
+        let previous_valid_until = now + self.config.grace_s as i64;
+
+        let mut tokens = self.tokens.tokens.write();
+        let rotated = TokenSet {
+            current: Some(encode_hex(&token)),
+            previous: tokens.current.take().map(|previous| (previous, previous_valid_until)),
+            rotated_at: Some(now),
+        };
+        let mut store = SecretStore::load(&self.secrets_path)?;
+        store.set(LOCAL_API_TOKENS, &serde_json::to_vec(&rotated)?)?;
+        let had_previous = rotated.previous.is_some();
+        *tokens = rotated;
+        drop(tokens);
+
+        info!(grace_s = self.config.grace_s, "Local API token rotated");
+        self.announce("api", had_previous.then_some(previous_valid_until));
+        Ok(())
+    }
+
+    /// Trade the master token for a new one and keep it for the next start
+    async fn rotate_master_token(&self, rest: &RestFallback, now: i64) -> Result<()> {
+        let token = rest.rotate_token().await.context("Master refused to rotate the token")?;
+        let mut store = SecretStore::load(&self.secrets_path)?;
+        store.set(API_TOKEN, token.as_bytes())?;
+        store.set(API_TOKEN_ROTATED_AT, now.to_string().as_bytes())?;
+        info!("Master token rotated");
+        self.announce("master", None);
+        Ok(())
+    }
+
+    fn master_rotated_at(&self) -> Result<Option<i64>> {
+        let Some(rotated_at) = SecretStore::load(&self.secrets_path)?.get(API_TOKEN_ROTATED_AT)? else {
+            return Ok(None);
+        };
+        let rotated_at = String::from_utf8(rotated_at).context("Stored rotation time is not UTF-8")?;
+        Ok(Some(rotated_at.parse().context("Stored rotation time is not a number")?))
+    }
+
+    fn announce(&self, token: &str, previous_valid_until: Option<i64>) {
+        let event = Event::TokenRotated { token: token.to_string(), previous_valid_until };
+        if let Err(e) = self.event_bus.emit(event) {
+            warn!(error = %e, "Failed to report token rotation");
+        }
+    }
+
+    fn record(&self, action: &str, result: &Result<()>) {
+        if let Err(e) = result {
+            warn!(action, error = %e, "Token rotation failed");
+        }
+        if let Some(audit) = &self.audit {
+            audit.record(AuditEntry::new(action, AuditSource::System, result));
+        }
+    }
+}
+
+/// Master token saved by a rotation, which takes over from the one given at startup
+pub fn rotated_master_token(config: &AppConfig) -> Result<Option<String>> {
+    let store = SecretStore::load(&config.secrets_path())?;
+    if store.get(API_TOKEN_ROTATED_AT)?.is_none() {
+        return Ok(None);
+    }
+    store
+        .get(API_TOKEN)?
+        .map(|token| String::from_utf8(token).context("Stored API token is not UTF-8"))
+        .transpose()
+}
+
+/// Whether a token last rotated at `rotated_at` is due; never rotated means now
+fn due(rotated_at: Option<i64>, days: u64, now: i64) -> bool {
+    days > 0 && rotated_at.is_none_or(|at| now - at >= days as i64 * DAY_S)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloud::MasterToken;
+    use crate::security::{AuditOutcome, AuditQuery};
+    use axum::{http::HeaderMap, routing::post, Json, Router};
+
+    #[tokio::test]
+    async fn test_rotation_with_grace_period() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::test_default();
+        config.system.data_dir = dir.path().to_path_buf();
+        config.system.api_key = Some("from-master".to_string());
+        config.rotation.enabled = true;
+
+        // The master hands out a new token only for the current one
+        let master = Router::new().route(
+            "/clients/:client_id/token/rotate",
+            post(|headers: HeaderMap| async move {
+                match headers.get("authorization").and_then(|value| value.to_str().ok()) {
+                    Some("Bearer from-master") => Ok(Json(serde_json::json!({"api_token": "rotated"}))),
+                    _ => Err(axum::http::StatusCode::UNAUTHORIZED),
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, master).await.unwrap() });
+
+        let (bus, mut rx) = EventBus::new();
+        let tokens = ApiTokens::open(&config).unwrap();
+        assert!(tokens.accepts("from-master"));
+        let master_token = MasterToken::new(config.system.api_key.clone());
+        let mut rotator = TokenRotator::new(&config, tokens.clone(), bus);
+        rotator.set_rest_fallback(
+            RestFallback::new(&format!("http://{}", addr), "c-1", master_token.clone(), None).unwrap(),
+        );
+        let audit = Arc::new(AuditLog::open(dir.path().join("audit"), &config.audit).unwrap());
+        rotator.set_audit(audit.clone());
+        rotator.rotate_due().await;
+
+        // Old and new local tokens are both accepted until the grace period ends
+        let current = tokens.current().unwrap();
+        assert_ne!(current, "from-master");
+        assert!(tokens.accepts(&current) && tokens.accepts("from-master"));
+        assert!(!tokens.accepts("guess"));
+        assert!(matches!(rx.recv().await.unwrap(), Event::TokenRotated { token, previous_valid_until: Some(_) } if token == "api"));
+        assert_eq!(master_token.get().as_deref(), Some("rotated"));
+        assert_eq!(rotated_master_token(&config).unwrap().as_deref(), Some("rotated"));
+
+        // Nothing is due again until the period has passed, and the tokens survive a restart
+        rotator.rotate_due().await;
+        assert_eq!(tokens.current(), Some(current.clone()));
+        let reopened = ApiTokens::open(&config).unwrap();
+        assert!(reopened.accepts(&current) && reopened.accepts("from-master"));
+        let now = Utc::now().timestamp();
+        rotator.tokens.tokens.write().previous = Some(("from-master".to_string(), now - 1));
+        assert!(!tokens.accepts("from-master"));
+
+        let entries = audit.query(&AuditQuery { channel: Some("system".to_string()), ..AuditQuery::default() }).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|entry| entry.outcome == AuditOutcome::Accepted));
+        assert!(!due(Some(now), 30, now + 29 * DAY_S) && due(Some(now), 30, now + 30 * DAY_S));
+        assert!(!due(None, 0, now));
+    }
+}
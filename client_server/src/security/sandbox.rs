@@ -0,0 +1,216 @@
+//! Landlock and seccomp restrictions on the running agent
+//!
+//! Landlock only binds the thread that applies it and the threads it starts
+//! afterwards, so the path rules go on before the async runtime spawns its
+//! workers. They are drawn from the configuration: the agent's data, the
+//! devices it drives and read-only system directories. The configuration
+//! directory is never writable, whatever the paths configured.
+//! The seccomp filter follows once startup is done and is synchronised to
+//! every thread; it refuses syscalls the agent has no use for (mount, ptrace,
+//! module loading, kexec, namespaces, ...) with `EPERM`. Processes the agent
+//! runs, such as `iw` or `rtl_433`, inherit both.
+
+use crate::config::{AppConfig, CONFIG_PATH};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// System directories readable under landlock
+const SYSTEM_READ: [&str; 10] =
+    ["/etc", "/usr", "/bin", "/sbin", "/lib", "/lib64", "/proc", "/sys", "/run", "/var/lib/bluetooth"];
+/// Devices and scratch space writable under landlock besides the configured devices
+const SYSTEM_WRITE: [&str; 7] = ["/dev/null", "/dev/zero", "/dev/random", "/dev/urandom", "/dev/bus/usb", "/dev/shm", "/tmp"];
+
+/// Paths the landlock rules allow
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathProfile {
+    pub read: Vec<PathBuf>,
+    pub write: Vec<PathBuf>,
+}
+
+impl PathProfile {
+    pub fn new(config: &AppConfig) -> Self {
+        let parent = |path: PathBuf| path.parent().map(Path::to_path_buf).unwrap_or(path);
+        let mut write = vec![
+            config.system.data_dir.clone(),
+            parent(config.secrets_path()),
+            parent(config.credentials_path()),
+            config.log_dir(),
+            parent(config.journal_path()),
+            parent(config.history_path()),
+            parent(config.audit_path()),
+            parent(config.dead_letter_path()),
+            parent(config.queue_path()),
+            config.update_dir(),
+            config.gpio.chip.clone(),
+            config.gpio.file_dir.clone(),
+            config.network.wpa_supplicant_conf.clone(),
+            config.network.dhcpcd_conf.clone(),
+            config.display.i2c_bus.clone(),
+            config.adc.spi_dev.clone(),
+            config.signing.tpm.device.clone(),
+            config.signing.secure_element.bus.clone(),
+        ];
+        write.extend(config.sinks.iter().filter_map(|sink| match &sink.kind {
+            crate::config::SinkKind::File { path } => Some(parent(path.clone())),
+            _ => None,
+        }));
+        write.extend(SYSTEM_WRITE.iter().map(PathBuf::from));
+        write.extend(config.sandbox.write_paths.iter().cloned());
+        // The agent could otherwise rewrite its own privileges and sandbox settings
+        let config_dir = parent(PathBuf::from(CONFIG_PATH));
+        write.retain(|path| !path.starts_with(&config_dir) && !config_dir.starts_with(path));
+
+        let mut read: Vec<PathBuf> = SYSTEM_READ.iter().map(PathBuf::from).collect();
+        read.extend(config.sandbox.read_paths.iter().cloned());
+
+        for paths in [&mut read, &mut write] {
+            paths.sort();
+            paths.dedup();
+        }
+        Self { read, write }
+    }
+}
+
+/// Limit file access to the configured paths, for this thread and those it starts
+#[cfg(target_os = "linux")]
+pub fn restrict_paths(config: &AppConfig) -> Result<()> {
+    use anyhow::Context;
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus, ABI,
+    };
+    use tracing::{info, warn};
+
+    let profile = PathProfile::new(config);
+    let abi = ABI::V5;
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))
+        .and_then(|ruleset| ruleset.create())
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(&profile.read, AccessFs::from_read(abi))))
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(&profile.write, AccessFs::from_all(abi))))
+        .and_then(|ruleset| ruleset.restrict_self())
+        .context("Failed to apply landlock rules")?;
+
+    let read: Vec<_> = profile.read.iter().filter(|path| path.exists()).map(|path| path.display()).collect();
+    let write: Vec<_> = profile.write.iter().filter(|path| path.exists()).map(|path| path.display()).collect();
+    match status.ruleset {
+        RulesetStatus::NotEnforced => warn!("Landlock not supported by this kernel, file access is not restricted"),
+        enforcement => info!(?enforcement, read = ?read, write = ?write, "Landlock rules applied"),
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn restrict_paths(_config: &AppConfig) -> Result<()> {
+    tracing::warn!("Landlock not supported on this system");
+    Ok(())
+}
+
+/// Syscalls refused once the agent is running
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+const DENIED_SYSCALLS: [(&str, i64); 33] = {
+    use nix::libc::*;
+    [
+        ("ptrace", SYS_ptrace),
+        ("process_vm_readv", SYS_process_vm_readv),
+        ("process_vm_writev", SYS_process_vm_writev),
+        ("mount", SYS_mount),
+        ("umount2", SYS_umount2),
+        ("pivot_root", SYS_pivot_root),
+        ("chroot", SYS_chroot),
+        ("fsopen", SYS_fsopen),
+        ("fsmount", SYS_fsmount),
+        ("fspick", SYS_fspick),
+        ("move_mount", SYS_move_mount),
+        ("open_tree", SYS_open_tree),
+        ("init_module", SYS_init_module),
+        ("finit_module", SYS_finit_module),
+        ("delete_module", SYS_delete_module),
+        ("kexec_load", SYS_kexec_load),
+        ("kexec_file_load", SYS_kexec_file_load),
+        ("reboot", SYS_reboot),
+        ("swapon", SYS_swapon),
+        ("swapoff", SYS_swapoff),
+        ("bpf", SYS_bpf),
+        ("perf_event_open", SYS_perf_event_open),
+        ("userfaultfd", SYS_userfaultfd),
+        ("keyctl", SYS_keyctl),
+        ("add_key", SYS_add_key),
+        ("request_key", SYS_request_key),
+        ("unshare", SYS_unshare),
+        ("setns", SYS_setns),
+        ("acct", SYS_acct),
+        ("quotactl", SYS_quotactl),
+        ("open_by_handle_at", SYS_open_by_handle_at),
+        ("name_to_handle_at", SYS_name_to_handle_at),
+        ("syslog", SYS_syslog),
+    ]
+};
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+fn syscall_filter() -> Result<Option<seccompiler::BpfProgram>> {
+    use seccompiler::{SeccompAction, SeccompFilter, TargetArch};
+
+    // seccompiler only knows x86_64, aarch64 and riscv64
+    let Ok(arch) = TargetArch::try_from(std::env::consts::ARCH) else {
+        return Ok(None);
+    };
+    let rules = DENIED_SYSCALLS.iter().map(|(_, number)| (*number, vec![])).collect();
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(nix::libc::EPERM as u32),
+        arch,
+    )?;
+    Ok(Some(filter.try_into()?))
+}
+
+/// Refuse the denied syscalls in every thread of the process
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+pub fn restrict_syscalls() -> Result<()> {
+    use anyhow::Context;
+    use tracing::{info, warn};
+
+    let Some(program) = syscall_filter().context("Failed to build seccomp filter")? else {
+        warn!(arch = std::env::consts::ARCH, "Seccomp filter not available on this architecture");
+        return Ok(());
+    };
+    seccompiler::apply_filter_all_threads(&program).context("Failed to apply seccomp filter")?;
+    let denied: Vec<_> = DENIED_SYSCALLS.iter().map(|(name, _)| *name).collect();
+    info!(denied = ?denied, "Seccomp filter applied");
+    Ok(())
+}
+
+#[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+pub fn restrict_syscalls() -> Result<()> {
+    tracing::warn!("Seccomp filter not available on this system");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_covers_agent_paths() {
+        let mut config = AppConfig::test_default();
+        config.sandbox.read_paths = vec![PathBuf::from("/opt/scripts")];
+        // Paths that would open up the configuration directory are left out
+        config.journal.path = Some(PathBuf::from("/etc/pi-door-client/journal"));
+        config.sandbox.write_paths = vec![PathBuf::from("/etc")];
+        let profile = PathProfile::new(&config);
+
+        for path in [config.system.data_dir.clone(), config.gpio.chip.clone()] {
+            assert!(profile.write.contains(&path), "{} not writable", path.display());
+        }
+        assert!(!profile.write.contains(&PathBuf::from("/etc/pi-door-client")));
+        assert!(profile.read.contains(&PathBuf::from("/etc")));
+        assert!(profile.read.contains(&PathBuf::from("/opt/scripts")));
+        assert!(!profile.write.contains(&PathBuf::from("/etc")));
+        assert!(!profile.write.contains(&PathBuf::from("/dev")));
+
+        #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+        if matches!(std::env::consts::ARCH, "x86_64" | "aarch64" | "riscv64") {
+            assert!(!syscall_filter().unwrap().unwrap().is_empty());
+        }
+    }
+}
@@ -0,0 +1,313 @@
+//! Device secrets kept on local disk
+//!
+//! Secrets are hex encoded in a JSON file readable only by the service user.
+//! They never leave the device through the API. With `signing.key_storage`
+//! set to a TPM or secure element the device key lives there instead, and
+//! the file only keeps an HMAC of a fixed message to recognise it by.
+
+use anyhow::{bail, Context, Result};
+use parking_lot::Mutex;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use super::signing::hmac_sha256;
+use super::tpm::TpmKey;
+use crate::config::{AppConfig, KeyStorage, SigningConfig};
+
+/// Name of the key that signs event envelopes
+pub const DEVICE_KEY: &str = "device_key";
+
+/// Client id the master assigned at registration
+pub const CLIENT_ID: &str = "client_id";
+
+/// Token the master issued at registration
+pub const API_TOKEN: &str = "api_token";
+
+/// HMAC of [`KEY_CHECK_MESSAGE`] under the device key held in hardware
+const DEVICE_KEY_CHECK: &str = "device_key_check";
+const KEY_CHECK_MESSAGE: &[u8] = b"pi-door-client device key check";
+
+/// Signing operations on a key that never leaves its hardware
+pub trait KeyHardware: Send + std::fmt::Debug {
+    /// Where the key lives, for logs
+    fn describe(&self) -> String;
+    /// Replace the held key with `key`
+    fn import(&mut self, key: &[u8]) -> Result<()>;
+    fn hmac_sha256(&mut self, message: &[u8]) -> Result<[u8; 32]>;
+}
+
+/// The device key, in memory or behind its hardware
+#[derive(Debug, Clone)]
+pub enum SigningKey {
+    Local(Vec<u8>),
+    Hardware(Arc<Mutex<Box<dyn KeyHardware>>>),
+}
+
+impl SigningKey {
+    pub fn hmac_sha256(&self, message: &[u8]) -> Result<[u8; 32]> {
+        match self {
+            Self::Local(key) => Ok(hmac_sha256(key, message)),
+            Self::Hardware(hardware) => hardware.lock().hmac_sha256(message),
+        }
+    }
+
+    pub fn location(&self) -> String {
+        match self {
+            Self::Local(_) => "secrets file".to_string(),
+            Self::Hardware(hardware) => hardware.lock().describe(),
+        }
+    }
+}
+
+/// Hardware `signing.key_storage` names, if any
+pub fn key_hardware(config: &SigningConfig) -> Result<Option<Box<dyn KeyHardware>>> {
+    match config.key_storage {
+        KeyStorage::File => Ok(None),
+        KeyStorage::Tpm => Ok(Some(Box::new(TpmKey::open(&config.tpm.device, config.tpm.handle)?))),
+        KeyStorage::Atecc608 => {
+            #[cfg(feature = "secure-element")]
+            {
+                let element = &config.secure_element;
+                let key = super::atecc::SecureElementKey::open(&element.bus, element.address, element.slot)?;
+                Ok(Some(Box::new(key)))
+            }
+
+            #[cfg(not(feature = "secure-element"))]
+            bail!("Secure element support is not compiled in; rebuild with the secure-element feature")
+        }
+    }
+}
+
+/// Named secrets persisted as JSON
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SecretStore {
+    #[serde(skip)]
+    path: PathBuf,
+    secrets: BTreeMap<String, String>,
+    #[serde(skip)]
+    hardware: Option<Arc<Mutex<Box<dyn KeyHardware>>>>,
+}
+
+impl SecretStore {
+    /// Load the store, starting empty if the file does not exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut store: Self = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid secrets file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        store.path = path.to_path_buf();
+        Ok(store)
+    }
+
+    /// Load the store `config` names, with the key hardware when signing is enabled
+    pub fn open(config: &AppConfig) -> Result<Self> {
+        let mut store = Self::load(&config.secrets_path())?;
+        if config.signing.enabled {
+            if let Some(hardware) = key_hardware(&config.signing)? {
+                store.set_hardware(hardware);
+            }
+        }
+        Ok(store)
+    }
+
+    /// Keep the device key in `hardware` rather than in the file
+    pub fn set_hardware(&mut self, hardware: Box<dyn KeyHardware>) {
+        self.hardware = Some(Arc::new(Mutex::new(hardware)));
+    }
+
+    /// Write the store atomically, owner read/write only
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600))
+                .with_context(|| format!("Failed to restrict {}", tmp.display()))?;
+        }
+        std::fs::rename(&tmp, &self.path).with_context(|| format!("Failed to replace {}", self.path.display()))
+    }
+
+    /// Secret bytes, if one is stored under `name`
+    pub fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        self.secrets
+            .get(name)
+            .map(|value| decode_hex(value).with_context(|| format!("Secret {} is not valid hex", name)))
+            .transpose()
+    }
+
+    /// Store a secret under `name`, replacing any previous one, and save
+    pub fn set(&mut self, name: &str, secret: &[u8]) -> Result<()> {
+        self.secrets.insert(name.to_string(), encode_hex(secret));
+        self.save()
+    }
+
+    /// Drop the secret under `name`, if there is one, and save
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        if self.secrets.remove(name).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Stored secret, or a new random one of `len` bytes saved for next time
+    pub fn get_or_create(&mut self, name: &str, len: usize) -> Result<Vec<u8>> {
+        if let Some(secret) = self.get(name)? {
+            return Ok(secret);
+        }
+        let mut secret = vec![0u8; len];
+        rand::thread_rng().fill_bytes(&mut secret);
+        self.secrets.insert(name.to_string(), encode_hex(&secret));
+        self.save()?;
+        Ok(secret)
+    }
+
+    /// Key that signs events, created on first use
+    ///
+    /// With hardware, a device key still in the file is moved into it and
+    /// removed from the file.
+    pub fn device_key(&mut self) -> Result<SigningKey> {
+        let Some(hardware) = self.hardware.clone() else {
+            return Ok(SigningKey::Local(self.get_or_create(DEVICE_KEY, 32)?));
+        };
+        let key = SigningKey::Hardware(hardware);
+        if let Some(stored) = self.get(DEVICE_KEY)? {
+            self.import(&stored)?;
+            info!(hardware = %key.location(), "Moved device key out of the secrets file");
+            return Ok(key);
+        }
+        if let Some(check) = self.get(DEVICE_KEY_CHECK)? {
+            if key.hmac_sha256(KEY_CHECK_MESSAGE)?[..] == check[..] {
+                return Ok(key);
+            }
+            warn!(hardware = %key.location(), "Device key no longer in hardware, creating a new one");
+        }
+        self.share_device_key()?;
+        Ok(key)
+    }
+
+    /// Device key to hand to the master at registration
+    ///
+    /// A key in hardware cannot be read back, so a new one replaces it.
+    pub fn share_device_key(&mut self) -> Result<Vec<u8>> {
+        if self.hardware.is_none() {
+            return self.get_or_create(DEVICE_KEY, 32);
+        }
+        let mut key = vec![0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        self.import(&key)?;
+        Ok(key)
+    }
+
+    /// Load `key` into the hardware, keeping only its check value in the file
+    fn import(&mut self, key: &[u8]) -> Result<()> {
+        let hardware = self.hardware.as_ref().context("No key hardware configured")?;
+        hardware.lock().import(key)?;
+        self.secrets.remove(DEVICE_KEY);
+        self.secrets
+            .insert(DEVICE_KEY_CHECK.to_string(), encode_hex(&hmac_sha256(key, KEY_CHECK_MESSAGE)));
+        self.save()
+    }
+}
+
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn decode_hex(value: &str) -> Result<Vec<u8>> {
+    if !value.len().is_multiple_of(2) || !value.is_ascii() {
+        bail!("Odd length or non-ASCII hex string");
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).context("Invalid hex digit"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_generated_secret_is_kept() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("secrets.json");
+
+        let key = SecretStore::load(&path).unwrap().get_or_create(DEVICE_KEY, 32).unwrap();
+        assert_eq!(key.len(), 32);
+
+        let store = SecretStore::load(&path).unwrap();
+        assert_eq!(store.get(DEVICE_KEY).unwrap(), Some(key));
+        assert_eq!(store.get("missing").unwrap(), None);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    /// Computes HMACs with a key it keeps to itself, like a TPM would
+    #[derive(Debug, Default)]
+    struct FakeHardware {
+        key: Arc<Mutex<Option<Vec<u8>>>>,
+    }
+
+    impl KeyHardware for FakeHardware {
+        fn describe(&self) -> String {
+            "fake".to_string()
+        }
+
+        fn import(&mut self, key: &[u8]) -> Result<()> {
+            *self.key.lock() = Some(key.to_vec());
+            Ok(())
+        }
+
+        fn hmac_sha256(&mut self, message: &[u8]) -> Result<[u8; 32]> {
+            let key = self.key.lock().clone().context("No key loaded")?;
+            Ok(hmac_sha256(&key, message))
+        }
+    }
+
+    #[test]
+    fn test_device_key_moves_into_hardware() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("secrets.json");
+        let stored = SecretStore::load(&path).unwrap().get_or_create(DEVICE_KEY, 32).unwrap();
+
+        let hardware = FakeHardware::default();
+        let held = hardware.key.clone();
+        let mut store = SecretStore::load(&path).unwrap();
+        store.set_hardware(Box::new(hardware));
+        let key = store.device_key().unwrap();
+        assert_eq!(key.hmac_sha256(b"event").unwrap(), hmac_sha256(&stored, b"event"));
+        assert!(!std::fs::read_to_string(&path).unwrap().contains(&encode_hex(&stored)));
+
+        // Recognised on the next start
+        let mut store = SecretStore::load(&path).unwrap();
+        store.set_hardware(Box::new(FakeHardware { key: held.clone() }));
+        store.device_key().unwrap();
+        assert_eq!(held.lock().as_deref(), Some(&stored[..]));
+
+        // A cleared chip gets a fresh key, as does registration
+        *held.lock() = Some(vec![0; 32]);
+        let mut store = SecretStore::load(&path).unwrap();
+        store.set_hardware(Box::new(FakeHardware { key: held.clone() }));
+        store.device_key().unwrap();
+        let shared = store.share_device_key().unwrap();
+        assert_ne!(shared, stored);
+        assert_eq!(held.lock().as_deref(), Some(&shared[..]));
+    }
+}
@@ -0,0 +1,258 @@
+//! Device key held in a TPM 2.0
+//!
+//! The key is loaded once as a keyed-hash primary object and made persistent
+//! at `signing.tpm.handle`; afterwards the TPM only ever hands out HMACs.
+//! Commands are written raw to the kernel resource manager (`/dev/tpmrm0`),
+//! authorised with the empty owner password the factory state has.
+
+use anyhow::{bail, Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use super::secrets::KeyHardware;
+
+const TPM_ST_NO_SESSIONS: u16 = 0x8001;
+const TPM_ST_SESSIONS: u16 = 0x8002;
+
+const TPM_CC_EVICT_CONTROL: u32 = 0x120;
+const TPM_CC_CREATE_PRIMARY: u32 = 0x131;
+const TPM_CC_SEQUENCE_COMPLETE: u32 = 0x13E;
+const TPM_CC_SEQUENCE_UPDATE: u32 = 0x15C;
+const TPM_CC_HMAC_START: u32 = 0x15B;
+const TPM_CC_FLUSH_CONTEXT: u32 = 0x165;
+
+const TPM_RH_OWNER: u32 = 0x4000_0001;
+const TPM_RH_NULL: u32 = 0x4000_0007;
+const TPM_RS_PW: u32 = 0x4000_0009;
+
+const TPM_ALG_HMAC: u16 = 0x0005;
+const TPM_ALG_KEYEDHASH: u16 = 0x0008;
+const TPM_ALG_SHA256: u16 = 0x000B;
+
+/// fixedTPM | fixedParent | userWithAuth | noDA | sign
+const KEY_ATTRIBUTES: u32 = 0x2 | 0x10 | 0x40 | 0x400 | 0x40000;
+/// Largest buffer every TPM accepts in one sequence update
+const MAX_BUFFER: usize = 1024;
+
+/// A TPM command under construction
+struct Command {
+    bytes: Vec<u8>,
+}
+
+impl Command {
+    fn new(tag: u16, code: u32) -> Self {
+        let mut bytes = Vec::new();
+        bytes.extend(tag.to_be_bytes());
+        bytes.extend([0; 4]);
+        bytes.extend(code.to_be_bytes());
+        Self { bytes }
+    }
+
+    fn u8(mut self, value: u8) -> Self {
+        self.bytes.push(value);
+        self
+    }
+
+    fn u16(mut self, value: u16) -> Self {
+        self.bytes.extend(value.to_be_bytes());
+        self
+    }
+
+    fn u32(mut self, value: u32) -> Self {
+        self.bytes.extend(value.to_be_bytes());
+        self
+    }
+
+    /// Sized buffer (`TPM2B`)
+    fn sized(mut self, data: &[u8]) -> Self {
+        self.bytes.extend((data.len() as u16).to_be_bytes());
+        self.bytes.extend(data);
+        self
+    }
+
+    /// Password session with the empty password, authorising one handle
+    fn password(self) -> Self {
+        // Area size, session handle, empty nonce, continueSession, empty password
+        self.u32(9).u32(TPM_RS_PW).u16(0).u8(0x01).u16(0)
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let size = self.bytes.len() as u32;
+        self.bytes[2..6].copy_from_slice(&size.to_be_bytes());
+        self.bytes
+    }
+}
+
+/// Check a response header, returning everything after it
+fn response_body(response: &[u8]) -> Result<&[u8]> {
+    if response.len() < 10 {
+        bail!("Truncated TPM response");
+    }
+    let code = u32::from_be_bytes(response[6..10].try_into().unwrap());
+    if code != 0 {
+        bail!("TPM returned error 0x{:03x}", code);
+    }
+    Ok(&response[10..])
+}
+
+fn read_u32(body: &[u8], offset: usize) -> Result<u32> {
+    body.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        .context("Truncated TPM response")
+}
+
+/// Public area of an HMAC-SHA256 key
+fn hmac_key_template() -> Vec<u8> {
+    Command { bytes: Vec::new() }
+        .u16(TPM_ALG_KEYEDHASH)
+        .u16(TPM_ALG_SHA256)
+        .u32(KEY_ATTRIBUTES)
+        .sized(&[])
+        .u16(TPM_ALG_HMAC)
+        .u16(TPM_ALG_SHA256)
+        .sized(&[])
+        .bytes
+}
+
+/// HMAC key at a persistent handle
+#[derive(Debug)]
+pub struct TpmKey {
+    device: File,
+    handle: u32,
+}
+
+impl TpmKey {
+    pub fn open(device: &Path, handle: u32) -> Result<Self> {
+        if !(0x8100_0000..=0x81FF_FFFF).contains(&handle) {
+            bail!("TPM handle 0x{:08x} is not a persistent handle", handle);
+        }
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(device)
+            .with_context(|| format!("Failed to open {}", device.display()))?;
+        Ok(Self { device, handle })
+    }
+
+    fn execute(&mut self, command: Vec<u8>) -> Result<Vec<u8>> {
+        self.device.write_all(&command).context("Failed to send TPM command")?;
+        let mut response = vec![0; 4096];
+        let read = self.device.read(&mut response).context("Failed to read TPM response")?;
+        response.truncate(read);
+        Ok(response)
+    }
+
+    fn flush(&mut self, handle: u32) {
+        let _ = self.execute(Command::new(TPM_ST_NO_SESSIONS, TPM_CC_FLUSH_CONTEXT).u32(handle).finish());
+    }
+
+    fn evict(&mut self, object: u32) -> Result<()> {
+        let command = Command::new(TPM_ST_SESSIONS, TPM_CC_EVICT_CONTROL)
+            .u32(TPM_RH_OWNER)
+            .u32(object)
+            .password()
+            .u32(self.handle)
+            .finish();
+        response_body(&self.execute(command)?).map(|_| ())
+    }
+
+    fn update_sequence(&mut self, sequence: u32, message: &[u8]) -> Result<[u8; 32]> {
+        let mut chunks = message.chunks(MAX_BUFFER);
+        let last = chunks.next_back().unwrap_or_default();
+        for chunk in chunks {
+            let command = Command::new(TPM_ST_SESSIONS, TPM_CC_SEQUENCE_UPDATE)
+                .u32(sequence)
+                .password()
+                .sized(chunk)
+                .finish();
+            response_body(&self.execute(command)?)?;
+        }
+        let command = Command::new(TPM_ST_SESSIONS, TPM_CC_SEQUENCE_COMPLETE)
+            .u32(sequence)
+            .password()
+            .sized(last)
+            .u32(TPM_RH_NULL)
+            .finish();
+        let response = self.execute(command)?;
+        // Parameter size, then the digest as a sized buffer
+        let body = response_body(&response)?;
+        match body.get(4..6).map(|len| u16::from_be_bytes(len.try_into().unwrap())) {
+            Some(32) => Ok(body.get(6..38).context("Truncated TPM digest")?.try_into().unwrap()),
+            _ => bail!("TPM returned a digest of unexpected size"),
+        }
+    }
+}
+
+impl KeyHardware for TpmKey {
+    fn describe(&self) -> String {
+        format!("TPM handle 0x{:08x}", self.handle)
+    }
+
+    fn import(&mut self, key: &[u8]) -> Result<()> {
+        // Whatever the handle held before goes; the old key is useless now
+        let _ = self.evict(self.handle);
+
+        // Empty auth value, then the key itself
+        let sensitive = Command { bytes: Vec::new() }.sized(&[]).sized(key).bytes;
+        let command = Command::new(TPM_ST_SESSIONS, TPM_CC_CREATE_PRIMARY)
+            .u32(TPM_RH_OWNER)
+            .password()
+            .sized(&sensitive)
+            .sized(&hmac_key_template());
+        // No outside info, no PCR selection
+        let command = command.sized(&[]).u32(0).finish();
+        let response = self.execute(command)?;
+        let object = read_u32(response_body(&response).context("Failed to load key into the TPM")?, 0)?;
+
+        let persisted = self.evict(object);
+        self.flush(object);
+        persisted.with_context(|| format!("Failed to persist key at 0x{:08x}", self.handle))
+    }
+
+    fn hmac_sha256(&mut self, message: &[u8]) -> Result<[u8; 32]> {
+        let command = Command::new(TPM_ST_SESSIONS, TPM_CC_HMAC_START)
+            .u32(self.handle)
+            .password()
+            .sized(&[])
+            .u16(TPM_ALG_SHA256)
+            .finish();
+        let response = self.execute(command)?;
+        let sequence = read_u32(response_body(&response).context("Failed to start TPM HMAC")?, 0)?;
+        let digest = self.update_sequence(sequence, message);
+        if digest.is_err() {
+            self.flush(sequence);
+        }
+        digest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_encoding() {
+        let command = Command::new(TPM_ST_SESSIONS, TPM_CC_HMAC_START)
+            .u32(0x8101_0001)
+            .password()
+            .sized(&[])
+            .u16(TPM_ALG_SHA256)
+            .finish();
+        assert_eq!(
+            command,
+            [
+                0x80, 0x02, 0, 0, 0, 0x1F, 0, 0, 0x01, 0x5B, // header, 31 bytes
+                0x81, 0x01, 0x00, 0x01, // key handle
+                0, 0, 0, 9, 0x40, 0, 0, 9, 0, 0, 0x01, 0, 0, // password session
+                0, 0, 0x00, 0x0B, // empty auth, SHA-256
+            ]
+        );
+        assert_eq!(hmac_key_template().len(), 16);
+
+        assert!(response_body(&[0x80, 0x01, 0, 0, 0, 10, 0, 0, 0x01, 0x8B]).is_err());
+        let body = response_body(&[0x80, 0x01, 0, 0, 0, 14, 0, 0, 0, 0, 0x80, 0, 0, 2]).unwrap();
+        assert_eq!(read_u32(body, 0).unwrap(), 0x8000_0002);
+        assert!(TpmKey::open(Path::new("/dev/null"), 0x8000_0000).is_err());
+    }
+}
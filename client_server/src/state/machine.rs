@@ -1,12 +1,24 @@
 //! State machine implementation
 
-use super::{AlarmState, AppState, ActuatorState};
+use super::{AlarmState, AppState, ActuatorState, ArmMode, TimerState};
 use super::transitions::next_state;
-use crate::config::TimerConfig;
-use crate::events::{Event, EventBus, EventEnvelope, TimerId};
+use crate::actuators::{OutputRegistry, SirenPattern};
+use crate::config::{EventBusConfig, TimerConfig};
+use crate::events::{
+    correlated, current_correlation_id, AlarmKind, DeadLetterStore, Event, EventBus, EventEnvelope, QueuedEvent,
+    RetryPolicy, SequenceCounter, TimerId, ZoneType,
+};
+use crate::health::{Liveness, Subsystem, Supervisor, BEAT_INTERVAL};
+use crate::history::{HistoryRecord, HistoryRecorder};
+use crate::observability::Metrics;
+use crate::security::EnvelopeSigner;
 use anyhow::Result;
-use tokio::sync::mpsc;
-use tracing::{debug, info, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
 
 /// State machine that processes events and manages state transitions
 pub struct StateMachine {
@@ -20,14 +32,49 @@ pub struct StateMachine {
     client_id: String,
     /// Timer handles
     timer_tx: mpsc::UnboundedSender<TimerCommand>,
+    /// Automatic lighting stays off until then after a manual floodlight "off"
+    floodlight_override_until: Option<Instant>,
+    /// Numbers envelopes that enter the event history
+    sequence: SequenceCounter,
+    /// Signs history envelopes when event signing is enabled
+    signer: Option<EnvelopeSigner>,
+    /// How failing events are retried
+    retry: RetryPolicy,
+    /// Where events that keep failing end up
+    dead_letters: Option<Arc<DeadLetterStore>>,
+    /// Shift history timestamps by the measured clock skew while it is out of tolerance
+    correct_timestamps: bool,
+    /// Counts transitions and alarms
+    metrics: Option<Metrics>,
+    /// Queue wait beyond which the loop counts as lagging, and for how long before it is reported
+    lag_threshold: Duration,
+    lag_sustained: Duration,
+    /// Keeps transitions for the usage history
+    history: Option<HistoryRecorder>,
+    /// Configured outputs; commands for any other name are ignored
+    outputs: Option<OutputRegistry>,
+    /// Supervisor of the timer manager, stopped along with the state machine
+    timer_supervisor: JoinHandle<()>,
+}
+
+/// What the timer manager holds, kept outside its task so a restart picks up the running timers
+struct TimerTasks {
+    rx: mpsc::UnboundedReceiver<TimerCommand>,
+    handles: HashMap<TimerId, JoinHandle<()>>,
+    liveness: Option<Liveness>,
 }
 
 /// Commands for timer management
 #[derive(Debug)]
 enum TimerCommand {
-    Start { id: TimerId, duration_s: u64 },
+    /// Ticks and expiry carry the correlation ID of whatever started the timer
+    Start { id: TimerId, duration_s: u64, correlation_id: Option<String> },
+    /// Sub-second one-shot timer for relay pulses
+    StartMs { id: TimerId, duration_ms: u64, correlation_id: Option<String> },
     Cancel { id: TimerId },
     CancelAll,
+    /// Mark progress of the timer manager from now on
+    Watch(Liveness),
 }
 
 impl StateMachine {
@@ -39,12 +86,13 @@ impl StateMachine {
         client_id: String,
     ) -> Self {
         let (timer_tx, timer_rx) = mpsc::unbounded_channel();
+        let bus_config = EventBusConfig::default();
         
-        // Spawn timer manager task
+        // Spawn timer manager task, restarted should it ever stop
+        let timers = Arc::new(Mutex::new(TimerTasks { rx: timer_rx, handles: HashMap::new(), liveness: None }));
         let bus_clone = event_bus.clone();
-        tokio::spawn(async move {
-            Self::timer_manager(timer_rx, bus_clone).await;
-        });
+        let timer_supervisor = Supervisor::new(state.clone())
+            .supervise("timers", move || Self::timer_manager(timers.clone(), bus_clone.clone()));
 
         Self {
             state,
@@ -52,11 +100,163 @@ impl StateMachine {
             timer_config,
             client_id,
             timer_tx,
+            floodlight_override_until: None,
+            sequence: SequenceCounter::in_memory(),
+            signer: None,
+            retry: RetryPolicy::default(),
+            dead_letters: None,
+            correct_timestamps: false,
+            metrics: None,
+            lag_threshold: Duration::from_millis(bus_config.lag_warn_ms),
+            lag_sustained: Duration::from_secs(bus_config.lag_sustained_s),
+            history: None,
+            outputs: None,
+            timer_supervisor,
+        }
+    }
+
+    /// Replace the in-memory sequence counter, e.g. with one persisted on disk
+    pub fn set_sequence(&mut self, sequence: SequenceCounter) {
+        self.sequence = sequence;
+    }
+
+    /// Sign every envelope that enters the event history
+    pub fn set_signer(&mut self, signer: EnvelopeSigner) {
+        self.signer = Some(signer);
+    }
+
+    /// Retry failing events by `retry`, then keep them in `dead_letters` rather than dropping them
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy, dead_letters: Option<Arc<DeadLetterStore>>) {
+        self.retry = retry;
+        self.dead_letters = dead_letters;
+    }
+
+    /// Stamp history envelopes with the master's time rather than a clock known to be off
+    pub fn set_timestamp_correction(&mut self, enabled: bool) {
+        self.correct_timestamps = enabled;
+    }
+
+    /// Count state transitions, alarms and processed events in `metrics`
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Report lag once events have waited over `threshold` for `sustained`; a zero threshold never does
+    pub fn set_lag_threshold(&mut self, threshold: Duration, sustained: Duration) {
+        self.lag_threshold = threshold;
+        self.lag_sustained = sustained;
+    }
+
+    /// Record every transition in the usage history
+    pub fn set_history(&mut self, history: HistoryRecorder) {
+        self.history = Some(history);
+    }
+
+    /// Only switch and pulse the outputs in `outputs`
+    pub fn set_outputs(&mut self, outputs: OutputRegistry) {
+        self.outputs = Some(outputs);
+    }
+
+    fn known_output(&self, name: &str) -> bool {
+        let known = self.outputs.as_ref().is_none_or(|outputs| outputs.contains(name));
+        if !known {
+            warn!(output = name, "Command for an unknown output ignored");
+        }
+        known
+    }
+
+    /// Have the timer manager mark its progress for the watchdog
+    pub fn set_liveness(&mut self, liveness: Liveness) {
+        let _ = self.timer_tx.send(TimerCommand::Watch(liveness));
+    }
+
+    /// Process a queued event, retrying failures and dead-lettering what still fails
+    pub async fn process_with_retry(&mut self, queued: QueuedEvent) {
+        let wait = queued.queued_at.elapsed();
+        let event_type = self.metrics.as_ref().map(|_| queued.event.type_name());
+        let started = Instant::now();
+        self.retry_until_done(queued).await;
+        self.record_timing(event_type, wait, started.elapsed());
+    }
+
+    async fn retry_until_done(&mut self, queued: QueuedEvent) {
+        let mut attempt = 1;
+        loop {
+            let error = match self.process_queued(queued.clone()).await {
+                Ok(()) => {
+                    if attempt > 1 {
+                        info!(attempt, "Event processed on retry");
+                        if let Some(store) = &self.dead_letters {
+                            store.note_recovered();
+                        }
+                    }
+                    return;
+                }
+                Err(e) => e,
+            };
+            if let Some(store) = &self.dead_letters {
+                store.note_failure();
+            }
+
+            if attempt >= self.retry.max_attempts {
+                error!(error = %error, attempts = attempt, event = ?queued.event, "Failed to process event");
+                if let Some(store) = &self.dead_letters {
+                    match store.record(queued, &error.to_string(), attempt) {
+                        Ok(letter) => warn!(id = letter.id, "Event moved to dead-letter store"),
+                        Err(e) => error!(error = %e, "Failed to dead-letter event, it is lost"),
+                    }
+                }
+                return;
+            }
+
+            warn!(error = %error, attempt, "Failed to process event, retrying");
+            attempt += 1;
+            tokio::time::sleep(self.retry.delay(attempt)).await;
         }
     }
 
+    /// Note how long an event waited and took, warning when the wait has stayed long
+    fn record_timing(&self, event_type: Option<String>, wait: Duration, took: Duration) {
+        if let (Some(metrics), Some(event_type)) = (&self.metrics, event_type) {
+            metrics.record_event(&event_type, wait, took);
+        }
+        let mut state = self.state.write();
+        if state.event_loop.observe(wait, self.lag_threshold, self.lag_sustained, chrono::Utc::now()) {
+            warn!(
+                wait_ms = state.event_loop.last_wait_ms,
+                since = ?state.event_loop.lagging_since,
+                "State machine is falling behind its queue"
+            );
+        }
+    }
+
+    /// Process a queued event inside the correlation scope it was emitted in
+    pub async fn process_queued(&mut self, queued: QueuedEvent) -> Result<()> {
+        correlated(queued.correlation_id, self.process_event(queued.event)).await
+    }
+
     /// Process an incoming event
     pub async fn process_event(&mut self, event: Event) -> Result<()> {
+        // Countdown ticks only refresh timer state; they are too frequent to keep in history
+        if let Event::TimerTick { id, remaining_s } = &event {
+            return self.handle_timer_tick(id, *remaining_s, &event);
+        }
+
+        // Temperature telemetry is forwarded to subscribers but likewise kept out of history
+        if let Event::TemperatureReading { sensor, celsius } = &event {
+            self.state.write().set_temperature(sensor, *celsius);
+            let mut envelope = EventEnvelope::new(event.clone(), self.client_id.clone());
+            envelope.correlation_id = current_correlation_id();
+            return self.event_bus.broadcast(envelope);
+        }
+
+        // Reconnect attempts are only of interest to live subscribers
+        if let Event::CloudStatusChanged { .. } = &event {
+            let mut envelope = EventEnvelope::new(event, self.client_id.clone());
+            envelope.correlation_id = current_correlation_id();
+            return self.event_bus.broadcast(envelope);
+        }
+
         debug!(?event, "Processing event");
 
         let current_state = {
@@ -66,43 +266,167 @@ impl StateMachine {
 
         // Handle the event based on current state
         match &event {
-            Event::UserArm { exit_delay_s, .. } => {
-                self.handle_user_arm(current_state, *exit_delay_s).await?;
+            Event::UserArm { exit_delay_s, mode, .. } => {
+                self.handle_user_arm(current_state, *exit_delay_s, *mode).await?;
             }
             Event::UserDisarm { auto_rearm_s, .. } => {
                 self.handle_user_disarm(current_state, *auto_rearm_s).await?;
             }
+            Event::AlarmAcknowledged { .. } => {
+                self.handle_alarm_acknowledged().await?;
+            }
             Event::DoorOpen => {
                 self.handle_door_open(current_state).await?;
             }
             Event::DoorClose => {
                 self.handle_door_close().await?;
             }
+            Event::MotionDetected { zone } => {
+                self.handle_motion_detected(current_state, *zone).await?;
+            }
+            Event::GlassBreak => {
+                self.handle_glass_break(current_state).await?;
+            }
+            Event::ZoneTriggered { zone, zone_type } => {
+                self.handle_zone_triggered(current_state, zone, *zone_type, &event).await?;
+            }
+            Event::Tamper { device } => {
+                self.raise_alarm(current_state, AlarmKind::Tamper, device, &event).await?;
+            }
+            Event::RfJamming { duration_ms } => {
+                warn!(duration_ms, "RF jamming detected, wireless sensors may be blocked");
+            }
+            Event::RfReplaySuspected { code, remote } => {
+                warn!(%code, ?remote, "RF code looks replayed");
+            }
+            Event::ClockSkewDetected { skew_ms } => {
+                warn!(skew_ms, "Local clock disagrees with the master, event timestamps may be off");
+            }
+            Event::ConfigApplied { changed, restart_required, timers } => {
+                if let Some(timers) = timers {
+                    self.timer_config = timers.clone();
+                }
+                info!(?changed, restart_required, "Configuration from the master applied");
+            }
+            Event::ConfigReloaded { changed, restart_required, timers, .. } => {
+                if let Some(timers) = timers {
+                    self.timer_config = timers.clone();
+                }
+                info!(?changed, ?restart_required, "Configuration file reloaded");
+            }
+            Event::ConfigRejected { error, .. } => {
+                warn!(%error, "Configuration rejected");
+            }
+            Event::CertificatePinMismatch { host } => {
+                error!(%host, "Cloud connection refused, certificate matches no SPKI pin");
+            }
+            Event::BeaconPresence { beacon, present } => {
+                info!(beacon = %beacon, present, "Beacon presence changed");
+                self.state.write().set_beacon_present(beacon, *present);
+            }
+            Event::ZoneBypassed { zone, bypassed } => {
+                info!(zone = %zone, bypassed, "Zone bypass changed");
+                self.state.write().set_zone_bypassed(zone, *bypassed);
+            }
+            Event::Panic { source } => {
+                self.raise_alarm(current_state, AlarmKind::Panic, &format!("{:?}", source).to_lowercase(), &event)
+                    .await?;
+            }
+            Event::ZoneRestored { zone, .. } => {
+                let mut state = self.state.write();
+                state.set_zone_state(zone, false);
+                debug!(zone = %zone, "Zone restored");
+            }
             Event::TimerExitExpired => {
+                self.set_remaining(&TimerId::ExitDelay, 0);
                 self.handle_timer_exit_expired(current_state).await?;
             }
             Event::TimerEntryExpired => {
+                self.set_remaining(&TimerId::EntryDelay, 0);
                 self.handle_timer_entry_expired(current_state).await?;
             }
             Event::TimerAutoRearmExpired => {
+                self.set_remaining(&TimerId::AutoRearm, 0);
                 self.handle_timer_auto_rearm_expired(current_state).await?;
             }
             Event::TimerSirenExpired => {
+                self.set_remaining(&TimerId::Siren, 0);
                 self.handle_timer_siren_expired().await?;
             }
-            Event::SirenControl { on, duration_s } => {
-                self.handle_siren_control(*on, *duration_s).await?;
+            Event::SirenControl { on, duration_s, pattern } => {
+                self.handle_siren_control(*on, *duration_s, *pattern).await?;
+            }
+            Event::TimerFloodlightExpired => {
+                let mut state = self.state.write();
+                let mut actuators = state.actuators;
+                actuators.floodlight = false;
+                state.set_actuators(actuators);
+                debug!("Floodlight timer expired");
             }
             Event::FloodlightControl { on, duration_s } => {
                 self.handle_floodlight_control(*on, *duration_s).await?;
             }
+            Event::OutputControl { name, on, duration_s } => {
+                self.handle_output_control(name, *on, *duration_s).await?;
+            }
+            Event::TemperatureAlert { sensor, celsius, limit } => {
+                warn!(sensor = %sensor, celsius, ?limit, "Temperature outside limits");
+                self.state.write().set_temperature(sensor, *celsius);
+            }
+            Event::TemperatureRestored { sensor, celsius } => {
+                info!(sensor = %sensor, celsius, "Temperature back within limits");
+                self.state.write().set_temperature(sensor, *celsius);
+            }
+            Event::PowerLost { voltage } => {
+                warn!(voltage, "Mains power lost, running on battery");
+                self.state.write().set_battery(true, false);
+            }
+            Event::LowBattery { voltage } => {
+                warn!(voltage, "Backup battery low");
+                self.state.write().set_battery(true, true);
+            }
+            Event::PowerRestored { voltage } => {
+                info!(voltage, "Mains power restored");
+                self.state.write().set_battery(false, false);
+            }
+            Event::OutputPulse { name, duration_ms } => {
+                self.handle_output_pulse(name, *duration_ms)?;
+            }
+            Event::AccessGranted { user, method, source } => {
+                info!(user = %user, ?method, ?source, "Access granted");
+            }
+            Event::AccessDenied { method, card, source } => {
+                warn!(?method, ?card, ?source, "Access denied");
+            }
+            Event::AccessLockout { lockout_s } => {
+                warn!(lockout_s, "Credentials locked out");
+            }
+            Event::SelfTestCompleted { report } => {
+                self.state.write().set_wiring(report.clone());
+            }
             _ => {
                 debug!(?event, "Event does not require state machine action");
             }
         }
 
-        // Create and store event envelope
-        let envelope = EventEnvelope::new(event, self.client_id.clone());
+        // Create, number and store event envelope
+        let mut envelope = EventEnvelope::new(event, self.client_id.clone());
+        envelope.correlation_id = current_correlation_id();
+        envelope.seq = self.sequence.next().unwrap_or_else(|e| {
+            warn!(error = %e, "Failed to assign event sequence number");
+            0
+        });
+        if self.correct_timestamps {
+            let connectivity = &self.state.read().connectivity;
+            if let (true, Some(skew_ms)) = (connectivity.clock_skewed, connectivity.clock_skew_ms) {
+                envelope.timestamp -= chrono::Duration::milliseconds(skew_ms);
+            }
+        }
+        if let Some(signer) = &self.signer {
+            if let Err(e) = signer.sign(&mut envelope) {
+                warn!(error = %e, "Failed to sign event envelope");
+            }
+        }
         {
             let mut state = self.state.write();
             state.add_event(envelope.clone());
@@ -114,18 +438,25 @@ impl StateMachine {
         Ok(())
     }
 
-    async fn handle_user_arm(&mut self, current_state: AlarmState, exit_delay_s: Option<u64>) -> Result<()> {
+    async fn handle_user_arm(&mut self, current_state: AlarmState, exit_delay_s: Option<u64>, mode: ArmMode) -> Result<()> {
         if let Some(new_state) = next_state(current_state, &Event::UserArm { 
             source: crate::events::EventSource::System,
-            exit_delay_s 
+            exit_delay_s,
+            mode,
         }) {
+            {
+                let mut state = self.state.write();
+                state.set_arm_mode(mode);
+                // Arming again implicitly acknowledges any earlier alarm
+                state.set_alarm_memory(false);
+            }
             self.transition_to(new_state).await?;
             
             // Start exit delay timer
             let delay = exit_delay_s.unwrap_or(self.timer_config.exit_delay_s);
             self.start_timer(TimerId::ExitDelay, delay)?;
             
-            info!(exit_delay_s = delay, "System arming with exit delay");
+            info!(exit_delay_s = delay, %mode, "System arming with exit delay");
         }
         Ok(())
     }
@@ -140,12 +471,14 @@ impl StateMachine {
             
             self.transition_to(new_state).await?;
             
-            // Set actuators to off
+            // Set actuators to off and drop bypasses; alarm memory stays until acknowledged
             {
                 let mut state = self.state.write();
+                state.bypassed_zones.clear();
                 state.set_actuators(ActuatorState {
                     siren: false,
                     floodlight: false,
+                    strobe: false,
                 });
             }
             
@@ -161,19 +494,37 @@ impl StateMachine {
         Ok(())
     }
 
+    async fn handle_alarm_acknowledged(&mut self) -> Result<()> {
+        let mut state = self.state.write();
+        if !state.alarm_memory {
+            debug!("No alarm memory to acknowledge");
+            return Ok(());
+        }
+
+        let mut actuators = state.actuators;
+        actuators.strobe = false;
+        state.set_actuators(actuators);
+        state.set_alarm_memory(false);
+        info!("Alarm acknowledged - strobe off");
+        Ok(())
+    }
+
     async fn handle_door_open(&mut self, current_state: AlarmState) -> Result<()> {
-        {
+        let mode = {
             let mut state = self.state.write();
             state.set_door_state(true);
-        }
+            state.arm_mode
+        };
+        self.courtesy_light(current_state, mode)?;
 
         if let Some(new_state) = next_state(current_state, &Event::DoorOpen) {
             self.transition_to(new_state).await?;
             
             // Start entry delay timer
-            self.start_timer(TimerId::EntryDelay, self.timer_config.entry_delay_s)?;
+            let entry_delay = self.entry_delay_for(ZoneType::Perimeter);
+            self.start_timer(TimerId::EntryDelay, entry_delay)?;
             
-            warn!(entry_delay_s = self.timer_config.entry_delay_s, "Door opened while armed - entry delay started");
+            warn!(entry_delay_s = entry_delay, "Door opened while armed - entry delay started");
         } else {
             debug!("Door opened (no state change)");
         }
@@ -190,6 +541,86 @@ impl StateMachine {
         Ok(())
     }
 
+    async fn handle_motion_detected(&mut self, current_state: AlarmState, zone: ZoneType) -> Result<()> {
+        let mode = {
+            let mut state = self.state.write();
+            state.record_motion();
+            state.arm_mode
+        };
+
+        // Security light: motion while armed switches the floodlight on for a while
+        let floodlight_s = self.timer_config.motion_floodlight_s;
+        if current_state != AlarmState::Disarmed && floodlight_s > 0 {
+            self.auto_floodlight(floodlight_s, "motion")?;
+        }
+        self.courtesy_light(current_state, mode)?;
+
+        self.handle_intrusion(current_state, zone, mode, &Event::MotionDetected { zone }).await
+    }
+
+    async fn handle_zone_triggered(
+        &mut self,
+        current_state: AlarmState,
+        zone: &str,
+        zone_type: ZoneType,
+        event: &Event,
+    ) -> Result<()> {
+        let (mode, bypassed) = {
+            let mut state = self.state.write();
+            state.set_zone_state(zone, true);
+            (state.arm_mode, state.bypassed_zones.contains(zone))
+        };
+
+        if bypassed {
+            debug!(zone = %zone, "Bypassed zone triggered");
+            return Ok(());
+        }
+        if let Some(kind) = zone_type.instant_alarm() {
+            return self.raise_alarm(current_state, kind, zone, event).await;
+        }
+        if !zone_type.is_intrusion() {
+            // Environmental zones are reported regardless of arm state but never sound the siren
+            warn!(zone = %zone, "Environmental zone alert");
+            return Ok(());
+        }
+
+        self.handle_intrusion(current_state, zone_type, mode, event).await
+    }
+
+    /// Start the entry delay if an intrusion sensor in an armed zone fired
+    async fn handle_intrusion(
+        &mut self,
+        current_state: AlarmState,
+        zone: ZoneType,
+        mode: ArmMode,
+        event: &Event,
+    ) -> Result<()> {
+        if !zone.is_armed_in(mode) {
+            debug!(?zone, %mode, "Sensor in bypassed zone");
+            return Ok(());
+        }
+
+        if let Some(new_state) = next_state(current_state, event) {
+            self.transition_to(new_state).await?;
+
+            let entry_delay = self.entry_delay_for(zone);
+            self.start_timer(TimerId::EntryDelay, entry_delay)?;
+
+            warn!(?zone, entry_delay_s = entry_delay, "Sensor triggered while armed - entry delay started");
+        }
+        Ok(())
+    }
+
+    /// Entry delay for a zone given the current arm mode (night mode makes the perimeter instant)
+    fn entry_delay_for(&self, zone: ZoneType) -> u64 {
+        let mode = self.state.read().arm_mode;
+        if mode == ArmMode::Night && zone == ZoneType::Perimeter {
+            0
+        } else {
+            self.timer_config.entry_delay_s
+        }
+    }
+
     async fn handle_timer_exit_expired(&mut self, current_state: AlarmState) -> Result<()> {
         if let Some(new_state) = next_state(current_state, &Event::TimerExitExpired) {
             self.transition_to(new_state).await?;
@@ -201,24 +632,80 @@ impl StateMachine {
     async fn handle_timer_entry_expired(&mut self, current_state: AlarmState) -> Result<()> {
         if let Some(new_state) = next_state(current_state, &Event::TimerEntryExpired) {
             self.transition_to(new_state).await?;
-            
-            // Activate alarm
-            {
-                let mut state = self.state.write();
-                state.set_actuators(ActuatorState {
-                    siren: true,
-                    floodlight: true,
-                });
-            }
-            
-            // Start siren timer
-            self.start_timer(TimerId::Siren, self.timer_config.siren_max_s)?;
+            self.activate_alarm(AlarmKind::Burglar, "entry_delay")?;
             
             warn!("ALARM TRIGGERED - entry delay expired");
         }
         Ok(())
     }
 
+    async fn handle_glass_break(&mut self, current_state: AlarmState) -> Result<()> {
+        if let Some(new_state) = next_state(current_state, &Event::GlassBreak) {
+            self.cancel_timer(TimerId::EntryDelay)?;
+            self.transition_to(new_state).await?;
+            self.activate_alarm(AlarmKind::Burglar, "glass_break")?;
+
+            warn!("ALARM TRIGGERED - glass break detected");
+        } else {
+            debug!("Glass break detected while not armed");
+        }
+        Ok(())
+    }
+
+    /// Alarm straight away for a 24/7 zone or panic button, skipping the entry delay.
+    ///
+    /// While an alarm is already sounding, only a higher-priority cause takes
+    /// over the siren; anything else is just logged.
+    async fn raise_alarm(&mut self, current_state: AlarmState, kind: AlarmKind, cause: &str, event: &Event) -> Result<()> {
+        if current_state == AlarmState::Alarm {
+            let active = self.state.read().alarm_kind;
+            if active.is_some_and(|active| active.priority() >= kind.priority()) {
+                warn!(%kind, cause, "Alarm already sounding");
+                return Ok(());
+            }
+        } else if let Some(new_state) = next_state(current_state, event) {
+            for id in [TimerId::ExitDelay, TimerId::EntryDelay, TimerId::AutoRearm] {
+                self.cancel_timer(id)?;
+            }
+            self.transition_to(new_state).await?;
+        } else {
+            return Ok(());
+        }
+
+        self.activate_alarm(kind, cause)?;
+        warn!(%kind, cause, "ALARM TRIGGERED");
+        Ok(())
+    }
+
+    /// Switch on siren and strobe, and the floodlight unless it is a tamper
+    /// alarm, then start the siren cut-off timer.
+    ///
+    /// Each cause sounds its own cadence. The strobe is not timed: it keeps
+    /// flashing until the alarm is acknowledged.
+    fn activate_alarm(&mut self, kind: AlarmKind, cause: &str) -> Result<()> {
+        {
+            let mut state = self.state.write();
+            state.set_siren_pattern(kind.siren_pattern());
+            state.set_alarm_kind(Some(kind));
+            state.set_alarm_memory(true);
+            let floodlight = kind.lights_floodlight() || state.actuators.floodlight;
+            state.set_actuators(ActuatorState {
+                siren: true,
+                floodlight,
+                strobe: true,
+            });
+        }
+
+        if let Some(metrics) = &self.metrics {
+            metrics.record_alarm(kind);
+        }
+        self.event_bus.emit(Event::AlarmTriggered {
+            kind,
+            cause: cause.to_string(),
+        })?;
+        self.start_timer(TimerId::Siren, self.timer_config.siren_max_for(kind))
+    }
+
     async fn handle_timer_auto_rearm_expired(&mut self, current_state: AlarmState) -> Result<()> {
         if let Some(new_state) = next_state(current_state, &Event::TimerAutoRearmExpired) {
             self.transition_to(new_state).await?;
@@ -242,11 +729,20 @@ impl StateMachine {
         Ok(())
     }
 
-    async fn handle_siren_control(&mut self, on: bool, duration_s: Option<u64>) -> Result<()> {
+    async fn handle_siren_control(
+        &mut self,
+        on: bool,
+        duration_s: Option<u64>,
+        pattern: Option<SirenPattern>,
+    ) -> Result<()> {
+        let pattern = pattern.unwrap_or_default();
         {
             let mut state = self.state.write();
             let mut actuators = state.actuators;
             actuators.siren = on;
+            if on {
+                state.set_siren_pattern(pattern);
+            }
             state.set_actuators(actuators);
         }
 
@@ -254,7 +750,7 @@ impl StateMachine {
             if let Some(duration) = duration_s {
                 self.start_timer(TimerId::Siren, duration)?;
             }
-            info!(duration_s, "Siren manually activated");
+            info!(duration_s, %pattern, "Siren manually activated");
         } else {
             self.cancel_timer(TimerId::Siren)?;
             info!("Siren manually deactivated");
@@ -263,7 +759,51 @@ impl StateMachine {
         Ok(())
     }
 
+    /// Switch the floodlight on for a while, unless it is already on (e.g. held on by an
+    /// active alarm) or was recently switched off by hand
+    fn auto_floodlight(&mut self, duration_s: u64, reason: &str) -> Result<()> {
+        if self.floodlight_override_until.is_some_and(|until| Instant::now() < until) {
+            debug!(reason, "Automatic floodlight suppressed by manual override");
+            return Ok(());
+        }
+
+        let switched_on = {
+            let mut state = self.state.write();
+            let mut actuators = state.actuators;
+            let was_off = !actuators.floodlight;
+            actuators.floodlight = true;
+            state.set_actuators(actuators);
+            was_off
+        };
+        if switched_on {
+            self.start_timer(TimerId::Floodlight, duration_s)?;
+            debug!(duration_s, reason, "Floodlight switched on automatically");
+        }
+        Ok(())
+    }
+
+    /// Courtesy light: activity at night lights the way while disarmed or armed-stay
+    fn courtesy_light(&mut self, current_state: AlarmState, mode: ArmMode) -> Result<()> {
+        let courtesy_s = self.timer_config.courtesy_light_s;
+        let occupied = match current_state {
+            AlarmState::Disarmed => true,
+            AlarmState::Armed => mode == ArmMode::Stay,
+            _ => false,
+        };
+        if courtesy_s == 0 || !occupied || !self.timer_config.is_night(chrono::Local::now().time()) {
+            return Ok(());
+        }
+        self.auto_floodlight(courtesy_s, "courtesy")
+    }
+
     async fn handle_floodlight_control(&mut self, on: bool, duration_s: Option<u64>) -> Result<()> {
+        // Timer expiry has its own event, so an "off" here is always someone's explicit choice
+        self.floodlight_override_until = if on {
+            None
+        } else {
+            Some(Instant::now() + Duration::from_secs(self.timer_config.courtesy_override_s))
+        };
+
         {
             let mut state = self.state.write();
             let mut actuators = state.actuators;
@@ -284,50 +824,158 @@ impl StateMachine {
         Ok(())
     }
 
+    async fn handle_output_control(&mut self, name: &str, on: bool, duration_s: Option<u64>) -> Result<()> {
+        if !self.known_output(name) {
+            return Ok(());
+        }
+        {
+            let mut state = self.state.write();
+            state.set_output(name, on);
+        }
+
+        let timer = TimerId::Output(name.to_string());
+        if on {
+            match duration_s {
+                Some(duration) => self.start_timer(timer, duration)?,
+                None => self.cancel_timer(timer)?,
+            }
+            info!(output = name, duration_s, "Output activated");
+        } else {
+            self.cancel_timer(timer)?;
+            info!(output = name, "Output deactivated");
+        }
+
+        Ok(())
+    }
+
+    fn handle_output_pulse(&mut self, name: &str, duration_ms: u64) -> Result<()> {
+        if !self.known_output(name) {
+            return Ok(());
+        }
+        {
+            let mut state = self.state.write();
+            // Interlock: never re-trigger a relay that is still closed
+            if state.outputs.get(name).copied().unwrap_or(false) {
+                warn!(output = name, "Pulse ignored, output already active");
+                return Ok(());
+            }
+            state.set_output(name, true);
+        }
+
+        let id = TimerId::Output(name.to_string());
+        debug!(?id, duration_ms, "Pulse timer started");
+        self.timer_tx.send(TimerCommand::StartMs {
+            id,
+            duration_ms,
+            correlation_id: current_correlation_id(),
+        })?;
+        info!(output = name, duration_ms, "Output pulsed");
+        Ok(())
+    }
+
+    fn handle_timer_tick(&mut self, id: &TimerId, remaining_s: u64, event: &Event) -> Result<()> {
+        {
+            let mut state = self.state.write();
+            match state.timers.remaining_mut(id) {
+                // A tick queued before the timer was cancelled must not revive it
+                Some(remaining) if *remaining > 0 => *remaining = remaining_s,
+                _ => return Ok(()),
+            }
+        }
+
+        let mut envelope = EventEnvelope::new(event.clone(), self.client_id.clone());
+        envelope.correlation_id = current_correlation_id();
+        self.event_bus.broadcast(envelope)
+    }
+
+    /// Update the visible countdown for a timer
+    fn set_remaining(&self, id: &TimerId, remaining_s: u64) {
+        let mut state = self.state.write();
+        if let Some(remaining) = state.timers.remaining_mut(id) {
+            *remaining = remaining_s;
+        }
+    }
+
     async fn transition_to(&mut self, new_state: AlarmState) -> Result<()> {
         let old_state = {
             let mut state = self.state.write();
             let old = state.alarm_state;
             state.set_alarm_state(new_state);
+            if new_state != AlarmState::Alarm {
+                state.set_alarm_kind(None);
+            }
             old
         };
 
         info!(from = %old_state, to = %new_state, "State transition");
+        if let Some(metrics) = self.metrics.as_ref().filter(|_| old_state != new_state) {
+            metrics.record_transition(old_state, new_state);
+        }
+        if let Some(history) = self.history.as_ref().filter(|_| old_state != new_state) {
+            history.record(HistoryRecord::Transition { at: chrono::Utc::now(), from: old_state, to: new_state });
+        }
         
         Ok(())
     }
 
     fn start_timer(&self, id: TimerId, duration_s: u64) -> Result<()> {
-        self.timer_tx.send(TimerCommand::Start { id, duration_s })?;
         debug!(?id, duration_s, "Timer started");
+        self.set_remaining(&id, duration_s);
+        self.timer_tx.send(TimerCommand::Start {
+            id,
+            duration_s,
+            correlation_id: current_correlation_id(),
+        })?;
         Ok(())
     }
 
     fn cancel_timer(&self, id: TimerId) -> Result<()> {
-        self.timer_tx.send(TimerCommand::Cancel { id })?;
         debug!(?id, "Timer cancelled");
+        self.set_remaining(&id, 0);
+        self.timer_tx.send(TimerCommand::Cancel { id })?;
         Ok(())
     }
 
     fn cancel_all_timers(&self) -> Result<()> {
         self.timer_tx.send(TimerCommand::CancelAll)?;
+        self.state.write().timers = TimerState::default();
         debug!("All timers cancelled");
         Ok(())
     }
 
-    /// Timer manager task
-    async fn timer_manager(
-        mut rx: mpsc::UnboundedReceiver<TimerCommand>,
-        event_bus: EventBus,
-    ) {
-        use std::collections::HashMap;
-        use tokio::task::JoinHandle;
+    /// Event emitted when a timer runs out
+    fn expired_event(timer: TimerId) -> Event {
+        match timer {
+            TimerId::ExitDelay => Event::TimerExitExpired,
+            TimerId::EntryDelay => Event::TimerEntryExpired,
+            TimerId::AutoRearm => Event::TimerAutoRearmExpired,
+            TimerId::Siren => Event::TimerSirenExpired,
+            TimerId::Floodlight => Event::TimerFloodlightExpired,
+            TimerId::Output(name) => Event::OutputControl { name, on: false, duration_s: None },
+        }
+    }
 
-        let mut handles: HashMap<TimerId, JoinHandle<()>> = HashMap::new();
+    /// Timer manager task
+    async fn timer_manager(timers: Arc<Mutex<TimerTasks>>, event_bus: EventBus) {
+        let mut timers = timers.lock().await;
+        let TimerTasks { rx, handles, liveness } = &mut *timers;
+        let mut beat = tokio::time::interval(BEAT_INTERVAL);
 
-        while let Some(cmd) = rx.recv().await {
+        loop {
+            let cmd = tokio::select! {
+                cmd = rx.recv() => match cmd {
+                    Some(cmd) => cmd,
+                    None => break,
+                },
+                _ = beat.tick() => {
+                    if let Some(liveness) = liveness.as_ref() {
+                        liveness.beat(Subsystem::Timers);
+                    }
+                    continue;
+                }
+            };
             match cmd {
-                TimerCommand::Start { id, duration_s } => {
+                TimerCommand::Start { id, duration_s, correlation_id } => {
                     // Cancel existing timer if any
                     if let Some(handle) = handles.remove(&id) {
                         handle.abort();
@@ -335,19 +983,35 @@ impl StateMachine {
 
                     // Start new timer
                     let bus = event_bus.clone();
-                    let handle = tokio::spawn(async move {
-                        tokio::time::sleep(tokio::time::Duration::from_secs(duration_s)).await;
+                    let timer = id.clone();
+                    let handle = tokio::spawn(correlated(correlation_id, async move {
+                        if timer.is_countdown() {
+                            for remaining_s in (0..duration_s).rev() {
+                                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                                if remaining_s > 0 {
+                                    let _ = bus.emit(Event::TimerTick { id: timer.clone(), remaining_s });
+                                }
+                            }
+                        } else {
+                            tokio::time::sleep(tokio::time::Duration::from_secs(duration_s)).await;
+                        }
                         
-                        let event = match id {
-                            TimerId::ExitDelay => Event::TimerExitExpired,
-                            TimerId::EntryDelay => Event::TimerEntryExpired,
-                            TimerId::AutoRearm => Event::TimerAutoRearmExpired,
-                            TimerId::Siren => Event::TimerSirenExpired,
-                            TimerId::Floodlight => Event::FloodlightControl { on: false, duration_s: None },
-                        };
-
-                        let _ = bus.emit(event);
-                    });
+                        let _ = bus.emit(Self::expired_event(timer));
+                    }));
+
+                    handles.insert(id, handle);
+                }
+                TimerCommand::StartMs { id, duration_ms, correlation_id } => {
+                    if let Some(handle) = handles.remove(&id) {
+                        handle.abort();
+                    }
+
+                    let bus = event_bus.clone();
+                    let timer = id.clone();
+                    let handle = tokio::spawn(correlated(correlation_id, async move {
+                        tokio::time::sleep(tokio::time::Duration::from_millis(duration_ms)).await;
+                        let _ = bus.emit(Self::expired_event(timer));
+                    }));
 
                     handles.insert(id, handle);
                 }
@@ -357,15 +1021,31 @@ impl StateMachine {
                     }
                 }
                 TimerCommand::CancelAll => {
-                    for (_, handle) in handles.drain() {
+                    // Auxiliary outputs are independent of the alarm cycle
+                    handles.retain(|id, handle| {
+                        if matches!(id, TimerId::Output(_)) {
+                            return true;
+                        }
                         handle.abort();
-                    }
+                        false
+                    });
+                }
+                TimerCommand::Watch(watch) => {
+                    watch.beat(Subsystem::Timers);
+                    *liveness = Some(watch);
                 }
             }
         }
     }
 }
 
+impl Drop for StateMachine {
+    fn drop(&mut self) {
+        // The timer manager then ends with its command channel instead of being restarted
+        self.timer_supervisor.abort();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -377,6 +1057,12 @@ mod tests {
             entry_delay_s: 5,
             auto_rearm_s: 10,
             siren_max_s: 10,
+            siren_max_by_kind: Default::default(),
+            motion_floodlight_s: 10,
+            courtesy_light_s: 0,
+            courtesy_override_s: 900,
+            night_start: "18:00".to_string(),
+            night_end: "07:00".to_string(),
         }
     }
 
@@ -398,6 +1084,7 @@ mod tests {
         sm.process_event(Event::UserArm {
             source: crate::events::EventSource::Local,
             exit_delay_s: Some(5),
+            mode: ArmMode::Away,
         }).await.unwrap();
 
         assert_eq!(state.read().alarm_state, AlarmState::ExitDelay);
@@ -411,6 +1098,94 @@ mod tests {
         assert_eq!(state.read().alarm_state, AlarmState::Disarmed);
     }
 
+    #[tokio::test]
+    async fn test_history_events_are_sequenced() {
+        let state = new_app_state();
+        let (bus, _rx) = EventBus::new();
+        let mut sm = StateMachine::new(state.clone(), bus.clone(), test_config(), "test".to_string());
+        let mut sub = bus.subscribe();
+
+        sm.process_event(Event::DoorOpen).await.unwrap();
+        sm.process_event(Event::TemperatureReading { sensor: "attic".to_string(), celsius: 21.0 })
+            .await
+            .unwrap();
+        sm.process_event(Event::DoorClose).await.unwrap();
+
+        let seqs: Vec<u64> = std::iter::from_fn(|| sub.try_recv().ok()).map(|e| e.seq).collect();
+        assert_eq!(seqs, vec![1, 0, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_timestamps_corrected_while_clock_skewed() {
+        let state = new_app_state();
+        let (bus, _rx) = EventBus::new();
+        let mut sm = StateMachine::new(state.clone(), bus.clone(), test_config(), "test".to_string());
+        sm.set_timestamp_correction(true);
+        let mut sub = bus.subscribe();
+
+        // Within tolerance nothing is shifted
+        state.write().set_clock_skew(2_000, 30_000);
+        sm.process_event(Event::DoorOpen).await.unwrap();
+        let drift = chrono::Utc::now() - sub.try_recv().unwrap().timestamp;
+        assert!(drift < chrono::Duration::seconds(1));
+
+        // An hour fast: events are stamped with the master's time
+        state.write().set_clock_skew(3_600_000, 30_000);
+        sm.process_event(Event::DoorClose).await.unwrap();
+        let drift = chrono::Utc::now() - sub.try_recv().unwrap().timestamp;
+        assert!(drift >= chrono::Duration::minutes(59));
+    }
+
+    #[tokio::test]
+    async fn test_correlation_follows_timers() {
+        let state = new_app_state();
+        let (bus, mut rx) = EventBus::new();
+        let mut sm = StateMachine::new(state.clone(), bus.clone(), test_config(), "test".to_string());
+        let mut sub = bus.subscribe();
+
+        sm.process_queued(QueuedEvent {
+            event: Event::UserArm { source: crate::events::EventSource::Local, exit_delay_s: Some(1), mode: ArmMode::Away },
+            correlation_id: Some("c123".to_string()),
+            queued_at: Instant::now(),
+        })
+        .await
+        .unwrap();
+        assert_eq!(sub.try_recv().unwrap().correlation_id.as_deref(), Some("c123"));
+
+        // The exit delay expiry is emitted later by the timer task, still under the arm command
+        let expired = tokio::time::timeout(Duration::from_secs(3), rx.recv_queued()).await.unwrap().unwrap();
+        assert!(matches!(expired.event, Event::TimerExitExpired));
+        sm.process_queued(expired).await.unwrap();
+        assert_eq!(state.read().alarm_state, AlarmState::Armed);
+        assert_eq!(sub.try_recv().unwrap().correlation_id.as_deref(), Some("c123"));
+    }
+
+    #[tokio::test]
+    async fn test_failing_event_is_dead_lettered() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = Arc::new(DeadLetterStore::open(dir.path(), &Default::default()).unwrap());
+        let state = new_app_state();
+        let (bus, rx) = EventBus::new();
+        let mut sm = StateMachine::new(state.clone(), bus.clone(), test_config(), "test".to_string());
+        let retry = RetryPolicy { max_attempts: 1, backoff: Duration::from_millis(1) };
+        sm.set_retry_policy(retry, Some(store.clone()));
+
+        // Raising the alarm emits a follow-up event, which fails once the bus receiver is gone
+        drop(rx);
+        sm.process_with_retry(QueuedEvent {
+            event: Event::Panic { source: crate::events::EventSource::Local },
+            correlation_id: None,
+            queued_at: Instant::now(),
+        })
+        .await;
+
+        let letters = store.list().unwrap();
+        assert_eq!(letters.len(), 1);
+        assert!(matches!(letters[0].event, Event::Panic { .. }));
+        assert_eq!(letters[0].attempts, 1);
+        assert_eq!(store.metrics().failures, 1);
+    }
+
     #[tokio::test]
     async fn test_door_open_triggers_entry_delay() {
         let state = new_app_state();
@@ -426,6 +1201,7 @@ mod tests {
         sm.process_event(Event::UserArm {
             source: crate::events::EventSource::Local,
             exit_delay_s: Some(5),
+            mode: ArmMode::Away,
         }).await.unwrap();
 
         // Complete exit delay
@@ -437,4 +1213,403 @@ mod tests {
         assert_eq!(state.read().alarm_state, AlarmState::EntryDelay);
         assert!(state.read().door_open);
     }
+
+    #[tokio::test]
+    async fn test_motion_in_stay_mode_is_bypassed() {
+        let state = new_app_state();
+        let (bus, _rx) = EventBus::new();
+        let mut sm = StateMachine::new(
+            state.clone(),
+            bus.clone(),
+            test_config(),
+            "test".to_string(),
+        );
+
+        sm.process_event(Event::UserArm {
+            source: crate::events::EventSource::Local,
+            exit_delay_s: Some(5),
+            mode: ArmMode::Stay,
+        }).await.unwrap();
+        sm.process_event(Event::TimerExitExpired).await.unwrap();
+        assert_eq!(state.read().arm_mode, ArmMode::Stay);
+
+        // Interior motion is ignored, but still lights the floodlight
+        sm.process_event(Event::MotionDetected { zone: ZoneType::Interior }).await.unwrap();
+        assert_eq!(state.read().alarm_state, AlarmState::Armed);
+        assert!(state.read().last_motion.is_some());
+        assert!(state.read().actuators.floodlight);
+
+        // Perimeter motion still trips the entry delay
+        sm.process_event(Event::MotionDetected { zone: ZoneType::Perimeter }).await.unwrap();
+        assert_eq!(state.read().alarm_state, AlarmState::EntryDelay);
+    }
+
+    #[tokio::test]
+    async fn test_motion_while_disarmed_leaves_floodlight_off() {
+        let state = new_app_state();
+        let (bus, _rx) = EventBus::new();
+        let mut sm = StateMachine::new(
+            state.clone(),
+            bus.clone(),
+            test_config(),
+            "test".to_string(),
+        );
+
+        sm.process_event(Event::MotionDetected { zone: ZoneType::Interior }).await.unwrap();
+        assert_eq!(state.read().alarm_state, AlarmState::Disarmed);
+        assert!(!state.read().actuators.floodlight);
+    }
+
+    #[tokio::test]
+    async fn test_courtesy_light_and_manual_override() {
+        let state = new_app_state();
+        let (bus, _rx) = EventBus::new();
+        let mut config = test_config();
+        config.courtesy_light_s = 30;
+        // Equal start and end make it night all day
+        config.night_start = "00:00".to_string();
+        config.night_end = "00:00".to_string();
+        let mut sm = StateMachine::new(state.clone(), bus.clone(), config, "test".to_string());
+
+        sm.process_event(Event::DoorOpen).await.unwrap();
+        assert!(state.read().actuators.floodlight);
+        sm.process_event(Event::TimerFloodlightExpired).await.unwrap();
+        assert!(!state.read().actuators.floodlight);
+
+        // An explicit "off" keeps automatic lighting away for the override period
+        sm.process_event(Event::FloodlightControl { on: false, duration_s: None }).await.unwrap();
+        sm.process_event(Event::MotionDetected { zone: ZoneType::Interior }).await.unwrap();
+        assert!(!state.read().actuators.floodlight);
+
+        sm.process_event(Event::FloodlightControl { on: true, duration_s: Some(1) }).await.unwrap();
+        sm.process_event(Event::TimerFloodlightExpired).await.unwrap();
+        sm.process_event(Event::DoorClose).await.unwrap();
+        sm.process_event(Event::MotionDetected { zone: ZoneType::Interior }).await.unwrap();
+        assert!(state.read().actuators.floodlight);
+    }
+
+    #[tokio::test]
+    async fn test_glass_break_skips_entry_delay() {
+        let state = new_app_state();
+        let (bus, _rx) = EventBus::new();
+        let mut sm = StateMachine::new(
+            state.clone(),
+            bus.clone(),
+            test_config(),
+            "test".to_string(),
+        );
+
+        // Ignored while disarmed
+        sm.process_event(Event::GlassBreak).await.unwrap();
+        assert_eq!(state.read().alarm_state, AlarmState::Disarmed);
+
+        sm.process_event(Event::UserArm {
+            source: crate::events::EventSource::Local,
+            exit_delay_s: Some(5),
+            mode: ArmMode::Stay,
+        }).await.unwrap();
+        sm.process_event(Event::TimerExitExpired).await.unwrap();
+
+        sm.process_event(Event::GlassBreak).await.unwrap();
+        assert_eq!(state.read().alarm_state, AlarmState::Alarm);
+        assert!(state.read().actuators.siren);
+        assert_eq!(state.read().siren_pattern, SirenPattern::Pulsed);
+    }
+
+    #[tokio::test]
+    async fn test_fire_zone_alarms_while_disarmed() {
+        let state = new_app_state();
+        let (bus, mut rx) = EventBus::new();
+        let mut config = test_config();
+        config.siren_max_by_kind.insert(AlarmKind::Fire, 600);
+        let mut sm = StateMachine::new(state.clone(), bus.clone(), config, "test".to_string());
+
+        sm.process_event(Event::ZoneTriggered {
+            zone: "smoke".to_string(),
+            zone_type: ZoneType::Fire,
+        }).await.unwrap();
+        {
+            let state = state.read();
+            assert_eq!(state.alarm_state, AlarmState::Alarm);
+            assert_eq!(state.alarm_kind, Some(AlarmKind::Fire));
+            assert_eq!(state.siren_pattern, SirenPattern::Temporal3);
+            assert!(state.actuators.siren && state.actuators.floodlight);
+        }
+        match rx.try_recv().unwrap() {
+            Event::AlarmTriggered { kind, cause } => {
+                assert_eq!(kind, AlarmKind::Fire);
+                assert_eq!(cause, "smoke");
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+
+        // Disarming silences it and clears the cause
+        sm.process_event(Event::UserDisarm {
+            source: crate::events::EventSource::Local,
+            auto_rearm_s: None,
+        }).await.unwrap();
+        assert_eq!(state.read().alarm_state, AlarmState::Disarmed);
+        assert_eq!(state.read().alarm_kind, None);
+    }
+
+    #[tokio::test]
+    async fn test_alarm_priority_escalation() {
+        let state = new_app_state();
+        let (bus, _rx) = EventBus::new();
+        let mut sm = StateMachine::new(state.clone(), bus.clone(), test_config(), "test".to_string());
+
+        // Tamper leaves the floodlight alone
+        sm.process_event(Event::ZoneTriggered {
+            zone: "siren_box".to_string(),
+            zone_type: ZoneType::Tamper,
+        }).await.unwrap();
+        assert_eq!(state.read().alarm_kind, Some(AlarmKind::Tamper));
+        assert_eq!(state.read().siren_pattern, SirenPattern::Chirp);
+        assert!(!state.read().actuators.floodlight);
+
+        // Panic outranks tamper and takes over the siren
+        sm.process_event(Event::Panic { source: crate::events::EventSource::Local }).await.unwrap();
+        assert_eq!(state.read().alarm_kind, Some(AlarmKind::Panic));
+        assert_eq!(state.read().siren_pattern, SirenPattern::Steady);
+        assert!(state.read().actuators.floodlight);
+
+        // A lower-priority cause does not
+        sm.process_event(Event::ZoneTriggered {
+            zone: "siren_box".to_string(),
+            zone_type: ZoneType::Tamper,
+        }).await.unwrap();
+        assert_eq!(state.read().alarm_kind, Some(AlarmKind::Panic));
+    }
+
+    #[tokio::test]
+    async fn test_bypassed_zone_ignored_until_disarm() {
+        let state = new_app_state();
+        let (bus, _rx) = EventBus::new();
+        let mut sm = StateMachine::new(state.clone(), bus.clone(), test_config(), "test".to_string());
+        let window = || Event::ZoneTriggered {
+            zone: "window".to_string(),
+            zone_type: ZoneType::Perimeter,
+        };
+
+        sm.process_event(Event::ZoneBypassed { zone: "window".to_string(), bypassed: true }).await.unwrap();
+        sm.process_event(Event::UserArm {
+            source: crate::events::EventSource::Local,
+            exit_delay_s: Some(5),
+            mode: ArmMode::Away,
+        }).await.unwrap();
+        sm.process_event(Event::TimerExitExpired).await.unwrap();
+
+        sm.process_event(window()).await.unwrap();
+        assert_eq!(state.read().alarm_state, AlarmState::Armed);
+
+        sm.process_event(Event::UserDisarm {
+            source: crate::events::EventSource::Local,
+            auto_rearm_s: None,
+        }).await.unwrap();
+        assert!(state.read().bypassed_zones.is_empty());
+
+        // Device tamper alarms even while disarmed
+        sm.process_event(Event::Tamper { device: "reader".to_string() }).await.unwrap();
+        assert_eq!(state.read().alarm_state, AlarmState::Alarm);
+        assert_eq!(state.read().alarm_kind, Some(AlarmKind::Tamper));
+    }
+
+    #[tokio::test]
+    async fn test_environmental_zone_never_sounds_siren() {
+        let state = new_app_state();
+        let (bus, _rx) = EventBus::new();
+        let mut sm = StateMachine::new(
+            state.clone(),
+            bus.clone(),
+            test_config(),
+            "test".to_string(),
+        );
+
+        sm.process_event(Event::UserArm {
+            source: crate::events::EventSource::Local,
+            exit_delay_s: Some(5),
+            mode: ArmMode::Away,
+        }).await.unwrap();
+        sm.process_event(Event::TimerExitExpired).await.unwrap();
+
+        sm.process_event(Event::ZoneTriggered {
+            zone: "water_leak".to_string(),
+            zone_type: ZoneType::Environmental,
+        }).await.unwrap();
+        assert_eq!(state.read().alarm_state, AlarmState::Armed);
+        assert!(!state.read().actuators.siren);
+        assert_eq!(state.read().zones.get("water_leak"), Some(&true));
+
+        sm.process_event(Event::ZoneRestored {
+            zone: "water_leak".to_string(),
+            zone_type: ZoneType::Environmental,
+        }).await.unwrap();
+        assert_eq!(state.read().zones.get("water_leak"), Some(&false));
+    }
+
+    #[tokio::test]
+    async fn test_output_pulse_expires() {
+        let state = new_app_state();
+        let (bus, mut rx) = EventBus::new();
+        let mut sm = StateMachine::new(
+            state.clone(),
+            bus.clone(),
+            test_config(),
+            "test".to_string(),
+        );
+
+        sm.process_event(Event::OutputControl {
+            name: "gate".to_string(),
+            on: true,
+            duration_s: Some(1),
+        }).await.unwrap();
+        assert_eq!(state.read().outputs.get("gate"), Some(&true));
+
+        let expired = tokio::time::timeout(std::time::Duration::from_secs(3), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        match &expired {
+            Event::OutputControl { name, on, .. } => {
+                assert_eq!(name, "gate");
+                assert!(!on);
+            }
+            _ => panic!("Wrong event type"),
+        }
+
+        sm.process_event(expired).await.unwrap();
+        assert_eq!(state.read().outputs.get("gate"), Some(&false));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_output_ignored() {
+        let state = new_app_state();
+        let (bus, _rx) = EventBus::new();
+        let mut sm = StateMachine::new(state.clone(), bus, test_config(), "test".to_string());
+        let mut config = crate::config::AppConfig::test_default();
+        config.gpio.outputs.push(crate::config::OutputConfig {
+            name: "gate".to_string(),
+            pin: 5,
+            pulse_ms: None,
+        });
+        sm.set_outputs(OutputRegistry::from_config(&config));
+
+        for name in ["gate", "made_up"] {
+            sm.process_event(Event::OutputControl { name: name.to_string(), on: true, duration_s: None })
+                .await
+                .unwrap();
+        }
+        sm.process_event(Event::OutputPulse { name: "other".to_string(), duration_ms: 200 }).await.unwrap();
+
+        let outputs = state.read().outputs.clone();
+        assert_eq!(outputs.get("gate"), Some(&true));
+        assert_eq!(outputs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pulse_interlock() {
+        let state = new_app_state();
+        let (bus, mut rx) = EventBus::new();
+        let mut sm = StateMachine::new(
+            state.clone(),
+            bus.clone(),
+            test_config(),
+            "test".to_string(),
+        );
+
+        let pulse = Event::OutputPulse {
+            name: "lock".to_string(),
+            duration_ms: 200,
+        };
+        sm.process_event(pulse.clone()).await.unwrap();
+        assert_eq!(state.read().outputs.get("lock"), Some(&true));
+
+        // A second pulse while the relay is closed must not extend it
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        sm.process_event(pulse).await.unwrap();
+
+        let started = std::time::Instant::now();
+        let released = tokio::time::timeout(std::time::Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_millis(150));
+        assert!(matches!(&released, Event::OutputControl { on: false, .. }));
+
+        sm.process_event(released).await.unwrap();
+        assert_eq!(state.read().outputs.get("lock"), Some(&false));
+    }
+
+    #[tokio::test]
+    async fn test_strobe_outlives_siren_until_acknowledged() {
+        let state = new_app_state();
+        let (bus, _rx) = EventBus::new();
+        let mut sm = StateMachine::new(
+            state.clone(),
+            bus.clone(),
+            test_config(),
+            "test".to_string(),
+        );
+
+        sm.process_event(Event::UserArm {
+            source: crate::events::EventSource::Local,
+            exit_delay_s: Some(5),
+            mode: ArmMode::Away,
+        }).await.unwrap();
+        sm.process_event(Event::TimerExitExpired).await.unwrap();
+        sm.process_event(Event::GlassBreak).await.unwrap();
+        assert!(state.read().actuators.strobe);
+        assert!(state.read().alarm_memory);
+
+        sm.process_event(Event::TimerSirenExpired).await.unwrap();
+        assert!(!state.read().actuators.siren);
+        assert!(state.read().actuators.strobe);
+
+        sm.process_event(Event::AlarmAcknowledged {
+            source: crate::events::EventSource::Local,
+        }).await.unwrap();
+        assert!(!state.read().actuators.strobe);
+        assert!(!state.read().alarm_memory);
+        assert_eq!(state.read().alarm_state, AlarmState::Alarm);
+    }
+
+    #[tokio::test]
+    async fn test_countdown_ticks_update_timers() {
+        let state = new_app_state();
+        let (bus, mut rx) = EventBus::new();
+        let mut sm = StateMachine::new(
+            state.clone(),
+            bus.clone(),
+            test_config(),
+            "test".to_string(),
+        );
+
+        sm.process_event(Event::UserArm {
+            source: crate::events::EventSource::Local,
+            exit_delay_s: Some(3),
+            mode: ArmMode::Away,
+        }).await.unwrap();
+        assert_eq!(state.read().timers.exit_s, 3);
+
+        let tick = tokio::time::timeout(std::time::Duration::from_secs(2), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            &tick,
+            Event::TimerTick { id: TimerId::ExitDelay, remaining_s: 2 }
+        ));
+        sm.process_event(tick).await.unwrap();
+        assert_eq!(state.read().timers.exit_s, 2);
+        // Ticks are not kept in the event history
+        assert_eq!(state.read().last_events.len(), 1);
+
+        // A stale tick after disarming does not revive the countdown
+        sm.process_event(Event::UserDisarm {
+            source: crate::events::EventSource::Local,
+            auto_rearm_s: Some(0),
+        }).await.unwrap();
+        sm.process_event(Event::TimerTick { id: TimerId::ExitDelay, remaining_s: 1 }).await.unwrap();
+        assert_eq!(state.read().timers.exit_s, 0);
+    }
 }
@@ -5,5 +5,5 @@ mod transitions;
 mod shared;
 
 pub use machine::StateMachine;
-pub use shared::{AlarmState, SharedState, ActuatorState, ConnectivityState, CloudStatus, AppState, new_app_state};
+pub use shared::{AlarmState, ArmMode, SharedState, ActuatorState, ConnectivityState, CloudStatus, EventLoopHealth, PowerState, TimerState, AppState, new_app_state};
 pub use transitions::StateTransition;
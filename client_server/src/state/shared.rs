@@ -3,10 +3,18 @@
 use chrono::{DateTime, Utc};
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::events::EventEnvelope;
+use crate::access::{LockdownStatus, PinLockout};
+use crate::actuators::{LimitStatus, SirenPattern};
+use crate::cloud::LinkMetrics;
+use crate::events::{AlarmKind, EventEnvelope, TimerId};
+use crate::gpio::WiringReport;
+use crate::health::{Component, HealthRegistry, HealthStatus, MemoryStatus, SocStatus, StorageStatus, TaskStatus};
+use crate::network::{InterfaceHealth, LinkQuality, PendingNetworkChange};
+use crate::rf433::{LearnSession, RfMetrics};
 
 /// Main alarm state
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -31,11 +39,37 @@ impl std::fmt::Display for AlarmState {
     }
 }
 
+/// Arming mode selected when the system was armed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArmMode {
+    /// Everyone has left: perimeter and interior zones are armed
+    #[default]
+    Away,
+    /// Occupants at home: perimeter armed, interior bypassed
+    Stay,
+    /// Occupants asleep: perimeter armed without entry delay, interior bypassed
+    Night,
+}
+
+impl std::fmt::Display for ArmMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArmMode::Away => write!(f, "away"),
+            ArmMode::Stay => write!(f, "stay"),
+            ArmMode::Night => write!(f, "night"),
+        }
+    }
+}
+
 /// Actuator state
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ActuatorState {
     pub siren: bool,
     pub floodlight: bool,
+    /// Strobe outlives the siren and stays on until the alarm is acknowledged
+    #[serde(default)]
+    pub strobe: bool,
 }
 
 impl Default for ActuatorState {
@@ -43,6 +77,7 @@ impl Default for ActuatorState {
         Self {
             siren: false,
             floodlight: false,
+            strobe: false,
         }
     }
 }
@@ -52,6 +87,18 @@ impl Default for ActuatorState {
 pub struct ConnectivityState {
     pub cloud: CloudStatus,
     pub interface: Option<String>,
+    /// When the cloud connection last came up
+    #[serde(default)]
+    pub cloud_last_connected: Option<DateTime<Utc>>,
+    /// Local clock minus the master's, in milliseconds, from the last comparison
+    #[serde(default)]
+    pub clock_skew_ms: Option<i64>,
+    /// The skew is beyond `cloud.clock_skew_threshold_s`
+    #[serde(default)]
+    pub clock_skewed: bool,
+    /// SSID of the Wi-Fi setup access point while it runs
+    #[serde(default)]
+    pub setup_ap: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -62,15 +109,39 @@ pub enum CloudStatus {
     Connecting,
 }
 
+impl ConnectivityState {
+    /// Whether the active uplink is a cellular modem
+    pub fn on_lte(&self) -> bool {
+        self.interface
+            .as_deref()
+            .is_some_and(|iface| iface.starts_with("wwan") || iface.starts_with("ppp"))
+    }
+}
+
 impl Default for ConnectivityState {
     fn default() -> Self {
         Self {
             cloud: CloudStatus::Offline,
             interface: None,
+            cloud_last_connected: None,
+            clock_skew_ms: None,
+            clock_skewed: false,
+            setup_ap: None,
         }
     }
 }
 
+/// Analog readings and backup power status
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PowerState {
+    /// Latest scaled value of each ADC channel
+    pub analog: BTreeMap<String, f64>,
+    /// Mains power is lost and the backup battery is in use
+    pub on_battery: bool,
+    /// Backup battery is nearly flat
+    pub low_battery: bool,
+}
+
 /// Timer state tracking
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TimerState {
@@ -84,17 +155,119 @@ pub struct TimerState {
     pub siren_s: u64,
 }
 
+impl TimerState {
+    /// Remaining-seconds field tracking a countdown timer, if it has one
+    pub fn remaining_mut(&mut self, id: &TimerId) -> Option<&mut u64> {
+        match id {
+            TimerId::ExitDelay => Some(&mut self.exit_s),
+            TimerId::EntryDelay => Some(&mut self.entry_s),
+            TimerId::AutoRearm => Some(&mut self.auto_rearm_s),
+            TimerId::Siren => Some(&mut self.siren_s),
+            _ => None,
+        }
+    }
+}
+
+/// How long events wait for the state machine
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventLoopHealth {
+    /// Events processed since startup
+    pub processed: u64,
+    /// Time the last event spent in the queue
+    pub last_wait_ms: u64,
+    /// Longest an event has spent in the queue since startup
+    pub max_wait_ms: u64,
+    /// Every event since then waited longer than `event_bus.lag_warn_ms`
+    pub lagging_since: Option<DateTime<Utc>>,
+    /// The wait has stayed over the threshold for `event_bus.lag_sustained_s`
+    pub lagging: bool,
+}
+
+impl EventLoopHealth {
+    /// Record how long an event waited; true when the lag has just become sustained
+    pub fn observe(&mut self, wait: Duration, threshold: Duration, sustained: Duration, now: DateTime<Utc>) -> bool {
+        let wait_ms = wait.as_millis() as u64;
+        self.processed += 1;
+        self.last_wait_ms = wait_ms;
+        self.max_wait_ms = self.max_wait_ms.max(wait_ms);
+
+        if threshold.is_zero() || wait < threshold {
+            self.lagging_since = None;
+            self.lagging = false;
+            return false;
+        }
+        let since = *self.lagging_since.get_or_insert(now);
+        let was_lagging = self.lagging;
+        self.lagging = (now - since).to_std().is_ok_and(|held| held >= sustained);
+        self.lagging && !was_lagging
+    }
+}
+
 /// Shared application state
 #[derive(Debug, Clone)]
 pub struct SharedState {
     /// Current alarm state
     pub alarm_state: AlarmState,
+    /// Mode the system was last armed in
+    pub arm_mode: ArmMode,
     /// Door sensor state (true = open)
     pub door_open: bool,
+    /// When the motion sensor last triggered
+    pub last_motion: Option<DateTime<Utc>>,
+    /// Named zone inputs and whether each is currently active
+    pub zones: BTreeMap<String, bool>,
+    /// Zones excluded from alarm processing until the next disarm
+    pub bypassed_zones: BTreeSet<String>,
     /// Actuator states
     pub actuators: ActuatorState,
+    /// An alarm has occurred and not yet been acknowledged
+    pub alarm_memory: bool,
+    /// Cause of the alarm currently sounding
+    pub alarm_kind: Option<AlarmKind>,
+    /// Cadence the siren plays while switched on
+    pub siren_pattern: SirenPattern,
+    /// Named auxiliary outputs and whether each is switched on
+    pub outputs: BTreeMap<String, bool>,
     /// Connectivity state
     pub connectivity: ConnectivityState,
+    /// Latest reading of each 1-Wire temperature sensor in degrees Celsius
+    pub temperatures: BTreeMap<String, f64>,
+    /// Registered BLE beacons currently in range
+    pub present_beacons: BTreeSet<String>,
+    /// ADC readings and battery status
+    pub power: PowerState,
+    /// Result of the most recent GPIO wiring self-test
+    pub wiring: Option<WiringReport>,
+    /// Actuators held off by their safety limits
+    pub actuator_limits: LimitStatus,
+    /// Latency and reliability of the cloud WebSocket
+    pub cloud_link: LinkMetrics,
+    /// Frame and press counts of the 433 MHz receiver
+    pub rf433: RfMetrics,
+    /// RF learn mode and the unknown codes it heard
+    pub rf_learn: LearnSession,
+    /// Network settings change waiting for connectivity to confirm it
+    pub network_change: Option<PendingNetworkChange>,
+    /// Latest signal readings of the radio interfaces
+    pub link_quality: Vec<LinkQuality>,
+    /// Stability of the interfaces in `network.prefer`
+    pub interface_health: Vec<InterfaceHealth>,
+    /// Failed credential attempts and any lockout they caused
+    pub pin_lockout: PinLockout,
+    /// Lockdown ordered by the master, limiting how the system can be disarmed
+    pub lockdown: Option<LockdownStatus>,
+    /// Time events spend queued for the state machine
+    pub event_loop: EventLoopHealth,
+    /// Status and last error of each subsystem
+    pub health: HealthRegistry,
+    /// Latest SoC temperature and throttling flags
+    pub soc: SocStatus,
+    /// Free space on the data partition and offline queue size
+    pub storage: StorageStatus,
+    /// Resident memory of the agent and its growth
+    pub memory: MemoryStatus,
+    /// Restarts of each supervised background task
+    pub tasks: BTreeMap<String, TaskStatus>,
     /// Active timer state
     pub timers: TimerState,
     /// Recent events (limited to last 50)
@@ -110,9 +283,36 @@ impl Default for SharedState {
         let now = Utc::now();
         Self {
             alarm_state: AlarmState::Disarmed,
+            arm_mode: ArmMode::default(),
             door_open: false,
+            last_motion: None,
+            zones: BTreeMap::new(),
+            bypassed_zones: BTreeSet::new(),
             actuators: ActuatorState::default(),
+            alarm_memory: false,
+            alarm_kind: None,
+            siren_pattern: SirenPattern::default(),
+            outputs: BTreeMap::new(),
             connectivity: ConnectivityState::default(),
+            temperatures: BTreeMap::new(),
+            present_beacons: BTreeSet::new(),
+            power: PowerState::default(),
+            wiring: None,
+            actuator_limits: LimitStatus::default(),
+            cloud_link: LinkMetrics::default(),
+            rf433: RfMetrics::default(),
+            rf_learn: LearnSession::default(),
+            network_change: None,
+            link_quality: Vec::new(),
+            interface_health: Vec::new(),
+            pin_lockout: PinLockout::default(),
+            lockdown: None,
+            event_loop: EventLoopHealth::default(),
+            health: HealthRegistry::default(),
+            soc: SocStatus::default(),
+            storage: StorageStatus::default(),
+            memory: MemoryStatus::default(),
+            tasks: BTreeMap::new(),
             timers: TimerState::default(),
             last_events: VecDeque::with_capacity(50),
             last_updated: now,
@@ -147,6 +347,35 @@ impl SharedState {
         self.last_updated = Utc::now();
     }
 
+    /// Set arm mode and update timestamp
+    pub fn set_arm_mode(&mut self, mode: ArmMode) {
+        self.arm_mode = mode;
+        self.last_updated = Utc::now();
+    }
+
+    /// Record a motion detection and update timestamp
+    pub fn record_motion(&mut self) {
+        let now = Utc::now();
+        self.last_motion = Some(now);
+        self.last_updated = now;
+    }
+
+    /// Set a named zone's active flag and update timestamp
+    pub fn set_zone_state(&mut self, zone: &str, active: bool) {
+        self.zones.insert(zone.to_string(), active);
+        self.last_updated = Utc::now();
+    }
+
+    /// Bypass or restore a named zone and update timestamp
+    pub fn set_zone_bypassed(&mut self, zone: &str, bypassed: bool) {
+        if bypassed {
+            self.bypassed_zones.insert(zone.to_string());
+        } else {
+            self.bypassed_zones.remove(zone);
+        }
+        self.last_updated = Utc::now();
+    }
+
     /// Set door state and update timestamp
     pub fn set_door_state(&mut self, open: bool) {
         self.door_open = open;
@@ -159,11 +388,113 @@ impl SharedState {
         self.last_updated = Utc::now();
     }
 
+    /// Set alarm memory and update timestamp
+    pub fn set_alarm_memory(&mut self, memory: bool) {
+        self.alarm_memory = memory;
+        self.last_updated = Utc::now();
+    }
+
+    /// Set the active alarm cause and update timestamp
+    pub fn set_alarm_kind(&mut self, kind: Option<AlarmKind>) {
+        self.alarm_kind = kind;
+        self.last_updated = Utc::now();
+    }
+
+    /// Record a temperature reading and update timestamp
+    pub fn set_temperature(&mut self, sensor: &str, celsius: f64) {
+        self.temperatures.insert(sensor.to_string(), celsius);
+        self.last_updated = Utc::now();
+    }
+
+    /// Record a beacon arriving or leaving and update timestamp
+    pub fn set_beacon_present(&mut self, beacon: &str, present: bool) {
+        if present {
+            self.present_beacons.insert(beacon.to_string());
+        } else {
+            self.present_beacons.remove(beacon);
+        }
+        self.last_updated = Utc::now();
+    }
+
+    /// Record an ADC channel reading and update timestamp
+    pub fn set_analog(&mut self, channel: &str, value: f64) {
+        self.power.analog.insert(channel.to_string(), value);
+        self.last_updated = Utc::now();
+    }
+
+    /// Set battery status flags and update timestamp
+    pub fn set_battery(&mut self, on_battery: bool, low_battery: bool) {
+        self.power.on_battery = on_battery;
+        self.power.low_battery = low_battery;
+        self.last_updated = Utc::now();
+    }
+
+    /// Store a wiring self-test result and update timestamp
+    pub fn set_wiring(&mut self, report: WiringReport) {
+        self.wiring = Some(report);
+        self.last_updated = Utc::now();
+    }
+
+    /// Store actuator safety limit status and update timestamp
+    pub fn set_actuator_limits(&mut self, limits: LimitStatus) {
+        self.actuator_limits = limits;
+        self.last_updated = Utc::now();
+    }
+
+    /// Set siren cadence and update timestamp
+    pub fn set_siren_pattern(&mut self, pattern: SirenPattern) {
+        self.siren_pattern = pattern;
+        self.last_updated = Utc::now();
+    }
+
+    /// Set a named auxiliary output and update timestamp
+    pub fn set_output(&mut self, name: &str, on: bool) {
+        self.outputs.insert(name.to_string(), on);
+        self.last_updated = Utc::now();
+    }
+
     /// Set connectivity state and update timestamp
     pub fn set_connectivity(&mut self, connectivity: ConnectivityState) {
         self.connectivity = connectivity;
         self.last_updated = Utc::now();
     }
+
+    /// Set the cloud link status, noting when it comes online, and update timestamp
+    pub fn set_cloud_status(&mut self, status: CloudStatus) {
+        let now = Utc::now();
+        if status == CloudStatus::Online {
+            self.connectivity.cloud_last_connected = Some(now);
+            self.health.report_ok(Component::Cloud);
+        }
+        self.connectivity.cloud = status;
+        self.last_updated = now;
+    }
+
+    /// Record the uplink interface in use, if any
+    pub fn set_interface(&mut self, interface: Option<String>) {
+        match &interface {
+            Some(_) => self.health.report_ok(Component::Network),
+            None => self.health.report_error(Component::Network, HealthStatus::Degraded, "No usable network interface"),
+        }
+        self.connectivity.interface = interface;
+        self.last_updated = Utc::now();
+    }
+
+    pub fn set_setup_ap(&mut self, ssid: Option<String>) {
+        self.connectivity.setup_ap = ssid;
+        self.last_updated = Utc::now();
+    }
+
+    /// Record a clock comparison with the master, returning whether the clock
+    /// has just gone out of tolerance
+    pub fn set_clock_skew(&mut self, skew_ms: i64, threshold_ms: i64) -> bool {
+        let skewed = skew_ms.abs() > threshold_ms;
+        let newly_skewed = skewed && !self.connectivity.clock_skewed;
+        self.connectivity.clock_skew_ms = Some(skew_ms);
+        self.connectivity.clock_skewed = skewed;
+        self.last_updated = Utc::now();
+        newly_skewed
+    }
 }
 
 /// Thread-safe shared application state
@@ -216,10 +547,44 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_cloud_status_keeps_last_connected() {
+        let mut state = SharedState::new();
+        state.set_cloud_status(CloudStatus::Connecting);
+        assert_eq!(state.connectivity.cloud_last_connected, None);
+
+        state.set_cloud_status(CloudStatus::Online);
+        let connected = state.connectivity.cloud_last_connected;
+        assert!(connected.is_some());
+
+        state.set_cloud_status(CloudStatus::Offline);
+        assert_eq!(state.connectivity.cloud, CloudStatus::Offline);
+        assert_eq!(state.connectivity.cloud_last_connected, connected);
+    }
+
     #[test]
     fn test_uptime_calculation() {
         let state = SharedState::new();
         std::thread::sleep(std::time::Duration::from_millis(100));
         assert!(state.uptime_s() >= 0);
     }
+
+    #[test]
+    fn test_event_loop_lag_must_be_sustained() {
+        let mut health = EventLoopHealth::default();
+        let (threshold, sustained) = (Duration::from_millis(500), Duration::from_secs(30));
+        let start = Utc::now();
+        let slow = Duration::from_secs(1);
+
+        assert!(!health.observe(slow, threshold, sustained, start));
+        assert!(!health.lagging && health.lagging_since == Some(start));
+        assert!(health.observe(slow, threshold, sustained, start + chrono::Duration::seconds(30)));
+        assert!(!health.observe(slow, threshold, sustained, start + chrono::Duration::seconds(31)));
+        assert!(health.lagging);
+
+        // One prompt event clears it
+        health.observe(Duration::from_millis(2), threshold, sustained, start + chrono::Duration::seconds(32));
+        assert!(!health.lagging && health.lagging_since.is_none());
+        assert_eq!((health.processed, health.last_wait_ms, health.max_wait_ms), (4, 2, 1000));
+    }
 }
@@ -33,9 +33,31 @@ pub fn next_state(current: AlarmState, event: &Event) -> Option<AlarmState> {
         // Door open while armed -> entry delay
         (AlarmState::Armed, Event::DoorOpen) => Some(AlarmState::EntryDelay),
         
+        // Motion in an armed zone -> entry delay
+        (AlarmState::Armed, Event::MotionDetected { .. }) => Some(AlarmState::EntryDelay),
+        
         // User disarm from armed -> disarmed
         (AlarmState::Armed, Event::UserDisarm { .. }) => Some(AlarmState::Disarmed),
         
+        // Intrusion zone input while armed -> entry delay
+        (AlarmState::Armed, Event::ZoneTriggered { zone_type, .. }) if zone_type.is_intrusion() => {
+            Some(AlarmState::EntryDelay)
+        }
+        
+        // Fire and tamper zones or a panic button -> immediate alarm, armed or not
+        (state, Event::ZoneTriggered { zone_type, .. })
+            if state != AlarmState::Alarm && zone_type.instant_alarm().is_some() =>
+        {
+            Some(AlarmState::Alarm)
+        }
+        (state, Event::Panic { .. } | Event::Tamper { .. }) if state != AlarmState::Alarm => {
+            Some(AlarmState::Alarm)
+        }
+        
+        // Glass break while armed -> immediate alarm
+        (AlarmState::Armed, Event::GlassBreak) => Some(AlarmState::Alarm),
+        (AlarmState::EntryDelay, Event::GlassBreak) => Some(AlarmState::Alarm),
+        
         // Entry delay expired -> alarm
         (AlarmState::EntryDelay, Event::TimerEntryExpired) => Some(AlarmState::Alarm),
         
@@ -76,10 +98,12 @@ pub fn actuator_state_for(alarm_state: AlarmState, in_alarm: bool) -> ActuatorSt
         AlarmState::Alarm => ActuatorState {
             siren: in_alarm, // Siren on only if we're in active alarm
             floodlight: true,
+            strobe: true, // Strobe keeps flashing after the siren times out
         },
         _ => ActuatorState {
             siren: false,
             floodlight: false,
+            strobe: false,
         },
     }
 }
@@ -93,13 +117,15 @@ pub fn is_valid_transition(from: AlarmState, to: AlarmState, event: &Event) -> b
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::events::EventSource;
+    use crate::events::{EventSource, ZoneType};
+    use crate::state::ArmMode;
 
     #[test]
     fn test_disarmed_to_exit_delay() {
         let event = Event::UserArm {
             source: EventSource::Local,
             exit_delay_s: Some(30),
+            mode: ArmMode::Away,
         };
         assert_eq!(
             next_state(AlarmState::Disarmed, &event),
@@ -125,6 +151,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_armed_to_entry_delay_on_motion() {
+        let event = Event::MotionDetected { zone: ZoneType::Interior };
+        assert_eq!(
+            next_state(AlarmState::Armed, &event),
+            Some(AlarmState::EntryDelay)
+        );
+        assert_eq!(next_state(AlarmState::Disarmed, &event), None);
+    }
+
     #[test]
     fn test_entry_delay_to_alarm() {
         let event = Event::TimerEntryExpired;
@@ -134,6 +170,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_environmental_zone_never_transitions() {
+        let leak = Event::ZoneTriggered {
+            zone: "water_leak".to_string(),
+            zone_type: ZoneType::Environmental,
+        };
+        assert_eq!(next_state(AlarmState::Armed, &leak), None);
+
+        let window = Event::ZoneTriggered {
+            zone: "window".to_string(),
+            zone_type: ZoneType::Perimeter,
+        };
+        assert_eq!(next_state(AlarmState::Armed, &window), Some(AlarmState::EntryDelay));
+    }
+
+    #[test]
+    fn test_glass_break_triggers_alarm() {
+        let event = Event::GlassBreak;
+        assert_eq!(next_state(AlarmState::Armed, &event), Some(AlarmState::Alarm));
+        assert_eq!(next_state(AlarmState::EntryDelay, &event), Some(AlarmState::Alarm));
+        assert_eq!(next_state(AlarmState::Disarmed, &event), None);
+        assert_eq!(next_state(AlarmState::ExitDelay, &event), None);
+    }
+
+    #[test]
+    fn test_fire_and_panic_alarm_from_any_state() {
+        let smoke = Event::ZoneTriggered {
+            zone: "smoke".to_string(),
+            zone_type: ZoneType::Fire,
+        };
+        let panic = Event::Panic { source: EventSource::Local };
+        for state in [AlarmState::Disarmed, AlarmState::ExitDelay, AlarmState::Armed, AlarmState::EntryDelay] {
+            assert_eq!(next_state(state, &smoke), Some(AlarmState::Alarm));
+            assert_eq!(next_state(state, &panic), Some(AlarmState::Alarm));
+        }
+        assert_eq!(next_state(AlarmState::Alarm, &smoke), None);
+
+        let tamper = Event::Tamper { device: "siren_box".to_string() };
+        assert_eq!(next_state(AlarmState::Disarmed, &tamper), Some(AlarmState::Alarm));
+    }
+
     #[test]
     fn test_disarm_from_any_state() {
         let event = Event::UserDisarm {
@@ -182,17 +259,17 @@ mod tests {
     fn test_actuator_states() {
         assert_eq!(
             actuator_state_for(AlarmState::Disarmed, false),
-            ActuatorState { siren: false, floodlight: false }
+            ActuatorState { siren: false, floodlight: false, strobe: false }
         );
         
         assert_eq!(
             actuator_state_for(AlarmState::Alarm, true),
-            ActuatorState { siren: true, floodlight: true }
+            ActuatorState { siren: true, floodlight: true, strobe: true }
         );
         
         assert_eq!(
             actuator_state_for(AlarmState::Alarm, false), // Siren timer expired
-            ActuatorState { siren: false, floodlight: true }
+            ActuatorState { siren: false, floodlight: true, strobe: true }
         );
     }
 
@@ -201,6 +278,7 @@ mod tests {
         let event = Event::UserArm {
             source: EventSource::Local,
             exit_delay_s: Some(30),
+            mode: ArmMode::Away,
         };
         
         assert!(is_valid_transition(
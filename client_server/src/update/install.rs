@@ -0,0 +1,160 @@
+//! Root side: installing staged updates and rolling back ones that keep failing
+//!
+//! Runs from `pi-door-client --install-update` before each start. Everything
+//! in the staging directory is writable by the agent, so the staged file is
+//! verified again here and a pending update can at worst be rolled back to
+//! the previously installed binary.
+
+use super::{
+    read_manifest, verify, write_manifest, PendingUpdate, UpdateKind, UpdateRequest, PENDING_MANIFEST,
+    ROLLED_BACK_MANIFEST, STAGED_FILE, STAGED_MANIFEST,
+};
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{error, info, warn};
+
+/// Roll back an update that failed to start too often, then install what is staged
+pub fn prepare_start(dir: &Path, install_path: &Path, public_key: Option<&[u8]>, max_boot_attempts: u32) -> Result<()> {
+    check_boot(dir, install_path, max_boot_attempts)?;
+    match public_key {
+        Some(public_key) => install_staged(dir, install_path, public_key),
+        None => Ok(()),
+    }
+}
+
+/// Where the binary replaced by the last update is kept
+fn previous_path(install_path: &Path) -> PathBuf {
+    let mut path = install_path.as_os_str().to_owned();
+    path.push(".previous");
+    PathBuf::from(path)
+}
+
+/// Count another start of an unconfirmed update, restoring the previous binary once it is out of attempts
+fn check_boot(dir: &Path, install_path: &Path, max_boot_attempts: u32) -> Result<()> {
+    let path = dir.join(PENDING_MANIFEST);
+    let Some(mut pending) = read_manifest::<PendingUpdate>(&path)? else {
+        return Ok(());
+    };
+    if pending.boots < max_boot_attempts {
+        pending.boots += 1;
+        info!(version = %pending.version, boot = pending.boots, "Starting unconfirmed update");
+        return write_manifest(&path, &pending);
+    }
+
+    warn!(version = %pending.version, previous = %pending.previous_version, "Update never came up, rolling back");
+    replace_binary(install_path, &std::fs::read(previous_path(install_path))?)?;
+    write_manifest(&dir.join(ROLLED_BACK_MANIFEST), &pending)?;
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+/// Verify and install the staged update; it is removed either way so a bad one is not retried
+fn install_staged(dir: &Path, install_path: &Path, public_key: &[u8]) -> Result<()> {
+    let manifest = dir.join(STAGED_MANIFEST);
+    let Some(request) = read_manifest::<UpdateRequest>(&manifest)? else {
+        return Ok(());
+    };
+    let staged = dir.join(STAGED_FILE);
+    let result = install(&request, &staged, install_path, public_key);
+    std::fs::remove_file(&manifest).ok();
+    std::fs::remove_file(&staged).ok();
+    if let Err(e) = &result {
+        error!(version = %request.version, error = %e, "Staged update not installed");
+        return result;
+    }
+
+    let pending = PendingUpdate {
+        version: request.version.clone(),
+        previous_version: crate::VERSION.to_string(),
+        // The start about to happen is the first
+        boots: 1,
+    };
+    write_manifest(&dir.join(PENDING_MANIFEST), &pending)?;
+    info!(version = %request.version, "Update installed");
+    Ok(())
+}
+
+fn install(request: &UpdateRequest, staged: &Path, install_path: &Path, public_key: &[u8]) -> Result<()> {
+    let data = std::fs::read(staged).with_context(|| format!("Failed to read {}", staged.display()))?;
+    verify(public_key, &data, &request.signature)?;
+
+    std::fs::copy(install_path, previous_path(install_path))
+        .with_context(|| format!("Failed to keep a copy of {}", install_path.display()))?;
+    match request.kind {
+        UpdateKind::Binary => replace_binary(install_path, &data),
+        UpdateKind::Deb => {
+            let status = Command::new("dpkg").arg("-i").arg(staged).status().context("Failed to run dpkg")?;
+            if !status.success() {
+                bail!("dpkg -i failed with {}", status);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Write an executable next to `install_path`, then move it into place
+fn replace_binary(install_path: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp = install_path.as_os_str().to_owned();
+    tmp.push(".new");
+    let tmp = PathBuf::from(tmp);
+    std::fs::write(&tmp, data).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o755))?;
+    }
+    std::fs::rename(&tmp, install_path).with_context(|| format!("Failed to replace {}", install_path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::update::tests::{sign, test_key};
+    use crate::update::write_atomic;
+    use ring::signature::KeyPair;
+    use tempfile::TempDir;
+
+    fn stage(dir: &Path, data: &[u8], signature: String) {
+        let request = UpdateRequest {
+            version: "99.0.0".to_string(),
+            url: "https://updates.example.com/agent".to_string(),
+            signature,
+            kind: UpdateKind::Binary,
+        };
+        write_atomic(&dir.join(STAGED_FILE), data).unwrap();
+        write_manifest(&dir.join(STAGED_MANIFEST), &request).unwrap();
+    }
+
+    #[test]
+    fn test_install_and_roll_back() {
+        let key = test_key();
+        let public_key = key.public_key().as_ref();
+        let staging = TempDir::new().unwrap();
+        let bin = TempDir::new().unwrap();
+        let install_path = bin.path().join("pi-door-client");
+        std::fs::write(&install_path, b"old agent").unwrap();
+
+        // A file that does not match its signature never gets installed
+        stage(staging.path(), b"evil agent", sign(&key, b"new agent"));
+        assert!(prepare_start(staging.path(), &install_path, Some(public_key), 3).is_err());
+        assert_eq!(std::fs::read(&install_path).unwrap(), b"old agent");
+        assert!(!staging.path().join(STAGED_MANIFEST).exists());
+
+        stage(staging.path(), b"new agent", sign(&key, b"new agent"));
+        prepare_start(staging.path(), &install_path, Some(public_key), 3).unwrap();
+        assert_eq!(std::fs::read(&install_path).unwrap(), b"new agent");
+        assert_eq!(std::fs::read(previous_path(&install_path)).unwrap(), b"old agent");
+
+        // Two more starts are allowed, the next one restores the old binary
+        for _ in 0..2 {
+            prepare_start(staging.path(), &install_path, Some(public_key), 3).unwrap();
+            assert_eq!(std::fs::read(&install_path).unwrap(), b"new agent");
+        }
+        prepare_start(staging.path(), &install_path, Some(public_key), 3).unwrap();
+        assert_eq!(std::fs::read(&install_path).unwrap(), b"old agent");
+        assert!(!staging.path().join(PENDING_MANIFEST).exists());
+        let rolled_back: PendingUpdate = read_manifest(&staging.path().join(ROLLED_BACK_MANIFEST)).unwrap().unwrap();
+        assert_eq!(rolled_back.version, "99.0.0");
+    }
+}
@@ -0,0 +1,159 @@
+//! Signed over-the-air updates of the agent
+//!
+//! An `update` command from the master names a version, where to download it
+//! and an Ed25519 signature over the file. The agent downloads it, checks the
+//! signature against the key embedded at build time, stages it in
+//! `<data_dir>/update` and exits so systemd restarts it. Before every start
+//! `pi-door-client --install-update` runs as root (`ExecStartPre=+`), checks
+//! the signature again and installs the file, keeping the previous binary
+//! next to it. The new version is kept once it stays up for
+//! `update.confirm_after_s`; one that fails to get there within
+//! `update.max_boot_attempts` starts is replaced by the previous binary.
+
+mod install;
+mod stage;
+
+pub use install::prepare_start;
+pub use stage::{confirm_boot, Updater};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::path::Path;
+
+/// Base64 Ed25519 public key updates must be signed with, set when building
+const EMBEDDED_KEY: Option<&str> = option_env!("PI_DOOR_UPDATE_KEY");
+
+/// Downloaded file awaiting installation
+const STAGED_FILE: &str = "staged";
+/// The [`UpdateRequest`] the staged file came with
+const STAGED_MANIFEST: &str = "staged.json";
+/// Installed version that has not been confirmed yet
+const PENDING_MANIFEST: &str = "pending.json";
+/// Left by a rollback for the agent to report
+const ROLLED_BACK_MANIFEST: &str = "rolled_back.json";
+
+/// Arguments of the `update` command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateRequest {
+    pub version: String,
+    pub url: String,
+    /// Base64 Ed25519 signature over the downloaded file
+    pub signature: String,
+    #[serde(default)]
+    pub kind: UpdateKind,
+}
+
+/// What the download holds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateKind {
+    /// The agent executable, replacing the running one
+    #[default]
+    Binary,
+    /// A Debian package, installed with `dpkg -i`
+    Deb,
+}
+
+/// How far an update got
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateStage {
+    /// Downloaded and verified; installed on the next start
+    Staged,
+    /// Could not be downloaded or verified
+    Failed,
+    /// The new version stayed up and is kept
+    Installed,
+    /// The new version kept failing and the previous one is back
+    RolledBack,
+}
+
+/// An installed version and how often it has been started without being confirmed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingUpdate {
+    version: String,
+    previous_version: String,
+    boots: u32,
+}
+
+/// The update key compiled into this binary
+pub fn embedded_key() -> Result<Vec<u8>> {
+    let key = EMBEDDED_KEY.context("Built without PI_DOOR_UPDATE_KEY, updates are disabled")?;
+    let key = STANDARD.decode(key.trim()).context("Invalid PI_DOOR_UPDATE_KEY")?;
+    if key.len() != 32 {
+        bail!("PI_DOOR_UPDATE_KEY must be a 32-byte Ed25519 public key");
+    }
+    Ok(key)
+}
+
+/// Check a base64 Ed25519 signature over `data`
+pub fn verify(public_key: &[u8], data: &[u8], signature: &str) -> Result<()> {
+    let signature = STANDARD.decode(signature.trim()).context("Invalid update signature encoding")?;
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(data, &signature)
+        .map_err(|_| anyhow!("Update signature does not match the update key"))
+}
+
+/// Ask the running agent to shut down cleanly so systemd starts it again
+pub fn restart() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use nix::sys::signal::{kill, Signal};
+        use nix::unistd::Pid;
+        kill(Pid::this(), Signal::SIGTERM).context("Failed to signal restart")
+    }
+    #[cfg(not(unix))]
+    bail!("Restarting for an update needs systemd")
+}
+
+fn read_manifest<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(Some(
+            serde_json::from_slice(&bytes).with_context(|| format!("Corrupt {}", path.display()))?,
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Replace `path` atomically so a crash never leaves half a file behind
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, contents).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+fn write_manifest<T: Serialize>(path: &Path, manifest: &T) -> Result<()> {
+    write_atomic(path, &serde_json::to_vec_pretty(manifest)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    /// Key pair standing in for the release key
+    pub(crate) fn test_key() -> Ed25519KeyPair {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+    }
+
+    pub(crate) fn sign(key: &Ed25519KeyPair, data: &[u8]) -> String {
+        STANDARD.encode(key.sign(data).as_ref())
+    }
+
+    #[test]
+    fn test_verify() {
+        let key = test_key();
+        let public_key = key.public_key().as_ref();
+        let signature = sign(&key, b"new agent");
+
+        assert!(verify(public_key, b"new agent", &signature).is_ok());
+        assert!(verify(public_key, b"tampered agent", &signature).is_err());
+        assert!(verify(test_key().public_key().as_ref(), b"new agent", &signature).is_err());
+        assert!(verify(public_key, b"new agent", "not base64!").is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! Agent side: downloading, verifying and staging updates, and confirming them once running
+
+use super::{
+    read_manifest, verify, write_atomic, write_manifest, PendingUpdate, UpdateRequest, UpdateStage,
+    PENDING_MANIFEST, ROLLED_BACK_MANIFEST, STAGED_FILE, STAGED_MANIFEST,
+};
+use crate::cloud::http_client;
+use crate::config::{ProxyConfig, UpdateConfig};
+use crate::events::{Event, EventBus};
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Downloads signed updates into the staging directory
+#[derive(Clone)]
+pub struct Updater {
+    dir: PathBuf,
+    public_key: Vec<u8>,
+    max_size: u64,
+    http: reqwest::Client,
+}
+
+impl Updater {
+    pub fn new(dir: PathBuf, public_key: Vec<u8>, config: &UpdateConfig, proxy: Option<&ProxyConfig>) -> Result<Self> {
+        Ok(Self {
+            dir,
+            public_key,
+            max_size: config.max_size_mb * 1024 * 1024,
+            http: http_client(proxy, Duration::from_secs(600))?,
+        })
+    }
+
+    /// Download and verify the update, then stage it for the next start
+    pub async fn stage(&self, request: &UpdateRequest) -> Result<()> {
+        if request.version == crate::VERSION {
+            bail!("Version {} is already running", request.version);
+        }
+        let data = self.download(&request.url).await?;
+        verify(&self.public_key, &data, &request.signature)?;
+
+        std::fs::create_dir_all(&self.dir).with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        write_atomic(&self.dir.join(STAGED_FILE), &data)?;
+        write_manifest(&self.dir.join(STAGED_MANIFEST), request)?;
+        info!(version = %request.version, kind = ?request.kind, bytes = data.len(), "Update staged");
+        Ok(())
+    }
+
+    async fn download(&self, url: &str) -> Result<Vec<u8>> {
+        let mut response = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to download {}", url))?;
+        if !response.status().is_success() {
+            bail!("Update server answered {} for {}", response.status(), url);
+        }
+        let mut data = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if (data.len() + chunk.len()) as u64 > self.max_size {
+                bail!("Update is larger than update.max_size_mb");
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+}
+
+/// Report the outcome of the last installation: a rollback at once, a new
+/// version once it has stayed up for `confirm_after`
+pub async fn confirm_boot(dir: PathBuf, confirm_after: Duration, event_bus: EventBus) -> Result<()> {
+    let rolled_back = dir.join(ROLLED_BACK_MANIFEST);
+    if let Some(pending) = read_manifest::<PendingUpdate>(&rolled_back)? {
+        warn!(version = %pending.version, "Update failed to start, previous version restored");
+        event_bus.emit(Event::AgentUpdate {
+            version: pending.version,
+            stage: UpdateStage::RolledBack,
+            error: None,
+        })?;
+        std::fs::remove_file(&rolled_back)?;
+    }
+
+    let path = dir.join(PENDING_MANIFEST);
+    let Some(pending) = read_manifest::<PendingUpdate>(&path)? else {
+        return Ok(());
+    };
+    if pending.version != crate::VERSION {
+        // Not the version that was installed; the next start counts against it
+        warn!(expected = %pending.version, running = crate::VERSION, "Running version does not match the installed update");
+        return Ok(());
+    }
+
+    tokio::time::sleep(confirm_after).await;
+    std::fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    info!(version = %pending.version, previous = %pending.previous_version, "Update confirmed");
+    event_bus.emit(Event::AgentUpdate {
+        version: pending.version,
+        stage: UpdateStage::Installed,
+        error: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::update::tests::{sign, test_key};
+    use crate::update::UpdateKind;
+    use axum::{routing::get, Router};
+    use ring::signature::KeyPair;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_stage_verifies_download() {
+        let app = Router::new().route("/agent", get(|| async { "new agent" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let key = test_key();
+        let dir = TempDir::new().unwrap();
+        let updater =
+            Updater::new(dir.path().to_path_buf(), key.public_key().as_ref().to_vec(), &UpdateConfig::default(), None)
+                .unwrap();
+        let mut request = UpdateRequest {
+            version: "99.0.0".to_string(),
+            url: format!("http://{}/agent", addr),
+            signature: sign(&key, b"something else"),
+            kind: UpdateKind::Binary,
+        };
+
+        assert!(updater.stage(&request).await.is_err());
+        assert!(!dir.path().join(STAGED_FILE).exists());
+
+        request.signature = sign(&key, b"new agent");
+        updater.stage(&request).await.unwrap();
+        assert_eq!(std::fs::read(dir.path().join(STAGED_FILE)).unwrap(), b"new agent");
+        let staged: UpdateRequest = read_manifest(&dir.path().join(STAGED_MANIFEST)).unwrap().unwrap();
+        assert_eq!(staged.version, "99.0.0");
+    }
+
+    #[tokio::test]
+    async fn test_confirm_boot() {
+        let dir = TempDir::new().unwrap();
+        let pending = PendingUpdate {
+            version: crate::VERSION.to_string(),
+            previous_version: "0.0.1".to_string(),
+            boots: 1,
+        };
+        write_manifest(&dir.path().join(PENDING_MANIFEST), &pending).unwrap();
+
+        let (bus, mut rx) = EventBus::new();
+        confirm_boot(dir.path().to_path_buf(), Duration::from_millis(10), bus).await.unwrap();
+        assert!(!dir.path().join(PENDING_MANIFEST).exists());
+        match rx.recv().await.unwrap() {
+            Event::AgentUpdate { version, stage, .. } => {
+                assert_eq!(version, crate::VERSION);
+                assert_eq!(stage, UpdateStage::Installed);
+            }
+            other => panic!("Unexpected event {other:?}"),
+        }
+    }
+}
@@ -29,7 +29,7 @@ async fn start_test_server() -> (String, tokio::task::JoinHandle<()>) {
         }
     });
     
-    let app = api::create_router(state, event_bus, config);
+    let app = api::create_router(api::ApiContext::for_test(state, event_bus, config));
     let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
         .await
         .unwrap();
@@ -206,3 +206,29 @@ async fn test_full_arm_disarm_workflow() {
     
     handle.abort();
 }
+
+#[tokio::test]
+async fn test_request_ids() {
+    let (url, handle) = start_test_server().await;
+    let client = reqwest::Client::new();
+
+    // Each response carries a request ID, generated unless the caller sent one
+    let response = client.get(format!("{}/v1/health", url)).send().await.unwrap();
+    let generated = response.headers()["x-request-id"].to_str().unwrap().to_string();
+    assert_eq!(generated.len(), 36);
+    assert_eq!(response.headers()["x-correlation-id"].to_str().unwrap(), generated);
+
+    // Error bodies name it, so a failure can be found in the logs
+    let response = client
+        .get(format!("{}/v1/token", url))
+        .header("x-request-id", "req-42")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+    assert_eq!(response.headers()["x-request-id"], "req-42");
+    let json: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(json["request_id"], "req-42");
+
+    handle.abort();
+}
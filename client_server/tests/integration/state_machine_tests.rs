@@ -3,7 +3,7 @@
 use pi_door_client::{
     config::TimerConfig,
     events::{Event, EventBus, EventSource},
-    state::{new_app_state, AlarmState, StateMachine},
+    state::{new_app_state, AlarmState, ArmMode, StateMachine},
 };
 use tokio::time::{sleep, Duration};
 
@@ -13,6 +13,12 @@ fn test_timer_config() -> TimerConfig {
         entry_delay_s: 2,
         auto_rearm_s: 3,
         siren_max_s: 2,
+        siren_max_by_kind: Default::default(),
+        motion_floodlight_s: 2,
+        courtesy_light_s: 0,
+        courtesy_override_s: 900,
+        night_start: "18:00".to_string(),
+        night_end: "07:00".to_string(),
     }
 }
 
@@ -42,6 +48,7 @@ async fn test_complete_arm_cycle() {
         .emit(Event::UserArm {
             source: EventSource::Local,
             exit_delay_s: Some(2),
+            mode: ArmMode::Away,
         })
         .unwrap();
 
@@ -76,6 +83,7 @@ async fn test_alarm_trigger_on_door_open() {
         .emit(Event::UserArm {
             source: EventSource::Local,
             exit_delay_s: Some(2),
+            mode: ArmMode::Away,
         })
         .unwrap();
     sleep(Duration::from_secs(3)).await;
@@ -117,6 +125,7 @@ async fn test_disarm_during_entry_delay() {
         .emit(Event::UserArm {
             source: EventSource::Local,
             exit_delay_s: Some(2),
+            mode: ArmMode::Away,
         })
         .unwrap();
     sleep(Duration::from_secs(3)).await;
@@ -203,6 +212,7 @@ async fn test_siren_timer_expiration() {
         .emit(Event::UserArm {
             source: EventSource::Local,
             exit_delay_s: Some(1),
+            mode: ArmMode::Away,
         })
         .unwrap();
     sleep(Duration::from_secs(2)).await;
@@ -243,6 +253,7 @@ async fn test_manual_actuator_control() {
         .emit(Event::SirenControl {
             on: true,
             duration_s: Some(2),
+            pattern: None,
         })
         .unwrap();
     sleep(Duration::from_millis(100)).await;
@@ -296,6 +307,7 @@ async fn test_door_close_during_entry_delay_doesnt_cancel() {
         .emit(Event::UserArm {
             source: EventSource::Local,
             exit_delay_s: Some(1),
+            mode: ArmMode::Away,
         })
         .unwrap();
     sleep(Duration::from_secs(2)).await;
@@ -7,6 +7,13 @@ mod m20250108_000004_create_sessions;
 mod m20250108_000005_create_events;
 mod m20250108_000006_create_commands;
 mod m20250108_000007_create_heartbeats;
+mod m20250108_000008_add_event_seq;
+mod m20250108_000009_add_client_signing_key;
+mod m20250108_000010_add_command_signatures;
+mod m20250108_000011_add_command_expiry;
+mod m20250108_000012_add_client_tokens;
+mod m20250108_000013_create_health_reports;
+mod m20250108_000014_add_client_config;
 
 pub struct Migrator;
 
@@ -21,6 +28,13 @@ impl MigratorTrait for Migrator {
             Box::new(m20250108_000005_create_events::Migration),
             Box::new(m20250108_000006_create_commands::Migration),
             Box::new(m20250108_000007_create_heartbeats::Migration),
+            Box::new(m20250108_000008_add_event_seq::Migration),
+            Box::new(m20250108_000009_add_client_signing_key::Migration),
+            Box::new(m20250108_000010_add_command_signatures::Migration),
+            Box::new(m20250108_000011_add_command_expiry::Migration),
+            Box::new(m20250108_000012_add_client_tokens::Migration),
+            Box::new(m20250108_000013_create_health_reports::Migration),
+            Box::new(m20250108_000014_add_client_config::Migration),
         ]
     }
 }
@@ -0,0 +1,63 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Per-client sequence number, null for clients that predate it
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Events::Table)
+                    .add_column(ColumnDef::new(Events::Seq).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        // One row per client sequence number; nulls never collide
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_events_client_id_seq")
+                    .table(Events::Table)
+                    .col(Events::ClientId)
+                    .col(Events::Seq)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_events_client_id_seq")
+                    .table(Events::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Events::Table)
+                    .drop_column(Events::Seq)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Events {
+    Table,
+    ClientId,
+    Seq,
+}
@@ -0,0 +1,65 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Command key each client trusts, null for clients registered before signing
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Clients::Table)
+                    .add_column(ColumnDef::new(Clients::CommandKey).string())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Signature sent along with each command
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Commands::Table)
+                    .add_column(ColumnDef::new(Commands::Signature).string())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Commands::Table)
+                    .drop_column(Commands::Signature)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Clients::Table)
+                    .drop_column(Clients::CommandKey)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Clients {
+    Table,
+    CommandKey,
+}
+
+#[derive(DeriveIden)]
+enum Commands {
+    Table,
+    Signature,
+}
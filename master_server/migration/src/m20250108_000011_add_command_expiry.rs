@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Signed along with the command; clients refuse it afterwards
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Commands::Table)
+                    .add_column(ColumnDef::new(Commands::ExpiresAt).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Commands::Table)
+                    .drop_column(Commands::ExpiresAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Commands {
+    Table,
+    ExpiresAt,
+}
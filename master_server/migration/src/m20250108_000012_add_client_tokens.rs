@@ -0,0 +1,42 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SHA-256 of the client's API token, and of the one it replaced while that is still accepted
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Clients::Table)
+                    .add_column(ColumnDef::new(Clients::ApiTokenHash).string())
+                    .add_column(ColumnDef::new(Clients::PreviousTokenHash).string())
+                    .add_column(ColumnDef::new(Clients::PreviousTokenExpiresAt).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Clients::Table)
+                    .drop_column(Clients::ApiTokenHash)
+                    .drop_column(Clients::PreviousTokenHash)
+                    .drop_column(Clients::PreviousTokenExpiresAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Clients {
+    Table,
+    ApiTokenHash,
+    PreviousTokenHash,
+    PreviousTokenExpiresAt,
+}
@@ -0,0 +1,89 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Health reports clients send daily and when a subsystem changes status
+        manager
+            .create_table(
+                Table::create()
+                    .table(HealthReports::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(HealthReports::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(HealthReports::ClientId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(HealthReports::Ts)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(HealthReports::Status).string().not_null())
+                    .col(ColumnDef::new(HealthReports::Trigger).string().not_null())
+                    .col(ColumnDef::new(HealthReports::Report).json_binary().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_health_reports_client_id")
+                            .from(HealthReports::Table, HealthReports::ClientId)
+                            .to(Clients::Table, Clients::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Latest reports of a client, and of the whole fleet, are read by time
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_health_reports_client_id_ts")
+                    .table(HealthReports::Table)
+                    .col(HealthReports::ClientId)
+                    .col(HealthReports::Ts)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_health_reports_ts")
+                    .table(HealthReports::Table)
+                    .col(HealthReports::Ts)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(HealthReports::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum HealthReports {
+    Table,
+    Id,
+    ClientId,
+    Ts,
+    Status,
+    Trigger,
+    Report,
+}
+
+#[derive(DeriveIden)]
+enum Clients {
+    Table,
+    Id,
+}
@@ -6,12 +6,14 @@ use sea_orm::DatabaseConnection;
 use std::sync::Arc;
 use tower_http::trace::TraceLayer;
 
-use crate::{config::Config, handlers};
+use crate::{auth::CommandSigner, config::Config, handlers};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: DatabaseConnection,
     pub config: Arc<Config>,
+    /// Signs commands for clients, none when no command key is configured
+    pub command_signer: Option<Arc<CommandSigner>>,
 }
 
 pub fn create_router(state: AppState) -> Router {
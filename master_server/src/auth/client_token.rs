@@ -0,0 +1,36 @@
+use chrono::{DateTime, FixedOffset};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::entities::clients;
+
+/// Which of a client's tokens a presented token matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenMatch {
+    Current,
+    /// Replaced by a rotation but still inside its grace period
+    Previous,
+}
+
+/// A new client API token and the hash stored for it
+pub fn issue_client_token() -> (String, String) {
+    let random_bytes: [u8; 32] = rand::thread_rng().gen();
+    let token = hex::encode(random_bytes);
+    let hash = hash_client_token(&token);
+    (token, hash)
+}
+
+/// SHA-256 hex of a client token; only the hash is kept
+fn hash_client_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Check a presented token against the client's current and previous tokens
+pub fn match_client_token(client: &clients::Model, token: &str, now: DateTime<FixedOffset>) -> Option<TokenMatch> {
+    let hash = hash_client_token(token);
+    if client.api_token_hash.as_deref() == Some(hash.as_str()) {
+        return Some(TokenMatch::Current);
+    }
+    let in_grace = client.previous_token_expires_at.is_some_and(|expires_at| expires_at > now);
+    (in_grace && client.previous_token_hash.as_deref() == Some(hash.as_str())).then_some(TokenMatch::Previous)
+}
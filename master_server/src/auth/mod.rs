@@ -2,6 +2,8 @@ pub mod password;
 pub mod session;
 pub mod otp;
 pub mod middleware;
+pub mod signing;
+pub mod client_token;
 
 pub use password::hash_password;
 pub use password::verify_password;
@@ -10,3 +12,8 @@ pub use session::verify_session;
 pub use otp::generate_otp_secret;
 pub use otp::verify_otp_code;
 pub use otp::get_otp_uri;
+pub use signing::verify_event_signature;
+pub use signing::CommandSigner;
+pub use client_token::issue_client_token;
+pub use client_token::match_client_token;
+pub use client_token::TokenMatch;
@@ -0,0 +1,128 @@
+use anyhow::{bail, Context, Result};
+use data_encoding::BASE64;
+use ed25519_dalek::{Signer, SigningKey};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::config::Config;
+
+/// Verify a client's HMAC-SHA256 signature over an event envelope.
+///
+/// The signed bytes are the envelope JSON without its `signature` field,
+/// with object keys sorted, matching what the client signs.
+pub fn verify_event_signature(key_hex: &str, envelope: &Value, signature_hex: &str) -> Result<bool> {
+    let key = hex::decode(key_hex)?;
+    let Ok(signature) = hex::decode(signature_hex) else {
+        return Ok(false);
+    };
+
+    let mut envelope = envelope.clone();
+    if let Some(fields) = envelope.as_object_mut() {
+        fields.remove("signature");
+    }
+    sort_keys(&mut envelope);
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key)?;
+    mac.update(&serde_json::to_vec(&envelope)?);
+    Ok(mac.verify_slice(&signature).is_ok())
+}
+
+/// Ed25519 signatures on commands, so clients act only on what this master issued.
+///
+/// During a rotation the previous key keeps signing for clients that have not
+/// accepted the new one yet.
+pub struct CommandSigner {
+    current: SigningKey,
+    previous: Option<SigningKey>,
+}
+
+impl CommandSigner {
+    /// Signer for `COMMAND_SIGNING_KEY`, or none when it is not set
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let Some(current) = &config.command_signing_key else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            current: decode_seed(current).context("Invalid COMMAND_SIGNING_KEY")?,
+            previous: config
+                .command_signing_key_previous
+                .as_deref()
+                .map(decode_seed)
+                .transpose()
+                .context("Invalid COMMAND_SIGNING_KEY_PREVIOUS")?,
+        }))
+    }
+
+    /// Base64 public key handed to clients
+    pub fn public_key(&self) -> String {
+        BASE64.encode(self.current.verifying_key().as_bytes())
+    }
+
+    /// Sign a command for a client trusting the public key `trusted`.
+    ///
+    /// The signed bytes are the JSON of `{args, client_id, expires_at, id, name}`
+    /// with keys sorted, `expires_at` being Unix seconds and left out when
+    /// `None`. Returns `None` if the client trusts neither key.
+    pub fn sign(
+        &self,
+        trusted: &str,
+        client_id: &str,
+        id: &str,
+        name: &str,
+        args: &Value,
+        expires_at: Option<i64>,
+    ) -> Option<String> {
+        let key = [Some(&self.current), self.previous.as_ref()]
+            .into_iter()
+            .flatten()
+            .find(|key| BASE64.encode(key.verifying_key().as_bytes()) == trusted)?;
+        let mut message = json!({
+            "args": args,
+            "client_id": client_id,
+            "id": id,
+            "name": name,
+        });
+        if let Some(expires_at) = expires_at {
+            message["expires_at"] = json!(expires_at);
+        }
+        sort_keys(&mut message);
+        let message = serde_json::to_vec(&message).ok()?;
+        Some(BASE64.encode(&key.sign(&message).to_bytes()))
+    }
+
+    /// Base32 TOTP key for the lockdown command `id`.
+    ///
+    /// Derived from the current signing key rather than stored, so the
+    /// database never holds it; a lockdown still pending when the key is
+    /// rotated has to be sent again.
+    pub fn lockdown_otp_secret(&self, id: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.current.as_bytes()).expect("HMAC takes any key length");
+        mac.update(b"lockdown-otp:");
+        mac.update(id.as_bytes());
+        data_encoding::BASE32_NOPAD.encode(&mac.finalize().into_bytes()[..20])
+    }
+}
+
+fn decode_seed(seed: &str) -> Result<SigningKey> {
+    let seed = BASE64.decode(seed.trim().as_bytes())?;
+    let Ok(seed) = <[u8; 32]>::try_from(seed.as_slice()) else {
+        bail!("Command signing key must be a 32-byte seed");
+    };
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+fn sort_keys(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            let mut sorted: Vec<_> = std::mem::take(fields).into_iter().collect();
+            sorted.sort_by(|a, b| a.0.cmp(&b.0));
+            for (key, mut field) in sorted {
+                sort_keys(&mut field);
+                fields.insert(key, field);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(sort_keys),
+        _ => {}
+    }
+}
@@ -25,12 +25,14 @@ async fn main() -> Result<()> {
     if args.len() < 2 {
         println!("Usage: masterctl <command>");
         println!("Commands:");
-        println!("  bootstrap-admin  - Create the first admin user");
+        println!("  bootstrap-admin       - Create the first admin user");
+        println!("  generate-command-key  - Create a key for signing client commands");
         return Ok(());
     }
 
     match args[1].as_str() {
         "bootstrap-admin" => bootstrap_admin().await?,
+        "generate-command-key" => generate_command_key(),
         _ => {
             println!("Unknown command: {}", args[1]);
             println!("Run 'masterctl' without arguments for usage.");
@@ -102,3 +104,15 @@ async fn bootstrap_admin() -> Result<()> {
 
     Ok(())
 }
+
+fn generate_command_key() {
+    let key = ed25519_dalek::SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+
+    println!("=== Command Signing Key ===");
+    println!();
+    println!("COMMAND_SIGNING_KEY={}", data_encoding::BASE64.encode(&key.to_bytes()));
+    println!("Public key: {}", data_encoding::BASE64.encode(key.verifying_key().as_bytes()));
+    println!();
+    println!("To rotate, move the current value to COMMAND_SIGNING_KEY_PREVIOUS,");
+    println!("restart, and send each client a rotate_command_key command.");
+}
@@ -6,6 +6,14 @@ pub struct Config {
     pub server_bind: String,
     pub token_ttl_hours: i64,
     pub otp_required: bool,
+    /// Base64 Ed25519 seed that signs commands for clients
+    pub command_signing_key: Option<String>,
+    /// Key being rotated out, still used for clients that trust only it
+    pub command_signing_key_previous: Option<String>,
+    /// Seconds a command stays valid; clients refuse it after that
+    pub command_ttl_s: i64,
+    /// Seconds a client's API token is still accepted after it was rotated
+    pub client_token_grace_s: i64,
 }
 
 impl Config {
@@ -28,11 +36,28 @@ impl Config {
             .and_then(|v| v.parse().ok())
             .unwrap_or(false);
 
+        let command_signing_key = env::var("COMMAND_SIGNING_KEY").ok().filter(|v| !v.is_empty());
+        let command_signing_key_previous = env::var("COMMAND_SIGNING_KEY_PREVIOUS").ok().filter(|v| !v.is_empty());
+
+        let command_ttl_s = env::var("COMMAND_TTL_S")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300);
+
+        let client_token_grace_s = env::var("CLIENT_TOKEN_GRACE_S")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3600);
+
         Self {
             database_url,
             server_bind,
             token_ttl_hours,
             otp_required,
+            command_signing_key,
+            command_signing_key_previous,
+            command_ttl_s,
+            client_token_grace_s,
         }
     }
 }
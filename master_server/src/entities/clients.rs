@@ -15,6 +15,17 @@ pub struct Model {
     pub status: ClientStatus,
     pub last_seen_at: Option<DateTimeWithTimeZone>,
     pub created_at: DateTimeWithTimeZone,
+    /// Hex device key the client signs events with, if it signs them
+    pub signing_key: Option<String>,
+    /// Base64 command key the client verifies commands with
+    pub command_key: Option<String>,
+    /// SHA-256 hex of the API token issued to the client
+    pub api_token_hash: Option<String>,
+    /// Token replaced by the last rotation, accepted until `previous_token_expires_at`
+    pub previous_token_hash: Option<String>,
+    pub previous_token_expires_at: Option<DateTimeWithTimeZone>,
+    /// JSON configuration document the client fetches on `config_pull`, as served
+    pub config_document: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
@@ -38,6 +49,8 @@ pub enum Relation {
     Commands,
     #[sea_orm(has_many = "super::heartbeats::Entity")]
     Heartbeats,
+    #[sea_orm(has_many = "super::health_reports::Entity")]
+    HealthReports,
 }
 
 impl Related<super::user_clients::Entity> for Entity {
@@ -64,4 +77,10 @@ impl Related<super::heartbeats::Entity> for Entity {
     }
 }
 
+impl Related<super::health_reports::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::HealthReports.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
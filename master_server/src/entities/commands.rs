@@ -14,6 +14,10 @@ pub struct Model {
     pub status: CommandStatus,
     pub ts_updated: DateTimeWithTimeZone,
     pub error: Option<String>,
+    /// Base64 Ed25519 signature from the command key the client trusts
+    pub signature: Option<String>,
+    /// Clients refuse the command after this; covered by the signature
+    pub expires_at: Option<DateTimeWithTimeZone>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
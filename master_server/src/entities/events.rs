@@ -12,6 +12,7 @@ pub struct Model {
     pub kind: String,
     pub message: String,
     pub meta: Option<Json>,
+    pub seq: Option<i64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
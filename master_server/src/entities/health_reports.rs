@@ -0,0 +1,36 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "health_reports")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub client_id: Uuid,
+    /// When the report was received
+    pub ts: DateTimeWithTimeZone,
+    /// Overall status the client reported: `ok`, `degraded` or `failed`
+    pub status: String,
+    /// What made the client send it: `scheduled` or `change`
+    pub trigger: String,
+    /// The whole report as the client sent it
+    pub report: Json,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::clients::Entity",
+        from = "Column::ClientId",
+        to = "super::clients::Column::Id"
+    )]
+    Clients,
+}
+
+impl Related<super::clients::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Clients.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -5,6 +5,7 @@ pub mod sessions;
 pub mod events;
 pub mod commands;
 pub mod heartbeats;
+pub mod health_reports;
 
 pub mod prelude {
     pub use super::users::Entity as Users;
@@ -14,4 +15,5 @@ pub mod prelude {
     pub use super::events::Entity as Events;
     pub use super::commands::Entity as Commands;
     pub use super::heartbeats::Entity as Heartbeats;
+    pub use super::health_reports::Entity as HealthReports;
 }
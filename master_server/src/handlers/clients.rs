@@ -1,14 +1,15 @@
-use axum::{  extract::{Path, Query, State},  http::StatusCode,  middleware,
+use axum::{  extract::{Path, Query, State},  http::{header, HeaderMap, StatusCode},  middleware,
     routing::{delete, get, patch, post, Router},
     Extension, Json,
 };
 use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
 use crate::{
     app::AppState,
-    auth::middleware::AuthUser,
+    auth::{issue_client_token, match_client_token, middleware::AuthUser, TokenMatch},
     entities::{prelude::*, clients, user_clients, users},
 };
 
@@ -35,6 +36,8 @@ pub struct RegisterClientRequest {
     pub eth0_ip: Option<String>,
     pub wlan0_ip: Option<String>,
     pub service_port: Option<i32>,
+    /// Hex device key; once set, unsigned or badly signed events are rejected
+    pub signing_key: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -59,6 +62,21 @@ pub struct CreateClientResponse {
 pub struct RegisterClientResponse {
     pub client_id: Uuid,
     pub api_token: String,
+    /// Base64 Ed25519 key the client checks commands against
+    pub command_key: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RotateTokenResponse {
+    pub api_token: String,
+    /// Until when the token this one replaces is still accepted
+    pub previous_valid_until: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConfigDocumentResponse {
+    /// SHA-256 hex of the document as served, which `config_pull` commands carry
+    pub sha256: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -99,6 +117,12 @@ async fn create_client(
         status: Set(clients::ClientStatus::Unknown),
         last_seen_at: Set(None),
         created_at: Set(chrono::Utc::now().into()),
+        signing_key: Set(None),
+        command_key: Set(None),
+        api_token_hash: Set(None),
+        previous_token_hash: Set(None),
+        previous_token_expires_at: Set(None),
+        config_document: Set(None),
     };
 
     client.insert(&state.db).await.map_err(|_| {
@@ -429,6 +453,16 @@ async fn register_client(
     State(state): State<AppState>,
     Json(req): Json<RegisterClientRequest>,
 ) -> Result<Json<RegisterClientResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if let Some(key) = &req.signing_key {
+        if hex::decode(key).map_or(true, |key| key.is_empty()) {
+            return Err((StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: "Invalid signing key".to_string(),
+                }),
+            ));
+        }
+    }
+
     // Find client by provision key
     let client = Clients::find()
         .filter(clients::Column::ProvisionKey.eq(req.provision_key))
@@ -453,8 +487,16 @@ async fn register_client(
     client.eth0_ip = Set(req.eth0_ip);
     client.wlan0_ip = Set(req.wlan0_ip);
     client.service_port = Set(req.service_port);
+    client.signing_key = Set(req.signing_key);
+    client.command_key = Set(state.command_signer.as_ref().map(|signer| signer.public_key()));
     client.provision_key = Set(Uuid::nil()); // Invalidate provision key
 
+    // Only the token's hash is kept, to check it when the client rotates it
+    let (token, token_hash) = issue_client_token();
+    client.api_token_hash = Set(Some(token_hash));
+    client.previous_token_hash = Set(None);
+    client.previous_token_expires_at = Set(None);
+
     let client = client.update(&state.db).await.map_err(|_| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -464,21 +506,210 @@ async fn register_client(
             )
         })?;
 
-    // Generate client API token (using session system with special user ID)
-    let token = hex::encode(rand::random::<[u8; 32]>());
-
-    // In a real implementation, we'd store client tokens separately
-    // For MVP, we'll just return a generated token
-
     Ok(Json(RegisterClientResponse {
         client_id: client.id,
         api_token: token,
+        command_key: client.command_key,
+    }))
+}
+
+/// Replace a client's API token, authenticated with the token being replaced
+///
+/// The old token stays valid for `CLIENT_TOKEN_GRACE_S` so requests already
+/// made with it still go through. A client retrying with that old token
+/// during the grace period gets another new token and keeps the old one's
+/// deadline.
+async fn rotate_token(
+    State(state): State<AppState>,
+    Path(client_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Json<RotateTokenResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let unauthorized = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "Invalid client token".to_string(),
+            }),
+        )
+    };
+    let token = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(unauthorized)?;
+
+    let client = Clients::find_by_id(client_id)
+        .one(&state.db)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Database error".to_string(),
+                }),
+            )
+        })?
+        .ok_or_else(unauthorized)?;
+
+    let now = chrono::Utc::now();
+    let matched = match_client_token(&client, token, now.into()).ok_or_else(unauthorized)?;
+
+    let (new_token, new_hash) = issue_client_token();
+    let mut active: clients::ActiveModel = client.clone().into();
+    if matched == TokenMatch::Current {
+        active.previous_token_hash = Set(client.api_token_hash.clone());
+        active.previous_token_expires_at =
+            Set(Some((now + chrono::Duration::seconds(state.config.client_token_grace_s)).into()));
+    }
+    active.api_token_hash = Set(Some(new_hash));
+
+    let client = active.update(&state.db).await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "Failed to rotate token".to_string(),
+            }),
+        )
+    })?;
+    tracing::info!(client_id = %client.id, ?matched, "Client API token rotated");
+
+    Ok(Json(RotateTokenResponse {
+        api_token: new_token,
+        previous_valid_until: client.previous_token_expires_at.map(|dt| dt.to_rfc3339()),
     }))
 }
 
+/// SHA-256 hex of a stored configuration document, over the bytes the client receives
+pub fn config_digest(document: &str) -> String {
+    hex::encode(Sha256::digest(document.as_bytes()))
+}
+
+/// Store the configuration document a client applies on its next `config_pull`
+async fn put_config(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(client_id): Path<Uuid>,
+    Json(document): Json<serde_json::Value>,
+) -> Result<Json<ConfigDocumentResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if !document.is_object() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Configuration document must be a JSON object".to_string(),
+            }),
+        ));
+    }
+
+    let client = Clients::find_by_id(client_id)
+        .one(&state.db)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Error".to_string(),
+                }),
+            )
+        })?
+        .ok_or((StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Error".to_string(),
+            }),
+        ))?;
+
+    // Check access for non-admin
+    if auth_user.role != users::UserRole::Admin {
+        let assignment = UserClients::find()
+            .filter(user_clients::Column::UserId.eq(auth_user.id))
+            .filter(user_clients::Column::ClientId.eq(client_id))
+            .one(&state.db)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Error".to_string(),
+                }),
+            )
+        })?;
+
+        if assignment.is_none() {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ErrorResponse {
+                    error: "Access denied".to_string(),
+                }),
+            ));
+        }
+    }
+
+    let document = document.to_string();
+    let sha256 = config_digest(&document);
+    let mut client: clients::ActiveModel = client.into();
+    client.config_document = Set(Some(document));
+    client.update(&state.db).await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "Failed to store configuration".to_string(),
+            }),
+        )
+    })?;
+    tracing::info!(%client_id, %sha256, "Client configuration document stored");
+
+    Ok(Json(ConfigDocumentResponse { sha256 }))
+}
+
+/// Serve a client its configuration document, authenticated with its API token
+///
+/// The body is the stored text byte for byte, so it hashes to the digest in
+/// the `config_pull` command.
+async fn get_config(
+    State(state): State<AppState>,
+    Path(client_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<([(header::HeaderName, &'static str); 1], String), (StatusCode, Json<ErrorResponse>)> {
+    let unauthorized = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "Invalid client token".to_string(),
+            }),
+        )
+    };
+    let token = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(unauthorized)?;
+
+    let client = Clients::find_by_id(client_id)
+        .one(&state.db)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Database error".to_string(),
+                }),
+            )
+        })?
+        .ok_or_else(unauthorized)?;
+    match_client_token(&client, token, chrono::Utc::now().into()).ok_or_else(unauthorized)?;
+
+    let document = client.config_document.ok_or((
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: "No configuration stored for this client".to_string(),
+        }),
+    ))?;
+    Ok(([(header::CONTENT_TYPE, "application/json")], document))
+}
+
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/register", post(register_client))
+        .route("/:id/token/rotate", post(rotate_token))
         .route(
             "/",
             post(create_client)
@@ -493,6 +724,11 @@ pub fn router() -> Router<AppState> {
             "/:id/network",
             patch(update_network),
         )
+        .route(
+            "/:id/config",
+            get(get_config)
+                .put(put_config),
+        )
         .route(
             "/:id/assign",
             post(assign_user),
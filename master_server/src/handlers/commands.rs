@@ -8,10 +8,20 @@ use uuid::Uuid;
 
 use crate::{
     app::AppState,
-    auth::middleware::AuthUser,
-    entities::{prelude::*, commands, user_clients, users},
+    auth::{get_otp_uri, middleware::AuthUser, CommandSigner},
+    entities::{prelude::*, clients, commands, user_clients, users},
+    handlers::clients::config_digest,
 };
 
+/// Command moving a client to the current command key
+const ROTATE_COMMAND_KEY: &str = "rotate_command_key";
+
+/// Command making a client fetch and apply its configuration document
+const CONFIG_PULL: &str = "config_pull";
+
+/// Command stopping BLE, RF and keypad disarm on a client until `lift_lockdown`
+const LOCKDOWN: &str = "lockdown";
+
 #[derive(Debug, Deserialize)]
 pub struct CreateCommandRequest {
     pub command: String,
@@ -40,6 +50,12 @@ pub struct CommandResponse {
     pub status: commands::CommandStatus,
     pub ts_updated: String,
     pub error: Option<String>,
+    pub signature: Option<String>,
+    /// Unix seconds after which the client refuses the command
+    pub expires_at: Option<i64>,
+    /// For `lockdown`, the key of the one-time codes that disarm locally, to add to an authenticator
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otp_uri: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -48,7 +64,13 @@ pub struct ErrorResponse {
 }
 
 impl From<commands::Model> for CommandResponse {
-    fn from(cmd: commands::Model) -> Self {
+    fn from(mut cmd: commands::Model) -> Self {
+        // Lockdowns stored before the key was derived may still carry it
+        if cmd.command == LOCKDOWN {
+            if let Some(params) = cmd.params.as_mut().and_then(|params| params.as_object_mut()) {
+                params.remove("otp_secret");
+            }
+        }
         Self {
             id: cmd.id,
             client_id: cmd.client_id,
@@ -59,6 +81,9 @@ impl From<commands::Model> for CommandResponse {
             status: cmd.status,
             ts_updated: cmd.ts_updated.to_rfc3339(),
             error: cmd.error,
+            signature: cmd.signature,
+            expires_at: cmd.expires_at.map(|expires_at| expires_at.timestamp()),
+            otp_uri: None,
         }
     }
 }
@@ -70,7 +95,7 @@ async fn create_command(
     Json(req): Json<CreateCommandRequest>,
 ) -> Result<(StatusCode, Json<CommandResponse>), (StatusCode, Json<ErrorResponse>)> {
     // Check client exists
-    Clients::find_by_id(client_id)
+    let client = Clients::find_by_id(client_id)
         .one(&state.db)
         .await
         .map_err(|_| {
@@ -113,17 +138,96 @@ async fn create_command(
         }
     }
 
+    let mut params = req.params;
+    if req.command == ROTATE_COMMAND_KEY {
+        if auth_user.role != users::UserRole::Admin {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ErrorResponse {
+                    error: "Access denied".to_string(),
+                }),
+            ));
+        }
+        let Some(signer) = &state.command_signer else {
+            return Err((
+                StatusCode::CONFLICT,
+                Json(ErrorResponse {
+                    error: "No command signing key configured".to_string(),
+                }),
+            ));
+        };
+        params = Some(serde_json::json!({ "public_key": signer.public_key() }));
+    }
+    // The client only applies a document matching the digest it was sent
+    if req.command == CONFIG_PULL {
+        let Some(document) = &client.config_document else {
+            return Err((
+                StatusCode::CONFLICT,
+                Json(ErrorResponse {
+                    error: "No configuration stored for this client".to_string(),
+                }),
+            ));
+        };
+        params = Some(serde_json::json!({ "sha256": config_digest(document) }));
+    }
+    // Each lockdown gets its own TOTP key, so a lost phone's authenticator cannot disarm.
+    // The key only travels in the signed command, so lockdown needs a signing key.
+    let id = Uuid::new_v4();
+    let mut otp_uri = None;
+    if req.command == LOCKDOWN {
+        let Some(signer) = &state.command_signer else {
+            return Err((
+                StatusCode::CONFLICT,
+                Json(ErrorResponse {
+                    error: "No command signing key configured".to_string(),
+                }),
+            ));
+        };
+        let reason = params.as_ref().and_then(|params| params.get("reason")).cloned();
+        let secret = signer.lockdown_otp_secret(&id.to_string());
+        otp_uri = Some(get_otp_uri(&secret, &client.label, "Pi Door lockdown"));
+        params = Some(serde_json::json!({ "reason": reason }));
+    }
+
+    // Sign with the key the client trusts; clients registered before signing get unsigned commands
     let now = chrono::Utc::now();
+    let expires_at = now + chrono::Duration::seconds(state.config.command_ttl_s);
+    let signature = match (&state.command_signer, &client.command_key) {
+        (Some(signer), Some(trusted)) => {
+            let args = command_args(signer, id, &req.command, params.as_ref());
+            let signature = signer.sign(
+                trusted,
+                &client_id.to_string(),
+                &id.to_string(),
+                &req.command,
+                &args,
+                Some(expires_at.timestamp()),
+            );
+            if signature.is_none() {
+                return Err((
+                    StatusCode::CONFLICT,
+                    Json(ErrorResponse {
+                        error: "Client trusts neither command signing key".to_string(),
+                    }),
+                ));
+            }
+            signature
+        }
+        _ => None,
+    };
+
     let command = commands::ActiveModel {
-        id: Set(Uuid::new_v4()),
+        id: Set(id),
         client_id: Set(client_id),
         issued_by: Set(auth_user.id),
         ts_issued: Set(now.into()),
         command: Set(req.command),
-        params: Set(req.params.map(sea_orm::prelude::Json::from)),
+        params: Set(params.map(sea_orm::prelude::Json::from)),
         status: Set(commands::CommandStatus::Pending),
         ts_updated: Set(now.into()),
         error: Set(None),
+        signature: Set(signature),
+        expires_at: Set(Some(expires_at.into())),
     };
 
     let command = command.insert(&state.db).await.map_err(|_| {
@@ -135,14 +239,60 @@ async fn create_command(
             )
         })?;
 
-    Ok((StatusCode::CREATED, Json(command.into())))
+    let mut response: CommandResponse = command.into();
+    response.otp_uri = otp_uri;
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+/// Arguments as the client receives and the signature covers them: the stored
+/// params, plus the derived TOTP key for `lockdown`
+pub fn command_args(
+    signer: &CommandSigner,
+    id: Uuid,
+    command: &str,
+    params: Option<&serde_json::Value>,
+) -> serde_json::Value {
+    let mut args = params.cloned().unwrap_or(serde_json::Value::Null);
+    if command == LOCKDOWN {
+        if let Some(fields) = args.as_object_mut() {
+            fields.insert("otp_secret".to_string(), signer.lockdown_otp_secret(&id.to_string()).into());
+        }
+    }
+    args
 }
 
 async fn list_commands(
     State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
     Path(client_id): Path<Uuid>,
     Query(query): Query<ListCommandsQuery>,
 ) -> Result<Json<Vec<CommandResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    // Check access for non-admin
+    if auth_user.role != users::UserRole::Admin {
+        let assignment = UserClients::find()
+            .filter(user_clients::Column::UserId.eq(auth_user.id))
+            .filter(user_clients::Column::ClientId.eq(client_id))
+            .one(&state.db)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Error".to_string(),
+                }),
+            )
+        })?;
+
+        if assignment.is_none() {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ErrorResponse {
+                    error: "Access denied".to_string(),
+                }),
+            ));
+        }
+    }
+
     let mut q = Commands::find().filter(commands::Column::ClientId.eq(client_id));
 
     if let Some(status) = query.status {
@@ -198,6 +348,29 @@ async fn ack_command(
             }),
         ))?;
 
+    // A client that took the new command key only accepts commands signed with it
+    if req.success && command.command == ROTATE_COMMAND_KEY {
+        let public_key = command
+            .params
+            .as_ref()
+            .and_then(|params| params.get("public_key"))
+            .and_then(|key| key.as_str())
+            .map(str::to_string);
+        let client = clients::ActiveModel {
+            id: Set(client_id),
+            command_key: Set(public_key),
+            ..Default::default()
+        };
+        client.update(&state.db).await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Error".to_string(),
+                }),
+            )
+        })?;
+    }
+
     let mut command: commands::ActiveModel = command.into();
     command.status = Set(if req.success {
         commands::CommandStatus::Acked
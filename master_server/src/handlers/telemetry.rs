@@ -2,14 +2,16 @@ use axum::{  extract::{Path, Query, State},  http::StatusCode,  middleware,
     routing::{get, post, Router},
     Extension, Json,
 };
-use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, Set};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, Set,
+};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
     app::AppState,
-    auth::middleware::AuthUser,
-    entities::{prelude::*, clients, events, heartbeats, user_clients, users},
+    auth::{middleware::AuthUser, verify_event_signature},
+    entities::{prelude::*, clients, events, health_reports, heartbeats, user_clients, users},
 };
 
 #[derive(Debug, Deserialize)]
@@ -23,6 +25,37 @@ pub struct EventRequest {
     pub kind: String,
     pub message: String,
     pub meta: Option<serde_json::Value>,
+    /// Client's sequence number, used to drop redelivered events and spot lost ones
+    pub seq: Option<i64>,
+    /// Hex HMAC-SHA256 of `meta`, the client's event envelope, under its device key
+    pub signature: Option<String>,
+}
+
+/// Crash report a client left after a panic; kept whole as the event's `meta`
+#[derive(Debug, Deserialize)]
+pub struct CrashReportRequest {
+    pub message: String,
+    pub location: Option<String>,
+    #[serde(flatten)]
+    pub rest: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Health report a client sends daily and when a subsystem changes status; kept whole
+#[derive(Debug, Deserialize)]
+pub struct HealthReportRequest {
+    /// `ok`, `degraded` or `failed`
+    pub status: String,
+    /// `scheduled` or `change`
+    pub trigger: String,
+    #[serde(flatten)]
+    pub rest: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListHealthReportsQuery {
+    pub since: Option<String>,
+    pub status: Option<String>,
+    pub limit: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -30,6 +63,8 @@ pub struct ListEventsQuery {
     pub since: Option<String>,
     pub level: Option<String>,
     pub limit: Option<u64>,
+    /// Only events caused by this command, as recorded in the client envelope
+    pub correlation_id: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -41,6 +76,17 @@ pub struct EventResponse {
     pub kind: String,
     pub message: String,
     pub meta: Option<serde_json::Value>,
+    pub seq: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReportResponse {
+    pub id: i64,
+    pub client_id: Uuid,
+    pub ts: String,
+    pub status: String,
+    pub trigger: String,
+    pub report: serde_json::Value,
 }
 
 #[derive(Debug, Serialize)]
@@ -67,6 +113,20 @@ impl From<events::Model> for EventResponse {
             kind: event.kind,
             message: event.message,
             meta: event.meta,
+            seq: event.seq,
+        }
+    }
+}
+
+impl From<health_reports::Model> for HealthReportResponse {
+    fn from(report: health_reports::Model) -> Self {
+        Self {
+            id: report.id,
+            client_id: report.client_id,
+            ts: report.ts.to_rfc3339(),
+            status: report.status,
+            trigger: report.trigger,
+            report: report.report,
         }
     }
 }
@@ -132,6 +192,54 @@ async fn create_event(
     Path(client_id): Path<Uuid>,
     Json(req): Json<EventRequest>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    verify_signature(&state, client_id, &req).await?;
+
+    if let Some(seq) = req.seq {
+        let last = Events::find()
+            .filter(events::Column::ClientId.eq(client_id))
+            .filter(events::Column::Seq.is_not_null())
+            .order_by_desc(events::Column::Seq)
+            .one(&state.db)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse {
+                        error: "Error".to_string(),
+                    }),
+                )
+            })?
+            .and_then(|event| event.seq);
+
+        if let Some(last) = last {
+            let duplicate = Events::find()
+                .filter(events::Column::ClientId.eq(client_id))
+                .filter(events::Column::Seq.eq(seq))
+                .count(&state.db)
+                .await
+                .map_err(|_| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(ErrorResponse {
+                            error: "Error".to_string(),
+                        }),
+                    )
+                })?
+                > 0;
+
+            // Redelivered events are acknowledged without being stored twice
+            if duplicate {
+                tracing::debug!(%client_id, seq, "Duplicate event ignored");
+                return Ok(StatusCode::OK);
+            }
+
+            // Severity filtering on the client also leaves gaps
+            if seq > last + 1 {
+                tracing::warn!(%client_id, seq, last, missing = seq - last - 1, "Gap in client event sequence");
+            }
+        }
+    }
+
     let event = events::ActiveModel {
         id: Set(0),
         client_id: Set(client_id),
@@ -140,6 +248,7 @@ async fn create_event(
         kind: Set(req.kind),
         message: Set(req.message),
         meta: Set(req.meta.map(sea_orm::prelude::Json::from)),
+        seq: Set(req.seq),
     };
 
     event.insert(&state.db).await.map_err(|_| {
@@ -154,6 +263,131 @@ async fn create_event(
     Ok(StatusCode::ACCEPTED)
 }
 
+/// Store a client's crash report as an `error` event of kind `crash_report`
+async fn create_crash_report(
+    State(state): State<AppState>,
+    Path(client_id): Path<Uuid>,
+    Json(req): Json<CrashReportRequest>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let message = match &req.location {
+        Some(location) => format!("Agent panicked at {}: {}", location, req.message),
+        None => format!("Agent panicked: {}", req.message),
+    };
+    tracing::warn!(%client_id, %message, "Client crash report received");
+
+    let mut report = req.rest;
+    report.insert("message".to_string(), req.message.into());
+    report.insert("location".to_string(), req.location.into());
+    let event = events::ActiveModel {
+        id: Set(0),
+        client_id: Set(client_id),
+        ts: Set(chrono::Utc::now().into()),
+        level: Set(events::EventLevel::Error),
+        kind: Set("crash_report".to_string()),
+        message: Set(message),
+        meta: Set(Some(serde_json::Value::Object(report))),
+        seq: Set(None),
+    };
+
+    event.insert(&state.db).await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Error".to_string(),
+                }),
+            )
+        })?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Store a client's health report for fleet health dashboards
+async fn create_health_report(
+    State(state): State<AppState>,
+    Path(client_id): Path<Uuid>,
+    Json(req): Json<HealthReportRequest>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    if !["ok", "degraded", "failed"].contains(&req.status.as_str()) {
+        return Err((StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Unknown health status {:?}", req.status),
+            }),
+        ));
+    }
+    if req.status != "ok" {
+        tracing::info!(%client_id, status = %req.status, trigger = %req.trigger, "Client reports reduced health");
+    }
+
+    let mut report = req.rest;
+    report.insert("status".to_string(), req.status.clone().into());
+    report.insert("trigger".to_string(), req.trigger.clone().into());
+    let report = health_reports::ActiveModel {
+        id: Set(0),
+        client_id: Set(client_id),
+        ts: Set(chrono::Utc::now().into()),
+        status: Set(req.status),
+        trigger: Set(req.trigger),
+        report: Set(serde_json::Value::Object(report)),
+    };
+
+    report.insert(&state.db).await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Error".to_string(),
+                }),
+            )
+        })?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Reject events from signing clients unless the signature over `meta` checks out
+async fn verify_signature(
+    state: &AppState,
+    client_id: Uuid,
+    req: &EventRequest,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let client = Clients::find_by_id(client_id)
+        .one(&state.db)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Error".to_string(),
+                }),
+            )
+        })?
+        .ok_or((StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Error".to_string(),
+            }),
+        ))?;
+
+    let Some(key) = client.signing_key else {
+        return Ok(());
+    };
+
+    let verified = match (&req.meta, &req.signature) {
+        (Some(envelope), Some(signature)) => {
+            verify_event_signature(&key, envelope, signature).unwrap_or(false)
+        }
+        _ => false,
+    };
+
+    if !verified {
+        tracing::warn!(%client_id, kind = %req.kind, "Rejected event with missing or invalid signature");
+        return Err((StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "Invalid event signature".to_string(),
+            }),
+        ));
+    }
+
+    Ok(())
+}
+
 async fn list_events(
     State(state): State<AppState>,
     Extension(auth_user): Extension<AuthUser>,
@@ -195,6 +429,13 @@ async fn list_events(
         }
     }
 
+    if let Some(correlation_id) = query.correlation_id {
+        q = q.filter(Expr::cust_with_values(
+            "meta ->> 'correlation_id' = $1",
+            [correlation_id],
+        ));
+    }
+
     if let Some(level) = query.level {
         let level_enum = match level.as_str() {
             "info" => events::EventLevel::Info,
@@ -237,6 +478,68 @@ async fn list_events(
     Ok(Json(events.into_iter().map(|e| e.into()).collect()))
 }
 
+/// A client's health reports, newest first
+async fn list_health_reports(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(client_id): Path<Uuid>,
+    Query(query): Query<ListHealthReportsQuery>,
+) -> Result<Json<Vec<HealthReportResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    // Check access for non-admin
+    if auth_user.role != users::UserRole::Admin {
+        let assignment = UserClients::find()
+            .filter(user_clients::Column::UserId.eq(auth_user.id))
+            .filter(user_clients::Column::ClientId.eq(client_id))
+            .one(&state.db)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Error".to_string(),
+                }),
+            )
+        })?;
+
+        if assignment.is_none() {
+            return Err((StatusCode::FORBIDDEN,
+                    Json(ErrorResponse {
+                        error: "Error".to_string(),
+                    }),
+                ));
+        }
+    }
+
+    let mut q = HealthReports::find()
+        .filter(health_reports::Column::ClientId.eq(client_id))
+        .order_by_desc(health_reports::Column::Ts);
+
+    if let Some(since) = query.since {
+        if let Ok(since_dt) = chrono::DateTime::parse_from_rfc3339(&since) {
+            q = q.filter(health_reports::Column::Ts.gt(since_dt));
+        }
+    }
+
+    if let Some(status) = query.status {
+        q = q.filter(health_reports::Column::Status.eq(status));
+    }
+
+    let reports = q
+        .paginate(&state.db, query.limit.unwrap_or(30))
+        .fetch_page(0)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Error".to_string(),
+                }),
+            )
+        })?;
+
+    Ok(Json(reports.into_iter().map(|r| r.into()).collect()))
+}
+
 async fn get_status(
     State(state): State<AppState>,
     Extension(auth_user): Extension<AuthUser>,
@@ -297,10 +600,16 @@ pub fn router() -> Router<AppState> {
     Router::new()
         .route("/:client_id/heartbeat", post(heartbeat))
         .route("/:client_id/events", post(create_event))
+        .route("/:client_id/crash_reports", post(create_crash_report))
+        .route("/:client_id/health_reports", post(create_health_report))
         .route(
             "/:client_id/events",
             get(list_events),
         )
+        .route(
+            "/:client_id/health_reports",
+            get(list_health_reports),
+        )
         .route(
             "/:client_id/status",
             get(get_status),
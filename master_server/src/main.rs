@@ -10,6 +10,7 @@ use std::sync::Arc;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::app::{create_router, AppState};
+use crate::auth::CommandSigner;
 use crate::config::Config;
 
 #[tokio::main]
@@ -30,10 +31,18 @@ async fn main() -> Result<()> {
     // Connect to database and run migrations
     let db = db::connect(&config.database_url).await?;
 
+    // Load the command signing key
+    let command_signer = CommandSigner::from_config(&config)?.map(Arc::new);
+    match &command_signer {
+        Some(signer) => tracing::info!(public_key = %signer.public_key(), "Signing client commands"),
+        None => tracing::warn!("COMMAND_SIGNING_KEY not set, client commands are sent unsigned"),
+    }
+
     // Create application state
     let state = AppState {
         db,
         config: Arc::new(config.clone()),
+        command_signer,
     };
 
     // Create router